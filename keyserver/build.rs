@@ -1,4 +1,5 @@
 fn main() {
     prost_build::compile_protos(&["src/proto/database.proto"], &["src/"]).unwrap();
     prost_build::compile_protos(&["src/pubsub/proto/broadcast.proto"], &["src/"]).unwrap();
+    prost_build::compile_protos(&["src/proto/capabilities.proto"], &["src/"]).unwrap();
 }
@@ -0,0 +1,274 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use cashweb::keyserver::{Peer, Peers};
+use hyper::http::uri::{InvalidUri, Uri};
+use prost::{DecodeError, Message as _};
+use thiserror::Error;
+
+use crate::{
+    db::Database,
+    models::database::{DatabaseWrapper, MetadataRecord},
+    settings::Command,
+    SETTINGS,
+};
+
+const STATS_PROPERTIES: &[&str] = &[
+    "rocksdb.estimate-num-keys",
+    "rocksdb.estimate-live-data-size",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.stats",
+];
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("database failure: {0}")]
+    DB(rocksdb::Error),
+    #[error("invalid peer url: {0}")]
+    InvalidUrl(InvalidUri),
+    #[error("peer already exists")]
+    PeerExists,
+    #[error("peer not found")]
+    PeerNotFound,
+    #[error("io failure: {0}")]
+    Io(io::Error),
+    #[error("failed to decode record: {0}")]
+    Decode(DecodeError),
+    #[error("record is missing metadata")]
+    MissingMetadata,
+}
+
+impl From<rocksdb::Error> for CommandError {
+    fn from(err: rocksdb::Error) -> Self {
+        Self::DB(err)
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Run an operational subcommand against the database.
+pub fn run(command: &Command) -> Result<(), CommandError> {
+    let db = Database::try_new(&SETTINGS.db_path)?;
+    match command {
+        Command::Serve => (),
+        Command::DbCompact => {
+            db.compact();
+            println!("compaction complete");
+        }
+        Command::DbStats => {
+            for name in STATS_PROPERTIES {
+                let value = db.property(name)?.unwrap_or_default();
+                println!("{}: {}", name, value);
+            }
+        }
+        Command::PeersList => {
+            for peer in db.get_peers()?.unwrap_or_default().peers {
+                println!("{}", peer.url);
+            }
+        }
+        Command::PeersAdd(url) => {
+            add_peer(&db, url)?;
+            println!("added {}", url);
+        }
+        Command::PeersRemove(url) => {
+            remove_peer(&db, url)?;
+            println!("removed {}", url);
+        }
+        Command::Export(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            let n_records = export_metadata(&db, &mut writer)?;
+            writer.flush()?;
+            println!("exported {} records to {}", n_records, path);
+        }
+        Command::Import(path) => {
+            let mut reader = BufReader::new(File::open(path)?);
+            let n_records = import_metadata(&db, &mut reader)?;
+            println!("imported {} records from {}", n_records, path);
+        }
+    }
+    Ok(())
+}
+
+fn persist_peers(db: &Database, peers: &Peers) -> Result<(), rocksdb::Error> {
+    let mut raw_peers = Vec::with_capacity(peers.encoded_len());
+    peers.encode(&mut raw_peers).unwrap(); // This is safe
+    db.put_peers(&raw_peers)
+}
+
+/// Add a peer to the persisted peer list.
+pub fn add_peer(db: &Database, url: &str) -> Result<(), CommandError> {
+    url.parse::<Uri>().map_err(CommandError::InvalidUrl)?;
+
+    let mut peers = db.get_peers()?.unwrap_or_default();
+    if peers.peers.iter().any(|peer| peer.url == url) {
+        return Err(CommandError::PeerExists);
+    }
+    peers.peers.push(Peer {
+        url: url.to_string(),
+        info: None,
+    });
+    persist_peers(db, &peers)?;
+    Ok(())
+}
+
+/// Remove a peer from the persisted peer list.
+pub fn remove_peer(db: &Database, url: &str) -> Result<(), CommandError> {
+    let mut peers = db.get_peers()?.unwrap_or_default();
+    let original_len = peers.peers.len();
+    peers.peers.retain(|peer| peer.url != url);
+    if peers.peers.len() == original_len {
+        return Err(CommandError::PeerNotFound);
+    }
+    persist_peers(db, &peers)?;
+    Ok(())
+}
+
+/// Write all metadata to `writer` as length-delimited `MetadataRecord`s, returning the number of
+/// records written.
+pub fn export_metadata<W: Write>(db: &Database, writer: &mut W) -> Result<usize, CommandError> {
+    let mut n_records = 0;
+    for (address, raw_wrapper) in db.iter_raw_metadata() {
+        let wrapper = DatabaseWrapper::decode(&raw_wrapper[..]).map_err(CommandError::Decode)?;
+        let record = MetadataRecord {
+            address,
+            wrapper: Some(wrapper),
+        };
+        let mut raw_record = Vec::with_capacity(record.encoded_len() + 10);
+        record.encode_length_delimited(&mut raw_record).unwrap(); // This is safe
+        writer.write_all(&raw_record)?;
+        n_records += 1;
+    }
+    Ok(n_records)
+}
+
+/// Read a varint length delimiter, returning `None` at the end of the stream.
+fn read_length_delimiter<R: Read>(reader: &mut R) -> Result<Option<usize>, CommandError> {
+    let mut raw_delimiter = Vec::with_capacity(10);
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            if raw_delimiter.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        raw_delimiter.push(byte[0]);
+        if byte[0] & 0x80 == 0 || raw_delimiter.len() == 10 {
+            break;
+        }
+    }
+    prost::decode_length_delimiter(&raw_delimiter[..])
+        .map(Some)
+        .map_err(CommandError::Decode)
+}
+
+/// Read length-delimited `MetadataRecord`s from `reader` into the database, returning the number of
+/// records imported.
+pub fn import_metadata<R: Read>(db: &Database, reader: &mut R) -> Result<usize, CommandError> {
+    let mut n_records = 0;
+    while let Some(len) = read_length_delimiter(reader)? {
+        let mut raw_record = vec![0; len];
+        reader.read_exact(&mut raw_record)?;
+        let record = MetadataRecord::decode(&raw_record[..]).map_err(CommandError::Decode)?;
+        let wrapper = record.wrapper.ok_or(CommandError::MissingMetadata)?;
+
+        let mut raw_wrapper = Vec::with_capacity(wrapper.encoded_len());
+        wrapper.encode(&mut raw_wrapper).unwrap(); // This is safe
+        db.put_metadata(&record.address, &raw_wrapper)?;
+        n_records += 1;
+    }
+    Ok(n_records)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{Options, DB};
+
+    use super::*;
+
+    #[test]
+    fn add_remove_peers() {
+        const TEST_NAME: &str = "./tests/add_remove_peers";
+
+        let database = Database::try_new(TEST_NAME).unwrap();
+
+        add_peer(&database, "http://127.0.0.1:8080").unwrap();
+        add_peer(&database, "http://127.0.0.1:8081").unwrap();
+        assert!(matches!(
+            add_peer(&database, "http://127.0.0.1:8080"),
+            Err(CommandError::PeerExists)
+        ));
+        assert!(matches!(
+            add_peer(&database, "not a url"),
+            Err(CommandError::InvalidUrl(_))
+        ));
+
+        remove_peer(&database, "http://127.0.0.1:8080").unwrap();
+        assert!(matches!(
+            remove_peer(&database, "http://127.0.0.1:8080"),
+            Err(CommandError::PeerNotFound)
+        ));
+
+        let peers = database.get_peers().unwrap().unwrap();
+        assert_eq!(
+            peers.peers,
+            vec![Peer {
+                url: "http://127.0.0.1:8081".to_string(),
+                info: None,
+            }]
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn export_import() {
+        const SOURCE_NAME: &str = "./tests/export_source";
+        const DESTINATION_NAME: &str = "./tests/export_destination";
+
+        let source = Database::try_new(SOURCE_NAME).unwrap();
+        let destination = Database::try_new(DESTINATION_NAME).unwrap();
+
+        // Populate source
+        let addresses = [vec![1; 20], vec![2; 20], vec![3; 20]];
+        for address in &addresses {
+            let wrapper = DatabaseWrapper {
+                serialized_auth_wrapper: address.clone(),
+                token: vec![4; 32],
+            };
+            let mut raw_wrapper = Vec::with_capacity(wrapper.encoded_len());
+            wrapper.encode(&mut raw_wrapper).unwrap();
+            source.put_metadata(address, &raw_wrapper).unwrap();
+        }
+        source.put_peers(&[]).unwrap();
+
+        // Export then import
+        let mut dump = Vec::new();
+        assert_eq!(export_metadata(&source, &mut dump).unwrap(), 3);
+        assert_eq!(import_metadata(&destination, &mut &dump[..]).unwrap(), 3);
+
+        for address in &addresses {
+            assert_eq!(
+                destination.get_raw_metadata(address).unwrap(),
+                source.get_raw_metadata(address).unwrap()
+            );
+        }
+
+        // Truncated dumps fail
+        assert!(import_metadata(&destination, &mut &dump[..dump.len() - 1]).is_err());
+
+        // Destroy databases
+        drop(source);
+        drop(destination);
+        DB::destroy(&Options::default(), SOURCE_NAME).unwrap();
+        DB::destroy(&Options::default(), DESTINATION_NAME).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use ring::digest::{Context, SHA256};
+use ripemd160::{Digest, Ripemd160};
 use std::convert::TryInto;
 
 pub fn sha256(data: &[u8]) -> [u8; 32] {
@@ -6,3 +7,11 @@ pub fn sha256(data: &[u8]) -> [u8; 32] {
     sha256_context.update(data);
     sha256_context.finish().as_ref().try_into().unwrap()
 }
+
+/// RIPEMD160 digest of the SHA256 digest, as used to derive addresses from public keys.
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(&sha256(data))
+        .as_slice()
+        .try_into()
+        .unwrap()
+}
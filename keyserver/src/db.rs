@@ -1,13 +1,23 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 
 use cashweb::keyserver::Peers;
 use prost::Message;
-use rocksdb::{Error as RocksError, Options, DB};
+use rocksdb::{Direction, Error as RocksError, IteratorMode, Options, WriteBatch, DB};
 
 use crate::models::database::DatabaseWrapper;
 
 const METADATA_NAMESPACE: u8 = b'm';
 const PEER_NAMESPACE: u8 = b'p';
+const HISTORY_NAMESPACE: u8 = b'h';
+const TOKEN_NAMESPACE: u8 = b't';
+const REVOCATION_NAMESPACE: u8 = b'r';
+const AUDIT_NAMESPACE: u8 = b'a';
+
+// Orders audit entries recorded within the same millisecond
+static AUDIT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Clone)]
 pub struct Database(Arc<DB>);
@@ -43,6 +53,153 @@ impl Database {
         self.0.put(key, raw)
     }
 
+    /// Iterate over the addresses and serialized `DatabaseWrapper`s of all stored metadata.
+    pub fn iter_raw_metadata(&self) -> impl Iterator<Item = (Vec<u8>, Box<[u8]>)> + '_ {
+        self.0
+            .iterator(IteratorMode::From(
+                &[METADATA_NAMESPACE],
+                Direction::Forward,
+            ))
+            .take_while(|(key, _)| key.first() == Some(&METADATA_NAMESPACE))
+            .map(|(key, value)| (key[1..].to_vec(), value))
+    }
+
+    /// Get the history keys and serialized `AuthWrapper`s of an address, oldest first.
+    fn metadata_history_entries(&self, addr: &[u8]) -> Vec<(Box<[u8]>, Box<[u8]>)> {
+        let prefix = [&[HISTORY_NAMESPACE], addr].concat();
+        let key_len = prefix.len() + 8;
+        self.0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter(|(key, _)| key.len() == key_len)
+            .collect()
+    }
+
+    /// Append a serialized `AuthWrapper` to the metadata history of an address, retaining at most
+    /// `max_len` of the most recent entries.
+    pub fn push_metadata_history(
+        &self,
+        addr: &[u8],
+        timestamp: u64,
+        raw_auth_wrapper: &[u8],
+        max_len: usize,
+    ) -> Result<(), RocksError> {
+        let key = [&[HISTORY_NAMESPACE], addr, &timestamp.to_be_bytes()].concat();
+        self.0.put(key, raw_auth_wrapper)?;
+
+        // Prune oldest entries
+        let entries = self.metadata_history_entries(addr);
+        if entries.len() > max_len {
+            let mut batch = WriteBatch::default();
+            for (key, _) in &entries[..entries.len() - max_len] {
+                batch.delete(key);
+            }
+            self.0.write(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Get the serialized `AuthWrapper`s in the metadata history of an address, newest first.
+    pub fn get_metadata_history(&self, addr: &[u8]) -> Vec<Vec<u8>> {
+        self.metadata_history_entries(addr)
+            .into_iter()
+            .rev()
+            .map(|(_, value)| value.to_vec())
+            .collect()
+    }
+
+    /// Get the serialized revocation `AuthWrapper` of a public key.
+    pub fn get_revocation(&self, public_key: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
+        let key = [&[REVOCATION_NAMESPACE], public_key].concat();
+        self.0.get(key)
+    }
+
+    /// Put the serialized revocation `AuthWrapper` of a public key to the database.
+    pub fn put_revocation(&self, public_key: &[u8], raw: &[u8]) -> Result<(), RocksError> {
+        let key = [&[REVOCATION_NAMESPACE], public_key].concat();
+        self.0.put(key, raw)
+    }
+
+    /// Append a serialized `AuditEntry`, recorded at `timestamp` milliseconds, to the audit log.
+    pub fn push_audit_entry(&self, timestamp: u64, raw_entry: &[u8]) -> Result<(), RocksError> {
+        let sequence = AUDIT_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let key = [
+            &[AUDIT_NAMESPACE][..],
+            &timestamp.to_be_bytes(),
+            &sequence.to_be_bytes(),
+        ]
+        .concat();
+        self.0.put(key, raw_entry)
+    }
+
+    /// Get at most `limit` serialized `AuditEntry`s recorded at or after `since` milliseconds,
+    /// oldest first.
+    pub fn get_audit_entries(&self, since: u64, limit: usize) -> Vec<Vec<u8>> {
+        let start = [&[AUDIT_NAMESPACE][..], &since.to_be_bytes()].concat();
+        self.0
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+            .take_while(|(key, _)| key.first() == Some(&AUDIT_NAMESPACE))
+            .take(limit)
+            .map(|(_, value)| value.to_vec())
+            .collect()
+    }
+
+    /// Get the number of blocks seen by the token cache.
+    pub fn get_token_epoch(&self) -> Result<Option<u64>, RocksError> {
+        Ok(self.0.get([TOKEN_NAMESPACE])?.map(|raw_epoch| {
+            let mut epoch = [0; 8];
+            epoch.copy_from_slice(&raw_epoch[..8]); // This panics if stored bytes are malformed
+            u64::from_be_bytes(epoch)
+        }))
+    }
+
+    /// Get the addresses awaiting broadcast, paired with the epoch at which they were added.
+    pub fn get_pending_tokens(&self) -> Vec<(String, u64)> {
+        self.0
+            .iterator(IteratorMode::From(&[TOKEN_NAMESPACE], Direction::Forward))
+            .take_while(|(key, _)| key.first() == Some(&TOKEN_NAMESPACE))
+            .filter(|(key, value)| key.len() > 1 && value.len() == 8)
+            .filter_map(|(key, value)| {
+                let addr = String::from_utf8(key[1..].to_vec()).ok()?;
+                let mut epoch = [0; 8];
+                epoch.copy_from_slice(&value);
+                Some((addr, u64::from_be_bytes(epoch)))
+            })
+            .collect()
+    }
+
+    /// Record an address awaiting broadcast.
+    pub fn put_pending_token(&self, addr: &str, epoch: u64) -> Result<(), RocksError> {
+        let key = [&[TOKEN_NAMESPACE], addr.as_bytes()].concat();
+        self.0.put(key, epoch.to_be_bytes())
+    }
+
+    /// Remove addresses awaiting broadcast, optionally updating the token cache epoch.
+    pub fn remove_pending_tokens<'a>(
+        &self,
+        addrs: impl IntoIterator<Item = &'a str>,
+        epoch: Option<u64>,
+    ) -> Result<(), RocksError> {
+        let mut batch = WriteBatch::default();
+        for addr in addrs {
+            batch.delete([&[TOKEN_NAMESPACE], addr.as_bytes()].concat());
+        }
+        if let Some(epoch) = epoch {
+            batch.put([TOKEN_NAMESPACE], epoch.to_be_bytes());
+        }
+        self.0.write(batch)
+    }
+
+    /// Compact the entire database.
+    pub fn compact(&self) {
+        self.0.compact_range::<&[u8], &[u8]>(None, None)
+    }
+
+    /// Get a RocksDB property, for example `rocksdb.estimate-num-keys`.
+    pub fn property(&self, name: &str) -> Result<Option<String>, RocksError> {
+        self.0.property_value(name)
+    }
+
     /// Get `Peers` from database.
     pub fn get_peers(&self) -> Result<Option<Peers>, RocksError> {
         self.get_peers_raw().map(|raw_peers_opt| {
@@ -81,12 +238,15 @@ pub mod tests {
         // Create peers
         let peer_a = Peer {
             url: "url a".to_string(),
+            info: None,
         };
         let peer_b = Peer {
             url: "url b".to_string(),
+            info: None,
         };
         let peers_in = Peers {
             peers: vec![peer_a, peer_b],
+            info: None,
         };
         let mut peers_raw = Vec::with_capacity(peers_in.encoded_len());
         peers_in.encode(&mut peers_raw).unwrap();
@@ -132,4 +292,90 @@ pub mod tests {
         drop(database);
         DB::destroy(&Options::default(), TEST_NAME).unwrap();
     }
+
+    #[test]
+    fn audit_log() {
+        const TEST_NAME: &str = "./tests/audit_log";
+
+        // Create database
+        let database = Database::try_new(TEST_NAME).unwrap();
+        assert!(database.get_audit_entries(0, 10).is_empty());
+
+        // Entries in the same millisecond are kept in order
+        database.push_audit_entry(1_000, &[1]).unwrap();
+        database.push_audit_entry(2_000, &[2]).unwrap();
+        database.push_audit_entry(2_000, &[3]).unwrap();
+        database.put_revocation(&[2; 33], &[4]).unwrap();
+
+        assert_eq!(
+            database.get_audit_entries(0, 10),
+            vec![vec![1], vec![2], vec![3]]
+        );
+        assert_eq!(
+            database.get_audit_entries(1_500, 10),
+            vec![vec![2], vec![3]]
+        );
+        assert_eq!(database.get_audit_entries(0, 1), vec![vec![1]]);
+        assert!(database.get_audit_entries(3_000, 10).is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn revocation() {
+        const TEST_NAME: &str = "./tests/revocation";
+
+        // Create database
+        let database = Database::try_new(TEST_NAME).unwrap();
+
+        let public_key = vec![2; 33];
+        assert_eq!(database.get_revocation(&public_key).unwrap(), None);
+
+        // Put to database
+        database.put_revocation(&public_key, &[1, 2, 3]).unwrap();
+
+        // Get from database
+        assert_eq!(
+            database.get_revocation(&public_key).unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(database.get_revocation(&[3; 33]).unwrap(), None);
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn metadata_history() {
+        const TEST_NAME: &str = "./tests/metadata_history";
+
+        // Create database
+        let database = Database::try_new(TEST_NAME).unwrap();
+
+        let addr = vec![0, 3, 4, 3, 2];
+        let other_addr = vec![0, 3, 4, 3, 3];
+        for timestamp in 0..5u8 {
+            database
+                .push_metadata_history(&addr, timestamp as u64, &[timestamp], 3)
+                .unwrap();
+        }
+        database
+            .push_metadata_history(&other_addr, 0, &[9], 3)
+            .unwrap();
+
+        // Only the most recent are retained, newest first
+        assert_eq!(
+            database.get_metadata_history(&addr),
+            vec![vec![4], vec![3], vec![2]]
+        );
+        assert_eq!(database.get_metadata_history(&other_addr), vec![vec![9]]);
+        assert!(database.get_metadata_history(&[1]).is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
 }
@@ -0,0 +1,285 @@
+//! An in-process keyserver for end-to-end tests.
+//!
+//! [`TestServer`] serves the routes constructed by [`routes`](crate::routes::routes) over
+//! temporary databases and a [`MockBitcoinClient`], bound to an ephemeral local port.
+
+use std::{ops::Deref, time::Duration};
+
+use cashweb::bitcoin_client::{mock::MockBitcoinClient, ChainBackend, FailoverClient};
+use cashweb_server_core::harness::{self, wait_until};
+use rocksdb::{Options, DB};
+use tokio::sync::broadcast;
+
+use crate::{
+    db::Database,
+    peering::{self, PeerHandler, TokenCache},
+    pubsub::{BurnPolicy, MessageBus, PubSubDatabase, TopicModeration, BROADCAST_CHANNEL_CAPACITY},
+    routes::routes,
+};
+
+/// Time allowed for websockets to subscribe.
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A keyserver running inside the test process.
+pub struct TestServer {
+    /// The bitcoin client used by the server, shared so tests can script responses.
+    pub bitcoin_client: MockBitcoinClient,
+    server: harness::TestServer,
+    msg_bus: MessageBus,
+    db_path: String,
+    pubsub_db_path: String,
+}
+
+impl Deref for TestServer {
+    type Target = harness::TestServer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.server
+    }
+}
+
+impl TestServer {
+    /// Spawn a server, storing its databases under `./tests/<name>_*`.
+    pub fn spawn(name: &str) -> Self {
+        let db_path = format!("./tests/{}_db", name);
+        let pubsub_db_path = format!("./tests/{}_pubsub_db", name);
+        let db = Database::try_new(&db_path).unwrap();
+        let pubsub_db = PubSubDatabase::new(&pubsub_db_path).unwrap();
+        let bitcoin_client = MockBitcoinClient::new();
+        let (msg_bus, _) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
+        let token_cache = TokenCache::load(db.clone()).unwrap();
+
+        let server = harness::TestServer::spawn(routes(
+            db,
+            pubsub_db,
+            FailoverClient::new(vec![ChainBackend::from(bitcoin_client.clone())]),
+            msg_bus.clone(),
+            PeerHandler::new(vec![], peering::server_info()),
+            token_cache,
+            TopicModeration::default(),
+            BurnPolicy::default(),
+        ));
+
+        Self {
+            bitcoin_client,
+            server,
+            msg_bus,
+            db_path,
+            pubsub_db_path,
+        }
+    }
+
+    /// Wait until `n` websockets are subscribed to the messages.
+    ///
+    /// Sockets subscribe after the upgrade completes, so messages put in the meantime are missed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sockets do not subscribe within [`SUBSCRIBE_TIMEOUT`].
+    pub async fn wait_for_subscribers(&self, n: usize) {
+        let msg_bus = self.msg_bus.clone();
+        wait_until(move || msg_bus.receiver_count() >= n, SUBSCRIBE_TIMEOUT).await;
+    }
+
+    /// Stop the server, once its connections are closed, and destroy its databases.
+    pub async fn shutdown(self) {
+        self.server.shutdown().await;
+        DB::destroy(&Options::default(), &self.db_path).unwrap();
+        DB::destroy(&Options::default(), &self.pubsub_db_path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use bitcoincash_addr::{Address, Network};
+    use cashweb::{
+        auth_wrapper::{AuthWrapper, AuthWrapperSet, BurnOutputs, SignatureScheme},
+        bitcoin::{
+            transaction::{output::Output, script::Script, Transaction},
+            Encodable,
+        },
+        payments::bip70,
+    };
+    use futures::prelude::*;
+    use hyper::{
+        body::to_bytes,
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+        Body, Client, Request,
+    };
+    use prost::Message as _;
+    use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+    use super::*;
+    use crate::{
+        crypto::{hash160, sha256},
+        models::broadcast::BroadcastMessage,
+        net::ServerIdentity,
+        HISTORY_PATH, MESSAGES_PATH, METADATA_PATH, PAYMENTS_PATH, WS_PATH,
+    };
+
+    fn encode_message(message: &impl prost::Message) -> Vec<u8> {
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+        raw_message
+    }
+
+    fn put(url: String, body: Vec<u8>, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::put(url);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, token);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    /// Sign a payload with an ECDSA key.
+    fn sign(identity: &ServerIdentity, payload: Vec<u8>) -> AuthWrapper {
+        AuthWrapper {
+            public_key: identity.public_key().serialize().to_vec(),
+            signature: identity.sign(&payload).to_vec(),
+            scheme: SignatureScheme::Ecdsa as i32,
+            payload,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn payment_metadata_message() {
+        let server = TestServer::spawn("harness_payment_metadata_message");
+        let client = Client::new();
+        let identity = ServerIdentity::from_hex(&"01".repeat(32)).unwrap();
+
+        let metadata = sign(&identity, b"metadata".to_vec());
+        let raw_metadata = encode_message(&metadata);
+        let address = Address {
+            body: hash160(&metadata.public_key).to_vec(),
+            network: Network::Regtest,
+            ..Default::default()
+        };
+        let metadata_path = format!("{}/{}", METADATA_PATH, address.encode().unwrap());
+
+        // Without a token, the metadata requires a payment
+        let response = client
+            .request(put(server.url(&metadata_path), raw_metadata.clone(), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 402);
+        let raw_invoice = to_bytes(response.into_body()).await.unwrap();
+        let invoice = bip70::PaymentRequest::decode(raw_invoice).unwrap();
+        let payment_details =
+            bip70::PaymentDetails::decode(invoice.serialized_payment_details.as_slice()).unwrap();
+
+        // Pay the invoice, the node then serves the broadcast transaction
+        let mut tx = Transaction::default();
+        tx.outputs = payment_details
+            .outputs
+            .iter()
+            .map(|output| Output {
+                value: output.amount.unwrap_or(0),
+                script: Script::from(output.script.clone()),
+            })
+            .collect();
+        let mut raw_tx = Vec::with_capacity(tx.encoded_len());
+        tx.encode(&mut raw_tx).unwrap();
+        server
+            .bitcoin_client
+            .insert_transaction(tx.transaction_id_rev().to_vec(), raw_tx.clone());
+        let payment = bip70::Payment {
+            merchant_data: payment_details.merchant_data,
+            transactions: vec![raw_tx],
+            ..Default::default()
+        };
+        let request = Request::post(server.url(PAYMENTS_PATH))
+            .header(CONTENT_TYPE, "application/bitcoincash-payment")
+            .header(ACCEPT, "application/bitcoincash-paymentack")
+            .body(Body::from(encode_message(&payment)))
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let token = response.headers()[AUTHORIZATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // The token unlocks the metadata
+        let response = client
+            .request(put(server.url(&metadata_path), raw_metadata, Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = client
+            .get(
+                server
+                    .url(&format!("{}/{}", metadata_path, HISTORY_PATH))
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let raw_history = to_bytes(response.into_body()).await.unwrap();
+        let history = AuthWrapperSet::decode(raw_history).unwrap();
+        assert_eq!(history.items, vec![metadata]);
+
+        // Subscribers receive the messages put to their topic
+        let (mut socket, _) = connect_async(
+            server
+                .ws_url(&format!("{}/{}?topic=cashweb", WS_PATH, MESSAGES_PATH))
+                .as_str(),
+        )
+        .await
+        .unwrap();
+        server.wait_for_subscribers(1).await;
+
+        let broadcast_message = BroadcastMessage {
+            topic: "cashweb.harness".to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            ..Default::default()
+        };
+        let mut message = sign(&identity, encode_message(&broadcast_message));
+        let burn_script = [
+            &[106, 4][..],
+            &b"POND"[..],
+            &[81, 32][..],
+            &sha256(&message.payload)[..],
+        ]
+        .concat();
+        let mut burn_tx = Transaction::default();
+        burn_tx.outputs.push(Output {
+            value: 0,
+            script: Script::from(burn_script),
+        });
+        let mut raw_burn_tx = Vec::with_capacity(burn_tx.encoded_len());
+        burn_tx.encode(&mut raw_burn_tx).unwrap();
+        message.transactions.push(BurnOutputs {
+            tx: raw_burn_tx,
+            index: 0,
+        });
+        let response = client
+            .request(put(
+                server.url(MESSAGES_PATH),
+                encode_message(&message),
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let raw_received = loop {
+            match socket.next().await.unwrap().unwrap() {
+                WsMessage::Binary(raw_received) => break raw_received,
+                _ => continue,
+            }
+        };
+        let received = AuthWrapper::decode(raw_received.as_slice()).unwrap();
+        assert_eq!(received.payload, message.payload);
+
+        drop(socket);
+        drop(client);
+        server.shutdown().await;
+    }
+}
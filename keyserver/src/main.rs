@@ -2,46 +2,63 @@
 extern crate clap;
 extern crate serde;
 
+mod commands;
 mod crypto;
 mod db;
+#[cfg(test)]
+mod harness;
 mod models;
 mod net;
 mod peering;
 mod pubsub;
+mod routes;
 mod settings;
 
 #[cfg(feature = "monitoring")]
 pub mod monitoring;
 
-use std::{env, sync::Arc, time::Duration};
-
-use cashweb::{
-    auth_wrapper::AuthWrapper, bitcoin_client::BitcoinClientHTTP, payments::preprocess_payment,
-    token::schemes::chain_commitment::ChainCommitmentScheme,
+use std::{
+    env, process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use futures::prelude::*;
+
+use cashweb::bitcoin_client::FailoverClient;
+use cashweb_server_core::listener;
 use hyper::{client::HttpConnector, http::Uri};
 use lazy_static::lazy_static;
-use prost::Message as _;
-use serde::Deserialize;
-use tracing::{error, info};
+use tokio::{sync::broadcast, time::interval};
+use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
-use warp::{
-    http::{header, Method},
-    Filter,
-};
+use warp::{http::header, Filter};
 
 use crate::{
     db::Database,
     peering::{PeerHandler, TokenCache},
-    pubsub::PubSubDatabase,
-    settings::Settings,
+    pubsub::{BurnPolicy, PubSubDatabase, TopicModeration},
+    settings::{Command, Settings},
 };
 
 const METADATA_PATH: &str = "keys";
 const PEERS_PATH: &str = "peers";
+const REVOCATIONS_PATH: &str = "revocations";
 pub const PAYMENTS_PATH: &str = "payments";
 const MESSAGES_PATH: &str = "messages";
+const REPLIES_PATH: &str = "replies";
+const BURNS_PATH: &str = "burns";
+const WS_PATH: &str = "ws";
+const ADMIN_PATH: &str = "admin";
+const BANNED_TOPICS_PATH: &str = "banned_topics";
+const REPORTS_PATH: &str = "reports";
+const HIDDEN_MESSAGES_PATH: &str = "hidden_messages";
+const BLOCKED_SENDERS_PATH: &str = "blocked_senders";
+const AUDIT_PATH: &str = "audit";
+const POLICY_PATH: &str = "policy";
+const SYNC_PATH: &str = "sync";
+const HISTORY_PATH: &str = "history";
+const CAPABILITIES_PATH: &str = "capabilities";
+
+const MESSAGE_SIZE_LIMIT: u64 = 1_000 * 100; // 100KB
+const REPORT_SIZE_LIMIT: u64 = 1_000 * 2; // 2KB
 
 lazy_static! {
     // Static settings
@@ -58,6 +75,15 @@ async fn main() {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("no global subscriber has been set");
 
+    // Run operational subcommand
+    if SETTINGS.command != Command::Serve {
+        if let Err(err) = commands::run(&SETTINGS.command) {
+            error!(message = "command failed", error = %err);
+            process::exit(1);
+        }
+        return;
+    }
+
     // Initialize databases
     let db = Database::try_new(&SETTINGS.db_path).expect("failed to open database");
     let pubsub_db = PubSubDatabase::new(&SETTINGS.pubsub_db_path).expect("failed to open database");
@@ -89,7 +115,7 @@ async fn main() {
     connector.set_connect_timeout(Some(Duration::from_secs(SETTINGS.peering.timeout)));
 
     // Setup peer state
-    let peer_handler = PeerHandler::new(peers);
+    let peer_handler = PeerHandler::new(peers, peering::server_info());
     if let Err(err) = peer_handler.inflate().await {
         error!(message = "failed to inflate peer list", error = %err)
     };
@@ -100,203 +126,122 @@ async fn main() {
     }
 
     // Token cache
-    let token_cache = TokenCache::default();
-
-    // Setup ZMQ stream
-    let mut subscriber = async_zmq::subscribe(&SETTINGS.bitcoin_rpc.zmq_address)
-        .unwrap()
-        .connect()
-        .unwrap();
-    subscriber.set_subscribe("hashblock").unwrap(); // Unrecoverable
-
-    // Start broadcast heartbeat
-    let token_cache_inner = token_cache.clone();
-    let peer_handler_inner = peer_handler.clone();
-    let db_inner = db.clone();
-    let broadcast_heartbeat = async move {
-        while let Some(val) = subscriber.next().await {
-            if let Ok(inner) = val {
-                if let Some(block) = inner.get(1) {
-                    info!(message = "found block", block_id = %hex::encode(block.as_ref()));
-                    token_cache_inner
-                        .broadcast_block(&peer_handler_inner, &db_inner)
-                        .await;
-                }
-            }
-        }
-    };
-    tokio::spawn(broadcast_heartbeat);
+    let token_cache = TokenCache::load(db.clone()).expect("failed to load token cache");
 
     // Peer state
-    let peer_handler = warp::any().map(move || peer_handler.clone());
-
-    // Database state
-    let db_state = warp::any().map(move || db.clone());
+    let peer_handler_sync = peer_handler.clone();
+    let peer_handler_heartbeat = peer_handler.clone();
 
     // PubSub Database state
-    let pubsub_db_state = warp::any().map(move || pubsub_db.clone());
+    let pubsub_db_sync = pubsub_db.clone();
+
+    // PubSub message broadcast state
+    let (message_bus, _) = broadcast::channel(pubsub::BROADCAST_CHANNEL_CAPACITY);
+    let message_bus_sync = message_bus.clone();
+
+    // Topic moderation state
+    let moderation = TopicModeration::new(
+        SETTINGS.moderation.banned_topics.clone(),
+        SETTINGS
+            .moderation
+            .min_burns
+            .iter()
+            .map(|min_burn| (min_burn.topic.clone(), min_burn.amount))
+            .collect(),
+    );
+    let moderation_sync = moderation.clone();
+
+    // Burn policy state
+    let policy = BurnPolicy {
+        min_burn: SETTINGS.policy.min_burn,
+        min_burn_per_byte: SETTINGS.policy.min_burn_per_byte,
+        max_transactions: SETTINGS.limits.burn_transactions,
+    };
 
     // Initialize bitcoin client
-    let bitcoin_client = BitcoinClientHTTP::new(
-        SETTINGS.bitcoin_rpc.address.clone(),
-        SETTINGS.bitcoin_rpc.username.clone(),
-        SETTINGS.bitcoin_rpc.password.clone(),
+    let bitcoin_client = FailoverClient::new(
+        std::iter::once(&SETTINGS.bitcoin_rpc.address)
+            .chain(&SETTINGS.bitcoin_rpc.fallback_addresses)
+            .map(|address| SETTINGS.bitcoin_rpc.client(address))
+            .collect(),
     );
 
-    // Address string converter
-    let addr_base = warp::path::param().and_then(|addr_str: String| async move {
-        net::address_decode(&addr_str).map_err(warp::reject::custom)
-    });
-
-    // Token generator
-    let token_scheme = Arc::new(ChainCommitmentScheme::from_client(bitcoin_client.clone()));
-    let token_scheme_state = warp::any().map(move || token_scheme.clone());
-
-    // Token cache state
-    let token_cache_state = warp::any().map(move || token_cache.clone());
-
-    // Bitcoin client state
-    let bitcoin_client_state = warp::any().map(move || bitcoin_client.clone());
-
-    // Protection
-    let addr_protected = addr_base
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.metadata_size,
-        ))
-        .and(warp::body::bytes())
-        .and(warp::header::headers_cloned())
-        .and(token_scheme_state.clone())
-        .and_then(move |addr, body, headers, token_scheme| {
-            net::pop_protection(addr, body, headers, token_scheme).map_err(warp::reject::custom)
-        })
-        .untuple_one();
-
-    // Metadata handlers
-    let metadata_get = warp::path(METADATA_PATH)
-        .and(addr_base)
-        .and(warp::get())
-        .and(warp::header::headers_cloned())
-        .and(db_state.clone())
-        .and(peer_handler.clone())
-        .and_then(move |addr, headers, db, peer_handler| {
-            net::get_metadata(addr, headers, db, peer_handler).map_err(warp::reject::custom)
-        });
-    let metadata_put = warp::path(METADATA_PATH)
-        .and(addr_protected)
-        .and(warp::put())
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.metadata_size,
-        ))
-        .and(db_state.clone())
-        .and(token_cache_state)
-        .and_then(
-            move |addr, auth_wrapper_raw, auth_wrapper, raw_token, db, token_cache| {
-                net::put_metadata(
-                    addr,
-                    auth_wrapper_raw,
-                    auth_wrapper,
-                    raw_token,
-                    db,
-                    token_cache,
-                )
-                .map_err(warp::reject::custom)
-            },
-        );
-
-    // Peer handler
-    let peers_get = warp::path(PEERS_PATH)
-        .and(warp::get())
-        .and(peer_handler)
-        .and_then(move |peer_handler| net::get_peers(peer_handler).map_err(warp::reject::custom));
-
-    let payload_digest_path_param =
-        warp::path::param().and_then(|payload_digest: String| async move {
-            hex::decode(&payload_digest).map_err(|_| warp::reject::not_found())
+    // Bitcoin client health checks
+    if !SETTINGS.bitcoin_rpc.fallback_addresses.is_empty() {
+        let bitcoin_client = bitcoin_client.clone();
+        tokio::spawn(async move {
+            let mut health_check_interval = interval(Duration::from_millis(
+                SETTINGS.bitcoin_rpc.health_check_interval,
+            ));
+            loop {
+                health_check_interval.tick().await;
+                if bitcoin_client.health_check().await == 0 {
+                    warn!("no healthy bitcoin nodes");
+                }
+            }
         });
-
-    #[derive(Deserialize)]
-    struct MessageGetQueryParameters {
-        topic: String,
-        from: i64,
-        to: i64,
     }
-    let messages_get = warp::path(MESSAGES_PATH)
-        .and(warp::get())
-        .and(pubsub_db_state.clone())
-        .and(warp::query::<MessageGetQueryParameters>())
-        .and_then(|db: PubSubDatabase, params: MessageGetQueryParameters| {
-            pubsub::get_messages(db, params.topic, params.from, params.to)
-        });
-
-    let messages_get_id = warp::path(MESSAGES_PATH)
-        .and(warp::get())
-        .and(pubsub_db_state.clone())
-        .and(payload_digest_path_param)
-        .and_then(|db: PubSubDatabase, payload_digest: Vec<u8>| {
-            pubsub::get_message(db, payload_digest)
-        });
 
-    let messages_put = warp::path(MESSAGES_PATH)
-        .and(warp::put())
-        .and(pubsub_db_state.clone())
-        .and(bitcoin_client_state.clone())
-        .and(warp::body::content_length_limit(100_000))
-        .and(warp::body::bytes())
-        .and_then(move |db, bitcoin_client, body| {
-            println!("Received new message");
-            let wrapper = AuthWrapper::decode(body).unwrap();
-            pubsub::put_message(db, bitcoin_client, wrapper)
-        });
-
-    // Payment handler
-    let payments = warp::path(PAYMENTS_PATH)
-        .and(warp::post())
-        .and(warp::header::headers_cloned())
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.payment_size,
-        ))
-        .and(warp::body::bytes())
-        .and_then(move |headers, body| {
-            preprocess_payment(headers, body)
-                .map_err(net::PaymentError::Preprocess)
-                .map_err(warp::reject::custom)
-        })
-        .and(bitcoin_client_state.clone())
-        .and_then(move |payment, bitcoin_client| async move {
-            net::process_payment(payment, bitcoin_client)
+    // Start message sync heartbeat
+    if SETTINGS.peering.enabled {
+        let bitcoin_client_sync = bitcoin_client.clone();
+        let sync_heartbeat = async move {
+            let mut since = 0;
+            let mut sync_interval = interval(Duration::from_millis(SETTINGS.peering.sync_interval));
+            loop {
+                sync_interval.tick().await;
+                let pull_time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as i64;
+                match pubsub::pull_messages(
+                    &peer_handler_sync,
+                    &pubsub_db_sync,
+                    &bitcoin_client_sync,
+                    &message_bus_sync,
+                    &moderation_sync,
+                    &policy,
+                    since,
+                )
                 .await
-                .map_err(warp::reject::custom)
-        });
+                {
+                    Ok(accepted) => {
+                        info!(message = "synced messages", accepted);
+                        // Overlap pulls to tolerate clock skew between peers
+                        since = pull_time - SETTINGS.peering.sync_interval as i64;
+                    }
+                    Err(err) => error!(message = "failed to sync messages", error = %err),
+                }
+            }
+        };
+        tokio::spawn(sync_heartbeat);
+    }
 
-    // Root handler
-    let root = warp::path::end()
-        .and(warp::get())
-        .and(warp::fs::file("./static/index.html"));
+    // Start broadcast heartbeat
+    tokio::spawn(peering::broadcast_heartbeat(
+        token_cache.clone(),
+        peer_handler_heartbeat,
+        bitcoin_client.clone(),
+    ));
 
     // CORs
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_methods(vec![Method::GET, Method::PUT, Method::POST, Method::DELETE])
-        .allow_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
-        .expose_headers(vec![
-            header::AUTHORIZATION,
-            header::ACCEPT,
-            header::LOCATION,
-        ])
+    let cors = cashweb_server_core::cors()
+        .allow_header(header::IF_MATCH)
         .build();
 
     // Init REST API
-    let rest_api = root
-        .or(payments)
-        .or(metadata_get)
-        .or(metadata_put)
-        .or(peers_get)
-        .or(messages_get)
-        .or(messages_get_id)
-        .or(messages_put)
-        .recover(net::handle_rejection)
-        .with(cors)
-        .with(warp::trace::request());
+    let rest_api = routes::routes(
+        db,
+        pubsub_db,
+        bitcoin_client,
+        message_bus,
+        peer_handler,
+        token_cache,
+        moderation,
+        policy,
+    )
+    .with(cors)
+    .with(warp::trace::request());
 
     // If monitoring is enabled
     #[cfg(feature = "monitoring")]
@@ -304,16 +249,18 @@ async fn main() {
         info!(monitoring = true);
 
         // Init Prometheus server
-        let prometheus_server = warp::path("metrics").map(monitoring::export);
-        let prometheus_task = warp::serve(prometheus_server).run(SETTINGS.bind_prom);
+        let prometheus_server = warp::path("metrics").map(monitoring::export).boxed();
+        let prometheus_task = listener::serve(prometheus_server, &SETTINGS.bind_prom);
 
         // Init REST API
-        let rest_api = rest_api.with(warp::log::custom(monitoring::measure));
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
+        let rest_api = rest_api
+            .with(warp::log::custom(monitoring::measure))
+            .boxed();
+        let rest_api_task = listener::serve(rest_api, &SETTINGS.bind);
 
         // Spawn servers
         tokio::spawn(prometheus_task);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        rest_api_task.await;
     }
 
     // If monitoring is disabled
@@ -321,7 +268,6 @@ async fn main() {
     {
         info!(monitoring = false);
 
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        listener::serve(rest_api.boxed(), &SETTINGS.bind).await;
     }
 }
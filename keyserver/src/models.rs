@@ -5,3 +5,7 @@ pub mod database {
 pub mod broadcast {
     include!(concat!(env!("OUT_DIR"), "/broadcast.rs"));
 }
+
+pub mod capabilities {
+    include!(concat!(env!("OUT_DIR"), "/capabilities.rs"));
+}
@@ -1,5 +1,6 @@
+pub use cashweb_server_core::monitoring::export;
 use lazy_static::lazy_static;
-use prometheus::{CounterVec, Encoder, HistogramVec, TextEncoder};
+use prometheus::{CounterVec, HistogramVec, IntCounter, IntCounterVec, IntGauge};
 use warp::filters::log::Info;
 
 use prometheus_static_metric::make_static_metric;
@@ -79,6 +80,57 @@ lazy_static! {
     )
     .unwrap();
     pub static ref HTTP_ELAPSED: RequestDurationHistogram = RequestDurationHistogram::from(&HTTP_ELAPSED_VEC);
+
+    // Payment funnel counters
+    pub static ref PAYMENT_INVOICE_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "payment_invoice_total",
+        "Total number of payment requests issued, by the route requiring payment.",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref PAYMENT_RECEIVED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "payment_received_total",
+        "Total number of payments accepted."
+    )
+    .unwrap();
+    pub static ref PAYMENT_REJECTED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "payment_rejected_total",
+        "Total number of payments rejected, by reason.",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref TOKEN_ISSUED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "token_issued_total",
+        "Total number of tokens issued, by route.",
+        &["route"]
+    )
+    .unwrap();
+
+    // Token cache
+    pub static ref TOKEN_CACHE_SIZE: IntGauge = prometheus::register_int_gauge!(
+        "token_cache_size",
+        "Number of addresses awaiting broadcast."
+    )
+    .unwrap();
+    pub static ref BROADCAST_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "broadcast_total",
+        "Total number of metadata broadcasts by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+
+    // Peer push guards
+    pub static ref PEER_CIRCUITS_OPEN: IntGauge = prometheus::register_int_gauge!(
+        "peer_circuits_open",
+        "Number of peers whose pushes are paused after repeated failures."
+    )
+    .unwrap();
+    pub static ref PUSH_THROTTLED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "push_throttled_total",
+        "Total number of pushes withheld from peers, by reason.",
+        &["reason"]
+    )
+    .unwrap();
 }
 
 pub fn measure(info: Info) {
@@ -95,12 +147,3 @@ pub fn measure(info: Info) {
         .get(route)
         .observe(duration_secs as f64);
 }
-
-pub fn export() -> Vec<u8> {
-    let metric_families = prometheus::gather();
-
-    let mut buffer = Vec::new();
-    let encoder = TextEncoder::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-    buffer
-}
@@ -0,0 +1,121 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message as _;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::error;
+use warp::{
+    http::{HeaderValue, Response},
+    hyper::Body,
+    path::FullPath,
+    reject::{Reject, Rejection},
+    Filter, Reply,
+};
+
+use crate::{
+    db::Database,
+    models::database::{AuditEntry, AuditLog},
+    net::{generate_request_id, is_valid_request_id, ToResponse, REQUEST_ID},
+};
+
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1_000;
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("limit exceeds {0}")]
+    Limit(usize),
+}
+
+impl Reject for AuditError {}
+
+impl ToResponse for AuditError {
+    fn to_status(&self) -> u16 {
+        400
+    }
+}
+
+/// Record a successful operation in the audit log.
+///
+/// Failing to record is logged rather than returned, as the operation has already been applied.
+fn record(database: &Database, action: &str, path: &str, request_id: String) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let entry = AuditEntry {
+        timestamp,
+        action: action.to_string(),
+        path: path.to_string(),
+        request_id,
+    };
+    let mut raw_entry = Vec::with_capacity(entry.encoded_len());
+    entry.encode(&mut raw_entry).unwrap(); // This is safe
+
+    if let Err(err) = database.push_audit_entry(timestamp, &raw_entry) {
+        error!(message = "failed to record audit entry", action = %action, error = %err);
+    }
+}
+
+/// Wrap a privileged or mutating route, recording its successful responses in the audit log.
+///
+/// The request is identified by the client's `X-Request-Id` header, if valid, else by a generated
+/// ID, which is returned to the client.
+pub fn audited<F, R>(
+    action: &'static str,
+    filter: F,
+    database: Database,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone,
+    R: Reply,
+{
+    warp::path::full()
+        .and(warp::header::optional::<String>(REQUEST_ID))
+        .and(filter)
+        .map(
+            move |path: FullPath, request_id: Option<String>, reply: R| {
+                let mut response = reply.into_response();
+                if response.status().is_success() {
+                    let request_id = request_id
+                        .filter(|request_id| is_valid_request_id(request_id))
+                        .unwrap_or_else(generate_request_id);
+                    record(&database, action, path.as_str(), request_id.clone());
+                    response.headers_mut().insert(
+                        REQUEST_ID,
+                        HeaderValue::from_str(&request_id).unwrap(), // This is safe
+                    );
+                }
+                response
+            },
+        )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    since: Option<u64>,
+    limit: Option<usize>,
+}
+
+/// Handles audit log GET requests, returning entries recorded at or after `since` milliseconds,
+/// oldest first.
+pub async fn get_audit_log(
+    query: AuditQuery,
+    database: Database,
+) -> Result<Response<Body>, AuditError> {
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    if limit > MAX_AUDIT_LIMIT {
+        return Err(AuditError::Limit(MAX_AUDIT_LIMIT));
+    }
+
+    let entries = database
+        .get_audit_entries(query.since.unwrap_or(0), limit)
+        .into_iter()
+        .filter_map(|raw_entry| AuditEntry::decode(&raw_entry[..]).ok())
+        .collect();
+    let audit_log = AuditLog { entries };
+    let mut raw_audit_log = Vec::with_capacity(audit_log.encoded_len());
+    audit_log.encode(&mut raw_audit_log).unwrap(); // This is safe
+
+    Ok(Response::builder().body(Body::from(raw_audit_log)).unwrap())
+}
@@ -0,0 +1,183 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use cashweb::secp256k1::key::PublicKey;
+use prost::Message as _;
+use warp::{http::Response, hyper::Body};
+
+use crate::{
+    models::capabilities::{Capabilities, Endpoint, Limits, MetadataTier, Pricing},
+    ADMIN_PATH, AUDIT_PATH, BANNED_TOPICS_PATH, BLOCKED_SENDERS_PATH, BURNS_PATH,
+    CAPABILITIES_PATH, HIDDEN_MESSAGES_PATH, HISTORY_PATH, MESSAGES_PATH, MESSAGE_SIZE_LIMIT,
+    METADATA_PATH, PAYMENTS_PATH, PEERS_PATH, POLICY_PATH, REPLIES_PATH, REPORTS_PATH,
+    REPORT_SIZE_LIMIT, REVOCATIONS_PATH, SETTINGS, SYNC_PATH, WS_PATH,
+};
+
+/// Scheme of the tokens protecting metadata updates.
+const POP_SCHEME: &str = "POP";
+
+fn endpoint(path: String, methods: &[&str]) -> Endpoint {
+    Endpoint {
+        path,
+        methods: methods.iter().map(|method| method.to_string()).collect(),
+    }
+}
+
+/// List the endpoints served, including the admin endpoints only if they are enabled.
+pub fn endpoints(admin: bool) -> Vec<Endpoint> {
+    let mut endpoints = vec![
+        endpoint(
+            format!("/{}", CAPABILITIES_PATH),
+            &["GET", "HEAD", "OPTIONS"],
+        ),
+        endpoint(
+            format!("/{}/{{address}}", METADATA_PATH),
+            &["GET", "HEAD", "PUT"],
+        ),
+        endpoint(
+            format!("/{}/{{address}}/{}", METADATA_PATH, HISTORY_PATH),
+            &["GET", "HEAD"],
+        ),
+        endpoint(format!("/{}", REVOCATIONS_PATH), &["PUT"]),
+        endpoint(
+            format!("/{}/{{public_key}}", REVOCATIONS_PATH),
+            &["GET", "HEAD"],
+        ),
+        endpoint(format!("/{}", PAYMENTS_PATH), &["POST"]),
+        endpoint(format!("/{}", PEERS_PATH), &["GET"]),
+        endpoint(format!("/{}", MESSAGES_PATH), &["GET", "HEAD", "PUT"]),
+        endpoint(
+            format!("/{}/{}", MESSAGES_PATH, SYNC_PATH),
+            &["GET", "HEAD"],
+        ),
+        endpoint(
+            format!("/{}/{{payload_digest}}", MESSAGES_PATH),
+            &["GET", "HEAD"],
+        ),
+        endpoint(
+            format!("/{}/{{payload_digest}}/{}", MESSAGES_PATH, REPLIES_PATH),
+            &["GET", "HEAD"],
+        ),
+        endpoint(
+            format!("/{}/{{payload_digest}}/{}", MESSAGES_PATH, BURNS_PATH),
+            &["GET", "HEAD"],
+        ),
+        endpoint(format!("/{}/{}", WS_PATH, MESSAGES_PATH), &["GET"]),
+        endpoint(format!("/{}", REPORTS_PATH), &["POST"]),
+        endpoint(format!("/{}", POLICY_PATH), &["GET"]),
+    ];
+    if admin {
+        endpoints.extend(vec![
+            endpoint(format!("/{}/{}", ADMIN_PATH, BANNED_TOPICS_PATH), &["GET"]),
+            endpoint(
+                format!("/{}/{}/{{topic}}", ADMIN_PATH, BANNED_TOPICS_PATH),
+                &["PUT", "DELETE"],
+            ),
+            endpoint(format!("/{}/{}", ADMIN_PATH, REPORTS_PATH), &["GET"]),
+            endpoint(
+                format!("/{}/{}/{{payload_digest}}", ADMIN_PATH, REPORTS_PATH),
+                &["DELETE"],
+            ),
+            endpoint(
+                format!(
+                    "/{}/{}/{{payload_digest}}",
+                    ADMIN_PATH, HIDDEN_MESSAGES_PATH
+                ),
+                &["DELETE"],
+            ),
+            endpoint(
+                format!("/{}/{}/{{pubkey_hash}}", ADMIN_PATH, BLOCKED_SENDERS_PATH),
+                &["DELETE"],
+            ),
+            endpoint(format!("/{}/{}", ADMIN_PATH, AUDIT_PATH), &["GET"]),
+        ]);
+    }
+    endpoints
+}
+
+/// List the optional features enabled, both in the settings and at compile time.
+fn features() -> Vec<String> {
+    let mut features = Vec::new();
+    if SETTINGS.peering.enabled {
+        features.push("peering");
+    }
+    if SETTINGS.moderation.admin_token.is_some() {
+        features.push("moderation");
+    }
+    if SETTINGS.identity.sign_responses {
+        features.push("signed_responses");
+    }
+    if SETTINGS.payments.webhook_url.is_some() {
+        features.push("payment_webhook");
+    }
+    #[cfg(feature = "monitoring")]
+    features.push("monitoring");
+    features.into_iter().map(str::to_string).collect()
+}
+
+/// Construct the serialized capabilities of the server.
+pub fn construct_capabilities(identity: Option<&PublicKey>) -> Bytes {
+    let limits = Limits {
+        metadata_size: SETTINGS.limits.metadata_size,
+        payment_size: SETTINGS.limits.payment_size,
+        message_size: MESSAGE_SIZE_LIMIT,
+        report_size: REPORT_SIZE_LIMIT,
+        metadata_history: SETTINGS.limits.metadata_history as u64,
+        burn_transactions: SETTINGS.limits.burn_transactions as u64,
+        payment_transactions: SETTINGS.limits.payment_transactions as u64,
+    };
+    let metadata_tiers = SETTINGS
+        .limits
+        .metadata_tiers
+        .iter()
+        .map(|tier| MetadataTier {
+            size: tier.size,
+            commitment: tier.commitment,
+        })
+        .collect();
+    let pricing = Pricing {
+        commitment_fee: SETTINGS.payments.commitment_fee,
+        metadata_tiers,
+        min_burn: SETTINGS.policy.min_burn,
+        min_burn_per_byte: SETTINGS.policy.min_burn_per_byte,
+    };
+    let capabilities = Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        network: SETTINGS.network.clone(),
+        endpoints: endpoints(SETTINGS.moderation.admin_token.is_some()),
+        limits: Some(limits),
+        token_schemes: vec![POP_SCHEME.to_string()],
+        pricing: Some(pricing),
+        features: features(),
+        identity: identity
+            .map(|public_key| public_key.serialize().to_vec())
+            .unwrap_or_default(),
+    };
+    let mut raw_capabilities = Vec::with_capacity(capabilities.encoded_len());
+    capabilities.encode(&mut raw_capabilities).unwrap(); // This is safe
+    Bytes::from(raw_capabilities)
+}
+
+/// Handles capabilities GET and OPTIONS requests.
+pub async fn get_capabilities(raw_capabilities: Bytes) -> Result<Response<Body>, Infallible> {
+    Ok(Response::builder()
+        .body(Body::from(raw_capabilities))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_endpoints() {
+        let public = endpoints(false);
+        let all = endpoints(true);
+        assert!(public.len() < all.len());
+        assert!(!public
+            .iter()
+            .any(|endpoint| endpoint.path.starts_with("/admin/")));
+        assert!(all.iter().any(|endpoint| endpoint.path == "/admin/audit"));
+        assert!(all.iter().all(|endpoint| !endpoint.methods.is_empty()));
+    }
+}
@@ -0,0 +1,125 @@
+use std::convert::Infallible;
+
+use cashweb::secp256k1::{key::PublicKey, Error as SecpError, Message, Secp256k1, SecretKey};
+use ring::digest::{digest, SHA256};
+use thiserror::Error;
+use tracing::error;
+use warp::{http::Response, hyper::Body, Reply};
+
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const IDENTITY_HEADER: &str = "X-Identity";
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("failed to decode hex: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("invalid private key: {0}")]
+    PrivateKey(SecpError),
+}
+
+/// Keypair identifying this server.
+///
+/// Used to sign response bodies so that clients can hold the server accountable for the
+/// data it serves.
+#[derive(Clone, Debug)]
+pub struct ServerIdentity {
+    private_key: SecretKey,
+    public_key: PublicKey,
+}
+
+impl ServerIdentity {
+    /// Construct the identity from a hex encoded private key.
+    pub fn from_hex(private_key: &str) -> Result<Self, IdentityError> {
+        let raw_private_key = hex::decode(private_key)?;
+        let private_key =
+            SecretKey::from_slice(&raw_private_key).map_err(IdentityError::PrivateKey)?;
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+
+    /// The identity public key.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Sign the SHA256 digest of a body, returning the compact signature.
+    pub fn sign(&self, body: &[u8]) -> [u8; 64] {
+        let body_digest = digest(&SHA256, body);
+        let message = Message::from_slice(body_digest.as_ref()).unwrap(); // This is safe
+        Secp256k1::signing_only()
+            .sign(&message, &self.private_key)
+            .serialize_compact()
+    }
+}
+
+/// Attach an `X-Signature` header, signing the response body, and an `X-Identity` header
+/// containing the public key.
+///
+/// Responses are passed through untouched if no identity is given.
+pub async fn attest(
+    reply: impl Reply,
+    identity: Option<ServerIdentity>,
+) -> Result<Response<Body>, Infallible> {
+    let response = reply.into_response();
+    let identity = match identity {
+        Some(some) => some,
+        None => return Ok(response),
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let raw_body = match hyper::body::to_bytes(body).await {
+        Ok(ok) => ok,
+        Err(err) => {
+            error!(message = "failed to buffer response body", error = %err);
+            return Ok(Response::builder().status(500).body(Body::empty()).unwrap());
+        }
+    };
+
+    let signature = hex::encode(identity.sign(&raw_body));
+    let public_key = hex::encode(identity.public_key().serialize());
+    parts
+        .headers
+        .insert(SIGNATURE_HEADER, signature.parse().unwrap()); // This is safe
+    parts
+        .headers
+        .insert(IDENTITY_HEADER, public_key.parse().unwrap()); // This is safe
+
+    Ok(Response::from_parts(parts, Body::from(raw_body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb::secp256k1::Signature;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn attest_response() {
+        let identity = ServerIdentity::from_hex(&"01".repeat(32)).unwrap();
+        let response = Response::builder().body(Body::from("hello")).unwrap();
+
+        let response = attest(response, Some(identity.clone())).await.unwrap();
+        let raw_signature = hex::decode(response.headers()[SIGNATURE_HEADER].as_bytes()).unwrap();
+        let raw_public_key = hex::decode(response.headers()[IDENTITY_HEADER].as_bytes()).unwrap();
+        let raw_body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(raw_body.as_ref(), b"hello");
+
+        // Signature verifies against the public key
+        let public_key = PublicKey::from_slice(&raw_public_key).unwrap();
+        assert_eq!(&public_key, identity.public_key());
+        let signature = Signature::from_compact(&raw_signature).unwrap();
+        let body_digest = digest(&SHA256, b"hello");
+        let message = Message::from_slice(body_digest.as_ref()).unwrap();
+        Secp256k1::verification_only()
+            .verify(&message, &signature, &public_key)
+            .unwrap();
+
+        // No identity
+        let response = Response::builder().body(Body::from("hello")).unwrap();
+        let response = attest(response, None).await.unwrap();
+        assert!(response.headers().get(SIGNATURE_HEADER).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use cashweb::auth_wrapper::{ParseError, VerifyError};
+use cashweb::auth_wrapper::VerifyError;
 use thiserror::Error;
 use warp::reject::Reject;
 
@@ -8,10 +8,14 @@ use crate::net::ToResponse;
 pub enum PutMetadataError {
     #[error("failed to write to database: {0}")]
     Database(rocksdb::Error),
-    #[error("failed to verify authorization wrapper: {0}")]
-    InvalidAuthWrapper(ParseError),
     #[error("failed to parse authorization wrapper: {0}")]
     VerifyAuthWrapper(VerifyError),
+    #[error("metadata does not match if-match precondition")]
+    PreconditionFailed,
+    #[error("signing key has been revoked")]
+    Revoked,
+    #[error("insufficient commitment value: {0} < {1}")]
+    InsufficientCommitment(u64, u64),
 }
 
 impl From<rocksdb::Error> for PutMetadataError {
@@ -26,6 +30,8 @@ impl ToResponse for PutMetadataError {
     fn to_status(&self) -> u16 {
         match self {
             Self::Database(_) => 500,
+            Self::PreconditionFailed => 412,
+            Self::Revoked => 403,
             _ => 400,
         }
     }
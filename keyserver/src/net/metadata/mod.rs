@@ -1,16 +1,29 @@
 mod errors;
+mod negative_cache;
 
 pub use crate::net::metadata::errors::*;
+pub use crate::net::metadata::negative_cache::*;
 
-use std::fmt;
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bitcoincash_addr::Address;
 use bytes::Bytes;
-use cashweb::auth_wrapper::AuthWrapper;
+use cashweb::{
+    auth_wrapper::{AuthWrapper, AuthWrapperSet},
+    keyserver::AddressMetadata,
+};
 use http::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    header::{
+        HeaderMap, HeaderValue, AUTHORIZATION, CACHE_CONTROL, ETAG, IF_NONE_MATCH, LAST_MODIFIED,
+        VARY,
+    },
     Request,
 };
+use lazy_static::lazy_static;
 use prost::Message as _;
 use tokio::task;
 use tower_service::Service;
@@ -19,17 +32,84 @@ use warp::{http::Response, hyper::Body};
 use crate::{
     db::Database,
     models::database::DatabaseWrapper,
-    net::{HEADER_VALUE_FALSE, SAMPLING},
-    peering::{PeerHandler, TokenCache},
+    net::{
+        cache_control, etag, etag_matches, get_signer_revocation, last_modified, ProtectedBody,
+        HEADER_VALUE_FALSE, REVOCATION, SAMPLING,
+    },
+    peering::{sample_metadata, PeerHandler, SampleBudget, TokenCache},
+    settings::MetadataTier,
     SETTINGS,
 };
 
+lazy_static! {
+    /// Serializes metadata writes so that `If-Match` preconditions are checked atomically.
+    static ref WRITE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Construct a metadata response, or a 304 response if the `If-None-Match` header matches the
+/// payload digest of the `AuthWrapper`.
+///
+/// If the signing key has been revoked then the revocation is attached in the `X-Revocation`
+/// header.
+fn metadata_response(
+    headers: &HeaderMap,
+    raw_auth_wrapper: Bytes,
+    token: String,
+    revocation: Option<Vec<u8>>,
+) -> Response<Body> {
+    let builder = Response::builder().header(AUTHORIZATION, token);
+    let builder = match revocation {
+        Some(raw_revocation) => {
+            let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+            builder.header(
+                REVOCATION,
+                base64::encode_config(raw_revocation, url_safe_config),
+            )
+        }
+        None => builder,
+    };
+    let builder = builder
+        .header(
+            CACHE_CONTROL,
+            cache_control(SETTINGS.cache.metadata_max_age),
+        )
+        .header(VARY, format!("Accept-Encoding, {}", SAMPLING));
+
+    // Sampled peers may return malformed wrappers, these are passed through without an ETag
+    let auth_wrapper = match AuthWrapper::decode(&raw_auth_wrapper[..]) {
+        Ok(ok) => ok,
+        Err(_) => return builder.body(Body::from(raw_auth_wrapper)).unwrap(),
+    };
+    let tag = etag(&auth_wrapper.digest());
+
+    // Derive Last-Modified from the signed timestamp
+    let timestamp = AddressMetadata::decode(&auth_wrapper.payload[..])
+        .map(|metadata| metadata.timestamp)
+        .unwrap_or_default();
+    let builder = match last_modified(timestamp) {
+        Some(last_modified) => builder.header(LAST_MODIFIED, last_modified),
+        None => builder,
+    };
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| etag_matches(value, &tag));
+
+    let builder = builder.header(ETAG, tag);
+    if not_modified {
+        builder.status(304).body(Body::empty()).unwrap()
+    } else {
+        builder.body(Body::from(raw_auth_wrapper)).unwrap()
+    }
+}
+
 /// Handles metadata GET requests.
 pub async fn get_metadata<S>(
     addr: Address,
     headers: HeaderMap,
     database: Database,
     peer_handler: PeerHandler<S>,
+    negative_cache: NegativeCache,
 ) -> Result<Response<Body>, GetMetadataError>
 where
     S: Service<Request<Body>, Response = Response<Body>>,
@@ -51,10 +131,15 @@ where
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
         let token = format!("POP {}", base64::encode_config(raw_token, url_safe_config));
 
-        return Ok(Response::builder()
-            .header(AUTHORIZATION, token)
-            .body(Body::from(raw_auth_wrapper))
-            .unwrap()); // TODO: Headers
+        // Serve stale metadata alongside the revocation of its key
+        let revocation = get_signer_revocation(&database, &raw_auth_wrapper)?;
+
+        return Ok(metadata_response(
+            &headers,
+            raw_auth_wrapper.into(),
+            token,
+            revocation,
+        ));
     }
 
     // If MAX_FORWARDS is 0 then don't sample peers
@@ -62,58 +147,153 @@ where
         return Err(GetMetadataError::NotFound);
     }
 
+    // If recently missed then don't sample peers
+    if negative_cache.contains(addr.as_body()) {
+        return Err(GetMetadataError::NotFound);
+    }
+
     // Sample peers
     let addr_str = addr.encode().unwrap();
-    match peer_handler
-        .get_keyserver_manager()
-        .uniform_sample_metadata(&addr_str, SETTINGS.peering.pull_fan_size)
-        .await
+    match sample_metadata(
+        peer_handler.get_keyserver_manager(),
+        &addr_str,
+        SETTINGS.peering.pull_fan_size,
+        SampleBudget::from_settings(),
+    )
+    .await
     {
-        Ok(sample_response) => {
-            if let Some((_, metadata_package)) = sample_response.response {
-                let token = metadata_package.token;
-                let raw_auth_wrapper = metadata_package.raw_auth_wrapper;
-                Ok(Response::builder()
-                    .header(AUTHORIZATION, token)
-                    .body(Body::from(raw_auth_wrapper))
-                    .unwrap())
-            } else {
-                Err(GetMetadataError::NotFound)
-            }
+        Some((_, metadata_package)) => {
+            let token = metadata_package.token;
+            let raw_auth_wrapper = metadata_package.raw_auth_wrapper;
+            let revocation = get_signer_revocation(&database, &raw_auth_wrapper)?;
+            Ok(metadata_response(
+                &headers,
+                raw_auth_wrapper,
+                token,
+                revocation,
+            ))
+        }
+        None => {
+            negative_cache.insert(addr.as_body());
+            Err(GetMetadataError::NotFound)
         }
-        _ => Err(GetMetadataError::NotFound),
     }
 }
 
+/// Checks an `If-Match` header value against the currently stored metadata.
+///
+/// Both entity tags and bare hex payload digests are accepted. The precondition fails if no
+/// metadata is stored.
+fn if_match_satisfied(
+    database: &Database,
+    addr: &[u8],
+    if_match: &str,
+) -> Result<bool, PutMetadataError> {
+    let current = match database.get_metadata(addr)? {
+        Some(some) => some,
+        None => return Ok(false),
+    };
+    let digest = match AuthWrapper::decode(&current.serialized_auth_wrapper[..]) {
+        Ok(auth_wrapper) => auth_wrapper.digest(),
+        Err(_) => return Ok(false),
+    };
+    let hex_digest = hex::encode(&digest);
+    Ok(etag_matches(if_match, &etag(&digest))
+        || if_match
+            .split(',')
+            .any(|candidate| candidate.trim().eq_ignore_ascii_case(&hex_digest)))
+}
+
+/// Calculate the value a token must commit to for metadata of the given size.
+///
+/// The largest commitment among the tiers whose size is exceeded applies.
+pub fn required_commitment(tiers: &[MetadataTier], size: usize) -> u64 {
+    tiers
+        .iter()
+        .filter(|tier| size as u64 > tier.size)
+        .map(|tier| tier.commitment)
+        .max()
+        .unwrap_or(0)
+}
+
 /// Handles metadata PUT requests.
 pub async fn put_metadata(
     addr: Address,
-    auth_wrapper_raw: Bytes,
-    auth_wrapper: AuthWrapper,
-    token_raw: Vec<u8>,
+    body: ProtectedBody,
+    if_match: Option<String>,
     db_data: Database,
     token_cache: TokenCache,
+    negative_cache: NegativeCache,
 ) -> Result<Response<Body>, PutMetadataError> {
+    let ProtectedBody {
+        raw,
+        auth_wrapper,
+        token,
+        value,
+    } = body;
+
+    // Larger metadata requires larger commitments
+    let required = required_commitment(&SETTINGS.limits.metadata_tiers, raw.len());
+    if value < required {
+        return Err(PutMetadataError::InsufficientCommitment(value, required));
+    }
+
     // Verify signatures
     auth_wrapper
-        .parse()
-        .map_err(PutMetadataError::InvalidAuthWrapper)?
         .verify()
         .map_err(PutMetadataError::VerifyAuthWrapper)?;
 
+    // Refuse metadata signed by revoked keys
+    let signing_keys = std::iter::once(&auth_wrapper.public_key).chain(
+        auth_wrapper
+            .endorsements
+            .iter()
+            .map(|endorsement| &endorsement.public_key),
+    );
+    for public_key in signing_keys {
+        if db_data.get_revocation(&public_key.serialize())?.is_some() {
+            return Err(PutMetadataError::Revoked);
+        }
+    }
+
     // Wrap with database
     let database_wrapper = DatabaseWrapper {
-        serialized_auth_wrapper: auth_wrapper_raw.to_vec(),
-        token: token_raw,
+        serialized_auth_wrapper: raw.to_vec(),
+        token,
     };
     let mut raw_database_wrapper = Vec::with_capacity(database_wrapper.encoded_len());
     database_wrapper.encode(&mut raw_database_wrapper).unwrap(); // This is safe
 
     // Put to database
     let addr_raw = addr.as_body().to_vec();
-    task::spawn_blocking(move || db_data.put_metadata(&addr_raw, &raw_database_wrapper))
-        .await
-        .unwrap()?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    task::spawn_blocking(move || {
+        let _guard = WRITE_LOCK.lock().unwrap();
+
+        // Check precondition
+        if let Some(if_match) = if_match {
+            if !if_match_satisfied(&db_data, &addr_raw, &if_match)? {
+                return Err(PutMetadataError::PreconditionFailed);
+            }
+        }
+
+        db_data.put_metadata(&addr_raw, &raw_database_wrapper)?;
+        db_data.push_metadata_history(
+            &addr_raw,
+            timestamp,
+            &raw,
+            SETTINGS.limits.metadata_history,
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap()?;
+
+    // Metadata, whether put locally or by a peer, is no longer missing
+    negative_cache.invalidate(addr.as_body());
 
     // Put token to cache
     token_cache.add_token(addr).await;
@@ -121,3 +301,141 @@ pub async fn put_metadata(
     // Respond
     Ok(Response::builder().body(Body::empty()).unwrap())
 }
+
+/// Handles metadata history GET requests.
+pub async fn get_metadata_history(
+    addr: Address,
+    database: Database,
+) -> Result<Response<Body>, GetMetadataError> {
+    let raw_history = task::spawn_blocking(move || database.get_metadata_history(addr.as_body()))
+        .await
+        .unwrap();
+    if raw_history.is_empty() {
+        return Err(GetMetadataError::NotFound);
+    }
+
+    let items = raw_history
+        .iter()
+        .map(|raw_auth_wrapper| AuthWrapper::decode(raw_auth_wrapper.as_slice()).unwrap()) // This panics if stored bytes are malformed
+        .collect();
+    let history = AuthWrapperSet { items };
+    let mut raw_history = Vec::with_capacity(history.encoded_len());
+    history.encode(&mut raw_history).unwrap(); // This is safe
+
+    Ok(Response::builder().body(Body::from(raw_history)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cashweb::auth_wrapper::SignatureScheme;
+    use rocksdb::{Options, DB};
+
+    use super::*;
+    use crate::net::ServerIdentity;
+
+    #[test]
+    fn required_commitments() {
+        let tiers = vec![
+            MetadataTier {
+                size: 3_000,
+                commitment: 20_000,
+            },
+            MetadataTier {
+                size: 1_000,
+                commitment: 5_000,
+            },
+        ];
+
+        assert_eq!(required_commitment(&tiers, 500), 0);
+        assert_eq!(required_commitment(&tiers, 1_000), 0);
+        assert_eq!(required_commitment(&tiers, 1_001), 5_000);
+        assert_eq!(required_commitment(&tiers, 4_000), 20_000);
+        assert_eq!(required_commitment(&[], 4_000), 0);
+    }
+
+    #[test]
+    fn if_match() {
+        const TEST_NAME: &str = "./tests/if_match";
+
+        let database = Database::try_new(TEST_NAME).unwrap();
+        let addr = vec![1; 20];
+
+        // Preconditions fail when nothing is stored
+        assert!(!if_match_satisfied(&database, &addr, "*").unwrap());
+
+        let auth_wrapper = AuthWrapper {
+            payload_digest: vec![0xab; 32],
+            ..Default::default()
+        };
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        let database_wrapper = DatabaseWrapper {
+            serialized_auth_wrapper: raw_auth_wrapper,
+            token: vec![],
+        };
+        let mut raw_database_wrapper = Vec::with_capacity(database_wrapper.encoded_len());
+        database_wrapper.encode(&mut raw_database_wrapper).unwrap();
+        database.put_metadata(&addr, &raw_database_wrapper).unwrap();
+
+        let hex_digest = hex::encode(vec![0xab; 32]);
+        assert!(if_match_satisfied(&database, &addr, &hex_digest).unwrap());
+        assert!(if_match_satisfied(&database, &addr, &format!("\"{}\"", hex_digest)).unwrap());
+        assert!(if_match_satisfied(&database, &addr, "*").unwrap());
+        assert!(!if_match_satisfied(&database, &addr, &hex::encode(vec![0; 32])).unwrap());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoked_signers() {
+        const TEST_NAME: &str = "./tests/revoked_signers";
+
+        let database = Database::try_new(TEST_NAME).unwrap();
+        let identity = ServerIdentity::from_hex(&"02".repeat(32)).unwrap();
+        database
+            .put_revocation(&identity.public_key().serialize(), b"revocation")
+            .unwrap();
+
+        // Metadata signed by a revoked key is refused, however the key is serialized
+        let raw_public_keys = vec![
+            identity.public_key().serialize().to_vec(),
+            identity.public_key().serialize_uncompressed().to_vec(),
+        ];
+        for raw_public_key in raw_public_keys {
+            let payload = b"metadata".to_vec();
+            let auth_wrapper = AuthWrapper {
+                public_key: raw_public_key,
+                signature: identity.sign(&payload).to_vec(),
+                scheme: SignatureScheme::Ecdsa as i32,
+                payload,
+                ..Default::default()
+            };
+            let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+            auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+            let body = ProtectedBody {
+                raw: raw_auth_wrapper.into(),
+                auth_wrapper: auth_wrapper.parse().unwrap(),
+                token: vec![],
+                value: u64::MAX,
+            };
+            let result = put_metadata(
+                Address::default(),
+                body,
+                None,
+                database.clone(),
+                TokenCache::load(database.clone()).unwrap(),
+                NegativeCache::new(Duration::from_secs(1), 1),
+            )
+            .await;
+            assert!(matches!(result, Err(PutMetadataError::Revoked)));
+        }
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+/// Addresses recently found to have no metadata, locally or on peers.
+///
+/// Requests for these addresses are answered without sampling peers until the entry expires or
+/// metadata for the address is put.
+#[derive(Clone, Debug)]
+pub struct NegativeCache {
+    misses: Arc<DashMap<Vec<u8>, Instant>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl NegativeCache {
+    /// Construct a negative cache.
+    ///
+    /// A `ttl` of zero disables the cache.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            misses: Default::default(),
+            ttl,
+            max_entries,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.ttl != Duration::from_secs(0) && self.max_entries != 0
+    }
+
+    /// Checks whether the address recently had no metadata.
+    pub fn contains(&self, addr: &[u8]) -> bool {
+        let now = Instant::now();
+        let expired = match self.misses.get(addr) {
+            Some(expiry) => now >= *expiry,
+            None => return false,
+        };
+        if expired {
+            self.misses.remove(addr);
+        }
+        !expired
+    }
+
+    /// Record that the address has no metadata.
+    pub fn insert(&self, addr: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        // Remove expired entries when full, giving up if none have expired
+        let now = Instant::now();
+        if self.misses.len() >= self.max_entries {
+            self.misses.retain(|_, expiry| now < *expiry);
+            if self.misses.len() >= self.max_entries {
+                return;
+            }
+        }
+        self.misses.insert(addr.to_vec(), now + self.ttl);
+    }
+
+    /// Forget a miss, for example after metadata for the address is put.
+    pub fn invalidate(&self, addr: &[u8]) {
+        self.misses.remove(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    #[test]
+    fn expiry_and_invalidation() {
+        let negative_cache = NegativeCache::new(Duration::from_millis(50), 2);
+        negative_cache.insert(&[1; 20]);
+        assert!(negative_cache.contains(&[1; 20]));
+        assert!(!negative_cache.contains(&[2; 20]));
+
+        negative_cache.invalidate(&[1; 20]);
+        assert!(!negative_cache.contains(&[1; 20]));
+
+        // Full caches only admit entries once others expire
+        negative_cache.insert(&[1; 20]);
+        negative_cache.insert(&[2; 20]);
+        negative_cache.insert(&[3; 20]);
+        assert!(!negative_cache.contains(&[3; 20]));
+        sleep(Duration::from_millis(60));
+        assert!(!negative_cache.contains(&[1; 20]));
+        negative_cache.insert(&[3; 20]);
+        assert!(negative_cache.contains(&[3; 20]));
+
+        // Disabled
+        let negative_cache = NegativeCache::new(Duration::from_secs(0), 2);
+        negative_cache.insert(&[1; 20]);
+        assert!(!negative_cache.contains(&[1; 20]));
+    }
+}
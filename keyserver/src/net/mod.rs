@@ -1,83 +1,52 @@
+mod audit;
+mod capabilities;
+mod identity;
 mod metadata;
 mod payments;
 mod peers;
 mod protection;
+mod revocation;
+mod webhook;
 
+pub use crate::net::audit::*;
+pub use crate::net::capabilities::*;
+pub use crate::net::identity::*;
 pub use crate::net::metadata::*;
 pub use crate::net::payments::*;
 pub use crate::net::peers::*;
 pub use crate::net::protection::*;
+pub use crate::net::revocation::*;
+pub use crate::net::webhook::*;
+pub use cashweb_server_core::{
+    address::AddressDecode, etag::*, head::*, load_shed::*, problem::*, ToResponse,
+};
 
-use std::{convert::Infallible, fmt};
+use std::convert::{Infallible, TryFrom};
 
 use bitcoincash_addr::Address;
-use thiserror::Error;
+use cashweb::bitcoin::Network;
+use cashweb_server_core::handle_common_rejection;
 use tracing::error;
-use warp::{
-    http::Response,
-    hyper::Body,
-    reject::{PayloadTooLarge, Reject, Rejection},
+use warp::{http::Response, hyper::Body, reject::Rejection};
+
+use crate::{
+    pubsub::{MessagesRpcRejection, ModerationError, ReportError},
+    SETTINGS,
 };
 
 pub const SAMPLING: &str = "Sample-Peers";
+pub const NEXT_SINCE: &str = "Next-Since";
 pub const HEADER_VALUE_FALSE: &str = "false";
 
-#[derive(Debug, Error)]
-pub struct AddressDecode(
-    bitcoincash_addr::cashaddr::DecodingError,
-    bitcoincash_addr::base58::DecodingError,
-);
-
-impl Reject for AddressDecode {}
-
-impl fmt::Display for AddressDecode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}, {}", self.0, self.1)
-    }
-}
-
-/// Helper method for decoding an address string.
+/// Helper method for decoding an address string on the configured network.
 pub fn address_decode(addr_str: &str) -> Result<Address, AddressDecode> {
-    // Convert address
-    Address::decode(addr_str).map_err(|(cash_err, base58_err)| AddressDecode(cash_err, base58_err))
-}
-
-impl ToResponse for AddressDecode {
-    fn to_status(&self) -> u16 {
-        400
-    }
-}
-
-/// Helper trait for converting errors into a response.
-pub trait ToResponse: fmt::Display + Sized {
-    /// Convert error into a status code.
-    fn to_status(&self) -> u16;
-
-    /// Convert error into a `Response`.
-    fn to_response(&self) -> Response<Body> {
-        let status = self.to_status();
-
-        if status != 500 {
-            Response::builder()
-                .status(status)
-                .body(Body::from(self.to_string()))
-                .unwrap()
-        } else {
-            Response::builder()
-                .status(status)
-                .body(Body::empty())
-                .unwrap()
-        }
-    }
+    let network = Network::try_from(SETTINGS.network.clone())
+        .map_err(|_| AddressDecode::NetworkMismatch(SETTINGS.network.clone()))?;
+    cashweb_server_core::address::address_decode(addr_str, network, &SETTINGS.address_prefixes)
 }
 
 /// Global rejection handler, takes an rejection and converts it into a `Response`.
 pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallible> {
-    if let Some(err) = err.find::<AddressDecode>() {
-        error!(message = "failed to decode address", error = %err);
-        return Ok(err.to_response());
-    }
-
     if let Some(err) = err.find::<GetMetadataError>() {
         error!(message = "failed to get metadata", error = %err);
         return Ok(err.to_response());
@@ -88,8 +57,14 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<RevocationError>() {
+        error!(message = "revocation request failed", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<PaymentError>() {
         error!(message = "payment failed", error = %err);
+        record_payment_rejection(err);
         return Ok(err.to_response());
     }
 
@@ -98,21 +73,30 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
-    if let Some(err) = err.find::<ProtectionError>() {
-        error!(message = "protection triggered", error = %err);
-        return Ok(protection_error_recovery(err).await);
+    if let Some(err) = err.find::<MessagesRpcRejection>() {
+        error!(message = "message request failed", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<ReportError>() {
+        error!(message = "report failed", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<AuditError>() {
+        error!(message = "audit log request failed", error = %err);
+        return Ok(err.to_response());
     }
 
-    if err.find::<PayloadTooLarge>().is_some() {
-        error!("payload too large");
-        return Ok(Response::builder().status(413).body(Body::empty()).unwrap());
+    if let Some(err) = err.find::<ModerationError>() {
+        error!(message = "moderation request failed", error = %err);
+        return Ok(err.to_response());
     }
 
-    if err.is_not_found() {
-        error!("page not found");
-        return Ok(Response::builder().status(404).body(Body::empty()).unwrap());
+    if let Some(err) = err.find::<ProtectionError>() {
+        error!(message = "protection triggered", error = %err);
+        return Ok(protection_error_recovery(err).await);
     }
 
-    error!(message = "unexpected error", error = ?err);
-    Ok(Response::builder().status(500).body(Body::empty()).unwrap())
+    Ok(handle_common_rejection(&err))
 }
@@ -1,4 +1,4 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bitcoincash_addr::{cashaddr, Address};
 use cashweb::{
@@ -6,9 +6,13 @@ use cashweb::{
         transaction::{self, Transaction},
         Decodable,
     },
-    bitcoin_client::{BitcoinClient, BitcoinClientHTTP, NodeError},
-    payments::{bip70, PreprocessingError},
-    token::schemes::chain_commitment::{construct_commitment, construct_token},
+    bitcoin_client::{BitcoinClient, NodeError},
+    payments::{
+        bip70,
+        zeroconf::{ZeroConfError, ZeroConfPolicy},
+        PreprocessingError,
+    },
+    token::schemes::chain_commitment::{construct_commitment, construct_split_token},
 };
 use prost::Message as _;
 use ring::digest::{digest, SHA256};
@@ -22,7 +26,10 @@ use warp::{
     reject::Reject,
 };
 
-use crate::{net::ToResponse, METADATA_PATH, PAYMENTS_PATH, SETTINGS};
+use crate::{
+    net::{self, PaymentEvent, ToResponse},
+    METADATA_PATH, PAYMENTS_PATH, SETTINGS,
+};
 
 pub const COMMITMENT_PREIMAGE_SIZE: usize = 32 + 32;
 pub const COMMITMENT_SIZE: usize = 32;
@@ -34,6 +41,12 @@ pub enum PaymentError {
     Preprocess(PreprocessingError),
     #[error("missing commitment")]
     MissingCommitment,
+    #[error("{0} commitment outputs commit to different metadata")]
+    CommitmentMismatch(usize),
+    #[error("insufficient commitment value: {0} < {1}")]
+    InsufficientCommitment(u64, u64),
+    #[error("too many transactions: {0} > {1}")]
+    TooManyTransactions(usize, usize),
     #[error("malformed tx: {0}")]
     MalformedTx(transaction::DecodeError),
     #[error("missing merchant data")]
@@ -44,10 +57,64 @@ pub enum PaymentError {
     IncorrectLengthPreimage,
     #[error("address encoding failed: {0}")]
     Address(cashaddr::EncodingError),
+    #[error("zero-conf policy violated: {0}")]
+    ZeroConf(ZeroConfError),
 }
 
 impl Reject for PaymentError {}
 
+impl PaymentError {
+    /// A short label describing why the payment was rejected.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Address(_) => "address",
+            Self::IncorrectLengthPreimage => "incorrect_length_preimage",
+            Self::Preprocess(_) => "preprocess",
+            Self::TooManyTransactions(..) => "too_many_transactions",
+            Self::MalformedTx(_) => "malformed_tx",
+            Self::MissingMerchantData => "missing_merchant_data",
+            Self::MissingCommitment => "missing_commitment",
+            Self::CommitmentMismatch(_) => "commitment_mismatch",
+            Self::InsufficientCommitment(..) => "insufficient_commitment",
+            Self::Node(err) => match err {
+                NodeError::TxAlreadyKnown(_) => "tx_already_known",
+                NodeError::MissingInputs(_) => "missing_inputs",
+                NodeError::TxRejectedByPolicy(_) => "tx_rejected",
+                _ => "node",
+            },
+            Self::ZeroConf(err) => match err {
+                ZeroConfError::LowFeeRate(..) => "low_fee_rate",
+                ZeroConfError::UnconfirmedParent(_) => "unconfirmed_parent",
+                ZeroConfError::DoubleSpent(_) => "double_spent",
+                ZeroConfError::MissingInput(..) => "missing_input",
+                ZeroConfError::Transaction(_) => "malformed_parent",
+                ZeroConfError::Node(_) => "node",
+            },
+        }
+    }
+}
+
+pub fn record_invoice(_route: &str) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::PAYMENT_INVOICE_TOTAL
+        .with_label_values(&[_route])
+        .inc();
+}
+
+pub fn record_payment_rejection(_err: &PaymentError) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::PAYMENT_REJECTED_TOTAL
+        .with_label_values(&[_err.reason()])
+        .inc();
+}
+
+pub fn record_token_issued(_route: &str) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::TOKEN_ISSUED_TOTAL
+        .with_label_values(&[_route])
+        .inc();
+}
+
 impl ToResponse for PaymentError {
     fn to_status(&self) -> u16 {
         match self {
@@ -58,21 +125,124 @@ impl ToResponse for PaymentError {
                 PreprocessingError::MissingContentTypeHeader => 415,
                 PreprocessingError::PaymentDecode(_) => 400,
             },
+            Self::TooManyTransactions(..) => 400,
             Self::MalformedTx(_) => 400,
             Self::MissingMerchantData => 400,
             Self::MissingCommitment => 400,
+            Self::CommitmentMismatch(_) => 400,
+            Self::InsufficientCommitment(..) => 400,
             Self::Node(err) => match err {
-                NodeError::Rpc(_) => 400,
+                NodeError::TxAlreadyKnown(_) => 409,
+                NodeError::NotFound(_) => 404,
+                NodeError::WalletNotLoaded(_) => 503,
+                NodeError::MissingInputs(_)
+                | NodeError::TxRejectedByPolicy(_)
+                | NodeError::Rpc(_) => 400,
                 _ => 500,
             },
+            Self::ZeroConf(err) => match err {
+                ZeroConfError::LowFeeRate(..) => 400,
+                ZeroConfError::UnconfirmedParent(_) => 400,
+                ZeroConfError::DoubleSpent(_) => 409,
+                ZeroConfError::MissingInput(..) => 400,
+                ZeroConfError::Transaction(_) => 500,
+                ZeroConfError::Node(_) => 500,
+            },
+        }
+    }
+}
+
+/// The policies an unconfirmed payment must satisfy before a token is issued.
+pub fn zero_conf_policy() -> ZeroConfPolicy {
+    ZeroConfPolicy {
+        min_fee_rate: SETTINGS.payments.min_fee_rate,
+        reject_unconfirmed_parents: SETTINGS.payments.reject_unconfirmed_parents,
+        dsproof_wait: Duration::from_millis(SETTINGS.payments.dsproof_wait),
+    }
+}
+
+/// An output committing to the metadata.
+#[derive(Debug, PartialEq, Eq)]
+struct CommitmentOutput<'a> {
+    tx_id: &'a [u8],
+    vout: u32,
+    value: u64,
+}
+
+/// Find the outputs committing to `expected_commitment` across the transactions of a payment,
+/// requiring their total value to be at least `fee`.
+///
+/// Outputs which are not commitments, such as change, are ignored.
+fn find_commitments<'a>(
+    txs: &'a [(Transaction, Vec<u8>)],
+    expected_commitment: &[u8],
+    fee: u64,
+) -> Result<Vec<CommitmentOutput<'a>>, PaymentError> {
+    let mut commitments = Vec::new();
+    let mut n_mismatched = 0;
+    for (tx, tx_id) in txs {
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            let raw_script = output.script.as_bytes();
+            if raw_script.len() != 2 + COMMITMENT_SIZE
+                || raw_script[0] != OP_RETURN
+                || raw_script[1] != COMMITMENT_SIZE as u8
+            {
+                continue;
+            }
+            if raw_script[2..34] != expected_commitment[..] {
+                n_mismatched += 1;
+                continue;
+            }
+            commitments.push(CommitmentOutput {
+                tx_id,
+                vout: vout as u32,
+                value: output.value,
+            });
+        }
+    }
+
+    if commitments.is_empty() {
+        if n_mismatched != 0 {
+            return Err(PaymentError::CommitmentMismatch(n_mismatched));
+        }
+        return Err(PaymentError::MissingCommitment);
+    }
+    let total: u64 = commitments.iter().map(|commitment| commitment.value).sum();
+    if total < fee {
+        return Err(PaymentError::InsufficientCommitment(total, fee));
+    }
+    Ok(commitments)
+}
+
+/// Construct the token of a payment, referring to a commitment output in each of its transactions.
+///
+/// All the commitment outputs of the referenced transactions count towards the token's value.
+fn payment_token(commitments: &[CommitmentOutput]) -> String {
+    let mut outpoints: Vec<(&[u8], u32)> = Vec::with_capacity(commitments.len());
+    for commitment in commitments {
+        if !outpoints
+            .iter()
+            .any(|(tx_id, _)| *tx_id == commitment.tx_id)
+        {
+            outpoints.push((commitment.tx_id, commitment.vout));
         }
     }
+    construct_split_token(&outpoints)
 }
 
 pub async fn process_payment(
     payment: bip70::Payment,
-    bitcoin_client: BitcoinClientHTTP,
+    bitcoin_client: impl BitcoinClient,
 ) -> Result<Response<Body>, PaymentError> {
+    // Bound the number of transactions before any decoding or RPC calls
+    let n_transactions = payment.transactions.len();
+    if n_transactions > SETTINGS.limits.payment_transactions {
+        return Err(PaymentError::TooManyTransactions(
+            n_transactions,
+            SETTINGS.limits.payment_transactions,
+        ));
+    }
+
     // Deserialize transactions
     let txs_res: Result<Vec<(Transaction, Vec<u8>)>, _> = payment
         .transactions
@@ -109,27 +279,16 @@ pub async fn process_payment(
 
     let expected_commitment = construct_commitment(pub_key_hash, address_metadata_hash);
 
-    let (tx_id, vout) = txs
-        .iter()
-        .find_map(|(tx, tx_id)| {
-            tx.outputs
-                .iter()
-                .enumerate()
-                .find_map(|(vout, output)| {
-                    let raw_script = output.script.as_bytes();
-                    if raw_script.len() == 2 + COMMITMENT_SIZE
-                        && raw_script[0] == OP_RETURN
-                        && raw_script[1] == COMMITMENT_SIZE as u8
-                        && raw_script[2..34] == expected_commitment[..]
-                    {
-                        Some(vout)
-                    } else {
-                        None
-                    }
-                })
-                .map(|vout| (tx_id, vout))
-        })
-        .ok_or(PaymentError::MissingCommitment)?;
+    let commitments =
+        find_commitments(&txs, &expected_commitment, SETTINGS.payments.commitment_fee)?;
+    let amount: u64 = commitments.iter().map(|commitment| commitment.value).sum();
+
+    // Reject cheap or chained payments before they are broadcast
+    let transactions: Vec<Transaction> = txs.iter().map(|(tx, _)| tx.clone()).collect();
+    zero_conf_policy()
+        .check_transactions(&bitcoin_client, &transactions)
+        .await
+        .map_err(PaymentError::ZeroConf)?;
 
     // Broadcast transactions
     for tx in &payment.transactions {
@@ -139,8 +298,30 @@ pub async fn process_payment(
             .map_err(PaymentError::Node)?;
     }
 
+    // Guard against the payment being double-spent once the token is issued
+    let tx_ids: Vec<Vec<u8>> = txs.iter().map(|(_, tx_id)| tx_id.clone()).collect();
+    zero_conf_policy()
+        .check_broadcast(&bitcoin_client, &tx_ids)
+        .await
+        .map_err(PaymentError::ZeroConf)?;
+
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::PAYMENT_RECEIVED_TOTAL.inc();
+
     // Construct token
-    let token = format!("POP {}", construct_token(tx_id, vout as u32));
+    let token = format!("POP {}", payment_token(&commitments));
+    record_token_issued(PAYMENTS_PATH);
+
+    // Notify operator
+    net::notify_payment(PaymentEvent {
+        address: addr_str.clone(),
+        txids: txs.iter().map(|(_, tx_id)| hex::encode(tx_id)).collect(),
+        amount,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    });
 
     // Create PaymentAck
     let memo = Some(SETTINGS.payments.memo.clone());
@@ -157,14 +338,25 @@ pub async fn process_payment(
         .unwrap())
 }
 
-pub fn construct_payment_response(pub_key_hash: &[u8], metadata_digest: &[u8]) -> Response<Body> {
+pub fn construct_payment_response(
+    pub_key_hash: &[u8],
+    metadata_digest: &[u8],
+    metadata_size: usize,
+) -> Response<Body> {
     // Construct metadata commitment
     let commitment_preimage = [pub_key_hash, metadata_digest].concat();
     let commitment = digest(&SHA256, &commitment_preimage);
     let op_return_pre: [u8; 2] = [106, COMMITMENT_SIZE as u8];
     let script = [&op_return_pre[..], commitment.as_ref()].concat();
+    let fee = SETTINGS
+        .payments
+        .commitment_fee
+        .max(net::required_commitment(
+            &SETTINGS.limits.metadata_tiers,
+            metadata_size,
+        ));
     let output = bip70::Output {
-        amount: None,
+        amount: if fee != 0 { Some(fee) } else { None },
         script,
     };
 
@@ -203,3 +395,139 @@ pub fn construct_payment_response(pub_key_hash: &[u8], metadata_digest: &[u8]) -
         .body(Body::from(payment_invoice_raw))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use cashweb::{
+        bitcoin::{
+            transaction::{output::Output, script::Script},
+            Encodable,
+        },
+        bitcoin_client::mock::MockBitcoinClient,
+        token::schemes::chain_commitment::{construct_token, ChainCommitmentScheme},
+    };
+
+    use super::*;
+    use crate::settings::MetadataTier;
+
+    fn commitment_output(commitment: &[u8], value: u64) -> Output {
+        let script = [&[OP_RETURN, COMMITMENT_SIZE as u8][..], commitment].concat();
+        Output {
+            value,
+            script: Script::from(script),
+        }
+    }
+
+    #[test]
+    fn split_commitments() {
+        let commitment = [1; 32];
+        let change = Output {
+            value: 5000,
+            script: Script::p2pkh(&[2; 20]),
+        };
+        let mut tx_a = Transaction::default();
+        tx_a.outputs = vec![change.clone(), commitment_output(&commitment, 300)];
+        let mut tx_b = Transaction::default();
+        tx_b.outputs = vec![commitment_output(&commitment, 200), change];
+        let txs = vec![(tx_a, vec![3; 32]), (tx_b, vec![4; 32])];
+
+        let commitments = find_commitments(&txs, &commitment, 500).unwrap();
+        assert_eq!(
+            commitments,
+            vec![
+                CommitmentOutput {
+                    tx_id: &[3; 32],
+                    vout: 1,
+                    value: 300
+                },
+                CommitmentOutput {
+                    tx_id: &[4; 32],
+                    vout: 0,
+                    value: 200
+                }
+            ]
+        );
+        assert!(matches!(
+            find_commitments(&txs, &commitment, 501),
+            Err(PaymentError::InsufficientCommitment(500, 501))
+        ));
+        assert!(matches!(
+            find_commitments(&txs, &[5; 32], 0),
+            Err(PaymentError::CommitmentMismatch(2))
+        ));
+        assert!(matches!(
+            find_commitments(&txs[..0], &commitment, 0),
+            Err(PaymentError::MissingCommitment)
+        ));
+    }
+
+    #[tokio::test]
+    async fn split_payment_token() {
+        let pub_key_hash = [6; 32];
+        let metadata_hash = [7; 32];
+        let commitment = construct_commitment(&pub_key_hash, &metadata_hash);
+
+        // A payment split across two transactions
+        let client = MockBitcoinClient::new();
+        let mut txs = Vec::new();
+        for outputs in vec![
+            vec![
+                commitment_output(&commitment, 200),
+                commitment_output(&commitment, 100),
+            ],
+            vec![commitment_output(&commitment, 200)],
+        ] {
+            let mut tx = Transaction::default();
+            tx.outputs = outputs;
+            let mut raw_tx = Vec::with_capacity(tx.encoded_len());
+            tx.encode(&mut raw_tx).unwrap();
+            let tx_id = tx.transaction_id_rev().to_vec();
+            client.insert_transaction(tx_id.clone(), raw_tx);
+            txs.push((tx, tx_id));
+        }
+        let commitments = find_commitments(&txs, &commitment, 500).unwrap();
+        let token = payment_token(&commitments);
+
+        // The token carries the whole payment to the paid tier
+        let tiers = vec![MetadataTier {
+            size: 1_000,
+            commitment: 500,
+        }];
+        let scheme = ChainCommitmentScheme::from_client(client);
+        let validated = scheme
+            .validate_commitment(&pub_key_hash, &metadata_hash, &token)
+            .await
+            .unwrap();
+        assert_eq!(validated.value, 500);
+        assert!(validated.value >= net::required_commitment(&tiers, 2_000));
+
+        // A token referring to a single transaction only carries its part
+        let partial_token = construct_token(&txs[0].1, 0);
+        let validated = scheme
+            .validate_commitment(&pub_key_hash, &metadata_hash, &partial_token)
+            .await
+            .unwrap();
+        assert_eq!(validated.value, 300);
+        assert!(validated.value < net::required_commitment(&tiers, 2_000));
+    }
+
+    #[test]
+    fn rejection_reasons() {
+        assert_eq!(
+            PaymentError::InsufficientCommitment(1, 2).reason(),
+            "insufficient_commitment"
+        );
+        assert_eq!(
+            PaymentError::Node(NodeError::TxAlreadyKnown(String::new())).reason(),
+            "tx_already_known"
+        );
+        assert_eq!(
+            PaymentError::Node(NodeError::EmptyResponse).reason(),
+            "node"
+        );
+        assert_eq!(
+            PaymentError::ZeroConf(ZeroConfError::DoubleSpent(String::new())).reason(),
+            "double_spent"
+        );
+    }
+}
@@ -3,8 +3,8 @@ use std::sync::Arc;
 use bitcoincash_addr::Address;
 use bytes::Bytes;
 use cashweb::{
-    auth_wrapper::AuthWrapper,
-    bitcoin_client::BitcoinClientHTTP,
+    auth_wrapper::{AuthWrapper, ParseError, ParsedAuthWrapper},
+    bitcoin_client::BitcoinClient,
     token::{extract_pop, schemes::chain_commitment::*},
 };
 use http::header::HeaderMap;
@@ -13,16 +13,31 @@ use thiserror::Error;
 use tracing::info;
 use warp::{http::Response, hyper::Body, reject::Reject};
 
-use crate::{crypto::sha256, net::payments};
+use crate::{crypto::sha256, net::payments, METADATA_PATH};
 
 #[derive(Debug, Error)]
 pub enum ProtectionError {
     #[error("missing token, pubkey: {}", hex::encode(.0))]
-    MissingToken(Vec<u8>, Vec<u8>),
+    MissingToken(Vec<u8>, Vec<u8>, usize),
     #[error("validation failed: {0}")]
     Validation(ValidationError),
     #[error("failed to decode authorization wrapper: {0}")]
     Decode(prost::DecodeError),
+    #[error("failed to parse authorization wrapper: {0}")]
+    Parse(ParseError),
+}
+
+/// A request body whose token has been validated.
+#[derive(Debug)]
+pub struct ProtectedBody {
+    /// The serialized `AuthWrapper`, exactly as received.
+    pub raw: Bytes,
+    /// The parsed `AuthWrapper`. The token commits to its payload digest.
+    pub auth_wrapper: ParsedAuthWrapper,
+    /// The raw token.
+    pub token: Vec<u8>,
+    /// Value, in satoshis, committed by the token's transactions.
+    pub value: u64,
 }
 
 pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body> {
@@ -31,48 +46,68 @@ pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body>
             .status(400)
             .body(Body::from(err.to_string()))
             .unwrap(),
-        ProtectionError::MissingToken(pubkey_digest, metadata_digest) => {
-            payments::construct_payment_response(pubkey_digest, metadata_digest)
+        ProtectionError::MissingToken(pubkey_digest, metadata_digest, size) => {
+            // Only metadata updates are protected
+            payments::record_invoice(METADATA_PATH);
+            payments::construct_payment_response(pubkey_digest, metadata_digest, *size)
         }
         ProtectionError::Decode(err) => Response::builder()
             .status(400)
             .body(Body::from(err.to_string()))
             .unwrap(),
+        ProtectionError::Parse(err) => Response::builder()
+            .status(400)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
     }
 }
 
 impl Reject for ProtectionError {}
 
-pub async fn pop_protection(
+/// Validate the token of a request body.
+///
+/// The body is buffered once, and both the token and the returned [`ProtectedBody`] refer to the
+/// same bytes. Parsing the `AuthWrapper` ensures the payload digest the token commits to is the
+/// digest of the payload actually present in the body.
+pub async fn pop_protection<C: BitcoinClient>(
     addr: Address,
     auth_wrapper_raw: Bytes,
     header_map: HeaderMap,
-    token_scheme: Arc<ChainCommitmentScheme<BitcoinClientHTTP>>,
-) -> Result<(Address, Bytes, AuthWrapper, Vec<u8>), ProtectionError> {
+    token_scheme: Arc<ChainCommitmentScheme<C>>,
+) -> Result<(Address, ProtectedBody), ProtectionError> {
     let auth_wrapper =
         AuthWrapper::decode(auth_wrapper_raw.clone()).map_err(ProtectionError::Decode)?;
 
-    let metadata_hash = if auth_wrapper.payload_digest.len() == 32 {
-        auth_wrapper.payload_digest.clone()
-    } else {
-        sha256(&auth_wrapper.payload).to_vec()
-    };
-
-    // SHA256 of the public key
+    // SHA256 of the public key, as serialized by the client
     let pub_key_hash = sha256(&auth_wrapper.public_key);
 
+    let auth_wrapper = auth_wrapper.parse().map_err(ProtectionError::Parse)?;
+
     match extract_pop(&header_map) {
         Some(pop_token) => {
             info!(message = "found token", token = %pop_token);
-            let raw_token = token_scheme
-                .validate_token(pub_key_hash.as_ref(), &metadata_hash, pop_token)
+            let ValidatedToken { token, value } = token_scheme
+                .validate_commitment(
+                    pub_key_hash.as_ref(),
+                    &auth_wrapper.payload_digest,
+                    pop_token,
+                )
                 .await
                 .map_err(ProtectionError::Validation)?;
-            Ok((addr, auth_wrapper_raw, auth_wrapper, raw_token))
+            Ok((
+                addr,
+                ProtectedBody {
+                    raw: auth_wrapper_raw,
+                    auth_wrapper,
+                    token,
+                    value,
+                },
+            ))
         }
         None => Err(ProtectionError::MissingToken(
             pub_key_hash.to_vec(),
-            metadata_hash,
+            auth_wrapper.payload_digest.to_vec(),
+            auth_wrapper_raw.len(),
         )),
     }
 }
@@ -0,0 +1,228 @@
+use bytes::Bytes;
+use cashweb::{
+    auth_wrapper::{AuthWrapper, ParseError, VerifyError},
+    keyserver::Revocation,
+    secp256k1::key::PublicKey,
+};
+use prost::Message as _;
+use thiserror::Error;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{db::Database, net::ToResponse};
+
+/// Header carrying the revocation of the key which signed the returned metadata.
+pub const REVOCATION: &str = "X-Revocation";
+
+#[derive(Debug, Error)]
+pub enum RevocationError {
+    #[error("failed to decode authorization wrapper: {0}")]
+    Decode(prost::DecodeError),
+    #[error("failed to parse authorization wrapper: {0}")]
+    Parse(ParseError),
+    #[error("failed to verify authorization wrapper: {0}")]
+    Verify(VerifyError),
+    #[error("failed to decode revocation: {0}")]
+    DecodeRevocation(prost::DecodeError),
+    #[error("revoked public key does not match signing key")]
+    KeyMismatch,
+    #[error("invalid public key")]
+    InvalidPublicKey,
+    #[error("not found")]
+    NotFound,
+    #[error("failed to access database: {0}")]
+    Database(rocksdb::Error),
+}
+
+impl From<rocksdb::Error> for RevocationError {
+    fn from(err: rocksdb::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl Reject for RevocationError {}
+
+impl ToResponse for RevocationError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::NotFound => 404,
+            Self::Database(_) => 500,
+            _ => 400,
+        }
+    }
+}
+
+/// Serialize a public key in compressed form, under which revocations are keyed.
+fn compressed_public_key(raw_public_key: &[u8]) -> Result<[u8; 33], RevocationError> {
+    PublicKey::from_slice(raw_public_key)
+        .map(|public_key| public_key.serialize())
+        .map_err(|_| RevocationError::InvalidPublicKey)
+}
+
+/// Get the serialized revocation of the key which signed a serialized `AuthWrapper`, if any.
+pub fn get_signer_revocation(
+    database: &Database,
+    raw_auth_wrapper: &[u8],
+) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+    let public_key = match AuthWrapper::decode(raw_auth_wrapper)
+        .ok()
+        .and_then(|auth_wrapper| compressed_public_key(&auth_wrapper.public_key).ok())
+    {
+        Some(some) => some,
+        None => return Ok(None),
+    };
+    database.get_revocation(&public_key)
+}
+
+/// Handles revocation PUT requests.
+///
+/// The revocation must be signed by the key it revokes. Revocations are permanent, so an existing
+/// revocation is never replaced.
+pub async fn put_revocation(
+    body: Bytes,
+    database: Database,
+) -> Result<Response<Body>, RevocationError> {
+    // Parse and verify
+    let auth_wrapper = AuthWrapper::decode(&body[..])
+        .map_err(RevocationError::Decode)?
+        .parse()
+        .map_err(RevocationError::Parse)?;
+    auth_wrapper.verify().map_err(RevocationError::Verify)?;
+    let revocation =
+        Revocation::decode(&auth_wrapper.payload[..]).map_err(RevocationError::DecodeRevocation)?;
+    let public_key = auth_wrapper.public_key.serialize();
+    if compressed_public_key(&revocation.public_key)? != public_key {
+        return Err(RevocationError::KeyMismatch);
+    }
+
+    // Put to database
+    if database.get_revocation(&public_key)?.is_none() {
+        database.put_revocation(&public_key, &body)?;
+    }
+
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles revocation GET requests.
+pub async fn get_revocation(
+    public_key_hex: String,
+    database: Database,
+) -> Result<Response<Body>, RevocationError> {
+    let raw_public_key =
+        hex::decode(&public_key_hex).map_err(|_| RevocationError::InvalidPublicKey)?;
+    let public_key = compressed_public_key(&raw_public_key)?;
+    let raw_revocation = database
+        .get_revocation(&public_key)?
+        .ok_or(RevocationError::NotFound)?;
+    Ok(Response::builder()
+        .body(Body::from(raw_revocation))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb::auth_wrapper::SignatureScheme;
+    use rocksdb::{Options, DB};
+
+    use super::*;
+    use crate::net::ServerIdentity;
+
+    /// Sign a revocation of `revoked_public_key`, presenting the signing key as
+    /// `signer_public_key`.
+    fn revocation(
+        signer: &ServerIdentity,
+        signer_public_key: &[u8],
+        revoked_public_key: &[u8],
+    ) -> Bytes {
+        let revocation = Revocation {
+            public_key: revoked_public_key.to_vec(),
+            timestamp: 1,
+            ..Default::default()
+        };
+        let mut payload = Vec::with_capacity(revocation.encoded_len());
+        revocation.encode(&mut payload).unwrap();
+        let auth_wrapper = AuthWrapper {
+            public_key: signer_public_key.to_vec(),
+            signature: signer.sign(&payload).to_vec(),
+            scheme: SignatureScheme::Ecdsa as i32,
+            payload,
+            ..Default::default()
+        };
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        raw_auth_wrapper.into()
+    }
+
+    #[tokio::test]
+    async fn uncompressed_revocations() {
+        const TEST_NAME: &str = "./tests/uncompressed_revocations";
+
+        let database = Database::try_new(TEST_NAME).unwrap();
+        let identity = ServerIdentity::from_hex(&"02".repeat(32)).unwrap();
+        let compressed = identity.public_key().serialize();
+        let uncompressed = identity.public_key().serialize_uncompressed();
+
+        // Revocations are keyed by the compressed key, however it is presented
+        let raw_revocation = revocation(&identity, &uncompressed, &uncompressed);
+        put_revocation(raw_revocation.clone(), database.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            database.get_revocation(&compressed).unwrap(),
+            Some(raw_revocation.to_vec())
+        );
+        assert_eq!(
+            get_signer_revocation(&database, &raw_revocation).unwrap(),
+            Some(raw_revocation.to_vec())
+        );
+        for public_key in &[&compressed[..], &uncompressed[..]] {
+            let response = get_revocation(hex::encode(public_key), database.clone())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+        }
+
+        // Revocations are permanent, whichever serialization replaces them
+        put_revocation(
+            revocation(&identity, &compressed, &compressed),
+            database.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            database.get_revocation(&compressed).unwrap(),
+            Some(raw_revocation.to_vec())
+        );
+
+        // Revocations must be signed by the key they revoke
+        let other = ServerIdentity::from_hex(&"03".repeat(32)).unwrap();
+        let other_public_key = other.public_key().serialize();
+        assert!(matches!(
+            put_revocation(
+                revocation(&other, &other_public_key, &uncompressed),
+                database.clone()
+            )
+            .await,
+            Err(RevocationError::KeyMismatch)
+        ));
+        assert!(matches!(
+            put_revocation(
+                revocation(&other, &other_public_key, &[0; 33]),
+                database.clone()
+            )
+            .await,
+            Err(RevocationError::InvalidPublicKey)
+        ));
+        assert!(matches!(
+            get_revocation(hex::encode(&other_public_key), database.clone()).await,
+            Err(RevocationError::NotFound)
+        ));
+        assert!(matches!(
+            get_revocation("02".to_string(), database.clone()).await,
+            Err(RevocationError::InvalidPublicKey)
+        ));
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+}
@@ -0,0 +1,12 @@
+pub use cashweb_server_core::webhook::PaymentEvent;
+
+use crate::SETTINGS;
+
+/// Notify the payment webhook, if configured, of an accepted payment.
+pub fn notify_payment(event: PaymentEvent) {
+    cashweb_server_core::webhook::notify_payment(
+        SETTINGS.payments.webhook_url.as_deref(),
+        SETTINGS.payments.webhook_secret.as_deref(),
+        event,
+    )
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use cashweb::keyserver_client::{services::PutMetadataError, uniform_random_sampler};
+use hyper::Uri;
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "monitoring")]
+use crate::monitoring;
+use crate::SETTINGS;
+
+/// Token bucket pacing the pushes to a peer.
+#[derive(Debug)]
+struct RateLimiter {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(burst: u32, now: Instant) -> Self {
+        Self {
+            tokens: burst as f64,
+            last: now,
+        }
+    }
+
+    /// Take a token if one is available, refilling at `rate` tokens per second.
+    fn try_take(&mut self, rate: f64, burst: u32, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.last = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Circuit {
+    /// Pushes are allowed, counting the consecutive failures.
+    Closed(u32),
+    /// Pushes are paused until the given time.
+    Open(Instant),
+    /// A single trial push is in flight.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct PeerState {
+    limiter: RateLimiter,
+    circuit: Circuit,
+}
+
+/// Reason a push to a peer was withheld.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throttled {
+    RateLimited,
+    CircuitOpen,
+}
+
+impl Throttled {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::CircuitOpen => "circuit_open",
+        }
+    }
+}
+
+/// Limits on the pushes to each peer.
+#[derive(Clone, Copy, Debug)]
+pub struct PushLimits {
+    /// Pushes allowed per second, zero if unlimited.
+    pub rate: f64,
+    /// Pushes allowed in a burst.
+    pub burst: u32,
+    /// Consecutive failures after which pushes are paused, zero if never.
+    pub max_failures: u32,
+    /// Time pushes are paused for before a trial push.
+    pub cooldown: Duration,
+}
+
+impl PushLimits {
+    /// Construct the limits from the peering settings.
+    pub fn from_settings() -> Self {
+        Self {
+            rate: SETTINGS.peering.push_rate_limit as f64 / 60.,
+            burst: SETTINGS.peering.push_burst.max(1),
+            max_failures: SETTINGS.peering.circuit_failures,
+            cooldown: Duration::from_millis(SETTINGS.peering.circuit_cooldown),
+        }
+    }
+}
+
+/// Per-peer rate limiters and circuit breakers guarding outbound pushes.
+///
+/// A peer failing `max_failures` pushes in a row has its circuit opened, pausing pushes to it
+/// for the cooldown. A single trial push is then allowed, closing the circuit if it succeeds
+/// and reopening it otherwise.
+#[derive(Clone, Debug)]
+pub struct PushGuard {
+    limits: PushLimits,
+    peers: Arc<Mutex<HashMap<String, PeerState>>>,
+}
+
+fn record_throttled(_reason: Throttled) {
+    #[cfg(feature = "monitoring")]
+    monitoring::PUSH_THROTTLED_TOTAL
+        .with_label_values(&[_reason.as_str()])
+        .inc();
+}
+
+/// Returns true if a push error indicates the peer is unreachable or overloaded.
+///
+/// Peers rejecting the metadata or requiring payment are responsive and are not penalized.
+pub fn is_peer_failure<E: fmt::Debug + fmt::Display>(err: &PutMetadataError<E>) -> bool {
+    match err {
+        PutMetadataError::Service(_) => true,
+        PutMetadataError::UnexpectedStatusCode(status) => *status == 429 || *status >= 500,
+        PutMetadataError::PaymentRequired(_) => false,
+    }
+}
+
+fn update_open_metric(_peers: &HashMap<String, PeerState>) {
+    #[cfg(feature = "monitoring")]
+    monitoring::PEER_CIRCUITS_OPEN.set(
+        _peers
+            .values()
+            .filter(|state| !matches!(state.circuit, Circuit::Closed(_)))
+            .count() as i64,
+    );
+}
+
+impl PushGuard {
+    pub fn new(limits: PushLimits) -> Self {
+        Self {
+            limits,
+            peers: Default::default(),
+        }
+    }
+
+    /// Reserve a push to a peer at the given time.
+    pub fn try_acquire(&self, uri: &Uri, now: Instant) -> Result<(), Throttled> {
+        let limits = self.limits;
+        let mut peers = self.peers.lock().unwrap();
+        let state = peers.entry(uri.to_string()).or_insert_with(|| PeerState {
+            limiter: RateLimiter::new(limits.burst, now),
+            circuit: Circuit::Closed(0),
+        });
+
+        match state.circuit {
+            Circuit::Open(until) if now < until => return Err(Throttled::CircuitOpen),
+            Circuit::HalfOpen => return Err(Throttled::CircuitOpen),
+            _ => (),
+        }
+        if limits.rate > 0. && !state.limiter.try_take(limits.rate, limits.burst, now) {
+            return Err(Throttled::RateLimited);
+        }
+        if let Circuit::Open(_) = state.circuit {
+            state.circuit = Circuit::HalfOpen;
+        }
+        Ok(())
+    }
+
+    /// Select up to `size` random peers, reserving a push to each.
+    pub fn select(&self, uris: &[Uri], size: usize, now: Instant) -> Vec<Uri> {
+        uniform_random_sampler(uris, uris.len())
+            .into_iter()
+            .filter(|uri| match self.try_acquire(uri, now) {
+                Ok(()) => true,
+                Err(reason) => {
+                    debug!(message = "withheld push", uri = %uri, reason = reason.as_str());
+                    record_throttled(reason);
+                    false
+                }
+            })
+            .take(size)
+            .collect()
+    }
+
+    /// Record a push which reached the peer.
+    pub fn record_success(&self, uri: &Uri) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(state) = peers.get_mut(&uri.to_string()) {
+            if state.circuit == Circuit::HalfOpen {
+                info!(message = "closed peer circuit", uri = %uri);
+            }
+            state.circuit = Circuit::Closed(0);
+        }
+        update_open_metric(&peers);
+    }
+
+    /// Record a push which failed to reach the peer, at the given time.
+    pub fn record_failure(&self, uri: &Uri, now: Instant) {
+        let limits = self.limits;
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(state) = peers.get_mut(&uri.to_string()) {
+            state.circuit = match state.circuit {
+                Circuit::Closed(failures)
+                    if limits.max_failures == 0 || failures + 1 < limits.max_failures =>
+                {
+                    Circuit::Closed(failures + 1)
+                }
+                _ => {
+                    warn!(message = "opened peer circuit", uri = %uri);
+                    Circuit::Open(now + limits.cooldown)
+                }
+            };
+        }
+        update_open_metric(&peers);
+    }
+
+    /// Forget the peers no longer in the given list.
+    pub fn retain(&self, uris: &[Uri]) {
+        let uris: Vec<String> = uris.iter().map(Uri::to_string).collect();
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|uri, _| uris.contains(uri));
+        update_open_metric(&peers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_guard(rate: f64, burst: u32, max_failures: u32) -> PushGuard {
+        PushGuard::new(PushLimits {
+            rate,
+            burst,
+            max_failures,
+            cooldown: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn rate_limit() {
+        let guard = push_guard(1., 2, 0);
+        let uri: Uri = "http://peer".parse().unwrap();
+        let other: Uri = "http://other".parse().unwrap();
+        let now = Instant::now();
+
+        // Bursts are allowed, then pushes are paced
+        assert_eq!(guard.try_acquire(&uri, now), Ok(()));
+        assert_eq!(guard.try_acquire(&uri, now), Ok(()));
+        assert_eq!(guard.try_acquire(&uri, now), Err(Throttled::RateLimited));
+        assert_eq!(
+            guard.try_acquire(&uri, now + Duration::from_millis(500)),
+            Err(Throttled::RateLimited)
+        );
+        assert_eq!(
+            guard.try_acquire(&uri, now + Duration::from_secs(1)),
+            Ok(())
+        );
+
+        // Peers are limited independently
+        assert_eq!(guard.try_acquire(&other, now), Ok(()));
+
+        // No limit
+        let unlimited = push_guard(0., 1, 0);
+        for _ in 0..10 {
+            assert_eq!(unlimited.try_acquire(&uri, now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        let guard = push_guard(0., 1, 2);
+        let uri: Uri = "http://peer".parse().unwrap();
+        let now = Instant::now();
+
+        // Opens after consecutive failures
+        guard.try_acquire(&uri, now).unwrap();
+        guard.record_failure(&uri, now);
+        guard.record_success(&uri);
+        guard.record_failure(&uri, now);
+        assert_eq!(guard.try_acquire(&uri, now), Ok(()));
+        guard.record_failure(&uri, now);
+        assert_eq!(guard.try_acquire(&uri, now), Err(Throttled::CircuitOpen));
+
+        // A single trial push once cooled down
+        let later = now + Duration::from_secs(10);
+        assert_eq!(guard.try_acquire(&uri, later), Ok(()));
+        assert_eq!(guard.try_acquire(&uri, later), Err(Throttled::CircuitOpen));
+
+        // A failed trial reopens the circuit
+        guard.record_failure(&uri, later);
+        assert_eq!(guard.try_acquire(&uri, later), Err(Throttled::CircuitOpen));
+
+        // A successful trial closes it
+        let later = later + Duration::from_secs(10);
+        assert_eq!(guard.try_acquire(&uri, later), Ok(()));
+        guard.record_success(&uri);
+        assert_eq!(guard.try_acquire(&uri, later), Ok(()));
+    }
+
+    #[test]
+    fn select_skips_throttled() {
+        let guard = push_guard(0., 1, 1);
+        let uris: Vec<Uri> = ["http://a", "http://b", "http://c"]
+            .iter()
+            .map(|uri| uri.parse().unwrap())
+            .collect();
+        let now = Instant::now();
+
+        guard.try_acquire(&uris[0], now).unwrap();
+        guard.record_failure(&uris[0], now);
+        let selected = guard.select(&uris, 3, now);
+        assert_eq!(selected.len(), 2);
+        assert!(!selected.contains(&uris[0]));
+        assert_eq!(guard.select(&uris, 1, now).len(), 1);
+    }
+
+    #[test]
+    fn peer_failures() {
+        let failure = |err: PutMetadataError<String>| is_peer_failure(&err);
+        assert!(failure(PutMetadataError::Service("refused".to_string())));
+        assert!(failure(PutMetadataError::UnexpectedStatusCode(503)));
+        assert!(failure(PutMetadataError::UnexpectedStatusCode(429)));
+        assert!(!failure(PutMetadataError::UnexpectedStatusCode(400)));
+        assert!(!failure(PutMetadataError::PaymentRequired(
+            Default::default()
+        )));
+    }
+}
@@ -0,0 +1,207 @@
+use std::{fmt, time::Duration};
+
+use async_zmq::Subscribe;
+use cashweb::bitcoin_client::BitcoinClient;
+use futures::prelude::*;
+use hyper::{Body, Request, Response};
+use tokio::time::{interval, sleep};
+use tower_service::Service;
+use tracing::{info, warn};
+
+use crate::{
+    peering::{PeerHandler, TokenCache},
+    SETTINGS,
+};
+
+/// Tracks the chain tip in order to detect blocks missed while notifications were unavailable.
+#[derive(Debug, Default)]
+pub struct BlockTracker {
+    height: Option<u64>,
+}
+
+impl BlockTracker {
+    /// Record the chain tip, returning the number of blocks found since the last recorded tip.
+    pub fn advance(&mut self, tip: u64) -> u64 {
+        match self.height {
+            Some(height) if tip > height => {
+                self.height = Some(tip);
+                tip - height
+            }
+            Some(_) => 0,
+            None => {
+                self.height = Some(tip);
+                0
+            }
+        }
+    }
+
+    /// Record a single block whose height is unknown.
+    pub fn advance_one(&mut self) -> u64 {
+        self.height = self.height.map(|height| height + 1);
+        1
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+#[derive(Debug)]
+pub struct Backoff {
+    delay: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            delay: min,
+            min,
+            max,
+        }
+    }
+
+    /// Get the next delay, doubling the one after.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = self.min;
+    }
+}
+
+fn subscribe() -> Result<Subscribe, String> {
+    let subscriber = async_zmq::subscribe(&SETTINGS.bitcoin_rpc.zmq_address)
+        .map_err(|err| err.to_string())?
+        .connect()
+        .map_err(|err| err.to_string())?;
+    subscriber
+        .set_subscribe("hashblock")
+        .map_err(|err| err.to_string())?;
+    Ok(subscriber)
+}
+
+/// Broadcast the token cache once for each new block.
+///
+/// Block notifications are received over ZMQ, reconnecting with backoff when the socket fails.
+/// The chain tip is also polled over RPC so that blocks missed during an outage are caught up.
+pub async fn broadcast_heartbeat<S, C>(
+    token_cache: TokenCache,
+    peer_handler: PeerHandler<S>,
+    bitcoin_client: C,
+) where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: Send + fmt::Debug + fmt::Display,
+    C: BitcoinClient + Send + Sync,
+{
+    // Catching up beyond the broadcast delay has no further effect
+    let max_catch_up = SETTINGS.peering.broadcast_delay.max(1) as u64;
+    let process_blocks = |n_blocks: u64| {
+        let token_cache = &token_cache;
+        let peer_handler = &peer_handler;
+        async move {
+            for _ in 0..n_blocks.min(max_catch_up) {
+                token_cache.broadcast_block(peer_handler).await;
+            }
+        }
+    };
+
+    let mut tracker = BlockTracker::default();
+    match bitcoin_client.get_block_count().await {
+        Ok(tip) => {
+            tracker.advance(tip);
+        }
+        Err(err) => warn!(message = "failed to get block count", error = %err),
+    }
+
+    let mut backoff = Backoff::new(
+        Duration::from_millis(SETTINGS.bitcoin_rpc.zmq_reconnect_min),
+        Duration::from_millis(SETTINGS.bitcoin_rpc.zmq_reconnect_max),
+    );
+    let mut poll = interval(Duration::from_millis(
+        SETTINGS.bitcoin_rpc.block_poll_interval,
+    ));
+    loop {
+        let mut subscriber = match subscribe() {
+            Ok(ok) => ok,
+            Err(err) => {
+                warn!(message = "failed to subscribe to block notifications", error = %err);
+                sleep(backoff.next_delay()).await;
+                continue;
+            }
+        };
+        info!(message = "subscribed to block notifications");
+
+        loop {
+            tokio::select! {
+                notification = subscriber.next() => match notification {
+                    Some(Ok(multipart)) => {
+                        if let Some(block) = multipart.get(1) {
+                            info!(message = "found block", block_id = %hex::encode(block.as_ref()));
+                            backoff.reset();
+                            let n_blocks = match bitcoin_client.get_block_count().await {
+                                Ok(tip) => tracker.advance(tip),
+                                Err(_) => tracker.advance_one(),
+                            };
+                            process_blocks(n_blocks).await;
+                        }
+                    }
+                    Some(Err(err)) => {
+                        warn!(message = "block notification failed", error = %err);
+                        break;
+                    }
+                    None => {
+                        warn!(message = "block notification stream closed");
+                        break;
+                    }
+                },
+                _ = poll.tick() => {
+                    if let Ok(tip) = bitcoin_client.get_block_count().await {
+                        let n_blocks = tracker.advance(tip);
+                        if n_blocks != 0 {
+                            warn!(message = "recovering missed blocks", count = n_blocks);
+                            process_blocks(n_blocks).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        sleep(backoff.next_delay()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_tracker() {
+        let mut tracker = BlockTracker::default();
+        assert_eq!(tracker.advance(100), 0);
+        assert_eq!(tracker.advance(101), 1);
+        assert_eq!(tracker.advance(101), 0);
+
+        // Gaps are reported
+        assert_eq!(tracker.advance(105), 4);
+
+        // Reorganizations to lower heights are not
+        assert_eq!(tracker.advance(104), 0);
+        assert_eq!(tracker.advance(106), 1);
+
+        assert_eq!(tracker.advance_one(), 1);
+        assert_eq!(tracker.advance(107), 0);
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+}
@@ -1,11 +1,17 @@
+mod circuit;
+mod heartbeat;
+mod sampler;
 mod token_cache;
 
+pub use circuit::*;
+pub use heartbeat::*;
+pub use sampler::*;
 pub use token_cache::*;
 
 use std::{fmt, sync::Arc};
 
 use cashweb::{
-    keyserver::{Peer, Peers},
+    keyserver::{Peer, Peers, ServerInfo},
     keyserver_client::{
         services::{GetPeersError, SampleError},
         KeyserverManager,
@@ -18,7 +24,24 @@ use tokio::sync::RwLock;
 use tower_service::Service;
 use tracing::warn;
 
-use crate::db::Database;
+use crate::{db::Database, SETTINGS};
+
+/// Construct the [`ServerInfo`] advertised to peers and clients.
+pub fn server_info() -> ServerInfo {
+    let mut features = vec![
+        "pubsub".to_string(),
+        "websocket".to_string(),
+        "bip70".to_string(),
+    ];
+    if SETTINGS.peering.enabled {
+        features.push("peering".to_string());
+    }
+    ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        network: SETTINGS.network.clone(),
+        features,
+    }
+}
 
 pub fn parse_uri_warn(uri_str: &str) -> Option<Uri> {
     let uri = uri_str.parse();
@@ -34,36 +57,45 @@ pub fn parse_uri_warn(uri_str: &str) -> Option<Uri> {
 #[derive(Clone)]
 pub struct PeerHandler<S> {
     keyserver_manager: KeyserverManager<S>,
+    server_info: ServerInfo,
     peers_cache: Arc<RwLock<Vec<u8>>>,
+    push_guard: PushGuard,
 }
 
-fn uris_to_peers(uris: &[Uri]) -> Peers {
-    let peers = uris
-        .iter()
+fn uris_to_peers(uris: &[Uri]) -> Vec<Peer> {
+    uris.iter()
         .map(|uri| Peer {
             url: uri.to_string(),
+            info: None,
         })
-        .collect();
-    Peers { peers }
+        .collect()
 }
 
-fn uris_to_raw_peers(uris: &[Uri]) -> Vec<u8> {
-    let mut buffer = Vec::with_capacity(uris.len());
-    let peers = uris_to_peers(uris);
+fn peers_to_raw_peers(peers: Vec<Peer>, server_info: &ServerInfo) -> Vec<u8> {
+    let peers = Peers {
+        peers,
+        info: Some(server_info.clone()),
+    };
+    let mut buffer = Vec::with_capacity(peers.encoded_len());
     peers.encode(&mut buffer).unwrap(); // Never fails
     buffer
 }
 
 impl PeerHandler<hyper::Client<HttpsConnector<HttpConnector>>> {
     /// Construct new [`PeerHandler`].
-    pub fn new(uris: Vec<Uri>) -> Self {
+    pub fn new(uris: Vec<Uri>, server_info: ServerInfo) -> Self {
         let https = HttpsConnector::new();
         let http_client = hyper::Client::builder().build(https);
-        let peers_cache = Arc::new(RwLock::new(uris_to_raw_peers(&uris)));
+        let peers_cache = Arc::new(RwLock::new(peers_to_raw_peers(
+            uris_to_peers(&uris),
+            &server_info,
+        )));
         let keyserver_manager = KeyserverManager::from_service(http_client, uris);
         Self {
             keyserver_manager,
+            server_info,
             peers_cache,
+            push_guard: PushGuard::new(PushLimits::from_settings()),
         }
     }
 }
@@ -76,17 +108,28 @@ where
         &self.keyserver_manager
     }
 
+    /// Get the rate limiters and circuit breakers guarding pushes to peers.
+    pub fn get_push_guard(&self) -> &PushGuard {
+        &self.push_guard
+    }
+
     // TODO: actually use this
     #[allow(dead_code)]
     pub async fn get_urls(&self) -> Vec<Uri> {
         self.keyserver_manager.get_uris().read().await.clone()
     }
 
-    pub async fn set_peers(&self, uris: Vec<Uri>) {
+    /// Set the peers, retaining the [`ServerInfo`] they advertised.
+    pub async fn set_peers(&self, peers: Vec<Peer>) {
+        let uris: Vec<Uri> = peers
+            .iter()
+            .filter_map(|peer| parse_uri_warn(&peer.url))
+            .collect();
+        self.push_guard.retain(&uris);
         let mut peer_cache_write = self.peers_cache.write().await;
         let uris_shared = self.keyserver_manager.get_uris();
         let mut uris_write = uris_shared.write().await;
-        *peer_cache_write = uris_to_raw_peers(&uris);
+        *peer_cache_write = peers_to_raw_peers(peers, &self.server_info);
         *uris_write = uris;
     }
 
@@ -112,14 +155,21 @@ where
         let aggregate_response = self.get_keyserver_manager().crawl_peers().await?;
         // TODO: Ban misbehaviour
 
-        // Collect URIs
-        let uris = aggregate_response
+        // Collect peers
+        let peers = aggregate_response
             .response
             .peers
             .into_iter()
-            .filter_map(|peer| parse_uri_warn(&peer.url))
+            .filter(|peer| parse_uri_warn(&peer.url).is_some())
+            .filter(|peer| match &peer.info {
+                Some(info) if info.network != SETTINGS.network => {
+                    warn!(message = "peer on different network", url = %peer.url, network = %info.network);
+                    false
+                }
+                _ => true,
+            })
             .collect();
-        self.set_peers(uris).await;
+        self.set_peers(peers).await;
         Ok(())
     }
 }
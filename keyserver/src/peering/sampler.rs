@@ -0,0 +1,174 @@
+use std::{fmt, future::Future, time::Duration};
+
+use cashweb::keyserver_client::{
+    select_auth_wrapper, services::GetMetadata, uniform_random_sampler, KeyserverManager,
+    MetadataPackage,
+};
+use futures::stream::{FuturesUnordered, StreamExt};
+use hyper::{Body, Request, Response, Uri};
+use tokio::time::{sleep, timeout};
+use tower_service::Service;
+use tower_util::ServiceExt;
+use tracing::warn;
+
+use crate::SETTINGS;
+
+/// Time allowed for a peer sample.
+#[derive(Clone, Copy, Debug)]
+pub struct SampleBudget {
+    /// Total time allowed for the sample.
+    pub deadline: Duration,
+    /// Time allowed for each peer to answer.
+    pub peer_timeout: Duration,
+    /// Number of answers after which the remaining peers are abandoned.
+    pub quorum: usize,
+}
+
+impl SampleBudget {
+    /// Construct the budget from the peering settings.
+    pub fn from_settings() -> Self {
+        Self {
+            deadline: Duration::from_millis(SETTINGS.peering.sample_deadline),
+            peer_timeout: Duration::from_millis(SETTINGS.peering.sample_timeout),
+            quorum: SETTINGS.peering.sample_quorum,
+        }
+    }
+}
+
+/// Await `requests` concurrently until `quorum` of them succeed or the deadline passes.
+///
+/// Requests still in flight when this returns are dropped, cancelling them.
+pub async fn collect_quorum<F, R, E>(
+    requests: impl IntoIterator<Item = (Uri, F)>,
+    budget: SampleBudget,
+) -> Vec<(Uri, R)>
+where
+    F: Future<Output = Result<R, E>>,
+    E: fmt::Display,
+{
+    let peer_timeout = budget.peer_timeout;
+    let mut pending: FuturesUnordered<_> = requests
+        .into_iter()
+        .map(|(uri, request)| async move { (uri, timeout(peer_timeout, request).await) })
+        .collect();
+
+    let quorum = budget.quorum.max(1);
+    let mut answers = Vec::with_capacity(quorum);
+    let deadline = sleep(budget.deadline);
+    tokio::pin!(deadline);
+    while answers.len() < quorum {
+        tokio::select! {
+            next = pending.next() => match next {
+                Some((uri, Ok(Ok(answer)))) => answers.push((uri, answer)),
+                Some((uri, Ok(Err(err)))) => {
+                    warn!(message = "peer sample failed", uri = %uri, error = %err)
+                }
+                Some((uri, Err(_))) => warn!(message = "peer sample timed out", uri = %uri),
+                None => break,
+            },
+            _ = &mut deadline => {
+                warn!(message = "peer sample deadline exceeded", pending = pending.len());
+                break;
+            }
+        }
+    }
+    answers
+}
+
+/// Sample metadata from peers within `budget`, selecting the latest of the answers received.
+pub async fn sample_metadata<S>(
+    manager: &KeyserverManager<S>,
+    address: &str,
+    sample_size: usize,
+    budget: SampleBudget,
+) -> Option<(Uri, MetadataPackage)>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display + Send,
+{
+    let uris = manager.get_uris().read().await.clone();
+    let uris = uniform_random_sampler(&uris, sample_size);
+    let client = manager.clone().into_client();
+    let requests = uris.into_iter().map(|uri| {
+        let uri: Uri = format!("{}keys/{}", with_trailing_slash(&uri), address)
+            .parse()
+            .unwrap_or(uri);
+        let request = client.clone().oneshot((uri.clone(), GetMetadata));
+        (uri, request)
+    });
+    let answers = collect_quorum(requests, budget).await;
+    select_auth_wrapper(answers)
+}
+
+fn with_trailing_slash(uri: &Uri) -> String {
+    let uri = uri.to_string();
+    if uri.ends_with('/') {
+        uri
+    } else {
+        uri + "/"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn budget(quorum: usize) -> SampleBudget {
+        SampleBudget {
+            deadline: Duration::from_millis(200),
+            peer_timeout: Duration::from_millis(100),
+            quorum,
+        }
+    }
+
+    async fn answer(delay: u64, value: u32) -> Result<u32, String> {
+        sleep(Duration::from_millis(delay)).await;
+        Ok(value)
+    }
+
+    fn requests(delays: &[u64]) -> Vec<(Uri, impl Future<Output = Result<u32, String>>)> {
+        delays
+            .iter()
+            .enumerate()
+            .map(|(i, delay)| {
+                let uri = format!("http://127.0.0.{}/", i).parse().unwrap();
+                (uri, answer(*delay, i as u32))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn quorum_cancels_stragglers() {
+        let start = Instant::now();
+        let answers = collect_quorum(requests(&[10, 20, 10_000]), budget(2)).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        let mut values: Vec<u32> = answers.into_iter().map(|(_, value)| value).collect();
+        values.sort_unstable();
+        assert_eq!(values, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn slow_peers_time_out() {
+        let start = Instant::now();
+        let answers = collect_quorum(requests(&[10, 10_000, 10_000]), budget(3)).await;
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deadline_bounds_sample() {
+        let budget = SampleBudget {
+            deadline: Duration::from_millis(50),
+            peer_timeout: Duration::from_secs(10),
+            quorum: 1,
+        };
+        let start = Instant::now();
+        let answers = collect_quorum(requests(&[10_000]), budget).await;
+        assert!(start.elapsed() < Duration::from_millis(1_000));
+        assert!(answers.is_empty());
+    }
+}
@@ -1,56 +1,185 @@
-use std::{collections::VecDeque, fmt, sync::Arc};
+use std::{collections::VecDeque, fmt, sync::Arc, time::Instant};
 
 use bitcoincash_addr::Address;
-use dashmap::DashSet;
 use hyper::{Body, Request, Response};
+use indexmap::IndexSet;
 use tokio::sync::RwLock;
 use tower_service::Service;
+use tracing::{error, warn};
 
-use crate::{db::Database, peering::PeerHandler, SETTINGS};
+#[cfg(feature = "monitoring")]
+use crate::monitoring;
+use crate::{
+    db::Database,
+    peering::{is_peer_failure, PeerHandler},
+    SETTINGS,
+};
 
+struct TokenBlocks {
+    /// Number of blocks seen.
+    epoch: u64,
+    /// Addresses awaiting broadcast, newest block first. Within a block, addresses are ordered
+    /// from least to most recently added.
+    blocks: VecDeque<IndexSet<Address>>,
+    len: usize,
+}
+
+/// Addresses whose metadata is broadcast to peers once enough blocks have passed.
+///
+/// Entries are persisted so that pending broadcasts survive restarts. When more than
+/// `max_entries` addresses are pending, the least recently added are evicted.
 #[derive(Clone)]
 pub struct TokenCache {
-    tokens_blocks: Arc<RwLock<VecDeque<DashSet<Address>>>>,
+    tokens_blocks: Arc<RwLock<TokenBlocks>>,
+    db: Database,
+    max_entries: usize,
 }
 
-impl Default for TokenCache {
-    fn default() -> Self {
-        let deque = VecDeque::from(vec![Default::default(); SETTINGS.peering.broadcast_delay]);
-        Self {
-            tokens_blocks: Arc::new(RwLock::new(deque)),
-        }
-    }
+fn update_size_metric(_len: usize) {
+    #[cfg(feature = "monitoring")]
+    monitoring::TOKEN_CACHE_SIZE.set(_len as i64);
+}
+
+fn record_outcome(_outcome: &str) {
+    #[cfg(feature = "monitoring")]
+    monitoring::BROADCAST_TOTAL
+        .with_label_values(&[_outcome])
+        .inc();
 }
 
 impl TokenCache {
+    /// Construct the cache, restoring the entries persisted in the database.
+    pub fn load(db: Database) -> Result<Self, rocksdb::Error> {
+        Self::restore(
+            db,
+            SETTINGS.peering.broadcast_delay,
+            SETTINGS.peering.token_cache_size,
+        )
+    }
+
+    fn restore(
+        db: Database,
+        broadcast_delay: usize,
+        max_entries: usize,
+    ) -> Result<Self, rocksdb::Error> {
+        let n_blocks = broadcast_delay.max(1);
+        let epoch = db.get_token_epoch()?.unwrap_or_default();
+        let mut tokens_blocks = TokenBlocks {
+            epoch,
+            blocks: VecDeque::from(vec![IndexSet::new(); n_blocks]),
+            len: 0,
+        };
+
+        // Insert oldest first so that eviction order is preserved
+        let mut pending = db.get_pending_tokens();
+        pending.sort_by_key(|(_, added)| *added);
+        for (addr_str, added) in pending {
+            let addr = match Address::decode(&addr_str) {
+                Ok(ok) => ok,
+                Err(_) => {
+                    warn!(message = "dropping malformed pending token", address = %addr_str);
+                    continue;
+                }
+            };
+            let index = (epoch.saturating_sub(added) as usize).min(n_blocks - 1);
+            if tokens_blocks.blocks[index].insert(addr) {
+                tokens_blocks.len += 1;
+            }
+        }
+        update_size_metric(tokens_blocks.len);
+
+        Ok(Self {
+            tokens_blocks: Arc::new(RwLock::new(tokens_blocks)),
+            db,
+            max_entries,
+        })
+    }
+
     pub async fn add_token(&self, addr: Address) {
-        let token_blocks = self.tokens_blocks.read().await;
-        // TODO: Check previous blocks?
-        // TODO: Check consistency garauntees of the dashmap under iter + insert
-        token_blocks.front().unwrap().insert(addr); // TODO: Double check this is safe
+        let addr_str = addr.encode().unwrap(); // This is safe
+        let mut tokens_blocks = self.tokens_blocks.write().await;
+
+        // Refresh existing entry
+        let existing = tokens_blocks
+            .blocks
+            .iter_mut()
+            .any(|block| block.shift_remove(&addr));
+        if !existing {
+            tokens_blocks.len += 1;
+        }
+        tokens_blocks.blocks.front_mut().unwrap().insert(addr); // This is safe
+        if let Err(err) = self.db.put_pending_token(&addr_str, tokens_blocks.epoch) {
+            error!(message = "failed to persist pending token", error = %err);
+        }
+
+        // Evict least recently added
+        let mut evicted = Vec::new();
+        while tokens_blocks.len > self.max_entries {
+            let oldest = tokens_blocks
+                .blocks
+                .iter_mut()
+                .rev()
+                .find_map(|block| block.shift_remove_index(0));
+            match oldest {
+                Some(oldest) => {
+                    tokens_blocks.len -= 1;
+                    record_outcome("evicted");
+                    evicted.push(oldest.encode().unwrap()); // This is safe
+                }
+                None => break,
+            }
+        }
+        if !evicted.is_empty() {
+            warn!(message = "evicted pending tokens", count = evicted.len());
+            if let Err(err) = self
+                .db
+                .remove_pending_tokens(evicted.iter().map(String::as_str), None)
+            {
+                error!(message = "failed to remove evicted tokens", error = %err);
+            }
+        }
+        update_size_metric(tokens_blocks.len);
+    }
+
+    /// Cycle the blocks and remove the addresses due for broadcast.
+    async fn cycle(&self) -> IndexSet<Address> {
+        let mut tokens_blocks = self.tokens_blocks.write().await;
+        tokens_blocks.blocks.push_front(Default::default());
+        tokens_blocks.epoch += 1;
+        let token_block = tokens_blocks.blocks.pop_back().unwrap_or_default();
+        tokens_blocks.len -= token_block.len();
+
+        let addrs: Vec<String> = token_block
+            .iter()
+            .map(|addr| addr.encode().unwrap()) // This is safe
+            .collect();
+        if let Err(err) = self
+            .db
+            .remove_pending_tokens(addrs.iter().map(String::as_str), Some(tokens_blocks.epoch))
+        {
+            error!(message = "failed to remove broadcast tokens", error = %err);
+        }
+        update_size_metric(tokens_blocks.len);
+        token_block
     }
 
-    pub async fn broadcast_block<S>(&self, peer_handler: &PeerHandler<S>, db: &Database)
+    pub async fn broadcast_block<S>(&self, peer_handler: &PeerHandler<S>)
     where
         S: Service<Request<Body>, Response = Response<Body>>,
         S: Send + Clone + 'static,
         <S as Service<Request<Body>>>::Future: Send,
         S::Error: Send + fmt::Debug + fmt::Display,
     {
-        let mut token_blocks = self.tokens_blocks.write().await;
-
-        // Cycle blocks
-        token_blocks.push_front(Default::default());
-        let token_block = match token_blocks.pop_back() {
-            Some(some) => some,
-            None => return,
-        };
+        let token_block = self.cycle().await;
 
         // Broadcast each metadata
         for addr in token_block.into_iter() {
-            let db_wrapper = match db.get_metadata(addr.as_body()) {
+            let db_wrapper = match self.db.get_metadata(addr.as_body()) {
                 Ok(Some(some)) => some,
-                _ => continue,
+                _ => {
+                    record_outcome("missing");
+                    continue;
+                }
             };
             let addr_str = addr.encode().unwrap(); // This is safe
 
@@ -59,17 +188,117 @@ impl TokenCache {
             let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
             let token = format!("POP {}", base64::encode_config(raw_token, url_safe_config));
 
-            let _response = peer_handler
+            // Pace pushes and skip failing peers
+            let uris = peer_handler
                 .get_keyserver_manager()
-                .uniform_broadcast_raw_metadata(
+                .get_uris()
+                .read()
+                .await
+                .clone();
+            let push_guard = peer_handler.get_push_guard();
+            let targets = push_guard.select(&uris, SETTINGS.peering.push_fan_size, Instant::now());
+            if targets.is_empty() {
+                record_outcome("throttled");
+                continue;
+            }
+
+            let results = peer_handler
+                .get_keyserver_manager()
+                .broadcast_raw_metadata(
                     &addr_str,
                     db_wrapper.serialized_auth_wrapper,
                     token,
-                    SETTINGS.peering.push_fan_size,
+                    targets,
                 )
                 .await;
+            let now = Instant::now();
+            let mut n_errors = 0;
+            for (uri, result) in &results {
+                match result {
+                    Ok(()) => push_guard.record_success(uri),
+                    Err(err) => {
+                        n_errors += 1;
+                        if is_peer_failure(err) {
+                            push_guard.record_failure(uri, now);
+                        } else {
+                            push_guard.record_success(uri);
+                        }
+                    }
+                }
+            }
+            match n_errors {
+                0 => record_outcome("success"),
+                n if n == results.len() => record_outcome("failure"),
+                _ => record_outcome("partial"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{Options, DB};
 
-            // TODO: Remove errors from peer list
+    use super::*;
+
+    fn address(byte: u8) -> Address {
+        Address {
+            body: vec![byte; 20],
+            ..Default::default()
         }
     }
+
+    async fn pending(cache: &TokenCache) -> Vec<Vec<Address>> {
+        cache
+            .tokens_blocks
+            .read()
+            .await
+            .blocks
+            .iter()
+            .map(|block| block.iter().cloned().collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn evict_and_restore() {
+        const TEST_NAME: &str = "./tests/evict_and_restore";
+
+        let database = Database::try_new(TEST_NAME).unwrap();
+        let cache = TokenCache::restore(database.clone(), 2, 2).unwrap();
+
+        cache.add_token(address(1)).await;
+        cache.cycle().await;
+        cache.add_token(address(2)).await;
+        cache.add_token(address(1)).await;
+        assert_eq!(
+            pending(&cache).await,
+            vec![vec![address(2), address(1)], vec![]]
+        );
+
+        // Least recently added is evicted
+        cache.add_token(address(3)).await;
+        assert_eq!(
+            pending(&cache).await,
+            vec![vec![address(1), address(3)], vec![]]
+        );
+
+        // Restored entries keep their blocks
+        cache.cycle().await;
+        drop(cache);
+        let cache = TokenCache::restore(database.clone(), 2, 2).unwrap();
+        let restored = pending(&cache).await;
+        assert!(restored[0].is_empty());
+        assert_eq!(restored[1].len(), 2);
+        assert!(restored[1].contains(&address(1)));
+        assert!(restored[1].contains(&address(3)));
+
+        // Broadcast entries are removed from the database
+        assert_eq!(cache.cycle().await.len(), 2);
+        assert!(database.get_pending_tokens().is_empty());
+
+        // Destroy database
+        drop(cache);
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
 }
@@ -1,14 +1,27 @@
-use std::sync::Arc;
+use std::{convert::TryInto, sync::Arc};
 
 use cashweb::auth_wrapper::AuthWrapper;
 use prost::Message as _;
-use rocksdb::{ColumnFamily, Direction, IteratorMode, Options, DB};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, Options, WriteBatch, DB};
 use thiserror::Error;
 
-use crate::crypto::sha256;
+use crate::{
+    crypto::{hash160, sha256},
+    models::broadcast::{MessageBurns, ReportEntry},
+    pubsub::Topic,
+};
 
 const MESSAGE_CF_NAME: &str = "messages";
 const PAYLOADS_CF_NAME: &str = "payloads";
+const BANNED_TOPICS_CF_NAME: &str = "banned_topics";
+const BURN_OUTPOINTS_CF_NAME: &str = "burn_outpoints";
+const AUTHORS_CF_NAME: &str = "authors";
+const REPLIES_CF_NAME: &str = "replies";
+const BURNS_CF_NAME: &str = "burns";
+const REPORTS_CF_NAME: &str = "reports";
+const REPORTERS_CF_NAME: &str = "reporters";
+const HIDDEN_MESSAGES_CF_NAME: &str = "hidden_messages";
+const BLOCKED_SENDERS_CF_NAME: &str = "blocked_senders";
 
 #[derive(Clone)]
 pub struct PubSubDatabase {
@@ -24,12 +37,6 @@ pub enum PubSubDatabaseError {
     ProstDecode(#[from] prost::DecodeError),
     #[error("Value not found in messages: {0}")]
     MissingValue(String),
-    #[error("Topic has too many separators: {0} > 10")]
-    TopicTooLong(usize),
-    #[error("Topic contains invalid characters")]
-    TopicInvalidCharacters(),
-    #[error("Topic contains empty segments")]
-    TopicInvalidSegments(),
 }
 
 impl PubSubDatabase {
@@ -38,7 +45,23 @@ impl PubSubDatabase {
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
 
-        let db = DB::open_cf(&opts, &path, &[MESSAGE_CF_NAME, PAYLOADS_CF_NAME])?;
+        let db = DB::open_cf(
+            &opts,
+            &path,
+            &[
+                MESSAGE_CF_NAME,
+                PAYLOADS_CF_NAME,
+                BANNED_TOPICS_CF_NAME,
+                BURN_OUTPOINTS_CF_NAME,
+                AUTHORS_CF_NAME,
+                REPLIES_CF_NAME,
+                BURNS_CF_NAME,
+                REPORTS_CF_NAME,
+                REPORTERS_CF_NAME,
+                HIDDEN_MESSAGES_CF_NAME,
+                BLOCKED_SENDERS_CF_NAME,
+            ],
+        )?;
         Ok(PubSubDatabase { db: Arc::new(db) })
     }
 
@@ -46,18 +69,12 @@ impl PubSubDatabase {
     pub fn put_message(
         &self,
         timestamp: u64,
-        topic: &str,
+        topic: &Topic,
         message: &AuthWrapper,
     ) -> Result<(), PubSubDatabaseError> {
         let mut buf = Vec::new();
         message.encode(&mut buf)?;
-        let split_topic = topic.split('.').collect::<Vec<_>>();
-        if split_topic.len() > 10 {
-            return Err(PubSubDatabaseError::TopicTooLong(split_topic.len()));
-        }
-        if split_topic.iter().any(|segment| segment.is_empty()) {
-            return Err(PubSubDatabaseError::TopicInvalidSegments());
-        }
+        let split_topic = topic.segments();
 
         self.db
             .put_cf(self.cf_payloads(), &message.payload_digest, &buf)?;
@@ -74,6 +91,18 @@ impl PubSubDatabase {
             self.db
                 .put_cf(self.cf_message(), &topical_key, &message.payload_digest)?;
         }
+
+        // Index by author
+        if !message.public_key.is_empty() {
+            let author_key = [
+                hash160(&message.public_key).as_ref(),
+                timestamp.to_be_bytes().as_ref(),
+                &message.payload_digest,
+            ]
+            .concat();
+            self.db
+                .put_cf(self.cf_authors(), &author_key, &message.payload_digest)?;
+        }
         Ok(())
     }
 
@@ -90,18 +119,11 @@ impl PubSubDatabase {
     /// Get serialized `messages` from database.
     pub fn get_messages_to(
         &self,
-        topic: &str,
+        topic: &Topic,
         from: i64,
         to: i64,
     ) -> Result<Vec<AuthWrapper>, PubSubDatabaseError> {
-        let valid_topic = topic
-            .chars()
-            .all(|c| c.is_lowercase() || c.is_numeric() || c == '.' || c == '-');
-        if !valid_topic {
-            return Err(PubSubDatabaseError::TopicInvalidCharacters());
-        }
-
-        let topic_digest = sha256(topic.as_bytes());
+        let topic_digest = sha256(topic.as_str().as_bytes());
         let start_prefix = [&topic_digest, from.to_be_bytes().as_ref()].concat();
         let end_prefix = [&topic_digest, to.to_be_bytes().as_ref()].concat();
 
@@ -110,22 +132,134 @@ impl PubSubDatabase {
             IteratorMode::From(&start_prefix, Direction::Forward),
         );
 
-        iter.take_while(|(key, _)| key.as_ref() <= end_prefix.as_slice())
-            .map(|(_, payload_digest)| self.get_message(&payload_digest))
-            .collect()
+        let payload_digests = iter
+            .take_while(|(key, _)| key.as_ref() <= end_prefix.as_slice())
+            .map(|(_, payload_digest)| payload_digest);
+        self.get_visible_messages(payload_digests)
     }
 
     /// Get a vector of messages starting at some unix timestamp.
-    /// TODO: actually use this
-    #[allow(dead_code)]
     pub fn get_messages(
         &self,
-        topic: &str,
+        topic: &Topic,
         from: i64,
     ) -> Result<Vec<AuthWrapper>, PubSubDatabaseError> {
         self.get_messages_to(topic, from, i64::MAX)
     }
 
+    /// Get a page of at most `limit` messages starting at some unix timestamp.
+    ///
+    /// Messages sharing a timestamp are never split across pages, so a page may exceed the limit
+    /// when the final timestamp is shared. Also returns the timestamp at which the next page
+    /// starts, if any messages remain.
+    pub fn get_messages_page(
+        &self,
+        topic: &Topic,
+        from: i64,
+        limit: usize,
+    ) -> Result<(Vec<AuthWrapper>, Option<i64>), PubSubDatabaseError> {
+        let topic_digest = sha256(topic.as_str().as_bytes());
+        let start_prefix = [&topic_digest, from.to_be_bytes().as_ref()].concat();
+
+        let iter = self
+            .db
+            .iterator_cf(
+                self.cf_message(),
+                IteratorMode::From(&start_prefix, Direction::Forward),
+            )
+            .take_while(|(key, _)| key.starts_with(&topic_digest));
+
+        let mut payload_digests = Vec::new();
+        let mut last_timestamp = None;
+        let mut next_since = None;
+        for (key, payload_digest) in iter {
+            let raw_timestamp: [u8; 8] = key[32..40].try_into().unwrap(); // This is safe
+            let timestamp = i64::from_be_bytes(raw_timestamp);
+            if payload_digests.len() >= limit && last_timestamp != Some(timestamp) {
+                next_since = Some(timestamp);
+                break;
+            }
+            last_timestamp = Some(timestamp);
+            payload_digests.push(payload_digest);
+        }
+        let messages = self.get_visible_messages(payload_digests.into_iter())?;
+        Ok((messages, next_since))
+    }
+
+    /// Record a message as a reply to a parent message.
+    pub fn put_reply(
+        &self,
+        timestamp: u64,
+        parent_digest: &[u8],
+        payload_digest: &[u8],
+    ) -> Result<(), PubSubDatabaseError> {
+        let reply_key = [
+            parent_digest,
+            timestamp.to_be_bytes().as_ref(),
+            payload_digest,
+        ]
+        .concat();
+        self.db
+            .put_cf(self.cf_replies(), &reply_key, payload_digest)?;
+        Ok(())
+    }
+
+    /// Get the replies to a parent message, received between two unix timestamps.
+    pub fn get_replies(
+        &self,
+        parent_digest: &[u8],
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<AuthWrapper>, PubSubDatabaseError> {
+        let start_prefix = [parent_digest, from.to_be_bytes().as_ref()].concat();
+        let end_prefix = [parent_digest, to.to_be_bytes().as_ref()].concat();
+
+        let payload_digests = self
+            .db
+            .iterator_cf(
+                self.cf_replies(),
+                IteratorMode::From(&start_prefix, Direction::Forward),
+            )
+            .take_while(|(key, _)| key.as_ref() <= end_prefix.as_slice())
+            .map(|(_, payload_digest)| payload_digest);
+        self.get_visible_messages(payload_digests)
+    }
+
+    /// Get the messages authored by the public key hash, received between two unix timestamps.
+    pub fn get_messages_by_author(
+        &self,
+        pubkey_hash: &[u8],
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<AuthWrapper>, PubSubDatabaseError> {
+        let start_prefix = [pubkey_hash, from.to_be_bytes().as_ref()].concat();
+        let end_prefix = [pubkey_hash, to.to_be_bytes().as_ref()].concat();
+
+        let payload_digests = self
+            .db
+            .iterator_cf(
+                self.cf_authors(),
+                IteratorMode::From(&start_prefix, Direction::Forward),
+            )
+            .take_while(|(key, _)| key.as_ref() <= end_prefix.as_slice())
+            .map(|(_, payload_digest)| payload_digest);
+        self.get_visible_messages(payload_digests)
+    }
+
+    /// Get the messages with the given payload digests, skipping those hidden by the operator.
+    fn get_visible_messages(
+        &self,
+        payload_digests: impl Iterator<Item = Box<[u8]>>,
+    ) -> Result<Vec<AuthWrapper>, PubSubDatabaseError> {
+        let mut messages = Vec::new();
+        for payload_digest in payload_digests {
+            if !self.is_hidden(&payload_digest)? {
+                messages.push(self.get_message(&payload_digest)?);
+            }
+        }
+        Ok(messages)
+    }
+
     /// Get a specific message by payload hash.
     pub fn get_message(&self, payload_digest: &[u8]) -> Result<AuthWrapper, PubSubDatabaseError> {
         match self.db.get_cf(self.cf_payloads(), payload_digest)? {
@@ -136,6 +270,198 @@ impl PubSubDatabase {
         }
     }
 
+    /// Get the payload digest which a burn outpoint has been counted towards.
+    pub fn get_burn_outpoint(
+        &self,
+        outpoint: &[u8],
+    ) -> Result<Option<Vec<u8>>, PubSubDatabaseError> {
+        Ok(self.db.get_cf(self.cf_burn_outpoints(), outpoint)?)
+    }
+
+    /// Record burn outpoints as counted towards a payload digest.
+    pub fn put_burn_outpoints<'a>(
+        &self,
+        outpoints: impl IntoIterator<Item = &'a [u8]>,
+        payload_digest: &[u8],
+    ) -> Result<(), PubSubDatabaseError> {
+        let mut batch = WriteBatch::default();
+        for outpoint in outpoints {
+            batch.put_cf(self.cf_burn_outpoints(), outpoint, payload_digest);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Replace the burns counted towards a message.
+    pub fn put_burns(&self, burns: &MessageBurns) -> Result<(), PubSubDatabaseError> {
+        let mut buf = Vec::with_capacity(burns.encoded_len());
+        burns.encode(&mut buf)?;
+        self.db
+            .put_cf(self.cf_burns(), &burns.payload_digest, &buf)?;
+        Ok(())
+    }
+
+    /// Get the burns counted towards a message, without its payload.
+    pub fn get_burns(
+        &self,
+        payload_digest: &[u8],
+    ) -> Result<Option<MessageBurns>, PubSubDatabaseError> {
+        match self.db.get_cf(self.cf_burns(), payload_digest)? {
+            Some(raw_burns) => Ok(Some(MessageBurns::decode(raw_burns.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Add a banned topic prefix.
+    pub fn put_banned_topic(&self, topic: &str) -> Result<(), PubSubDatabaseError> {
+        self.db.put_cf(self.cf_banned_topics(), topic, b"")?;
+        Ok(())
+    }
+
+    /// Remove a banned topic prefix, returning whether it was present.
+    pub fn remove_banned_topic(&self, topic: &str) -> Result<bool, PubSubDatabaseError> {
+        if self.db.get_cf(self.cf_banned_topics(), topic)?.is_none() {
+            return Ok(false);
+        }
+        self.db.delete_cf(self.cf_banned_topics(), topic)?;
+        Ok(true)
+    }
+
+    /// Get all banned topic prefixes.
+    pub fn get_banned_topics(&self) -> Result<Vec<String>, PubSubDatabaseError> {
+        self.db
+            .iterator_cf(self.cf_banned_topics(), IteratorMode::Start)
+            .map(|(key, _)| Ok(String::from_utf8_lossy(&key).into_owned()))
+            .collect()
+    }
+
+    /// Store a report, indexing it by reporter.
+    pub fn put_report(&self, entry: &ReportEntry) -> Result<(), PubSubDatabaseError> {
+        let mut buf = Vec::with_capacity(entry.encoded_len());
+        entry.encode(&mut buf)?;
+        let reporter_key = [
+            &entry.reporter[..],
+            entry.received_time.to_be_bytes().as_ref(),
+            &entry.id,
+        ]
+        .concat();
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(self.cf_reports(), &entry.id, &buf);
+        batch.put_cf(self.cf_reporters(), &reporter_key, b"");
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Count the reports made by a reporter since a unix timestamp.
+    ///
+    /// Older entries are no longer needed to count reports and are pruned.
+    pub fn count_reports_since(
+        &self,
+        reporter: &[u8],
+        since: i64,
+    ) -> Result<usize, PubSubDatabaseError> {
+        let since_prefix = [reporter, since.to_be_bytes().as_ref()].concat();
+        let mut batch = WriteBatch::default();
+        let mut count = 0;
+        for (key, _) in self
+            .db
+            .iterator_cf(
+                self.cf_reporters(),
+                IteratorMode::From(reporter, Direction::Forward),
+            )
+            .take_while(|(key, _)| key.starts_with(reporter))
+        {
+            if key.as_ref() < since_prefix.as_slice() {
+                batch.delete_cf(self.cf_reporters(), key);
+            } else {
+                count += 1;
+            }
+        }
+        self.db.write(batch)?;
+        Ok(count)
+    }
+
+    /// Get a report by its ID.
+    pub fn get_report(&self, id: &[u8]) -> Result<Option<ReportEntry>, PubSubDatabaseError> {
+        match self.db.get_cf(self.cf_reports(), id)? {
+            Some(raw_entry) => Ok(Some(ReportEntry::decode(raw_entry.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get all reports awaiting review, oldest first.
+    pub fn get_reports(&self) -> Result<Vec<ReportEntry>, PubSubDatabaseError> {
+        let mut reports = self
+            .db
+            .iterator_cf(self.cf_reports(), IteratorMode::Start)
+            .map(|(_, raw_entry)| Ok(ReportEntry::decode(raw_entry.as_ref())?))
+            .collect::<Result<Vec<_>, PubSubDatabaseError>>()?;
+        reports.sort_by_key(|entry| entry.received_time);
+        Ok(reports)
+    }
+
+    /// Remove a report, returning whether it was present.
+    pub fn remove_report(&self, id: &[u8]) -> Result<bool, PubSubDatabaseError> {
+        if self.db.get_cf(self.cf_reports(), id)?.is_none() {
+            return Ok(false);
+        }
+        self.db.delete_cf(self.cf_reports(), id)?;
+        Ok(true)
+    }
+
+    /// Hide a message from listings.
+    pub fn put_hidden_message(&self, payload_digest: &[u8]) -> Result<(), PubSubDatabaseError> {
+        self.db
+            .put_cf(self.cf_hidden_messages(), payload_digest, b"")?;
+        Ok(())
+    }
+
+    /// Unhide a message, returning whether it was hidden.
+    pub fn remove_hidden_message(
+        &self,
+        payload_digest: &[u8],
+    ) -> Result<bool, PubSubDatabaseError> {
+        if !self.is_hidden(payload_digest)? {
+            return Ok(false);
+        }
+        self.db
+            .delete_cf(self.cf_hidden_messages(), payload_digest)?;
+        Ok(true)
+    }
+
+    /// Check whether a message has been hidden.
+    pub fn is_hidden(&self, payload_digest: &[u8]) -> Result<bool, PubSubDatabaseError> {
+        Ok(self
+            .db
+            .get_cf(self.cf_hidden_messages(), payload_digest)?
+            .is_some())
+    }
+
+    /// Block a sender, by public key hash, from posting messages.
+    pub fn put_blocked_sender(&self, pubkey_hash: &[u8]) -> Result<(), PubSubDatabaseError> {
+        self.db
+            .put_cf(self.cf_blocked_senders(), pubkey_hash, b"")?;
+        Ok(())
+    }
+
+    /// Unblock a sender, returning whether it was blocked.
+    pub fn remove_blocked_sender(&self, pubkey_hash: &[u8]) -> Result<bool, PubSubDatabaseError> {
+        if !self.is_blocked(pubkey_hash)? {
+            return Ok(false);
+        }
+        self.db.delete_cf(self.cf_blocked_senders(), pubkey_hash)?;
+        Ok(true)
+    }
+
+    /// Check whether a sender, by public key hash, has been blocked.
+    pub fn is_blocked(&self, pubkey_hash: &[u8]) -> Result<bool, PubSubDatabaseError> {
+        Ok(self
+            .db
+            .get_cf(self.cf_blocked_senders(), pubkey_hash)?
+            .is_some())
+    }
+
     fn cf_message(&self) -> &ColumnFamily {
         self.db.cf_handle(MESSAGE_CF_NAME).unwrap()
     }
@@ -143,6 +469,42 @@ impl PubSubDatabase {
     fn cf_payloads(&self) -> &ColumnFamily {
         self.db.cf_handle(PAYLOADS_CF_NAME).unwrap()
     }
+
+    fn cf_banned_topics(&self) -> &ColumnFamily {
+        self.db.cf_handle(BANNED_TOPICS_CF_NAME).unwrap()
+    }
+
+    fn cf_burn_outpoints(&self) -> &ColumnFamily {
+        self.db.cf_handle(BURN_OUTPOINTS_CF_NAME).unwrap()
+    }
+
+    fn cf_authors(&self) -> &ColumnFamily {
+        self.db.cf_handle(AUTHORS_CF_NAME).unwrap()
+    }
+
+    fn cf_replies(&self) -> &ColumnFamily {
+        self.db.cf_handle(REPLIES_CF_NAME).unwrap()
+    }
+
+    fn cf_burns(&self) -> &ColumnFamily {
+        self.db.cf_handle(BURNS_CF_NAME).unwrap()
+    }
+
+    fn cf_reports(&self) -> &ColumnFamily {
+        self.db.cf_handle(REPORTS_CF_NAME).unwrap()
+    }
+
+    fn cf_reporters(&self) -> &ColumnFamily {
+        self.db.cf_handle(REPORTERS_CF_NAME).unwrap()
+    }
+
+    fn cf_hidden_messages(&self) -> &ColumnFamily {
+        self.db.cf_handle(HIDDEN_MESSAGES_CF_NAME).unwrap()
+    }
+
+    fn cf_blocked_senders(&self) -> &ColumnFamily {
+        self.db.cf_handle(BLOCKED_SENDERS_CF_NAME).unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -151,6 +513,10 @@ mod tests {
 
     use super::*;
 
+    fn topic(raw: &str) -> Topic {
+        Topic::parse(raw).unwrap()
+    }
+
     #[test]
     fn messages() {
         const TEST_NAME: &str = "./tests/messages";
@@ -164,21 +530,21 @@ mod tests {
             ..Default::default()
         };
 
-        let data_wrapper_out_0 = database.get_messages("foo.bar.bob", 0).unwrap();
+        let data_wrapper_out_0 = database.get_messages(&topic("foo.bar.bob"), 0).unwrap();
         assert_eq!(data_wrapper_out_0.len(), 0);
 
         // Put to database
         database
-            .put_message(1, "foo.bar.bob", &message_one)
+            .put_message(1, &topic("foo.bar.bob"), &message_one)
             .unwrap();
 
         // Get from database
-        let data_wrapper_out = database.get_messages("foo.bar.bob", 0).unwrap();
+        let data_wrapper_out = database.get_messages(&topic("foo.bar.bob"), 0).unwrap();
         assert_eq!(data_wrapper_out.len(), 1);
         assert_eq!(message_one, data_wrapper_out[0]);
 
         // Get from database
-        let data_wrapper_out = database.get_messages("foo", 0).unwrap();
+        let data_wrapper_out = database.get_messages(&topic("foo"), 0).unwrap();
         assert_eq!(data_wrapper_out.len(), 1);
         assert_eq!(message_one, data_wrapper_out[0]);
 
@@ -189,20 +555,22 @@ mod tests {
         };
 
         // Put to database
-        database.put_message(1, "foo.bar", &message_two).unwrap();
+        database
+            .put_message(1, &topic("foo.bar"), &message_two)
+            .unwrap();
 
         // Get from database
-        let data_wrapper_out_two = database.get_messages("foo.bar.bob", 0).unwrap();
+        let data_wrapper_out_two = database.get_messages(&topic("foo.bar.bob"), 0).unwrap();
         assert_eq!(data_wrapper_out_two.len(), 1);
         assert_eq!(message_one, data_wrapper_out_two[0]);
 
         // Get from database
-        let data_wrapper_three = database.get_messages("foo", 0).unwrap();
+        let data_wrapper_three = database.get_messages(&topic("foo"), 0).unwrap();
         assert_eq!(data_wrapper_three.len(), 2);
         assert_eq!(message_one, data_wrapper_three[0]);
         assert_eq!(message_two, data_wrapper_three[1]);
 
-        let data_wrapper_four = database.get_messages("", 0).unwrap();
+        let data_wrapper_four = database.get_messages(&topic(""), 0).unwrap();
         assert_eq!(data_wrapper_four.len(), 2);
         assert_eq!(message_one, data_wrapper_four[0]);
         assert_eq!(message_two, data_wrapper_four[1]);
@@ -211,4 +579,263 @@ mod tests {
         drop(database);
         DB::destroy(&Options::default(), TEST_NAME).unwrap();
     }
+
+    #[test]
+    fn messages_page() {
+        const TEST_NAME: &str = "./tests/messages_page";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let messages: Vec<_> = (0..4)
+            .map(|i| AuthWrapper {
+                payload_digest: vec![i; 32],
+                ..Default::default()
+            })
+            .collect();
+        for (timestamp, message) in [1, 2, 2, 3].iter().zip(&messages) {
+            database
+                .put_message(*timestamp, &topic("foo"), message)
+                .unwrap();
+        }
+
+        // Pages end before the next timestamp
+        let (page, next_since) = database.get_messages_page(&topic(""), 0, 1).unwrap();
+        assert_eq!(page, messages[..1]);
+        assert_eq!(next_since, Some(2));
+
+        // Messages sharing a timestamp are kept together
+        let (page, next_since) = database.get_messages_page(&topic(""), 2, 1).unwrap();
+        assert_eq!(page, messages[1..3]);
+        assert_eq!(next_since, Some(3));
+
+        let (page, next_since) = database.get_messages_page(&topic(""), 3, 1).unwrap();
+        assert_eq!(page, messages[3..]);
+        assert_eq!(next_since, None);
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn messages_by_author() {
+        const TEST_NAME: &str = "./tests/messages_by_author";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let message_one = AuthWrapper {
+            public_key: vec![2; 33],
+            payload_digest: vec![0; 32],
+            ..Default::default()
+        };
+        let message_two = AuthWrapper {
+            public_key: vec![3; 33],
+            payload_digest: vec![1; 32],
+            ..Default::default()
+        };
+        let message_three = AuthWrapper {
+            public_key: vec![2; 33],
+            payload_digest: vec![2; 32],
+            ..Default::default()
+        };
+
+        // Put to database
+        database
+            .put_message(1, &topic("foo"), &message_one)
+            .unwrap();
+        database
+            .put_message(2, &topic("bar"), &message_two)
+            .unwrap();
+        database
+            .put_message(3, &topic("baz"), &message_three)
+            .unwrap();
+
+        // Get from database
+        let pubkey_hash = hash160(&[2; 33]);
+        let messages = database
+            .get_messages_by_author(&pubkey_hash, 0, i64::MAX)
+            .unwrap();
+        assert_eq!(messages, vec![message_one, message_three.clone()]);
+
+        let messages = database
+            .get_messages_by_author(&pubkey_hash, 2, i64::MAX)
+            .unwrap();
+        assert_eq!(messages, vec![message_three]);
+
+        let messages = database
+            .get_messages_by_author(&hash160(&[4; 33]), 0, i64::MAX)
+            .unwrap();
+        assert!(messages.is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn replies() {
+        const TEST_NAME: &str = "./tests/replies";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let parent = AuthWrapper {
+            payload_digest: vec![0; 32],
+            ..Default::default()
+        };
+        let reply_one = AuthWrapper {
+            payload_digest: vec![1; 32],
+            ..Default::default()
+        };
+        let reply_two = AuthWrapper {
+            payload_digest: vec![2; 32],
+            ..Default::default()
+        };
+
+        // Put to database
+        database.put_message(1, &topic("foo"), &parent).unwrap();
+        for (timestamp, reply) in [(2, &reply_one), (3, &reply_two)].iter() {
+            database
+                .put_message(*timestamp, &topic("foo"), reply)
+                .unwrap();
+            database
+                .put_reply(*timestamp, &parent.payload_digest, &reply.payload_digest)
+                .unwrap();
+        }
+
+        // Get from database
+        let replies = database
+            .get_replies(&parent.payload_digest, 0, i64::MAX)
+            .unwrap();
+        assert_eq!(replies, vec![reply_one.clone(), reply_two]);
+
+        let replies = database.get_replies(&parent.payload_digest, 0, 2).unwrap();
+        assert_eq!(replies, vec![reply_one]);
+
+        let replies = database.get_replies(&[2; 32], 0, i64::MAX).unwrap();
+        assert!(replies.is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn burn_outpoints() {
+        const TEST_NAME: &str = "./tests/burn_outpoints";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let outpoint_a = [vec![0; 32], 0u32.to_be_bytes().to_vec()].concat();
+        let outpoint_b = [vec![0; 32], 1u32.to_be_bytes().to_vec()].concat();
+        assert_eq!(database.get_burn_outpoint(&outpoint_a).unwrap(), None);
+
+        // Put to database
+        let payload_digest = vec![1; 32];
+        database
+            .put_burn_outpoints(vec![&outpoint_a[..], &outpoint_b[..]], &payload_digest)
+            .unwrap();
+
+        // Get from database
+        assert_eq!(
+            database.get_burn_outpoint(&outpoint_a).unwrap(),
+            Some(payload_digest.clone())
+        );
+        assert_eq!(
+            database.get_burn_outpoint(&outpoint_b).unwrap(),
+            Some(payload_digest)
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn hidden_messages() {
+        const TEST_NAME: &str = "./tests/hidden_messages";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let message_one = AuthWrapper {
+            payload_digest: vec![0; 32],
+            ..Default::default()
+        };
+        let message_two = AuthWrapper {
+            payload_digest: vec![1; 32],
+            ..Default::default()
+        };
+        database
+            .put_message(1, &topic("foo"), &message_one)
+            .unwrap();
+        database
+            .put_message(2, &topic("foo"), &message_two)
+            .unwrap();
+
+        // Hidden messages are skipped
+        database.put_hidden_message(&[0; 32]).unwrap();
+        assert_eq!(
+            database.get_messages(&topic("foo"), 0).unwrap(),
+            vec![message_two.clone()]
+        );
+
+        // Unhidden messages reappear
+        assert!(database.remove_hidden_message(&[0; 32]).unwrap());
+        assert!(!database.remove_hidden_message(&[0; 32]).unwrap());
+        assert_eq!(
+            database.get_messages(&topic("foo"), 0).unwrap(),
+            vec![message_one, message_two]
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn reports() {
+        const TEST_NAME: &str = "./tests/reports";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let reporter = hash160(&[2; 33]).to_vec();
+        for (id, received_time) in [(0, 100), (1, 200), (2, 300)].iter() {
+            let entry = ReportEntry {
+                id: vec![*id; 32],
+                reporter: reporter.clone(),
+                received_time: *received_time,
+                ..Default::default()
+            };
+            database.put_report(&entry).unwrap();
+        }
+
+        // Reports are counted within the window
+        assert_eq!(database.count_reports_since(&reporter, 0).unwrap(), 3);
+        assert_eq!(database.count_reports_since(&reporter, 200).unwrap(), 2);
+        assert_eq!(database.count_reports_since(&reporter, 0).unwrap(), 2);
+        assert_eq!(
+            database.count_reports_since(&hash160(&[3; 33]), 0).unwrap(),
+            0
+        );
+
+        // Reports are listed oldest first
+        let reports = database.get_reports().unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].received_time, 100);
+
+        // Remove report
+        assert!(database.remove_report(&[1; 32]).unwrap());
+        assert!(!database.remove_report(&[1; 32]).unwrap());
+        assert!(database.get_report(&[1; 32]).unwrap().is_none());
+        assert_eq!(database.get_reports().unwrap().len(), 2);
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
 }
@@ -4,10 +4,11 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use bytes::Bytes;
 use cashweb::{
     auth_wrapper::{AuthWrapper, AuthWrapperSet, BurnOutputs},
     bitcoin::{
-        transaction::{self, Transaction},
+        transaction::{self, output::Output, Transaction},
         Decodable,
     },
     bitcoin_client::{BitcoinClient, NodeError},
@@ -17,15 +18,21 @@ use thiserror::Error;
 use warp::{http::Response, reject::Reject, Rejection, Reply};
 
 use crate::{
-    crypto::sha256,
-    models::broadcast::BroadcastMessage,
-    pubsub::{PubSubDatabase, PubSubDatabaseError},
+    crypto::{hash160, sha256},
+    models::broadcast::{BroadcastMessage, Burn, MessageBurns},
+    net::ToResponse,
+    pubsub::{
+        BurnPolicy, MessageBus, PubSubDatabase, PubSubDatabaseError, Topic, TopicError,
+        TopicFilter, TopicModeration,
+    },
 };
 
 #[derive(Debug, Error)]
 pub enum MessagesRpcRejection {
     #[error("protobuf decode error: {0}")]
     ProtoBufDecodeError(#[from] prost::DecodeError),
+    #[error("unable to decode message: {0}")]
+    InvalidAuthWrapper(prost::DecodeError),
     #[error("DB Error error: {0}")]
     BitcoinRPCError(#[from] NodeError),
     #[error("DB Error error: {0}")]
@@ -35,15 +42,51 @@ pub enum MessagesRpcRejection {
     #[error("burn transaction commitment incorrect")]
     InvalidOutputCommitment,
     #[error("unable to decode a burn transaction")]
-    TransactionInvalidError(#[from] transaction::DecodeError),
+    InvalidTransaction(#[from] transaction::DecodeError),
+    #[error("burn output index {0} out of bounds for transaction with {1} outputs")]
+    IndexOutOfBounds(u32, usize),
     #[error("invalid transaction output amount")]
     TransactionOutputInvalid,
-    #[error("invalid topic format")]
-    InvalidTopicFormat,
+    #[error("invalid topic: {0}")]
+    InvalidTopic(#[from] TopicError),
+    #[error("message topic is missing")]
+    MissingTopic,
+    #[error("topic is banned")]
+    BannedTopic,
+    #[error("insufficient burn amount: {0} < {1}")]
+    InsufficientBurn(i64, i64),
+    #[error("burn output already counted towards another message")]
+    DuplicateBurnOutpoint,
+    #[error("too many burn transactions: {0}")]
+    TooManyTransactions(usize),
+    #[error("parent digest must be 32 bytes, found {0}")]
+    InvalidParentDigest(usize),
+    #[error("sender is blocked")]
+    BlockedSender,
 }
 
 impl Reject for MessagesRpcRejection {}
 
+impl ToResponse for MessagesRpcRejection {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::DatabaseError(PubSubDatabaseError::MissingValue(_)) => 404,
+            Self::DatabaseError(_) => 500,
+            Self::BitcoinRPCError(err) => match err {
+                NodeError::NotFound(_) => 404,
+                NodeError::WalletNotLoaded(_) => 503,
+                NodeError::MissingInputs(_)
+                | NodeError::TxRejectedByPolicy(_)
+                | NodeError::Rpc(_) => 400,
+                _ => 500,
+            },
+            Self::BannedTopic | Self::BlockedSender => 403,
+            Self::InsufficientBurn(..) => 402,
+            _ => 400,
+        }
+    }
+}
+
 static POND_PREFIX: [u8; 4] = [80, 79, 78, 68];
 
 pub async fn get_messages(
@@ -52,9 +95,15 @@ pub async fn get_messages(
     from: i64,
     to: i64,
 ) -> Result<impl Reply, Rejection> {
-    let messages = db
-        .get_messages_to(&topic, from, to)
+    let filter = TopicFilter::parse(&topic).map_err(MessagesRpcRejection::InvalidTopic)?;
+    let mut messages = db
+        .get_messages_to(filter.topic(), from, to)
         .map_err(MessagesRpcRejection::DatabaseError)?;
+    // The topic index includes the messages under the root of the subtree
+    if filter.is_wildcard() {
+        messages
+            .retain(|message| message_topic(message).map_or(true, |topic| filter.matches(&topic)));
+    }
     let message_page = AuthWrapperSet { items: messages };
     // Serialze message which is stored in database
     let mut raw_message_page = Vec::with_capacity(message_page.encoded_len());
@@ -63,10 +112,53 @@ pub async fn get_messages(
     Ok(Response::builder().body(raw_message_page).unwrap())
 }
 
+pub async fn get_messages_by_author(
+    db: PubSubDatabase,
+    pubkey_hash: Vec<u8>,
+    from: i64,
+    to: i64,
+) -> Result<impl Reply, Rejection> {
+    let messages = db
+        .get_messages_by_author(&pubkey_hash, from, to)
+        .map_err(MessagesRpcRejection::DatabaseError)?;
+    let message_page = AuthWrapperSet { items: messages };
+    let mut raw_message_page = Vec::with_capacity(message_page.encoded_len());
+    message_page.encode(&mut raw_message_page).unwrap();
+
+    Ok(Response::builder().body(raw_message_page).unwrap())
+}
+
+pub async fn get_replies(
+    db: PubSubDatabase,
+    parent_digest: Vec<u8>,
+    from: i64,
+    to: i64,
+) -> Result<impl Reply, Rejection> {
+    let messages = db
+        .get_replies(&parent_digest, from, to)
+        .map_err(MessagesRpcRejection::DatabaseError)?;
+    let message_page = AuthWrapperSet { items: messages };
+    let mut raw_message_page = Vec::with_capacity(message_page.encoded_len());
+    message_page.encode(&mut raw_message_page).unwrap();
+
+    Ok(Response::builder().body(raw_message_page).unwrap())
+}
+
 pub async fn get_message(
     db: PubSubDatabase,
     payload_digest: Vec<u8>,
 ) -> Result<impl Reply, Rejection> {
+    let hidden = db
+        .is_hidden(&payload_digest)
+        .map_err(MessagesRpcRejection::DatabaseError)?;
+    if hidden {
+        return Err(
+            MessagesRpcRejection::DatabaseError(PubSubDatabaseError::MissingValue(hex::encode(
+                &payload_digest,
+            )))
+            .into(),
+        );
+    }
     let message = db
         .get_message(&payload_digest)
         .map_err(MessagesRpcRejection::DatabaseError)?;
@@ -76,6 +168,37 @@ pub async fn get_message(
     Ok(Response::builder().body(raw_message).unwrap())
 }
 
+pub async fn get_burns(
+    db: PubSubDatabase,
+    payload_digest: Vec<u8>,
+) -> Result<impl Reply, Rejection> {
+    let burns = match db
+        .get_burns(&payload_digest)
+        .map_err(MessagesRpcRejection::DatabaseError)?
+    {
+        Some(some) => some,
+        None => {
+            // Messages stored before burns were recorded separately
+            let message = db
+                .get_message(&payload_digest)
+                .map_err(MessagesRpcRejection::DatabaseError)?;
+            let burns = message
+                .transactions
+                .iter()
+                .map(|transaction| {
+                    burn_amount(transaction)
+                        .map(|(_, amount)| BurnOutputsWithAmounts(transaction.clone(), amount))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            tally_burns(&payload_digest, &burns)
+        }
+    };
+    let mut raw_burns = Vec::with_capacity(burns.encoded_len());
+    burns.encode(&mut raw_burns).unwrap();
+
+    Ok(Response::builder().body(raw_burns).unwrap())
+}
+
 const COMMITMENT_LENGTH: usize = 1 /* OP_RETURN */
     + 1 /* PUSH4 */
     + 4 /* PREFIX */
@@ -85,14 +208,112 @@ const COMMITMENT_LENGTH: usize = 1 /* OP_RETURN */
 
 struct BurnOutputsWithAmounts(BurnOutputs, i64);
 
+/// Get the burn output of a transaction.
+fn burn_output(tx: &Transaction, idx: u32) -> Result<&Output, MessagesRpcRejection> {
+    tx.outputs
+        .get(idx as usize)
+        .ok_or_else(|| MessagesRpcRejection::IndexOutOfBounds(idx, tx.outputs.len()))
+}
+
+/// Get the outpoint and signed value of a previously validated burn output.
+fn burn_amount(transaction: &BurnOutputs) -> Result<(Vec<u8>, i64), MessagesRpcRejection> {
+    let tx = Transaction::decode(&mut transaction.tx.as_slice())
+        .map_err(MessagesRpcRejection::InvalidTransaction)?;
+    let idx = transaction.index;
+    let output = burn_output(&tx, idx)?;
+    let raw_script = output.script.as_bytes();
+    let upvote = raw_script[6] == 81;
+    let value: i64 = output
+        .value
+        .try_into()
+        .map_err(|_| MessagesRpcRejection::TransactionOutputInvalid)?;
+
+    let txid = tx.transaction_id();
+    let tx_map_key = [txid.as_ref(), idx.to_be_bytes().as_ref()].concat();
+    Ok((tx_map_key, if upvote { value } else { -value }))
+}
+
+/// Tally the burns counted towards a message.
+fn tally_burns<'a>(
+    payload_digest: &[u8],
+    burns: impl IntoIterator<Item = &'a BurnOutputsWithAmounts>,
+) -> MessageBurns {
+    let mut tally = MessageBurns {
+        payload_digest: payload_digest.to_vec(),
+        ..Default::default()
+    };
+    for BurnOutputsWithAmounts(burn_output, amount) in burns {
+        if *amount >= 0 {
+            tally.upvotes += amount;
+        } else {
+            tally.downvotes -= amount;
+        }
+        tally.burns.push(Burn {
+            tx: burn_output.tx.clone(),
+            index: burn_output.index,
+            amount: *amount,
+        });
+    }
+    tally.burn_amount = tally.upvotes - tally.downvotes;
+    tally
+}
+
+/// Get the normalized topic of a stored `AuthWrapper`.
+fn message_topic(message: &AuthWrapper) -> Option<Topic> {
+    BroadcastMessage::decode(message.payload.as_slice())
+        .ok()
+        .and_then(|payload| Topic::parse(&payload.topic).ok())
+}
+
+/// Push a newly accepted `AuthWrapper` to the websocket subscribers.
+fn publish_message(msg_bus: &MessageBus, topic: &Topic, message: &AuthWrapper) {
+    // An error only indicates that there are currently no subscribers
+    let _ = msg_bus.send((topic.clone(), message.clone()));
+}
+
 pub async fn put_message(
     db: PubSubDatabase,
     client: impl BitcoinClient,
-    mut message: AuthWrapper,
+    msg_bus: MessageBus,
+    moderation: TopicModeration,
+    policy: BurnPolicy,
+    message: AuthWrapper,
 ) -> Result<impl Reply, Rejection> {
+    accept_message(&db, Some(&client), &msg_bus, &moderation, &policy, message).await?;
+    Ok(Response::builder().status(200).body(b"".as_ref()).unwrap())
+}
+
+/// Handles message PUT requests, decoding the body as an `AuthWrapper`.
+pub async fn put_raw_message(
+    db: PubSubDatabase,
+    client: impl BitcoinClient,
+    msg_bus: MessageBus,
+    moderation: TopicModeration,
+    policy: BurnPolicy,
+    body: Bytes,
+) -> Result<impl Reply, Rejection> {
+    let message = AuthWrapper::decode(body).map_err(MessagesRpcRejection::InvalidAuthWrapper)?;
+    put_message(db, client, msg_bus, moderation, policy, message).await
+}
+
+/// Validate an `AuthWrapper`, storing it and notifying subscribers.
+///
+/// If a [`BitcoinClient`] is given the burn transactions are broadcast before storing,
+/// otherwise they are only verified locally.
+pub async fn accept_message<C: BitcoinClient>(
+    db: &PubSubDatabase,
+    client: Option<&C>,
+    msg_bus: &MessageBus,
+    moderation: &TopicModeration,
+    policy: &BurnPolicy,
+    mut message: AuthWrapper,
+) -> Result<(), MessagesRpcRejection> {
     if message.transactions.is_empty() {
-        return Err(warp::reject::custom(
-            MessagesRpcRejection::InvalidOutputFormat,
+        return Err(MessagesRpcRejection::InvalidOutputFormat);
+    }
+    if !policy.allows_transactions(message.transactions.len()) {
+        return Err(MessagesRpcRejection::TooManyTransactions(
+            message.transactions.len(),
         ));
     }
     if message.payload_digest.is_empty() {
@@ -105,29 +326,31 @@ pub async fn put_message(
 
     // In the case where the payload must be specified, we want to validate a
     // few items. In the case where this is simply a vote, ignore the checks.
+    let topic = Topic::parse(&payload.topic).map_err(MessagesRpcRejection::InvalidTopic)?;
     if message.payload.encoded_len() > 0 {
-        let topic = &payload.topic;
-        let valid_topic = topic
-            .chars()
-            .all(|c| c.is_lowercase() || c.is_numeric() || c == '.' || c == '-');
-        if !valid_topic {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidTopicFormat,
-            ));
+        if topic.is_root() {
+            return Err(MessagesRpcRejection::MissingTopic);
+        }
+
+        let banned = moderation
+            .is_banned(db, topic.as_str())
+            .map_err(MessagesRpcRejection::DatabaseError)?;
+        if banned {
+            return Err(MessagesRpcRejection::BannedTopic);
         }
 
-        let split_topic = topic.split('.').collect::<Vec<&str>>();
-        if split_topic.len() > 10 {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidTopicFormat,
-            ));
+        if !message.public_key.is_empty() {
+            let blocked = db
+                .is_blocked(&hash160(&message.public_key))
+                .map_err(MessagesRpcRejection::DatabaseError)?;
+            if blocked {
+                return Err(MessagesRpcRejection::BlockedSender);
+            }
         }
 
-        let invalid_segments = split_topic.iter().any(|segment| segment.is_empty());
-        if invalid_segments {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidTopicFormat,
-            ));
+        let parent_len = payload.parent_digest.len();
+        if parent_len != 0 && parent_len != 32 {
+            return Err(MessagesRpcRejection::InvalidParentDigest(parent_len));
         }
     }
 
@@ -137,18 +360,14 @@ pub async fn put_message(
     for transaction in &message.transactions {
         let idx = transaction.index;
         let tx = Transaction::decode(&mut transaction.tx.as_slice())
-            .expect("Failed to decode a transaction");
-        let output = &tx.outputs[idx as usize];
+            .map_err(MessagesRpcRejection::InvalidTransaction)?;
+        let output = burn_output(&tx, idx)?;
         if !output.script.is_op_return() {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidOutputFormat,
-            ));
+            return Err(MessagesRpcRejection::InvalidOutputFormat);
         }
         let raw_script = output.script.as_bytes();
         if raw_script.len() != COMMITMENT_LENGTH {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidOutputFormat,
-            ));
+            return Err(MessagesRpcRejection::InvalidOutputFormat);
         }
 
         // Lord have mercy on your soul
@@ -157,16 +376,12 @@ pub async fn put_message(
             || !(raw_script[6] == 81 || raw_script[6] == 0)
             || raw_script[7] != 32
         {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidOutputFormat,
-            ));
+            return Err(MessagesRpcRejection::InvalidOutputFormat);
         }
         let upvote = raw_script[6] == 81;
         let commitment = &raw_script[8..COMMITMENT_LENGTH];
         if &message.payload_digest[..] != commitment {
-            return Err(warp::reject::custom(
-                MessagesRpcRejection::InvalidOutputCommitment,
-            ));
+            return Err(MessagesRpcRejection::InvalidOutputCommitment);
         }
         let value: i64 = output
             .value
@@ -181,36 +396,81 @@ pub async fn put_message(
         );
     }
 
-    // Attempt to broadcast the transactions
-    for burn in &message.transactions {
-        client
-            .send_tx(burn.tx.as_ref())
-            .await
-            .map_err(|err| warp::reject::custom(MessagesRpcRejection::BitcoinRPCError(err)))?;
+    // Ensure burn outputs have not been counted towards another message
+    for tx_map_key in transactions.keys() {
+        let counted_digest = db
+            .get_burn_outpoint(tx_map_key)
+            .map_err(MessagesRpcRejection::DatabaseError)?;
+        if let Some(counted_digest) = counted_digest {
+            if counted_digest != message.payload_digest {
+                return Err(MessagesRpcRejection::DuplicateBurnOutpoint);
+            }
+        }
+    }
+
+    // Votes without a payload are merged into an existing message
+    let existing_value = if message.payload.is_empty() {
+        db.get_message(&message.payload_digest).ok()
+    } else {
+        None
+    };
+
+    // Ensure the burns meet the policy before they are broadcast
+    match &existing_value {
+        Some(wrapper) => {
+            // Each vote must burn at least the minimum for the message's topic
+            let topic_min_burn =
+                message_topic(wrapper).map_or(0, |topic| moderation.min_burn(topic.as_str()));
+            let required_burn = policy.required_burn(0, topic_min_burn);
+            let vote_amount = transactions
+                .values()
+                .map(|burn_output| burn_output.1.abs())
+                .sum::<i64>();
+            if vote_amount < required_burn {
+                return Err(MessagesRpcRejection::InsufficientBurn(
+                    vote_amount,
+                    required_burn,
+                ));
+            }
+        }
+        None => {
+            // Ensure the burn_amount is correct.
+            message.burn_amount = transactions
+                .values()
+                .map(|burn_output| burn_output.1)
+                .sum::<i64>();
+
+            // Ensure the burn_amount meets the policy
+            let required_burn =
+                policy.required_burn(message.payload.len(), moderation.min_burn(topic.as_str()));
+            if message.burn_amount < required_burn {
+                return Err(MessagesRpcRejection::InsufficientBurn(
+                    message.burn_amount,
+                    required_burn,
+                ));
+            }
+        }
     }
 
-    // Check to see if this thing already exists, if so just bump the number of burn transactions.
-    let existing_value = db.get_message(&message.payload_digest);
-    if existing_value.is_ok() && message.payload.is_empty() {
-        let mut wrapper = existing_value.unwrap();
+    // Attempt to broadcast the transactions, those already known having been broadcast by a peer
+    // or the sender
+    if let Some(client) = client {
+        for burn in &message.transactions {
+            match client.send_tx(burn.tx.as_ref()).await {
+                Ok(_) | Err(NodeError::TxAlreadyKnown(_)) => (),
+                Err(err) => return Err(MessagesRpcRejection::BitcoinRPCError(err)),
+            }
+        }
+    }
+
+    // If this thing already exists, just bump the number of burn transactions.
+    if let Some(mut wrapper) = existing_value {
         // Dedupe transactions
         for transaction in &wrapper.transactions {
-            let tx = Transaction::decode(&mut transaction.tx.as_slice())
-                .map_err(MessagesRpcRejection::TransactionInvalidError)?;
-            let idx = transaction.index;
-            let output = &tx.outputs[idx as usize];
-            let raw_script = output.script.as_bytes();
-            let upvote = raw_script[6] == 81;
-            let value: i64 = output
-                .value
-                .try_into()
-                .map_err(|_| MessagesRpcRejection::TransactionOutputInvalid)?;
-
-            let txid = tx.transaction_id();
-            let tx_map_key = [txid.as_ref(), idx.to_be_bytes().as_ref()].concat();
+            let (tx_map_key, amount) = burn_amount(transaction)?;
             transactions.insert(
                 tx_map_key,
-                BurnOutputsWithAmounts(transaction.clone(), if upvote { value } else { -value }),
+                BurnOutputsWithAmounts(transaction.clone(), amount),
             );
         }
         // Update the transactions in the database
@@ -224,8 +484,20 @@ pub async fn put_message(
             .sum::<i64>();
         db.update_message(&wrapper)
             .map_err(MessagesRpcRejection::DatabaseError)?;
+        db.put_burns(&tally_burns(&wrapper.payload_digest, transactions.values()))
+            .map_err(MessagesRpcRejection::DatabaseError)?;
+        db.put_burn_outpoints(
+            transactions.keys().map(Vec::as_slice),
+            &wrapper.payload_digest,
+        )
+        .map_err(MessagesRpcRejection::DatabaseError)?;
 
-        return Ok(Response::builder().status(200).body(b"".as_ref()).unwrap());
+        // Notify subscribers of the new burn amount
+        if let Some(existing_topic) = message_topic(&wrapper) {
+            publish_message(msg_bus, &existing_topic, &wrapper);
+        }
+
+        return Ok(());
     }
 
     // Time now
@@ -234,47 +506,42 @@ pub async fn put_message(
         .unwrap()
         .as_millis() as u64;
 
-    // Ensure the burn_amount is correct.
-    message.burn_amount = transactions
-        .values()
-        .map(|burn_output| burn_output.1)
-        .sum::<i64>();
-
-    db.put_message(timestamp, &payload.topic, &message)
+    db.put_message(timestamp, &topic, &message)
         .map_err(MessagesRpcRejection::DatabaseError)?;
-    Ok(Response::builder().status(200).body(b"".as_ref()).unwrap())
+    db.put_burns(&tally_burns(&message.payload_digest, transactions.values()))
+        .map_err(MessagesRpcRejection::DatabaseError)?;
+    if !payload.parent_digest.is_empty() {
+        db.put_reply(timestamp, &payload.parent_digest, &message.payload_digest)
+            .map_err(MessagesRpcRejection::DatabaseError)?;
+    }
+    db.put_burn_outpoints(
+        transactions.keys().map(Vec::as_slice),
+        &message.payload_digest,
+    )
+    .map_err(MessagesRpcRejection::DatabaseError)?;
+    publish_message(msg_bus, &topic, &message);
+
+    Ok(())
 }
 
 #[cfg(test)]
 pub mod tests {
-    use async_trait::async_trait;
     use cashweb::{
         auth_wrapper::BurnOutputs,
         bitcoin::{
             transaction::{output::Output, script::Script},
             Encodable,
         },
-        bitcoin_client::NodeError,
+        bitcoin_client::mock::{MockBitcoinClient, MockCall},
     };
     use rocksdb::{Options, DB};
+    use tokio::sync::broadcast;
 
     use super::*;
+    use crate::pubsub::BROADCAST_CHANNEL_CAPACITY;
 
-    struct MockTransactionSender {}
-
-    #[async_trait]
-    impl BitcoinClient for MockTransactionSender {
-        async fn send_tx(&self, _raw_tx: &[u8]) -> Result<String, NodeError> {
-            return Ok("".to_string());
-        }
-        /// Get a new receiving address from the bitcoin daemon
-        async fn get_new_addr(&self) -> Result<String, NodeError> {
-            Ok("".to_string())
-        }
-        /// Get a raw bitcoin transaction by txid
-        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
-            Ok(vec![])
-        }
+    fn msg_bus() -> MessageBus {
+        broadcast::channel(BROADCAST_CHANNEL_CAPACITY).0
     }
 
     #[tokio::test]
@@ -303,7 +570,15 @@ pub mod tests {
             ..Default::default()
         };
 
-        let result = put_message(database.clone(), MockTransactionSender {}, wrapper_in).await;
+        let result = put_message(
+            database.clone(),
+            MockBitcoinClient::new(),
+            msg_bus(),
+            TopicModeration::default(),
+            BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await;
 
         assert!(result.is_err(), "Result is error");
 
@@ -312,6 +587,49 @@ pub mod tests {
         DB::destroy(&Options::default(), TEST_NAME).unwrap();
     }
 
+    #[tokio::test]
+    async fn test_put_truncated_message() {
+        const TEST_NAME: &str = "./tests/test_put_truncated_message";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, tx_buf) = payload_and_burn_tx();
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: tx_buf,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        let mut raw_wrapper = Vec::with_capacity(wrapper_in.encoded_len());
+        wrapper_in.encode(&mut raw_wrapper).unwrap();
+        raw_wrapper.truncate(raw_wrapper.len() - 1);
+
+        let client = MockBitcoinClient::new();
+        let rejection = put_raw_message(
+            database.clone(),
+            client.clone(),
+            msg_bus(),
+            TopicModeration::default(),
+            BurnPolicy::default(),
+            Bytes::from(raw_wrapper),
+        )
+        .await
+        .err()
+        .unwrap();
+        let err = rejection.find::<MessagesRpcRejection>().unwrap();
+        assert!(matches!(err, MessagesRpcRejection::InvalidAuthWrapper(_)));
+        assert_eq!(err.to_status(), 400);
+        assert!(client.calls().is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
     #[tokio::test]
     async fn test_put_valid_message() {
         const TEST_NAME: &str = "./tests/test_put_valid_message";
@@ -363,7 +681,15 @@ pub mod tests {
             ..Default::default()
         };
 
-        let result = put_message(database.clone(), MockTransactionSender {}, wrapper_in).await;
+        let result = put_message(
+            database.clone(),
+            MockBitcoinClient::new(),
+            msg_bus(),
+            TopicModeration::default(),
+            BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await;
         if let Err(err) = result.as_ref() {
             println!("{:?}", err);
         }
@@ -377,6 +703,80 @@ pub mod tests {
         DB::destroy(&Options::default(), TEST_NAME).unwrap();
     }
 
+    #[tokio::test]
+    async fn test_put_insufficient_burn() {
+        const TEST_NAME: &str = "./tests/test_put_insufficient_burn";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let message = BroadcastMessage {
+            topic: "cashweb.is.amazing".to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            ..Default::default()
+        };
+
+        let mut message_buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut message_buf).unwrap();
+
+        // Create the burn transaction
+        let mut tx = Transaction::default();
+        let mut output = Vec::<u8>::with_capacity(COMMITMENT_LENGTH);
+        output.push(106);
+        output.push(4);
+        output.extend_from_slice(&POND_PREFIX);
+        output.push(81);
+        output.push(32);
+
+        let payload_hash = sha256(&message_buf);
+        output.extend(payload_hash);
+
+        tx.outputs.push(Output {
+            script: Script::from(output),
+            value: 0,
+        });
+
+        // Buffer with enough space to encode txn.
+        let mut tx_buf = Vec::with_capacity(50);
+        tx.encode(&mut tx_buf).unwrap();
+
+        // Create database wrapper
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: tx_buf,
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        let client = MockBitcoinClient::new();
+        let result = put_message(
+            database.clone(),
+            client.clone(),
+            msg_bus(),
+            TopicModeration::default(),
+            BurnPolicy {
+                min_burn: 1,
+                ..Default::default()
+            },
+            wrapper_in,
+        )
+        .await;
+        assert!(result.is_err(), "Result is error");
+
+        // The burn is not broadcast
+        assert!(client.calls().is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
     #[tokio::test]
     async fn test_put_invalid_topic() {
         const TEST_NAME: &str = "./tests/test_put_invalid_topic";
@@ -428,9 +828,480 @@ pub mod tests {
             ..Default::default()
         };
 
-        let result = put_message(database.clone(), MockTransactionSender {}, wrapper_in).await;
+        let result = put_message(
+            database.clone(),
+            MockBitcoinClient::new(),
+            msg_bus(),
+            TopicModeration::default(),
+            BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await;
         assert!(result.is_err(), "Result is error");
-        // TODO: Test specific error somehow
+        assert_eq!(
+            result
+                .err()
+                .unwrap()
+                .find::<MessagesRpcRejection>()
+                .unwrap()
+                .to_string(),
+            MessagesRpcRejection::InvalidTopic(TopicError::InvalidCharacter(' ')).to_string()
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    /// Construct a payload and a serialized transaction burning to it.
+    fn payload_and_burn_tx() -> (Vec<u8>, Vec<u8>) {
+        let message = BroadcastMessage {
+            topic: "cashweb.is.amazing".to_string(),
+            ..Default::default()
+        };
+        let mut message_buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut message_buf).unwrap();
+
+        let mut output = Vec::<u8>::with_capacity(COMMITMENT_LENGTH);
+        output.push(106);
+        output.push(4);
+        output.extend_from_slice(&POND_PREFIX);
+        output.push(81);
+        output.push(32);
+        output.extend(sha256(&message_buf));
+
+        let mut tx = Transaction::default();
+        tx.outputs.push(Output {
+            script: Script::from(output),
+            value: 0,
+        });
+        let mut tx_buf = Vec::with_capacity(tx.encoded_len());
+        tx.encode(&mut tx_buf).unwrap();
+
+        (message_buf, tx_buf)
+    }
+
+    /// Construct a serialized transaction burning `value` to a payload digest.
+    fn vote_tx(payload_digest: &[u8], upvote: bool, value: u64) -> Vec<u8> {
+        let mut output = Vec::<u8>::with_capacity(COMMITMENT_LENGTH);
+        output.push(106);
+        output.push(4);
+        output.extend_from_slice(&POND_PREFIX);
+        output.push(if upvote { 81 } else { 0 });
+        output.push(32);
+        output.extend_from_slice(payload_digest);
+
+        let mut tx = Transaction::default();
+        tx.outputs.push(Output {
+            script: Script::from(output),
+            value,
+        });
+        let mut tx_buf = Vec::with_capacity(tx.encoded_len());
+        tx.encode(&mut tx_buf).unwrap();
+        tx_buf
+    }
+
+    #[tokio::test]
+    async fn test_burns_tally() {
+        const TEST_NAME: &str = "./tests/test_burns_tally";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, _) = payload_and_burn_tx();
+        let payload_digest = sha256(&message_buf).to_vec();
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: vote_tx(&payload_digest, true, 100),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        accept_message(
+            &database,
+            None::<&MockBitcoinClient>,
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await
+        .unwrap();
+
+        // Downvote without the payload
+        let vote_in = AuthWrapper {
+            payload_digest: payload_digest.clone(),
+            transactions: vec![BurnOutputs {
+                tx: vote_tx(&payload_digest, false, 30),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        accept_message(
+            &database,
+            None::<&MockBitcoinClient>,
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            vote_in,
+        )
+        .await
+        .unwrap();
+
+        let burns = database.get_burns(&payload_digest).unwrap().unwrap();
+        assert_eq!(burns.payload_digest, payload_digest);
+        assert_eq!(burns.upvotes, 100);
+        assert_eq!(burns.downvotes, 30);
+        assert_eq!(burns.burn_amount, 70);
+        assert_eq!(burns.burns.len(), 2);
+        assert_eq!(
+            database.get_message(&payload_digest).unwrap().burn_amount,
+            70
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_vote_insufficient_burn() {
+        const TEST_NAME: &str = "./tests/test_vote_insufficient_burn";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, _) = payload_and_burn_tx();
+        let payload_digest = sha256(&message_buf).to_vec();
+        let policy = BurnPolicy {
+            min_burn: 50,
+            ..Default::default()
+        };
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: vote_tx(&payload_digest, true, 100),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        accept_message(
+            &database,
+            None::<&MockBitcoinClient>,
+            &msg_bus(),
+            &TopicModeration::default(),
+            &policy,
+            wrapper_in,
+        )
+        .await
+        .unwrap();
+
+        // Votes below the minimum are rejected before broadcast
+        let vote_in = AuthWrapper {
+            payload_digest: payload_digest.clone(),
+            transactions: vec![BurnOutputs {
+                tx: vote_tx(&payload_digest, false, 30),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        let client = MockBitcoinClient::new();
+        let result = accept_message(
+            &database,
+            Some(&client),
+            &msg_bus(),
+            &TopicModeration::default(),
+            &policy,
+            vote_in,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MessagesRpcRejection::InsufficientBurn(30, 50))
+        ));
+        assert!(client.calls().is_empty());
+        assert_eq!(
+            database.get_message(&payload_digest).unwrap().burn_amount,
+            100
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_burns_broadcast() {
+        const TEST_NAME: &str = "./tests/test_burns_broadcast";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, _) = payload_and_burn_tx();
+        let payload_digest = sha256(&message_buf).to_vec();
+        let burn_tx = vote_tx(&payload_digest, true, 100);
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: burn_tx.clone(),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        // Burns the node refuses are not counted
+        let client = MockBitcoinClient::new();
+        client.fail_next(1);
+        let result = accept_message(
+            &database,
+            Some(&client),
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            wrapper_in.clone(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MessagesRpcRejection::BitcoinRPCError(_))
+        ));
+        assert!(database.get_message(&payload_digest).is_err());
+
+        // Burns the node accepts are
+        accept_message(
+            &database,
+            Some(&client),
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            client.calls(),
+            vec![MockCall::SendTx(burn_tx.clone()), MockCall::SendTx(burn_tx)]
+        );
+        assert_eq!(
+            database.get_message(&payload_digest).unwrap().burn_amount,
+            100
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_truncated_transaction() {
+        const TEST_NAME: &str = "./tests/test_put_truncated_transaction";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, tx_buf) = payload_and_burn_tx();
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: tx_buf[..tx_buf.len() - 1].to_vec(),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+
+        let result = accept_message(
+            &database,
+            None::<&MockBitcoinClient>,
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MessagesRpcRejection::InvalidTransaction(_))
+        ));
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_output_index_out_of_bounds() {
+        const TEST_NAME: &str = "./tests/test_put_output_index_out_of_bounds";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, tx_buf) = payload_and_burn_tx();
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: tx_buf,
+                index: 1,
+            }],
+            ..Default::default()
+        };
+
+        let result = accept_message(
+            &database,
+            None::<&MockBitcoinClient>,
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MessagesRpcRejection::IndexOutOfBounds(1, 1))
+        ));
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_too_many_transactions() {
+        const TEST_NAME: &str = "./tests/test_put_too_many_transactions";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let (message_buf, tx_buf) = payload_and_burn_tx();
+        let burn = BurnOutputs {
+            tx: tx_buf,
+            index: 0,
+        };
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![burn.clone(), burn],
+            ..Default::default()
+        };
+
+        let client = MockBitcoinClient::new();
+        let result = accept_message(
+            &database,
+            Some(&client),
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy {
+                max_transactions: 1,
+                ..Default::default()
+            },
+            wrapper_in,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MessagesRpcRejection::TooManyTransactions(2))
+        ));
+        assert!(client.calls().is_empty());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_topic_normalized() {
+        const TEST_NAME: &str = "./tests/test_put_topic_normalized";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let message = BroadcastMessage {
+            topic: "CashWeb.Is.Amazing".to_string(),
+            ..Default::default()
+        };
+        let mut message_buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut message_buf).unwrap();
+        let payload_digest = sha256(&message_buf).to_vec();
+        let wrapper_in = AuthWrapper {
+            scheme: 1,
+            payload: message_buf,
+            transactions: vec![BurnOutputs {
+                tx: vote_tx(&payload_digest, true, 0),
+                index: 0,
+            }],
+            ..Default::default()
+        };
+        accept_message(
+            &database,
+            None::<&MockBitcoinClient>,
+            &msg_bus(),
+            &TopicModeration::default(),
+            &BurnPolicy::default(),
+            wrapper_in,
+        )
+        .await
+        .unwrap();
+
+        // Readable under the case folded topic
+        let topic = Topic::parse("cashweb.is").unwrap();
+        let messages = database.get_messages(&topic, 0).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload_digest, payload_digest);
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_messages_wildcard() {
+        const TEST_NAME: &str = "./tests/test_get_messages_wildcard";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        for (idx, raw_topic) in ["foo", "foo.bar", "foo.bar.bob", "baz"].iter().enumerate() {
+            let payload = BroadcastMessage {
+                topic: raw_topic.to_string(),
+                ..Default::default()
+            };
+            let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+            payload.encode(&mut raw_payload).unwrap();
+            let message = AuthWrapper {
+                payload_digest: sha256(&raw_payload).to_vec(),
+                payload: raw_payload,
+                ..Default::default()
+            };
+            let topic = Topic::parse(raw_topic).unwrap();
+            database.put_message(idx as u64, &topic, &message).unwrap();
+        }
+
+        let n_messages = |raw_topic: &str| {
+            let database = database.clone();
+            let raw_topic = raw_topic.to_string();
+            async move {
+                let response = get_messages(database, raw_topic, 0, i64::MAX)
+                    .await
+                    .unwrap()
+                    .into_response();
+                let raw_page = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                AuthWrapperSet::decode(raw_page).unwrap().items.len()
+            }
+        };
+        assert_eq!(n_messages("foo").await, 3);
+        assert_eq!(n_messages("foo.*").await, 2);
+        assert_eq!(n_messages("foo.bar.*").await, 1);
+        assert_eq!(n_messages("*").await, 4);
+        assert!(
+            get_messages(database.clone(), "foo.*.bar".to_string(), 0, i64::MAX)
+                .await
+                .is_err()
+        );
 
         // Destroy database
         drop(database);
@@ -1,5 +1,17 @@
 mod db;
 mod handlers;
+mod moderation;
+mod policy;
+mod reports;
+mod sync;
+mod topic;
+mod ws;
 
 pub use db::*;
 pub use handlers::*;
+pub use moderation::*;
+pub use policy::*;
+pub use reports::*;
+pub use sync::*;
+pub use topic::*;
+pub use ws::*;
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use http::header::{HeaderMap, AUTHORIZATION};
+use prost::Message as _;
+use ring::constant_time::verify_slices_are_equal;
+use thiserror::Error;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{
+    models::broadcast::BannedTopics,
+    net::ToResponse,
+    pubsub::{topic_matches, PubSubDatabase, PubSubDatabaseError, Topic},
+};
+
+/// Operator policy restricting which topics may be posted to.
+///
+/// Banned topic prefixes are the union of those given in the settings and those
+/// added at runtime via the admin endpoints, the latter being persisted in the
+/// [`PubSubDatabase`].
+#[derive(Clone, Debug, Default)]
+pub struct TopicModeration {
+    banned_topics: Arc<Vec<String>>,
+    min_burns: Arc<Vec<(String, i64)>>,
+}
+
+impl TopicModeration {
+    /// Construct a new [`TopicModeration`] from banned topic prefixes and
+    /// per-topic minimum burn amounts.
+    pub fn new(banned_topics: Vec<String>, min_burns: Vec<(String, i64)>) -> Self {
+        Self {
+            banned_topics: Arc::new(banned_topics),
+            min_burns: Arc::new(min_burns),
+        }
+    }
+
+    /// Check whether a topic falls under a banned topic prefix.
+    pub fn is_banned(&self, db: &PubSubDatabase, topic: &str) -> Result<bool, PubSubDatabaseError> {
+        if self
+            .banned_topics
+            .iter()
+            .any(|prefix| topic_matches(prefix, topic))
+        {
+            return Ok(true);
+        }
+        let banned = db
+            .get_banned_topics()?
+            .iter()
+            .any(|prefix| topic_matches(prefix, topic));
+        Ok(banned)
+    }
+
+    /// Get the per-topic minimum burn amounts.
+    pub fn min_burns(&self) -> &[(String, i64)] {
+        &self.min_burns
+    }
+
+    /// Get the minimum net burn required for a topic.
+    ///
+    /// The most specific matching topic prefix takes precedence.
+    pub fn min_burn(&self, topic: &str) -> i64 {
+        self.min_burns
+            .iter()
+            .filter(|(prefix, _)| topic_matches(prefix, topic))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, amount)| *amount)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ModerationError {
+    #[error("admin endpoints are disabled")]
+    Disabled,
+    #[error("invalid admin token")]
+    Unauthorized,
+    #[error("invalid topic prefix")]
+    InvalidTopic,
+    #[error("not found")]
+    NotFound,
+    #[error("action does not apply to the report")]
+    InvalidAction,
+    #[error("failed to access database: {0}")]
+    Database(#[from] PubSubDatabaseError),
+}
+
+impl Reject for ModerationError {}
+
+impl ToResponse for ModerationError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::Disabled => 501,
+            Self::Unauthorized => 401,
+            Self::InvalidTopic | Self::InvalidAction => 400,
+            Self::NotFound | Self::Database(PubSubDatabaseError::MissingValue(_)) => 404,
+            Self::Database(_) => 500,
+        }
+    }
+}
+
+/// Check the admin bearer token against the configured token.
+pub fn admin_protection(
+    headers: &HeaderMap,
+    admin_token: Option<&str>,
+) -> Result<(), ModerationError> {
+    let admin_token = admin_token.ok_or(ModerationError::Disabled)?;
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ModerationError::Unauthorized)?;
+    verify_slices_are_equal(bearer.as_bytes(), admin_token.as_bytes())
+        .map_err(|_| ModerationError::Unauthorized)
+}
+
+/// Handles banned topic GET requests.
+pub async fn get_banned_topics(db: PubSubDatabase) -> Result<Response<Body>, ModerationError> {
+    let banned_topics = BannedTopics {
+        topics: db.get_banned_topics()?,
+    };
+    let mut raw_banned_topics = Vec::with_capacity(banned_topics.encoded_len());
+    banned_topics.encode(&mut raw_banned_topics).unwrap(); // This is safe
+
+    Ok(Response::builder()
+        .body(Body::from(raw_banned_topics))
+        .unwrap())
+}
+
+/// Handles banned topic PUT requests.
+pub async fn put_banned_topic(
+    db: PubSubDatabase,
+    topic: String,
+) -> Result<Response<Body>, ModerationError> {
+    let topic = Topic::parse(&topic).map_err(|_| ModerationError::InvalidTopic)?;
+    // An empty prefix would ban every topic
+    if topic.is_root() {
+        return Err(ModerationError::InvalidTopic);
+    }
+    db.put_banned_topic(topic.as_str())?;
+
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles banned topic DELETE requests.
+pub async fn delete_banned_topic(
+    db: PubSubDatabase,
+    topic: String,
+) -> Result<Response<Body>, ModerationError> {
+    if !db.remove_banned_topic(&topic)? {
+        return Err(ModerationError::NotFound);
+    }
+
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{Options, DB};
+
+    use super::*;
+
+    #[test]
+    fn banned_topics() {
+        const TEST_NAME: &str = "./tests/banned_topics";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+        let moderation = TopicModeration::new(vec!["spam".to_string()], vec![]);
+
+        assert!(moderation.is_banned(&database, "spam").unwrap());
+        assert!(moderation.is_banned(&database, "spam.eggs").unwrap());
+        assert!(!moderation.is_banned(&database, "spammy").unwrap());
+        assert!(!moderation.is_banned(&database, "foo.bar").unwrap());
+
+        // Ban at runtime
+        database.put_banned_topic("foo").unwrap();
+        assert!(moderation.is_banned(&database, "foo.bar").unwrap());
+
+        // Unban at runtime
+        assert!(database.remove_banned_topic("foo").unwrap());
+        assert!(!database.remove_banned_topic("foo").unwrap());
+        assert!(!moderation.is_banned(&database, "foo.bar").unwrap());
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[test]
+    fn min_burn_most_specific() {
+        let moderation = TopicModeration::new(
+            vec![],
+            vec![("foo".to_string(), 100), ("foo.bar".to_string(), 1_000)],
+        );
+
+        assert_eq!(moderation.min_burn("baz"), 0);
+        assert_eq!(moderation.min_burn("foo"), 100);
+        assert_eq!(moderation.min_burn("foo.baz"), 100);
+        assert_eq!(moderation.min_burn("foo.bar.bob"), 1_000);
+    }
+}
@@ -0,0 +1,106 @@
+use std::convert::Infallible;
+
+use prost::Message as _;
+use warp::{http::Response, hyper::Body};
+
+use crate::{
+    models::broadcast::{MetadataTier, Policy, TopicMinBurn},
+    pubsub::TopicModeration,
+    SETTINGS,
+};
+
+/// Minimum net burn required to post a message.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BurnPolicy {
+    /// Minimum net burn per message.
+    pub min_burn: i64,
+    /// Additional net burn required per byte of payload.
+    pub min_burn_per_byte: i64,
+    /// Maximum number of burn transactions per message, zero disables the limit.
+    pub max_transactions: usize,
+}
+
+impl BurnPolicy {
+    /// Calculate the net burn required for a payload, given the minimum burn for its topic.
+    pub fn required_burn(&self, payload_len: usize, topic_min_burn: i64) -> i64 {
+        let payload_burn = self.min_burn_per_byte.saturating_mul(payload_len as i64);
+        self.min_burn
+            .max(topic_min_burn)
+            .saturating_add(payload_burn)
+    }
+
+    /// Check whether the number of burn transactions in a message is within the limit.
+    pub fn allows_transactions(&self, n_transactions: usize) -> bool {
+        self.max_transactions == 0 || n_transactions <= self.max_transactions
+    }
+}
+
+/// Handles policy GET requests.
+///
+/// Alongside the burn policy, the metadata size limit and commitment tiers are advertised.
+pub async fn get_policy(
+    policy: BurnPolicy,
+    moderation: TopicModeration,
+) -> Result<Response<Body>, Infallible> {
+    let topic_min_burns = moderation
+        .min_burns()
+        .iter()
+        .map(|(topic, amount)| TopicMinBurn {
+            topic: topic.clone(),
+            amount: *amount,
+        })
+        .collect();
+    let metadata_tiers = SETTINGS
+        .limits
+        .metadata_tiers
+        .iter()
+        .map(|tier| MetadataTier {
+            size: tier.size,
+            commitment: tier.commitment,
+        })
+        .collect();
+    let policy = Policy {
+        min_burn: policy.min_burn,
+        min_burn_per_byte: policy.min_burn_per_byte,
+        topic_min_burns,
+        max_transactions: policy.max_transactions as u64,
+        metadata_size: SETTINGS.limits.metadata_size,
+        metadata_tiers,
+    };
+    let mut raw_policy = Vec::with_capacity(policy.encoded_len());
+    policy.encode(&mut raw_policy).unwrap(); // This is safe
+
+    Ok(Response::builder().body(Body::from(raw_policy)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_burn() {
+        let policy = BurnPolicy {
+            min_burn: 1_000,
+            min_burn_per_byte: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.required_burn(0, 0), 1_000);
+        assert_eq!(policy.required_burn(10, 0), 1_020);
+        assert_eq!(policy.required_burn(10, 5_000), 5_020);
+        assert_eq!(BurnPolicy::default().required_burn(100, 0), 0);
+    }
+
+    #[test]
+    fn allows_transactions() {
+        let policy = BurnPolicy {
+            max_transactions: 2,
+            ..Default::default()
+        };
+
+        assert!(policy.allows_transactions(1));
+        assert!(policy.allows_transactions(2));
+        assert!(!policy.allows_transactions(3));
+        assert!(BurnPolicy::default().allows_transactions(1_000));
+    }
+}
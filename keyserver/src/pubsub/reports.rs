@@ -0,0 +1,296 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use cashweb::auth_wrapper::{AuthWrapper, ParseError, VerifyError};
+use prost::Message as _;
+use serde::Deserialize;
+use thiserror::Error;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{
+    crypto::hash160,
+    models::broadcast::{BroadcastMessage, Report, ReportEntry, ReportList},
+    net::ToResponse,
+    pubsub::{ModerationError, PubSubDatabase, PubSubDatabaseError, Topic},
+};
+
+/// Limits placed on the reports made by each reporter.
+#[derive(Clone, Copy, Debug)]
+pub struct ReportLimits {
+    /// Maximum number of reports within the window.
+    pub max_reports: usize,
+    /// Length of the window, in milliseconds.
+    pub window: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error("failed to decode authorization wrapper: {0}")]
+    WrapperDecode(prost::DecodeError),
+    #[error("failed to parse authorization wrapper: {0}")]
+    Parse(ParseError),
+    #[error("failed to verify authorization wrapper: {0}")]
+    Verify(VerifyError),
+    #[error("failed to decode report: {0}")]
+    ReportDecode(prost::DecodeError),
+    #[error("report must reference a message or topic")]
+    MissingTarget,
+    #[error("payload digest must be 32 bytes, found {0}")]
+    InvalidPayloadDigest(usize),
+    #[error("reported message not found")]
+    NotFound,
+    #[error("too many reports")]
+    RateLimited,
+    #[error("failed to access database: {0}")]
+    Database(#[from] PubSubDatabaseError),
+}
+
+impl Reject for ReportError {}
+
+impl ToResponse for ReportError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::NotFound => 404,
+            Self::RateLimited => 429,
+            Self::Database(_) => 500,
+            _ => 400,
+        }
+    }
+}
+
+fn get_unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// Handles report POST requests.
+///
+/// The report is signed by the reporter, who is limited to a number of reports within a window.
+pub async fn put_report(
+    db: PubSubDatabase,
+    limits: ReportLimits,
+    body: Bytes,
+) -> Result<Response<Body>, ReportError> {
+    // Verify report
+    let wrapper = AuthWrapper::decode(body).map_err(ReportError::WrapperDecode)?;
+    let reporter = hash160(&wrapper.public_key).to_vec();
+    let parsed_wrapper = wrapper.parse().map_err(ReportError::Parse)?;
+    parsed_wrapper.verify().map_err(ReportError::Verify)?;
+    let report =
+        Report::decode(parsed_wrapper.payload.as_slice()).map_err(ReportError::ReportDecode)?;
+
+    // Check target
+    if report.payload_digest.is_empty() && report.topic.is_empty() {
+        return Err(ReportError::MissingTarget);
+    }
+    if !report.payload_digest.is_empty() {
+        if report.payload_digest.len() != 32 {
+            return Err(ReportError::InvalidPayloadDigest(
+                report.payload_digest.len(),
+            ));
+        }
+        match db.get_message(&report.payload_digest) {
+            Ok(_) => (),
+            Err(PubSubDatabaseError::MissingValue(_)) => return Err(ReportError::NotFound),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    // Check rate limit
+    let received_time = get_unix_now();
+    let since = received_time.saturating_sub(limits.window as i64);
+    if db.count_reports_since(&reporter, since)? >= limits.max_reports {
+        return Err(ReportError::RateLimited);
+    }
+
+    let entry = ReportEntry {
+        id: parsed_wrapper.payload_digest.to_vec(),
+        reporter,
+        received_time,
+        report: Some(report),
+    };
+    db.put_report(&entry)?;
+
+    Ok(Response::builder()
+        .body(Body::from(hex::encode(&entry.id)))
+        .unwrap())
+}
+
+/// Handles report GET requests.
+pub async fn get_reports(db: PubSubDatabase) -> Result<Response<Body>, ModerationError> {
+    let report_list = ReportList {
+        reports: db.get_reports()?,
+    };
+    let mut raw_report_list = Vec::with_capacity(report_list.encoded_len());
+    report_list.encode(&mut raw_report_list).unwrap(); // This is safe
+
+    Ok(Response::builder()
+        .body(Body::from(raw_report_list))
+        .unwrap())
+}
+
+/// Action taken by the operator to resolve a report.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportAction {
+    /// Take no action.
+    Dismiss,
+    /// Hide the reported message.
+    HideMessage,
+    /// Ban the reported topic, or the topic of the reported message.
+    BanTopic,
+    /// Block the author of the reported message.
+    BlockSender,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportActionQuery {
+    action: ReportAction,
+}
+
+/// Apply an action to the subject of a report.
+fn apply_action(
+    db: &PubSubDatabase,
+    report: &Report,
+    action: ReportAction,
+) -> Result<(), ModerationError> {
+    if action == ReportAction::Dismiss {
+        return Ok(());
+    }
+    if action == ReportAction::BanTopic && !report.topic.is_empty() {
+        let topic = Topic::parse(&report.topic).map_err(|_| ModerationError::InvalidTopic)?;
+        db.put_banned_topic(topic.as_str())?;
+        return Ok(());
+    }
+
+    // Remaining actions apply to the reported message
+    if report.payload_digest.is_empty() {
+        return Err(ModerationError::InvalidAction);
+    }
+    match action {
+        ReportAction::HideMessage => db.put_hidden_message(&report.payload_digest)?,
+        ReportAction::BanTopic => {
+            let message = db.get_message(&report.payload_digest)?;
+            let raw_topic = BroadcastMessage::decode(message.payload.as_slice())
+                .map_err(|_| ModerationError::InvalidTopic)?
+                .topic;
+            let topic = Topic::parse(&raw_topic).map_err(|_| ModerationError::InvalidTopic)?;
+            if topic.is_root() {
+                return Err(ModerationError::InvalidTopic);
+            }
+            db.put_banned_topic(topic.as_str())?;
+        }
+        ReportAction::BlockSender => {
+            let message = db.get_message(&report.payload_digest)?;
+            if message.public_key.is_empty() {
+                return Err(ModerationError::InvalidAction);
+            }
+            db.put_blocked_sender(&hash160(&message.public_key))?;
+        }
+        ReportAction::Dismiss => (),
+    }
+    Ok(())
+}
+
+/// Handles report POST requests, resolving the report with an action.
+pub async fn resolve_report(
+    db: PubSubDatabase,
+    id: Vec<u8>,
+    query: ReportActionQuery,
+) -> Result<Response<Body>, ModerationError> {
+    let entry = db.get_report(&id)?.ok_or(ModerationError::NotFound)?;
+    let report = entry.report.unwrap_or_default();
+    apply_action(&db, &report, query.action)?;
+    db.remove_report(&id)?;
+
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles hidden message DELETE requests.
+pub async fn delete_hidden_message(
+    db: PubSubDatabase,
+    payload_digest: Vec<u8>,
+) -> Result<Response<Body>, ModerationError> {
+    if !db.remove_hidden_message(&payload_digest)? {
+        return Err(ModerationError::NotFound);
+    }
+
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles blocked sender DELETE requests.
+pub async fn delete_blocked_sender(
+    db: PubSubDatabase,
+    pubkey_hash: Vec<u8>,
+) -> Result<Response<Body>, ModerationError> {
+    if !db.remove_blocked_sender(&pubkey_hash)? {
+        return Err(ModerationError::NotFound);
+    }
+
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{Options, DB};
+
+    use super::*;
+
+    #[test]
+    fn report_actions() {
+        const TEST_NAME: &str = "./tests/report_actions";
+
+        // Create database
+        let database = PubSubDatabase::new(TEST_NAME).unwrap();
+
+        let payload = BroadcastMessage {
+            topic: "foo.bar".to_string(),
+            ..Default::default()
+        };
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap();
+        let message = AuthWrapper {
+            public_key: vec![2; 33],
+            payload: raw_payload,
+            payload_digest: vec![0; 32],
+            ..Default::default()
+        };
+        database
+            .put_message(1, &Topic::parse("foo.bar").unwrap(), &message)
+            .unwrap();
+
+        let report = Report {
+            payload_digest: vec![0; 32],
+            ..Default::default()
+        };
+        apply_action(&database, &report, ReportAction::HideMessage).unwrap();
+        assert!(database.is_hidden(&[0; 32]).unwrap());
+
+        apply_action(&database, &report, ReportAction::BlockSender).unwrap();
+        assert!(database.is_blocked(&hash160(&[2; 33])).unwrap());
+
+        apply_action(&database, &report, ReportAction::BanTopic).unwrap();
+        assert_eq!(database.get_banned_topics().unwrap(), vec!["foo.bar"]);
+
+        // Topic reports can only ban the topic
+        let report = Report {
+            topic: "baz".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_action(&database, &report, ReportAction::HideMessage),
+            Err(ModerationError::InvalidAction)
+        ));
+        apply_action(&database, &report, ReportAction::BanTopic).unwrap();
+        assert_eq!(
+            database.get_banned_topics().unwrap(),
+            vec!["baz", "foo.bar"]
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use cashweb::{
+    bitcoin_client::BitcoinClient,
+    keyserver_client::services::{SampleError, SyncMessagesError},
+};
+use hyper::{Body, Request, Response};
+use prost::Message as _;
+use tower_service::Service;
+use tracing::warn;
+use warp::{Rejection, Reply};
+
+use crate::{
+    net::NEXT_SINCE,
+    peering::PeerHandler,
+    pubsub::{
+        accept_message, BurnPolicy, MessageBus, MessagesRpcRejection, PubSubDatabase, Topic,
+        TopicModeration,
+    },
+    SETTINGS,
+};
+
+/// Handles message sync GET requests.
+///
+/// Responds with a page of the length-delimited `AuthWrapper`s received since `since`. If further
+/// messages remain, the timestamp at which the next page starts is given in the `Next-Since`
+/// header.
+pub async fn get_messages_sync(
+    db: PubSubDatabase,
+    since: i64,
+    limit: Option<usize>,
+) -> Result<impl Reply, Rejection> {
+    let limit = limit
+        .unwrap_or(SETTINGS.limits.sync_messages)
+        .min(SETTINGS.limits.sync_messages)
+        .max(1);
+    let (messages, next_since) = db
+        .get_messages_page(&Topic::default(), since, limit)
+        .map_err(MessagesRpcRejection::DatabaseError)?;
+
+    let mut raw_messages = Vec::new();
+    for message in messages {
+        message.encode_length_delimited(&mut raw_messages).unwrap(); // This is safe
+    }
+
+    let mut builder = Response::builder();
+    if let Some(next_since) = next_since {
+        builder = builder.header(NEXT_SINCE, next_since);
+    }
+    Ok(builder.body(raw_messages).unwrap())
+}
+
+/// Pull the messages received by peers since `since` and merge them into the database.
+///
+/// Burn transactions are broadcast, as they are for messages put directly, so only those the node
+/// accepts or already knows are counted. Returns the number of messages accepted.
+pub async fn pull_messages<S, C>(
+    peer_handler: &PeerHandler<S>,
+    db: &PubSubDatabase,
+    client: &C,
+    msg_bus: &MessageBus,
+    moderation: &TopicModeration,
+    policy: &BurnPolicy,
+    since: i64,
+) -> Result<usize, SampleError<SyncMessagesError<S::Error>>>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display + Send,
+    C: BitcoinClient,
+{
+    let aggregate_response = peer_handler
+        .get_keyserver_manager()
+        .collect_messages(since)
+        .await?;
+    for (uri, err) in &aggregate_response.errors {
+        warn!(message = "failed to sync messages from peer", peer = %uri, error = %err);
+    }
+
+    let mut accepted = 0;
+    for mut message in aggregate_response.response {
+        // Existing messages only have their burns merged
+        if db.get_message(&message.payload_digest).is_ok() {
+            message.payload = Vec::with_capacity(0);
+        }
+
+        match accept_message(db, Some(client), msg_bus, moderation, policy, message).await {
+            Ok(()) => accepted += 1,
+            Err(err) => warn!(message = "rejected synced message", error = %err),
+        }
+    }
+    Ok(accepted)
+}
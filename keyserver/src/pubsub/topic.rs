@@ -0,0 +1,219 @@
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::pubsub::topic_matches;
+
+/// Maximum number of segments in a topic.
+pub const MAX_TOPIC_SEGMENTS: usize = 10;
+/// Maximum length of a topic, in bytes.
+pub const MAX_TOPIC_LENGTH: usize = 256;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TopicError {
+    #[error("topic too long: {0} > {}", MAX_TOPIC_LENGTH)]
+    TooLong(usize),
+    #[error("topic has too many segments: {0} > {}", MAX_TOPIC_SEGMENTS)]
+    TooManySegments(usize),
+    #[error("topic contains empty segments")]
+    EmptySegment,
+    #[error("topic contains invalid character {0:?}")]
+    InvalidCharacter(char),
+    #[error("wildcard must be the last segment")]
+    InvalidWildcard,
+}
+
+/// A validated and normalized topic, consisting of `.` separated segments.
+///
+/// Topics are case folded to lowercase and may only contain lowercase alphanumeric characters,
+/// `-` and `.`. The empty topic is the root, under which every other topic falls.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Topic(String);
+
+impl Topic {
+    /// Validate and normalize a topic.
+    pub fn parse(raw: &str) -> Result<Self, TopicError> {
+        if raw.len() > MAX_TOPIC_LENGTH {
+            return Err(TopicError::TooLong(raw.len()));
+        }
+        let topic = raw.to_lowercase();
+        if topic.is_empty() {
+            return Ok(Topic(topic));
+        }
+        // Case folding may lengthen the topic
+        if topic.len() > MAX_TOPIC_LENGTH {
+            return Err(TopicError::TooLong(topic.len()));
+        }
+
+        if let Some(c) = topic
+            .chars()
+            .find(|c| !(c.is_lowercase() || c.is_numeric() || *c == '.' || *c == '-'))
+        {
+            return Err(TopicError::InvalidCharacter(c));
+        }
+
+        let n_segments = topic.split('.').count();
+        if n_segments > MAX_TOPIC_SEGMENTS {
+            return Err(TopicError::TooManySegments(n_segments));
+        }
+        if topic.split('.').any(|segment| segment.is_empty()) {
+            return Err(TopicError::EmptySegment);
+        }
+
+        Ok(Topic(topic))
+    }
+
+    /// Returns true if this is the root topic.
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The segments of the topic, the root topic having none.
+    pub fn segments(&self) -> Vec<&str> {
+        if self.is_root() {
+            return Vec::new();
+        }
+        self.0.split('.').collect()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Topic {
+    type Err = TopicError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        Topic::parse(raw)
+    }
+}
+
+impl AsRef<str> for Topic {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A topic query, matching a topic and its sub-topics.
+///
+/// A trailing `*` segment, as in `foo.*`, matches only the sub-topics of `foo`, the wildcard `*`
+/// alone matching every topic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopicFilter {
+    topic: Topic,
+    wildcard: bool,
+}
+
+impl TopicFilter {
+    /// Parse a topic query, validating and normalizing the topic.
+    pub fn parse(raw: &str) -> Result<Self, TopicError> {
+        if raw == "*" {
+            return Ok(TopicFilter {
+                topic: Topic::default(),
+                wildcard: true,
+            });
+        }
+        match raw.strip_suffix(".*") {
+            Some("") => Err(TopicError::EmptySegment),
+            Some(prefix) if prefix.contains('*') => Err(TopicError::InvalidWildcard),
+            Some(prefix) => Ok(TopicFilter {
+                topic: Topic::parse(prefix)?,
+                wildcard: true,
+            }),
+            None if raw.contains('*') => Err(TopicError::InvalidWildcard),
+            None => Ok(TopicFilter {
+                topic: Topic::parse(raw)?,
+                wildcard: false,
+            }),
+        }
+    }
+
+    /// The topic at the root of the matched subtree.
+    pub fn topic(&self) -> &Topic {
+        &self.topic
+    }
+
+    /// Returns true if the query excludes the topic at the root of the subtree.
+    pub fn is_wildcard(&self) -> bool {
+        self.wildcard
+    }
+
+    /// Returns true if the topic is matched by the query.
+    pub fn matches(&self, topic: &Topic) -> bool {
+        topic_matches(self.topic.as_str(), topic.as_str())
+            && !(self.wildcard && *topic == self.topic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        assert_eq!(Topic::parse("Foo.BAR-1").unwrap().as_str(), "foo.bar-1");
+        assert!(Topic::parse("").unwrap().is_root());
+        assert_eq!(
+            Topic::parse("foo.bar.bob").unwrap().segments(),
+            vec!["foo", "bar", "bob"]
+        );
+        assert!(Topic::parse("").unwrap().segments().is_empty());
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(
+            Topic::parse("this topic is not valid"),
+            Err(TopicError::InvalidCharacter(' '))
+        );
+        assert_eq!(Topic::parse("foo..bar"), Err(TopicError::EmptySegment));
+        assert_eq!(Topic::parse(".foo"), Err(TopicError::EmptySegment));
+        assert_eq!(
+            Topic::parse("a.b.c.d.e.f.g.h.i.j.k"),
+            Err(TopicError::TooManySegments(11))
+        );
+        let long = "a".repeat(MAX_TOPIC_LENGTH + 1);
+        assert_eq!(
+            Topic::parse(&long),
+            Err(TopicError::TooLong(MAX_TOPIC_LENGTH + 1))
+        );
+    }
+
+    #[test]
+    fn wildcard_filter() {
+        let topic = |raw: &str| Topic::parse(raw).unwrap();
+
+        let subtree = TopicFilter::parse("Foo.*").unwrap();
+        assert_eq!(subtree.topic(), &topic("foo"));
+        assert!(subtree.matches(&topic("foo.bar")));
+        assert!(subtree.matches(&topic("foo.bar.bob")));
+        assert!(!subtree.matches(&topic("foo")));
+        assert!(!subtree.matches(&topic("foobar.baz")));
+
+        let prefix = TopicFilter::parse("foo").unwrap();
+        assert!(prefix.matches(&topic("foo")));
+        assert!(prefix.matches(&topic("foo.bar")));
+
+        let all = TopicFilter::parse("*").unwrap();
+        assert!(all.topic().is_root());
+        assert!(all.matches(&topic("foo.bar")));
+
+        assert_eq!(TopicFilter::parse(".*"), Err(TopicError::EmptySegment));
+        assert_eq!(
+            TopicFilter::parse("foo.*.bar"),
+            Err(TopicError::InvalidWildcard)
+        );
+        assert_eq!(TopicFilter::parse("foo*"), Err(TopicError::InvalidWildcard));
+        assert_eq!(
+            TopicFilter::parse("foo.*.*"),
+            Err(TopicError::InvalidWildcard)
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use async_stream::stream;
+use cashweb::auth_wrapper::AuthWrapper;
+use futures::{pin_mut, prelude::*};
+use prost::Message as _;
+use thiserror::Error;
+use tokio::{
+    sync::broadcast,
+    time::{interval, Duration},
+};
+use tokio_stream::wrappers::IntervalStream;
+use tracing::error;
+use warp::{
+    ws::{Message, WebSocket, Ws},
+    Reply,
+};
+
+use crate::{
+    pubsub::{Topic, TopicFilter},
+    SETTINGS,
+};
+
+pub const BROADCAST_CHANNEL_CAPACITY: usize = 256;
+
+/// A topical message pushed to subscribers, consisting of the topic and the
+/// accepted `AuthWrapper`.
+pub type TopicMessage = (Topic, AuthWrapper);
+
+pub type MessageBus = broadcast::Sender<TopicMessage>;
+
+/// Returns true if `topic` falls under the subscription `prefix`.
+///
+/// The prefix is matched on whole segments, so `foo` matches `foo` and
+/// `foo.bar` but not `foobar`. The empty prefix matches every topic.
+pub fn topic_matches(prefix: &str, topic: &str) -> bool {
+    if prefix.is_empty() {
+        return true;
+    }
+    match topic.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+/// Serialize an `AuthWrapper` for the websocket, pruning payloads which are too long.
+///
+/// Subscribers can retrieve pruned payloads using the payload digest.
+fn encode_ws_message(mut message: AuthWrapper) -> Vec<u8> {
+    if message.payload.len() > SETTINGS.websocket.truncation_length as usize {
+        message.payload = Vec::with_capacity(0);
+    }
+    let mut raw_message = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut raw_message).unwrap(); // This is safe
+    raw_message
+}
+
+pub fn upgrade_ws(filter: TopicFilter, ws: Ws, msg_bus: MessageBus) -> impl Reply {
+    // Upgrade socket
+    ws.on_upgrade(move |socket| connect_ws(filter, socket, msg_bus))
+}
+
+#[derive(Debug, Error)]
+enum WsError {
+    #[error("websocket send failed: {0}")]
+    SinkError(warp::Error),
+    #[error("broadcast failure: {0}")]
+    BusError(broadcast::error::RecvError),
+}
+
+pub async fn connect_ws(filter: TopicFilter, ws: WebSocket, msg_bus: MessageBus) {
+    let rx = msg_bus.subscribe();
+
+    // Do this until broadcast::Receiver has a stream wrapper in tokio-stream library
+    let rx = stream! {
+        pin_mut!(rx);
+
+        loop {
+            yield rx.recv().await;
+        }
+    };
+    let rx = rx
+        .try_filter(move |(msg_topic, _)| future::ready(filter.matches(msg_topic)))
+        .map_ok(|(_, wrapper)| Message::binary(encode_ws_message(wrapper)))
+        .map_err(WsError::BusError);
+
+    let (user_ws_tx, _) = ws.split();
+
+    // Setup periodic ping
+    let periodic_ping = IntervalStream::new(interval(Duration::from_millis(
+        SETTINGS.websocket.ping_interval,
+    )))
+    .map(move |_| Ok(Message::ping(vec![])));
+    let merged = stream::select(rx, periodic_ping);
+
+    if let Err(err) = merged
+        .forward(user_ws_tx.sink_map_err(WsError::SinkError))
+        .await
+    {
+        error!(message = "forwarding error", error = %err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_prefix_matching() {
+        assert!(topic_matches("", "foo.bar"));
+        assert!(topic_matches("foo", "foo"));
+        assert!(topic_matches("foo", "foo.bar"));
+        assert!(topic_matches("foo.bar", "foo.bar.bob"));
+        assert!(!topic_matches("foo", "foobar"));
+        assert!(!topic_matches("foo.bar", "foo"));
+        assert!(!topic_matches("bar", "foo.bar"));
+    }
+}
@@ -0,0 +1,528 @@
+//! This module contains the construction of the REST API, shared by `main` and the test harness.
+
+use std::{sync::Arc, time::Duration};
+
+use cashweb::{
+    bitcoin_client::{ChainBackend, FailoverClient},
+    payments::preprocess_payment,
+    token::schemes::chain_commitment::ChainCommitmentScheme,
+};
+use futures::prelude::*;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde::Deserialize;
+use warp::{
+    filters::BoxedFilter,
+    http::{HeaderMap, Response},
+    hyper::Body,
+    Filter,
+};
+
+use crate::{
+    db::Database,
+    net,
+    peering::{PeerHandler, TokenCache},
+    pubsub::{
+        self, BurnPolicy, MessageBus, MessagesRpcRejection, PubSubDatabase, ReportLimits,
+        TopicFilter, TopicModeration,
+    },
+    ADMIN_PATH, AUDIT_PATH, BANNED_TOPICS_PATH, BLOCKED_SENDERS_PATH, BURNS_PATH,
+    CAPABILITIES_PATH, HIDDEN_MESSAGES_PATH, HISTORY_PATH, MESSAGES_PATH, MESSAGE_SIZE_LIMIT,
+    METADATA_PATH, PAYMENTS_PATH, PEERS_PATH, POLICY_PATH, REPLIES_PATH, REPORTS_PATH,
+    REPORT_SIZE_LIMIT, REVOCATIONS_PATH, SETTINGS, SYNC_PATH, WS_PATH,
+};
+
+/// Construct the REST API, without CORS or tracing.
+#[allow(clippy::too_many_arguments)]
+pub fn routes(
+    db: Database,
+    pubsub_db: PubSubDatabase,
+    bitcoin_client: FailoverClient<ChainBackend>,
+    msg_bus: MessageBus,
+    peer_handler: PeerHandler<hyper::Client<HttpsConnector<HttpConnector>>>,
+    token_cache: TokenCache,
+    moderation: TopicModeration,
+    policy: BurnPolicy,
+) -> BoxedFilter<(Response<Body>,)> {
+    // Peer state
+    let peer_handler = warp::any().map(move || peer_handler.clone());
+
+    // Database state
+    let audit_db = db.clone();
+    let db_state = warp::any().map(move || db.clone());
+
+    // PubSub Database state
+    let pubsub_db_state = warp::any().map(move || pubsub_db.clone());
+
+    // PubSub message broadcast state
+    let msg_bus_state = warp::any().map(move || msg_bus.clone());
+
+    // Topic moderation state
+    let moderation_state = warp::any().map(move || moderation.clone());
+
+    // Burn policy state
+    let policy_state = warp::any().map(move || policy);
+
+    // Server identity state
+    let identity = SETTINGS.identity.private_key.as_ref().map(|private_key| {
+        net::ServerIdentity::from_hex(private_key).expect("unable to interpret identity key")
+    });
+    let raw_capabilities = net::construct_capabilities(identity.as_ref().map(|i| i.public_key()));
+    let attestation = if SETTINGS.identity.sign_responses {
+        Some(
+            identity
+                .clone()
+                .expect("signing responses requires an identity key"),
+        )
+    } else {
+        None
+    };
+    let attestation_state = warp::any().map(move || attestation.clone());
+
+    // Load shedding
+    let load_shedder = net::LoadShedder::new(
+        SETTINGS.load_shedding.max_concurrent,
+        Duration::from_millis(SETTINGS.load_shedding.queue_timeout),
+    );
+    let load_shed = warp::any().and_then(move || {
+        let load_shedder = load_shedder.clone();
+        async move { load_shedder.acquire().await.map_err(warp::reject::custom) }
+    });
+
+    // Address string converter
+    let addr_base = warp::path::param().and_then(|addr_str: String| async move {
+        net::address_decode(&addr_str).map_err(warp::reject::custom)
+    });
+
+    // Token generator
+    let token_scheme = Arc::new(
+        ChainCommitmentScheme::from_client(bitcoin_client.clone())
+            .with_max_outpoints(SETTINGS.limits.payment_transactions),
+    );
+    let token_scheme_state = warp::any().map(move || token_scheme.clone());
+
+    // Token cache state
+    let token_cache_state = warp::any().map(move || token_cache.clone());
+
+    // Negative cache state
+    let negative_cache = net::NegativeCache::new(
+        Duration::from_millis(SETTINGS.peering.negative_cache_ttl),
+        SETTINGS.peering.negative_cache_size,
+    );
+    let negative_cache_state = warp::any().map(move || negative_cache.clone());
+
+    // Bitcoin client state
+    let bitcoin_client_state = warp::any().map(move || bitcoin_client.clone());
+
+    // Protection
+    let addr_protected = addr_base
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.metadata_size,
+        ))
+        .and(warp::body::bytes())
+        .and(warp::header::headers_cloned())
+        .and(token_scheme_state.clone())
+        .and_then(move |addr, body, headers, token_scheme| {
+            net::pop_protection(addr, body, headers, token_scheme).map_err(warp::reject::custom)
+        })
+        .untuple_one();
+
+    // Metadata handlers
+    let metadata_get = warp::path(METADATA_PATH)
+        .and(addr_base)
+        .and(net::get_or_head())
+        .and(warp::header::headers_cloned())
+        .and(db_state.clone())
+        .and(peer_handler.clone())
+        .and(negative_cache_state.clone())
+        .and_then(move |addr, headers, db, peer_handler, negative_cache| {
+            net::get_metadata(addr, headers, db, peer_handler, negative_cache)
+                .map_err(warp::reject::custom)
+        })
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+    let metadata_history_get = warp::path(METADATA_PATH)
+        .and(addr_base)
+        .and(warp::path(HISTORY_PATH))
+        .and(warp::path::end())
+        .and(net::get_or_head())
+        .and(db_state.clone())
+        .and_then(move |addr, db| net::get_metadata_history(addr, db).map_err(warp::reject::custom))
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+    let metadata_put = net::audited(
+        "metadata.put",
+        warp::path(METADATA_PATH)
+            .and(addr_protected)
+            .and(warp::put())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(db_state.clone())
+            .and(token_cache_state)
+            .and(negative_cache_state)
+            .and_then(
+                move |addr, body, if_match, db, token_cache, negative_cache| {
+                    net::put_metadata(addr, body, if_match, db, token_cache, negative_cache)
+                        .map_err(warp::reject::custom)
+                },
+            ),
+        audit_db.clone(),
+    );
+
+    // Revocation handlers
+    let revocation_get = warp::path(REVOCATIONS_PATH)
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(net::get_or_head())
+        .and(db_state.clone())
+        .and_then(move |public_key, db| {
+            net::get_revocation(public_key, db).map_err(warp::reject::custom)
+        });
+    let revocation_put = net::audited(
+        "revocation.put",
+        warp::path(REVOCATIONS_PATH)
+            .and(warp::path::end())
+            .and(warp::put())
+            .and(warp::body::content_length_limit(
+                SETTINGS.limits.metadata_size,
+            ))
+            .and(warp::body::bytes())
+            .and(db_state.clone())
+            .and_then(move |body, db| net::put_revocation(body, db).map_err(warp::reject::custom)),
+        audit_db.clone(),
+    );
+
+    // Peer handler
+    let peers_get = warp::path(PEERS_PATH)
+        .and(warp::get())
+        .and(peer_handler)
+        .and_then(move |peer_handler| net::get_peers(peer_handler).map_err(warp::reject::custom));
+
+    let payload_digest_path_param =
+        warp::path::param().and_then(|payload_digest: String| async move {
+            hex::decode(&payload_digest).map_err(|_| warp::reject::not_found())
+        });
+
+    #[derive(Deserialize)]
+    struct MessageGetQueryParameters {
+        topic: String,
+        from: i64,
+        to: i64,
+    }
+    let messages_get = warp::path(MESSAGES_PATH)
+        .and(net::get_or_head())
+        .and(pubsub_db_state.clone())
+        .and(warp::query::<MessageGetQueryParameters>())
+        .and_then(|db: PubSubDatabase, params: MessageGetQueryParameters| {
+            pubsub::get_messages(db, params.topic, params.from, params.to)
+        })
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+
+    #[derive(Deserialize)]
+    struct MessageAuthorQueryParameters {
+        author: String,
+        from: Option<i64>,
+        to: Option<i64>,
+    }
+    let messages_author_get = warp::path(MESSAGES_PATH)
+        .and(warp::path::end())
+        .and(net::get_or_head())
+        .and(pubsub_db_state.clone())
+        .and(warp::query::<MessageAuthorQueryParameters>())
+        .and_then(
+            |db: PubSubDatabase, params: MessageAuthorQueryParameters| async move {
+                let author = net::address_decode(&params.author).map_err(warp::reject::custom)?;
+                pubsub::get_messages_by_author(
+                    db,
+                    author.into_body(),
+                    params.from.unwrap_or(0),
+                    params.to.unwrap_or(i64::MAX),
+                )
+                .await
+            },
+        )
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+
+    #[derive(Deserialize)]
+    struct MessageSyncQueryParameters {
+        since: i64,
+        limit: Option<usize>,
+    }
+    let messages_sync = warp::path(MESSAGES_PATH)
+        .and(warp::path(SYNC_PATH))
+        .and(net::get_or_head())
+        .and(pubsub_db_state.clone())
+        .and(warp::query::<MessageSyncQueryParameters>())
+        .and_then(|db: PubSubDatabase, params: MessageSyncQueryParameters| {
+            pubsub::get_messages_sync(db, params.since, params.limit)
+        });
+
+    #[derive(Deserialize)]
+    struct MessageRepliesQueryParameters {
+        from: Option<i64>,
+        to: Option<i64>,
+    }
+    let messages_replies_get = warp::path(MESSAGES_PATH)
+        .and(net::get_or_head())
+        .and(pubsub_db_state.clone())
+        .and(payload_digest_path_param.clone())
+        .and(warp::path(REPLIES_PATH))
+        .and(warp::path::end())
+        .and(warp::query::<MessageRepliesQueryParameters>())
+        .and_then(
+            |db: PubSubDatabase, parent_digest: Vec<u8>, params: MessageRepliesQueryParameters| {
+                pubsub::get_replies(
+                    db,
+                    parent_digest,
+                    params.from.unwrap_or(0),
+                    params.to.unwrap_or(i64::MAX),
+                )
+            },
+        )
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+
+    let messages_burns_get = warp::path(MESSAGES_PATH)
+        .and(net::get_or_head())
+        .and(pubsub_db_state.clone())
+        .and(payload_digest_path_param.clone())
+        .and(warp::path(BURNS_PATH))
+        .and(warp::path::end())
+        .and_then(pubsub::get_burns)
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+
+    let messages_get_id = warp::path(MESSAGES_PATH)
+        .and(net::get_or_head())
+        .and(pubsub_db_state.clone())
+        .and(payload_digest_path_param.clone())
+        .and_then(|db: PubSubDatabase, payload_digest: Vec<u8>| {
+            pubsub::get_message(db, payload_digest)
+        })
+        .and(attestation_state.clone())
+        .and_then(net::attest);
+
+    let messages_put = warp::path(MESSAGES_PATH)
+        .and(warp::put())
+        .and(pubsub_db_state.clone())
+        .and(bitcoin_client_state.clone())
+        .and(msg_bus_state.clone())
+        .and(moderation_state.clone())
+        .and(policy_state.clone())
+        .and(warp::body::content_length_limit(MESSAGE_SIZE_LIMIT))
+        .and(warp::body::bytes())
+        .and_then(pubsub::put_raw_message);
+
+    // Policy handler
+    let policy_get = warp::path(POLICY_PATH)
+        .and(warp::get())
+        .and(policy_state)
+        .and(moderation_state)
+        .and_then(pubsub::get_policy);
+
+    // Admin protection
+    let admin_protected = warp::header::headers_cloned()
+        .and_then(|headers: HeaderMap| async move {
+            pubsub::admin_protection(&headers, SETTINGS.moderation.admin_token.as_deref())
+                .map_err(warp::reject::custom)
+        })
+        .untuple_one();
+
+    // Moderation handlers
+    let banned_topics_get = warp::path(ADMIN_PATH)
+        .and(warp::path(BANNED_TOPICS_PATH))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin_protected.clone())
+        .and(pubsub_db_state.clone())
+        .and_then(|db| pubsub::get_banned_topics(db).map_err(warp::reject::custom));
+    let banned_topics_put = net::audited(
+        "admin.banned_topic.put",
+        warp::path(ADMIN_PATH)
+            .and(warp::path(BANNED_TOPICS_PATH))
+            .and(warp::path::param())
+            .and(warp::put())
+            .and(admin_protected.clone())
+            .and(pubsub_db_state.clone())
+            .and_then(|topic, db| {
+                pubsub::put_banned_topic(db, topic).map_err(warp::reject::custom)
+            }),
+        audit_db.clone(),
+    );
+    let banned_topics_delete = net::audited(
+        "admin.banned_topic.delete",
+        warp::path(ADMIN_PATH)
+            .and(warp::path(BANNED_TOPICS_PATH))
+            .and(warp::path::param())
+            .and(warp::delete())
+            .and(admin_protected.clone())
+            .and(pubsub_db_state.clone())
+            .and_then(|topic, db| {
+                pubsub::delete_banned_topic(db, topic).map_err(warp::reject::custom)
+            }),
+        audit_db.clone(),
+    );
+    let reports_get = warp::path(ADMIN_PATH)
+        .and(warp::path(REPORTS_PATH))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin_protected.clone())
+        .and(pubsub_db_state.clone())
+        .and_then(|db| pubsub::get_reports(db).map_err(warp::reject::custom));
+    let reports_resolve = net::audited(
+        "admin.report.resolve",
+        warp::path(ADMIN_PATH)
+            .and(warp::path(REPORTS_PATH))
+            .and(payload_digest_path_param.clone())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::query())
+            .and(admin_protected.clone())
+            .and(pubsub_db_state.clone())
+            .and_then(|id, query, db| {
+                pubsub::resolve_report(db, id, query).map_err(warp::reject::custom)
+            }),
+        audit_db.clone(),
+    );
+    let hidden_messages_delete = net::audited(
+        "admin.hidden_message.delete",
+        warp::path(ADMIN_PATH)
+            .and(warp::path(HIDDEN_MESSAGES_PATH))
+            .and(payload_digest_path_param.clone())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(admin_protected.clone())
+            .and(pubsub_db_state.clone())
+            .and_then(|payload_digest, db| {
+                pubsub::delete_hidden_message(db, payload_digest).map_err(warp::reject::custom)
+            }),
+        audit_db.clone(),
+    );
+    let blocked_senders_delete = net::audited(
+        "admin.blocked_sender.delete",
+        warp::path(ADMIN_PATH)
+            .and(warp::path(BLOCKED_SENDERS_PATH))
+            .and(payload_digest_path_param.clone())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(admin_protected.clone())
+            .and(pubsub_db_state.clone())
+            .and_then(|pubkey_hash, db| {
+                pubsub::delete_blocked_sender(db, pubkey_hash).map_err(warp::reject::custom)
+            }),
+        audit_db,
+    );
+    let audit_get = warp::path(ADMIN_PATH)
+        .and(warp::path(AUDIT_PATH))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin_protected)
+        .and(warp::query())
+        .and(db_state)
+        .and_then(|query, db| net::get_audit_log(query, db).map_err(warp::reject::custom));
+
+    // Report handler
+    let report_limits = ReportLimits {
+        max_reports: SETTINGS.moderation.report_limit,
+        window: SETTINGS.moderation.report_window,
+    };
+    let reports_post = warp::path(REPORTS_PATH)
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(pubsub_db_state.clone())
+        .and(warp::body::content_length_limit(REPORT_SIZE_LIMIT))
+        .and(warp::body::bytes())
+        .and_then(move |db, body| {
+            pubsub::put_report(db, report_limits, body).map_err(warp::reject::custom)
+        });
+
+    // Websocket handlers
+    #[derive(Deserialize)]
+    struct MessageSubscribeQueryParameters {
+        topic: String,
+    }
+    let websocket_messages = warp::path(WS_PATH)
+        .and(warp::path(MESSAGES_PATH))
+        .and(warp::query::<MessageSubscribeQueryParameters>())
+        .and_then(|params: MessageSubscribeQueryParameters| async move {
+            TopicFilter::parse(&params.topic)
+                .map_err(MessagesRpcRejection::InvalidTopic)
+                .map_err(warp::reject::custom)
+        })
+        .and(warp::ws())
+        .and(msg_bus_state)
+        .map(pubsub::upgrade_ws);
+
+    // Payment handler
+    let payments = warp::path(PAYMENTS_PATH)
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.payment_size,
+        ))
+        .and(warp::body::bytes())
+        .and_then(move |headers, body| {
+            preprocess_payment(headers, body)
+                .map_err(net::PaymentError::Preprocess)
+                .map_err(warp::reject::custom)
+        })
+        .and(bitcoin_client_state.clone())
+        .and_then(move |payment, bitcoin_client| async move {
+            net::process_payment(payment, bitcoin_client)
+                .await
+                .map_err(warp::reject::custom)
+        });
+
+    // Capabilities handler
+    let capabilities_get = warp::path(CAPABILITIES_PATH)
+        .and(warp::path::end())
+        .and(net::get_or_head().or(warp::options()).unify())
+        .map(move || raw_capabilities.clone())
+        .and_then(net::get_capabilities)
+        .and(attestation_state)
+        .and_then(net::attest);
+
+    // Root handler
+    let root = warp::path::end()
+        .and(warp::get())
+        .and(warp::fs::file("./static/index.html"));
+
+    load_shed
+        .and(
+            root.or(capabilities_get)
+                .or(payments)
+                .or(metadata_history_get)
+                .or(metadata_get)
+                .or(metadata_put)
+                .or(revocation_get)
+                .or(revocation_put)
+                .or(peers_get)
+                .or(messages_sync)
+                .or(messages_author_get)
+                .or(messages_get)
+                .or(messages_replies_get)
+                .or(messages_burns_get)
+                .or(messages_get_id)
+                .or(messages_put)
+                .or(websocket_messages)
+                .or(banned_topics_get)
+                .or(banned_topics_put)
+                .or(banned_topics_delete)
+                .or(reports_get)
+                .or(reports_resolve)
+                .or(hidden_messages_delete)
+                .or(blocked_senders_delete)
+                .or(audit_get)
+                .or(reports_post)
+                .or(policy_get),
+        )
+        .map(net::release_permit)
+        .recover(net::handle_rejection)
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::optional::<String>(net::REQUEST_ID))
+        .map(net::render_problem)
+        .and(warp::method())
+        .map(net::head_response)
+        .boxed()
+}
@@ -1,49 +1,159 @@
-use std::net::SocketAddr;
-
-use clap::App;
-use config::{Config, ConfigError, File};
+use cashweb::bitcoin_client::{
+    Auth, BitcoinClientHTTP, BitcoinClientUnix, ChainBackend, IndexerClient,
+};
+use cashweb_server_core::settings::{
+    home_dir, merge_config_file, one_or_many, BindAddr, LoadShedding, UNIX_PREFIX,
+};
+use clap::{App, ArgMatches};
+use config::{Config, ConfigError};
 use serde::Deserialize;
 
+use crate::pubsub::Topic;
+
 const FOLDER_DIR: &str = ".keyserver";
 const DEFAULT_BIND: &str = "127.0.0.1:8080";
 const DEFAULT_RPC_ADDR: &str = "http://127.0.0.1:18443";
 const DEFAULT_RPC_USER: &str = "user";
 const DEFAULT_RPC_PASSWORD: &str = "password";
+const DEFAULT_RPC_AUTH: &str = "password";
+const DEFAULT_RPC_BACKEND: &str = "bitcoind";
+const DEFAULT_RPC_HEALTH_CHECK_INTERVAL: u64 = 10_000; // 10 seconds
 const DEFAULT_NETWORK: &str = "regtest";
+const DEFAULT_ADDRESS_PREFIXES: &[String] = &[];
 const DEFAULT_PING_INTERVAL: u64 = 10_000;
 const DEFAULT_METADATA_LIMIT: usize = 1_000 * 5; // 5KB
 const DEFAULT_PAYMENT_LIMIT: usize = 1_000 * 3; // 3KB
+const DEFAULT_METADATA_HISTORY_LIMIT: usize = 16;
+const DEFAULT_BURN_TRANSACTIONS_LIMIT: usize = 16;
+const DEFAULT_PAYMENT_TRANSACTIONS_LIMIT: usize = 8;
+const DEFAULT_SYNC_MESSAGES_LIMIT: usize = 1_000;
 const DEFAULT_TRUNCATION_LENGTH: usize = 500;
 const DEFAULT_MEMO: &str = "Thanks for your custom!";
+const DEFAULT_COMMITMENT_FEE: u64 = 0;
+const DEFAULT_MIN_FEE_RATE: u64 = 0;
+const DEFAULT_REJECT_UNCONFIRMED_PARENTS: bool = false;
+const DEFAULT_DSPROOF_WAIT: u64 = 0;
 const DEFAULT_MAX_PEERS: u32 = 128;
 const DEFAULT_PEERING: bool = true;
 const DEFAULT_ZMQ_ADDRESS: &str = "tcp://127.0.0.1:28332";
+const DEFAULT_ZMQ_RECONNECT_MIN: u64 = 1_000;
+const DEFAULT_ZMQ_RECONNECT_MAX: u64 = 60_000;
+const DEFAULT_BLOCK_POLL_INTERVAL: u64 = 30_000;
 const DEFAULT_PEERS: &[String] = &[];
 const DEFAULT_PEER_TIMEOUT: u64 = 60_000;
 const DEFAULT_PEER_KEEP_ALIVE: u64 = 30_000;
 const DEFAULT_PEER_BROADCAST_DELAY: usize = 2;
 const DEFAULT_PEER_FAN_SIZE: usize = 4;
+const DEFAULT_PEER_SYNC_INTERVAL: u64 = 60_000;
+const DEFAULT_TOKEN_CACHE_SIZE: usize = 10_000;
+const DEFAULT_NEGATIVE_CACHE_TTL: u64 = 30_000;
+const DEFAULT_NEGATIVE_CACHE_SIZE: usize = 10_000;
+const DEFAULT_PEER_SAMPLE_DEADLINE: u64 = 5_000;
+const DEFAULT_PEER_SAMPLE_TIMEOUT: u64 = 2_000;
+const DEFAULT_PEER_SAMPLE_QUORUM: usize = 2;
+const DEFAULT_PEER_PUSH_RATE_LIMIT: u64 = 60;
+const DEFAULT_PEER_PUSH_BURST: u32 = 10;
+const DEFAULT_PEER_CIRCUIT_FAILURES: u32 = 5;
+const DEFAULT_PEER_CIRCUIT_COOLDOWN: u64 = 60_000;
+const DEFAULT_BANNED_TOPICS: &[String] = &[];
+const DEFAULT_REPORT_LIMIT: usize = 10;
+const DEFAULT_REPORT_WINDOW: u64 = 1_000 * 60 * 60; // 1 hour
+const DEFAULT_METADATA_MAX_AGE: u64 = 60;
+const DEFAULT_MAX_CONCURRENT: usize = 1024;
+const DEFAULT_QUEUE_TIMEOUT: u64 = 1_000; // 1 second
+const DEFAULT_MIN_BURN: i64 = 0;
+const DEFAULT_MIN_BURN_PER_BYTE: i64 = 0;
 
 #[cfg(feature = "monitoring")]
 const DEFAULT_BIND_PROM: &str = "127.0.0.1:9095";
 
+/// Method of authenticating with the bitcoind JSON-RPC server.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcAuth {
+    Password,
+    Cookie,
+    None,
+}
+
+/// Chain backend the bitcoin client connects to.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcBackend {
+    Bitcoind,
+    Indexer,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct BitcoinRpc {
+    pub backend: RpcBackend,
     pub address: String,
+    pub auth: RpcAuth,
     pub username: String,
     pub password: String,
+    pub cookie_file: Option<String>,
+    pub fallback_addresses: Vec<String>,
+    pub health_check_interval: u64,
     pub zmq_address: String,
+    pub zmq_reconnect_min: u64,
+    pub zmq_reconnect_max: u64,
+    pub block_poll_interval: u64,
+}
+
+impl BitcoinRpc {
+    /// The authentication used by the bitcoin client.
+    pub fn client_auth(&self) -> Auth {
+        match self.auth {
+            RpcAuth::Password => Auth::UserPass(self.username.clone(), self.password.clone()),
+            RpcAuth::Cookie => {
+                Auth::CookieFile(self.cookie_file.clone().unwrap_or_default().into())
+            }
+            RpcAuth::None => Auth::None,
+        }
+    }
+
+    /// Construct a client for the chain backend at `address`.
+    pub fn client(&self, address: &str) -> ChainBackend {
+        match self.backend {
+            RpcBackend::Bitcoind => match address.strip_prefix(UNIX_PREFIX) {
+                Some(socket_path) => {
+                    BitcoinClientUnix::with_auth(socket_path, self.client_auth()).into()
+                }
+                None => {
+                    BitcoinClientHTTP::with_auth(address.to_string(), self.client_auth()).into()
+                }
+            },
+            RpcBackend::Indexer => IndexerClient::new(address.to_string()).into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataTier {
+    pub size: u64,
+    pub commitment: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Limits {
     pub metadata_size: u64,
+    pub metadata_tiers: Vec<MetadataTier>,
     pub payment_size: u64,
+    pub metadata_history: usize,
+    pub burn_transactions: usize,
+    pub payment_transactions: usize,
+    pub sync_messages: usize,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Payment {
     pub memo: String,
+    pub commitment_fee: u64,
+    pub min_fee_rate: u64,
+    pub reject_unconfirmed_parents: bool,
+    pub dsproof_wait: u64,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -55,21 +165,122 @@ pub struct Peering {
     pub pull_fan_size: usize,
     pub push_fan_size: usize,
     pub broadcast_delay: usize,
+    pub token_cache_size: usize,
+    pub negative_cache_ttl: u64,
+    pub negative_cache_size: usize,
+    pub sync_interval: u64,
+    pub sample_deadline: u64,
+    pub sample_timeout: u64,
+    pub sample_quorum: usize,
+    pub push_rate_limit: u64,
+    pub push_burst: u32,
+    pub circuit_failures: u32,
+    pub circuit_cooldown: u64,
     pub peers: Vec<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TopicMinBurn {
+    pub topic: String,
+    pub amount: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Moderation {
+    pub banned_topics: Vec<String>,
+    pub min_burns: Vec<TopicMinBurn>,
+    pub admin_token: Option<String>,
+    pub report_limit: usize,
+    pub report_window: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Policy {
+    pub min_burn: i64,
+    pub min_burn_per_byte: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Identity {
+    pub private_key: Option<String>,
+    pub sign_responses: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Websocket {
+    pub ping_interval: u64,
+    pub truncation_length: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Cache {
+    pub metadata_max_age: u64,
+}
+
+/// The subcommand given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    DbCompact,
+    DbStats,
+    PeersList,
+    PeersAdd(String),
+    PeersRemove(String),
+    Export(String),
+    Import(String),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self::Serve
+    }
+}
+
+impl Command {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        // NOTE: Required arguments are enforced by clap
+        match matches.subcommand() {
+            ("db", Some(db)) => match db.subcommand_name() {
+                Some("compact") => Self::DbCompact,
+                _ => Self::DbStats,
+            },
+            ("peers", Some(peers)) => match peers.subcommand() {
+                ("add", Some(add)) => Self::PeersAdd(add.value_of("url").unwrap().to_string()),
+                ("remove", Some(remove)) => {
+                    Self::PeersRemove(remove.value_of("url").unwrap().to_string())
+                }
+                _ => Self::PeersList,
+            },
+            ("export", Some(export)) => Self::Export(export.value_of("out").unwrap().to_string()),
+            ("import", Some(import)) => Self::Import(import.value_of("in").unwrap().to_string()),
+            _ => Self::Serve,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Settings {
-    pub bind: SocketAddr,
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind: Vec<BindAddr>,
     #[cfg(feature = "monitoring")]
-    pub bind_prom: SocketAddr,
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind_prom: Vec<BindAddr>,
     pub db_path: String,
     pub pubsub_db_path: String,
     pub network: String,
+    pub address_prefixes: Vec<String>,
     pub bitcoin_rpc: BitcoinRpc,
     pub limits: Limits,
     pub payments: Payment,
     pub peering: Peering,
+    pub moderation: Moderation,
+    pub policy: Policy,
+    pub websocket: Websocket,
+    pub identity: Identity,
+    pub load_shedding: LoadShedding,
+    pub cache: Cache,
+    #[serde(skip)]
+    pub command: Command,
 }
 
 impl Settings {
@@ -79,35 +290,75 @@ impl Settings {
         // Set defaults
         let yaml = load_yaml!("cli.yml");
         #[allow(deprecated)]
-        let matches = App::from_yaml(yaml)
+        let app = App::from_yaml(yaml)
             .about(crate_description!())
             .author(crate_authors!("\n"))
-            .version(crate_version!())
-            .get_matches();
-        let home_dir = match dirs::home_dir() {
-            Some(some) => some,
-            None => return Err(ConfigError::Message("no home directory".to_string())),
-        };
+            .version(crate_version!());
+        // The test runner's arguments are not ours, tests run with the defaults
+        #[cfg(not(test))]
+        let matches = app.get_matches();
+        #[cfg(test)]
+        let matches = app.get_matches_from(vec![crate_name!()]);
+        let data_dir = home_dir()?.join(FOLDER_DIR);
         s.set_default("bind", DEFAULT_BIND)?;
         #[cfg(feature = "monitoring")]
         s.set_default("bind_prom", DEFAULT_BIND_PROM)?;
         s.set_default("network", DEFAULT_NETWORK)?;
-        let mut default_db = home_dir.clone();
-        default_db.push(format!("{}/db", FOLDER_DIR));
+        s.set_default("address_prefixes", DEFAULT_ADDRESS_PREFIXES.to_vec())?;
+        let default_db = data_dir.join("db");
         s.set_default("db_path", default_db.to_str())?;
-        let mut default_pubsub_db = home_dir.clone();
-        default_pubsub_db.push(format!("{}/pubsub_db", FOLDER_DIR));
+        let default_pubsub_db = data_dir.join("pubsub_db");
         s.set_default("pubsub_db_path", default_pubsub_db.to_str())?;
 
+        s.set_default("bitcoin_rpc.backend", DEFAULT_RPC_BACKEND)?;
         s.set_default("bitcoin_rpc.address", DEFAULT_RPC_ADDR)?;
+        s.set_default("bitcoin_rpc.auth", DEFAULT_RPC_AUTH)?;
         s.set_default("bitcoin_rpc.username", DEFAULT_RPC_USER)?;
         s.set_default("bitcoin_rpc.password", DEFAULT_RPC_PASSWORD)?;
+        s.set_default("bitcoin_rpc.fallback_addresses", Vec::<String>::new())?;
+        s.set_default(
+            "bitcoin_rpc.health_check_interval",
+            DEFAULT_RPC_HEALTH_CHECK_INTERVAL as i64,
+        )?;
         s.set_default("bitcoin_rpc.zmq_address", DEFAULT_ZMQ_ADDRESS)?;
+        s.set_default(
+            "bitcoin_rpc.zmq_reconnect_min",
+            DEFAULT_ZMQ_RECONNECT_MIN as i64,
+        )?;
+        s.set_default(
+            "bitcoin_rpc.zmq_reconnect_max",
+            DEFAULT_ZMQ_RECONNECT_MAX as i64,
+        )?;
+        s.set_default(
+            "bitcoin_rpc.block_poll_interval",
+            DEFAULT_BLOCK_POLL_INTERVAL as i64,
+        )?;
 
         s.set_default("limits.metadata_size", DEFAULT_METADATA_LIMIT as i64)?;
+        s.set_default("limits.metadata_tiers", Vec::<String>::new())?;
         s.set_default("limits.payment_size", DEFAULT_PAYMENT_LIMIT as i64)?;
+        s.set_default(
+            "limits.metadata_history",
+            DEFAULT_METADATA_HISTORY_LIMIT as i64,
+        )?;
+        s.set_default(
+            "limits.burn_transactions",
+            DEFAULT_BURN_TRANSACTIONS_LIMIT as i64,
+        )?;
+        s.set_default(
+            "limits.payment_transactions",
+            DEFAULT_PAYMENT_TRANSACTIONS_LIMIT as i64,
+        )?;
+        s.set_default("limits.sync_messages", DEFAULT_SYNC_MESSAGES_LIMIT as i64)?;
 
         s.set_default("payments.memo", DEFAULT_MEMO)?;
+        s.set_default("payments.commitment_fee", DEFAULT_COMMITMENT_FEE as i64)?;
+        s.set_default("payments.min_fee_rate", DEFAULT_MIN_FEE_RATE as i64)?;
+        s.set_default(
+            "payments.reject_unconfirmed_parents",
+            DEFAULT_REJECT_UNCONFIRMED_PARENTS,
+        )?;
+        s.set_default("payments.dsproof_wait", DEFAULT_DSPROOF_WAIT as i64)?;
 
         s.set_default("peering.enabled", DEFAULT_PEERING)?;
         s.set_default("peering.max_peers", DEFAULT_MAX_PEERS as i64)?;
@@ -120,28 +371,68 @@ impl Settings {
             "peering.broadcast_delay",
             DEFAULT_PEER_BROADCAST_DELAY as i64,
         )?;
+        s.set_default("peering.token_cache_size", DEFAULT_TOKEN_CACHE_SIZE as i64)?;
+        s.set_default(
+            "peering.negative_cache_ttl",
+            DEFAULT_NEGATIVE_CACHE_TTL as i64,
+        )?;
+        s.set_default(
+            "peering.negative_cache_size",
+            DEFAULT_NEGATIVE_CACHE_SIZE as i64,
+        )?;
+        s.set_default("peering.sync_interval", DEFAULT_PEER_SYNC_INTERVAL as i64)?;
+        s.set_default(
+            "peering.sample_deadline",
+            DEFAULT_PEER_SAMPLE_DEADLINE as i64,
+        )?;
+        s.set_default("peering.sample_timeout", DEFAULT_PEER_SAMPLE_TIMEOUT as i64)?;
+        s.set_default("peering.sample_quorum", DEFAULT_PEER_SAMPLE_QUORUM as i64)?;
+        s.set_default(
+            "peering.push_rate_limit",
+            DEFAULT_PEER_PUSH_RATE_LIMIT as i64,
+        )?;
+        s.set_default("peering.push_burst", DEFAULT_PEER_PUSH_BURST as i64)?;
+        s.set_default(
+            "peering.circuit_failures",
+            DEFAULT_PEER_CIRCUIT_FAILURES as i64,
+        )?;
+        s.set_default(
+            "peering.circuit_cooldown",
+            DEFAULT_PEER_CIRCUIT_COOLDOWN as i64,
+        )?;
 
+        s.set_default("moderation.banned_topics", DEFAULT_BANNED_TOPICS.to_vec())?;
+        s.set_default("moderation.min_burns", Vec::<String>::new())?;
+        s.set_default("moderation.report_limit", DEFAULT_REPORT_LIMIT as i64)?;
+        s.set_default("moderation.report_window", DEFAULT_REPORT_WINDOW as i64)?;
+
+        s.set_default("policy.min_burn", DEFAULT_MIN_BURN)?;
+        s.set_default("policy.min_burn_per_byte", DEFAULT_MIN_BURN_PER_BYTE)?;
+
+        s.set_default("identity.sign_responses", false)?;
         s.set_default("websocket.ping_interval", DEFAULT_PING_INTERVAL as i64)?;
         s.set_default(
             "websocket.truncation_length",
             DEFAULT_TRUNCATION_LENGTH as i64,
         )?;
+        s.set_default("cache.metadata_max_age", DEFAULT_METADATA_MAX_AGE as i64)?;
+        s.set_default(
+            "load_shedding.max_concurrent",
+            DEFAULT_MAX_CONCURRENT as i64,
+        )?;
+        s.set_default("load_shedding.queue_timeout", DEFAULT_QUEUE_TIMEOUT as i64)?;
 
         // Load config from file
-        let mut default_config = home_dir;
-        default_config.push(format!("{}/config", FOLDER_DIR));
-        let default_config_str = default_config.to_str().unwrap();
-        let config_path = matches.value_of("config").unwrap_or(default_config_str);
-        s.merge(File::with_name(config_path).required(false))?;
+        merge_config_file(&mut s, &data_dir, matches.value_of("config"))?;
 
-        // Set bind address from cmd line
-        if let Some(bind) = matches.value_of("bind") {
-            s.set("bind", bind)?;
+        // Set bind addresses from cmd line
+        if let Some(bind) = matches.values_of("bind") {
+            s.set("bind", bind.collect::<Vec<_>>())?;
         }
 
-        // Set bind address from cmd line
-        if let Some(bind_prom) = matches.value_of("bind-prom") {
-            s.set("bind_prom", bind_prom)?;
+        // Set bind addresses from cmd line
+        if let Some(bind_prom) = matches.values_of("bind-prom") {
+            s.set("bind_prom", bind_prom.collect::<Vec<_>>())?;
         }
 
         // Set the bitcoin network
@@ -179,6 +470,47 @@ impl Settings {
             s.set("bitcoin_rpc.zmq_address", rpc_password)?;
         }
 
-        s.try_into()
+        let mut settings: Self = s.try_into()?;
+        if settings.bind.is_empty() {
+            return Err(ConfigError::Message(
+                "at least one bind address is required".to_string(),
+            ));
+        }
+        #[cfg(feature = "monitoring")]
+        {
+            if settings.bind_prom.is_empty() {
+                return Err(ConfigError::Message(
+                    "at least one prometheus bind address is required".to_string(),
+                ));
+            }
+        }
+        if settings.bitcoin_rpc.auth == RpcAuth::Cookie
+            && settings.bitcoin_rpc.cookie_file.is_none()
+        {
+            return Err(ConfigError::Message(
+                "cookie authentication requires a cookie file".to_string(),
+            ));
+        }
+        if settings.limits.sync_messages == 0 {
+            return Err(ConfigError::Message(
+                "the sync page size must be nonzero".to_string(),
+            ));
+        }
+        // Topic prefixes are matched against normalized topics
+        for topic in settings.moderation.banned_topics.iter_mut().chain(
+            settings
+                .moderation
+                .min_burns
+                .iter_mut()
+                .map(|min_burn| &mut min_burn.topic),
+        ) {
+            *topic = Topic::parse(topic)
+                .map_err(|err| {
+                    ConfigError::Message(format!("invalid topic prefix {:?}: {}", topic, err))
+                })?
+                .to_string();
+        }
+        settings.command = Command::from_matches(&matches);
+        Ok(settings)
     }
 }
@@ -33,6 +33,19 @@ pub struct ParsedAuthWrapper {
     pub payload: Vec<u8>,
     /// The SHA256 digest of the payload.
     pub payload_digest: [u8; 32],
+    /// Additional signatures covering the payload.
+    pub endorsements: Vec<ParsedEndorsement>,
+}
+
+/// Represents an [`Endorsement`] post-parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedEndorsement {
+    /// The public key associated with the signature.
+    pub public_key: PublicKey,
+    /// The signature by public key covering the payload.
+    pub signature: Signature,
+    /// The signature scheme used for signing.
+    pub scheme: SignatureScheme,
 }
 
 /// Error associated with validation and parsing of the [`AuthWrapper`].
@@ -56,9 +69,47 @@ pub enum ParseError {
     /// The `payload_digest` was not 32 bytes long.
     #[error("unexpected length digest")]
     UnexpectedLengthDigest,
+    /// The public key of the endorsement at the given index was invalid.
+    #[error("endorsement {0}: {1}")]
+    EndorsementPublicKey(usize, SecpError),
+    /// The signature of the endorsement at the given index was an invalid format.
+    #[error("endorsement {0}: {1}")]
+    EndorsementSignature(usize, SecpError),
+    /// The signature scheme of the endorsement at the given index is unsupported.
+    #[error("endorsement {0}: unsupported signature scheme")]
+    UnsupportedEndorsementScheme(usize),
+}
+
+impl Endorsement {
+    /// Parse the [`Endorsement`] at the given index to construct a [`ParsedEndorsement`].
+    fn parse(self, index: usize) -> Result<ParsedEndorsement, ParseError> {
+        let public_key = PublicKey::from_slice(&self.public_key)
+            .map_err(|err| ParseError::EndorsementPublicKey(index, err))?;
+        let scheme = SignatureScheme::from_i32(self.scheme)
+            .ok_or(ParseError::UnsupportedEndorsementScheme(index))?;
+        let signature = Signature::from_compact(&self.signature)
+            .map_err(|err| ParseError::EndorsementSignature(index, err))?;
+        Ok(ParsedEndorsement {
+            public_key,
+            signature,
+            scheme,
+        })
+    }
 }
 
 impl AuthWrapper {
+    /// Returns the payload digest, calculating it from the payload if it is absent.
+    ///
+    /// This does not check that a given digest matches the payload, see [`AuthWrapper::parse`].
+    #[inline]
+    pub fn digest(&self) -> Vec<u8> {
+        if self.payload_digest.is_empty() {
+            digest(&SHA256, &self.payload).as_ref().to_vec()
+        } else {
+            self.payload_digest.clone()
+        }
+    }
+
     /// Parse the [`AuthWrapper`] to construct a [`ParsedAuthWrapper`].
     ///
     /// The involves deserialization of both public keys, calculation of the payload digest, and coercion of byte fields
@@ -96,12 +147,21 @@ impl AuthWrapper {
             _ => return Err(ParseError::UnexpectedLengthDigest),
         };
 
+        // Parse endorsements
+        let endorsements = self
+            .endorsements
+            .into_iter()
+            .enumerate()
+            .map(|(index, endorsement)| endorsement.parse(index))
+            .collect::<Result<_, _>>()?;
+
         Ok(ParsedAuthWrapper {
             public_key,
             scheme,
             signature,
             payload_digest,
             payload: self.payload,
+            endorsements,
         })
     }
 }
@@ -115,21 +175,186 @@ pub enum VerifyError {
     /// The signature scheme provided is unsupported.
     #[error("unsupported signature scheme")]
     UnsupportedScheme,
+    /// The signature of the endorsement at the given index failed verification.
+    #[error("endorsement {0}: {1}")]
+    InvalidEndorsement(usize, SecpError),
+    /// The signature scheme of the endorsement at the given index is unsupported.
+    #[error("endorsement {0}: unsupported signature scheme")]
+    UnsupportedEndorsementScheme(usize),
+}
+
+/// Verify a signature over a payload digest.
+fn verify_signature(
+    scheme: SignatureScheme,
+    payload_digest: &[u8; 32],
+    signature: &Signature,
+    public_key: &PublicKey,
+) -> Result<(), Option<SecpError>> {
+    if scheme == SignatureScheme::Schnorr {
+        // TODO: Support Schnorr
+        return Err(None);
+    }
+    let msg = Message::from_slice(payload_digest.as_ref()).unwrap(); // This is safe
+    let secp = Secp256k1::verification_only();
+    secp.verify(&msg, signature, public_key).map_err(Some)
 }
 
 impl ParsedAuthWrapper {
-    /// Verify the signature on [`ParsedAuthWrapper`].
+    /// Verify the signature on [`ParsedAuthWrapper`], and the signatures of all its endorsements.
     #[inline]
     pub fn verify(&self) -> Result<(), VerifyError> {
-        if self.scheme == SignatureScheme::Schnorr {
-            // TODO: Support Schnorr
-            return Err(VerifyError::UnsupportedScheme);
-        }
         // Verify signature on the message
-        let msg = Message::from_slice(self.payload_digest.as_ref()).unwrap(); // This is safe
-        let secp = Secp256k1::verification_only();
-        secp.verify(&msg, &self.signature, &self.public_key)
-            .map_err(VerifyError::InvalidSignature)?;
+        verify_signature(
+            self.scheme,
+            &self.payload_digest,
+            &self.signature,
+            &self.public_key,
+        )
+        .map_err(|err| match err {
+            Some(err) => VerifyError::InvalidSignature(err),
+            None => VerifyError::UnsupportedScheme,
+        })?;
+
+        // Verify endorsements
+        for (index, endorsement) in self.endorsements.iter().enumerate() {
+            verify_signature(
+                endorsement.scheme,
+                &self.payload_digest,
+                &endorsement.signature,
+                &endorsement.public_key,
+            )
+            .map_err(|err| match err {
+                Some(err) => VerifyError::InvalidEndorsement(index, err),
+                None => VerifyError::UnsupportedEndorsementScheme(index),
+            })?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use prost::Message as _;
+    use secp256k1::SecretKey;
+
+    use super::*;
+
+    /// Sign the digest of a payload, returning the serialized public key and compact signature.
+    fn sign(private_key: [u8; 32], payload: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let secp = Secp256k1::signing_only();
+        let secret_key = SecretKey::from_slice(&private_key).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let payload_digest = digest(&SHA256, payload);
+        let msg = Message::from_slice(payload_digest.as_ref()).unwrap();
+        let signature = secp.sign(&msg, &secret_key);
+        (
+            public_key.serialize().to_vec(),
+            signature.serialize_compact().to_vec(),
+        )
+    }
+
+    /// Construct an `AuthWrapper` carrying a single endorsement.
+    fn endorsed_wrapper() -> AuthWrapper {
+        let payload = b"payload".to_vec();
+        let (public_key, signature) = sign([1; 32], &payload);
+        let (endorser_public_key, endorser_signature) = sign([2; 32], &payload);
+        AuthWrapper {
+            public_key,
+            signature,
+            scheme: SignatureScheme::Ecdsa as i32,
+            payload,
+            endorsements: vec![Endorsement {
+                public_key: endorser_public_key,
+                signature: endorser_signature,
+                scheme: SignatureScheme::Ecdsa as i32,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_endorsement() {
+        let auth_wrapper = endorsed_wrapper();
+        let endorser_public_key = auth_wrapper.endorsements[0].public_key.clone();
+
+        // Endorsements survive encoding
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        let parsed = AuthWrapper::decode(raw_auth_wrapper.as_slice())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(parsed.endorsements.len(), 1);
+        assert_eq!(
+            parsed.endorsements[0].public_key.serialize().to_vec(),
+            endorser_public_key
+        );
+        parsed.verify().unwrap();
+    }
+
+    #[test]
+    fn bad_endorsement_signature() {
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].signature = sign([2; 32], b"other payload").1;
+        assert!(matches!(
+            auth_wrapper.parse().unwrap().verify(),
+            Err(VerifyError::InvalidEndorsement(0, _))
+        ));
+    }
+
+    #[test]
+    fn wrong_endorser_key() {
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].public_key = sign([3; 32], b"").0;
+        assert!(matches!(
+            auth_wrapper.parse().unwrap().verify(),
+            Err(VerifyError::InvalidEndorsement(0, _))
+        ));
+
+        // The signer's own signature does not endorse
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].public_key = auth_wrapper.public_key.clone();
+        assert!(matches!(
+            auth_wrapper.parse().unwrap().verify(),
+            Err(VerifyError::InvalidEndorsement(0, _))
+        ));
+    }
+
+    #[test]
+    fn malformed_endorsement() {
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].public_key = vec![0; 33];
+        assert!(matches!(
+            auth_wrapper.parse(),
+            Err(ParseError::EndorsementPublicKey(0, _))
+        ));
+
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].signature.truncate(32);
+        assert!(matches!(
+            auth_wrapper.parse(),
+            Err(ParseError::EndorsementSignature(0, _))
+        ));
+
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].scheme = 7;
+        assert_eq!(
+            auth_wrapper.parse(),
+            Err(ParseError::UnsupportedEndorsementScheme(0))
+        );
+
+        let mut auth_wrapper = endorsed_wrapper();
+        auth_wrapper.endorsements[0].scheme = SignatureScheme::Schnorr as i32;
+        assert_eq!(
+            auth_wrapper.parse().unwrap().verify(),
+            Err(VerifyError::UnsupportedEndorsementScheme(0))
+        );
+
+        // Truncated encodings are not decoded
+        let auth_wrapper = endorsed_wrapper();
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        raw_auth_wrapper.pop();
+        assert!(AuthWrapper::decode(raw_auth_wrapper.as_slice()).is_err());
+    }
+}
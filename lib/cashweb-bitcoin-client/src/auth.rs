@@ -0,0 +1,41 @@
+//! This module contains [`Auth`], the methods of authenticating with bitcoind's JSON-RPC server.
+
+use std::{fs, io, path::PathBuf};
+
+/// Method of authenticating with bitcoind's JSON-RPC server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Auth {
+    /// No authentication, for example when a regtest node is fronted by a proxy.
+    None,
+    /// A static username and password, as given by `rpcuser` and `rpcpassword`.
+    UserPass(String, String),
+    /// The cookie file bitcoind writes on startup. The file is re-read when bitcoind restarts.
+    CookieFile(PathBuf),
+}
+
+/// Parse the contents of a cookie file into a username and password.
+fn parse_cookie(cookie: &str) -> Option<(String, String)> {
+    let index = cookie.find(':')?;
+    Some((cookie[..index].to_string(), cookie[index + 1..].to_string()))
+}
+
+impl Auth {
+    /// Get the username and password, reading the cookie file if necessary.
+    pub fn credentials(&self) -> Result<Option<(String, String)>, io::Error> {
+        match self {
+            Self::None => Ok(None),
+            Self::UserPass(username, password) => Ok(Some((username.clone(), password.clone()))),
+            Self::CookieFile(path) => {
+                let cookie = fs::read_to_string(path)?;
+                parse_cookie(cookie.trim()).map(Some).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "malformed cookie file")
+                })
+            }
+        }
+    }
+
+    /// Checks whether the credentials can change while the client is running.
+    pub fn is_refreshable(&self) -> bool {
+        matches!(self, Self::CookieFile(_))
+    }
+}
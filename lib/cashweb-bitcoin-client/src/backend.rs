@@ -0,0 +1,150 @@
+//! This module contains [`ChainBackend`], allowing the chain backend to be chosen at runtime.
+
+use async_trait::async_trait;
+
+use crate::{
+    BitcoinClient, BitcoinClientHTTP, BitcoinClientUnix, IndexerClient, MempoolEntry, NodeError,
+    Unspent,
+};
+
+/// A [`BitcoinClient`] backed by either bitcoind's JSON-RPC or an indexer.
+#[derive(Clone, Debug)]
+pub enum ChainBackend {
+    /// bitcoind's JSON-RPC over HTTP.
+    Bitcoind(BitcoinClientHTTP),
+    /// bitcoind's JSON-RPC over a unix domain socket.
+    BitcoindUnix(BitcoinClientUnix),
+    /// An Esplora-compatible indexer.
+    Indexer(IndexerClient),
+    /// A mock client, for tests.
+    #[cfg(feature = "test-util")]
+    Mock(crate::mock::MockBitcoinClient),
+}
+
+impl From<BitcoinClientHTTP> for ChainBackend {
+    fn from(client: BitcoinClientHTTP) -> Self {
+        Self::Bitcoind(client)
+    }
+}
+
+impl From<BitcoinClientUnix> for ChainBackend {
+    fn from(client: BitcoinClientUnix) -> Self {
+        Self::BitcoindUnix(client)
+    }
+}
+
+impl From<IndexerClient> for ChainBackend {
+    fn from(client: IndexerClient) -> Self {
+        Self::Indexer(client)
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl From<crate::mock::MockBitcoinClient> for ChainBackend {
+    fn from(client: crate::mock::MockBitcoinClient) -> Self {
+        Self::Mock(client)
+    }
+}
+
+#[async_trait]
+impl BitcoinClient for ChainBackend {
+    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.send_tx(raw_tx).await,
+            Self::BitcoindUnix(client) => client.send_tx(raw_tx).await,
+            Self::Indexer(client) => client.send_tx(raw_tx).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.send_tx(raw_tx).await,
+        }
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.get_new_addr().await,
+            Self::BitcoindUnix(client) => client.get_new_addr().await,
+            Self::Indexer(client) => client.get_new_addr().await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.get_new_addr().await,
+        }
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.get_raw_transaction(tx_id).await,
+            Self::BitcoindUnix(client) => client.get_raw_transaction(tx_id).await,
+            Self::Indexer(client) => client.get_raw_transaction(tx_id).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.get_raw_transaction(tx_id).await,
+        }
+    }
+
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.estimate_fee(conf_target).await,
+            Self::BitcoindUnix(client) => client.estimate_fee(conf_target).await,
+            Self::Indexer(client) => client.estimate_fee(conf_target).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.estimate_fee(conf_target).await,
+        }
+    }
+
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.get_raw_mempool().await,
+            Self::BitcoindUnix(client) => client.get_raw_mempool().await,
+            Self::Indexer(client) => client.get_raw_mempool().await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.get_raw_mempool().await,
+        }
+    }
+
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.get_mempool_entry(tx_id).await,
+            Self::BitcoindUnix(client) => client.get_mempool_entry(tx_id).await,
+            Self::Indexer(client) => client.get_mempool_entry(tx_id).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.get_mempool_entry(tx_id).await,
+        }
+    }
+
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.get_ds_proof(tx_id).await,
+            Self::BitcoindUnix(client) => client.get_ds_proof(tx_id).await,
+            Self::Indexer(client) => client.get_ds_proof(tx_id).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.get_ds_proof(tx_id).await,
+        }
+    }
+
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.get_block_count().await,
+            Self::BitcoindUnix(client) => client.get_block_count().await,
+            Self::Indexer(client) => client.get_block_count().await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.get_block_count().await,
+        }
+    }
+
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.list_unspent(min_conf).await,
+            Self::BitcoindUnix(client) => client.list_unspent(min_conf).await,
+            Self::Indexer(client) => client.list_unspent(min_conf).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.list_unspent(min_conf).await,
+        }
+    }
+
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        match self {
+            Self::Bitcoind(client) => client.sign_raw_transaction(raw_tx).await,
+            Self::BitcoindUnix(client) => client.sign_raw_transaction(raw_tx).await,
+            Self::Indexer(client) => client.sign_raw_transaction(raw_tx).await,
+            #[cfg(feature = "test-util")]
+            Self::Mock(client) => client.sign_raw_transaction(raw_tx).await,
+        }
+    }
+}
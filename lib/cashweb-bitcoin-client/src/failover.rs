@@ -0,0 +1,155 @@
+//! This module contains the [`FailoverClient`], a [`BitcoinClient`] spreading calls over several
+//! bitcoind nodes.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+
+use crate::{BitcoinClient, MempoolEntry, NodeError, Unspent};
+
+/// A [`BitcoinClient`] routing calls to the first healthy of several clients.
+///
+/// A client is marked unhealthy when a call to it fails to connect, and the call is retried on the
+/// next client. Unhealthy clients are only tried once all healthy clients have failed, and are
+/// marked healthy again by a successful call or [`FailoverClient::health_check`].
+///
+/// Clones share the same health state.
+#[derive(Clone, Debug)]
+pub struct FailoverClient<C> {
+    clients: Arc<Vec<C>>,
+    healthy: Arc<Vec<AtomicBool>>,
+}
+
+impl<C> FailoverClient<C>
+where
+    C: BitcoinClient + Clone + Send + Sync,
+{
+    /// Create a new [`FailoverClient`], preferring clients in the order given.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no clients are given.
+    pub fn new(clients: Vec<C>) -> Self {
+        assert!(!clients.is_empty(), "no bitcoin clients given");
+        let healthy = clients.iter().map(|_| AtomicBool::new(true)).collect();
+        Self {
+            clients: Arc::new(clients),
+            healthy: Arc::new(healthy),
+        }
+    }
+
+    /// Get the underlying clients.
+    pub fn clients(&self) -> &[C] {
+        &self.clients
+    }
+
+    /// Checks whether the client at `index` is considered healthy.
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.healthy[index].load(Ordering::Relaxed)
+    }
+
+    /// Check the health of every client by requesting its block count.
+    ///
+    /// Returns the number of healthy clients.
+    pub async fn health_check(&self) -> usize {
+        let mut n_healthy = 0;
+        for (client, healthy) in self.clients.iter().zip(self.healthy.iter()) {
+            let is_healthy = client.get_block_count().await.is_ok();
+            healthy.store(is_healthy, Ordering::Relaxed);
+            if is_healthy {
+                n_healthy += 1;
+            }
+        }
+        n_healthy
+    }
+
+    /// Make a call, failing over to the next client while calls fail to connect.
+    ///
+    /// Errors returned by bitcoind itself are returned immediately, as another node would likely
+    /// respond the same way.
+    async fn call<T, F, Fut>(&self, f: F) -> Result<T, NodeError>
+    where
+        F: Fn(C) -> Fut,
+        Fut: Future<Output = Result<T, NodeError>>,
+    {
+        let (healthy, unhealthy): (Vec<usize>, Vec<usize>) =
+            (0..self.clients.len()).partition(|index| self.is_healthy(*index));
+
+        let mut last_err = None;
+        for index in healthy.into_iter().chain(unhealthy) {
+            match f(self.clients[index].clone()).await {
+                Err(err @ NodeError::RpcConnectError(_)) => {
+                    self.healthy[index].store(false, Ordering::Relaxed);
+                    last_err = Some(err);
+                }
+                result => {
+                    self.healthy[index].store(true, Ordering::Relaxed);
+                    return result;
+                }
+            }
+        }
+        Err(last_err.unwrap()) // This is safe
+    }
+}
+
+#[async_trait]
+impl<C> BitcoinClient for FailoverClient<C>
+where
+    C: BitcoinClient + Clone + Send + Sync,
+{
+    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
+        self.call(|client| async move { client.send_tx(raw_tx).await })
+            .await
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        self.call(|client| async move { client.get_new_addr().await })
+            .await
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.call(|client| async move { client.get_raw_transaction(tx_id).await })
+            .await
+    }
+
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        self.call(|client| async move { client.estimate_fee(conf_target).await })
+            .await
+    }
+
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        self.call(|client| async move { client.get_raw_mempool().await })
+            .await
+    }
+
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        self.call(|client| async move { client.get_mempool_entry(tx_id).await })
+            .await
+    }
+
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        self.call(|client| async move { client.get_ds_proof(tx_id).await })
+            .await
+    }
+
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        self.call(|client| async move { client.get_block_count().await })
+            .await
+    }
+
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        self.call(|client| async move { client.list_unspent(min_conf).await })
+            .await
+    }
+
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.call(|client| async move { client.sign_raw_transaction(raw_tx).await })
+            .await
+    }
+}
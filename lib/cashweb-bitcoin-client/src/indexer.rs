@@ -0,0 +1,228 @@
+//! This module contains the [`IndexerClient`], a [`BitcoinClient`] backed by the REST API of an
+//! Esplora-compatible indexer rather than bitcoind's JSON-RPC.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use hyper::{body::Bytes, client::HttpConnector, Body, Method, Request, StatusCode};
+use hyper_tls::HttpsConnector;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{BitcoinClient, HttpsClient, MempoolEntry, MempoolFees, NodeError, Unspent};
+
+const SATS_PER_COIN: f64 = 100_000_000.;
+
+/// The confirmation status of a transaction.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TxStatus {
+    /// Whether the transaction is included in a block.
+    pub confirmed: bool,
+    /// Height of the block including the transaction, if confirmed.
+    #[serde(default)]
+    pub block_height: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct IndexerTx {
+    size: u64,
+    fee: u64,
+    status: TxStatus,
+}
+
+/// A [`BitcoinClient`] backed by an Esplora-compatible indexer, for nodes without an RPC wallet.
+///
+/// Indexers do not hold wallets, so [`BitcoinClient::get_new_addr`] is unsupported.
+#[derive(Clone, Debug)]
+pub struct IndexerClient {
+    client: HttpsClient,
+    base_url: String,
+}
+
+impl IndexerClient {
+    /// Create a new [`IndexerClient`] from the base URL of the indexer API, for example
+    /// `https://blockstream.info/api`.
+    pub fn new(base_url: String) -> Self {
+        let client = hyper::Client::builder().build(HttpsConnector::<HttpConnector>::new());
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn request(&self, method: Method, path: &str, body: Body) -> Result<Bytes, NodeError> {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base_url, path))
+            .body(body)
+            .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        let message = String::from_utf8_lossy(&body).into_owned();
+        Err(match status {
+            StatusCode::NOT_FOUND => NodeError::NotFound(message),
+            status if status.is_server_error() => NodeError::RpcConnectError(message),
+            _ => rejection_error(message),
+        })
+    }
+
+    async fn get_text(&self, path: &str) -> Result<String, NodeError> {
+        let body = self.request(Method::GET, path, Body::empty()).await?;
+        Ok(String::from_utf8_lossy(&body).trim().to_string())
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, NodeError> {
+        let body = self.request(Method::GET, path, Body::empty()).await?;
+        serde_json::from_slice(&body).map_err(|err| NodeError::Indexer(err.to_string()))
+    }
+
+    /// Get the confirmation status of a transaction by txid.
+    pub async fn get_tx_status(&self, tx_id: &[u8]) -> Result<TxStatus, NodeError> {
+        self.get_json(&format!("/tx/{}/status", hex::encode(tx_id)))
+            .await
+    }
+
+    /// Get the number of confirmations of a transaction by txid, zero if it is unconfirmed.
+    pub async fn get_confirmations(&self, tx_id: &[u8]) -> Result<u64, NodeError> {
+        let status = self.get_tx_status(tx_id).await?;
+        match status.block_height {
+            Some(block_height) if status.confirmed => {
+                let tip_height = self.get_block_count().await?;
+                Ok(tip_height.saturating_sub(block_height) + 1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+/// Classify the reason an indexer gave for rejecting a request, typically relayed from its node.
+fn rejection_error(message: String) -> NodeError {
+    if message.contains("already") {
+        NodeError::TxAlreadyKnown(message)
+    } else if message.contains("missing") {
+        NodeError::MissingInputs(message)
+    } else {
+        NodeError::TxRejectedByPolicy(message)
+    }
+}
+
+/// Select the fee rate, in satoshis per byte, estimated for the lowest target of at least
+/// `conf_target` blocks, falling back to the highest target estimated.
+fn select_fee_rate(estimates: &HashMap<String, f64>, conf_target: u32) -> Option<f64> {
+    let mut estimates: Vec<(u32, f64)> = estimates
+        .iter()
+        .filter_map(|(target, fee_rate)| Some((target.parse().ok()?, *fee_rate)))
+        .collect();
+    estimates.sort_by_key(|(target, _)| *target);
+    estimates
+        .iter()
+        .find(|(target, _)| *target >= conf_target)
+        .or_else(|| estimates.last())
+        .map(|(_, fee_rate)| *fee_rate)
+}
+
+#[async_trait]
+impl BitcoinClient for IndexerClient {
+    /// Posts to the `/tx` endpoint.
+    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
+        let body = self
+            .request(Method::POST, "/tx", Body::from(hex::encode(raw_tx)))
+            .await?;
+        Ok(String::from_utf8_lossy(&body).trim().to_string())
+    }
+
+    /// Unsupported, indexers do not hold wallets.
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        Err(NodeError::Unsupported("getnewaddress".to_string()))
+    }
+
+    /// Gets the `/tx/<txid>/hex` endpoint.
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        let tx_hex = self
+            .get_text(&format!("/tx/{}/hex", hex::encode(tx_id)))
+            .await?;
+        hex::decode(tx_hex).map_err(Into::into)
+    }
+
+    /// Gets the `/fee-estimates` endpoint, converting to coins per kilobyte.
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        let estimates: HashMap<String, f64> = self.get_json("/fee-estimates").await?;
+        let fee_rate = select_fee_rate(&estimates, conf_target).ok_or(NodeError::EmptyResponse)?;
+        Ok(fee_rate * 1_000. / SATS_PER_COIN)
+    }
+
+    /// Gets the `/mempool/txids` endpoint.
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        let tx_ids: Vec<String> = self.get_json("/mempool/txids").await?;
+        tx_ids
+            .into_iter()
+            .map(|tx_id| hex::decode(tx_id).map_err(Into::into))
+            .collect()
+    }
+
+    /// Gets the `/tx/<txid>` endpoint.
+    ///
+    /// Indexers do not record when a transaction entered the mempool, so the current time and
+    /// chain height are given instead, and dependencies are left empty.
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        let tx: IndexerTx = self
+            .get_json(&format!("/tx/{}", hex::encode(tx_id)))
+            .await?;
+        if tx.status.confirmed {
+            return Err(NodeError::NotFound(
+                "transaction not in mempool".to_string(),
+            ));
+        }
+        Ok(MempoolEntry {
+            size: tx.size,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+            height: self.get_block_count().await?,
+            depends: Vec::new(),
+            spentby: Vec::new(),
+            fees: MempoolFees {
+                base: tx.fee as f64 / SATS_PER_COIN,
+            },
+        })
+    }
+
+    /// Unsupported, indexers do not relay double-spend proofs.
+    async fn get_ds_proof(&self, _tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        Err(NodeError::Unsupported("getdsproof".to_string()))
+    }
+
+    /// Gets the `/blocks/tip/height` endpoint.
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        self.get_text("/blocks/tip/height")
+            .await?
+            .parse()
+            .map_err(|_| NodeError::Indexer("malformed block height".to_string()))
+    }
+
+    /// Unsupported, indexers do not hold wallets.
+    async fn list_unspent(&self, _min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        Err(NodeError::Unsupported("listunspent".to_string()))
+    }
+
+    /// Unsupported, indexers do not hold wallets.
+    async fn sign_raw_transaction(&self, _raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        Err(NodeError::Unsupported(
+            "signrawtransactionwithwallet".to_string(),
+        ))
+    }
+}
@@ -6,15 +6,39 @@
 )]
 
 //! `cashweb-bitcoin-client` is a library providing a [`BitcoinClient`] with
-//! basic asynchronous methods for interacting with bitcoind.
+//! basic asynchronous methods for interacting with bitcoind, or with an indexer via
+//! [`IndexerClient`].
+//!
+//! Enabling the `test-util` feature exposes a [`mock::MockBitcoinClient`] for use in tests.
+
+pub mod auth;
+pub mod backend;
+pub mod failover;
+pub mod indexer;
+#[cfg(feature = "test-util")]
+pub mod mock;
+
+pub use auth::Auth;
+pub use backend::ChainBackend;
+pub use failover::FailoverClient;
+pub use indexer::{IndexerClient, TxStatus};
+
+use std::{
+    future::Future,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
 use async_trait::async_trait;
 use hex::FromHexError;
 use hyper::client::{connect::Connect, HttpConnector};
 use hyper_tls::HttpsConnector;
+use hyperlocal::UnixConnector;
 use json_rpc::{
     clients::http::Client as JsonClient,
     prelude::{JsonError, RequestFactory, RpcError},
 };
+use serde::Deserialize;
 use serde_json::Value;
 use thiserror::Error;
 
@@ -30,7 +54,22 @@ pub enum NodeError {
     /// Error connecting to bitcoind.
     #[error("Connection error: {0}")]
     RpcConnectError(String),
-    /// bitcoind responded with an JSON-RPC error.
+    /// The transaction is already in the mempool or chain.
+    #[error("transaction already known: {0}")]
+    TxAlreadyKnown(String),
+    /// The transaction spends missing or already spent outputs.
+    #[error("missing inputs: {0}")]
+    MissingInputs(String),
+    /// The transaction was rejected by the node's mempool policy.
+    #[error("transaction rejected: {0}")]
+    TxRejectedByPolicy(String),
+    /// No wallet is loaded, or the requested wallet was not found.
+    #[error("wallet not loaded: {0}")]
+    WalletNotLoaded(String),
+    /// The requested transaction, address or key was not found.
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// bitcoind responded with an unrecognized JSON-RPC error.
     #[error("{0:?}")]
     Rpc(RpcError),
     /// Failed to deserialize response JSON.
@@ -42,6 +81,40 @@ pub enum NodeError {
     /// Failed to decode hexidecimal response.
     #[error(transparent)]
     HexDecode(#[from] FromHexError),
+    /// The indexer responded with an unexpected body.
+    #[error("indexer error: {0}")]
+    Indexer(String),
+    /// The method is not supported by the chain backend.
+    #[error("unsupported by backend: {0}")]
+    Unsupported(String),
+    /// The wallet could not sign every input of the transaction.
+    #[error("signing incomplete")]
+    SigningIncomplete,
+}
+
+// bitcoind JSON-RPC error codes
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+const RPC_WALLET_NOT_FOUND: i32 = -18;
+const RPC_WALLET_NOT_SPECIFIED: i32 = -19;
+const RPC_VERIFY_ERROR: i32 = -25;
+const RPC_VERIFY_REJECTED: i32 = -26;
+const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+
+impl From<RpcError> for NodeError {
+    fn from(err: RpcError) -> Self {
+        match err.code {
+            RPC_INVALID_ADDRESS_OR_KEY => Self::NotFound(err.message),
+            RPC_WALLET_NOT_FOUND | RPC_WALLET_NOT_SPECIFIED => Self::WalletNotLoaded(err.message),
+            RPC_VERIFY_ERROR => Self::MissingInputs(err.message),
+            RPC_VERIFY_ALREADY_IN_CHAIN => Self::TxAlreadyKnown(err.message),
+            // Older nodes report mempool duplicates as rejections
+            RPC_VERIFY_REJECTED if err.message.starts_with("txn-already") => {
+                Self::TxAlreadyKnown(err.message)
+            }
+            RPC_VERIFY_REJECTED => Self::TxRejectedByPolicy(err.message),
+            _ => Self::Rpc(err),
+        }
+    }
 }
 
 /// Bitcoin Client function traits
@@ -53,37 +126,213 @@ pub trait BitcoinClient {
     async fn get_new_addr(&self) -> Result<String, NodeError>;
     /// Get a raw bitcoin transaction by txid
     async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError>;
+    /// Estimate the fee rate, in coins per kilobyte, required for a transaction to
+    /// begin confirmation within `conf_target` blocks
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError>;
+    /// Get the IDs of all transactions in the mempool
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError>;
+    /// Get the mempool entry of a transaction by txid
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError>;
+    /// Get the double-spend proof of a transaction by txid, if one has been received
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError>;
+    /// Get the height of the most-work fully-validated chain
+    async fn get_block_count(&self) -> Result<u64, NodeError>;
+    /// List the unspent outputs of the wallet with at least `min_conf` confirmations
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError>;
+    /// Sign the inputs of a raw transaction using the keys in the wallet
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError>;
+}
+
+/// An unspent output of the wallet, as returned by `listunspent`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Unspent {
+    /// ID of the transaction containing the output, hex encoded.
+    pub txid: String,
+    /// Index of the output.
+    pub vout: u32,
+    /// Value of the output in coins.
+    pub amount: f64,
+    /// Number of confirmations of the transaction.
+    pub confirmations: u64,
+    /// The output script, hex encoded.
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: String,
+}
+
+/// A transaction in the mempool, as returned by `getmempoolentry`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MempoolEntry {
+    /// Size of the transaction in bytes.
+    pub size: u64,
+    /// Time the transaction entered the mempool, in seconds since the UNIX epoch.
+    pub time: i64,
+    /// Block height when the transaction entered the mempool.
+    pub height: u64,
+    /// IDs of unconfirmed transactions spent by this transaction.
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// IDs of unconfirmed transactions spending this transaction.
+    #[serde(default)]
+    pub spentby: Vec<String>,
+    /// Fees paid by the transaction.
+    #[serde(default)]
+    pub fees: MempoolFees,
+}
+
+/// The fees of a transaction in the mempool.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct MempoolFees {
+    /// Fee paid by the transaction in coins.
+    pub base: f64,
+}
+
+type BitcoinJsonClient<C> = JsonClient<hyper::Client<C>>;
+trait Connectable: Connect + Clone + Send + Sync + 'static {}
+impl<T: Connect + Clone + Send + Sync + 'static> Connectable for T {}
+
+type Credentials = Option<(String, String)>;
+type Constructor<C> = fn(String, Option<String>, Option<String>) -> BitcoinJsonClient<C>;
+
+/// A JSON-RPC client which is reconstructed when its credentials change.
+#[derive(Clone, Debug)]
+struct RpcClient<C> {
+    endpoint: String,
+    auth: Auth,
+    construct: Constructor<C>,
+    state: Arc<RwLock<(Credentials, BitcoinJsonClient<C>)>>,
+}
+
+impl<C: Connectable> RpcClient<C> {
+    fn new(endpoint: String, auth: Auth, construct: Constructor<C>) -> Self {
+        // The cookie file may not exist until bitcoind starts
+        let credentials = auth.credentials().unwrap_or(None);
+        let client = Self::construct_client(construct, &endpoint, &credentials);
+        Self {
+            endpoint,
+            auth,
+            construct,
+            state: Arc::new(RwLock::new((credentials, client))),
+        }
+    }
+
+    fn construct_client(
+        construct: Constructor<C>,
+        endpoint: &str,
+        credentials: &Credentials,
+    ) -> BitcoinJsonClient<C> {
+        match credentials {
+            Some((username, password)) => construct(
+                endpoint.to_string(),
+                Some(username.clone()),
+                Some(password.clone()),
+            ),
+            None => construct(endpoint.to_string(), None, None),
+        }
+    }
+
+    fn snapshot(&self) -> (Credentials, BitcoinJsonClient<C>) {
+        self.state.read().unwrap().clone()
+    }
+
+    /// Re-read the credentials, reconstructing the client if they changed.
+    fn refresh(&self) {
+        let credentials = match self.auth.credentials() {
+            Ok(ok) => ok,
+            Err(_) => return,
+        };
+        let mut state = self.state.write().unwrap();
+        if state.0 != credentials {
+            let client = Self::construct_client(self.construct, &self.endpoint, &credentials);
+            *state = (credentials, client);
+        }
+    }
+
+    /// Make a call, retrying once with fresh credentials if the call failed to connect and the
+    /// credentials have since changed, for example after bitcoind restarted and rewrote its cookie.
+    async fn call<T, F, Fut>(&self, f: F) -> Result<T, NodeError>
+    where
+        F: Fn(BitcoinJsonClient<C>) -> Fut,
+        Fut: Future<Output = Result<T, NodeError>>,
+    {
+        let (credentials, client) = self.snapshot();
+        match f(client).await {
+            Err(err @ NodeError::RpcConnectError(_)) if self.auth.is_refreshable() => {
+                self.refresh();
+                let (new_credentials, client) = self.snapshot();
+                if new_credentials == credentials {
+                    return Err(err);
+                }
+                f(client).await
+            }
+            result => result,
+        }
+    }
 }
 
 /// Basic Bitcoin JSON-RPC client.
 #[derive(Clone, Debug)]
-pub struct BitcoinClientHTTP(JsonClient<hyper::Client<HttpConnector>>);
+pub struct BitcoinClientHTTP(RpcClient<HttpConnector>);
 
 impl BitcoinClientHTTP {
     /// Create a new HTTP [`BitcoinClient`].
     pub fn new(endpoint: String, username: String, password: String) -> Self {
-        BitcoinClientHTTP(JsonClient::new(endpoint, Some(username), Some(password)))
+        Self::with_auth(endpoint, Auth::UserPass(username, password))
+    }
+
+    /// Create a new HTTP [`BitcoinClient`] using the given authentication method.
+    pub fn with_auth(endpoint: String, auth: Auth) -> Self {
+        BitcoinClientHTTP(RpcClient::new(
+            endpoint,
+            auth,
+            |endpoint, username, password| JsonClient::new(endpoint, username, password),
+        ))
     }
 }
 
 /// Basic HTTPS Bitcoin JSON-RPC client.
 #[derive(Clone, Debug)]
-pub struct BitcoinClientTLS(JsonClient<hyper::Client<HttpsConnector<HttpConnector>>>);
+pub struct BitcoinClientTLS(RpcClient<HttpsConnector<HttpConnector>>);
 
 impl BitcoinClientTLS {
     /// Create a new HTTPS [`BitcoinClient`].
     pub fn new(endpoint: String, username: String, password: String) -> Self {
-        BitcoinClientTLS(JsonClient::new_tls(
+        Self::with_auth(endpoint, Auth::UserPass(username, password))
+    }
+
+    /// Create a new HTTPS [`BitcoinClient`] using the given authentication method.
+    pub fn with_auth(endpoint: String, auth: Auth) -> Self {
+        BitcoinClientTLS(RpcClient::new(
             endpoint,
-            Some(username),
-            Some(password),
+            auth,
+            |endpoint, username, password| JsonClient::new_tls(endpoint, username, password),
         ))
     }
 }
 
-type BitcoinJsonClient<C> = JsonClient<hyper::Client<C>>;
-trait Connectable: Connect + Clone + Send + Sync + 'static {}
-impl<T: Connect + Clone + Send + Sync + 'static> Connectable for T {}
+/// Bitcoin JSON-RPC client connecting over a unix domain socket, for example one exposed by a
+/// reverse proxy co-located with bitcoind.
+#[derive(Clone, Debug)]
+pub struct BitcoinClientUnix(RpcClient<UnixConnector>);
+
+impl BitcoinClientUnix {
+    /// Create a new unix domain socket [`BitcoinClient`].
+    pub fn new<P: AsRef<Path>>(socket_path: P, username: String, password: String) -> Self {
+        Self::with_auth(socket_path, Auth::UserPass(username, password))
+    }
+
+    /// Create a new unix domain socket [`BitcoinClient`] using the given authentication method.
+    pub fn with_auth<P: AsRef<Path>>(socket_path: P, auth: Auth) -> Self {
+        let endpoint = hyper::Uri::from(hyperlocal::Uri::new(socket_path, "/")).to_string();
+        BitcoinClientUnix(RpcClient::new(
+            endpoint,
+            auth,
+            |endpoint, username, password| {
+                let client = hyper::Client::builder().build(UnixConnector);
+                JsonClient::from_service(client, endpoint, username, password)
+            },
+        ))
+    }
+}
 
 async fn get_new_addr<C: Connectable>(client: &BitcoinJsonClient<C>) -> Result<String, NodeError> {
     let request = client
@@ -96,7 +345,7 @@ async fn get_new_addr<C: Connectable>(client: &BitcoinJsonClient<C>) -> Result<S
         .await
         .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
     if response.is_error() {
-        return Err(NodeError::Rpc(response.error().unwrap()));
+        return Err(response.error().unwrap().into());
     }
     response
         .into_result()
@@ -119,8 +368,7 @@ async fn send_tx<C: Connectable>(
         .await
         .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
     if response.is_error() {
-        let err = response.error().unwrap();
-        return Err(NodeError::Rpc(err));
+        return Err(response.error().unwrap().into());
     }
     response
         .into_result()
@@ -144,7 +392,7 @@ async fn get_raw_transaction<C: Connectable>(
         .await
         .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
     if response.is_error() {
-        return Err(NodeError::Rpc(response.error().unwrap()));
+        return Err(response.error().unwrap().into());
     }
     let tx_hex: String = response
         .into_result()
@@ -153,21 +401,299 @@ async fn get_raw_transaction<C: Connectable>(
     hex::decode(tx_hex).map_err(Into::into)
 }
 
+#[derive(Deserialize)]
+struct SmartFeeEstimate {
+    feerate: Option<f64>,
+}
+
+/// Calls the `estimatesmartfee` method, falling back to `estimatefee` for nodes
+/// which do not support it.
+async fn estimate_fee<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    conf_target: u32,
+) -> Result<f64, NodeError> {
+    let request = client
+        .build_request()
+        .method("estimatesmartfee")
+        .params(vec![Value::from(conf_target)])
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if !response.is_error() {
+        let estimate: SmartFeeEstimate = response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)?;
+        return estimate.feerate.ok_or(NodeError::EmptyResponse);
+    }
+
+    let request = client
+        .build_request()
+        .method("estimatefee")
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        return Err(response.error().unwrap().into());
+    }
+    response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)
+}
+
+/// Calls the `getrawmempool` method.
+async fn get_raw_mempool<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+) -> Result<Vec<Vec<u8>>, NodeError> {
+    let request = client
+        .build_request()
+        .method("getrawmempool")
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        return Err(response.error().unwrap().into());
+    }
+    let tx_ids: Vec<String> = response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)?;
+    tx_ids
+        .into_iter()
+        .map(|tx_id| hex::decode(tx_id).map_err(Into::into))
+        .collect()
+}
+
+/// Calls the `getmempoolentry` method.
+async fn get_mempool_entry<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    tx_id: &[u8],
+) -> Result<MempoolEntry, NodeError> {
+    let request = client
+        .build_request()
+        .method("getmempoolentry")
+        .params(vec![Value::String(hex::encode(tx_id))])
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        return Err(response.error().unwrap().into());
+    }
+    response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)
+}
+
+#[derive(Deserialize)]
+struct DsProof {
+    hex: String,
+}
+
+/// Calls the `getdsproof` method, returning `None` if no proof has been received.
+async fn get_ds_proof<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    tx_id: &[u8],
+) -> Result<Option<Vec<u8>>, NodeError> {
+    let request = client
+        .build_request()
+        .method("getdsproof")
+        .params(vec![Value::String(hex::encode(tx_id)), Value::from(0)])
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        return match NodeError::from(response.error().unwrap()) {
+            NodeError::NotFound(_) => Ok(None),
+            err => Err(err),
+        };
+    }
+    let proof: Option<DsProof> = response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)?;
+    proof
+        .map(|proof| hex::decode(proof.hex).map_err(Into::into))
+        .transpose()
+}
+
+/// Calls the `getblockcount` method.
+async fn get_block_count<C: Connectable>(client: &BitcoinJsonClient<C>) -> Result<u64, NodeError> {
+    let request = client
+        .build_request()
+        .method("getblockcount")
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        return Err(response.error().unwrap().into());
+    }
+    response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)
+}
+
+/// Calls the `listunspent` method.
+async fn list_unspent<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    min_conf: u32,
+) -> Result<Vec<Unspent>, NodeError> {
+    let request = client
+        .build_request()
+        .method("listunspent")
+        .params(vec![Value::from(min_conf)])
+        .finish()
+        .unwrap();
+    let response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        return Err(response.error().unwrap().into());
+    }
+    response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)
+}
+
+#[derive(Deserialize)]
+struct SignedTransaction {
+    hex: String,
+    complete: bool,
+}
+
+/// Calls the `signrawtransactionwithwallet` method, falling back to `signrawtransaction` for nodes
+/// which do not support it.
+async fn sign_raw_transaction<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    raw_tx: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    let request = client
+        .build_request()
+        .method("signrawtransactionwithwallet")
+        .params(vec![Value::String(hex::encode(raw_tx))])
+        .finish()
+        .unwrap();
+    let mut response = client
+        .send(request)
+        .await
+        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    if response.is_error() {
+        let request = client
+            .build_request()
+            .method("signrawtransaction")
+            .params(vec![Value::String(hex::encode(raw_tx))])
+            .finish()
+            .unwrap();
+        response = client
+            .send(request)
+            .await
+            .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    }
+    if response.is_error() {
+        return Err(response.error().unwrap().into());
+    }
+    let signed: SignedTransaction = response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)?;
+    if !signed.complete {
+        return Err(NodeError::SigningIncomplete);
+    }
+    hex::decode(signed.hex).map_err(Into::into)
+}
+
 #[async_trait]
 impl BitcoinClient for BitcoinClientTLS {
     /// Calls the `getnewaddress` method.
     async fn get_new_addr(&self) -> Result<String, NodeError> {
-        get_new_addr(&self.0).await
+        self.0
+            .call(|client| async move { get_new_addr(&client).await })
+            .await
     }
 
     /// Calls the `sendrawtransaction` method.
     async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
-        send_tx(&self.0, raw_tx).await
+        self.0
+            .call(|client| async move { send_tx(&client, raw_tx).await })
+            .await
     }
 
     /// Calls the `getrawtransaction` method.
     async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
-        get_raw_transaction(&self.0, tx_id).await
+        self.0
+            .call(|client| async move { get_raw_transaction(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `estimatesmartfee` or `estimatefee` method.
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        self.0
+            .call(|client| async move { estimate_fee(&client, conf_target).await })
+            .await
+    }
+
+    /// Calls the `getrawmempool` method.
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        self.0
+            .call(|client| async move { get_raw_mempool(&client).await })
+            .await
+    }
+
+    /// Calls the `getmempoolentry` method.
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        self.0
+            .call(|client| async move { get_mempool_entry(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `getdsproof` method.
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        self.0
+            .call(|client| async move { get_ds_proof(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `getblockcount` method.
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        self.0
+            .call(|client| async move { get_block_count(&client).await })
+            .await
+    }
+
+    /// Calls the `listunspent` method.
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        self.0
+            .call(|client| async move { list_unspent(&client, min_conf).await })
+            .await
+    }
+
+    /// Calls the `signrawtransactionwithwallet` or `signrawtransaction` method.
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.0
+            .call(|client| async move { sign_raw_transaction(&client, raw_tx).await })
+            .await
     }
 }
 
@@ -175,16 +701,144 @@ impl BitcoinClient for BitcoinClientTLS {
 impl BitcoinClient for BitcoinClientHTTP {
     /// Calls the `getnewaddress` method.
     async fn get_new_addr(&self) -> Result<String, NodeError> {
-        get_new_addr(&self.0).await
+        self.0
+            .call(|client| async move { get_new_addr(&client).await })
+            .await
     }
 
     /// Calls the `sendrawtransaction` method.
     async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
-        send_tx(&self.0, raw_tx).await
+        self.0
+            .call(|client| async move { send_tx(&client, raw_tx).await })
+            .await
     }
 
     /// Calls the `getrawtransaction` method.
     async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
-        get_raw_transaction(&self.0, tx_id).await
+        self.0
+            .call(|client| async move { get_raw_transaction(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `estimatesmartfee` or `estimatefee` method.
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        self.0
+            .call(|client| async move { estimate_fee(&client, conf_target).await })
+            .await
+    }
+
+    /// Calls the `getrawmempool` method.
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        self.0
+            .call(|client| async move { get_raw_mempool(&client).await })
+            .await
+    }
+
+    /// Calls the `getmempoolentry` method.
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        self.0
+            .call(|client| async move { get_mempool_entry(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `getdsproof` method.
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        self.0
+            .call(|client| async move { get_ds_proof(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `getblockcount` method.
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        self.0
+            .call(|client| async move { get_block_count(&client).await })
+            .await
+    }
+
+    /// Calls the `listunspent` method.
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        self.0
+            .call(|client| async move { list_unspent(&client, min_conf).await })
+            .await
+    }
+
+    /// Calls the `signrawtransactionwithwallet` or `signrawtransaction` method.
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.0
+            .call(|client| async move { sign_raw_transaction(&client, raw_tx).await })
+            .await
+    }
+}
+
+#[async_trait]
+impl BitcoinClient for BitcoinClientUnix {
+    /// Calls the `getnewaddress` method.
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        self.0
+            .call(|client| async move { get_new_addr(&client).await })
+            .await
+    }
+
+    /// Calls the `sendrawtransaction` method.
+    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
+        self.0
+            .call(|client| async move { send_tx(&client, raw_tx).await })
+            .await
+    }
+
+    /// Calls the `getrawtransaction` method.
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.0
+            .call(|client| async move { get_raw_transaction(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `estimatesmartfee` or `estimatefee` method.
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        self.0
+            .call(|client| async move { estimate_fee(&client, conf_target).await })
+            .await
+    }
+
+    /// Calls the `getrawmempool` method.
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        self.0
+            .call(|client| async move { get_raw_mempool(&client).await })
+            .await
+    }
+
+    /// Calls the `getmempoolentry` method.
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        self.0
+            .call(|client| async move { get_mempool_entry(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `getdsproof` method.
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        self.0
+            .call(|client| async move { get_ds_proof(&client, tx_id).await })
+            .await
+    }
+
+    /// Calls the `getblockcount` method.
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        self.0
+            .call(|client| async move { get_block_count(&client).await })
+            .await
+    }
+
+    /// Calls the `listunspent` method.
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        self.0
+            .call(|client| async move { list_unspent(&client, min_conf).await })
+            .await
+    }
+
+    /// Calls the `signrawtransactionwithwallet` or `signrawtransaction` method.
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.0
+            .call(|client| async move { sign_raw_transaction(&client, raw_tx).await })
+            .await
     }
 }
@@ -0,0 +1,190 @@
+//! This module contains the [`MockBitcoinClient`], an in-memory [`BitcoinClient`] for use in tests.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+
+use crate::{BitcoinClient, MempoolEntry, NodeError, Unspent};
+
+/// A call made to a [`MockBitcoinClient`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockCall {
+    /// Call to [`BitcoinClient::send_tx`].
+    SendTx(Vec<u8>),
+    /// Call to [`BitcoinClient::get_new_addr`].
+    GetNewAddr,
+    /// Call to [`BitcoinClient::get_raw_transaction`].
+    GetRawTransaction(Vec<u8>),
+    /// Call to [`BitcoinClient::estimate_fee`].
+    EstimateFee(u32),
+    /// Call to [`BitcoinClient::get_raw_mempool`].
+    GetRawMempool,
+    /// Call to [`BitcoinClient::get_mempool_entry`].
+    GetMempoolEntry(Vec<u8>),
+    /// Call to [`BitcoinClient::get_ds_proof`].
+    GetDsProof(Vec<u8>),
+    /// Call to [`BitcoinClient::get_block_count`].
+    GetBlockCount,
+    /// Call to [`BitcoinClient::list_unspent`].
+    ListUnspent(u32),
+    /// Call to [`BitcoinClient::sign_raw_transaction`].
+    SignRawTransaction(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    calls: Vec<MockCall>,
+    failures: usize,
+    send_tx_response: String,
+    new_addr: String,
+    fee_rate: f64,
+    transactions: HashMap<Vec<u8>, Vec<u8>>,
+    mempool: HashMap<Vec<u8>, MempoolEntry>,
+    ds_proofs: HashMap<Vec<u8>, Vec<u8>>,
+    block_count: u64,
+    unspent: Vec<Unspent>,
+}
+
+/// An in-memory [`BitcoinClient`] with scripted responses, failure injection and call recording.
+///
+/// Clones share the same state, so a clone may be handed to the code under test while the
+/// original is used to script responses and inspect calls.
+#[derive(Clone, Debug, Default)]
+pub struct MockBitcoinClient(Arc<Mutex<MockState>>);
+
+impl MockBitcoinClient {
+    /// Create a new [`MockBitcoinClient`] which succeeds with empty responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the transaction ID returned by `send_tx`.
+    pub fn set_send_tx_response(&self, tx_id: impl Into<String>) {
+        self.0.lock().unwrap().send_tx_response = tx_id.into();
+    }
+
+    /// Set the address returned by `get_new_addr`.
+    pub fn set_new_addr(&self, addr: impl Into<String>) {
+        self.0.lock().unwrap().new_addr = addr.into();
+    }
+
+    /// Set the fee rate returned by `estimate_fee`.
+    pub fn set_fee_rate(&self, fee_rate: f64) {
+        self.0.lock().unwrap().fee_rate = fee_rate;
+    }
+
+    /// Add a transaction to be returned by `get_raw_transaction`.
+    pub fn insert_transaction(&self, tx_id: Vec<u8>, raw_tx: Vec<u8>) {
+        self.0.lock().unwrap().transactions.insert(tx_id, raw_tx);
+    }
+
+    /// Add a transaction to the mempool.
+    pub fn insert_mempool_entry(&self, tx_id: Vec<u8>, entry: MempoolEntry) {
+        self.0.lock().unwrap().mempool.insert(tx_id, entry);
+    }
+
+    /// Add a double-spend proof for a transaction.
+    pub fn insert_ds_proof(&self, tx_id: Vec<u8>, proof: Vec<u8>) {
+        self.0.lock().unwrap().ds_proofs.insert(tx_id, proof);
+    }
+
+    /// Set the height returned by `get_block_count`.
+    pub fn set_block_count(&self, block_count: u64) {
+        self.0.lock().unwrap().block_count = block_count;
+    }
+
+    /// Set the outputs returned by `list_unspent`.
+    pub fn set_unspent(&self, unspent: Vec<Unspent>) {
+        self.0.lock().unwrap().unspent = unspent;
+    }
+
+    /// Fail the next `n` calls with a connection error.
+    pub fn fail_next(&self, n: usize) {
+        self.0.lock().unwrap().failures = n;
+    }
+
+    /// Get the calls made so far, in order.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.0.lock().unwrap().calls.clone()
+    }
+
+    fn record(&self, call: MockCall) -> Result<MutexGuard<'_, MockState>, NodeError> {
+        let mut state = self.0.lock().unwrap();
+        state.calls.push(call);
+        if state.failures > 0 {
+            state.failures -= 1;
+            return Err(NodeError::RpcConnectError("injected failure".to_string()));
+        }
+        Ok(state)
+    }
+}
+
+#[async_trait]
+impl BitcoinClient for MockBitcoinClient {
+    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
+        let state = self.record(MockCall::SendTx(raw_tx.to_vec()))?;
+        Ok(state.send_tx_response.clone())
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        let state = self.record(MockCall::GetNewAddr)?;
+        Ok(state.new_addr.clone())
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        let state = self.record(MockCall::GetRawTransaction(tx_id.to_vec()))?;
+        state
+            .transactions
+            .get(tx_id)
+            .cloned()
+            .ok_or(NodeError::EmptyResponse)
+    }
+
+    async fn estimate_fee(&self, conf_target: u32) -> Result<f64, NodeError> {
+        let state = self.record(MockCall::EstimateFee(conf_target))?;
+        Ok(state.fee_rate)
+    }
+
+    async fn get_raw_mempool(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        let state = self.record(MockCall::GetRawMempool)?;
+        Ok(state.mempool.keys().cloned().collect())
+    }
+
+    async fn get_mempool_entry(&self, tx_id: &[u8]) -> Result<MempoolEntry, NodeError> {
+        let state = self.record(MockCall::GetMempoolEntry(tx_id.to_vec()))?;
+        state
+            .mempool
+            .get(tx_id)
+            .cloned()
+            .ok_or(NodeError::EmptyResponse)
+    }
+
+    async fn get_ds_proof(&self, tx_id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        let state = self.record(MockCall::GetDsProof(tx_id.to_vec()))?;
+        Ok(state.ds_proofs.get(tx_id).cloned())
+    }
+
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        let state = self.record(MockCall::GetBlockCount)?;
+        Ok(state.block_count)
+    }
+
+    async fn list_unspent(&self, min_conf: u32) -> Result<Vec<Unspent>, NodeError> {
+        let state = self.record(MockCall::ListUnspent(min_conf))?;
+        Ok(state
+            .unspent
+            .iter()
+            .filter(|unspent| unspent.confirmations >= min_conf as u64)
+            .cloned()
+            .collect())
+    }
+
+    /// Returns the transaction unchanged.
+    async fn sign_raw_transaction(&self, raw_tx: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.record(MockCall::SignRawTransaction(raw_tx.to_vec()))?;
+        Ok(raw_tx.to_vec())
+    }
+}
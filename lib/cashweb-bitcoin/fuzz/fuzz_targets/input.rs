@@ -0,0 +1,14 @@
+#![no_main]
+use cashweb_bitcoin::{transaction::input::Input, Decodable, Encodable};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = data;
+    if let Ok(decoded) = Input::decode(&mut buf) {
+        // Decoding must consume exactly the encoded bytes
+        let consumed = data.len() - buf.len();
+        let mut raw = Vec::with_capacity(decoded.encoded_len());
+        decoded.encode(&mut raw).unwrap();
+        assert_eq!(raw.as_slice(), &data[..consumed]);
+    }
+});
@@ -0,0 +1,152 @@
+//! Property-based tests for the decoding of Bitcoin structures. These structures are parsed from
+//! attacker-controlled bytes, so decoding arbitrary input must never panic and decoding must be
+//! the inverse of encoding.
+
+use bytes::Buf;
+use proptest::{collection::vec, prelude::*};
+
+use crate::{
+    psbt::{PartialInput, PartialSignature, PartiallySignedTransaction},
+    transaction::{input::Input, outpoint::Outpoint, output::Output, Transaction},
+    var_int::VarInt,
+    Decodable, Encodable,
+};
+
+fn arb_outpoint() -> impl Strategy<Value = Outpoint> {
+    (any::<[u8; 32]>(), any::<u32>()).prop_map(|(tx_id, vout)| Outpoint { tx_id, vout })
+}
+
+fn arb_input() -> impl Strategy<Value = Input> {
+    (arb_outpoint(), vec(any::<u8>(), 0..256), any::<u32>()).prop_map(
+        |(outpoint, script, sequence)| Input {
+            outpoint,
+            script: script.into(),
+            sequence,
+        },
+    )
+}
+
+fn arb_output() -> impl Strategy<Value = Output> {
+    (any::<u64>(), vec(any::<u8>(), 0..256)).prop_map(|(value, script)| Output {
+        value,
+        script: script.into(),
+    })
+}
+
+fn arb_transaction() -> impl Strategy<Value = Transaction> {
+    (
+        any::<u32>(),
+        vec(arb_input(), 0..8),
+        vec(arb_output(), 0..8),
+        any::<u32>(),
+    )
+        .prop_map(|(version, inputs, outputs, lock_time)| Transaction {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        })
+}
+
+fn arb_partial_input() -> impl Strategy<Value = PartialInput> {
+    let signature =
+        (vec(any::<u8>(), 0..66), vec(any::<u8>(), 0..74)).prop_map(|(public_key, signature)| {
+            PartialSignature {
+                public_key,
+                signature,
+            }
+        });
+    (proptest::option::of(arb_output()), vec(signature, 0..4)).prop_map(
+        |(prev_output, signatures)| PartialInput {
+            prev_output,
+            signatures,
+        },
+    )
+}
+
+fn arb_psbt() -> impl Strategy<Value = PartiallySignedTransaction> {
+    arb_transaction()
+        .prop_flat_map(|transaction| {
+            let n_inputs = transaction.inputs.len();
+            (
+                Just(PartiallySignedTransaction::new(transaction)),
+                vec(arb_partial_input(), n_inputs),
+            )
+        })
+        .prop_map(|(mut psbt, inputs)| {
+            psbt.inputs = inputs;
+            psbt
+        })
+}
+
+fn encode<T: Encodable>(value: &T) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(value.encoded_len());
+    value.encode(&mut raw).unwrap();
+    raw
+}
+
+fn roundtrip<T>(value: T) -> Result<(), TestCaseError>
+where
+    T: Encodable + Decodable + PartialEq + std::fmt::Debug,
+    T::Error: std::fmt::Debug,
+{
+    let raw = encode(&value);
+    prop_assert_eq!(raw.len(), value.encoded_len());
+    let mut buf = raw.as_slice();
+    let decoded = T::decode(&mut buf).unwrap();
+    prop_assert_eq!(buf.remaining(), 0);
+    prop_assert_eq!(decoded, value);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn var_int_roundtrip(n in any::<u64>()) {
+        roundtrip(VarInt(n))?;
+    }
+
+    #[test]
+    fn outpoint_roundtrip(outpoint in arb_outpoint()) {
+        roundtrip(outpoint)?;
+    }
+
+    #[test]
+    fn input_roundtrip(input in arb_input()) {
+        roundtrip(input)?;
+    }
+
+    #[test]
+    fn output_roundtrip(output in arb_output()) {
+        roundtrip(output)?;
+    }
+
+    #[test]
+    fn transaction_roundtrip(transaction in arb_transaction()) {
+        roundtrip(transaction)?;
+    }
+
+    #[test]
+    fn psbt_roundtrip(psbt in arb_psbt()) {
+        roundtrip(psbt)?;
+    }
+
+    #[test]
+    fn decode_arbitrary_bytes(raw in vec(any::<u8>(), 0..1024)) {
+        let _ = VarInt::decode(&mut raw.as_slice());
+        let _ = Outpoint::decode(&mut raw.as_slice());
+        let _ = Input::decode(&mut raw.as_slice());
+        let _ = Output::decode(&mut raw.as_slice());
+        let _ = Transaction::decode(&mut raw.as_slice());
+        let _ = PartiallySignedTransaction::decode(&mut raw.as_slice());
+    }
+
+    #[test]
+    fn decode_truncated_transaction(
+        transaction in arb_transaction(),
+        cut in any::<prop::sample::Index>(),
+    ) {
+        let raw = encode(&transaction);
+        let truncated = &raw[..cut.index(raw.len())];
+        prop_assert!(Transaction::decode(&mut &truncated[..]).is_err());
+    }
+}
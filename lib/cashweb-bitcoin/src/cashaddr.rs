@@ -0,0 +1,303 @@
+//! This module contains an implementation of the [`CashAddr`] address format which accepts arbitrary
+//! prefixes, allowing addresses from sibling networks such as eCash (`ecash:`) to be decoded.
+//!
+//! [`CashAddr`]: https://github.com/bitcoincashorg/bitcoincash.org/blob/master/spec/cashaddr.md
+
+use thiserror::Error;
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LEN: usize = 8;
+const HASH_SIZES: [usize; 8] = [20, 24, 28, 32, 40, 48, 56, 64];
+
+/// The type of script an address pays to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressType {
+    /// Pay to public key hash.
+    P2PKH,
+    /// Pay to script hash.
+    P2SH,
+}
+
+/// A decoded CashAddr address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CashAddress {
+    /// The human readable prefix, for example `bitcoincash` or `ecash`.
+    pub prefix: String,
+    /// The type of script the address pays to.
+    pub address_type: AddressType,
+    /// The public key hash or script hash.
+    pub hash: Vec<u8>,
+}
+
+/// Error associated with decoding a CashAddr address.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DecodingError {
+    /// The address did not contain a prefix.
+    #[error("missing prefix")]
+    MissingPrefix,
+    /// The prefix was not one of the expected prefixes.
+    #[error("unexpected prefix: {0}")]
+    UnexpectedPrefix(String),
+    /// The address contained both upper and lower case characters.
+    #[error("mixed case")]
+    MixedCase,
+    /// The address contained a character outside of the base32 character set.
+    #[error("invalid character: {0}")]
+    InvalidChar(char),
+    /// The checksum did not match.
+    #[error("invalid checksum")]
+    InvalidChecksum,
+    /// The payload had unexpected padding.
+    #[error("invalid padding")]
+    InvalidPadding,
+    /// The version byte specified an unknown address type.
+    #[error("unknown address type: {0}")]
+    UnknownType(u8),
+    /// The hash length did not match the length specified by the version byte.
+    #[error("invalid hash length: {0}")]
+    InvalidLength(usize),
+}
+
+/// Error associated with encoding a CashAddr address.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("invalid hash length: {0}")]
+pub struct InvalidLength(pub usize);
+
+fn polymod(values: impl Iterator<Item = u8>) -> u64 {
+    let mut checksum: u64 = 1;
+    for value in values {
+        let top = checksum >> 35;
+        checksum = ((checksum & 0x07_ffff_ffff) << 5) ^ u64::from(value);
+        if top & 0x01 != 0 {
+            checksum ^= 0x98_f2bc_8e61;
+        }
+        if top & 0x02 != 0 {
+            checksum ^= 0x79_b76d_99e2;
+        }
+        if top & 0x04 != 0 {
+            checksum ^= 0xf3_3e5f_b3c4;
+        }
+        if top & 0x08 != 0 {
+            checksum ^= 0xae_2eab_e2a8;
+        }
+        if top & 0x10 != 0 {
+            checksum ^= 0x1e_4f43_e470;
+        }
+    }
+    checksum ^ 1
+}
+
+fn expand_prefix(prefix: &str) -> impl Iterator<Item = u8> + '_ {
+    prefix
+        .bytes()
+        .map(|byte| byte & 0x1f)
+        .chain(std::iter::once(0))
+}
+
+/// Regroup a sequence of `from`-bit values into `to`-bit values.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max_value = (1 << to) - 1;
+    let mut converted = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data {
+        acc = (acc << from) | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max_value != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+/// Encode a hash as a CashAddr address with the given prefix.
+pub fn encode(
+    prefix: &str,
+    address_type: AddressType,
+    hash: &[u8],
+) -> Result<String, InvalidLength> {
+    let size_bits = HASH_SIZES
+        .iter()
+        .position(|size| *size == hash.len())
+        .ok_or_else(|| InvalidLength(hash.len()))? as u8;
+    let type_bits = match address_type {
+        AddressType::P2PKH => 0,
+        AddressType::P2SH => 1,
+    };
+    let version = (type_bits << 3) | size_bits;
+
+    let mut raw = Vec::with_capacity(hash.len() + 1);
+    raw.push(version);
+    raw.extend_from_slice(hash);
+    let payload = convert_bits(&raw, 8, 5, true).unwrap(); // This is safe
+
+    let checksum = polymod(
+        expand_prefix(prefix)
+            .chain(payload.iter().copied())
+            .chain(std::iter::repeat(0).take(CHECKSUM_LEN)),
+    );
+
+    let mut address = String::with_capacity(prefix.len() + 1 + payload.len() + CHECKSUM_LEN);
+    address.push_str(prefix);
+    address.push(':');
+    for value in payload {
+        address.push(CHARSET[value as usize] as char);
+    }
+    for i in 0..CHECKSUM_LEN {
+        let value = (checksum >> (5 * (CHECKSUM_LEN - 1 - i))) & 0x1f;
+        address.push(CHARSET[value as usize] as char);
+    }
+    Ok(address)
+}
+
+/// Decode a CashAddr address, requiring that its prefix is one of `prefixes`.
+///
+/// Prefixes are compared case-insensitively.
+pub fn decode(address: &str, prefixes: &[String]) -> Result<CashAddress, DecodingError> {
+    let has_lower = address.bytes().any(|byte| byte.is_ascii_lowercase());
+    let has_upper = address.bytes().any(|byte| byte.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(DecodingError::MixedCase);
+    }
+    let address = address.to_ascii_lowercase();
+
+    let separator = address.rfind(':').ok_or(DecodingError::MissingPrefix)?;
+    let (prefix, payload_str) = (&address[..separator], &address[separator + 1..]);
+    if !prefixes
+        .iter()
+        .any(|expected| expected.eq_ignore_ascii_case(prefix))
+    {
+        return Err(DecodingError::UnexpectedPrefix(prefix.to_string()));
+    }
+
+    // Decode base32
+    let payload = payload_str
+        .chars()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|charset_byte| *charset_byte as char == c)
+                .map(|value| value as u8)
+                .ok_or(DecodingError::InvalidChar(c))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    if payload.len() <= CHECKSUM_LEN {
+        return Err(DecodingError::InvalidLength(0));
+    }
+
+    // Verify checksum
+    if polymod(expand_prefix(prefix).chain(payload.iter().copied())) != 0 {
+        return Err(DecodingError::InvalidChecksum);
+    }
+
+    // Parse version byte
+    let raw = convert_bits(&payload[..payload.len() - CHECKSUM_LEN], 5, 8, false)
+        .ok_or(DecodingError::InvalidPadding)?;
+    let (version, hash) = raw.split_first().ok_or(DecodingError::InvalidLength(0))?;
+    let address_type = match version >> 3 {
+        0 => AddressType::P2PKH,
+        1 => AddressType::P2SH,
+        other => return Err(DecodingError::UnknownType(other)),
+    };
+    if HASH_SIZES[(version & 0x07) as usize] != hash.len() {
+        return Err(DecodingError::InvalidLength(hash.len()));
+    }
+
+    Ok(CashAddress {
+        prefix: prefix.to_string(),
+        address_type,
+        hash: hash.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "76a04053bda0a88bda5177b86a15c3b29f559873";
+
+    fn prefixes() -> Vec<String> {
+        vec!["bitcoincash".to_string(), "ecash".to_string()]
+    }
+
+    #[test]
+    fn vectors() {
+        let hash = hex::decode(HASH).unwrap();
+        let vectors = [
+            (
+                "bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a",
+                AddressType::P2PKH,
+            ),
+            (
+                "bitcoincash:ppm2qsznhks23z7629mms6s4cwef74vcwvn0h829pq",
+                AddressType::P2SH,
+            ),
+            (
+                "ecash:qpm2qsznhks23z7629mms6s4cwef74vcwva87rkuu2",
+                AddressType::P2PKH,
+            ),
+            (
+                "ecash:ppm2qsznhks23z7629mms6s4cwef74vcwv2zrv3l8h",
+                AddressType::P2SH,
+            ),
+        ];
+        for (address, address_type) in &vectors {
+            let prefix = &address[..address.find(':').unwrap()];
+            assert_eq!(&encode(prefix, *address_type, &hash).unwrap(), address);
+
+            let decoded = decode(address, &prefixes()).unwrap();
+            assert_eq!(decoded.prefix, prefix);
+            assert_eq!(decoded.address_type, *address_type);
+            assert_eq!(decoded.hash, hash);
+
+            // Upper case addresses are accepted
+            assert_eq!(decode(&address.to_uppercase(), &prefixes()), Ok(decoded));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let address = "ecash:qpm2qsznhks23z7629mms6s4cwef74vcwva87rkuu2";
+        assert_eq!(
+            decode(address, &["bitcoincash".to_string()]),
+            Err(DecodingError::UnexpectedPrefix("ecash".to_string()))
+        );
+        assert_eq!(
+            decode(
+                "ecash:qpm2qsznhks23z7629mms6s4cwef74vcwva87rkuu3",
+                &prefixes()
+            ),
+            Err(DecodingError::InvalidChecksum)
+        );
+        assert_eq!(
+            decode(
+                "ecash:Qpm2qsznhks23z7629mms6s4cwef74vcwva87rkuu2",
+                &prefixes()
+            ),
+            Err(DecodingError::MixedCase)
+        );
+        assert_eq!(
+            decode("qpm2qsznhks23z7629mms6s4cwef74vcwva87rkuu2", &prefixes()),
+            Err(DecodingError::MissingPrefix)
+        );
+        assert_eq!(
+            decode(
+                "ecash:qpm2qsznhks23z7629mms6s4cwef74vcwva87rkub2",
+                &prefixes()
+            ),
+            Err(DecodingError::InvalidChar('b'))
+        );
+        assert_eq!(
+            encode("ecash", AddressType::P2PKH, &[0; 19]),
+            Err(InvalidLength(19))
+        );
+    }
+}
@@ -11,10 +11,15 @@
 //! [`Hierarchical Deterministic Wallets`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
 
 pub mod bip32;
+pub mod cashaddr;
 pub mod merkle;
+pub mod psbt;
 pub mod transaction;
 pub mod var_int;
 
+#[cfg(test)]
+mod arbitrary;
+
 use std::convert::TryFrom;
 
 use bytes::{Buf, BufMut};
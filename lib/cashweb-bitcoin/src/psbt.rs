@@ -0,0 +1,372 @@
+//! This module contains the [`PartiallySignedTransaction`] struct which allows unsigned, or partially
+//! signed, transactions to be passed between components, such as wallets and signing services.
+//! It enjoys [`Encodable`] and [`Decodable`].
+
+use bytes::{Buf, BufMut};
+use thiserror::Error;
+
+use crate::{
+    transaction::{
+        output::{DecodeError as OutputDecodeError, Output},
+        script::Script,
+        DecodeError as TransactionDecodeError, Transaction,
+    },
+    var_int::{DecodeError as VarIntDecodeError, VarInt},
+    Decodable, Encodable,
+};
+
+/// Magic bytes prefixing a serialized [`PartiallySignedTransaction`].
+pub const MAGIC: [u8; 5] = *b"psbt\xff";
+
+/// A signature, and the public key it can be verified against, for a specific input.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartialSignature {
+    /// The serialized public key.
+    pub public_key: Vec<u8>,
+    /// The serialized signature, including the signature hash type byte.
+    pub signature: Vec<u8>,
+}
+
+impl Encodable for PartialSignature {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        VarInt(self.public_key.len() as u64).encoded_len()
+            + self.public_key.len()
+            + VarInt(self.signature.len() as u64).encoded_len()
+            + self.signature.len()
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        VarInt(self.public_key.len() as u64).encode_raw(buf);
+        buf.put_slice(&self.public_key);
+        VarInt(self.signature.len() as u64).encode_raw(buf);
+        buf.put_slice(&self.signature);
+    }
+}
+
+/// Signing metadata associated with an input of the unsigned transaction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartialInput {
+    /// The output being spent, required to calculate the signature hash.
+    pub prev_output: Option<Output>,
+    /// The signatures collected so far.
+    pub signatures: Vec<PartialSignature>,
+}
+
+impl Encodable for PartialInput {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        let prev_output_len = self
+            .prev_output
+            .as_ref()
+            .map(|output| output.encoded_len())
+            .unwrap_or_default();
+        let signatures_len: usize = self
+            .signatures
+            .iter()
+            .map(|signature| signature.encoded_len())
+            .sum();
+        1 + prev_output_len + VarInt(self.signatures.len() as u64).encoded_len() + signatures_len
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        match &self.prev_output {
+            Some(output) => {
+                buf.put_u8(1);
+                output.encode_raw(buf);
+            }
+            None => buf.put_u8(0),
+        }
+        VarInt(self.signatures.len() as u64).encode_raw(buf);
+        for signature in &self.signatures {
+            signature.encode_raw(buf);
+        }
+    }
+}
+
+/// Represents an unsigned transaction alongside the metadata required to sign it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartiallySignedTransaction {
+    /// The unsigned transaction. All input scripts are empty.
+    pub transaction: Transaction,
+    /// Signing metadata, one per input of the unsigned transaction.
+    pub inputs: Vec<PartialInput>,
+}
+
+impl PartiallySignedTransaction {
+    /// Construct a new [`PartiallySignedTransaction`] from a transaction, clearing its input scripts.
+    pub fn new(mut transaction: Transaction) -> Self {
+        for input in &mut transaction.inputs {
+            input.script = Script::default();
+        }
+        let inputs = vec![PartialInput::default(); transaction.inputs.len()];
+        Self {
+            transaction,
+            inputs,
+        }
+    }
+
+    /// Checks whether every input has at least one signature.
+    #[inline]
+    pub fn is_signed(&self) -> bool {
+        self.inputs.iter().all(|input| !input.signatures.is_empty())
+    }
+}
+
+impl Encodable for PartiallySignedTransaction {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        let inputs_len: usize = self.inputs.iter().map(|input| input.encoded_len()).sum();
+        MAGIC.len()
+            + VarInt(self.transaction.encoded_len() as u64).encoded_len()
+            + self.transaction.encoded_len()
+            + inputs_len
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(&MAGIC);
+        VarInt(self.transaction.encoded_len() as u64).encode_raw(buf);
+        self.transaction.encode_raw(buf);
+        for input in &self.inputs {
+            input.encode_raw(buf);
+        }
+    }
+}
+
+/// Error associated with [`PartiallySignedTransaction`] deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    /// Magic bytes are missing or incorrect.
+    #[error("invalid magic bytes")]
+    Magic,
+    /// Unable to decode the transaction length variable-length integer.
+    #[error("transaction length: {0}")]
+    TransactionLen(VarIntDecodeError),
+    /// Transaction is too short.
+    #[error("transaction too short")]
+    TransactionTooShort,
+    /// Failed to decode the unsigned transaction.
+    #[error("transaction: {0}")]
+    Transaction(TransactionDecodeError),
+    /// The serialized transaction is followed by unexpected bytes.
+    #[error("transaction has trailing bytes")]
+    TransactionTrailingBytes,
+    /// The unsigned transaction contains a non-empty input script.
+    #[error("transaction is signed")]
+    Signed,
+    /// Exhausted buffer when decoding the previous output flag.
+    #[error("previous output flag too short")]
+    PrevOutputFlagTooShort,
+    /// Previous output flag is neither 0 nor 1.
+    #[error("invalid previous output flag: {0}")]
+    PrevOutputFlag(u8),
+    /// Failed to decode a previous output.
+    #[error("previous output: {0}")]
+    PrevOutput(OutputDecodeError),
+    /// Unable to decode the signature count variable-length integer.
+    #[error("signature count: {0}")]
+    SignatureCount(VarIntDecodeError),
+    /// Unable to decode the public key length variable-length integer.
+    #[error("public key length: {0}")]
+    PublicKeyLen(VarIntDecodeError),
+    /// Public key is too short.
+    #[error("public key too short")]
+    PublicKeyTooShort,
+    /// Unable to decode the signature length variable-length integer.
+    #[error("signature length: {0}")]
+    SignatureLen(VarIntDecodeError),
+    /// Signature is too short.
+    #[error("signature too short")]
+    SignatureTooShort,
+}
+
+fn decode_bytes<B: Buf>(
+    buf: &mut B,
+    len_err: fn(VarIntDecodeError) -> DecodeError,
+    short_err: DecodeError,
+) -> Result<Vec<u8>, DecodeError> {
+    let len: u64 = VarInt::decode(buf).map_err(len_err)?.into();
+    let len = len as usize;
+    if buf.remaining() < len {
+        return Err(short_err);
+    }
+    let mut raw = vec![0; len];
+    buf.copy_to_slice(&mut raw);
+    Ok(raw)
+}
+
+impl Decodable for PartialInput {
+    type Error = DecodeError;
+
+    #[inline]
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        // Parse previous output
+        if buf.remaining() < 1 {
+            return Err(Self::Error::PrevOutputFlagTooShort);
+        }
+        let prev_output = match buf.get_u8() {
+            0 => None,
+            1 => Some(Output::decode(buf).map_err(Self::Error::PrevOutput)?),
+            flag => return Err(Self::Error::PrevOutputFlag(flag)),
+        };
+
+        // Parse signatures
+        let n_signatures: u64 = VarInt::decode(buf)
+            .map_err(Self::Error::SignatureCount)?
+            .into();
+        let signatures = (0..n_signatures)
+            .map(|_| {
+                let public_key = decode_bytes(
+                    buf,
+                    Self::Error::PublicKeyLen,
+                    Self::Error::PublicKeyTooShort,
+                )?;
+                let signature = decode_bytes(
+                    buf,
+                    Self::Error::SignatureLen,
+                    Self::Error::SignatureTooShort,
+                )?;
+                Ok(PartialSignature {
+                    public_key,
+                    signature,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(PartialInput {
+            prev_output,
+            signatures,
+        })
+    }
+}
+
+impl Decodable for PartiallySignedTransaction {
+    type Error = DecodeError;
+
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        // Check magic bytes
+        if buf.remaining() < MAGIC.len() {
+            return Err(Self::Error::Magic);
+        }
+        let mut magic = [0; 5];
+        buf.copy_to_slice(&mut magic);
+        if magic != MAGIC {
+            return Err(Self::Error::Magic);
+        }
+
+        // Parse unsigned transaction
+        let raw_transaction = decode_bytes(
+            buf,
+            Self::Error::TransactionLen,
+            Self::Error::TransactionTooShort,
+        )?;
+        let mut raw_transaction = raw_transaction.as_slice();
+        let transaction =
+            Transaction::decode(&mut raw_transaction).map_err(Self::Error::Transaction)?;
+        if !raw_transaction.is_empty() {
+            return Err(Self::Error::TransactionTrailingBytes);
+        }
+        if transaction
+            .inputs
+            .iter()
+            .any(|input| !input.script.is_empty())
+        {
+            return Err(Self::Error::Signed);
+        }
+
+        // Parse input metadata
+        let inputs = (0..transaction.inputs.len())
+            .map(|_| PartialInput::decode(buf))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(PartiallySignedTransaction {
+            transaction,
+            inputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{input::Input, outpoint::Outpoint};
+
+    fn test_psbt() -> PartiallySignedTransaction {
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![
+                Input {
+                    outpoint: Outpoint {
+                        tx_id: [1; 32],
+                        vout: 0,
+                    },
+                    script: vec![0xab, 0xcd].into(),
+                    sequence: 0xffff_ffff,
+                },
+                Input {
+                    outpoint: Outpoint {
+                        tx_id: [2; 32],
+                        vout: 3,
+                    },
+                    script: Script::default(),
+                    sequence: 0xffff_ffff,
+                },
+            ],
+            outputs: vec![Output {
+                value: 1_000,
+                script: vec![0x6a].into(),
+            }],
+            lock_time: 0,
+        };
+        let mut psbt = PartiallySignedTransaction::new(transaction);
+        psbt.inputs[0].prev_output = Some(Output {
+            value: 2_000,
+            script: vec![0x51].into(),
+        });
+        psbt.inputs[0].signatures.push(PartialSignature {
+            public_key: vec![2; 33],
+            signature: vec![3; 65],
+        });
+        psbt
+    }
+
+    #[test]
+    fn new_clears_scripts() {
+        let psbt = test_psbt();
+        assert!(psbt
+            .transaction
+            .inputs
+            .iter()
+            .all(|input| input.script.is_empty()));
+        assert_eq!(psbt.inputs.len(), 2);
+        assert!(!psbt.is_signed());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let psbt = test_psbt();
+
+        let mut raw_psbt = Vec::with_capacity(psbt.encoded_len());
+        psbt.encode(&mut raw_psbt).unwrap();
+        assert_eq!(raw_psbt.len(), psbt.encoded_len());
+
+        let decoded = PartiallySignedTransaction::decode(&mut raw_psbt.as_slice()).unwrap();
+        assert_eq!(decoded, psbt);
+    }
+
+    #[test]
+    fn decode_signed() {
+        let mut psbt = test_psbt();
+        psbt.transaction.inputs[1].script = vec![0xab].into();
+
+        let mut raw_psbt = Vec::with_capacity(psbt.encoded_len());
+        psbt.encode(&mut raw_psbt).unwrap();
+        assert_eq!(
+            PartiallySignedTransaction::decode(&mut raw_psbt.as_slice()),
+            Err(DecodeError::Signed)
+        );
+    }
+}
@@ -125,6 +125,33 @@ impl Transaction {
         merkle::sha256d(&buf)
     }
 
+    /// Calculate the fee paid by the transaction, given the values of the outputs spent by
+    /// each input.
+    ///
+    /// Returns `None` if the total output value exceeds the total input value.
+    #[inline]
+    pub fn fee(&self, input_values: &[u64]) -> Option<u64> {
+        let input_total = input_values
+            .iter()
+            .try_fold(0u64, |total, value| total.checked_add(*value))?;
+        let output_total = self
+            .outputs
+            .iter()
+            .try_fold(0u64, |total, output| total.checked_add(output.value))?;
+        input_total.checked_sub(output_total)
+    }
+
+    /// Calculate the fee required for the transaction to meet a fee rate, given in satoshis per
+    /// kilobyte.
+    ///
+    /// The size is taken from the current input scripts, so unsigned transactions should be
+    /// given placeholder scripts of the expected length.
+    #[inline]
+    pub fn required_fee(&self, fee_per_kb: u64) -> u64 {
+        let size = self.encoded_len() as u64;
+        size.saturating_mul(fee_per_kb).saturating_add(999) / 1000
+    }
+
     /// Calculate input count VarInt.
     #[inline]
     fn input_count_varint(&self) -> VarInt {
@@ -375,6 +402,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn fee() {
+        let tx = Transaction {
+            outputs: vec![
+                Output {
+                    value: 1_000,
+                    ..Default::default()
+                },
+                Output {
+                    value: 500,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(tx.fee(&[2_000]), Some(500));
+        assert_eq!(tx.fee(&[1_000, 500]), Some(0));
+        assert_eq!(tx.fee(&[1_000]), None);
+        assert_eq!(tx.fee(&[u64::MAX, 1]), None);
+    }
+
+    #[test]
+    fn required_fee() {
+        for hex_tx in test_txs() {
+            let raw_tx = hex::decode(hex_tx).unwrap();
+            let tx = Transaction::decode(&mut raw_tx.as_slice()).unwrap();
+
+            assert_eq!(tx.required_fee(1_000), raw_tx.len() as u64);
+            assert_eq!(tx.required_fee(0), 0);
+        }
+    }
+
     #[test]
     fn test_txid_calculations() {
         for (hex_tx, hex_txid) in test_txs_for_txid() {
@@ -24,6 +24,30 @@ impl From<Vec<u8>> for Script {
 }
 
 impl Script {
+    /// Construct a pay-to-pubkey-hash script.
+    #[inline]
+    pub fn p2pkh(pubkey_hash: &[u8; 20]) -> Self {
+        let mut raw_script = Vec::with_capacity(25);
+        raw_script.extend_from_slice(&[
+            opcodes::OP_DUP,
+            opcodes::OP_HASH160,
+            opcodes::OP_PUSHBYTES_20,
+        ]);
+        raw_script.extend_from_slice(pubkey_hash);
+        raw_script.extend_from_slice(&[opcodes::OP_EQUALVERIFY, opcodes::OP_CHECKSIG]);
+        Script(raw_script)
+    }
+
+    /// Construct a pay-to-script-hash script.
+    #[inline]
+    pub fn p2sh(script_hash: &[u8; 20]) -> Self {
+        let mut raw_script = Vec::with_capacity(23);
+        raw_script.extend_from_slice(&[opcodes::OP_HASH160, opcodes::OP_PUSHBYTES_20]);
+        raw_script.extend_from_slice(script_hash);
+        raw_script.push(opcodes::OP_EQUAL);
+        Script(raw_script)
+    }
+
     /// Check whether the script is empty.
     #[inline]
     pub fn is_empty(&self) -> bool {
@@ -70,6 +94,15 @@ impl Script {
             && self.0[23] == opcodes::OP_EQUALVERIFY
             && self.0[24] == opcodes::OP_CHECKSIG
     }
+
+    /// Checks whether the scripts the P2SH pattern.
+    #[inline]
+    pub fn is_p2sh(&self) -> bool {
+        self.0.len() == 23
+            && self.0[0] == opcodes::OP_HASH160
+            && self.0[1] == opcodes::OP_PUSHBYTES_20
+            && self.0[22] == opcodes::OP_EQUAL
+    }
 }
 
 impl Encodable for Script {
@@ -83,3 +116,23 @@ impl Encodable for Script {
         buf.put(&self.0[..]);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_scripts() {
+        let hash = [7; 20];
+
+        let p2pkh = Script::p2pkh(&hash);
+        assert!(p2pkh.is_p2pkh());
+        assert!(!p2pkh.is_p2sh());
+        assert_eq!(&p2pkh.as_bytes()[3..23], &hash[..]);
+
+        let p2sh = Script::p2sh(&hash);
+        assert!(p2sh.is_p2sh());
+        assert!(!p2sh.is_p2pkh());
+        assert_eq!(&p2sh.as_bytes()[2..22], &hash[..]);
+    }
+}
@@ -12,6 +12,9 @@ pub const OP_HASH160: u8 = 0xa9;
 /// OP_PUSHBYTES_20
 pub const OP_PUSHBYTES_20: u8 = 0x14;
 
+/// OP_EQUAL
+pub const OP_EQUAL: u8 = 0x87;
+
 /// OP_EQUALVERIFY
 pub const OP_EQUALVERIFY: u8 = 0x88;
 
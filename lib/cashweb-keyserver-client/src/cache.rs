@@ -0,0 +1,159 @@
+//! This module contains [`MetadataCache`] which allows verified metadata to be persisted locally, and
+//! [`FileCache`], an implementation backed by a directory on disk.
+
+use std::{
+    convert::TryInto,
+    fmt, fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use cashweb_auth_wrapper::AuthWrapper;
+use cashweb_keyserver::AddressMetadata;
+use hyper::Uri;
+use prost::Message as _;
+
+use crate::client::MetadataPackage;
+
+/// A verified [`MetadataPackage`] paired with the [`Uri`] it was fetched from and the time at which it was cached.
+#[derive(Clone, Debug)]
+pub struct CachedMetadata {
+    /// The [`Uri`] of the keyserver the metadata was fetched from.
+    pub uri: Uri,
+    /// The verified metadata.
+    pub package: MetadataPackage,
+    /// Unix timestamp, in milliseconds, at which the metadata was cached.
+    pub cached_at: u64,
+}
+
+impl CachedMetadata {
+    /// Create a cache entry, timestamped now.
+    pub fn new(uri: Uri, package: MetadataPackage) -> Self {
+        Self {
+            uri,
+            package,
+            cached_at: now_millis(),
+        }
+    }
+
+    /// Age of the entry in milliseconds.
+    pub fn age(&self) -> u64 {
+        now_millis().saturating_sub(self.cached_at)
+    }
+}
+
+/// A local store of the latest verified metadata, keyed by address.
+pub trait MetadataCache: fmt::Debug + Send + Sync {
+    /// Get the cached metadata of an address.
+    fn get(&self, address: &str) -> Option<CachedMetadata>;
+
+    /// Replace the cached metadata of an address.
+    fn put(&self, address: &str, cached: &CachedMetadata) -> io::Result<()>;
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Reconstruct a [`MetadataPackage`], verifying the signature of the [`AuthWrapper`].
+fn verify_package(token: String, raw_auth_wrapper: Bytes) -> Option<MetadataPackage> {
+    let parsed_auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.clone())
+        .ok()?
+        .parse()
+        .ok()?;
+    parsed_auth_wrapper.verify().ok()?;
+    let metadata = AddressMetadata::decode(&mut parsed_auth_wrapper.payload.as_slice()).ok()?;
+    Some(MetadataPackage {
+        token,
+        public_key: parsed_auth_wrapper.public_key,
+        metadata,
+        raw_auth_wrapper,
+    })
+}
+
+/// A [`MetadataCache`] storing one file per address within a directory.
+///
+/// Entries are re-verified when read, so a tampered or corrupted file is treated as a cache miss.
+#[derive(Clone, Debug)]
+pub struct FileCache {
+    directory: PathBuf,
+}
+
+impl FileCache {
+    /// Create a cache within a directory, creating the directory if it does not exist.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, address: &str) -> PathBuf {
+        let file_name: String = address
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.directory.join(file_name)
+    }
+}
+
+/// Split a length-prefixed field from the front of a buffer.
+fn split_field(raw: &[u8]) -> Option<(&[u8], &[u8])> {
+    if raw.len() < 4 {
+        return None;
+    }
+    let (raw_len, rest) = raw.split_at(4);
+    let len = u32::from_be_bytes(raw_len.try_into().unwrap()) as usize; // This is safe
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+impl MetadataCache for FileCache {
+    fn get(&self, address: &str) -> Option<CachedMetadata> {
+        let raw = fs::read(self.path(address)).ok()?;
+        if raw.len() < 8 {
+            return None;
+        }
+        let (raw_cached_at, rest) = raw.split_at(8);
+        let cached_at = u64::from_be_bytes(raw_cached_at.try_into().unwrap()); // This is safe
+        let (raw_uri, rest) = split_field(rest)?;
+        let (raw_token, raw_auth_wrapper) = split_field(rest)?;
+
+        let uri = std::str::from_utf8(raw_uri).ok()?.parse().ok()?;
+        let token = String::from_utf8(raw_token.to_vec()).ok()?;
+        let package = verify_package(token, Bytes::copy_from_slice(raw_auth_wrapper))?;
+        Some(CachedMetadata {
+            uri,
+            package,
+            cached_at,
+        })
+    }
+
+    fn put(&self, address: &str, cached: &CachedMetadata) -> io::Result<()> {
+        let uri = cached.uri.to_string();
+        let token = &cached.package.token;
+        let mut raw = Vec::with_capacity(
+            16 + uri.len() + token.len() + cached.package.raw_auth_wrapper.len(),
+        );
+        raw.extend_from_slice(&cached.cached_at.to_be_bytes());
+        raw.extend_from_slice(&(uri.len() as u32).to_be_bytes());
+        raw.extend_from_slice(uri.as_bytes());
+        raw.extend_from_slice(&(token.len() as u32).to_be_bytes());
+        raw.extend_from_slice(token.as_bytes());
+        raw.extend_from_slice(&cached.package.raw_auth_wrapper);
+
+        // Write then rename so that readers never observe a partial entry
+        let path = self.path(address);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&raw)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)
+    }
+}
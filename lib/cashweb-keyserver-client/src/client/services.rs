@@ -2,13 +2,17 @@
 
 use std::{fmt, pin::Pin};
 
+use bytes::{Buf, Bytes};
 use cashweb_auth_wrapper::{AuthWrapper, ParseError, VerifyError};
 use cashweb_keyserver::{AddressMetadata, Peers};
 use futures_core::{
     task::{Context, Poll},
     Future,
 };
-use futures_util::future::{join, join_all};
+use futures_util::{
+    future::join,
+    stream::{FuturesUnordered, StreamExt},
+};
 use hyper::{
     body::{aggregate, to_bytes},
     http::header::AUTHORIZATION,
@@ -309,6 +313,25 @@ pub enum PutMetadataError<E: fmt::Debug + fmt::Display> {
     /// Unexpected status code.
     #[error("unexpected status code: {0}")]
     UnexpectedStatusCode(u16),
+    /// The keyserver requires payment. Contains the encoded BIP70 payment request.
+    #[error("payment required")]
+    PaymentRequired(Bytes),
+}
+
+/// Check the response to a metadata put, reading the payment request if payment is required.
+async fn check_put_response<E: fmt::Debug + fmt::Display>(
+    response: Response<Body>,
+) -> Result<(), PutMetadataError<E>> {
+    match response.status() {
+        StatusCode::OK => Ok(()),
+        StatusCode::PAYMENT_REQUIRED => {
+            let payment_request = to_bytes(response.into_body())
+                .await
+                .map_err(|_| PutMetadataError::UnexpectedStatusCode(402))?;
+            Err(PutMetadataError::PaymentRequired(payment_request))
+        }
+        code => Err(PutMetadataError::UnexpectedStatusCode(code.as_u16())),
+    }
 }
 
 impl<S> Service<(Uri, PutMetadata)> for KeyserverClient<S>
@@ -350,13 +373,7 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
-            }
-
-            Ok(())
+            check_put_response(response).await
         };
         Box::pin(fut)
     }
@@ -409,25 +426,276 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
-            }
+            check_put_response(response).await
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Header in which a keyserver gives the timestamp at which the next page of synced messages
+/// starts.
+pub const NEXT_SINCE: &str = "Next-Since";
+
+/// Represents a request for the length-delimited stream of [`AuthWrapper`]s
+/// received by a keyserver.
+///
+/// The pages of the stream are followed until the keyserver indicates that no messages remain,
+/// failing if it sends more than `max_pages` pages or `max_items` messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncMessages {
+    /// The maximum number of pages followed.
+    pub max_pages: usize,
+    /// The maximum number of messages received.
+    pub max_items: usize,
+}
 
-            Ok(())
+impl Default for SyncMessages {
+    fn default() -> Self {
+        Self {
+            max_pages: 64,
+            max_items: 16_384,
+        }
+    }
+}
+
+/// Error associated with syncing [`AuthWrapper`]s from a keyserver.
+#[derive(Debug, Error)]
+pub enum SyncMessagesError<E: fmt::Debug + fmt::Display> {
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Error while decoding the body.
+    #[error("body decoding failure: {0}")]
+    Decode(prost::DecodeError),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+    /// The next page cursor was malformed or did not advance.
+    #[error("invalid next page cursor")]
+    InvalidCursor,
+    /// The keyserver sent more pages or messages than allowed.
+    #[error("sync limit exceeded")]
+    LimitExceeded,
+}
+
+impl<S> Service<(Uri, SyncMessages)> for KeyserverClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = Vec<AuthWrapper>;
+    type Error = SyncMessagesError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(SyncMessagesError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, SyncMessages)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        let fut = async move {
+            let mut auth_wrappers = Vec::new();
+            let mut page_uri = uri.clone();
+            let mut last_since = None;
+            for _ in 0..request.max_pages {
+                let http_request = Request::builder()
+                    .method(Method::GET)
+                    .uri(page_uri)
+                    .body(Body::empty())
+                    .unwrap(); // This is safe
+
+                // Get response
+                let response = client
+                    .call(http_request)
+                    .await
+                    .map_err(Self::Error::Service)?;
+
+                // Check status code
+                match response.status() {
+                    StatusCode::OK => (),
+                    code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+                }
+
+                // Parse the next page cursor
+                let opt_next_since = response
+                    .headers()
+                    .get(NEXT_SINCE)
+                    .map(|value| {
+                        value
+                            .to_str()
+                            .ok()
+                            .and_then(|value| value.parse::<i64>().ok())
+                            .ok_or(Self::Error::InvalidCursor)
+                    })
+                    .transpose()?;
+
+                // Decode length-delimited auth wrappers
+                let body = response.into_body();
+                let mut buf = aggregate(body).await.map_err(Self::Error::Body)?;
+                while buf.has_remaining() {
+                    if auth_wrappers.len() == request.max_items {
+                        return Err(Self::Error::LimitExceeded);
+                    }
+                    let auth_wrapper = AuthWrapper::decode_length_delimited(&mut buf)
+                        .map_err(Self::Error::Decode)?;
+                    auth_wrappers.push(auth_wrapper);
+                }
+
+                // Follow the cursor, which must advance
+                let next_since = match opt_next_since {
+                    Some(some) => some,
+                    None => return Ok(auth_wrappers),
+                };
+                if last_since.map_or(false, |last_since| next_since <= last_since) {
+                    return Err(Self::Error::InvalidCursor);
+                }
+                last_since = Some(next_since);
+                let mut parts = uri.clone().into_parts();
+                parts.path_and_query = Some(
+                    format!("{}?since={}", uri.path(), next_since)
+                        .parse()
+                        .map_err(|_| Self::Error::InvalidCursor)?,
+                );
+                page_uri = Uri::from_parts(parts).map_err(|_| Self::Error::InvalidCursor)?;
+            }
+            Err(Self::Error::LimitExceeded)
         };
         Box::pin(fut)
     }
 }
 
+/// Policy deciding whether a sample of keyservers succeeded.
+///
+/// The default policy succeeds when at least one keyserver responds successfully.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplePolicy {
+    /// The minimum number of successful responses.
+    pub min_successes: usize,
+    /// The minimum fraction, between 0 and 1, of sampled keyservers which must respond
+    /// successfully.
+    pub min_fraction: f64,
+    /// Stop waiting for outstanding responses once this many requests have failed.
+    pub max_failures: Option<usize>,
+    /// Stop waiting for outstanding responses once the quorum can no longer be met.
+    pub fail_fast: bool,
+}
+
+impl Default for SamplePolicy {
+    fn default() -> Self {
+        Self {
+            min_successes: 1,
+            min_fraction: 0.,
+            max_failures: None,
+            fail_fast: false,
+        }
+    }
+}
+
+impl SamplePolicy {
+    /// Create a policy requiring at least `min_successes` successful responses.
+    pub fn quorum(min_successes: usize) -> Self {
+        Self {
+            min_successes,
+            ..Default::default()
+        }
+    }
+
+    /// Require at least a fraction of the sampled keyservers to respond successfully.
+    pub fn with_fraction(mut self, min_fraction: f64) -> Self {
+        self.min_fraction = min_fraction;
+        self
+    }
+
+    /// Stop waiting for outstanding responses once `max_failures` requests have failed.
+    pub fn with_max_failures(mut self, max_failures: usize) -> Self {
+        self.max_failures = Some(max_failures);
+        self
+    }
+
+    /// Stop waiting for outstanding responses once the quorum can no longer be met.
+    pub fn with_fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    /// The number of successful responses required from a sample of the given size.
+    pub fn required(&self, sample_size: usize) -> usize {
+        let from_fraction =
+            (self.min_fraction.max(0.).min(1.) * sample_size as f64).ceil() as usize;
+        self.min_successes.max(from_fraction)
+    }
+
+    /// Whether to stop waiting for outstanding responses.
+    fn should_stop(&self, sample_size: usize, failures: usize) -> bool {
+        if self
+            .max_failures
+            .map_or(false, |max_failures| failures >= max_failures)
+        {
+            return true;
+        }
+        self.fail_fast && sample_size - failures < self.required(sample_size)
+    }
+}
+
 /// Request for performing multiple requests to a range of keyservers.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SampleRequest<T> {
     /// The [`Uri`]s of the targetted keyservers.
     pub uris: Vec<Uri>,
     /// The request to be broadcast.
     pub request: T,
+    /// The policy deciding whether the sample succeeded.
+    pub policy: SamplePolicy,
+}
+
+/// Report of the responses to a [`SampleRequest`].
+#[derive(Debug)]
+pub struct SampleReport<R, E> {
+    /// The results paired with the [`Uri`] of the keyserver they originated at.
+    pub responses: Vec<(Uri, Result<R, E>)>,
+    /// The [`Uri`]s of keyservers whose responses were not waited for.
+    pub cancelled: Vec<Uri>,
+    /// The number of successful responses required by the [`SamplePolicy`].
+    pub required: usize,
+}
+
+impl<R, E> SampleReport<R, E> {
+    /// The number of successful responses.
+    pub fn successes(&self) -> usize {
+        self.responses.iter().filter(|(_, res)| res.is_ok()).count()
+    }
+
+    /// The number of failed requests.
+    pub fn failures(&self) -> usize {
+        self.responses.len() - self.successes()
+    }
+
+    /// Whether enough keyservers responded successfully.
+    pub fn quorum_met(&self) -> bool {
+        self.successes() >= self.required
+    }
+
+    /// Split the report into its successful responses and errors.
+    #[allow(clippy::type_complexity)]
+    pub fn partition(self) -> (Vec<(Uri, R)>, Vec<(Uri, E)>) {
+        let mut oks = Vec::new();
+        let mut errors = Vec::new();
+        for (uri, result) in self.responses {
+            match result {
+                Ok(ok) => oks.push((uri, ok)),
+                Err(err) => errors.push((uri, err)),
+            }
+        }
+        (oks, errors)
+    }
 }
 
 /// Error associated with sending sample requests.
@@ -439,6 +707,17 @@ pub enum SampleError<E: fmt::Debug + fmt::Display> {
     /// Sample totally failed. Contains errors paired with the [`Uri`] of the keyserver they originated at.
     #[error("sampling failure: {0:?}")] // TODO: Make this prettier
     Sample(Vec<(Uri, E)>),
+    /// Too few keyservers responded successfully to meet the [`SamplePolicy`]. Contains errors
+    /// paired with the [`Uri`] of the keyserver they originated at.
+    #[error("quorum not met: {successes} of {required} required successes: {errors:?}")]
+    Quorum {
+        /// The number of successful responses required.
+        required: usize,
+        /// The number of successful responses.
+        successes: usize,
+        /// The errors paired with the [`Uri`] of the keyserver they originated at.
+        errors: Vec<(Uri, E)>,
+    },
 }
 
 impl<S, T> Service<SampleRequest<T>> for KeyserverClient<S>
@@ -450,11 +729,8 @@ where
     <Self as Service<(Uri, T)>>::Error: fmt::Debug + fmt::Display + Send,
     <Self as Service<(Uri, T)>>::Future: Send,
 {
-    #[allow(clippy::type_complexity)]
-    type Response = Vec<(
-        Uri,
-        Result<<Self as Service<(Uri, T)>>::Response, <Self as Service<(Uri, T)>>::Error>,
-    )>;
+    type Response =
+        SampleReport<<Self as Service<(Uri, T)>>::Response, <Self as Service<(Uri, T)>>::Error>;
     type Error = SampleError<<Self as Service<(Uri, T)>>::Error>;
     type Future = FutResponse<Self::Response, Self::Error>;
 
@@ -462,28 +738,66 @@ where
         self.poll_ready(context).map_err(SampleError::Poll)
     }
 
-    fn call(&mut self, SampleRequest { uris, request }: SampleRequest<T>) -> Self::Future {
+    fn call(
+        &mut self,
+        SampleRequest {
+            uris,
+            request,
+            policy,
+        }: SampleRequest<T>,
+    ) -> Self::Future {
         let mut inner_client = self.clone();
 
         let fut = async move {
+            let sample_size = uris.len();
+            let required = policy.required(sample_size);
+
             // Collect futures
-            let response_futs = uris.into_iter().map(move |uri| {
-                let response_fut = inner_client.call((uri.clone(), request.clone()));
-                let uri_fut = async move { uri };
-                join(uri_fut, response_fut)
-            });
-            let responses: Vec<(Uri, Result<_, _>)> = join_all(response_futs).await;
-
-            // If no successes then return all errors
-            if responses.iter().all(|(_, res)| res.is_err()) {
-                let errors = responses
-                    .into_iter()
-                    .map(|(uri, result)| (uri, result.unwrap_err()))
-                    .collect();
-                return Err(SampleError::Sample(errors));
+            let mut pending = uris.clone();
+            let mut response_futs: FuturesUnordered<_> = uris
+                .into_iter()
+                .map(move |uri| {
+                    let response_fut = inner_client.call((uri.clone(), request.clone()));
+                    let uri_fut = async move { uri };
+                    join(uri_fut, response_fut)
+                })
+                .collect();
+
+            // Await responses, stopping early if the policy allows
+            let mut responses: Vec<(Uri, Result<_, _>)> = Vec::with_capacity(sample_size);
+            let mut failures = 0;
+            while let Some((uri, result)) = response_futs.next().await {
+                pending.retain(|pending_uri| pending_uri != &uri);
+                if result.is_err() {
+                    failures += 1;
+                }
+                responses.push((uri, result));
+                if policy.should_stop(sample_size, failures) {
+                    break;
+                }
+            }
+            let report = SampleReport {
+                responses,
+                cancelled: pending,
+                required,
+            };
+
+            if !report.quorum_met() {
+                let successes = report.successes();
+                let (_, errors) = report.partition();
+
+                // If no successes then return all errors
+                if successes == 0 {
+                    return Err(SampleError::Sample(errors));
+                }
+                return Err(SampleError::Quorum {
+                    required,
+                    successes,
+                    errors,
+                });
             }
 
-            Ok(responses)
+            Ok(report)
         };
         Box::pin(fut)
     }
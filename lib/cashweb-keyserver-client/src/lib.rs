@@ -9,8 +9,10 @@
 //! interaction with specific keyservers and [`KeyserverManager`]
 //! which allows sampling and aggregation over multiple keyservers.
 
+mod cache;
 mod client;
 mod manager;
 
+pub use cache::*;
 pub use client::*;
 pub use manager::*;
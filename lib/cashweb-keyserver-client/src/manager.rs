@@ -1,7 +1,15 @@
-use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
+use bytes::Bytes;
 use cashweb_auth_wrapper::AuthWrapper;
-use cashweb_keyserver::{Peer, Peers};
+use cashweb_keyserver::{Peer, Peers, ServerInfo};
+use futures_util::future::join_all;
 use hyper::{
     client::Client as HyperClient,
     client::HttpConnector,
@@ -15,8 +23,12 @@ use tower_service::Service;
 use tower_util::ServiceExt;
 
 use crate::{
+    cache::{CachedMetadata, MetadataCache},
     client::{KeyserverClient, MetadataPackage},
-    services::{GetMetadata, GetPeers, PutMetadata, PutRawAuthWrapper, SampleError, SampleRequest},
+    services::{
+        GetMetadata, GetPeers, PutMetadata, PutMetadataError, PutRawAuthWrapper, SampleError,
+        SamplePolicy, SampleRequest, SyncMessages,
+    },
 };
 
 /// KeyserverManager wraps a client and allows sampling and selecting of queries across a set of keyservers.
@@ -24,6 +36,7 @@ use crate::{
 pub struct KeyserverManager<S> {
     inner_client: KeyserverClient<S>,
     uris: Arc<RwLock<Vec<Uri>>>,
+    cache: Option<(Arc<dyn MetadataCache>, Duration)>,
 }
 
 impl<S> KeyserverManager<S> {
@@ -32,9 +45,20 @@ impl<S> KeyserverManager<S> {
         Self {
             inner_client: KeyserverClient::from_service(service),
             uris: Arc::new(RwLock::new(uris)),
+            cache: None,
         }
     }
 
+    /// Attach a [`MetadataCache`] to the manager.
+    ///
+    /// Cached metadata younger than `max_age` is returned without sampling keyservers. Older
+    /// cached metadata is returned when sampling fails, or when it is newer than the sampled
+    /// metadata.
+    pub fn with_cache<C: MetadataCache + 'static>(mut self, cache: C, max_age: Duration) -> Self {
+        self.cache = Some((Arc::new(cache), max_age));
+        self
+    }
+
     /// Get shared reference the [`Uri`]s.
     pub fn get_uris(&self) -> Arc<RwLock<Vec<Uri>>> {
         self.uris.clone()
@@ -54,6 +78,7 @@ impl KeyserverManager<HyperClient<HttpConnector>> {
         Ok(Self {
             inner_client: KeyserverClient::new(),
             uris: Arc::new(RwLock::new(uris)),
+            cache: None,
         })
     }
 }
@@ -115,7 +140,16 @@ pub fn aggregate_peers(peers: Vec<(Uri, Peers)>) -> Peers {
         .map(move |(_, peer)| peer.peers)
         .flatten()
         .collect();
-    Peers { peers }
+    Peers { peers, info: None }
+}
+
+/// Aggregate a collection of [`AuthWrapper`]s into a single list.
+pub fn aggregate_auth_wrappers(auth_wrappers: Vec<(Uri, Vec<AuthWrapper>)>) -> Vec<AuthWrapper> {
+    auth_wrappers
+        .into_iter()
+        .map(move |(_, auth_wrappers)| auth_wrappers)
+        .flatten()
+        .collect()
 }
 
 /// Response to a sample query.
@@ -190,6 +224,144 @@ where
     }
 }
 
+/// A set of keyservers which returned the same payload digest during a cross-check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DigestGroup {
+    /// The payload digest of the [`AuthWrapper`] returned.
+    pub payload_digest: Vec<u8>,
+    /// The timestamp of the returned metadata.
+    pub timestamp: i64,
+    /// The [`Uri`]s of the keyservers which returned the payload digest.
+    pub uris: Vec<Uri>,
+}
+
+/// Report of the agreement between keyservers during a cross-check.
+#[derive(Debug)]
+pub struct CrossCheckReport<E> {
+    /// Keyservers grouped by the payload digest they returned, newest metadata first.
+    pub groups: Vec<DigestGroup>,
+    /// The [`Uri`]s of keyservers which returned an [`AuthWrapper`] which could not be decoded.
+    pub malformed: Vec<Uri>,
+    /// The errors paired with the [`Uri`] of the keyserver they originated at.
+    pub errors: Vec<(Uri, E)>,
+}
+
+impl<E> CrossCheckReport<E> {
+    /// Create a cross-check report from a list of results.
+    pub fn from_responses(responses: Vec<(Uri, Result<MetadataPackage, E>)>) -> Self {
+        let mut groups: Vec<DigestGroup> = Vec::new();
+        let mut malformed = Vec::new();
+        let mut errors = Vec::new();
+        for (uri, result) in responses {
+            let package = match result {
+                Ok(ok) => ok,
+                Err(err) => {
+                    errors.push((uri, err));
+                    continue;
+                }
+            };
+            let payload_digest = match AuthWrapper::decode(&package.raw_auth_wrapper[..]) {
+                Ok(auth_wrapper) => auth_wrapper.digest(),
+                Err(_) => {
+                    malformed.push(uri);
+                    continue;
+                }
+            };
+            match groups
+                .iter_mut()
+                .find(|group| group.payload_digest == payload_digest)
+            {
+                Some(group) => group.uris.push(uri),
+                None => groups.push(DigestGroup {
+                    payload_digest,
+                    timestamp: package.metadata.timestamp,
+                    uris: vec![uri],
+                }),
+            }
+        }
+        groups.sort_by(|a, b| {
+            b.timestamp
+                .cmp(&a.timestamp)
+                .then_with(|| b.uris.len().cmp(&a.uris.len()))
+        });
+
+        CrossCheckReport {
+            groups,
+            malformed,
+            errors,
+        }
+    }
+
+    /// Whether every responding keyserver returned the same payload digest.
+    pub fn is_consistent(&self) -> bool {
+        self.groups.len() <= 1 && self.malformed.is_empty()
+    }
+
+    /// The group of keyservers which returned the newest metadata.
+    pub fn latest(&self) -> Option<&DigestGroup> {
+        self.groups.first()
+    }
+
+    /// The [`Uri`]s of keyservers which did not return the newest metadata, these may be stale or
+    /// byzantine.
+    pub fn divergent(&self) -> impl Iterator<Item = &Uri> {
+        self.groups
+            .iter()
+            .skip(1)
+            .flat_map(|group| group.uris.iter())
+            .chain(self.malformed.iter())
+    }
+}
+
+/// Outcome of putting metadata to a single keyserver during a broadcast.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BroadcastOutcome<E> {
+    /// The keyserver accepted the metadata.
+    Accepted,
+    /// The keyserver requires payment. Contains the encoded BIP70 payment request, which must be
+    /// paid to obtain a token before retrying.
+    PaymentRequired(Bytes),
+    /// The put failed.
+    Failed(E),
+}
+
+/// Report of the outcomes of a broadcast, paired with the [`Uri`] of each keyserver.
+#[derive(Debug)]
+pub struct BroadcastReport<E> {
+    /// The outcome of the put to each keyserver.
+    pub outcomes: Vec<(Uri, BroadcastOutcome<E>)>,
+}
+
+impl<E> BroadcastReport<E> {
+    /// The [`Uri`]s of keyservers which accepted the metadata.
+    pub fn accepted(&self) -> impl Iterator<Item = &Uri> {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, BroadcastOutcome::Accepted))
+            .map(|(uri, _)| uri)
+    }
+
+    /// The [`Uri`]s of keyservers which require payment, paired with their payment request.
+    pub fn payment_required(&self) -> impl Iterator<Item = (&Uri, &Bytes)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(uri, outcome)| match outcome {
+                BroadcastOutcome::PaymentRequired(payment_request) => Some((uri, payment_request)),
+                _ => None,
+            })
+    }
+
+    /// The errors paired with the [`Uri`] of the keyserver they originated at.
+    pub fn failed(&self) -> impl Iterator<Item = (&Uri, &E)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(uri, outcome)| match outcome {
+                BroadcastOutcome::Failed(err) => Some((uri, err)),
+                _ => None,
+            })
+    }
+}
+
 impl<S> KeyserverManager<S>
 where
     S: Service<Request<Body>, Response = Response<Body>>,
@@ -198,6 +370,8 @@ where
     S::Error: fmt::Debug + fmt::Display + Send,
 {
     /// Perform a uniform sample of metadata over keyservers and select the latest.
+    ///
+    /// If a [`MetadataCache`] is attached it is consulted before sampling and updated after.
     pub async fn uniform_sample_metadata(
         &self,
         address: &str,
@@ -206,6 +380,37 @@ where
         SampleResponse<MetadataPackage, <KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
     > {
+        self.uniform_sample_metadata_with_policy(address, sample_size, SamplePolicy::default())
+            .await
+    }
+
+    /// Perform a uniform sample of metadata over keyservers and select the latest, failing unless
+    /// the sample satisfies the [`SamplePolicy`].
+    ///
+    /// If a [`MetadataCache`] is attached it is consulted before sampling and updated after.
+    pub async fn uniform_sample_metadata_with_policy(
+        &self,
+        address: &str,
+        sample_size: usize,
+        policy: SamplePolicy,
+    ) -> Result<
+        SampleResponse<MetadataPackage, <KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
+        SampleError<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
+    > {
+        // Consult cache
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|(cache, _)| cache.get(address));
+        if let (Some(cached), Some((_, max_age))) = (&cached, &self.cache) {
+            if cached.age() < max_age.as_millis() as u64 {
+                return Ok(SampleResponse {
+                    response: Some((cached.uri.clone(), cached.package.clone())),
+                    errors: Vec::new(),
+                });
+            }
+        }
+
         let uris = self.uris.read().await.clone();
         let uris = uris
             .into_iter()
@@ -215,14 +420,94 @@ where
         let sample_request = SampleRequest {
             request: GetMetadata,
             uris,
+            policy,
         };
 
-        let responses = self.inner_client.clone().oneshot(sample_request).await?;
-        let sample_response = SampleResponse::select(responses, select_auth_wrapper);
+        let responses = match self.inner_client.clone().oneshot(sample_request).await {
+            Ok(ok) => ok.responses,
+            Err(SampleError::Sample(errors)) => {
+                // Fallback to cache
+                if let Some(cached) = cached {
+                    return Ok(SampleResponse {
+                        response: Some((cached.uri, cached.package)),
+                        errors,
+                    });
+                }
+                return Err(SampleError::Sample(errors));
+            }
+            Err(SampleError::Quorum {
+                required,
+                successes,
+                errors,
+            }) => {
+                // Fallback to cache
+                if let Some(cached) = cached {
+                    return Ok(SampleResponse {
+                        response: Some((cached.uri, cached.package)),
+                        errors,
+                    });
+                }
+                return Err(SampleError::Quorum {
+                    required,
+                    successes,
+                    errors,
+                });
+            }
+            Err(err) => return Err(err),
+        };
+        let mut sample_response = SampleResponse::select(responses, select_auth_wrapper);
+
+        // Update cache, never replacing cached metadata with older metadata
+        if let Some((cache, _)) = &self.cache {
+            let sampled = sample_response.response.take();
+            let selected = match (sampled, cached) {
+                (Some(sampled), Some(cached))
+                    if cached.package.metadata.timestamp > sampled.1.metadata.timestamp =>
+                {
+                    Some((cached.uri, cached.package))
+                }
+                (Some((uri, package)), _) => {
+                    // Failure to cache does not affect the response
+                    let _ = cache.put(address, &CachedMetadata::new(uri.clone(), package.clone()));
+                    Some((uri, package))
+                }
+                (None, cached) => cached.map(|cached| (cached.uri, cached.package)),
+            };
+            sample_response.response = selected;
+        }
 
         Ok(sample_response)
     }
 
+    /// Fetch metadata from each of the given keyservers and compare the payload digests returned.
+    pub async fn cross_check(
+        &self,
+        uris: Vec<Uri>,
+        address: &str,
+    ) -> Result<
+        CrossCheckReport<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
+        SampleError<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
+    > {
+        let uris = uris
+            .into_iter()
+            .map(|uri| append_path(uri, &format!("/keys/{}", address)))
+            .collect::<Vec<Uri>>();
+        let sample_request = SampleRequest {
+            request: GetMetadata,
+            uris,
+            policy: SamplePolicy::default(),
+        };
+
+        let responses = self
+            .inner_client
+            .clone()
+            .oneshot(sample_request)
+            .await?
+            .responses;
+
+        Ok(CrossCheckReport::from_responses(responses))
+    }
+
     /// Collect all peers from keyservers.
     pub async fn collect_peers(
         &self,
@@ -238,15 +523,56 @@ where
         let sample_request = SampleRequest {
             uris,
             request: GetPeers,
+            policy: SamplePolicy::default(),
         };
-        let responses = self.inner_client.clone().oneshot(sample_request).await?;
+        let responses = self
+            .inner_client
+            .clone()
+            .oneshot(sample_request)
+            .await?
+            .responses;
 
         let aggregate_response = AggregateResponse::aggregate(responses, aggregate_peers);
 
         Ok(aggregate_response)
     }
 
+    /// Collect all messages received by the keyservers since a unix timestamp, given in milliseconds.
+    pub async fn collect_messages(
+        &self,
+        since: i64,
+    ) -> Result<
+        AggregateResponse<
+            Vec<AuthWrapper>,
+            <KeyserverClient<S> as Service<(Uri, SyncMessages)>>::Error,
+        >,
+        SampleError<<KeyserverClient<S> as Service<(Uri, SyncMessages)>>::Error>,
+    > {
+        let uris = self.uris.read().await.clone();
+        let uris = uris
+            .into_iter()
+            .map(|uri| append_path(uri, &format!("/messages/sync?since={}", since)))
+            .collect::<Vec<Uri>>();
+        let sample_request = SampleRequest {
+            uris,
+            request: SyncMessages::default(),
+            policy: SamplePolicy::default(),
+        };
+        let responses = self
+            .inner_client
+            .clone()
+            .oneshot(sample_request)
+            .await?
+            .responses;
+
+        let aggregate_response = AggregateResponse::aggregate(responses, aggregate_auth_wrappers);
+
+        Ok(aggregate_response)
+    }
+
     /// Crawl peers.
+    ///
+    /// The [`ServerInfo`] advertised by each peer which responded is attached to its [`Peer`].
     #[allow(clippy::mutable_key_type)]
     pub async fn crawl_peers(
         &self,
@@ -259,6 +585,7 @@ where
 
         let mut total: HashSet<_> = read_uris.iter().cloned().collect();
 
+        let mut infos: HashMap<Uri, ServerInfo> = HashMap::new();
         let mut total_errors = Vec::new();
         while !found_uris.is_empty() {
             // Get sample
@@ -269,8 +596,24 @@ where
             let sample_request = SampleRequest {
                 uris,
                 request: GetPeers,
+                policy: SamplePolicy::default(),
             };
-            let responses: Vec<_> = self.inner_client.clone().oneshot(sample_request).await?;
+            let responses: Vec<_> = self
+                .inner_client
+                .clone()
+                .oneshot(sample_request)
+                .await?
+                .responses;
+
+            // Record advertised info
+            for (uri, result) in &responses {
+                if let Ok(Peers {
+                    info: Some(info), ..
+                }) = result
+                {
+                    infos.insert(uri.clone(), info.clone());
+                }
+            }
 
             let AggregateResponse { response, errors } =
                 AggregateResponse::aggregate(responses, aggregate_peers);
@@ -295,8 +638,10 @@ where
                 .into_iter()
                 .map(|uri| Peer {
                     url: uri.to_string(),
+                    info: infos.get(&append_path(uri, "/peers")).cloned(),
                 })
                 .collect(),
+            info: None,
         };
         Ok(AggregateResponse {
             response,
@@ -329,8 +674,17 @@ where
             token,
             raw_auth_wrapper,
         };
-        let sample_request = SampleRequest { uris, request };
-        let responses = self.inner_client.clone().call(sample_request).await?;
+        let sample_request = SampleRequest {
+            uris,
+            request,
+            policy: SamplePolicy::default(),
+        };
+        let responses = self
+            .inner_client
+            .clone()
+            .call(sample_request)
+            .await?
+            .responses;
 
         Ok(AggregateResponse::aggregate(responses, |_| ()))
     }
@@ -356,9 +710,80 @@ where
             token,
             raw_auth_wrapper,
         };
-        let sample_request = SampleRequest { uris, request };
-        let responses = self.inner_client.clone().call(sample_request).await?;
+        let sample_request = SampleRequest {
+            uris,
+            request,
+            policy: SamplePolicy::default(),
+        };
+        let responses = self
+            .inner_client
+            .clone()
+            .call(sample_request)
+            .await?
+            .responses;
 
         Ok(AggregateResponse::aggregate(responses, |_| ()))
     }
+
+    /// Put raw metadata to each of the given keyservers concurrently.
+    ///
+    /// Results are paired with the [`Uri`] of the keyserver, as given, rather than the
+    /// [`Uri`] of the metadata.
+    pub async fn broadcast_raw_metadata(
+        &self,
+        address: &str,
+        raw_auth_wrapper: Vec<u8>,
+        token: String,
+        uris: Vec<Uri>,
+    ) -> Vec<(
+        Uri,
+        Result<(), <KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
+    )> {
+        let put_futs = uris.into_iter().map(|uri| {
+            let request = PutRawAuthWrapper {
+                token: token.clone(),
+                raw_auth_wrapper: raw_auth_wrapper.clone(),
+            };
+            let keys_uri = append_path(uri.clone(), &format!("/keys/{}", address));
+            let put_fut = self.inner_client.clone().oneshot((keys_uri, request));
+            async move { (uri, put_fut.await) }
+        });
+        join_all(put_futs).await
+    }
+
+    /// Put metadata to each of the given keyservers concurrently.
+    ///
+    /// The POP token for each keyserver is looked up in `token_map` by its [`Uri`]. Keyservers
+    /// without a token, or which reject their token, report the payment they require so that the
+    /// put can be retried once paid.
+    #[allow(clippy::mutable_key_type)]
+    pub async fn broadcast_put(
+        &self,
+        address: &str,
+        auth_wrapper: AuthWrapper,
+        uris: Vec<Uri>,
+        token_map: &HashMap<Uri, String>,
+    ) -> BroadcastReport<<KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error> {
+        let put_futs = uris.into_iter().map(|uri| {
+            let request = PutMetadata {
+                token: token_map.get(&uri).cloned().unwrap_or_default(),
+                auth_wrapper: auth_wrapper.clone(),
+            };
+            let keys_uri = append_path(uri.clone(), &format!("/keys/{}", address));
+            let put_fut = self.inner_client.clone().oneshot((keys_uri, request));
+            async move {
+                let outcome = match put_fut.await {
+                    Ok(()) => BroadcastOutcome::Accepted,
+                    Err(PutMetadataError::PaymentRequired(payment_request)) => {
+                        BroadcastOutcome::PaymentRequired(payment_request)
+                    }
+                    Err(err) => BroadcastOutcome::Failed(err),
+                };
+                (uri, outcome)
+            }
+        });
+        let outcomes = join_all(put_futs).await;
+
+        BroadcastReport { outcomes }
+    }
 }
@@ -0,0 +1,125 @@
+//! This module contains helpers for building and reading the [`Entry`]s of an [`AddressMetadata`].
+
+use std::str::{from_utf8, Utf8Error};
+
+use crate::{AddressMetadata, Entry, Header};
+
+/// The [`Entry`] kind whose body is the UTF-8 URL of the address' relay server.
+pub const P2P_KIND: &str = "p2p";
+
+/// The [`Entry`] kind whose body is a serialized public key.
+pub const PUBKEY_KIND: &str = "pubkey";
+
+impl Entry {
+    /// Construct a new [`Entry`] with the given kind and body.
+    pub fn new(kind: impl Into<String>, body: Vec<u8>) -> Self {
+        Self {
+            kind: kind.into(),
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// Construct a new [`Entry`] with the given kind and a UTF-8 body.
+    pub fn text(kind: impl Into<String>, body: impl Into<String>) -> Self {
+        Self::new(kind, body.into().into_bytes())
+    }
+
+    /// Add a header to the [`Entry`].
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push(Header {
+            name: name.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Get the value of the first header with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name == name)
+            .map(|header| header.value.as_str())
+    }
+
+    /// Interpret the body as UTF-8.
+    pub fn body_text(&self) -> Result<&str, Utf8Error> {
+        from_utf8(&self.body)
+    }
+}
+
+impl AddressMetadata {
+    /// Get the first [`Entry`] of the given kind.
+    pub fn entry(&self, kind: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.kind == kind)
+    }
+
+    /// Iterate over the [`Entry`]s of the given kind.
+    pub fn entries_by_kind<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Entry> + 'a {
+        self.entries.iter().filter(move |entry| entry.kind == kind)
+    }
+
+    /// Set an [`Entry`], replacing any existing entries of the same kind.
+    pub fn set_entry(&mut self, entry: Entry) {
+        self.remove_entries(&entry.kind);
+        self.entries.push(entry);
+    }
+
+    /// Remove all [`Entry`]s of the given kind, returning the number removed.
+    pub fn remove_entries(&mut self, kind: &str) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.kind != kind);
+        before - self.entries.len()
+    }
+
+    /// Get the relay server URL.
+    pub fn relay_url(&self) -> Option<Result<&str, Utf8Error>> {
+        self.entry(P2P_KIND).map(Entry::body_text)
+    }
+
+    /// Set the relay server URL.
+    pub fn set_relay_url(&mut self, url: impl Into<String>) {
+        self.set_entry(Entry::text(P2P_KIND, url));
+    }
+
+    /// Get the serialized public key.
+    pub fn pubkey(&self) -> Option<&[u8]> {
+        self.entry(PUBKEY_KIND).map(|entry| entry.body.as_slice())
+    }
+
+    /// Set the serialized public key.
+    pub fn set_pubkey(&mut self, pubkey: Vec<u8>) {
+        self.set_entry(Entry::new(PUBKEY_KIND, pubkey));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_get_entries() {
+        let mut metadata = AddressMetadata::default();
+        assert!(metadata.relay_url().is_none());
+        assert!(metadata.pubkey().is_none());
+
+        metadata.set_relay_url("https://relay.example.com");
+        metadata.set_pubkey(vec![2; 33]);
+        metadata.set_relay_url("https://relay2.example.com");
+        metadata
+            .entries
+            .push(Entry::text("telegram", "@alice").with_header("visibility", "public"));
+
+        assert_eq!(metadata.entries.len(), 3);
+        assert_eq!(metadata.relay_url(), Some(Ok("https://relay2.example.com")));
+        assert_eq!(metadata.pubkey(), Some(&[2; 33][..]));
+
+        let telegram = metadata.entry("telegram").unwrap();
+        assert_eq!(telegram.body_text(), Ok("@alice"));
+        assert_eq!(telegram.header("visibility"), Some("public"));
+        assert_eq!(telegram.header("missing"), None);
+
+        assert_eq!(metadata.remove_entries(PUBKEY_KIND), 1);
+        assert_eq!(metadata.entries_by_kind(PUBKEY_KIND).count(), 0);
+    }
+}
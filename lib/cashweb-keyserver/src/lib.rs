@@ -1,3 +1,7 @@
 #![warn(missing_debug_implementations, rust_2018_idioms, unreachable_pub)]
 
 include!(concat!(env!("OUT_DIR"), "/keyserver.rs"));
+
+pub mod entries;
+
+pub use entries::{P2P_KIND, PUBKEY_KIND};
@@ -6,16 +6,19 @@
 )]
 
 //! `cashweb-payments` is a library providing structures and utilities related to
-//! the [`BIP70: Payment Protocol`] and a [`Wallet`] structure to allow receiving
-//! payments.
+//! the [`BIP70: Payment Protocol`], a [`Wallet`] structure to allow receiving
+//! payments and a [`ZeroConfPolicy`] to guard against unconfirmed payments being double-spent.
 //!
 //! [`Wallet`]: wallet::Wallet
+//! [`ZeroConfPolicy`]: zeroconf::ZeroConfPolicy
 //! [`BIP70: Payment Protocol`]: https://github.com/bitcoin/bips/blob/master/bip-0070.mediawiki
 
 pub mod wallet;
+pub mod zeroconf;
 
 use bytes::Buf;
-use http::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE};
+use http::header::{HeaderMap, HeaderName, ACCEPT, CONTENT_TYPE};
+use mime::Mime;
 use prost::Message as _;
 use thiserror::Error;
 
@@ -44,30 +47,64 @@ pub enum PreprocessingError {
     PaymentDecode(prost::DecodeError),
 }
 
+/// The media types accepted by [`preprocess_payment_with`].
+///
+/// Media types are compared by essence, so parameters such as `charset` and differences in casing
+/// are ignored.
+#[derive(Clone, Debug)]
+pub struct AcceptedMediaTypes {
+    /// Media types accepted in the `Content-Type` header.
+    pub content_types: Vec<Mime>,
+    /// Media types, at least one of which must be present in the `Accept` header.
+    pub accept: Vec<Mime>,
+}
+
+impl Default for AcceptedMediaTypes {
+    fn default() -> Self {
+        Self {
+            content_types: vec!["application/bitcoincash-payment".parse().unwrap()], // This is safe
+            accept: vec!["application/bitcoincash-paymentack".parse().unwrap()],     // This is safe
+        }
+    }
+}
+
+/// Check whether any media type listed in the header `name` has the same essence as one of
+/// `allowed`.
+fn contains_media_type(headers: &HeaderMap, name: HeaderName, allowed: &[Mime]) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|header_val| header_val.to_str().ok())
+        .flat_map(|header_str| header_str.split(','))
+        .filter_map(|item| item.trim().parse::<Mime>().ok())
+        .any(|media_type| {
+            allowed
+                .iter()
+                .any(|allowed| allowed.essence_str() == media_type.essence_str())
+        })
+}
+
 /// Validates and parses the BIP70 payment.
 pub async fn preprocess_payment<B: Buf>(
     headers: HeaderMap,
     body: B,
 ) -> Result<Payment, PreprocessingError> {
-    // Bitcoin Cash Headers
-    let bch_content_type_value = HeaderValue::from_static("application/bitcoincash-payment");
-    let bch_accept_value = HeaderValue::from_static("application/bitcoincash-paymentack");
+    preprocess_payment_with(headers, body, &AcceptedMediaTypes::default()).await
+}
 
+/// Validates and parses the BIP70 payment, accepting the given media types.
+pub async fn preprocess_payment_with<B: Buf>(
+    headers: HeaderMap,
+    body: B,
+    media_types: &AcceptedMediaTypes,
+) -> Result<Payment, PreprocessingError> {
     // Check for content-type header
-    if !headers
-        .get_all(CONTENT_TYPE)
-        .iter()
-        .any(|header_val| header_val == bch_content_type_value)
-    {
+    if !contains_media_type(&headers, CONTENT_TYPE, &media_types.content_types) {
         return Err(PreprocessingError::MissingContentTypeHeader);
     }
 
     // Check for accept header
-    if !headers
-        .get_all(ACCEPT)
-        .iter()
-        .any(|header_val| header_val == bch_accept_value)
-    {
+    if !contains_media_type(&headers, ACCEPT, &media_types.accept) {
         return Err(PreprocessingError::MissingAcceptHeader);
     }
 
@@ -76,3 +113,53 @@ pub async fn preprocess_payment<B: Buf>(
 
     Ok(payment)
 }
+
+#[cfg(test)]
+mod tests {
+    use http::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(content_type: &'static str, accept: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers.insert(ACCEPT, HeaderValue::from_static(accept));
+        headers
+    }
+
+    #[test]
+    fn media_types() {
+        let media_types = AcceptedMediaTypes::default();
+        let accepted = [
+            (
+                "application/bitcoincash-payment",
+                "application/bitcoincash-paymentack",
+            ),
+            (
+                "application/bitcoincash-payment; charset=utf-8",
+                "text/plain, application/bitcoincash-paymentack;q=0.9",
+            ),
+            (
+                "Application/BitcoinCash-Payment",
+                "APPLICATION/BITCOINCASH-PAYMENTACK",
+            ),
+        ];
+        for (content_type, accept) in &accepted {
+            let headers = headers(content_type, accept);
+            assert!(contains_media_type(
+                &headers,
+                CONTENT_TYPE,
+                &media_types.content_types
+            ));
+            assert!(contains_media_type(&headers, ACCEPT, &media_types.accept));
+        }
+
+        let headers = headers("application/json", "application/bitcoincash-payment");
+        assert!(!contains_media_type(
+            &headers,
+            CONTENT_TYPE,
+            &media_types.content_types
+        ));
+        assert!(!contains_media_type(&headers, ACCEPT, &media_types.accept));
+    }
+}
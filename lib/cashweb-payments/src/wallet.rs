@@ -1,6 +1,13 @@
 //! This module contains the [`Wallet`] struct which allows for basic caching and payment of invoices.
 
-use std::{fmt, sync::Arc, time::Duration};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use dashmap::DashMap;
 use thiserror::Error;
@@ -11,11 +18,22 @@ use tokio::time::sleep;
 #[error("received unexpected outputs")]
 pub struct UnexpectedOutputs;
 
+/// A set of outputs expected in a payment, identified so that it can be expired independently.
+#[derive(Debug)]
+struct PendingOutputs<O> {
+    id: u64,
+    outputs: Vec<O>,
+}
+
 /// Provides a simple interface to allow parallel caching and retrieval of UTXOs.
+///
+/// Each key may have several sets of expected outputs pending at once, for example invoices for
+/// the same address issued to different devices.
 #[derive(Clone)]
 pub struct Wallet<K, O> {
     timeout: Duration,
-    pending: Arc<DashMap<K, Vec<O>>>, // script:amount
+    pending: Arc<DashMap<K, Vec<PendingOutputs<O>>>>,
+    next_id: Arc<AtomicU64>,
 }
 
 // NOTE: CHALK will remove the need for this manual impl
@@ -43,18 +61,24 @@ where
         Wallet {
             timeout,
             pending: Default::default(),
+            next_id: Default::default(),
         }
     }
 
     /// Synchronously adds outputs to the wallet and returns a delayed Future removing the output.
+    ///
+    /// Outputs already pending under the same key are kept, and expire independently.
     pub fn add_outputs(
         &self,
         key: K,
         outputs: Vec<O>,
     ) -> impl std::future::Future<Output = ()> + Send + 'static {
-        // TODO: Check whether pre-existing?
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
         let key_inner = key.clone();
-        self.pending.insert(key, outputs);
+        self.pending
+            .entry(key)
+            .or_insert_with(Vec::new)
+            .push(PendingOutputs { id, outputs });
 
         let pending_inner = self.pending.clone();
         let timeout_inner = self.timeout;
@@ -62,22 +86,62 @@ where
         // Remove from pending map after timeout
         async move {
             sleep(timeout_inner).await;
-            pending_inner.remove(&key_inner);
+            if let Some(mut pending) = pending_inner.get_mut(&key_inner) {
+                pending.retain(|pending_outputs| pending_outputs.id != id);
+            }
+            pending_inner.remove_if(&key_inner, |_, pending| pending.is_empty());
         }
     }
 
-    /// Removes an output from the wallet, else raises an error.
+    /// Removes a set of expected outputs, contained in the given outputs, from the wallet, else
+    /// raises an error.
     pub fn recv_outputs(&self, key: &K, outputs: &[O]) -> Result<(), UnexpectedOutputs> {
-        let check_subset = |_: &K, expected_outputs: &Vec<O>| {
-            expected_outputs
+        {
+            let mut pending = self.pending.get_mut(key).ok_or(UnexpectedOutputs)?;
+            let index = pending
                 .iter()
-                .all(|output| outputs.contains(output))
-        };
-
-        if self.pending.remove_if(key, check_subset).is_some() {
-            Ok(())
-        } else {
-            Err(UnexpectedOutputs)
+                .position(|pending_outputs| {
+                    pending_outputs
+                        .outputs
+                        .iter()
+                        .all(|output| outputs.contains(output))
+                })
+                .ok_or(UnexpectedOutputs)?;
+            pending.remove(index);
         }
+        self.pending.remove_if(key, |_, pending| pending.is_empty());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_invoices() {
+        let wallet = Wallet::new(Duration::from_secs(60));
+        let _first_cleanup = wallet.add_outputs(1, vec![10, 20]);
+        let _second_cleanup = wallet.add_outputs(1, vec![30]);
+
+        // Either invoice may be paid, each only once
+        assert_eq!(wallet.recv_outputs(&1, &[30, 40]), Ok(()));
+        assert_eq!(wallet.recv_outputs(&1, &[30]), Err(UnexpectedOutputs));
+        assert_eq!(wallet.recv_outputs(&1, &[10]), Err(UnexpectedOutputs));
+        assert_eq!(wallet.recv_outputs(&1, &[20, 10]), Ok(()));
+        assert_eq!(wallet.recv_outputs(&1, &[20, 10]), Err(UnexpectedOutputs));
+        assert_eq!(wallet.recv_outputs(&2, &[10, 20]), Err(UnexpectedOutputs));
+    }
+
+    #[tokio::test]
+    async fn independent_expiry() {
+        let wallet = Wallet::new(Duration::from_millis(0));
+        let first_cleanup = wallet.add_outputs(1, vec![10]);
+        let _second_cleanup = wallet.add_outputs(1, vec![20]);
+
+        // Expiring one invoice keeps the other
+        first_cleanup.await;
+        assert_eq!(wallet.recv_outputs(&1, &[10]), Err(UnexpectedOutputs));
+        assert_eq!(wallet.recv_outputs(&1, &[20]), Ok(()));
     }
 }
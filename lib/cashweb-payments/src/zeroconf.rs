@@ -0,0 +1,302 @@
+//! This module contains [`ZeroConfPolicy`] which guards against accepting unconfirmed payments
+//! which are likely to be double-spent.
+
+use std::{collections::HashMap, time::Duration};
+
+use bitcoin::{
+    transaction::{self, Transaction},
+    Decodable, Encodable,
+};
+use bitcoin_client::{BitcoinClient, NodeError};
+use thiserror::Error;
+use tokio::time::sleep;
+
+/// Error associated with the zero-confirmation risk checks.
+#[derive(Debug, Error)]
+pub enum ZeroConfError {
+    /// The fee rate of a transaction, in satoshis per kilobyte, is below the minimum.
+    #[error("fee rate too low: {0} < {1} sat/kB")]
+    LowFeeRate(u64, u64),
+    /// A transaction spends an unconfirmed transaction outside of the payment.
+    #[error("transaction spends unconfirmed parent {0}")]
+    UnconfirmedParent(String),
+    /// A double-spend proof was received for a transaction.
+    #[error("double-spend proof received for {0}")]
+    DoubleSpent(String),
+    /// A transaction spends an output which could not be found.
+    #[error("transaction spends missing output {0}:{1}")]
+    MissingInput(String, u32),
+    /// Failed to decode the transaction spent by an input.
+    #[error("failed to decode parent transaction: {0}")]
+    Transaction(transaction::DecodeError),
+    /// Failed to query the node.
+    #[error("bitcoin request failed: {0}")]
+    Node(#[from] NodeError),
+}
+
+/// Policies an unconfirmed payment must satisfy before it is accepted.
+///
+/// The default policy accepts every payment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ZeroConfPolicy {
+    /// Minimum fee rate of each transaction, in satoshis per kilobyte.
+    pub min_fee_rate: u64,
+    /// Reject transactions spending unconfirmed outputs of transactions outside of the payment.
+    pub reject_unconfirmed_parents: bool,
+    /// Time to wait for a double-spend proof, zero to skip waiting.
+    pub dsproof_wait: Duration,
+}
+
+impl ZeroConfPolicy {
+    /// Check the transactions of a payment against the fee rate and unconfirmed parent policies,
+    /// before they are broadcast.
+    ///
+    /// The transactions spent from outside of the payment are looked up on the node.
+    pub async fn check_transactions<C: BitcoinClient>(
+        &self,
+        client: &C,
+        transactions: &[Transaction],
+    ) -> Result<(), ZeroConfError> {
+        if self.min_fee_rate == 0 && !self.reject_unconfirmed_parents {
+            return Ok(());
+        }
+
+        // Chains within the payment are checked individually
+        let mut parents: HashMap<[u8; 32], Transaction> = transactions
+            .iter()
+            .map(|transaction| (transaction.transaction_id(), transaction.clone()))
+            .collect();
+        for input in transactions
+            .iter()
+            .flat_map(|transaction| &transaction.inputs)
+        {
+            let tx_id = input.outpoint.tx_id;
+            if parents.contains_key(&tx_id) {
+                continue;
+            }
+
+            // The node's RPC uses the reversed byte order
+            let mut rpc_tx_id = tx_id;
+            rpc_tx_id.reverse();
+            if self.reject_unconfirmed_parents {
+                match client.get_mempool_entry(&rpc_tx_id).await {
+                    Ok(_) => return Err(ZeroConfError::UnconfirmedParent(hex::encode(rpc_tx_id))),
+                    Err(NodeError::NotFound(_)) | Err(NodeError::EmptyResponse) => (),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            if self.min_fee_rate != 0 {
+                let raw_parent = match client.get_raw_transaction(&rpc_tx_id).await {
+                    Ok(ok) => ok,
+                    Err(NodeError::NotFound(_)) | Err(NodeError::EmptyResponse) => {
+                        return Err(ZeroConfError::MissingInput(
+                            hex::encode(rpc_tx_id),
+                            input.outpoint.vout,
+                        ))
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+                let parent = Transaction::decode(&mut raw_parent.as_slice())
+                    .map_err(ZeroConfError::Transaction)?;
+                parents.insert(tx_id, parent);
+            }
+        }
+
+        if self.min_fee_rate != 0 {
+            for transaction in transactions {
+                let mut input_values = Vec::with_capacity(transaction.inputs.len());
+                for input in &transaction.inputs {
+                    let outpoint = &input.outpoint;
+                    let output = parents
+                        .get(&outpoint.tx_id)
+                        .and_then(|parent| parent.outputs.get(outpoint.vout as usize))
+                        .ok_or_else(|| {
+                            let mut rpc_tx_id = outpoint.tx_id;
+                            rpc_tx_id.reverse();
+                            ZeroConfError::MissingInput(hex::encode(rpc_tx_id), outpoint.vout)
+                        })?;
+                    input_values.push(output.value);
+                }
+                let fee = transaction.fee(&input_values).unwrap_or(0);
+                let fee_rate =
+                    fee.saturating_mul(1_000) / (transaction.encoded_len() as u64).max(1);
+                if fee_rate < self.min_fee_rate {
+                    return Err(ZeroConfError::LowFeeRate(fee_rate, self.min_fee_rate));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for double-spend proofs against the broadcast transactions of a payment.
+    ///
+    /// The transaction IDs are given in the byte order used by the node's RPC.
+    pub async fn check_broadcast<C: BitcoinClient>(
+        &self,
+        client: &C,
+        tx_ids: &[Vec<u8>],
+    ) -> Result<(), ZeroConfError> {
+        if self.dsproof_wait != Duration::default() {
+            sleep(self.dsproof_wait).await;
+            for tx_id in tx_ids {
+                if client.get_ds_proof(tx_id).await?.is_some() {
+                    return Err(ZeroConfError::DoubleSpent(hex::encode(tx_id)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::transaction::{input::Input, outpoint::Outpoint, output::Output, script::Script};
+    use bitcoin_client::{
+        mock::{MockBitcoinClient, MockCall},
+        MempoolEntry, MempoolFees,
+    };
+
+    use super::*;
+
+    const SATS_PER_COIN: f64 = 100_000_000.;
+
+    /// Construct a transaction spending an output of each parent, paying `value` to a single
+    /// output.
+    fn spend(parents: &[&Transaction], value: u64) -> Transaction {
+        Transaction {
+            inputs: parents
+                .iter()
+                .map(|parent| Input {
+                    outpoint: Outpoint {
+                        tx_id: parent.transaction_id(),
+                        vout: 0,
+                    },
+                    script: Script::from(vec![0; 100]),
+                    sequence: 0,
+                })
+                .collect(),
+            outputs: vec![Output {
+                value,
+                script: Script::p2pkh(&[1; 20]),
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Make a transaction known to the node.
+    fn insert(client: &MockBitcoinClient, transaction: &Transaction) {
+        let mut raw_transaction = Vec::with_capacity(transaction.encoded_len());
+        transaction.encode(&mut raw_transaction).unwrap();
+        client.insert_transaction(transaction.transaction_id_rev().to_vec(), raw_transaction);
+    }
+
+    #[tokio::test]
+    async fn transactions_fee_rate() {
+        let client = MockBitcoinClient::new();
+        let parent = spend(&[], 10_000);
+        insert(&client, &parent);
+
+        // The payment pays 1_000 satoshis in fees
+        let child = spend(&[&parent], 9_000);
+        let fee_rate = 1_000 * 1_000 / child.encoded_len() as u64;
+        let policy = ZeroConfPolicy {
+            min_fee_rate: fee_rate,
+            ..Default::default()
+        };
+        assert!(policy
+            .check_transactions(&client, &[child.clone()])
+            .await
+            .is_ok());
+
+        let policy = ZeroConfPolicy {
+            min_fee_rate: fee_rate + 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            policy.check_transactions(&client, &[child.clone()]).await,
+            Err(ZeroConfError::LowFeeRate(rate, _)) if rate == fee_rate
+        ));
+
+        // Nothing is broadcast or looked up in the mempool
+        assert!(client
+            .calls()
+            .iter()
+            .all(|call| matches!(call, MockCall::GetRawTransaction(_))));
+
+        // Spending unknown outputs is rejected
+        let orphan = spend(&[&spend(&[], 1)], 0);
+        assert!(matches!(
+            policy.check_transactions(&client, &[orphan]).await,
+            Err(ZeroConfError::MissingInput(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn transactions_unconfirmed_parents() {
+        let client = MockBitcoinClient::new();
+        let confirmed = spend(&[], 10_000);
+        let unconfirmed = spend(&[], 20_000);
+        client.insert_mempool_entry(
+            unconfirmed.transaction_id_rev().to_vec(),
+            entry(250, 250, Vec::new()),
+        );
+        let policy = ZeroConfPolicy {
+            reject_unconfirmed_parents: true,
+            ..Default::default()
+        };
+
+        // Parents within the payment are allowed
+        let child = spend(&[&confirmed], 9_000);
+        let grandchild = spend(&[&child], 8_000);
+        assert!(policy
+            .check_transactions(&client, &[child, grandchild])
+            .await
+            .is_ok());
+
+        let child = spend(&[&unconfirmed], 19_000);
+        assert!(matches!(
+            policy.check_transactions(&client, &[child]).await,
+            Err(ZeroConfError::UnconfirmedParent(parent))
+                if parent == hex::encode(unconfirmed.transaction_id_rev())
+        ));
+    }
+
+    fn entry(size: u64, fee: u64, depends: Vec<String>) -> MempoolEntry {
+        MempoolEntry {
+            size,
+            time: 0,
+            height: 0,
+            depends,
+            spentby: Vec::new(),
+            fees: MempoolFees {
+                base: fee as f64 / SATS_PER_COIN,
+            },
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn double_spend_proof() {
+        let client = MockBitcoinClient::new();
+        let tx_ids = vec![vec![1; 32]];
+        let policy = ZeroConfPolicy {
+            dsproof_wait: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert!(policy.check_broadcast(&client, &tx_ids).await.is_ok());
+
+        client.insert_ds_proof(vec![1; 32], vec![0; 8]);
+        assert!(matches!(
+            policy.check_broadcast(&client, &tx_ids).await,
+            Err(ZeroConfError::DoubleSpent(_))
+        ));
+
+        // Without a wait, proofs are not queried
+        let calls = client.calls().len();
+        assert!(ZeroConfPolicy::default()
+            .check_broadcast(&client, &tx_ids)
+            .await
+            .is_ok());
+        assert_eq!(client.calls().len(), calls);
+    }
+}
@@ -0,0 +1,179 @@
+//! This module contains methods for constructing [`Message`]s whose [`Payload`] is end-to-end encrypted
+//! to the destination public key, using the [`EncryptionScheme::EphemeralDh`] scheme.
+
+use std::convert::TryInto;
+
+use block_modes::BlockModeError;
+use prost::{DecodeError as MessageDecodeError, Message as _};
+use ring::{
+    digest::{digest, SHA256},
+    hmac::{self, sign, HMAC_SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use secp256k1::{key::PublicKey, Error as SecpError, Secp256k1, SecretKey};
+use thiserror::Error;
+
+use crate::{
+    create_shared_key, decrypt_payload, encrypt_payload, EncryptionScheme, InvalidHmac, Message,
+    Payload,
+};
+
+/// Length of the salt generated by [`seal`].
+pub const SALT_LEN: usize = 32;
+
+/// Error associated with [`seal`] and [`seal_with_salt`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SealError {
+    /// Failed to construct shared key.
+    #[error("shared key: {0}")]
+    SharedKey(SecpError),
+    /// Failed to generate a random salt.
+    #[error("failed to generate salt")]
+    Salt,
+}
+
+/// Encrypt a [`Payload`] to the destination public key, constructing an unstamped [`Message`].
+///
+/// The salt is generated randomly. The stamp must be attached before the message is sent.
+pub fn seal(
+    payload: &Payload,
+    source_private_key: &SecretKey,
+    destination_public_key: &PublicKey,
+) -> Result<Message, SealError> {
+    let mut salt = vec![0; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| SealError::Salt)?;
+    seal_with_salt(payload, source_private_key, destination_public_key, salt)
+}
+
+/// Encrypt a [`Payload`] to the destination public key, using the given salt, constructing an
+/// unstamped [`Message`].
+///
+/// The salt should never be reused between the same source and destination.
+pub fn seal_with_salt(
+    payload: &Payload,
+    source_private_key: &SecretKey,
+    destination_public_key: &PublicKey,
+    salt: Vec<u8>,
+) -> Result<Message, SealError> {
+    let source_public_key =
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), source_private_key);
+
+    // Create shared key, sdG = dsG
+    let shared_key = create_shared_key(*destination_public_key, &source_private_key[..], &salt)
+        .map_err(SealError::SharedKey)?;
+
+    // Encrypt
+    let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+    payload.encode(&mut raw_payload).unwrap(); // This is safe
+    let ciphertext = encrypt_payload(&shared_key, &raw_payload);
+
+    // Authenticate
+    let payload_digest = digest(&SHA256, &ciphertext);
+    let key = hmac::Key::new(HMAC_SHA256, &shared_key);
+    let payload_hmac = sign(&key, payload_digest.as_ref());
+
+    Ok(Message {
+        source_public_key: source_public_key.serialize().to_vec(),
+        destination_public_key: destination_public_key.serialize().to_vec(),
+        received_time: 0,
+        payload_digest: payload_digest.as_ref().to_vec(),
+        stamp: None,
+        scheme: EncryptionScheme::EphemeralDh.into(),
+        salt,
+        payload_hmac: payload_hmac.as_ref().to_vec(),
+        payload_size: ciphertext.len() as u64,
+        sequence: 0,
+        stamp_value: 0,
+        broadcast_outpoints: Vec::new(),
+        ttl: 0,
+        payload: ciphertext,
+    })
+}
+
+/// Error associated with [`unseal`].
+#[derive(Debug, Clone, Error)]
+pub enum UnsealError {
+    /// Unable to parse the source public key.
+    #[error("source public key: {0}")]
+    SourcePublicKey(SecpError),
+    /// Failed to construct shared key.
+    #[error("shared key: {0}")]
+    SharedKey(SecpError),
+    /// Failed authentication.
+    #[error(transparent)]
+    Authentication(InvalidHmac),
+    /// Failed to decrypt the ciphertext [`Payload`].
+    #[error("decryption failure: {0}")]
+    Decrypt(BlockModeError),
+    /// Failed to decode the plaintext [`Payload`].
+    #[error("payload decoding failure: {0}")]
+    Payload(MessageDecodeError),
+}
+
+/// Authenticate and decrypt the [`Payload`] of a [`Message`] using the destination private key.
+///
+/// This does not verify the stamp.
+pub fn unseal(
+    message: &Message,
+    destination_private_key: &SecretKey,
+) -> Result<Payload, UnsealError> {
+    let source_public_key =
+        PublicKey::from_slice(&message.source_public_key).map_err(UnsealError::SourcePublicKey)?;
+    let shared_key = create_shared_key(
+        source_public_key,
+        &destination_private_key[..],
+        &message.salt,
+    )
+    .map_err(UnsealError::SharedKey)?;
+
+    // Authenticate
+    let payload_digest: [u8; 32] = digest(&SHA256, &message.payload)
+        .as_ref()
+        .try_into()
+        .unwrap(); // This is safe
+    let key = hmac::Key::new(HMAC_SHA256, &shared_key);
+    hmac::verify(&key, &payload_digest, &message.payload_hmac)
+        .map_err(|_| UnsealError::Authentication(InvalidHmac))?;
+
+    // Decrypt and decode
+    let raw_payload =
+        decrypt_payload(&shared_key, &message.payload).map_err(UnsealError::Decrypt)?;
+    Payload::decode(raw_payload.as_slice()).map_err(UnsealError::Payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PayloadEntry;
+
+    #[test]
+    fn seal_unseal() {
+        let context = Secp256k1::signing_only();
+        let source_private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let destination_private_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let destination_public_key = PublicKey::from_secret_key(&context, &destination_private_key);
+
+        let payload = Payload {
+            timestamp: 1234,
+            entries: vec![PayloadEntry {
+                kind: "text-utf8".to_string(),
+                headers: vec![],
+                body: b"hello".to_vec(),
+            }],
+        };
+        let message = seal(&payload, &source_private_key, &destination_public_key).unwrap();
+        assert_eq!(message.salt.len(), SALT_LEN);
+        assert_eq!(message.digest().unwrap().to_vec(), message.payload_digest);
+
+        // Destination can decrypt
+        assert_eq!(unseal(&message, &destination_private_key).unwrap(), payload);
+
+        // Others cannot
+        assert!(matches!(
+            unseal(&message, &source_private_key),
+            Err(UnsealError::Authentication(_))
+        ));
+    }
+}
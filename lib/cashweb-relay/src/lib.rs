@@ -10,13 +10,18 @@
 //!
 //! [`Relay Protocol`]: https://github.com/cashweb/specifications/blob/master/authorization-wrapper/specification.mediawiki
 
+pub mod encryption;
 #[allow(unreachable_pub, missing_docs)]
 mod models;
+pub mod pages;
 pub mod stamp;
 
 pub use crate::models::{
-    message::EncryptionScheme, Message, MessagePage, MessageSet, Payload, PayloadPage, Profile,
-    Stamp,
+    message::EncryptionScheme, push_registration::PushService, stamp::StampType, ArchiveItem,
+    BroadcastOutpoint, Capabilities, Conversation, ConversationList, Deletion, ImportSummary,
+    InboxSummary, Message, MessagePage, MessageSearch, MessageSet, NamespaceSummary, Payload,
+    PayloadEntry, PayloadPage, Profile, ProfileEntry, PushRegistration, RemovalSummary, Stamp,
+    SyncCursor, SyncPage, TokenInfo,
 };
 
 use std::convert::TryInto;
@@ -67,6 +72,14 @@ pub struct ParsedMessage {
     pub payload_hmac: [u8; 32],
     /// The size, in bytes, of the `payload`.
     pub payload_size: u64,
+    /// Server-assigned sequence number.
+    pub sequence: u64,
+    /// Server-computed total value, in satoshis, of the stamp outputs.
+    pub stamp_value: u64,
+    /// Server-recorded stamp outputs whose transactions were broadcast.
+    pub broadcast_outpoints: Vec<BroadcastOutpoint>,
+    /// Period, in milliseconds after `received_time`, after which the message is removed.
+    pub ttl: u64,
     /// The encrypted `payload`.
     pub payload: Vec<u8>,
 }
@@ -84,6 +97,10 @@ impl ParsedMessage {
             salt: self.salt,
             payload_hmac: self.payload_hmac.to_vec(),
             payload_size: self.payload_size,
+            sequence: self.sequence,
+            stamp_value: self.stamp_value,
+            broadcast_outpoints: self.broadcast_outpoints,
+            ttl: self.ttl,
             payload: self.payload,
         }
     }
@@ -202,6 +219,10 @@ impl Message {
             salt: self.salt,
             payload_hmac,
             payload_size: self.payload_size,
+            sequence: self.sequence,
+            stamp_value: self.stamp_value,
+            broadcast_outpoints: self.broadcast_outpoints,
+            ttl: self.ttl,
             payload: self.payload,
         })
     }
@@ -326,6 +347,20 @@ impl ParsedMessage {
             .verify_stamp(&self.payload_digest, &self.destination_public_key)
     }
 
+    /// Verify the stamp on the message, deriving stamp keys along `derivation_path`, and return
+    /// the decoded transactions.
+    #[inline]
+    pub fn verify_stamp_with_path(
+        &self,
+        derivation_path: &[u32],
+    ) -> Result<Vec<Transaction>, stamp::StampError> {
+        self.stamp.verify_stamp_with_path(
+            &self.payload_digest,
+            &self.destination_public_key,
+            derivation_path,
+        )
+    }
+
     /// Verify the stamp, authenticate the HMAC payload, and then decrypt and decode the payload.
     ///
     /// This is done in-place, replacing the encrypted `payload` field with the plain text.
@@ -409,6 +444,8 @@ impl From<MessagePage> for PayloadPage {
             end_time: message_page.end_time,
             start_digest: message_page.start_digest,
             end_digest: message_page.end_digest,
+            start_sequence: message_page.start_sequence,
+            end_sequence: message_page.end_sequence,
             payloads,
         }
     }
@@ -435,3 +472,14 @@ pub fn encrypt_payload_in_place(shared_key: &[u8], payload: &mut [u8]) {
     let cipher = Aes128Cbc::new_var(key, iv).unwrap(); // This is safe
     cipher.encrypt(payload, 0).unwrap(); // TODO: Double check this is safe
 }
+
+/// Decrypt a payload using a shared key.
+///
+/// Typically the shared key is `HMAC(sdG, salt)` created using the [`create_shared_key`] method.
+pub fn decrypt_payload(shared_key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, BlockModeError> {
+    let (key, iv) = shared_key.split_at(16);
+    let key = GenericArray::<u8, U16>::from_slice(key);
+    let iv = GenericArray::<u8, U16>::from_slice(iv);
+    let cipher = Aes128Cbc::new_var(key, iv).unwrap(); // This is safe
+    cipher.decrypt_vec(ciphertext)
+}
@@ -0,0 +1,130 @@
+//! This module contains methods for merging and filtering [`MessagePage`]s, for example when
+//! aggregating a mailbox across several relays.
+
+use std::collections::HashSet;
+
+use crate::{Message, MessagePage};
+
+impl MessagePage {
+    /// Merge pages, typically fetched from different relays, into a single page.
+    ///
+    /// Messages are ordered by received time and duplicates, identified by payload digest, are
+    /// removed. Sequence numbers are local to each relay, so the sequence bounds of the merged page
+    /// are cleared.
+    pub fn merge<I: IntoIterator<Item = MessagePage>>(pages: I) -> MessagePage {
+        let messages = pages.into_iter().flat_map(|page| page.messages).collect();
+        let mut message_page = MessagePage {
+            messages,
+            ..Default::default()
+        };
+        message_page
+            .messages
+            .sort_by_key(|message| message.received_time);
+        message_page.dedup();
+        message_page.start_sequence = 0;
+        message_page.end_sequence = 0;
+        message_page
+    }
+
+    /// Remove messages whose payload digest has already been seen, keeping the first occurrence.
+    ///
+    /// Messages whose digest cannot be calculated are kept.
+    pub fn dedup(&mut self) {
+        let mut seen = HashSet::with_capacity(self.messages.len());
+        self.retain(|message| match message.digest() {
+            Ok(payload_digest) => seen.insert(payload_digest),
+            Err(_) => true,
+        });
+    }
+
+    /// Keep only messages received within the given bounds, both inclusive.
+    pub fn filter_time(&mut self, start_time: Option<i64>, end_time: Option<i64>) {
+        self.retain(|message| {
+            start_time.map_or(true, |start_time| start_time <= message.received_time)
+                && end_time.map_or(true, |end_time| message.received_time <= end_time)
+        });
+    }
+
+    /// Keep only messages sent from the given public key.
+    pub fn filter_sender(&mut self, source_public_key: &[u8]) {
+        self.retain(|message| message.source_public_key == source_public_key);
+    }
+
+    /// Keep only messages satisfying the predicate, then update the bounds of the page.
+    pub fn retain<F: FnMut(&Message) -> bool>(&mut self, predicate: F) {
+        self.messages.retain(predicate);
+        self.update_bounds();
+    }
+
+    /// Set the start and end fields of the page from its first and last messages.
+    fn update_bounds(&mut self) {
+        let (first, last) = match (self.messages.first(), self.messages.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                *self = MessagePage::default();
+                return;
+            }
+        };
+        self.start_time = first.received_time;
+        self.start_digest = first
+            .digest()
+            .map(|payload_digest| payload_digest.to_vec())
+            .unwrap_or_default();
+        self.start_sequence = first.sequence;
+        self.end_time = last.received_time;
+        self.end_digest = last
+            .digest()
+            .map(|payload_digest| payload_digest.to_vec())
+            .unwrap_or_default();
+        self.end_sequence = last.sequence;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(source: u8, received_time: i64, payload: &[u8]) -> Message {
+        Message {
+            source_public_key: vec![source; 33],
+            received_time,
+            payload: payload.to_vec(),
+            sequence: received_time as u64,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_and_filter() {
+        let page_a = MessagePage {
+            messages: vec![message(1, 10, b"a"), message(2, 30, b"c")],
+            ..Default::default()
+        };
+        let page_b = MessagePage {
+            messages: vec![message(1, 10, b"a"), message(1, 20, b"b")],
+            ..Default::default()
+        };
+        let mut merged = MessagePage::merge(vec![page_a, page_b]);
+        let times: Vec<i64> = merged.messages.iter().map(|m| m.received_time).collect();
+        assert_eq!(times, vec![10, 20, 30]);
+        assert_eq!(merged.start_time, 10);
+        assert_eq!(merged.end_time, 30);
+        assert_eq!(
+            merged.end_digest,
+            merged.messages[2].digest().unwrap().to_vec()
+        );
+        assert_eq!(merged.start_sequence, 0);
+
+        merged.filter_time(Some(15), None);
+        assert_eq!(merged.messages.len(), 2);
+        assert_eq!(merged.start_time, 20);
+        assert_eq!(merged.start_sequence, 20);
+
+        merged.filter_sender(&[1; 33]);
+        assert_eq!(merged.messages.len(), 1);
+        assert_eq!(merged.end_time, 20);
+
+        merged.filter_time(None, Some(15));
+        assert_eq!(merged, MessagePage::default());
+    }
+}
@@ -1,9 +1,14 @@
 //! This module contains the [`Stamp`] message and methods for verifying and constructing them.
 
+use std::collections::HashSet;
+
 use cashweb_bitcoin::{
     bip32::*,
-    transaction::{self, Transaction},
-    Decodable,
+    psbt::PartiallySignedTransaction,
+    transaction::{
+        self, input::Input, outpoint::Outpoint, output::Output, script::Script, Transaction,
+    },
+    Decodable, Encodable,
 };
 use ring::digest::{digest, SHA256};
 use ripemd160::{Digest, Ripemd160};
@@ -39,6 +44,43 @@ pub enum StampError {
     /// Stamp type was `None`.
     #[error("stamp type is none")]
     NoneType,
+    /// A specified stamp output was not a burn committing to the payload digest.
+    #[error("output is not a burn committing to the payload digest")]
+    NotBurn,
+    /// A burn stamp specified no outputs.
+    #[error("burn stamp has no outputs")]
+    NoBurnOutputs,
+    /// A stamp output was specified more than once.
+    #[error("duplicate output: {0}")]
+    DuplicateOutput(u32),
+}
+
+/// Default derivation path, below the stamp master key, of the keys of each stamp transaction.
+///
+/// This is the BIP44 purpose followed by the Bitcoin Cash coin type. Networks with other coin types,
+/// such as eCash (899) or Lotus (10605), may substitute their own.
+pub const DEFAULT_STAMP_DERIVATION_PATH: [u32; 2] = [44, 145];
+
+/// Convert a stamp derivation path into child numbers, returning `None` if an index is hardened.
+fn stamp_path(derivation_path: &[u32]) -> Option<Vec<ChildNumber>> {
+    derivation_path
+        .iter()
+        .map(|index| ChildNumber::from_normal_index(*index).ok())
+        .collect()
+}
+
+/// Prefix of the burn stamp commitment.
+pub const BURN_PREFIX: [u8; 4] = *b"STMP";
+
+/// Construct the script of a burn stamp output committing to the payload digest.
+pub fn burn_script(payload_digest: &[u8; 32]) -> Script {
+    let mut script = Vec::with_capacity(2 + BURN_PREFIX.len() + 1 + payload_digest.len());
+    script.push(0x6a); // OP_RETURN
+    script.push(BURN_PREFIX.len() as u8);
+    script.extend_from_slice(&BURN_PREFIX);
+    script.push(payload_digest.len() as u8);
+    script.extend_from_slice(payload_digest);
+    script.into()
 }
 
 impl Stamp {
@@ -49,13 +91,53 @@ impl Stamp {
         payload_digest: &[u8; 32],
         destination_public_key: &PublicKey,
     ) -> Result<Vec<Transaction>, StampError> {
-        verify_stamp(
+        self.verify_stamp_with_path(
+            payload_digest,
+            destination_public_key,
+            &DEFAULT_STAMP_DERIVATION_PATH,
+        )
+    }
+
+    /// Verify that the stamp covers the payload_digest, deriving stamp keys along
+    /// `derivation_path`.
+    #[inline]
+    pub fn verify_stamp_with_path(
+        &self,
+        payload_digest: &[u8; 32],
+        destination_public_key: &PublicKey,
+        derivation_path: &[u32],
+    ) -> Result<Vec<Transaction>, StampError> {
+        verify_stamp_with_path(
             &self.stamp_outpoints,
             payload_digest,
             destination_public_key,
             StampType::from_i32(self.stamp_type).ok_or(StampError::UnsupportedStampType)?, // This is safe
+            derivation_path,
         )
     }
+
+    /// Calculate the total value, in satoshis, of the stamp outputs.
+    ///
+    /// Outputs specified more than once are counted once.
+    pub fn value(&self) -> Result<u64, StampError> {
+        let mut value: u64 = 0;
+        let mut seen = HashSet::new();
+        for outpoint in &self.stamp_outpoints {
+            let tx = Transaction::decode(&mut outpoint.stamp_tx.as_slice())
+                .map_err(StampError::Decode)?;
+            let tx_id = tx.transaction_id();
+            for vout in &outpoint.vouts {
+                let output = tx
+                    .outputs
+                    .get(*vout as usize)
+                    .ok_or(StampError::MissingOutput)?;
+                if seen.insert((tx_id, *vout)) {
+                    value = value.saturating_add(output.value);
+                }
+            }
+        }
+        Ok(value)
+    }
 }
 
 /// Verify that the stamp covers the payload_digest.
@@ -66,8 +148,27 @@ pub fn verify_stamp(
     destination_public_key: &PublicKey,
     stamp_type: StampType,
 ) -> Result<Vec<Transaction>, StampError> {
-    if stamp_type == StampType::None {
-        return Err(StampError::NoneType);
+    verify_stamp_with_path(
+        stamp_outpoints,
+        payload_digest,
+        destination_public_key,
+        stamp_type,
+        &DEFAULT_STAMP_DERIVATION_PATH,
+    )
+}
+
+/// Verify that the stamp covers the payload_digest, deriving stamp keys along `derivation_path`.
+pub fn verify_stamp_with_path(
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_type: StampType,
+    derivation_path: &[u32],
+) -> Result<Vec<Transaction>, StampError> {
+    match stamp_type {
+        StampType::None => return Err(StampError::NoneType),
+        StampType::Burn => return verify_burn_stamp(stamp_outpoints, payload_digest),
+        StampType::MessageCommitment => (),
     }
 
     // Calculate master pubkey
@@ -80,21 +181,18 @@ pub fn verify_stamp(
     let master_pk = ExtendedPublicKey::new_master(combined_key, *payload_digest);
 
     // Calculate intermediate child
+    let path_prefix = stamp_path(derivation_path).ok_or(StampError::ChildNumberOverflow)?;
     let intermediate_child = master_pk
-        .derive_public_path(
-            &Secp256k1::verification_only(),
-            &[
-                ChildNumber::from_normal_index(44).unwrap(),
-                ChildNumber::from_normal_index(145).unwrap(),
-            ],
-        )
-        .unwrap(); // This is safe
+        .derive_public_path::<_, Vec<ChildNumber>>(&Secp256k1::verification_only(), &path_prefix)
+        .unwrap(); // This is safe as the path is not hardened
 
     let context = Secp256k1::verification_only();
     let mut txs = Vec::with_capacity(stamp_outpoints.len());
+    let mut seen = HashSet::new();
     for (tx_num, outpoint) in stamp_outpoints.iter().enumerate() {
         let tx =
             Transaction::decode(&mut outpoint.stamp_tx.as_slice()).map_err(StampError::Decode)?;
+        let tx_id = tx.transaction_id();
 
         // Calculate intermediate child
         let child_number = ChildNumber::from_normal_index(tx_num as u32)
@@ -104,6 +202,9 @@ pub fn verify_stamp(
             .unwrap(); // TODO: Double check this is safe
 
         for (index, vout) in outpoint.vouts.iter().enumerate() {
+            if !seen.insert((tx_id, *vout)) {
+                return Err(StampError::DuplicateOutput(*vout));
+            }
             let output = tx
                 .outputs
                 .get(*vout as usize)
@@ -139,6 +240,43 @@ pub fn verify_stamp(
     Ok(txs)
 }
 
+/// Verify that every stamp output is a burn committing to the payload digest, and is specified
+/// once.
+fn verify_burn_stamp(
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+) -> Result<Vec<Transaction>, StampError> {
+    if stamp_outpoints.is_empty() {
+        return Err(StampError::NoBurnOutputs);
+    }
+
+    let expected_script = burn_script(payload_digest);
+    let mut txs = Vec::with_capacity(stamp_outpoints.len());
+    let mut seen = HashSet::new();
+    for outpoint in stamp_outpoints {
+        if outpoint.vouts.is_empty() {
+            return Err(StampError::NoBurnOutputs);
+        }
+        let tx =
+            Transaction::decode(&mut outpoint.stamp_tx.as_slice()).map_err(StampError::Decode)?;
+        let tx_id = tx.transaction_id();
+        for vout in &outpoint.vouts {
+            if !seen.insert((tx_id, *vout)) {
+                return Err(StampError::DuplicateOutput(*vout));
+            }
+            let output = tx
+                .outputs
+                .get(*vout as usize)
+                .ok_or(StampError::MissingOutput)?;
+            if output.script != expected_script {
+                return Err(StampError::NotBurn);
+            }
+        }
+        txs.push(tx);
+    }
+    Ok(txs)
+}
+
 /// Error associated with creating stamp private keys.
 #[derive(Debug, Error)]
 pub enum StampKeyError {
@@ -154,9 +292,29 @@ pub enum StampKeyError {
 ///
 /// The `output_profile` is an iterable collection of the number of each stamp vouts.
 pub fn create_stamp_private_keys<O>(
+    private_key: SecretKey,
+    payload_digest: &[u8; 32],
+    output_profile: O,
+) -> Result<Vec<Vec<SecretKey>>, StampKeyError>
+where
+    for<'a> &'a O: IntoIterator<Item = &'a u32>,
+{
+    create_stamp_private_keys_with_path(
+        private_key,
+        payload_digest,
+        output_profile,
+        &DEFAULT_STAMP_DERIVATION_PATH,
+    )
+}
+
+/// Construct stamp private keys, derived along `derivation_path`.
+///
+/// The `output_profile` is an iterable collection of the number of each stamp vouts.
+pub fn create_stamp_private_keys_with_path<O>(
     mut private_key: SecretKey,
     payload_digest: &[u8; 32],
     output_profile: O,
+    derivation_path: &[u32],
 ) -> Result<Vec<Vec<SecretKey>>, StampKeyError>
 where
     for<'a> &'a O: IntoIterator<Item = &'a u32>,
@@ -168,12 +326,9 @@ where
     let master_private_key = ExtendedPrivateKey::new_master(private_key, *payload_digest);
 
     // Create intermediate child
-    let path_prefix = [
-        ChildNumber::from_normal_index(44).unwrap(),
-        ChildNumber::from_normal_index(145).unwrap(),
-    ];
+    let path_prefix = stamp_path(derivation_path).ok_or(StampKeyError::ChildNumberOverflow)?;
     let intermediate_child =
-        master_private_key.derive_private_path::<_, [ChildNumber; 2]>(&context, &path_prefix);
+        master_private_key.derive_private_path::<_, Vec<ChildNumber>>(&context, &path_prefix);
     output_profile
         .into_iter()
         .enumerate()
@@ -194,3 +349,432 @@ where
         })
         .collect()
 }
+
+/// Derive the public keys which stamp outputs must pay to.
+///
+/// The `output_profile` is an iterable collection of the number of each stamp vouts.
+pub fn create_stamp_public_keys<O>(
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    output_profile: O,
+) -> Result<Vec<Vec<PublicKey>>, StampError>
+where
+    for<'a> &'a O: IntoIterator<Item = &'a u32>,
+{
+    create_stamp_public_keys_with_path(
+        payload_digest,
+        destination_public_key,
+        output_profile,
+        &DEFAULT_STAMP_DERIVATION_PATH,
+    )
+}
+
+/// Derive the public keys which stamp outputs must pay to, along `derivation_path`.
+///
+/// The `output_profile` is an iterable collection of the number of each stamp vouts.
+pub fn create_stamp_public_keys_with_path<O>(
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    output_profile: O,
+    derivation_path: &[u32],
+) -> Result<Vec<Vec<PublicKey>>, StampError>
+where
+    for<'a> &'a O: IntoIterator<Item = &'a u32>,
+{
+    // Calculate master pubkey
+    let payload_secret_key = SecretKey::from_slice(payload_digest.as_ref()).unwrap(); // This is safe
+    let payload_public_key =
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &payload_secret_key);
+    let combined_key = destination_public_key
+        .combine(&payload_public_key)
+        .map_err(|_| StampError::DegenerateCombination)?;
+    let master_pk = ExtendedPublicKey::new_master(combined_key, *payload_digest);
+
+    // Calculate intermediate child
+    let context = Secp256k1::verification_only();
+    let path_prefix = stamp_path(derivation_path).ok_or(StampError::ChildNumberOverflow)?;
+    let intermediate_child = master_pk
+        .derive_public_path::<_, Vec<ChildNumber>>(&context, &path_prefix)
+        .unwrap(); // This is safe as the path is not hardened
+
+    output_profile
+        .into_iter()
+        .enumerate()
+        .map(|(tx_num, n_index)| {
+            let child_number = ChildNumber::from_normal_index(tx_num as u32)
+                .map_err(|_| StampError::ChildNumberOverflow)?;
+            let tx_child = intermediate_child
+                .derive_public_child(&context, child_number)
+                .unwrap(); // TODO: Double check this is safe
+            (0..*n_index)
+                .map(|index| {
+                    let child_number = ChildNumber::from_normal_index(index)
+                        .map_err(|_| StampError::ChildNumberOverflow)?;
+                    let child_key = tx_child
+                        .derive_public_child(&context, child_number)
+                        .unwrap(); // TODO: Double check this is safe
+                    Ok(*child_key.get_public_key())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Construct a pay-to-pubkey-hash script paying to the public key.
+fn p2pkh_script(public_key: &PublicKey) -> Script {
+    let sha256_digest = digest(&SHA256, &public_key.serialize());
+    let hash160_digest = Ripemd160::digest(sha256_digest.as_ref());
+    let mut pubkey_hash = [0; 20];
+    pubkey_hash.copy_from_slice(&hash160_digest);
+    Script::p2pkh(&pubkey_hash)
+}
+
+/// Estimated length of a pay-to-pubkey-hash input script, used for fee calculation.
+const P2PKH_SCRIPT_SIG_LEN: usize = 107;
+
+/// Outputs with a value below this are not created.
+const DUST_LIMIT: u64 = 546;
+
+/// Error associated with building stamps.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BuildStampError {
+    /// Failed to derive the stamp public keys.
+    #[error(transparent)]
+    Derivation(StampError),
+    /// The UTXOs given cannot cover the stamp outputs and fee.
+    #[error("insufficient funds: {0} < {1}")]
+    InsufficientFunds(u64, u64),
+}
+
+/// An unsigned stamp transaction, alongside the stamp vouts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsignedStamp {
+    /// The unsigned stamp transaction.
+    pub psbt: PartiallySignedTransaction,
+    /// The indexes of the stamp outputs.
+    pub vouts: Vec<u32>,
+}
+
+impl UnsignedStamp {
+    /// Construct the [`Stamp`] from the signed stamp transaction.
+    pub fn into_stamp(self, signed_transaction: &Transaction) -> Stamp {
+        let mut stamp_tx = Vec::with_capacity(signed_transaction.encoded_len());
+        signed_transaction.encode(&mut stamp_tx).unwrap(); // This is safe
+        Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: vec![StampOutpoints {
+                stamp_tx,
+                vouts: self.vouts,
+            }],
+        }
+    }
+}
+
+/// Build an unsigned stamp transaction covering the payload digest.
+///
+/// Each of the `stamp_values` produces a stamp output paying to the keys derived by
+/// [`create_stamp_public_keys`]. UTXOs are spent in the order given until the stamp outputs and
+/// miner fee, at `fee_per_kb` satoshis per kilobyte, are covered. Any remaining change above the
+/// dust limit is paid to `change_script`.
+pub fn build_stamp_outputs(
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_values: &[u64],
+    utxos: &[(Outpoint, Output)],
+    change_script: Script,
+    fee_per_kb: u64,
+) -> Result<UnsignedStamp, BuildStampError> {
+    build_stamp_outputs_with_path(
+        payload_digest,
+        destination_public_key,
+        stamp_values,
+        utxos,
+        change_script,
+        fee_per_kb,
+        &DEFAULT_STAMP_DERIVATION_PATH,
+    )
+}
+
+/// Build an unsigned stamp transaction covering the payload digest, as [`build_stamp_outputs`],
+/// deriving the stamp output keys along `derivation_path`.
+pub fn build_stamp_outputs_with_path(
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_values: &[u64],
+    utxos: &[(Outpoint, Output)],
+    change_script: Script,
+    fee_per_kb: u64,
+    derivation_path: &[u32],
+) -> Result<UnsignedStamp, BuildStampError> {
+    // Derive stamp outputs
+    let output_profile = [stamp_values.len() as u32];
+    let public_keys = create_stamp_public_keys_with_path(
+        payload_digest,
+        destination_public_key,
+        output_profile,
+        derivation_path,
+    )
+    .map_err(BuildStampError::Derivation)?;
+    let mut outputs: Vec<Output> = public_keys[0]
+        .iter()
+        .zip(stamp_values)
+        .map(|(public_key, value)| Output {
+            value: *value,
+            script: p2pkh_script(public_key),
+        })
+        .collect();
+    let vouts = (0..outputs.len() as u32).collect();
+    let stamp_total: u64 = stamp_values.iter().sum();
+
+    // Select inputs, using placeholder scripts to estimate the signed size
+    let mut transaction = Transaction {
+        version: 2,
+        inputs: Vec::new(),
+        outputs: outputs.clone(),
+        lock_time: 0,
+    };
+    let mut prev_outputs = Vec::new();
+    let mut input_total = 0u64;
+    let mut required = stamp_total;
+    for (outpoint, output) in utxos {
+        if input_total >= required {
+            break;
+        }
+        transaction.inputs.push(Input {
+            outpoint: outpoint.clone(),
+            script: vec![0; P2PKH_SCRIPT_SIG_LEN].into(),
+            sequence: 0xffff_ffff,
+        });
+        prev_outputs.push(output.clone());
+        input_total += output.value;
+        required = stamp_total + transaction.required_fee(fee_per_kb);
+    }
+    if input_total < required {
+        return Err(BuildStampError::InsufficientFunds(input_total, required));
+    }
+
+    // Add change output
+    let change_output = Output {
+        value: 0,
+        script: change_script,
+    };
+    transaction.outputs.push(change_output.clone());
+    let fee_with_change = transaction.required_fee(fee_per_kb);
+    let change = input_total.saturating_sub(stamp_total + fee_with_change);
+    transaction.outputs.pop();
+    if change >= DUST_LIMIT {
+        outputs.push(Output {
+            value: change,
+            ..change_output
+        });
+    }
+    transaction.outputs = outputs;
+
+    let mut psbt = PartiallySignedTransaction::new(transaction);
+    for (input, prev_output) in psbt.inputs.iter_mut().zip(prev_outputs) {
+        input.prev_output = Some(prev_output);
+    }
+    Ok(UnsignedStamp { psbt, vouts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_and_verify_stamp() {
+        let destination_private_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let destination_public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &destination_private_key);
+        let payload_digest = [3; 32];
+
+        let utxos = vec![
+            (
+                Outpoint {
+                    tx_id: [1; 32],
+                    vout: 0,
+                },
+                Output {
+                    value: 5_000,
+                    script: Script::default(),
+                },
+            ),
+            (
+                Outpoint {
+                    tx_id: [1; 32],
+                    vout: 1,
+                },
+                Output {
+                    value: 100_000,
+                    script: Script::default(),
+                },
+            ),
+        ];
+        let unsigned_stamp = build_stamp_outputs(
+            &payload_digest,
+            &destination_public_key,
+            &[4_000, 4_000],
+            &utxos,
+            vec![0x51].into(),
+            1_000,
+        )
+        .unwrap();
+        let transaction = unsigned_stamp.psbt.transaction.clone();
+        assert_eq!(transaction.inputs.len(), 2);
+        assert_eq!(transaction.outputs.len(), 3);
+        assert_eq!(unsigned_stamp.vouts, vec![0, 1]);
+
+        // Change covers the fee
+        let fee = transaction.fee(&[5_000, 100_000]).unwrap();
+        assert!(fee > 0 && fee < 1_000);
+
+        // Stamp verifies against the destination
+        let stamp = unsigned_stamp.into_stamp(&transaction);
+        stamp
+            .verify_stamp(&payload_digest, &destination_public_key)
+            .unwrap();
+        assert_eq!(stamp.value().unwrap(), 8_000);
+
+        // Stamp does not verify along another path, which must not be hardened
+        assert!(matches!(
+            stamp.verify_stamp_with_path(&payload_digest, &destination_public_key, &[44, 899]),
+            Err(StampError::UnexpectedAddress(..))
+        ));
+        assert_eq!(
+            stamp.verify_stamp_with_path(&payload_digest, &destination_public_key, &[1 << 31]),
+            Err(StampError::ChildNumberOverflow)
+        );
+
+        // Stamp built along another path verifies along that path only
+        let unsigned_stamp = build_stamp_outputs_with_path(
+            &payload_digest,
+            &destination_public_key,
+            &[4_000],
+            &utxos,
+            vec![0x51].into(),
+            1_000,
+            &[44, 899],
+        )
+        .unwrap();
+        let transaction = unsigned_stamp.psbt.transaction.clone();
+        let other_stamp = unsigned_stamp.into_stamp(&transaction);
+        other_stamp
+            .verify_stamp_with_path(&payload_digest, &destination_public_key, &[44, 899])
+            .unwrap();
+        assert!(matches!(
+            other_stamp.verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::UnexpectedAddress(..))
+        ));
+
+        // Stamp does not verify as a burn
+        let burn_stamp = Stamp {
+            stamp_type: StampType::Burn.into(),
+            ..stamp.clone()
+        };
+        assert_eq!(
+            burn_stamp.verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::NotBurn)
+        );
+
+        // Insufficient funds
+        assert!(matches!(
+            build_stamp_outputs(
+                &payload_digest,
+                &destination_public_key,
+                &[200_000],
+                &utxos,
+                Script::default(),
+                1_000,
+            ),
+            Err(BuildStampError::InsufficientFunds(105_000, _))
+        ));
+    }
+
+    #[test]
+    fn verify_burn() {
+        let destination_private_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let destination_public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &destination_private_key);
+        let payload_digest = [3; 32];
+
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![Input::default()],
+            outputs: vec![
+                Output {
+                    value: 1_000,
+                    script: vec![0x51].into(),
+                },
+                Output {
+                    value: 5_000,
+                    script: burn_script(&payload_digest),
+                },
+                Output {
+                    value: 5_000,
+                    script: burn_script(&[4; 32]),
+                },
+            ],
+            lock_time: 0,
+        };
+        let mut stamp_tx = Vec::with_capacity(transaction.encoded_len());
+        transaction.encode(&mut stamp_tx).unwrap();
+        let burn_stamp = |vouts: Vec<u32>| Stamp {
+            stamp_type: StampType::Burn.into(),
+            stamp_outpoints: vec![StampOutpoints {
+                stamp_tx: stamp_tx.clone(),
+                vouts,
+            }],
+        };
+
+        let stamp = burn_stamp(vec![1]);
+        stamp
+            .verify_stamp(&payload_digest, &destination_public_key)
+            .unwrap();
+        assert_eq!(stamp.value().unwrap(), 5_000);
+
+        // Outputs must burn and commit to the payload digest
+        assert_eq!(
+            burn_stamp(vec![0]).verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::NotBurn)
+        );
+        assert_eq!(
+            burn_stamp(vec![2]).verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::NotBurn)
+        );
+        assert_eq!(
+            burn_stamp(vec![3]).verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::MissingOutput)
+        );
+
+        // Burns must specify outputs
+        assert_eq!(
+            burn_stamp(vec![]).verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::NoBurnOutputs)
+        );
+        let empty_stamp = Stamp {
+            stamp_outpoints: Vec::new(),
+            ..burn_stamp(vec![1])
+        };
+        assert_eq!(
+            empty_stamp.verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::NoBurnOutputs)
+        );
+
+        // Repeated outputs are rejected, and valued once
+        let repeated_stamp = burn_stamp(vec![1, 1]);
+        assert_eq!(
+            repeated_stamp.verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::DuplicateOutput(1))
+        );
+        assert_eq!(repeated_stamp.value().unwrap(), 5_000);
+        let relisted_stamp = Stamp {
+            stamp_outpoints: [stamp.stamp_outpoints.clone(), stamp.stamp_outpoints].concat(),
+            ..burn_stamp(vec![1])
+        };
+        assert_eq!(
+            relisted_stamp.verify_stamp(&payload_digest, &destination_public_key),
+            Err(StampError::DuplicateOutput(1))
+        );
+        assert_eq!(relisted_stamp.value().unwrap(), 5_000);
+    }
+}
@@ -0,0 +1,122 @@
+//! This module contains [`address_decode`], decoding the addresses given in requests.
+
+use bitcoin::{
+    cashaddr::{self, AddressType},
+    Network,
+};
+use bitcoincash_addr::{Address, HashType, Network as AddressNetwork, Scheme};
+use thiserror::Error;
+use warp::reject::Reject;
+
+use crate::ToResponse;
+
+/// Error associated with decoding an address.
+#[derive(Debug, Error)]
+pub enum AddressDecode {
+    /// The address is neither a valid cashaddr nor base58 address.
+    #[error("address decoding failed: {0}, {1}")]
+    Decode(
+        bitcoincash_addr::cashaddr::DecodingError,
+        bitcoincash_addr::base58::DecodingError,
+    ),
+    /// The address carries an additional prefix but is malformed.
+    #[error("address decoding failed: {0}")]
+    CashAddr(cashaddr::DecodingError),
+    /// The address payload is not the expected length.
+    #[error("expected address payload of length 20, found {0}")]
+    UnexpectedBodyLength(usize),
+    /// The address belongs to another network.
+    #[error("address is not on the {0} network")]
+    NetworkMismatch(String),
+}
+
+impl Reject for AddressDecode {}
+
+impl ToResponse for AddressDecode {
+    fn to_status(&self) -> u16 {
+        400
+    }
+}
+
+/// Checks whether an address network belongs to the given network.
+///
+/// Legacy testnet and regtest addresses share version bytes, so testnet addresses are accepted on
+/// regtest.
+pub fn network_matches(network: Network, address_network: &AddressNetwork) -> bool {
+    matches!(
+        (network, address_network),
+        (Network::Mainnet, AddressNetwork::Main)
+            | (Network::Testnet, AddressNetwork::Test)
+            | (Network::Regtest, AddressNetwork::Regtest)
+            | (Network::Regtest, AddressNetwork::Test)
+    )
+}
+
+/// Decode an address using one of the additional `prefixes`.
+///
+/// The address is assumed to belong to `network`.
+fn address_decode_prefixed(
+    addr_str: &str,
+    network: Network,
+    prefixes: &[String],
+) -> Result<Address, cashaddr::DecodingError> {
+    let cash_address = cashaddr::decode(addr_str, prefixes)?;
+    let hash_type = match cash_address.address_type {
+        AddressType::P2PKH => HashType::Key,
+        AddressType::P2SH => HashType::Script,
+    };
+    let network = match network {
+        Network::Mainnet => AddressNetwork::Main,
+        Network::Testnet => AddressNetwork::Test,
+        Network::Regtest => AddressNetwork::Regtest,
+    };
+    Ok(Address {
+        body: cash_address.hash,
+        scheme: Scheme::CashAddr,
+        hash_type,
+        network,
+    })
+}
+
+/// Decode an address string, accepting the additional cashaddr `prefixes` and requiring the address
+/// to belong to `network`.
+pub fn address_decode(
+    addr_str: &str,
+    network: Network,
+    prefixes: &[String],
+) -> Result<Address, AddressDecode> {
+    // Convert address
+    let address = match Address::decode(addr_str) {
+        Ok(ok) => ok,
+        Err((cash_err, base58_err)) => match address_decode_prefixed(addr_str, network, prefixes) {
+            Ok(ok) => ok,
+            Err(cashaddr::DecodingError::UnexpectedPrefix(_))
+            | Err(cashaddr::DecodingError::MissingPrefix) => {
+                return Err(AddressDecode::Decode(cash_err, base58_err))
+            }
+            Err(err) => return Err(AddressDecode::CashAddr(err)),
+        },
+    };
+
+    // Check address network
+    if !network_matches(network, &address.network) {
+        return Err(AddressDecode::NetworkMismatch(network.into()));
+    }
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks() {
+        assert!(network_matches(Network::Mainnet, &AddressNetwork::Main));
+        assert!(network_matches(Network::Testnet, &AddressNetwork::Test));
+        assert!(network_matches(Network::Regtest, &AddressNetwork::Regtest));
+        assert!(network_matches(Network::Regtest, &AddressNetwork::Test));
+        assert!(!network_matches(Network::Mainnet, &AddressNetwork::Test));
+        assert!(!network_matches(Network::Testnet, &AddressNetwork::Main));
+        assert!(!network_matches(Network::Regtest, &AddressNetwork::Main));
+    }
+}
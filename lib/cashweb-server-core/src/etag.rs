@@ -0,0 +1,65 @@
+//! This module contains helpers for entity tags and cache headers.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Construct a strong entity tag from a payload digest.
+pub fn etag(payload_digest: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(payload_digest))
+}
+
+/// Checks whether an `If-None-Match` or `If-Match` header value matches an entity tag.
+///
+/// Weak comparison is used, so `W/` prefixes are ignored.
+pub fn etag_matches(header_value: &str, etag: &str) -> bool {
+    header_value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// Construct a `Cache-Control` header value permitting shared caches to store a response for
+/// `max_age` seconds.
+///
+/// A `max_age` of zero requires caches to revalidate every response.
+pub fn cache_control(max_age: u64) -> String {
+    if max_age == 0 {
+        "no-cache".to_string()
+    } else {
+        format!("public, max-age={}", max_age)
+    }
+}
+
+/// Construct a `Last-Modified` header value from a signed timestamp, given in milliseconds.
+pub fn last_modified(timestamp: i64) -> Option<String> {
+    if timestamp <= 0 {
+        return None;
+    }
+    let time = UNIX_EPOCH + Duration::from_millis(timestamp as u64);
+    Some(httpdate::fmt_http_date(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let tag = etag(&[0xab, 0xcd]);
+        assert_eq!(tag, "\"abcd\"");
+        assert!(etag_matches("\"abcd\"", &tag));
+        assert!(etag_matches("\"0000\", W/\"abcd\"", &tag));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches("\"0000\"", &tag));
+        assert!(!etag_matches("abcd", &tag));
+    }
+
+    #[test]
+    fn cache_headers() {
+        assert_eq!(cache_control(0), "no-cache");
+        assert_eq!(cache_control(60), "public, max-age=60");
+        assert_eq!(
+            last_modified(784_111_777_000).unwrap(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(last_modified(0), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! This module contains [`TestServer`], serving a filter inside the test process for end-to-end
+//! tests.
+
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    sync::oneshot,
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
+use warp::{filters::BoxedFilter, Reply};
+
+/// Interval at which [`wait_until`] checks its condition.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A server running inside the test process, bound to an ephemeral local port.
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Serve `filter` on an ephemeral local port.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn<R>(filter: BoxedFilter<(R,)>) -> Self
+    where
+        R: Reply + 'static,
+    {
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let (addr, server) =
+            warp::serve(filter).bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async move {
+                shutdown_signal.await.ok();
+            });
+        let server = tokio::spawn(server);
+        Self {
+            addr,
+            shutdown,
+            server,
+        }
+    }
+
+    /// The bound address.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of a path on the server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{}", self.addr, path)
+    }
+
+    /// The websocket URL of a path on the server.
+    pub fn ws_url(&self, path: &str) -> String {
+        format!("ws://{}/{}", self.addr, path)
+    }
+
+    /// Stop the server, once its connections are closed.
+    pub async fn shutdown(self) {
+        self.shutdown.send(()).ok();
+        self.server.await.unwrap();
+    }
+}
+
+/// Wait until `condition` holds, checking it periodically.
+///
+/// # Panics
+///
+/// Panics if `condition` does not hold within `timeout`.
+pub async fn wait_until(mut condition: impl FnMut() -> bool, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while !condition() {
+        assert!(
+            Instant::now() < deadline,
+            "condition not met within {:?}",
+            timeout
+        );
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::Filter;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serve_and_wait() {
+        let server = TestServer::spawn(warp::any().map(|| "ok").boxed());
+        assert!(server.url("path").ends_with("/path"));
+        assert_eq!(server.addr().ip().to_string(), "127.0.0.1");
+
+        let mut n_checks = 0;
+        wait_until(
+            || {
+                n_checks += 1;
+                n_checks == 3
+            },
+            Duration::from_secs(1),
+        )
+        .await;
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "condition not met")]
+    async fn wait_timeout() {
+        wait_until(|| false, Duration::from_millis(20)).await;
+    }
+}
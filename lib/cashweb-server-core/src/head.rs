@@ -0,0 +1,51 @@
+//! This module contains helpers for serving HEAD requests.
+
+use warp::{
+    http::{header::CONTENT_LENGTH, HeaderValue, Method, Response},
+    hyper::{body::HttpBody, Body},
+    Filter, Rejection, Reply,
+};
+
+/// Filter matching GET and HEAD requests.
+pub fn get_or_head() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::get().or(warp::head()).unify()
+}
+
+/// Remove the body of responses to HEAD requests, keeping its length in the `Content-Length`
+/// header when known.
+pub fn head_response<R: Reply>(reply: R, method: Method) -> Response<Body> {
+    let mut response = reply.into_response();
+    if method != Method::HEAD {
+        return response;
+    }
+
+    if let Some(len) = response.body().size_hint().exact() {
+        response
+            .headers_mut()
+            .entry(CONTENT_LENGTH)
+            .or_insert_with(|| HeaderValue::from(len));
+    }
+    *response.body_mut() = Body::empty();
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_head_body() {
+        let response = Response::builder()
+            .header("ETag", "\"abcd\"")
+            .body(Body::from(vec![0; 5]))
+            .unwrap();
+        let response = head_response(response, Method::HEAD);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(response.headers()["ETag"], "\"abcd\"");
+        assert_eq!(response.body().size_hint().exact(), Some(0));
+
+        let response = Response::new(Body::from(vec![0; 5]));
+        let response = head_response(response, Method::GET);
+        assert_eq!(response.body().size_hint().exact(), Some(5));
+    }
+}
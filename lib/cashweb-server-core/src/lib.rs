@@ -0,0 +1,109 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-server-core` is a library providing the HTTP scaffolding shared by the cash:web
+//! Keyserver and Relay servers, such as structured error responses, address decoding, load
+//! shedding, CORS and settings loading.
+//!
+//! Enabling the `test-harness` feature exposes a [`harness::TestServer`] for end-to-end tests.
+
+pub mod address;
+pub mod etag;
+#[cfg(feature = "test-harness")]
+pub mod harness;
+pub mod head;
+pub mod listener;
+pub mod load_shed;
+#[cfg(feature = "monitoring")]
+pub mod monitoring;
+pub mod problem;
+pub mod settings;
+pub mod webhook;
+
+use tracing::error;
+use warp::{
+    cors::Builder,
+    http::{header, Method, Response},
+    hyper::Body,
+    reject::{PayloadTooLarge, Rejection},
+};
+
+use address::AddressDecode;
+use load_shed::Overloaded;
+use problem::{Problem, REQUEST_ID};
+
+/// Helper trait for converting errors into a response.
+pub trait ToResponse: std::error::Error + Sized {
+    /// Convert error into a status code.
+    fn to_status(&self) -> u16;
+
+    /// Convert error into a structured `Problem`.
+    fn to_problem(&self) -> Problem {
+        Problem::from_error(self, self.to_status())
+    }
+
+    /// Convert error into a `Response`.
+    fn to_response(&self) -> Response<Body> {
+        self.to_problem().into_response()
+    }
+}
+
+/// Convert the rejections common to every server into a `Response`.
+///
+/// Servers handle their own rejections first, deferring the remainder to this.
+pub fn handle_common_rejection(err: &Rejection) -> Response<Body> {
+    if let Some(err) = err.find::<AddressDecode>() {
+        error!(message = "invalid address", error = %err);
+        return err.to_response();
+    }
+
+    if let Some(err) = err.find::<Overloaded>() {
+        error!(message = "load shed", error = %err);
+        return err.to_response();
+    }
+
+    if err.find::<PayloadTooLarge>().is_some() {
+        error!("payload too large");
+        return Problem::new(413, "payload_too_large", "payload too large").into_response();
+    }
+
+    if err.is_not_found() {
+        error!("page not found");
+        return Problem::new(404, "not_found", "not found").into_response();
+    }
+
+    error!(message = "unexpected error", error = ?err);
+    Problem::new(500, "internal", "internal server error").into_response()
+}
+
+/// Construct the CORS policy shared by the servers, allowing any origin.
+///
+/// Servers may extend the policy with their own headers before building it.
+pub fn cors() -> Builder {
+    warp::cors()
+        .allow_any_origin()
+        .allow_methods(vec![
+            Method::GET,
+            Method::HEAD,
+            Method::PUT,
+            Method::POST,
+            Method::DELETE,
+        ])
+        .allow_headers(vec![
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_NONE_MATCH,
+        ])
+        .expose_headers(vec![
+            header::AUTHORIZATION,
+            header::ACCEPT,
+            header::LOCATION,
+            header::ETAG,
+        ])
+        .allow_header(REQUEST_ID)
+        .expose_header(REQUEST_ID)
+}
@@ -0,0 +1,44 @@
+//! This module contains [`serve`], serving a filter on TCP and unix domain sockets.
+
+use std::{fs, io, os::unix::fs::FileTypeExt, path::Path};
+
+use futures::future::select_all;
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tracing::info;
+use warp::{filters::BoxedFilter, Reply};
+
+use crate::settings::BindAddr;
+
+/// Bind a unix domain socket, replacing a stale socket left by a previous run.
+fn bind_unix(path: &Path) -> Result<UnixListener, io::Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        _ => (),
+    }
+    UnixListener::bind(path)
+}
+
+/// Serve `filter` on every address given, returning when any of the servers stops.
+///
+/// # Panics
+///
+/// Panics if an address cannot be bound.
+pub async fn serve<R>(filter: BoxedFilter<(R,)>, addrs: &'static [BindAddr])
+where
+    R: Reply + 'static,
+{
+    let servers = addrs.iter().map(|addr| {
+        info!(message = "listening", address = %addr);
+        let server = warp::serve(filter.clone());
+        match addr {
+            BindAddr::Tcp(addr) => tokio::spawn(server.run(*addr)),
+            BindAddr::Unix(path) => {
+                let listener = bind_unix(path).expect("failed to bind unix socket");
+                tokio::spawn(server.run_incoming(UnixListenerStream::new(listener)))
+            }
+        }
+    });
+    let (result, _, _) = select_all(servers).await;
+    result.unwrap(); // Unrecoverable
+}
@@ -0,0 +1,108 @@
+//! This module contains [`LoadShedder`], which sheds requests when the server is saturated.
+
+use std::{sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::timeout,
+};
+use warp::{
+    http::{HeaderValue, Response},
+    hyper::Body,
+    reject::Reject,
+    Reply,
+};
+
+use crate::ToResponse;
+
+/// Seconds clients are asked to wait before retrying an overloaded server.
+const RETRY_AFTER: u64 = 1;
+
+/// The server is too busy to handle a request.
+#[derive(Debug, Error)]
+#[error("server overloaded")]
+pub struct Overloaded;
+
+impl Reject for Overloaded {}
+
+impl ToResponse for Overloaded {
+    fn to_status(&self) -> u16 {
+        503
+    }
+
+    fn to_response(&self) -> Response<Body> {
+        let mut response = self.to_problem().into_response();
+        response
+            .headers_mut()
+            .insert("Retry-After", HeaderValue::from(RETRY_AFTER));
+        response
+    }
+}
+
+/// Limits the number of requests handled concurrently, shedding those which cannot be handled
+/// promptly.
+#[derive(Clone, Debug)]
+pub struct LoadShedder {
+    permits: Option<Arc<Semaphore>>,
+    queue_timeout: Duration,
+}
+
+impl LoadShedder {
+    /// Construct a load shedder.
+    ///
+    /// Requests beyond `max_concurrent` wait up to `queue_timeout` for a permit. A `max_concurrent`
+    /// of zero disables load shedding.
+    pub fn new(max_concurrent: usize, queue_timeout: Duration) -> Self {
+        let permits = if max_concurrent == 0 {
+            None
+        } else {
+            Some(Arc::new(Semaphore::new(max_concurrent)))
+        };
+        Self {
+            permits,
+            queue_timeout,
+        }
+    }
+
+    /// Acquire a permit to handle a request, which is released when dropped.
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, Overloaded> {
+        let permits = match &self.permits {
+            Some(some) => some.clone(),
+            None => return Ok(None),
+        };
+        if let Ok(permit) = permits.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+        match timeout(self.queue_timeout, permits.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            _ => Err(Overloaded),
+        }
+    }
+}
+
+/// Release the permit held while handling a request.
+pub fn release_permit<R: Reply>(_permit: Option<OwnedSemaphorePermit>, reply: R) -> R {
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shed_when_saturated() {
+        let load_shedder = LoadShedder::new(1, Duration::from_millis(10));
+        let permit = load_shedder.acquire().await.unwrap();
+        assert!(permit.is_some());
+        assert!(load_shedder.acquire().await.is_err());
+
+        // Released permits are reused
+        drop(permit);
+        assert!(load_shedder.acquire().await.unwrap().is_some());
+
+        // Disabled
+        let load_shedder = LoadShedder::new(0, Duration::from_millis(10));
+        assert!(load_shedder.acquire().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,13 @@
+//! This module contains helpers for exporting Prometheus metrics.
+
+use prometheus::{Encoder, TextEncoder};
+
+/// Encode the metrics in the default registry in the Prometheus text format.
+pub fn export() -> Vec<u8> {
+    let metric_families = prometheus::gather();
+
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+    buffer
+}
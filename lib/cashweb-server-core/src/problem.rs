@@ -0,0 +1,212 @@
+//! This module contains [`Problem`], a structured error description attached to error responses.
+
+use std::{error::Error, fmt};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use warp::{
+    http::{header::CONTENT_TYPE, HeaderValue, Response},
+    hyper::Body,
+    Reply,
+};
+
+/// Header carrying the ID of a request, given by the client or generated by the server.
+pub const REQUEST_ID: &str = "X-Request-Id";
+
+const MAX_REQUEST_ID_LEN: usize = 64;
+const PROBLEM_JSON: &str = "application/problem+json";
+const JSON: &str = "application/json";
+
+/// A structured description of an error.
+///
+/// Problems are attached to error responses, which are plaintext unless the client accepts JSON.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Problem {
+    /// A stable code identifying the error.
+    pub code: String,
+    /// The HTTP status code of the response.
+    pub status: u16,
+    /// A human readable description of the error.
+    pub message: String,
+    /// The underlying cause of the error, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    /// The ID of the request, echoed from the client or generated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Problem {
+    /// Construct a problem without details.
+    pub fn new(status: u16, code: &str, message: &str) -> Self {
+        Self {
+            code: code.to_string(),
+            status,
+            message: message.to_string(),
+            details: None,
+            request_id: None,
+        }
+    }
+
+    /// Construct a problem from an error, hiding the details of internal errors.
+    pub fn from_error<E: Error>(err: &E, status: u16) -> Self {
+        let code = error_code(err);
+        if status == 500 {
+            return Self::new(status, &code, "internal server error");
+        }
+        Self {
+            details: err.source().map(ToString::to_string),
+            ..Self::new(status, &code, &err.to_string())
+        }
+    }
+
+    /// Construct a plaintext response carrying the problem.
+    pub fn into_response(self) -> Response<Body> {
+        let body = if self.status == 500 {
+            Body::empty()
+        } else {
+            Body::from(self.message.clone())
+        };
+        let mut response = Response::builder().status(self.status).body(body).unwrap();
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Derive an error code, in snake case, from the name of the error variant.
+fn error_code<E: fmt::Debug>(err: &E) -> String {
+    let debug = format!("{:?}", err);
+    let name: Vec<char> = debug
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+    let mut code = String::with_capacity(name.len() + 4);
+    for (index, c) in name.iter().enumerate() {
+        if c.is_ascii_uppercase() && index != 0 {
+            let prev_lower = name[index - 1].is_ascii_lowercase();
+            let next_lower = name.get(index + 1).map_or(false, char::is_ascii_lowercase);
+            if prev_lower || (next_lower && name[index - 1].is_ascii_uppercase()) {
+                code.push('_');
+            }
+        }
+        code.push(c.to_ascii_lowercase());
+    }
+    code
+}
+
+/// Checks whether a client supplied request ID is acceptable.
+pub fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Generate a random request ID.
+pub fn generate_request_id() -> String {
+    let mut raw_request_id = [0; 8];
+    SystemRandom::new().fill(&mut raw_request_id).unwrap(); // This is safe
+    hex::encode(raw_request_id)
+}
+
+/// Select the JSON content type preferred by an `Accept` header value, if any.
+fn json_content_type(accept: &str) -> Option<&'static str> {
+    accept
+        .split(',')
+        .filter_map(|item| item.split(';').next())
+        .find_map(|media_type| match media_type.trim() {
+            PROBLEM_JSON => Some(PROBLEM_JSON),
+            JSON => Some(JSON),
+            _ => None,
+        })
+}
+
+/// Tag error responses with a request ID, rendering their problem as JSON if the client accepts
+/// it.
+pub fn render_problem<R: Reply>(
+    reply: R,
+    accept: Option<String>,
+    request_id: Option<String>,
+) -> Response<Body> {
+    let mut response = reply.into_response();
+    let mut problem = match response.extensions_mut().remove::<Problem>() {
+        Some(some) => some,
+        None => return response,
+    };
+
+    // Use the client request ID, if valid
+    let request_id = request_id
+        .filter(|request_id| is_valid_request_id(request_id))
+        .unwrap_or_else(generate_request_id);
+    response.headers_mut().insert(
+        REQUEST_ID,
+        HeaderValue::from_str(&request_id).unwrap(), // This is safe
+    );
+    problem.request_id = Some(request_id);
+
+    if let Some(content_type) = accept.as_deref().and_then(json_content_type) {
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        *response.body_mut() = Body::from(serde_json::to_vec(&problem).unwrap());
+        // This is safe
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use thiserror::Error;
+
+    use super::*;
+
+    #[derive(Debug, Error)]
+    enum TestError {
+        #[error("digest not found")]
+        StartDigestNotFound,
+        #[error("failed to read from database: {0}")]
+        DB(String),
+        #[error("failed to decode")]
+        Decode(#[source] std::fmt::Error),
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(
+            error_code(&TestError::StartDigestNotFound),
+            "start_digest_not_found"
+        );
+        assert_eq!(error_code(&TestError::DB("foo".to_string())), "db");
+
+        let problem = Problem::from_error(&TestError::DB("secret".to_string()), 500);
+        assert_eq!(problem.message, "internal server error");
+        let problem = Problem::from_error(&TestError::Decode(std::fmt::Error), 400);
+        assert_eq!(
+            problem.details.as_deref(),
+            Some("an error occurred when formatting an argument")
+        );
+    }
+
+    #[test]
+    fn render() {
+        let response = Problem::new(404, "not_found", "not found").into_response();
+        let response = render_problem(
+            response,
+            Some("text/html, application/json;q=0.9".to_string()),
+            Some("abc-123".to_string()),
+        );
+        assert_eq!(response.headers()[CONTENT_TYPE], JSON);
+        assert_eq!(response.headers()[REQUEST_ID], "abc-123");
+
+        // Plaintext is kept if JSON is not accepted, and malformed IDs are replaced
+        let response = Problem::new(404, "not_found", "not found").into_response();
+        let response = render_problem(response, None, Some("bad id".to_string()));
+        assert!(response.headers().get(CONTENT_TYPE).is_none());
+        assert_eq!(response.headers()[REQUEST_ID].len(), 16);
+
+        // Successful responses are untouched
+        let response = render_problem(Response::new(Body::empty()), None, None);
+        assert!(response.headers().get(REQUEST_ID).is_none());
+    }
+}
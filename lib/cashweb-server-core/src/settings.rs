@@ -0,0 +1,128 @@
+//! This module contains the settings shared by the servers and helpers for loading them.
+
+use std::{
+    fmt,
+    net::{AddrParseError, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use config::{Config, ConfigError, File};
+use serde::{de, Deserialize, Deserializer};
+
+/// Prefix of addresses referring to unix domain sockets.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// Address a listener binds to, either a TCP socket address or a `unix:` prefixed socket path.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BindAddr {
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+    /// The path of a unix domain socket.
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(UNIX_PREFIX) {
+            Some(path) => Ok(Self::Unix(path.into())),
+            None => s.parse().map(Self::Tcp),
+        }
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BindAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let addr = String::deserialize(deserializer)?;
+        addr.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Deserialize either a single value or a list of values.
+pub fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Limits on the requests handled concurrently.
+#[derive(Debug, Deserialize)]
+pub struct LoadShedding {
+    /// Requests handled concurrently, zero if unlimited.
+    pub max_concurrent: usize,
+    /// Time, in milliseconds, a request waits to be handled before being shed.
+    pub queue_timeout: u64,
+}
+
+/// Get the home directory, under which the data directory is placed by default.
+pub fn home_dir() -> Result<PathBuf, ConfigError> {
+    dirs::home_dir().ok_or_else(|| ConfigError::Message("no home directory".to_string()))
+}
+
+/// Merge the config file at `config_path`, defaulting to `config` within `data_dir`, if it exists.
+pub fn merge_config_file(
+    s: &mut Config,
+    data_dir: &Path,
+    config_path: Option<&str>,
+) -> Result<(), ConfigError> {
+    let default_config = data_dir.join("config");
+    let default_config_str = default_config.to_str().unwrap();
+    let config_path = config_path.unwrap_or(default_config_str);
+    s.merge(File::with_name(config_path).required(false))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_addr() {
+        let tcp: BindAddr = "[::1]:8080".parse().unwrap();
+        assert_eq!(tcp, BindAddr::Tcp("[::1]:8080".parse().unwrap()));
+        assert_eq!(tcp.to_string(), "[::1]:8080");
+
+        let unix: BindAddr = "unix:/run/relay.sock".parse().unwrap();
+        assert_eq!(unix, BindAddr::Unix("/run/relay.sock".into()));
+        assert_eq!(unix.to_string(), "unix:/run/relay.sock");
+
+        assert!("localhost".parse::<BindAddr>().is_err());
+    }
+
+    #[test]
+    fn bind_one_or_many() {
+        let mut s = Config::new();
+        s.set("bind", "127.0.0.1:8080").unwrap();
+        let bind: Vec<BindAddr> = one_or_many(s.get::<config::Value>("bind").unwrap()).unwrap();
+        assert_eq!(bind, vec![BindAddr::Tcp("127.0.0.1:8080".parse().unwrap())]);
+
+        s.set("bind", vec!["127.0.0.1:8080", "unix:relay.sock"])
+            .unwrap();
+        let bind: Vec<BindAddr> = one_or_many(s.get::<config::Value>("bind").unwrap()).unwrap();
+        assert_eq!(bind.len(), 2);
+        assert_eq!(bind[1], BindAddr::Unix("relay.sock".into()));
+    }
+}
@@ -0,0 +1,92 @@
+//! This module contains [`notify_payment`], reporting accepted payments to the operator.
+
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Client, Method, Request,
+};
+use hyper_tls::HttpsConnector;
+use ring::hmac;
+use serde::Serialize;
+use tracing::{error, info};
+
+/// Header carrying the hex encoded HMAC-SHA256 of the webhook request body.
+pub const WEBHOOK_SIGNATURE: &str = "X-Webhook-Signature";
+
+/// An accepted payment, reported to the operator's webhook.
+#[derive(Debug, Serialize)]
+pub struct PaymentEvent {
+    /// Address the token was issued for.
+    pub address: String,
+    /// IDs of the transactions in the payment.
+    pub txids: Vec<String>,
+    /// Amount paid, in satoshis.
+    pub amount: u64,
+    /// Unix timestamp, in seconds, at which the payment was accepted.
+    pub timestamp: u64,
+}
+
+/// Construct the webhook request, signing the body with `secret` if given.
+fn webhook_request(url: &str, secret: Option<&str>, event: &PaymentEvent) -> Request<Body> {
+    let body = serde_json::to_vec(event).unwrap(); // This is safe
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Some(secret) = secret {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = hmac::sign(&key, &body);
+        request = request.header(WEBHOOK_SIGNATURE, hex::encode(signature.as_ref()));
+    }
+    request.body(Body::from(body)).unwrap() // This is safe
+}
+
+/// Notify the payment webhook at `url`, if given, of an accepted payment, signing the request with
+/// `secret` if given.
+///
+/// The request is sent in the background and failures are only logged.
+pub fn notify_payment(url: Option<&str>, secret: Option<&str>, event: PaymentEvent) {
+    let url = match url {
+        Some(some) => some,
+        None => return,
+    };
+    let request = webhook_request(url, secret, &event);
+    tokio::spawn(async move {
+        let client: Client<HttpsConnector<HttpConnector>> =
+            Client::builder().build(HttpsConnector::new());
+        match client.request(request).await {
+            Ok(response) if response.status().is_success() => {
+                info!(message = "payment webhook notified", address = %event.address)
+            }
+            Ok(response) => {
+                error!(message = "payment webhook rejected", status = %response.status())
+            }
+            Err(err) => error!(message = "payment webhook failed", error = %err),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_request() {
+        let event = PaymentEvent {
+            address: "bitcoincash:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65".to_string(),
+            txids: vec!["00".repeat(32)],
+            amount: 1000,
+            timestamp: 1,
+        };
+        let secret = "secret";
+        let request = webhook_request("http://127.0.0.1:8080/hook", Some(secret), &event);
+        let body = serde_json::to_vec(&event).unwrap();
+
+        let signature = hex::decode(request.headers()[WEBHOOK_SIGNATURE].as_bytes()).unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        assert!(hmac::verify(&key, &body, &signature).is_ok());
+
+        let request = webhook_request("http://127.0.0.1:8080/hook", None, &event);
+        assert!(request.headers().get(WEBHOOK_SIGNATURE).is_none());
+    }
+}
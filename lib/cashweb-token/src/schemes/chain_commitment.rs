@@ -3,7 +3,7 @@
 //!
 //! [`Keyserver Protocol`]: https://github.com/cashweb/specifications/blob/master/keyserver-protocol/specification.mediawiki
 
-use std::convert::TryInto;
+use std::{collections::HashSet, convert::TryInto};
 
 use cashweb_bitcoin::{
     transaction::{self, Transaction},
@@ -40,15 +40,33 @@ pub enum ValidationError {
     /// Token was unexpected length.
     #[error("unexpected token length")]
     TokenLength,
+    /// Token referred to more outpoints than permitted.
+    #[error("too many outpoints: {0} > {1}")]
+    TooManyOutpoints(usize, usize),
 }
 
 /// Chain commitment scheme used in the keyserver protocol.
 #[derive(Clone, Debug)]
 pub struct ChainCommitmentScheme<C: BitcoinClient> {
     client: C,
+    max_outpoints: usize,
 }
 
 const COMMITMENT_LEN: usize = 32;
+const OUTPOINT_LEN: usize = 32 + 4;
+
+/// Default maximum number of outpoints in a token.
+pub const DEFAULT_MAX_OUTPOINTS: usize = 16;
+
+/// A validated token, alongside the value committed to by its transactions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatedToken {
+    /// The raw token, the outpoints of the commitment.
+    pub token: Vec<u8>,
+    /// Total value, in satoshis, of the outputs in the token's transactions carrying the
+    /// commitment.
+    pub value: u64,
+}
 
 /// Construct the commitment.
 pub fn construct_commitment(pub_key_hash: &[u8], address_metadata_hash: &[u8]) -> Vec<u8> {
@@ -65,7 +83,22 @@ pub fn construct_token_raw(tx_id: &[u8], vout: u32) -> Vec<u8> {
 
 /// Construct the token.
 pub fn construct_token(tx_id: &[u8], vout: u32) -> String {
-    let raw_token = construct_token_raw(tx_id, vout);
+    construct_split_token(&[(tx_id, vout)])
+}
+
+/// Construct the raw token of a commitment split across several transactions, given an outpoint
+/// of the commitment in each.
+pub fn construct_split_token_raw(outpoints: &[(&[u8], u32)]) -> Vec<u8> {
+    outpoints
+        .iter()
+        .flat_map(|(tx_id, vout)| construct_token_raw(tx_id, *vout))
+        .collect()
+}
+
+/// Construct the token of a commitment split across several transactions, given an outpoint of
+/// the commitment in each.
+pub fn construct_split_token(outpoints: &[(&[u8], u32)]) -> String {
+    let raw_token = construct_split_token_raw(outpoints);
     let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
     base64::encode_config(raw_token, url_safe_config)
 }
@@ -73,7 +106,16 @@ pub fn construct_token(tx_id: &[u8], vout: u32) -> String {
 impl<Client: BitcoinClient> ChainCommitmentScheme<Client> {
     /// Create a [`ChainCommitmentScheme`] from a [`BitcoinClient`].
     pub fn from_client(client: Client) -> Self {
-        ChainCommitmentScheme { client }
+        ChainCommitmentScheme {
+            client,
+            max_outpoints: DEFAULT_MAX_OUTPOINTS,
+        }
+    }
+
+    /// Set the maximum number of outpoints in a token.
+    pub fn with_max_outpoints(mut self, max_outpoints: usize) -> Self {
+        self.max_outpoints = max_outpoints;
+        self
     }
 
     /// Validate a token.
@@ -83,55 +125,97 @@ impl<Client: BitcoinClient> ChainCommitmentScheme<Client> {
         address_metadata_hash: &[u8],
         token: &str,
     ) -> Result<Vec<u8>, ValidationError> {
+        self.validate_commitment(pub_key_hash, address_metadata_hash, token)
+            .await
+            .map(|validated| validated.token)
+    }
+
+    /// Validate a token, returning it alongside the value committed.
+    ///
+    /// A token refers to an outpoint of the commitment in each transaction of the payment. A
+    /// commitment may also be split across several outputs of a transaction, the value is the
+    /// total of the outputs carrying the commitment across all the referenced transactions.
+    pub async fn validate_commitment(
+        &self,
+        pub_key_hash: &[u8],
+        address_metadata_hash: &[u8],
+        token: &str,
+    ) -> Result<ValidatedToken, ValidationError> {
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        let outpoint_raw =
+        let outpoints_raw =
             base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
 
         // Check token length
-        const PAYLOAD_LEN: usize = 32 + 4;
-        if outpoint_raw.len() != PAYLOAD_LEN {
+        if outpoints_raw.is_empty() || outpoints_raw.len() % OUTPOINT_LEN != 0 {
             return Err(ValidationError::TokenLength);
         }
-
-        // Parse ID
-        let tx_id = &outpoint_raw[..32];
-
-        // Get transaction
-        let raw_transaction = self
-            .client
-            .get_raw_transaction(tx_id)
-            .await
-            .map_err(ValidationError::Node)?;
-        let transaction = Transaction::decode(&mut raw_transaction.as_slice())
-            .map_err(ValidationError::Transaction)?;
-
-        // Get vout
-        let vout_raw: [u8; 4] = outpoint_raw[32..36].try_into().unwrap(); // This is safe
-        let vout = u32::from_le_bytes(vout_raw);
-
-        // Parse script
-        let output = transaction
-            .outputs
-            .get(vout as usize)
-            .ok_or(ValidationError::OutputNotFound)?;
-
-        if !output.script.is_op_return() {
-            return Err(ValidationError::NotOpReturn);
-        }
-
-        let raw_script = output.script.as_bytes();
-
-        // Check length
-        if raw_script.len() != 2 + COMMITMENT_LEN || raw_script[1] != COMMITMENT_LEN as u8 {
-            return Err(ValidationError::IncorrectLength);
+        let n_outpoints = outpoints_raw.len() / OUTPOINT_LEN;
+        if n_outpoints > self.max_outpoints {
+            return Err(ValidationError::TooManyOutpoints(
+                n_outpoints,
+                self.max_outpoints,
+            ));
         }
 
-        // Check commitment
-        let commitment = &raw_script[2..34];
         let expected_commitment = construct_commitment(pub_key_hash, address_metadata_hash);
-        if expected_commitment != commitment {
-            return Err(ValidationError::Invalid);
+        let mut tx_ids = HashSet::with_capacity(n_outpoints);
+        let mut value = 0;
+        for outpoint_raw in outpoints_raw.chunks(OUTPOINT_LEN) {
+            // Parse ID
+            let tx_id = &outpoint_raw[..32];
+
+            // Each transaction is only counted once
+            if !tx_ids.insert(tx_id) {
+                return Err(ValidationError::Invalid);
+            }
+
+            // Get transaction
+            let raw_transaction = self
+                .client
+                .get_raw_transaction(tx_id)
+                .await
+                .map_err(ValidationError::Node)?;
+            let transaction = Transaction::decode(&mut raw_transaction.as_slice())
+                .map_err(ValidationError::Transaction)?;
+
+            // Get vout
+            let vout_raw: [u8; 4] = outpoint_raw[32..36].try_into().unwrap(); // This is safe
+            let vout = u32::from_le_bytes(vout_raw);
+
+            // Parse script
+            let output = transaction
+                .outputs
+                .get(vout as usize)
+                .ok_or(ValidationError::OutputNotFound)?;
+
+            if !output.script.is_op_return() {
+                return Err(ValidationError::NotOpReturn);
+            }
+
+            let raw_script = output.script.as_bytes();
+
+            // Check length
+            if raw_script.len() != 2 + COMMITMENT_LEN || raw_script[1] != COMMITMENT_LEN as u8 {
+                return Err(ValidationError::IncorrectLength);
+            }
+
+            // Check commitment
+            let commitment = &raw_script[2..34];
+            if expected_commitment != commitment {
+                return Err(ValidationError::Invalid);
+            }
+
+            // Total the value of all outputs carrying the commitment
+            value += transaction
+                .outputs
+                .iter()
+                .filter(|output| output.script.as_bytes() == raw_script)
+                .map(|output| output.value)
+                .sum::<u64>();
         }
-        Ok(outpoint_raw)
+        Ok(ValidatedToken {
+            token: outpoints_raw,
+            value,
+        })
     }
 }
@@ -1,4 +1,9 @@
 //! This module contains [`HmacScheme`] which provides a rudimentary HMAC validation scheme.
+//!
+//! Tokens may be restricted to a set of [`Scopes`], allowing a limited-capability token to be
+//! handed to a third party.
+
+use std::{fmt, ops::BitOr, str::FromStr};
 
 use ring::hmac;
 use thiserror::Error;
@@ -14,6 +19,100 @@ pub enum ValidationError {
     Invalid,
 }
 
+/// Error associated with parsing [`Scopes`].
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("unknown scope: {0}")]
+pub struct UnknownScope(pub String);
+
+/// The set of capabilities granted by a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Scopes(u8);
+
+const SCOPE_NAMES: [(Scopes, &str); 4] = [
+    (Scopes::READ_MESSAGES, "read-messages"),
+    (Scopes::WRITE_PROFILE, "write-profile"),
+    (Scopes::DELETE, "delete"),
+    (Scopes::FEEDS, "feeds"),
+];
+
+impl Scopes {
+    /// Read messages and payloads, and subscribe to new messages.
+    pub const READ_MESSAGES: Self = Self(1);
+    /// Replace the profile.
+    pub const WRITE_PROFILE: Self = Self(1 << 1);
+    /// Delete and restore messages.
+    pub const DELETE: Self = Self(1 << 2);
+    /// Manage feeds.
+    pub const FEEDS: Self = Self(1 << 3);
+    /// All capabilities.
+    pub const ALL: Self = Self(0b1111);
+
+    /// The empty set of capabilities.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Construct from a bit representation, failing if unknown bits are set.
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits & !Self::ALL.0 == 0 {
+            Some(Self(bits))
+        } else {
+            None
+        }
+    }
+
+    /// The bit representation.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all of the capabilities in `other` are granted.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The names of the granted capabilities.
+    pub fn names(self) -> Vec<&'static str> {
+        SCOPE_NAMES
+            .iter()
+            .filter(|(scope, _)| self.contains(*scope))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl BitOr for Scopes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.names().join(","))
+    }
+}
+
+impl FromStr for Scopes {
+    type Err = UnknownScope;
+
+    /// Parse a comma separated list of scope names.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .try_fold(Self::empty(), |scopes, name| {
+                SCOPE_NAMES
+                    .iter()
+                    .find(|(_, scope_name)| *scope_name == name)
+                    .map(|(scope, _)| scopes | *scope)
+                    .ok_or_else(|| UnknownScope(name.to_string()))
+            })
+    }
+}
+
 /// Basic HMAC token scheme.
 #[derive(Debug)]
 pub struct HmacScheme {
@@ -27,17 +126,93 @@ impl HmacScheme {
         Self { key }
     }
 
-    /// Construct a token.
+    /// Construct a token granting all capabilities.
     pub fn construct_token(&self, data: &[u8]) -> String {
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
         let tag = hmac::sign(&self.key, data);
         base64::encode_config(tag.as_ref(), url_safe_config)
     }
 
-    /// Validate a token.
-    pub fn validate_token(&self, data: &[u8], token: &str) -> Result<(), ValidationError> {
+    /// Construct a token granting only the given capabilities.
+    ///
+    /// The scope is prepended to the tag, which covers both the data and the scope.
+    pub fn construct_scoped_token(&self, data: &[u8], scopes: Scopes) -> String {
+        if scopes == Scopes::ALL {
+            return self.construct_token(data);
+        }
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let tag = hmac::sign(&self.key, &[data, &[scopes.bits()]].concat());
+        let raw_token = [&[scopes.bits()][..], tag.as_ref()].concat();
+        base64::encode_config(raw_token, url_safe_config)
+    }
+
+    /// Validate a token, returning the capabilities it grants.
+    pub fn validate_token(&self, data: &[u8], token: &str) -> Result<Scopes, ValidationError> {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw_token =
+            base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
+
+        // Unscoped tokens consist of the tag alone
+        if raw_token.len() == hmac::HMAC_SHA256.digest_algorithm().output_len {
+            hmac::verify(&self.key, data, &raw_token).map_err(|_| ValidationError::Invalid)?;
+            return Ok(Scopes::ALL);
+        }
+
+        let (raw_scopes, tag) = raw_token.split_first().ok_or(ValidationError::Invalid)?;
+        hmac::verify(&self.key, &[data, &[*raw_scopes]].concat(), tag)
+            .map_err(|_| ValidationError::Invalid)?;
+        Scopes::from_bits(*raw_scopes).ok_or(ValidationError::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_parse() {
+        assert_eq!(
+            "read-messages, feeds".parse(),
+            Ok(Scopes::READ_MESSAGES | Scopes::FEEDS)
+        );
+        assert_eq!("".parse(), Ok(Scopes::empty()));
+        assert_eq!(
+            "read-messages,write".parse::<Scopes>(),
+            Err(UnknownScope("write".to_string()))
+        );
+        assert_eq!(
+            Scopes::ALL.to_string(),
+            "read-messages,write-profile,delete,feeds"
+        );
+    }
+
+    #[test]
+    fn scoped_tokens() {
+        let scheme = HmacScheme::new(&[1; 32]);
+        let data = [2; 20];
+
+        let token = scheme.construct_token(&data);
+        assert_eq!(scheme.validate_token(&data, &token), Ok(Scopes::ALL));
+
+        let scopes = Scopes::READ_MESSAGES | Scopes::DELETE;
+        let token = scheme.construct_scoped_token(&data, scopes);
+        assert_eq!(scheme.validate_token(&data, &token), Ok(scopes));
+        assert!(!scopes.contains(Scopes::WRITE_PROFILE));
+
+        // Tokens are bound to the data
+        assert_eq!(
+            scheme.validate_token(&[3; 20], &token),
+            Err(ValidationError::Invalid)
+        );
+
+        // Scopes cannot be escalated
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        let tag = base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
-        hmac::verify(&self.key, data, &tag).map_err(|_| ValidationError::Invalid)
+        let mut raw_token = base64::decode_config(&token, url_safe_config).unwrap();
+        raw_token[0] = Scopes::ALL.bits();
+        let forged_token = base64::encode_config(raw_token, url_safe_config);
+        assert_eq!(
+            scheme.validate_token(&data, &forged_token),
+            Err(ValidationError::Invalid)
+        );
     }
 }
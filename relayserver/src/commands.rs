@@ -0,0 +1,97 @@
+use cashweb::token::schemes::hmac_bearer::{HmacScheme, Scopes, UnknownScope};
+use hex::FromHexError;
+use thiserror::Error;
+
+use crate::{
+    db::Database,
+    net::{self, AddressDecode},
+    settings::Command,
+    SETTINGS,
+};
+
+const STATS_PROPERTIES: &[&str] = &[
+    "rocksdb.estimate-num-keys",
+    "rocksdb.estimate-live-data-size",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.stats",
+];
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("database failure: {0}")]
+    DB(rocksdb::Error),
+    #[error("invalid address: {0}")]
+    Address(AddressDecode),
+    #[error("unable to interpret hmac key as hex: {0}")]
+    HmacSecret(FromHexError),
+    #[error("invalid scopes: {0}")]
+    Scopes(UnknownScope),
+}
+
+impl From<rocksdb::Error> for CommandError {
+    fn from(err: rocksdb::Error) -> Self {
+        Self::DB(err)
+    }
+}
+
+/// Run an operational subcommand.
+pub fn run(command: &Command) -> Result<(), CommandError> {
+    match command {
+        Command::Serve => (),
+        Command::DbCompact => {
+            let db = Database::try_new(&SETTINGS.db_path)?;
+            db.compact();
+            println!("compaction complete");
+        }
+        Command::DbStats => {
+            let db = Database::try_new(&SETTINGS.db_path)?;
+            for name in STATS_PROPERTIES {
+                let value = db.property(name)?.unwrap_or_default();
+                println!("{}: {}", name, value);
+            }
+        }
+        Command::TokenIssue(addr_str, scopes_str) => {
+            let key =
+                hex::decode(&SETTINGS.payments.hmac_secret).map_err(CommandError::HmacSecret)?;
+            let scopes = match scopes_str {
+                Some(some) => some.parse().map_err(CommandError::Scopes)?,
+                None => Scopes::ALL,
+            };
+            let token = issue_token(&key, addr_str, scopes)?;
+            println!("POP {}", token);
+        }
+    }
+    Ok(())
+}
+
+/// Issue a POP token for an address, bypassing payment.
+pub fn issue_token(key: &[u8], addr_str: &str, scopes: Scopes) -> Result<String, CommandError> {
+    let addr = net::address_decode(addr_str).map_err(CommandError::Address)?;
+    let token_scheme = HmacScheme::new(key);
+    Ok(token_scheme.construct_scoped_token(addr.as_body(), scopes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_token_validates() {
+        let key = [1; 32];
+        let addr_str = "bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65";
+        let token = issue_token(&key, addr_str, Scopes::ALL).unwrap();
+
+        let addr = net::address_decode(addr_str).unwrap();
+        assert_eq!(
+            HmacScheme::new(&key).validate_token(addr.as_body(), &token),
+            Ok(Scopes::ALL)
+        );
+        assert!(issue_token(&key, "invalid", Scopes::ALL).is_err());
+
+        let token = issue_token(&key, addr_str, Scopes::READ_MESSAGES).unwrap();
+        assert_eq!(
+            HmacScheme::new(&key).validate_token(addr.as_body(), &token),
+            Ok(Scopes::READ_MESSAGES)
+        );
+    }
+}
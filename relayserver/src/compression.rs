@@ -0,0 +1,184 @@
+use std::{
+    borrow::Cow,
+    io::{self, Read, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use thiserror::Error;
+
+/// Protobuf field numbers begin at one, so no serialized message begins with a zero byte. This is
+/// used to flag compressed values in the database.
+const STORED_HEADER: u8 = 0;
+const STORED_ZSTD: u8 = 1;
+
+/// A content coding supported on the message endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    #[error("unsupported content encoding: {0}")]
+    Unsupported(String),
+    #[error("decompressed body too large")]
+    TooLarge,
+    #[error("failed to decompress: {0}")]
+    Io(io::Error),
+}
+
+impl Encoding {
+    /// Parse a `Content-Encoding` header value.
+    pub fn from_header(value: &str) -> Result<Self, CompressionError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(Self::Identity),
+            "gzip" | "x-gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            other => Err(CompressionError::Unsupported(other.to_string())),
+        }
+    }
+
+    /// Select the preferred encoding from an `Accept-Encoding` header value, preferring zstd.
+    pub fn negotiate(accept_encoding: &str) -> Self {
+        let accepted: Vec<&str> = accept_encoding
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';').map(str::trim);
+                let coding = parts.next()?;
+                let refused = parts.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .map_or(false, |q| q == 0.0)
+                });
+                if refused {
+                    None
+                } else {
+                    Some(coding)
+                }
+            })
+            .collect();
+        if accepted
+            .iter()
+            .any(|coding| coding.eq_ignore_ascii_case("zstd"))
+        {
+            Self::Zstd
+        } else if accepted
+            .iter()
+            .any(|coding| coding.eq_ignore_ascii_case("gzip"))
+        {
+            Self::Gzip
+        } else {
+            Self::Identity
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+pub fn compress(encoding: Encoding, raw: &[u8], level: i32) -> Vec<u8> {
+    match encoding {
+        Encoding::Identity => raw.to_vec(),
+        Encoding::Gzip => {
+            let mut encoder =
+                GzEncoder::new(Vec::new(), Compression::new(level.max(0).min(9) as u32));
+            encoder.write_all(raw).unwrap(); // This is safe
+            encoder.finish().unwrap() // This is safe
+        }
+        Encoding::Zstd => zstd::encode_all(raw, level).unwrap(), // This is safe
+    }
+}
+
+/// Decompress a body, failing if the result exceeds `limit` bytes.
+pub fn decompress(encoding: Encoding, raw: &[u8], limit: u64) -> Result<Vec<u8>, CompressionError> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        Encoding::Identity => return Ok(raw.to_vec()),
+        Encoding::Gzip => Box::new(GzDecoder::new(raw)),
+        Encoding::Zstd => Box::new(zstd::Decoder::new(raw).map_err(CompressionError::Io)?),
+    };
+    let mut decompressed = Vec::new();
+    reader
+        .take(limit + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(CompressionError::Io)?;
+    if decompressed.len() as u64 > limit {
+        return Err(CompressionError::TooLarge);
+    }
+    Ok(decompressed)
+}
+
+/// Prepare a serialized message for storage, compressing it if it is at least `threshold` bytes
+/// and compression is beneficial. A `threshold` of zero disables compression.
+pub fn pack_value(raw: &[u8], threshold: usize, level: i32) -> Cow<'_, [u8]> {
+    if threshold == 0 || raw.len() < threshold {
+        return Cow::Borrowed(raw);
+    }
+    let compressed = compress(Encoding::Zstd, raw, level);
+    if compressed.len() + 2 >= raw.len() {
+        return Cow::Borrowed(raw);
+    }
+    let mut value = Vec::with_capacity(compressed.len() + 2);
+    value.push(STORED_HEADER);
+    value.push(STORED_ZSTD);
+    value.extend_from_slice(&compressed);
+    Cow::Owned(value)
+}
+
+/// Recover a serialized message from a stored value.
+pub fn unpack_value(value: &[u8]) -> Cow<'_, [u8]> {
+    match value {
+        [STORED_HEADER, STORED_ZSTD, compressed @ ..] => {
+            Cow::Owned(zstd::decode_all(compressed).unwrap()) // This panics if stored bytes are malformed
+        }
+        _ => Cow::Borrowed(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Encoding::negotiate("gzip, deflate, br"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("gzip, zstd"), Encoding::Zstd);
+        assert_eq!(Encoding::negotiate("zstd;q=0, gzip;q=0.5"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("br"), Encoding::Identity);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let raw = vec![7; 4096];
+        for encoding in &[Encoding::Identity, Encoding::Gzip, Encoding::Zstd] {
+            let compressed = compress(*encoding, &raw, 3);
+            assert_eq!(decompress(*encoding, &compressed, 4096).unwrap(), raw);
+            if *encoding != Encoding::Identity {
+                assert!(matches!(
+                    decompress(*encoding, &compressed, 4095),
+                    Err(CompressionError::TooLarge)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn pack_unpack() {
+        let raw = vec![7; 4096];
+        let packed = pack_value(&raw, 1024, 3);
+        assert!(packed.len() < raw.len());
+        assert_eq!(unpack_value(&packed), &raw[..]);
+
+        // Small and disabled values are stored as-is
+        assert_eq!(pack_value(&raw[..100], 1024, 3), &raw[..100]);
+        assert_eq!(pack_value(&raw, 0, 3), &raw[..]);
+        assert_eq!(unpack_value(&raw), &raw[..]);
+    }
+}
@@ -1,11 +1,26 @@
-use std::sync::Arc;
+use std::{
+    convert::TryInto,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use cashweb::{
     auth_wrapper::AuthWrapper,
-    relay::{Message, MessagePage},
+    relay::{Conversation, Message, MessagePage, NamespaceSummary, PushRegistration},
 };
+use lazy_static::lazy_static;
 use prost::Message as _;
-use rocksdb::{Direction, Error as RocksError, IteratorMode, Options, DB};
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
+use rocksdb::{
+    ColumnFamily, Direction, Error as RocksError, IteratorMode, Options, WriteBatch, DB,
+};
+use tracing::warn;
+
+use crate::compression::unpack_value;
 
 const DIGEST_LEN: usize = 4;
 const NAMESPACE_LEN: usize = 20 + 1;
@@ -14,6 +29,83 @@ const DIGEST_NAMESPACE: u8 = b'd';
 pub const FEED_NAMESPACE: u8 = b'f';
 pub const MESSAGE_NAMESPACE: u8 = b'm';
 const PROFILE_NAMESPACE: u8 = b'p';
+const SEQUENCE_NAMESPACE: u8 = b's';
+const SEQUENCE_COUNTER_NAMESPACE: u8 = b'n';
+const SENDER_NAMESPACE: u8 = b'c';
+const THUMBNAIL_NAMESPACE: u8 = b'v';
+const PUSH_NAMESPACE: u8 = b'w';
+
+const TOMBSTONES_CF_NAME: &str = "tombstones";
+const EXPIRIES_CF_NAME: &str = "expiries";
+const OFFLOADS_CF_NAME: &str = "offloads";
+
+lazy_static! {
+    /// Serializes sequenced writes so that sequence numbers become visible in order.
+    static ref SEQUENCE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Duration, in milliseconds, above which database operations are logged. Zero disables logging.
+static SLOW_OP_THRESHOLD: AtomicU64 = AtomicU64::new(0);
+
+/// Set the duration, in milliseconds, above which database operations are logged.
+pub fn set_slow_op_threshold(threshold: u64) {
+    SLOW_OP_THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+fn namespace_name(namespace: u8) -> &'static str {
+    match namespace {
+        MESSAGE_NAMESPACE => "messages",
+        FEED_NAMESPACE => "feeds",
+        PROFILE_NAMESPACE => "profiles",
+        THUMBNAIL_NAMESPACE => "thumbnails",
+        DIGEST_NAMESPACE => "digests",
+        PUSH_NAMESPACE => "push_registrations",
+        _ => "other",
+    }
+}
+
+/// Times a database operation, recording it when dropped.
+struct OpTimer {
+    operation: &'static str,
+    namespace: &'static str,
+    start: Instant,
+}
+
+impl OpTimer {
+    fn new(operation: &'static str, namespace: &'static str) -> Self {
+        Self {
+            operation,
+            namespace,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+
+        #[cfg(feature = "monitoring")]
+        crate::monitoring::DB_ELAPSED_VEC
+            .with_label_values(&[self.operation, self.namespace])
+            .observe(elapsed as f64);
+
+        let threshold = SLOW_OP_THRESHOLD.load(Ordering::Relaxed);
+        if threshold != 0 && elapsed >= threshold {
+            warn!(
+                message = "slow database operation",
+                operation = self.operation,
+                namespace = self.namespace,
+                elapsed
+            );
+
+            #[cfg(feature = "monitoring")]
+            crate::monitoring::DB_SLOW_TOTAL_VEC
+                .with_label_values(&[self.operation, self.namespace])
+                .inc();
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Database(Arc<DB>);
@@ -34,12 +126,287 @@ pub fn msg_prefix(pubkey_hash: &[u8], timestamp: u64, namespace: u8) -> Vec<u8>
     [pubkey_hash, &[namespace], &raw_timestamp].concat()
 }
 
+fn push_key(pubkey_hash: &[u8], endpoint: &str) -> Vec<u8> {
+    let endpoint_digest = digest(&SHA256, endpoint.as_bytes());
+    [pubkey_hash, &[PUSH_NAMESPACE], endpoint_digest.as_ref()].concat()
+}
+
+fn digest_key(pubkey_hash: &[u8], digest: &[u8]) -> Vec<u8> {
+    [pubkey_hash, &[DIGEST_NAMESPACE], digest].concat()
+}
+
+fn offload_key(payload_digest: &[u8], pubkey_hash: &[u8]) -> Vec<u8> {
+    [payload_digest, pubkey_hash].concat()
+}
+
+fn sequence_key(pubkey_hash: &[u8], namespace: u8, sequence: u64) -> Vec<u8> {
+    [
+        pubkey_hash,
+        &[SEQUENCE_NAMESPACE, namespace],
+        &sequence.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn sender_prefix(pubkey_hash: &[u8], namespace: u8, sender_pubkey_hash: &[u8]) -> Vec<u8> {
+    [
+        pubkey_hash,
+        &[SENDER_NAMESPACE, namespace],
+        sender_pubkey_hash,
+    ]
+    .concat()
+}
+
+/// Construct the sender index key of a message from its message key.
+fn sender_key(msg_key: &[u8], sender_pubkey_hash: &[u8]) -> Vec<u8> {
+    let (pubkey_hash, namespace) = (&msg_key[..NAMESPACE_LEN - 1], msg_key[NAMESPACE_LEN - 1]);
+    [
+        &sender_prefix(pubkey_hash, namespace, sender_pubkey_hash),
+        &msg_key[NAMESPACE_LEN..],
+    ]
+    .concat()
+}
+
+/// Construct a page from messages, oldest first.
+fn message_page(messages: Vec<Message>) -> MessagePage {
+    let mut message_page = MessagePage::default();
+    if let Some(message) = messages.first() {
+        message_page.start_time = message.received_time;
+        let payload_digest = message.digest().unwrap(); // This is safe
+        message_page.start_digest = payload_digest.to_vec();
+        message_page.start_sequence = message.sequence;
+    }
+    if let Some(message) = messages.last() {
+        message_page.end_time = message.received_time;
+        let payload_digest = message.digest().unwrap(); // This is safe
+        message_page.end_digest = payload_digest.to_vec();
+        message_page.end_sequence = message.sequence;
+    }
+    message_page.messages = messages;
+    message_page
+}
+
 impl Database {
     pub fn try_new(path: &str) -> Result<Self, RocksError> {
         let mut opts = Options::default();
         opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+
+        DB::open_cf(
+            &opts,
+            &path,
+            &[TOMBSTONES_CF_NAME, EXPIRIES_CF_NAME, OFFLOADS_CF_NAME],
+        )
+        .map(Arc::new)
+        .map(Database)
+    }
+
+    fn cf_tombstones(&self) -> &ColumnFamily {
+        self.0.cf_handle(TOMBSTONES_CF_NAME).unwrap()
+    }
+
+    fn cf_expiries(&self) -> &ColumnFamily {
+        self.0.cf_handle(EXPIRIES_CF_NAME).unwrap()
+    }
+
+    fn cf_offloads(&self) -> &ColumnFamily {
+        self.0.cf_handle(OFFLOADS_CF_NAME).unwrap()
+    }
+
+    /// Checks whether a digest index entry refers to a stored or tombstoned message, other than
+    /// the message at `excluded_key`.
+    fn digest_referenced(
+        &self,
+        pubkey_hash: &[u8],
+        digest: &[u8],
+        raw_timestamp: &[u8],
+        excluded_key: &[u8],
+    ) -> Result<bool, RocksError> {
+        for namespace in &[MESSAGE_NAMESPACE, FEED_NAMESPACE] {
+            let key = [
+                pubkey_hash,
+                &[*namespace],
+                raw_timestamp,
+                &digest[..DIGEST_LEN],
+            ]
+            .concat();
+            if key == excluded_key {
+                continue;
+            }
+            if self.0.get(&key)?.is_some() || self.0.get_cf(self.cf_tombstones(), &key)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Add the removal of the index entries of a stored message to a batch.
+    ///
+    /// The digest index entry is kept if it refers to another message with the same digest.
+    fn batch_remove_indexes(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), RocksError> {
+        let message = match Message::decode(&unpack_value(value)[..]) {
+            Ok(ok) => ok,
+            Err(_) => return Ok(()),
+        };
+        let (pubkey_hash, namespace) = (&key[..NAMESPACE_LEN - 1], key[NAMESPACE_LEN - 1]);
+        let sender_pubkey_hash =
+            Ripemd160::digest(digest(&SHA256, &message.source_public_key).as_ref());
+        batch.delete(sender_key(key, &sender_pubkey_hash));
+        if message.sequence != 0 {
+            batch.delete(sequence_key(pubkey_hash, namespace, message.sequence));
+        }
+
+        // Remove the digest index entry if no other message refers to it
+        if let Ok(payload_digest) = message.digest() {
+            let digest_key = digest_key(pubkey_hash, &payload_digest);
+            let raw_timestamp = &key[NAMESPACE_LEN..NAMESPACE_LEN + 8];
+            if self.0.get(&digest_key)?.as_deref() == Some(raw_timestamp)
+                && !self.digest_referenced(pubkey_hash, &payload_digest, raw_timestamp, key)?
+            {
+                batch.delete(digest_key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the removal of a message to a batch, keeping a tombstone of it if `tombstone` is set.
+    fn batch_remove(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        value: &[u8],
+        tombstone: bool,
+    ) -> Result<(), RocksError> {
+        batch.delete(key);
+        if !tombstone {
+            self.batch_remove_indexes(batch, key, value)?;
+        } else {
+            let deleted_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            let tombstone_value = [deleted_time.to_be_bytes().as_ref(), value].concat();
+            batch.put_cf(self.cf_tombstones(), key, tombstone_value);
+        }
+        Ok(())
+    }
+
+    /// Release the offloaded payloads of permanently removed messages from the mailboxes of their
+    /// source and destination which no longer refer to them, returning the digests of those no
+    /// mailbox holds.
+    fn orphaned_payloads(&self, removed: &[Vec<u8>]) -> Result<Vec<[u8; 32]>, RocksError> {
+        let mut batch = WriteBatch::default();
+        let mut released = Vec::new();
+        for value in removed {
+            let message = match Message::decode(&unpack_value(value)[..]) {
+                Ok(ok) => ok,
+                Err(_) => continue,
+            };
+            let payload_digest = match message.digest() {
+                Ok(ok) => ok,
+                Err(_) => continue,
+            };
+
+            for public_key in &[&message.source_public_key, &message.destination_public_key] {
+                let pubkey_hash = Ripemd160::digest(digest(&SHA256, public_key).as_ref());
+                let offload_key = offload_key(&payload_digest, &pubkey_hash);
+                if self.0.get_cf(self.cf_offloads(), &offload_key)?.is_none() {
+                    continue;
+                }
+                let referenced = match self.0.get(digest_key(&pubkey_hash, &payload_digest))? {
+                    Some(raw_timestamp) => {
+                        self.digest_referenced(&pubkey_hash, &payload_digest, &raw_timestamp, &[])?
+                    }
+                    None => false,
+                };
+                if !referenced {
+                    batch.delete_cf(self.cf_offloads(), offload_key);
+                    if !released.contains(&payload_digest) {
+                        released.push(payload_digest);
+                    }
+                }
+            }
+        }
+        self.0.write(batch)?;
+
+        // A payload is orphaned once no mailbox holds it
+        let mut orphans = Vec::with_capacity(released.len());
+        for payload_digest in released {
+            let held = self
+                .0
+                .iterator_cf(
+                    self.cf_offloads(),
+                    IteratorMode::From(&payload_digest, Direction::Forward),
+                )
+                .next()
+                .map_or(false, |(key, _)| key.starts_with(&payload_digest));
+            if !held {
+                orphans.push(payload_digest);
+            }
+        }
+        Ok(orphans)
+    }
+
+    /// Record that the payload of a message in a mailbox was offloaded.
+    ///
+    /// Only the server marks payloads as offloaded, messages are never trusted to.
+    pub fn mark_offloaded(
+        &self,
+        pubkey_hash: &[u8],
+        payload_digest: &[u8],
+    ) -> Result<(), RocksError> {
+        self.0.put_cf(
+            self.cf_offloads(),
+            offload_key(payload_digest, pubkey_hash),
+            b"",
+        )
+    }
+
+    /// Checks whether a mailbox holds an offloaded payload.
+    pub fn is_offloaded(
+        &self,
+        pubkey_hash: &[u8],
+        payload_digest: &[u8],
+    ) -> Result<bool, RocksError> {
+        self.0
+            .get_cf(self.cf_offloads(), offload_key(payload_digest, pubkey_hash))
+            .map(|opt_value| opt_value.is_some())
+    }
+
+    /// Remove digest index entries which no longer refer to a stored or tombstoned message,
+    /// returning the number removed.
+    pub fn scavenge_digests(&self) -> Result<usize, RocksError> {
+        let _timer = OpTimer::new("scavenge_digests", namespace_name(DIGEST_NAMESPACE));
+        let mut batch = WriteBatch::default();
+        let mut n_orphans = 0;
+        for (digest_key, raw_timestamp) in
+            self.0.iterator(IteratorMode::Start).filter(|(key, value)| {
+                key.len() == NAMESPACE_LEN + 32
+                    && key[NAMESPACE_LEN - 1] == DIGEST_NAMESPACE
+                    && value.len() == 8
+            })
+        {
+            let (pubkey_hash, payload_digest) = (
+                &digest_key[..NAMESPACE_LEN - 1],
+                &digest_key[NAMESPACE_LEN..],
+            );
+            if self.digest_referenced(pubkey_hash, payload_digest, &raw_timestamp, &[])? {
+                continue;
+            }
 
-        DB::open(&opts, &path).map(Arc::new).map(Database)
+            // Skip entries replaced since iteration began
+            if self.0.get(&digest_key)?.as_deref() == Some(&raw_timestamp[..]) {
+                batch.delete(digest_key);
+                n_orphans += 1;
+            }
+        }
+        self.0.write(batch)?;
+        Ok(n_orphans)
     }
 
     pub fn get_msg_key_by_digest(
@@ -48,7 +415,7 @@ impl Database {
         digest: &[u8],
         namespace: u8,
     ) -> Result<Option<Vec<u8>>, RocksError> {
-        let digest_key = [pubkey_hash, &[DIGEST_NAMESPACE], digest].concat();
+        let digest_key = digest_key(pubkey_hash, digest);
 
         let opt_timestamp = self.0.get(digest_key)?;
         Ok(opt_timestamp.map(|timestamp| {
@@ -56,19 +423,147 @@ impl Database {
         }))
     }
 
+    /// Remove a message, keeping a tombstone of it if `tombstone` is set.
+    ///
+    /// Returns the digests of the offloaded payloads which are no longer referred to.
     pub fn remove_message_by_digest(
         &self,
         pubkey_hash: &[u8],
         digest: &[u8],
         namespace: u8,
+        tombstone: bool,
+    ) -> Result<Option<Vec<[u8; 32]>>, RocksError> {
+        let _timer = OpTimer::new("remove_message_by_digest", namespace_name(namespace));
+        let key = match self.get_msg_key_by_digest(pubkey_hash, digest, namespace)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+        let value = match self.0.get(&key)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+        let mut batch = WriteBatch::default();
+        self.batch_remove(&mut batch, &key, &value, tombstone)?;
+        self.0.write(batch)?;
+        if tombstone {
+            return Ok(Some(Vec::new()));
+        }
+        self.orphaned_payloads(&[value]).map(Some)
+    }
+
+    /// Restore a message from its tombstone.
+    pub fn restore_message_by_digest(
+        &self,
+        pubkey_hash: &[u8],
+        digest: &[u8],
+        namespace: u8,
     ) -> Result<Option<()>, RocksError> {
-        match self.get_msg_key_by_digest(pubkey_hash, digest, namespace)? {
-            Some(some) => {
-                self.0.delete(&some)?;
-                Ok(Some(()))
+        let _timer = OpTimer::new("restore_message_by_digest", namespace_name(namespace));
+        let key = match self.get_msg_key_by_digest(pubkey_hash, digest, namespace)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+        let tombstone_value = match self.0.get_cf(self.cf_tombstones(), &key)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+        let mut batch = WriteBatch::default();
+        batch.put(&key, &tombstone_value[8..]);
+        batch.delete_cf(self.cf_tombstones(), &key);
+        self.0.write(batch)?;
+        Ok(Some(()))
+    }
+
+    /// Permanently remove the tombstones of messages deleted before `deleted_before`, returning
+    /// the number purged and the digests of the offloaded payloads which are no longer referred to.
+    pub fn purge_tombstones(
+        &self,
+        deleted_before: u64,
+    ) -> Result<(usize, Vec<[u8; 32]>), RocksError> {
+        let _timer = OpTimer::new("purge_tombstones", "tombstones");
+        let mut batch = WriteBatch::default();
+        let mut removed = Vec::new();
+        for (key, value) in self
+            .0
+            .iterator_cf(self.cf_tombstones(), IteratorMode::Start)
+        {
+            let raw_deleted_time: [u8; 8] = value[..8].try_into().unwrap(); // This is safe
+            if u64::from_be_bytes(raw_deleted_time) < deleted_before {
+                self.batch_remove_indexes(&mut batch, &key, &value[8..])?;
+                batch.delete_cf(self.cf_tombstones(), key);
+                removed.push(value[8..].to_vec());
             }
-            None => Ok(None),
         }
+        self.0.write(batch)?;
+        Ok((removed.len(), self.orphaned_payloads(&removed)?))
+    }
+
+    /// Schedule the removal of a message at `expiry_time`.
+    ///
+    /// Entries whose message does not exist when they expire are discarded, so this may precede
+    /// the push of the message.
+    pub fn schedule_expiry(
+        &self,
+        pubkey_hash: &[u8],
+        timestamp: u64,
+        digest: &[u8],
+        namespace: u8,
+        expiry_time: u64,
+    ) -> Result<(), RocksError> {
+        let _timer = OpTimer::new("schedule_expiry", namespace_name(namespace));
+        let key = msg_key(pubkey_hash, timestamp, digest, namespace);
+        let expiry_key = [expiry_time.to_be_bytes().as_ref(), &key].concat();
+        self.0.put_cf(self.cf_expiries(), expiry_key, b"")
+    }
+
+    /// Permanently remove the messages, and their tombstones, which expired before `now`,
+    /// returning the number removed and the digests of the offloaded payloads which are no longer
+    /// referred to.
+    pub fn prune_expired(&self, now: u64) -> Result<(usize, Vec<[u8; 32]>), RocksError> {
+        let _timer = OpTimer::new("prune_expired", "expiries");
+        let mut batch = WriteBatch::default();
+        let mut removed = Vec::new();
+        for (expiry_key, _) in self
+            .0
+            .iterator_cf(self.cf_expiries(), IteratorMode::Start)
+            .take_while(|(expiry_key, _)| {
+                let raw_expiry_time: [u8; 8] = expiry_key[..8].try_into().unwrap(); // This is safe
+                u64::from_be_bytes(raw_expiry_time) < now
+            })
+        {
+            let key = &expiry_key[8..];
+            if let Some(value) = self.0.get(key)? {
+                self.batch_remove(&mut batch, key, &value, false)?;
+                removed.push(value);
+            } else if let Some(tombstone_value) = self.0.get_cf(self.cf_tombstones(), key)? {
+                self.batch_remove_indexes(&mut batch, key, &tombstone_value[8..])?;
+                batch.delete_cf(self.cf_tombstones(), key);
+                removed.push(tombstone_value[8..].to_vec());
+            }
+            batch.delete_cf(self.cf_expiries(), expiry_key);
+        }
+        self.0.write(batch)?;
+        Ok((removed.len(), self.orphaned_payloads(&removed)?))
+    }
+
+    /// Add a message and its digest index to a batch.
+    fn batch_push(
+        batch: &mut WriteBatch,
+        pubkey_hash: &[u8],
+        timestamp: u64,
+        raw_message: &[u8],
+        digest: &[u8],
+        namespace: u8,
+    ) -> Vec<u8> {
+        // Create key
+        let key = msg_key(pubkey_hash, timestamp, digest, namespace);
+        batch.put(&key, raw_message);
+
+        // Create digest key
+        let digest_key = digest_key(pubkey_hash, digest);
+        batch.put(digest_key, timestamp.to_be_bytes());
+
+        key
     }
 
     pub fn push_message(
@@ -79,23 +574,181 @@ impl Database {
         digest: &[u8],
         namespace: u8,
     ) -> Result<(), RocksError> {
-        // Create key
-        let raw_timestamp: [u8; 8] = timestamp.to_be_bytes();
-        let key = [
+        let _timer = OpTimer::new("push_message", namespace_name(namespace));
+        let mut batch = WriteBatch::default();
+        Self::batch_push(
+            &mut batch,
             pubkey_hash,
-            &[namespace],
-            &raw_timestamp,
-            &digest[..DIGEST_LEN],
-        ]
-        .concat();
-        self.0.put(key, raw_message)?;
+            timestamp,
+            raw_message,
+            digest,
+            namespace,
+        );
+        self.0.write(batch)
+    }
 
-        // Create digest key
-        let digest_key = [pubkey_hash, &[DIGEST_NAMESPACE], digest].concat();
+    /// Push a message, assigning it the next sequence number of the address and namespace, and
+    /// indexing it by sender.
+    ///
+    /// `encode` is given the sequence number and returns the value to store. Returns the sequence
+    /// number assigned.
+    pub fn push_sequenced_message<F>(
+        &self,
+        pubkey_hash: &[u8],
+        timestamp: u64,
+        digest: &[u8],
+        sender_pubkey_hash: &[u8],
+        namespace: u8,
+        encode: F,
+    ) -> Result<u64, RocksError>
+    where
+        F: FnOnce(u64) -> Vec<u8>,
+    {
+        let _timer = OpTimer::new("push_sequenced_message", namespace_name(namespace));
+        let _guard = SEQUENCE_LOCK.lock().unwrap();
 
-        self.0.put(digest_key, raw_timestamp)?;
+        // Allocate sequence number
+        let counter_key = [pubkey_hash, &[SEQUENCE_COUNTER_NAMESPACE, namespace]].concat();
+        let last_sequence = match self.0.get(&counter_key)? {
+            Some(raw_sequence) => {
+                let raw_sequence: [u8; 8] = raw_sequence[..].try_into().unwrap(); // This panics if stored bytes are malformed
+                u64::from_be_bytes(raw_sequence)
+            }
+            None => 0,
+        };
+        let sequence = last_sequence + 1;
 
-        Ok(())
+        let mut batch = WriteBatch::default();
+        let key = Self::batch_push(
+            &mut batch,
+            pubkey_hash,
+            timestamp,
+            &encode(sequence),
+            digest,
+            namespace,
+        );
+        batch.put(sequence_key(pubkey_hash, namespace, sequence), &key);
+        batch.put(sender_key(&key, sender_pubkey_hash), &key);
+        batch.put(counter_key, sequence.to_be_bytes());
+        self.0.write(batch)?;
+
+        Ok(sequence)
+    }
+
+    /// Get at most `limit` messages with sequence numbers greater than `after_sequence`, oldest
+    /// first.
+    pub fn get_messages_after_sequence(
+        &self,
+        pubkey_hash: &[u8],
+        namespace: u8,
+        after_sequence: u64,
+        limit: usize,
+    ) -> Result<MessagePage, RocksError> {
+        let _timer = OpTimer::new("get_messages_after_sequence", namespace_name(namespace));
+        let prefix = [pubkey_hash, &[SEQUENCE_NAMESPACE, namespace]].concat();
+        let start_key = sequence_key(pubkey_hash, namespace, after_sequence.saturating_add(1));
+
+        let mut messages = Vec::new();
+        for (sequence_key, key) in self
+            .0
+            .iterator(IteratorMode::From(&start_key, Direction::Forward))
+            .take_while(|(sequence_key, _)| sequence_key.starts_with(&prefix))
+        {
+            if messages.len() >= limit {
+                break;
+            }
+            let raw_sequence: [u8; 8] = sequence_key[prefix.len()..].try_into().unwrap(); // This panics if stored bytes are malformed
+            let raw_message = match self.get_message_by_key(&key)? {
+                Some(some) => some,
+                None => continue,
+            };
+            let message = Message::decode(&raw_message[..]).unwrap(); // This panics if stored bytes are malformed
+
+            // Skip messages since overwritten by a message with the same key
+            if message.sequence == u64::from_be_bytes(raw_sequence) {
+                messages.push(message);
+            }
+        }
+        Ok(message_page(messages))
+    }
+
+    /// Get at most `limit` of the messages from a sender received within a time range, oldest
+    /// first.
+    pub fn get_messages_by_sender(
+        &self,
+        pubkey_hash: &[u8],
+        namespace: u8,
+        sender_pubkey_hash: &[u8],
+        start_time: u64,
+        opt_end_time: Option<u64>,
+        limit: usize,
+    ) -> Result<MessagePage, RocksError> {
+        let _timer = OpTimer::new("get_messages_by_sender", namespace_name(namespace));
+        let prefix = sender_prefix(pubkey_hash, namespace, sender_pubkey_hash);
+        let start_key = [&prefix[..], &start_time.to_be_bytes()].concat();
+
+        // Check whether key is before end time
+        let before_end_key = |key: &[u8]| {
+            opt_end_time.map_or(true, |end_time| {
+                key[prefix.len()..prefix.len() + 8] < end_time.to_be_bytes()[..]
+            })
+        };
+
+        let mut messages = Vec::new();
+        for (_, key) in self
+            .0
+            .iterator(IteratorMode::From(&start_key, Direction::Forward))
+            .take_while(|(sender_key, _)| {
+                sender_key.starts_with(&prefix) && before_end_key(sender_key)
+            })
+        {
+            if messages.len() >= limit {
+                break;
+            }
+            if let Some(raw_message) = self.get_message_by_key(&key)? {
+                let message = Message::decode(&raw_message[..]).unwrap(); // This panics if stored bytes are malformed
+                messages.push(message);
+            }
+        }
+        Ok(message_page(messages))
+    }
+
+    /// Summarize the conversations in a namespace by sender, most recently active first.
+    pub fn get_conversations(
+        &self,
+        pubkey_hash: &[u8],
+        namespace: u8,
+    ) -> Result<Vec<Conversation>, RocksError> {
+        let _timer = OpTimer::new("get_conversations", namespace_name(namespace));
+        let prefix = [pubkey_hash, &[SENDER_NAMESPACE, namespace]].concat();
+        let mut conversations: Vec<Conversation> = Vec::new();
+        for (sender_key, key) in self
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(sender_key, _)| sender_key.starts_with(&prefix))
+        {
+            // Skip removed messages
+            if self.0.get(&key)?.is_none() {
+                continue;
+            }
+
+            let sender_pubkey_hash = &sender_key[prefix.len()..prefix.len() + 20];
+            let raw_timestamp: [u8; 8] = key[NAMESPACE_LEN..NAMESPACE_LEN + 8].try_into().unwrap(); // This is safe
+            let timestamp = u64::from_be_bytes(raw_timestamp) as i64;
+            match conversations.last_mut() {
+                Some(conversation) if conversation.sender_pubkey_hash == sender_pubkey_hash => {
+                    conversation.count += 1;
+                    conversation.latest_time = timestamp;
+                }
+                _ => conversations.push(Conversation {
+                    sender_pubkey_hash: sender_pubkey_hash.to_vec(),
+                    count: 1,
+                    latest_time: timestamp,
+                }),
+            }
+        }
+        conversations.sort_by(|a, b| b.latest_time.cmp(&a.latest_time));
+        Ok(conversations)
     }
 
     pub fn get_message_by_digest(
@@ -104,6 +757,7 @@ impl Database {
         digest: &[u8],
         namespace: u8,
     ) -> Result<Option<Vec<u8>>, RocksError> {
+        let _timer = OpTimer::new("get_message_by_digest", namespace_name(namespace));
         match self.get_msg_key_by_digest(pubkey_hash, digest, namespace)? {
             Some(some) => self.get_message_by_key(&some),
             None => Ok(None),
@@ -111,7 +765,9 @@ impl Database {
     }
 
     pub fn get_message_by_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
-        self.0.get(key)
+        self.0
+            .get(key)
+            .map(|opt_value| opt_value.map(|value| unpack_value(&value).into_owned()))
     }
 
     pub fn get_messages_range(
@@ -119,6 +775,10 @@ impl Database {
         start_prefix: &[u8],
         opt_end_prefix: Option<&[u8]>,
     ) -> Result<MessagePage, RocksError> {
+        let _timer = OpTimer::new(
+            "get_messages_range",
+            namespace_name(start_prefix[NAMESPACE_LEN - 1]),
+        );
         let namespace = &start_prefix[..NAMESPACE_LEN]; // addr || msg namespace byte
 
         // Check whether key is within namespace
@@ -136,98 +796,550 @@ impl Database {
             // Take items inside namespace and before end time
             iter.take_while(|(key, _)| in_namespace(key) && before_end_key(key))
                 .map(|(_, item)| {
-                    Message::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
+                    Message::decode(&unpack_value(&item)[..]).unwrap() // This panics if stored bytes are malformed
                 })
                 .collect()
         } else {
             // Take items inside namespace
             iter.take_while(|(key, _)| in_namespace(key))
                 .map(|(_, item)| {
-                    Message::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
+                    Message::decode(&unpack_value(&item)[..]).unwrap() // This panics if stored bytes are malformed
                 })
                 .collect()
         };
 
-        let mut message_page = MessagePage::default();
-        if let Some(message) = messages.first() {
-            message_page.start_time = message.received_time;
-            let payload_digest = message.digest().unwrap(); // This is safe
-            message_page.start_digest = payload_digest.to_vec();
-        }
-        if let Some(message) = messages.last() {
-            message_page.start_time = message.received_time;
-            let payload_digest = message.digest().unwrap(); // This is safe
-            message_page.start_digest = payload_digest.to_vec();
-        }
-        message_page.messages = messages;
-        Ok(message_page)
+        Ok(message_page(messages))
     }
 
+    /// Get at most `limit` messages stored after `opt_after_key`, oldest first, alongside the key
+    /// of the last message returned.
+    ///
+    /// The messages of an address and namespace can be walked by passing each returned key to the
+    /// next call, stopping once no key is returned.
+    pub fn get_messages_after_key(
+        &self,
+        pubkey_hash: &[u8],
+        namespace: u8,
+        opt_after_key: Option<&[u8]>,
+        limit: usize,
+    ) -> (Vec<Message>, Option<Vec<u8>>) {
+        let _timer = OpTimer::new("get_messages_after_key", namespace_name(namespace));
+        let prefix = [pubkey_hash, &[namespace]].concat();
+        let start_key = match opt_after_key {
+            Some(after_key) => [after_key, &[0]].concat(),
+            None => prefix.clone(),
+        };
+
+        let mut last_key = None;
+        let messages = self
+            .0
+            .iterator(IteratorMode::From(&start_key, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .take(limit)
+            .map(|(key, item)| {
+                last_key = Some(key.to_vec());
+                Message::decode(&unpack_value(&item)[..]).unwrap() // This panics if stored bytes are malformed
+            })
+            .collect();
+        (messages, last_key)
+    }
+
+    /// Get at most `limit` of the messages within a range matching `predicate`, oldest first.
+    pub fn search_messages<P>(
+        &self,
+        start_prefix: &[u8],
+        opt_end_prefix: Option<&[u8]>,
+        limit: usize,
+        predicate: P,
+    ) -> MessagePage
+    where
+        P: Fn(&Message) -> bool,
+    {
+        let _timer = OpTimer::new(
+            "search_messages",
+            namespace_name(start_prefix[NAMESPACE_LEN - 1]),
+        );
+        let namespace = &start_prefix[..NAMESPACE_LEN]; // addr || msg namespace byte
+
+        // Check whether key is within namespace
+        let in_namespace = |key: &[u8]| key[..NAMESPACE_LEN] == namespace[..];
+
+        // Check whether key is before end time
+        let before_end_key = |key: &[u8]| {
+            opt_end_prefix.map_or(true, |end_prefix| {
+                key[NAMESPACE_LEN..] < end_prefix[NAMESPACE_LEN..]
+            })
+        };
+
+        let messages = self
+            .0
+            .iterator(IteratorMode::From(start_prefix, Direction::Forward))
+            .take_while(|(key, _)| in_namespace(key) && before_end_key(key))
+            .map(|(_, item)| {
+                Message::decode(&unpack_value(&item)[..]).unwrap() // This panics if stored bytes are malformed
+            })
+            .filter(|message| predicate(message))
+            .take(limit)
+            .collect();
+
+        message_page(messages)
+    }
+
+    /// Remove at most `limit` of the messages within a range, oldest first.
+    ///
+    /// Returns the number of messages removed, or which would be removed if `dry_run` is set,
+    /// whether further messages remain within the range, and the digests of the offloaded payloads
+    /// which are no longer referred to. Tombstones of the removed messages are kept if `tombstone`
+    /// is set.
     pub fn remove_messages_range(
         &self,
         start_prefix: &[u8],
         opt_end_prefix: Option<&[u8]>,
-    ) -> Result<(), RocksError> {
+        limit: usize,
+        dry_run: bool,
+        tombstone: bool,
+    ) -> Result<(usize, bool, Vec<[u8; 32]>), RocksError> {
+        let _timer = OpTimer::new(
+            "remove_messages_range",
+            namespace_name(start_prefix[NAMESPACE_LEN - 1]),
+        );
         let namespace = &start_prefix[..NAMESPACE_LEN]; // addr || msg namespace byte
 
         // Check whether key is within namespace
         let in_namespace = |key: &[u8]| key[..NAMESPACE_LEN] == namespace[..];
 
-        // Init iterator
-        let iter = self
+        // Check whether key is before end time
+        let before_end_key = |key: &[u8]| {
+            opt_end_prefix.map_or(true, |end_prefix| {
+                key[NAMESPACE_LEN..] < end_prefix[NAMESPACE_LEN..]
+            })
+        };
+
+        // Take items inside namespace and before end time
+        let mut items: Vec<_> = self
             .0
-            .iterator(IteratorMode::From(start_prefix, Direction::Forward));
+            .iterator(IteratorMode::From(start_prefix, Direction::Forward))
+            .take_while(|(key, _)| in_namespace(key) && before_end_key(key))
+            .take(limit.saturating_add(1))
+            .collect();
+        let truncated = items.len() > limit;
+        items.truncate(limit);
 
-        if let Some(end_prefix) = opt_end_prefix {
-            // Check whether key is before end time
-            let before_end_key = |key: &[u8]| key[NAMESPACE_LEN..] < end_prefix[NAMESPACE_LEN..];
+        let mut orphans = Vec::new();
+        if !dry_run {
+            let mut batch = WriteBatch::default();
+            for (key, value) in &items {
+                self.batch_remove(&mut batch, key, value, tombstone)?;
+            }
+            self.0.write(batch)?;
+            if !tombstone {
+                let removed: Vec<_> = items.iter().map(|(_, value)| value.to_vec()).collect();
+                orphans = self.orphaned_payloads(&removed)?;
+            }
+        }
 
-            // Take items inside namespace and before end time
-            let iter = iter.take_while(|(key, _)| in_namespace(key) && before_end_key(key));
+        Ok((items.len(), truncated, orphans))
+    }
+
+    /// Summarize the messages in a namespace, counting those received after `read_time` as unread.
+    pub fn summarize_namespace(
+        &self,
+        pubkey_hash: &[u8],
+        namespace: u8,
+        read_time: u64,
+    ) -> NamespaceSummary {
+        let _timer = OpTimer::new("summarize_namespace", namespace_name(namespace));
+        let prefix = [pubkey_hash, &[namespace]].concat();
+        let iter = self
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix));
+
+        let mut summary = NamespaceSummary::default();
+        for (key, value) in iter {
+            let raw_timestamp: [u8; 8] = key[NAMESPACE_LEN..NAMESPACE_LEN + 8].try_into().unwrap(); // This is safe
+            let timestamp = u64::from_be_bytes(raw_timestamp);
 
-            for (key, _) in iter {
-                self.0.delete(key)?;
+            if summary.count == 0 {
+                summary.oldest_time = timestamp as i64;
             }
-        } else {
-            // Take items inside namespace
-            let iter = iter.take_while(|(key, _)| in_namespace(key));
+            summary.newest_time = timestamp as i64;
+            summary.count += 1;
+            summary.total_bytes += unpack_value(&value).len() as u64;
+            if timestamp > read_time {
+                summary.unread_count += 1;
+            }
+        }
+        summary
+    }
+
+    /// Compact the entire database.
+    pub fn compact(&self) {
+        self.0.compact_range::<&[u8], &[u8]>(None, None)
+    }
+
+    /// Get a RocksDB property, for example `rocksdb.estimate-num-keys`.
+    pub fn property(&self, name: &str) -> Result<Option<String>, RocksError> {
+        self.0.property_value(name)
+    }
+
+    /// Get the messages removed from an address after `deleted_after`, along with their namespace
+    /// and deletion time.
+    ///
+    /// Only messages whose tombstones have not yet been purged are found.
+    pub fn get_deletions(&self, pubkey_hash: &[u8], deleted_after: u64) -> Vec<(u8, Message, u64)> {
+        let _timer = OpTimer::new("get_deletions", "tombstones");
+        self.0
+            .iterator_cf(
+                self.cf_tombstones(),
+                IteratorMode::From(pubkey_hash, Direction::Forward),
+            )
+            .take_while(|(key, _)| key.starts_with(pubkey_hash))
+            .filter_map(|(key, value)| {
+                let raw_deleted_time: [u8; 8] = value[..8].try_into().unwrap(); // This is safe
+                let deleted_time = u64::from_be_bytes(raw_deleted_time);
+                if deleted_time <= deleted_after {
+                    return None;
+                }
+                let message = Message::decode(&unpack_value(&value[8..])[..]).unwrap(); // This panics if stored bytes are malformed
+                Some((key[NAMESPACE_LEN - 1], message, deleted_time))
+            })
+            .collect()
+    }
+
+    pub fn get_raw_profile(&self, addr: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
+        let _timer = OpTimer::new("get_raw_profile", namespace_name(PROFILE_NAMESPACE));
+        // Prefix key
+        let key = [addr, &[PROFILE_NAMESPACE]].concat();
+
+        self.0.get(key)
+    }
+
+    pub fn get_profile(&self, addr: &[u8]) -> Result<Option<AuthWrapper>, RocksError> {
+        self.get_raw_profile(addr).map(|raw_profile_opt| {
+            raw_profile_opt.map(|raw_profile| {
+                AuthWrapper::decode(&raw_profile[..]).unwrap() // This panics if stored bytes are malformed
+            })
+        })
+    }
+
+    /// Put a profile, replacing the avatar thumbnails of the address.
+    ///
+    /// Thumbnails are given as pairs of size and encoded image.
+    pub fn put_profile(
+        &self,
+        addr: &[u8],
+        raw_profile: &[u8],
+        thumbnails: &[(u32, Vec<u8>)],
+    ) -> Result<(), RocksError> {
+        let _timer = OpTimer::new("put_profile", namespace_name(PROFILE_NAMESPACE));
+        let mut batch = WriteBatch::default();
+
+        // Prefix key
+        let key = [addr, &[PROFILE_NAMESPACE]].concat();
+        batch.put(key, raw_profile);
+
+        // Replace thumbnails
+        let prefix = [addr, &[THUMBNAIL_NAMESPACE]].concat();
+        self.0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .for_each(|(key, _)| batch.delete(key));
+        for (size, thumbnail) in thumbnails {
+            let key = [&prefix[..], &size.to_be_bytes()].concat();
+            batch.put(key, thumbnail);
+        }
+
+        self.0.write(batch)
+    }
+
+    /// Get the avatar thumbnail of an address at the given size.
+    pub fn get_thumbnail(&self, addr: &[u8], size: u32) -> Result<Option<Vec<u8>>, RocksError> {
+        let _timer = OpTimer::new("get_thumbnail", namespace_name(THUMBNAIL_NAMESPACE));
+        let key = [addr, &[THUMBNAIL_NAMESPACE], &size.to_be_bytes()].concat();
+
+        self.0.get(key)
+    }
+
+    /// Get the push registrations of an address.
+    pub fn get_push_registrations(
+        &self,
+        pubkey_hash: &[u8],
+    ) -> Result<Vec<PushRegistration>, RocksError> {
+        let _timer = OpTimer::new("get_push_registrations", namespace_name(PUSH_NAMESPACE));
+        let prefix = [pubkey_hash, &[PUSH_NAMESPACE]].concat();
+        let registrations = self
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, item)| {
+                PushRegistration::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
+            })
+            .collect();
+        Ok(registrations)
+    }
+
+    /// Put a push registration of an address, replacing any registration of the same endpoint.
+    pub fn put_push_registration(
+        &self,
+        pubkey_hash: &[u8],
+        registration: &PushRegistration,
+    ) -> Result<(), RocksError> {
+        let _timer = OpTimer::new("put_push_registration", namespace_name(PUSH_NAMESPACE));
+        let mut raw_registration = Vec::with_capacity(registration.encoded_len());
+        registration.encode(&mut raw_registration).unwrap(); // This is safe
+        self.0.put(
+            push_key(pubkey_hash, &registration.endpoint),
+            raw_registration,
+        )
+    }
+
+    /// Remove the push registration of an endpoint, returning whether it existed.
+    pub fn remove_push_registration(
+        &self,
+        pubkey_hash: &[u8],
+        endpoint: &str,
+    ) -> Result<bool, RocksError> {
+        let _timer = OpTimer::new("remove_push_registration", namespace_name(PUSH_NAMESPACE));
+        let key = push_key(pubkey_hash, endpoint);
+        if self.0.get(&key)?.is_none() {
+            return Ok(false);
+        }
+        self.0.delete(key)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincash_addr::Address;
+    use ring::digest::{digest, SHA256};
+
+    #[test]
+    fn digest_cleanup() {
+        let database = Database::try_new("./test_dbs/digest_cleanup").unwrap();
+        let pubkey_hash = [9; 20];
+
+        let message = Message {
+            payload: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let payload_digest = message.digest().unwrap();
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+
+        // Removing a message removes its digest index entry
+        database
+            .push_message(
+                &pubkey_hash,
+                100,
+                &raw_message,
+                &payload_digest,
+                MESSAGE_NAMESPACE,
+            )
+            .unwrap();
+        database
+            .remove_message_by_digest(&pubkey_hash, &payload_digest, MESSAGE_NAMESPACE, false)
+            .unwrap()
+            .unwrap();
+        assert!(database
+            .get_msg_key_by_digest(&pubkey_hash, &payload_digest, MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_none());
+
+        // Dangling entries are scavenged
+        let orphan_key = digest_key(&pubkey_hash, &[0xee; 32]);
+        database.0.put(&orphan_key, 100u64.to_be_bytes()).unwrap();
+        assert!(database.scavenge_digests().unwrap() >= 1);
+        assert!(database.0.get(&orphan_key).unwrap().is_none());
+
+        // Referenced entries are kept
+        database
+            .push_message(
+                &pubkey_hash,
+                200,
+                &raw_message,
+                &payload_digest,
+                FEED_NAMESPACE,
+            )
+            .unwrap();
+        database.scavenge_digests().unwrap();
+        assert!(database
+            .get_msg_key_by_digest(&pubkey_hash, &payload_digest, FEED_NAMESPACE)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn orphaned_payloads() {
+        let database = Database::try_new("./test_dbs/orphaned_payloads").unwrap();
+
+        // An offloaded message delivered to both its source and destination
+        let message = Message {
+            source_public_key: vec![2; 33],
+            destination_public_key: vec![3; 33],
+            payload_digest: vec![7; 32],
+            ..Default::default()
+        };
+        let payload_digest = message.digest().unwrap();
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+        let pubkey_hashes: Vec<_> = [&message.source_public_key, &message.destination_public_key]
+            .iter()
+            .map(|public_key| Ripemd160::digest(digest(&SHA256, public_key).as_ref()))
+            .collect();
+        for pubkey_hash in &pubkey_hashes {
+            database
+                .push_message(
+                    pubkey_hash,
+                    100,
+                    &raw_message,
+                    &payload_digest,
+                    MESSAGE_NAMESPACE,
+                )
+                .unwrap();
+            database
+                .mark_offloaded(pubkey_hash, &payload_digest)
+                .unwrap();
+        }
+
+        // The payload is kept while either copy, or its tombstone, remains
+        assert_eq!(
+            database
+                .remove_message_by_digest(
+                    &pubkey_hashes[0],
+                    &payload_digest,
+                    MESSAGE_NAMESPACE,
+                    false
+                )
+                .unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            database
+                .remove_message_by_digest(
+                    &pubkey_hashes[1],
+                    &payload_digest,
+                    MESSAGE_NAMESPACE,
+                    true
+                )
+                .unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            database.purge_tombstones(u64::MAX).unwrap(),
+            (1, vec![payload_digest])
+        );
+        assert!(!database
+            .is_offloaded(&pubkey_hashes[1], &payload_digest)
+            .unwrap());
+    }
+
+    #[test]
+    fn foreign_offload_stubs() {
+        let database = Database::try_new("./test_dbs/foreign_offload_stubs").unwrap();
 
-            for (key, _) in iter {
-                self.0.delete(key)?;
-            }
+        // A victim's offloaded message
+        let victim = Message {
+            source_public_key: vec![4; 33],
+            destination_public_key: vec![4; 33],
+            payload_digest: vec![8; 32],
+            ..Default::default()
         };
+        let payload_digest = victim.digest().unwrap();
+        let mut raw_victim = Vec::with_capacity(victim.encoded_len());
+        victim.encode(&mut raw_victim).unwrap();
+        let victim_pubkey_hash =
+            Ripemd160::digest(digest(&SHA256, &victim.source_public_key).as_ref());
+        database
+            .push_message(
+                &victim_pubkey_hash,
+                100,
+                &raw_victim,
+                &payload_digest,
+                MESSAGE_NAMESPACE,
+            )
+            .unwrap();
+        database
+            .mark_offloaded(&victim_pubkey_hash, &payload_digest)
+            .unwrap();
 
-        Ok(())
-    }
+        // An attacker sends themselves a stub claiming the victim's payload
+        let stub = Message {
+            source_public_key: vec![5; 33],
+            destination_public_key: vec![5; 33],
+            payload_digest: payload_digest.to_vec(),
+            payload_size: 1024,
+            ..Default::default()
+        };
+        let mut raw_stub = Vec::with_capacity(stub.encoded_len());
+        stub.encode(&mut raw_stub).unwrap();
+        let attacker_pubkey_hash =
+            Ripemd160::digest(digest(&SHA256, &stub.source_public_key).as_ref());
+        database
+            .push_message(
+                &attacker_pubkey_hash,
+                100,
+                &raw_stub,
+                &payload_digest,
+                MESSAGE_NAMESPACE,
+            )
+            .unwrap();
 
-    pub fn get_raw_profile(&self, addr: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
-        // Prefix key
-        let key = [addr, &[PROFILE_NAMESPACE]].concat();
+        // The stub is not marked offloaded and removing it releases nothing
+        assert!(!database
+            .is_offloaded(&attacker_pubkey_hash, &payload_digest)
+            .unwrap());
+        assert_eq!(
+            database
+                .remove_message_by_digest(
+                    &attacker_pubkey_hash,
+                    &payload_digest,
+                    MESSAGE_NAMESPACE,
+                    false
+                )
+                .unwrap(),
+            Some(vec![])
+        );
+        assert!(database
+            .is_offloaded(&victim_pubkey_hash, &payload_digest)
+            .unwrap());
 
-        self.0.get(key)
+        // The payload is orphaned once the victim removes their copy
+        assert_eq!(
+            database
+                .remove_message_by_digest(
+                    &victim_pubkey_hash,
+                    &payload_digest,
+                    MESSAGE_NAMESPACE,
+                    false
+                )
+                .unwrap(),
+            Some(vec![payload_digest])
+        );
     }
 
-    pub fn get_profile(&self, addr: &[u8]) -> Result<Option<AuthWrapper>, RocksError> {
-        self.get_raw_profile(addr).map(|raw_profile_opt| {
-            raw_profile_opt.map(|raw_profile| {
-                AuthWrapper::decode(&raw_profile[..]).unwrap() // This panics if stored bytes are malformed
-            })
-        })
-    }
+    #[test]
+    fn thumbnails() {
+        let database = Database::try_new("./test_dbs/thumbnails").unwrap();
+        let addr = [7; 20];
 
-    pub fn put_profile(&self, addr: &[u8], raw_profile: &[u8]) -> Result<(), RocksError> {
-        // Prefix key
-        let key = [addr, &[PROFILE_NAMESPACE]].concat();
+        database
+            .put_profile(&addr, &[1], &[(64, vec![64]), (128, vec![128])])
+            .unwrap();
+        assert_eq!(database.get_thumbnail(&addr, 64).unwrap(), Some(vec![64]));
+        assert_eq!(database.get_thumbnail(&addr, 128).unwrap(), Some(vec![128]));
+        assert_eq!(database.get_thumbnail(&addr, 32).unwrap(), None);
 
-        self.0.put(key, raw_profile)
+        // Putting a profile replaces the thumbnails
+        database
+            .put_profile(&addr, &[2], &[(64, vec![65])])
+            .unwrap();
+        assert_eq!(database.get_raw_profile(&addr).unwrap(), Some(vec![2]));
+        assert_eq!(database.get_thumbnail(&addr, 64).unwrap(), Some(vec![65]));
+        assert_eq!(database.get_thumbnail(&addr, 128).unwrap(), None);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use bitcoincash_addr::Address;
-    use ring::digest::{digest, SHA256};
 
     #[test]
     fn get_digest() {
@@ -287,7 +1399,7 @@ mod tests {
             .is_some());
 
         assert!(database
-            .remove_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE)
+            .remove_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE, false)
             .unwrap()
             .is_some());
 
@@ -297,6 +1409,142 @@ mod tests {
             .is_none())
     }
 
+    #[test]
+    fn prune_expired() {
+        let database = Database::try_new("./test_dbs/prune_expired").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        let mut digests = Vec::new();
+        for timestamp in &[100, 200] {
+            let message = Message {
+                received_time: *timestamp as i64,
+                ..Default::default()
+            };
+            let mut raw_message = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut raw_message).unwrap();
+            let digest = digest(&SHA256, &raw_message);
+            database
+                .schedule_expiry(
+                    address_payload,
+                    *timestamp,
+                    digest.as_ref(),
+                    MESSAGE_NAMESPACE,
+                    timestamp * 10,
+                )
+                .unwrap();
+            database
+                .push_message(
+                    address_payload,
+                    *timestamp,
+                    &raw_message[..],
+                    digest.as_ref(),
+                    MESSAGE_NAMESPACE,
+                )
+                .unwrap();
+            digests.push(digest);
+        }
+
+        // Entries of messages which were never pushed are discarded
+        database
+            .schedule_expiry(address_payload, 300, &[0; 32], MESSAGE_NAMESPACE, 500)
+            .unwrap();
+
+        // Messages are kept until they expire
+        assert_eq!(database.prune_expired(500).unwrap().0, 0);
+        assert_eq!(database.prune_expired(1500).unwrap().0, 1);
+        assert!(database
+            .get_message_by_digest(address_payload, digests[0].as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_none());
+        assert!(database
+            .get_message_by_digest(address_payload, digests[1].as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_some());
+
+        // Expired tombstones cannot be restored
+        assert!(database
+            .remove_message_by_digest(
+                address_payload,
+                digests[1].as_ref(),
+                MESSAGE_NAMESPACE,
+                true
+            )
+            .unwrap()
+            .is_some());
+        assert_eq!(database.prune_expired(2500).unwrap().0, 1);
+        assert!(database
+            .restore_message_by_digest(address_payload, digests[1].as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_none());
+        assert_eq!(database.prune_expired(u64::MAX).unwrap().0, 0);
+    }
+
+    #[test]
+    fn restore_tombstone() {
+        let database = Database::try_new("./test_dbs/restore_tombstone").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        let message = Message::default();
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+        let digest = digest(&SHA256, &raw_message);
+
+        database
+            .push_message(
+                address_payload,
+                100,
+                &raw_message[..],
+                digest.as_ref(),
+                MESSAGE_NAMESPACE,
+            )
+            .unwrap();
+
+        // Remove then restore
+        assert!(database
+            .remove_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE, true)
+            .unwrap()
+            .is_some());
+        assert!(database
+            .get_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_none());
+        assert!(database
+            .restore_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            database
+                .get_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE)
+                .unwrap(),
+            Some(raw_message)
+        );
+
+        // Tombstones are only restored once
+        assert!(database
+            .restore_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_none());
+
+        // Purged tombstones cannot be restored
+        let prefix = msg_prefix(address_payload, 0, MESSAGE_NAMESPACE);
+        assert_eq!(
+            database
+                .remove_messages_range(&prefix, None, 10, false, true)
+                .unwrap(),
+            (1, false, vec![])
+        );
+        assert_eq!(database.purge_tombstones(0).unwrap().0, 0);
+        assert_eq!(database.purge_tombstones(u64::MAX).unwrap().0, 1);
+        assert!(database
+            .restore_message_by_digest(address_payload, digest.as_ref(), MESSAGE_NAMESPACE)
+            .unwrap()
+            .is_none());
+    }
+
     #[test]
     fn get_time_range() {
         let database = Database::try_new("./test_dbs/get_time_range").unwrap();
@@ -374,4 +1622,393 @@ mod tests {
             0
         )
     }
+
+    #[test]
+    fn remove_time_range() {
+        let database = Database::try_new("./test_dbs/remove_time_range").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        for timestamp in &[100, 105, 110] {
+            let message = Message {
+                received_time: *timestamp as i64,
+                payload_digest: vec![*timestamp as u8; 32],
+                ..Default::default()
+            };
+            let mut raw_message = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut raw_message).unwrap();
+            let digest = digest(&SHA256, &raw_message);
+            database
+                .push_message(
+                    address_payload,
+                    *timestamp,
+                    &raw_message[..],
+                    digest.as_ref(),
+                    MESSAGE_NAMESPACE,
+                )
+                .unwrap();
+        }
+        let prefix = msg_prefix(address_payload, 0, MESSAGE_NAMESPACE);
+        let count_messages = || {
+            database
+                .get_messages_range(&prefix, None)
+                .unwrap()
+                .messages
+                .len()
+        };
+
+        // Dry runs remove nothing
+        assert_eq!(
+            database
+                .remove_messages_range(&prefix, None, 10, true, false)
+                .unwrap(),
+            (3, false, vec![])
+        );
+        assert_eq!(count_messages(), 3);
+
+        // Removals within [0, 106) are limited
+        let prefix_end = msg_prefix(address_payload, 106, MESSAGE_NAMESPACE);
+        assert_eq!(
+            database
+                .remove_messages_range(&prefix, Some(&prefix_end), 1, false, false)
+                .unwrap(),
+            (1, true, vec![])
+        );
+        assert_eq!(count_messages(), 2);
+        assert_eq!(
+            database
+                .remove_messages_range(&prefix, Some(&prefix_end), 10, false, false)
+                .unwrap(),
+            (1, false, vec![])
+        );
+        assert_eq!(count_messages(), 1);
+
+        // Remove the remainder
+        assert_eq!(
+            database
+                .remove_messages_range(&prefix, None, 1, false, false)
+                .unwrap(),
+            (1, false, vec![])
+        );
+        assert_eq!(count_messages(), 0);
+    }
+
+    #[test]
+    fn summarize_namespace() {
+        let database = Database::try_new("./test_dbs/summarize_namespace").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        for timestamp in &[100, 105, 110] {
+            let message = Message {
+                received_time: *timestamp as i64,
+                payload_digest: vec![*timestamp as u8; 32],
+                ..Default::default()
+            };
+            let mut raw_message = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut raw_message).unwrap();
+            let digest = digest(&SHA256, &raw_message);
+            database
+                .push_message(
+                    address_payload,
+                    *timestamp,
+                    &raw_message[..],
+                    digest.as_ref(),
+                    MESSAGE_NAMESPACE,
+                )
+                .unwrap();
+        }
+
+        let summary = database.summarize_namespace(address_payload, MESSAGE_NAMESPACE, 100);
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.unread_count, 2);
+        assert_eq!(summary.oldest_time, 100);
+        assert_eq!(summary.newest_time, 110);
+        assert!(summary.total_bytes > 0);
+
+        let summary = database.summarize_namespace(address_payload, FEED_NAMESPACE, 0);
+        assert_eq!(summary, NamespaceSummary::default());
+    }
+
+    #[test]
+    fn push_registrations() {
+        let database = Database::try_new("./test_dbs/push_registrations").unwrap();
+        let pubkey_hash = [7; 20];
+        let registration = |endpoint: &str, auth: u8| PushRegistration {
+            endpoint: endpoint.to_string(),
+            auth: vec![auth; 16],
+            ..Default::default()
+        };
+
+        database
+            .put_push_registration(&pubkey_hash, &registration("https://push.example/a", 1))
+            .unwrap();
+        database
+            .put_push_registration(&pubkey_hash, &registration("https://push.example/b", 1))
+            .unwrap();
+
+        // Registering an endpoint again replaces it
+        database
+            .put_push_registration(&pubkey_hash, &registration("https://push.example/a", 2))
+            .unwrap();
+        let registrations = database.get_push_registrations(&pubkey_hash).unwrap();
+        assert_eq!(registrations.len(), 2);
+        assert!(registrations
+            .iter()
+            .any(|stored| stored.auth == vec![2; 16]));
+        assert!(database
+            .get_push_registrations(&[8; 20])
+            .unwrap()
+            .is_empty());
+
+        assert!(database
+            .remove_push_registration(&pubkey_hash, "https://push.example/a")
+            .unwrap());
+        assert!(!database
+            .remove_push_registration(&pubkey_hash, "https://push.example/a")
+            .unwrap());
+        assert_eq!(
+            database.get_push_registrations(&pubkey_hash).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn sequence_order() {
+        let database = Database::try_new("./test_dbs/sequence_order").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        // Later messages are given later sequence numbers, even when received earlier
+        let mut sequences = Vec::new();
+        for timestamp in &[110, 100, 105] {
+            let message = Message {
+                received_time: *timestamp as i64,
+                payload_digest: vec![*timestamp as u8; 32],
+                ..Default::default()
+            };
+            let sequence = database
+                .push_sequenced_message(
+                    address_payload,
+                    *timestamp,
+                    &message.payload_digest,
+                    &[8; 20],
+                    MESSAGE_NAMESPACE,
+                    |sequence| {
+                        let message = Message {
+                            sequence,
+                            ..message.clone()
+                        };
+                        let mut raw_message = Vec::with_capacity(message.encoded_len());
+                        message.encode(&mut raw_message).unwrap();
+                        raw_message
+                    },
+                )
+                .unwrap();
+            sequences.push(sequence);
+        }
+        assert_eq!(sequences[1], sequences[0] + 1);
+        assert_eq!(sequences[2], sequences[1] + 1);
+
+        // Messages are returned in sequence order, skipping those overwritten
+        let message_page = database
+            .get_messages_after_sequence(address_payload, MESSAGE_NAMESPACE, 0, usize::MAX)
+            .unwrap();
+        let times: Vec<i64> = message_page
+            .messages
+            .iter()
+            .map(|message| message.received_time)
+            .collect();
+        assert_eq!(times, vec![110, 100, 105]);
+        assert_eq!(message_page.start_sequence, sequences[0]);
+        assert_eq!(message_page.end_sequence, sequences[2]);
+        assert_eq!(message_page.end_time, 105);
+
+        // Pages resume after the given sequence number
+        let message_page = database
+            .get_messages_after_sequence(address_payload, MESSAGE_NAMESPACE, sequences[0], 1)
+            .unwrap();
+        assert_eq!(message_page.messages.len(), 1);
+        assert_eq!(message_page.start_sequence, sequences[1]);
+
+        // Other namespaces are sequenced independently
+        let message_page = database
+            .get_messages_after_sequence(address_payload, FEED_NAMESPACE, 0, usize::MAX)
+            .unwrap();
+        assert!(message_page.messages.is_empty());
+    }
+
+    #[test]
+    fn get_deletions() {
+        let database = Database::try_new("./test_dbs/get_deletions").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        let message = Message {
+            payload_digest: vec![3; 32],
+            ..Default::default()
+        };
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+        database
+            .push_message(
+                address_payload,
+                100,
+                &raw_message[..],
+                &message.payload_digest,
+                FEED_NAMESPACE,
+            )
+            .unwrap();
+
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        database
+            .remove_message_by_digest(
+                address_payload,
+                &message.payload_digest,
+                FEED_NAMESPACE,
+                true,
+            )
+            .unwrap()
+            .unwrap();
+
+        let deletions = database.get_deletions(address_payload, before - 1);
+        assert_eq!(deletions.len(), 1);
+        let (namespace, deleted_message, deleted_time) = &deletions[0];
+        assert_eq!(*namespace, FEED_NAMESPACE);
+        assert_eq!(deleted_message, &message);
+        assert!(*deleted_time >= before);
+
+        assert!(database.get_deletions(address_payload, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn search_messages() {
+        let database = Database::try_new("./test_dbs/search_messages").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        for timestamp in &[100, 105, 110, 115] {
+            let message = Message {
+                received_time: *timestamp as i64,
+                payload_size: *timestamp % 2,
+                payload_digest: vec![*timestamp as u8; 32],
+                ..Default::default()
+            };
+            let mut raw_message = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut raw_message).unwrap();
+            database
+                .push_message(
+                    address_payload,
+                    *timestamp,
+                    &raw_message[..],
+                    &message.payload_digest,
+                    MESSAGE_NAMESPACE,
+                )
+                .unwrap();
+        }
+
+        // Matches are filtered within the range
+        let start_prefix = msg_prefix(address_payload, 100, MESSAGE_NAMESPACE);
+        let end_prefix = msg_prefix(address_payload, 115, MESSAGE_NAMESPACE);
+        let message_page =
+            database.search_messages(&start_prefix, Some(&end_prefix), 10, |message| {
+                message.payload_size == 1
+            });
+        let times: Vec<i64> = message_page
+            .messages
+            .iter()
+            .map(|message| message.received_time)
+            .collect();
+        assert_eq!(times, vec![105]);
+
+        // Limit applies to matches
+        let message_page =
+            database.search_messages(&start_prefix, None, 1, |message| message.payload_size == 1);
+        assert_eq!(message_page.messages.len(), 1);
+        assert_eq!(message_page.start_time, 105);
+        assert_eq!(message_page.end_time, 105);
+    }
+
+    #[test]
+    fn conversations() {
+        let database = Database::try_new("./test_dbs/conversations").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+
+        let mut sender_pubkey_hashes = Vec::new();
+        for (timestamp, source) in &[(100, 2), (105, 3), (110, 2)] {
+            let message = Message {
+                source_public_key: vec![*source; 33],
+                received_time: *timestamp as i64,
+                payload_digest: vec![*timestamp as u8; 32],
+                ..Default::default()
+            };
+            let sender_pubkey_hash =
+                Ripemd160::digest(digest(&SHA256, &message.source_public_key).as_ref());
+            database
+                .push_sequenced_message(
+                    address_payload,
+                    *timestamp,
+                    &message.payload_digest,
+                    &sender_pubkey_hash,
+                    MESSAGE_NAMESPACE,
+                    |sequence| {
+                        let message = Message {
+                            sequence,
+                            ..message.clone()
+                        };
+                        let mut raw_message = Vec::with_capacity(message.encoded_len());
+                        message.encode(&mut raw_message).unwrap();
+                        raw_message
+                    },
+                )
+                .unwrap();
+            sender_pubkey_hashes.push(sender_pubkey_hash.to_vec());
+        }
+
+        // Most recently active first
+        let conversations = database
+            .get_conversations(address_payload, MESSAGE_NAMESPACE)
+            .unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].sender_pubkey_hash, sender_pubkey_hashes[0]);
+        assert_eq!(conversations[0].count, 2);
+        assert_eq!(conversations[0].latest_time, 110);
+        assert_eq!(conversations[1].sender_pubkey_hash, sender_pubkey_hashes[1]);
+        assert_eq!(conversations[1].count, 1);
+
+        // Messages by sender within a time range
+        let message_page = database
+            .get_messages_by_sender(
+                address_payload,
+                MESSAGE_NAMESPACE,
+                &sender_pubkey_hashes[0],
+                101,
+                None,
+                10,
+            )
+            .unwrap();
+        assert_eq!(message_page.messages.len(), 1);
+        assert_eq!(message_page.start_time, 110);
+
+        // Removed messages leave the index
+        let prefix = msg_prefix(address_payload, 110, MESSAGE_NAMESPACE);
+        database
+            .remove_messages_range(&prefix, None, 10, false, false)
+            .unwrap();
+        let conversations = database
+            .get_conversations(address_payload, MESSAGE_NAMESPACE)
+            .unwrap();
+        assert_eq!(conversations[0].sender_pubkey_hash, sender_pubkey_hashes[1]);
+        assert_eq!(conversations[1].count, 1);
+    }
 }
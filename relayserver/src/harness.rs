@@ -0,0 +1,206 @@
+//! An in-process relay server for end-to-end tests.
+//!
+//! [`TestServer`] serves the routes constructed by [`routes`](crate::routes::routes) over a
+//! temporary database and a [`MockBitcoinClient`], bound to an ephemeral local port.
+
+use std::{ops::Deref, sync::Arc, time::Duration};
+
+use cashweb::bitcoin_client::{mock::MockBitcoinClient, ChainBackend, FailoverClient};
+use cashweb_server_core::harness::{self, wait_until};
+use dashmap::DashMap;
+
+use crate::{db::Database, net::MessageBus, routes::routes};
+
+/// Time allowed for websockets to subscribe.
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A relay server running inside the test process.
+pub struct TestServer {
+    /// The bitcoin client used by the server, shared so tests can script responses.
+    pub bitcoin_client: MockBitcoinClient,
+    server: harness::TestServer,
+    msg_bus: MessageBus,
+}
+
+impl Deref for TestServer {
+    type Target = harness::TestServer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.server
+    }
+}
+
+impl TestServer {
+    /// Spawn a server, storing its database under `./test_dbs/<name>`.
+    pub fn spawn(name: &str) -> Self {
+        let db = Database::try_new(&format!("./test_dbs/{}", name)).unwrap();
+        let bitcoin_client = MockBitcoinClient::new();
+        let msg_bus: MessageBus = Arc::new(DashMap::new());
+
+        let server = harness::TestServer::spawn(routes(
+            db,
+            None,
+            FailoverClient::new(vec![ChainBackend::from(bitcoin_client.clone())]),
+            None,
+            msg_bus.clone(),
+            Arc::new(DashMap::new()),
+        ));
+
+        Self {
+            bitcoin_client,
+            server,
+            msg_bus,
+        }
+    }
+
+    /// Wait until `n` websockets are subscribed to the messages of `pubkey_hash`.
+    ///
+    /// Sockets subscribe after the upgrade completes, so messages put in the meantime are missed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sockets do not subscribe within [`SUBSCRIBE_TIMEOUT`].
+    pub async fn wait_for_subscribers(&self, pubkey_hash: &[u8], n: usize) {
+        let msg_bus = self.msg_bus.clone();
+        let pubkey_hash = pubkey_hash.to_vec();
+        wait_until(
+            move || {
+                msg_bus
+                    .get(&pubkey_hash)
+                    .map_or(0, |sender| sender.receiver_count())
+                    >= n
+            },
+            SUBSCRIBE_TIMEOUT,
+        )
+        .await;
+    }
+
+    /// Stop the server, once its connections are closed.
+    pub async fn shutdown(self) {
+        self.server.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincash_addr::{Address, Network};
+    use cashweb::{
+        bitcoin_client::mock::MockCall,
+        relay::{Message, MessagePage, MessageSet, Stamp},
+        token::schemes::hmac_bearer::{HmacScheme, Scopes},
+    };
+    use futures::prelude::*;
+    use hyper::{body::to_bytes, header::AUTHORIZATION, Body, Client, Request};
+    use prost::Message as _;
+    use ring::digest::{digest, SHA256};
+    use ripemd160::{Digest, Ripemd160};
+    use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+    use super::*;
+    use crate::{CAPABILITIES_PATH, MESSAGES_PATH, SETTINGS, WS_PATH};
+
+    /// The compressed secp256k1 generator point.
+    const PUBLIC_KEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn encode_message(message: &impl prost::Message) -> Vec<u8> {
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+        raw_message
+    }
+
+    #[tokio::test]
+    async fn token_message_websocket() {
+        let server = TestServer::spawn("harness_token_message_websocket");
+        let client = Client::new();
+
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+        let pubkey_hash = Ripemd160::digest(digest(&SHA256, &public_key).as_ref()).to_vec();
+        let address = Address {
+            body: pubkey_hash.clone(),
+            network: Network::Test,
+            ..Default::default()
+        }
+        .encode()
+        .unwrap();
+        let messages_path = format!("{}/{}", MESSAGES_PATH, address);
+
+        let response = client
+            .get(server.url(CAPABILITIES_PATH).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // Without a token, reading the messages requires a payment to a fresh address
+        server.bitcoin_client.set_new_addr(address.clone());
+        let response = client
+            .get(server.url(&messages_path).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 402);
+        assert_eq!(server.bitcoin_client.calls(), vec![MockCall::GetNewAddr]);
+
+        // Subscribers receive the messages put to their address
+        let key = hex::decode(&SETTINGS.payments.hmac_secret).unwrap();
+        let token = format!(
+            "POP {}",
+            HmacScheme::new(&key).construct_scoped_token(&pubkey_hash, Scopes::ALL)
+        );
+        let (mut socket, _) = connect_async(
+            server
+                .ws_url(&format!(
+                    "{}/{}/{}?access_token={}",
+                    WS_PATH,
+                    MESSAGES_PATH,
+                    address,
+                    token.replace(' ', "%20")
+                ))
+                .as_str(),
+        )
+        .await
+        .unwrap();
+        server.wait_for_subscribers(&pubkey_hash, 1).await;
+
+        // Messages to self need no stamp
+        let message = Message {
+            source_public_key: public_key.clone(),
+            destination_public_key: public_key,
+            payload: b"payload".to_vec(),
+            payload_hmac: vec![0; 32],
+            stamp: Some(Stamp::default()),
+            ..Default::default()
+        };
+        let message_set = MessageSet {
+            messages: vec![message.clone()],
+        };
+        let request = Request::put(server.url(&messages_path))
+            .body(Body::from(encode_message(&message_set)))
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let raw_received = loop {
+            match socket.next().await.unwrap().unwrap() {
+                WsMessage::Binary(raw_received) => break raw_received,
+                _ => continue,
+            }
+        };
+        let received = Message::decode(raw_received.as_slice()).unwrap();
+        assert_eq!(received.payload, message.payload);
+
+        // The token unlocks the stored messages
+        let request = Request::get(server.url(&messages_path))
+            .header(AUTHORIZATION, &token)
+            .body(Body::empty())
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let raw_page = to_bytes(response.into_body()).await.unwrap();
+        let page = MessagePage::decode(raw_page).unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].payload, message.payload);
+
+        drop(socket);
+        drop(client);
+        server.shutdown().await;
+    }
+}
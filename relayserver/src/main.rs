@@ -1,46 +1,59 @@
 #[macro_use]
 extern crate clap;
 
+pub mod commands;
+pub mod compression;
 pub mod db;
+#[cfg(test)]
+mod harness;
 pub mod net;
+pub mod notifications;
+pub mod payloads;
+pub mod routes;
 pub mod settings;
+pub mod sweep;
 
 #[cfg(feature = "monitoring")]
 pub mod monitoring;
 
-use std::{env, sync::Arc, time::Duration};
+use std::{env, process, sync::Arc, time::Duration};
 
-use cashweb::bitcoin_client::BitcoinClientHTTP;
-use cashweb::{
-    payments::{preprocess_payment, wallet::Wallet},
-    token::schemes::hmac_bearer::HmacScheme,
-};
+use cashweb::bitcoin_client::FailoverClient;
+use cashweb_server_core::listener;
 use dashmap::DashMap;
-use futures::prelude::*;
 use lazy_static::lazy_static;
-use serde::Deserialize;
-use tracing::info;
+use tokio::time::interval;
+use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
-use warp::{
-    http::{header, Method},
-    Filter,
-};
-
-#[cfg(feature = "monitoring")]
-use prometheus::{Encoder, TextEncoder};
+use warp::{http::header, Filter};
 
 use crate::{
-    db::{Database, FEED_NAMESPACE, MESSAGE_NAMESPACE},
-    settings::Settings,
+    db::Database,
+    settings::{Command, Settings},
 };
 
 const DASHMAP_CAPACITY: usize = 2048;
 
 const PROFILES_PATH: &str = "profiles";
+const AVATAR_PATH: &str = "avatar";
 const WS_PATH: &str = "ws";
 const MESSAGES_PATH: &str = "messages";
 const PAYLOADS_PATH: &str = "payloads";
 const FEEDS_PATH: &str = "feeds";
+const INBOX_PATH: &str = "inbox";
+const SUMMARY_PATH: &str = "summary";
+const RESTORE_PATH: &str = "restore";
+const SYNC_PATH: &str = "sync";
+const SEARCH_PATH: &str = "search";
+const CONVERSATIONS_PATH: &str = "conversations";
+const EXPORT_PATH: &str = "export";
+const IMPORT_PATH: &str = "import";
+const CAPABILITIES_PATH: &str = "capabilities";
+
+const SEARCH_SIZE_LIMIT: u64 = 1024; // 1Kb
+const TOKENS_PATH: &str = "tokens";
+const INTROSPECT_PATH: &str = "introspect";
+const NOTIFICATIONS_PATH: &str = "notifications";
 pub const PAYMENTS_PATH: &str = "payments";
 
 lazy_static! {
@@ -48,11 +61,6 @@ lazy_static! {
     pub static ref SETTINGS: Settings = Settings::new().expect("couldn't load config");
 }
 
-#[derive(Debug, Deserialize)]
-pub struct QueryAccessToken {
-    access_token: Option<String>,
-}
-
 #[tokio::main]
 async fn main() {
     if env::var_os("RUST_LOG").is_none() {
@@ -63,246 +71,143 @@ async fn main() {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("no global subscriber has been set");
 
+    // Run operational subcommand
+    if SETTINGS.command != Command::Serve {
+        if let Err(err) = commands::run(&SETTINGS.command) {
+            error!(message = "command failed", error = %err);
+            process::exit(1);
+        }
+        return;
+    }
+
     info!(message = "starting", version = crate_version!());
 
     // Database state
     info!(message = "opening database", path = %SETTINGS.db_path);
     let db = Database::try_new(&SETTINGS.db_path).expect("failed to open database");
-    let db_state = warp::any().map(move || db.clone());
+    db::set_slow_op_threshold(SETTINGS.db_slow_threshold);
+
+    // Payload storage state
+    let payload_store = SETTINGS.payload_storage.bucket.as_ref().map(|bucket| {
+        info!(message = "constructing payload store", bucket = %bucket);
+        let credentials = SETTINGS
+            .payload_storage
+            .access_key
+            .clone()
+            .zip(SETTINGS.payload_storage.secret_key.clone());
+        payloads::PayloadStore::new(
+            bucket.clone(),
+            SETTINGS.payload_storage.prefix.clone(),
+            SETTINGS.payload_storage.region.clone(),
+            SETTINGS.payload_storage.endpoint.clone(),
+            credentials,
+            SETTINGS.payload_storage.threshold,
+        )
+    });
+
+    // Tombstone purging
+    if SETTINGS.tombstones.grace_period != 0 {
+        info!(
+            message = "purging tombstones",
+            grace_period = SETTINGS.tombstones.grace_period
+        );
+        tokio::spawn(net::purge_tombstones(db.clone(), payload_store.clone()));
+    }
+
+    // Digest index scavenging
+    if SETTINGS.tombstones.scavenge_interval != 0 {
+        tokio::spawn(net::scavenge_digests(db.clone()));
+    }
+
+    // Expired message pruning
+    if SETTINGS.expiry.prune_interval != 0 {
+        tokio::spawn(net::prune_expired(db.clone(), payload_store.clone()));
+    }
+
+    // Push notification state
+    let notifier = if SETTINGS.notifications.enabled {
+        info!(
+            message = "constructing notifier",
+            ttl = SETTINGS.notifications.ttl
+        );
+        let vapid = SETTINGS
+            .notifications
+            .vapid_key
+            .as_deref()
+            .zip(SETTINGS.notifications.vapid_subject.clone());
+        let notifier = notifications::Notifier::new(
+            vapid,
+            SETTINGS.notifications.fcm_credentials.as_deref(),
+            SETTINGS.notifications.ttl,
+        )
+        .expect("unable to construct notifier");
+        Some(notifier)
+    } else {
+        None
+    };
 
     // Message broadcast state
     info!("constructing message bus");
     let message_bus = Arc::new(DashMap::with_capacity(DASHMAP_CAPACITY));
-    let msg_bus_state = warp::any().map(move || message_bus.clone());
 
     // Feed broadcast state
     info!("constructing feed bus");
     let feed_bus = Arc::new(DashMap::with_capacity(DASHMAP_CAPACITY));
-    let feed_bus_state = warp::any().map(move || feed_bus.clone());
-
-    // Wallet state
-    info!(
-        message = "constructing wallet",
-        timeout = SETTINGS.payments.timeout
-    );
-    let wallet = Wallet::new(Duration::from_millis(SETTINGS.payments.timeout));
-    let wallet_state = warp::any().map(move || wallet.clone());
 
     // Bitcoin client state
     info!(message = "constructing bitcoin client", address = %SETTINGS.bitcoin_rpc.address);
-    let bitcoin_client = BitcoinClientHTTP::new(
-        SETTINGS.bitcoin_rpc.address.clone(),
-        SETTINGS.bitcoin_rpc.username.clone(),
-        SETTINGS.bitcoin_rpc.password.clone(),
+    let bitcoin_client = FailoverClient::new(
+        std::iter::once(&SETTINGS.bitcoin_rpc.address)
+            .chain(&SETTINGS.bitcoin_rpc.fallback_addresses)
+            .map(|address| SETTINGS.bitcoin_rpc.client(address))
+            .collect(),
     );
-    let bitcoin_client_state = warp::any().map(move || bitcoin_client.clone());
-
-    // Address string converter
-    let addr_base = warp::path::param().and_then(|addr_str: String| async move {
-        net::address_decode(&addr_str).map_err(warp::reject::custom)
-    });
 
-    // Token generator
-    let key =
-        hex::decode(&SETTINGS.payments.hmac_secret).expect("unable to interpret hmac key as hex");
-    let token_scheme = Arc::new(HmacScheme::new(&key));
-    let token_scheme_state = warp::any().map(move || token_scheme.clone());
-
-    // Protection
-    let addr_protected = addr_base
-        .and(warp::header::headers_cloned())
-        .and(warp::query())
-        .and(token_scheme_state.clone())
-        .and(wallet_state.clone())
-        .and(bitcoin_client_state.clone())
-        .and_then(
-            move |addr, headers, query: QueryAccessToken, token_scheme, wallet, bitcoin| {
-                net::pop_protection(
-                    addr,
-                    headers,
-                    query.access_token,
-                    token_scheme,
-                    wallet,
-                    bitcoin,
-                )
-                .map_err(warp::reject::custom)
-            },
-        );
-
-    info!("constructing handlers");
-
-    // Message handlers
-    let messages_get = warp::path(MESSAGES_PATH)
-        .and(addr_protected.clone())
-        .and(warp::get())
-        .and(warp::query())
-        .and(db_state.clone())
-        .and_then(move |addr, query, db| {
-            net::get_messages(addr, query, db, MESSAGE_NAMESPACE).map_err(warp::reject::custom)
-        });
-    let messages_put = warp::path(MESSAGES_PATH)
-        .and(addr_base)
-        .and(warp::put())
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.message_size,
-        ))
-        .and(warp::body::bytes())
-        .and(db_state.clone())
-        .and(bitcoin_client_state.clone())
-        .and(msg_bus_state.clone())
-        .and_then(move |addr, body, db, bitcoin_client, msg_bus| {
-            net::put_message(addr, body, db, bitcoin_client, msg_bus, MESSAGE_NAMESPACE)
-                .map_err(warp::reject::custom)
-        });
-    let messages_delete = warp::path(MESSAGES_PATH)
-        .and(addr_protected.clone())
-        .and(warp::delete())
-        .and(warp::query())
-        .and(db_state.clone())
-        .and_then(move |addr, query, db| {
-            net::remove_messages(addr, query, db, MESSAGE_NAMESPACE).map_err(warp::reject::custom)
+    // Bitcoin client health checks
+    if !SETTINGS.bitcoin_rpc.fallback_addresses.is_empty() {
+        let bitcoin_client = bitcoin_client.clone();
+        tokio::spawn(async move {
+            let mut health_check_interval = interval(Duration::from_millis(
+                SETTINGS.bitcoin_rpc.health_check_interval,
+            ));
+            loop {
+                health_check_interval.tick().await;
+                if bitcoin_client.health_check().await == 0 {
+                    warn!("no healthy bitcoin nodes");
+                }
+            }
         });
+    }
 
-    // Feed handlers
-    let feeds_get = warp::path(FEEDS_PATH)
-        .and(addr_base)
-        .and(warp::get())
-        .and(warp::query())
-        .and(db_state.clone())
-        .and_then(move |addr, query, db| {
-            net::get_messages(addr, query, db, FEED_NAMESPACE).map_err(warp::reject::custom)
-        });
-    let feeds_put = warp::path(FEEDS_PATH)
-        .and(addr_protected.clone())
-        .and(warp::put())
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.message_size,
-        ))
-        .and(warp::body::bytes())
-        .and(db_state.clone())
-        .and(bitcoin_client_state.clone())
-        .and(msg_bus_state.clone())
-        .and_then(move |addr, body, db, bitcoin_client, msg_bus| {
-            net::put_message(addr, body, db, bitcoin_client, msg_bus, FEED_NAMESPACE)
-                .map_err(warp::reject::custom)
-        });
-    let feeds_delete = warp::path(FEEDS_PATH)
-        .and(addr_protected.clone())
-        .and(warp::delete())
-        .and(warp::query())
-        .and(db_state.clone())
-        .and_then(move |addr, query, db| {
-            net::remove_messages(addr, query, db, FEED_NAMESPACE).map_err(warp::reject::custom)
-        });
-
-    // Payload handlers
-    let payloads_get = warp::path(PAYLOADS_PATH)
-        .and(addr_protected.clone())
-        .and(warp::get())
-        .and(warp::query())
-        .and(db_state.clone())
-        .and_then(move |addr, query, db| {
-            net::get_payloads(addr, query, db, MESSAGE_NAMESPACE).map_err(warp::reject::custom)
-        });
-
-    // Websocket handlers
-    let websocket_messages = warp::path(WS_PATH)
-        .and(warp::path(MESSAGES_PATH))
-        .and(addr_protected.clone())
-        .and(warp::ws())
-        .and(msg_bus_state.clone())
-        .map(net::upgrade_ws);
-
-    let websocket_feeds = warp::path(WS_PATH)
-        .and(warp::path(FEEDS_PATH))
-        .and(addr_base)
-        .and(warp::ws())
-        .and(feed_bus_state)
-        .map(net::upgrade_ws);
-
-    let websocket_messages_fallback = warp::path(WS_PATH)
-        .and(addr_protected.clone())
-        .and(warp::ws())
-        .and(msg_bus_state.clone())
-        .map(net::upgrade_ws);
-
-    // Profile handlers
-    let profile_get = warp::path(PROFILES_PATH)
-        .and(addr_base)
-        .and(warp::get())
-        .and(db_state.clone())
-        .and_then(move |addr, db| net::get_profile(addr, db).map_err(warp::reject::custom));
-    let profile_put = warp::path(PROFILES_PATH)
-        .and(addr_protected)
-        .and(warp::put())
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.profile_size,
-        ))
-        .and(warp::body::bytes())
-        .and(db_state)
-        .and_then(move |addr, body, db| {
-            net::put_profile(addr, body, db).map_err(warp::reject::custom)
-        });
-
-    // Payment handler
-    let payments = warp::path(PAYMENTS_PATH)
-        .and(warp::post())
-        .and(warp::header::headers_cloned())
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.profile_size,
-        ))
-        .and(warp::body::bytes())
-        .and_then(move |headers, body| {
-            preprocess_payment(headers, body)
-                .map_err(net::PaymentError::Preprocess)
-                .map_err(warp::reject::custom)
-        })
-        .and(wallet_state.clone())
-        .and(bitcoin_client_state.clone())
-        .and(token_scheme_state)
-        .and_then(
-            move |payment, wallet, bitcoin_client, token_state| async move {
-                net::process_payment(payment, wallet, bitcoin_client, token_state)
-                    .await
-                    .map_err(warp::reject::custom)
-            },
-        );
-
-    // Root handler
-    let root = warp::path::end()
-        .and(warp::get())
-        .and(warp::fs::file("./static/index.html"));
+    // Token fee sweeping
+    if let Some(destination) = &SETTINGS.sweep.destination {
+        info!(message = "sweeping token fees", destination = %destination);
+        let address =
+            net::address_decode(destination).expect("unable to interpret sweep destination");
+        let destination =
+            net::output_script(&address).expect("unable to interpret sweep destination");
+        tokio::spawn(sweep::sweep_fees(bitcoin_client.clone(), destination));
+    }
 
     // CORs
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_methods(vec![Method::GET, Method::PUT, Method::POST, Method::DELETE])
-        .allow_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
-        .expose_headers(vec![
-            header::AUTHORIZATION,
-            header::ACCEPT,
-            header::LOCATION,
-        ])
+    let cors = cashweb_server_core::cors()
+        .allow_header(header::CONTENT_ENCODING)
+        .allow_header(net::MESSAGE_TTL)
+        .expose_header(net::PAYMENT_URI)
+        .expose_header(net::PAYMENT_QR)
         .build();
 
     // Init REST API
-    let rest_api = root
-        .or(payments)
-        .or(websocket_messages)
-        .or(websocket_feeds)
-        .or(websocket_messages_fallback)
-        .or(messages_get)
-        .or(messages_delete)
-        .or(messages_put)
-        .or(feeds_get)
-        .or(feeds_delete)
-        .or(feeds_put)
-        .or(payloads_get)
-        .or(profile_get)
-        .or(profile_put)
-        .recover(net::handle_rejection)
-        .with(cors)
-        .with(warp::trace::request());
+    let rest_api = routes::routes(
+        db,
+        payload_store,
+        bitcoin_client,
+        notifier,
+        message_bus,
+        feed_bus,
+    )
+    .with(cors)
+    .with(warp::trace::request());
 
     // If monitoring is enabled
     #[cfg(feature = "monitoring")]
@@ -310,15 +215,17 @@ async fn main() {
         info!(monitoring = true);
 
         // Init Prometheus server
-        let prometheus_server = warp::path("metrics").map(monitoring::export);
-        let prometheus_task = warp::serve(prometheus_server).run(SETTINGS.bind_prom);
+        let prometheus_server = warp::path("metrics").map(monitoring::export).boxed();
+        let prometheus_task = listener::serve(prometheus_server, &SETTINGS.bind_prom);
 
-        let rest_api = rest_api.with(warp::log::custom(monitoring::measure));
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
+        let rest_api = rest_api
+            .with(warp::log::custom(monitoring::measure))
+            .boxed();
+        let rest_api_task = listener::serve(rest_api, &SETTINGS.bind);
 
         // Spawn servers
         tokio::spawn(prometheus_task);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        rest_api_task.await;
     }
 
     // If monitoring is disabled
@@ -326,7 +233,6 @@ async fn main() {
     {
         info!(monitoring = false);
 
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        listener::serve(rest_api.boxed(), &SETTINGS.bind).await;
     }
 }
@@ -1,5 +1,6 @@
+pub use cashweb_server_core::monitoring::export;
 use lazy_static::lazy_static;
-use prometheus::{CounterVec, HistogramVec};
+use prometheus::{CounterVec, Histogram, HistogramVec, IntCounter, IntCounterVec};
 use warp::filters::log::Info;
 
 use prometheus_static_metric::make_static_metric;
@@ -94,6 +95,98 @@ lazy_static! {
     )
     .unwrap();
     pub static ref HTTP_ELAPSED: RequestDurationHistogram = RequestDurationHistogram::from(&HTTP_ELAPSED_VEC);
+
+    // Database operation duration
+    pub static ref DB_ELAPSED_VEC: HistogramVec = prometheus::register_histogram_vec!(
+        "db_operation_duration_milliseconds",
+        "Histogram of database operation times.",
+        &["operation", "namespace"]
+    )
+    .unwrap();
+
+    // Slow database operation counter
+    pub static ref DB_SLOW_TOTAL_VEC: IntCounterVec = prometheus::register_int_counter_vec!(
+        "db_slow_operation_total",
+        "Total number of database operations slower than the threshold.",
+        &["operation", "namespace"]
+    )
+    .unwrap();
+
+    // Payment funnel counters
+    pub static ref PAYMENT_INVOICE_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "payment_invoice_total",
+        "Total number of payment requests issued, by the route requiring payment.",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref PAYMENT_RECEIVED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "payment_received_total",
+        "Total number of payments accepted."
+    )
+    .unwrap();
+    pub static ref PAYMENT_REJECTED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "payment_rejected_total",
+        "Total number of payments rejected, by reason.",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref TOKEN_ISSUED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "token_issued_total",
+        "Total number of tokens issued, by route.",
+        &["route"]
+    )
+    .unwrap();
+
+    // Websocket delivery latency
+    pub static ref WS_DELIVERY_ELAPSED: Histogram = prometheus::register_histogram!(
+        "ws_delivery_duration_milliseconds",
+        "Histogram of times from accepting a message to sending it over a websocket.",
+        vec![1., 5., 10., 25., 50., 100., 250., 500., 1_000., 2_500., 5_000.]
+    )
+    .unwrap();
+
+    // Dropped websocket broadcast counter
+    pub static ref WS_DROPPED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "ws_dropped_broadcast_total",
+        "Total number of broadcasts dropped by lagging websocket receivers."
+    )
+    .unwrap();
+
+    // Websocket policy violation counter
+    pub static ref WS_VIOLATIONS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "ws_policy_violations_total",
+        "Total number of websockets closed for exceeding the inbound frame limits."
+    )
+    .unwrap();
+
+    // Orphaned digest index entry counter
+    pub static ref DB_ORPHANED_DIGESTS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "db_orphaned_digest_total",
+        "Total number of dangling digest index entries removed by the scavenger."
+    )
+    .unwrap();
+
+    // Token fee sweep counters
+    pub static ref SWEEP_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "sweep_total",
+        "Total number of sweeps of token fees from the node wallet."
+    )
+    .unwrap();
+    pub static ref SWEEP_FAILED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "sweep_failed_total",
+        "Total number of failed sweeps of token fees from the node wallet."
+    )
+    .unwrap();
+    pub static ref SWEPT_SATOSHIS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "sweep_swept_satoshis_total",
+        "Total value, in satoshis, of the outputs swept from the node wallet."
+    )
+    .unwrap();
+    pub static ref SWEEP_FEE_SATOSHIS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "sweep_fee_satoshis_total",
+        "Total fees, in satoshis, paid by sweep transactions."
+    )
+    .unwrap();
 }
 
 pub fn measure(info: Info) {
@@ -110,12 +203,3 @@ pub fn measure(info: Info) {
         .get(route)
         .observe(duration_secs as f64);
 }
-
-pub fn export() -> Vec<u8> {
-    let metric_families = prometheus::gather();
-
-    let mut buffer = Vec::new();
-    let encoder = TextEncoder::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-    buffer
-}
@@ -0,0 +1,549 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bitcoincash_addr::Address;
+use bytes::{Buf, Bytes};
+use cashweb::relay::{self, ArchiveItem, ImportSummary, Message};
+use dashmap::DashMap;
+use prost::Message as _;
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
+use thiserror::Error;
+use tokio::task;
+use tracing::warn;
+use warp::{
+    http::Response,
+    hyper::{self, body::Sender, Body},
+    reject::Reject,
+};
+
+use crate::{
+    compression,
+    db::{Database, FEED_NAMESPACE, MESSAGE_NAMESPACE},
+    net::{store_profile, ProfileSchema, PutProfileError, ToResponse},
+    payloads::{PayloadStore, PayloadStoreError},
+    SETTINGS,
+};
+
+/// Number of messages read from the database at a time while exporting.
+const EXPORT_PAGE_SIZE: usize = 128;
+
+/// Number of tracked addresses after which stale entries are pruned.
+const PRUNE_THRESHOLD: usize = 4096;
+
+const MESSAGES_NAME: &str = "messages";
+const FEEDS_NAME: &str = "feeds";
+const PROFILE_NAME: &str = "profile";
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("export attempted too soon, retry in {0} seconds")]
+    TooSoon(u64),
+    #[error("failed to access database: {0}")]
+    DB(#[from] rocksdb::Error),
+    #[error("failed to fetch payload: {0}")]
+    PayloadStore(PayloadStoreError),
+    #[error("failed to read payload: {0}")]
+    PayloadRead(hyper::Error),
+    #[error("failed to decode item {0}: {1}")]
+    Decode(usize, prost::DecodeError),
+    #[error("item {0} has unknown namespace {1}")]
+    UnknownNamespace(usize, String),
+    #[error("item {0} is missing its message")]
+    MissingMessage(usize),
+    #[error("failed to parse message of item {0}: {1}")]
+    MessageParsing(usize, relay::ParseError),
+    #[error("message of item {0} neither sent nor received by address")]
+    ForeignMessage(usize),
+    #[error("message of item {0} is missing its payload")]
+    MissingPayload(usize),
+    #[error("failed to import profile: {0}")]
+    Profile(PutProfileError),
+}
+
+impl Reject for ArchiveError {}
+
+impl ToResponse for ArchiveError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::TooSoon(_) => 429,
+            Self::DB(_) | Self::PayloadStore(_) | Self::PayloadRead(_) => 500,
+            Self::Profile(err) => err.to_status(),
+            _ => 400,
+        }
+    }
+}
+
+/// Limits how often the inbox of each address may be exported.
+#[derive(Clone, Debug)]
+pub struct ExportLimiter {
+    last_exports: Arc<DashMap<Vec<u8>, Instant>>,
+    interval: Duration,
+}
+
+impl ExportLimiter {
+    /// Construct an export limiter. An `interval` of zero disables the limit.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            last_exports: Default::default(),
+            interval,
+        }
+    }
+
+    /// Records an export of an address, failing if the previous export was too recent.
+    pub fn check(&self, addr_payload: &[u8]) -> Result<(), ArchiveError> {
+        if self.interval == Duration::from_millis(0) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if let Some(last_export) = self.last_exports.get(addr_payload) {
+            let elapsed = now.duration_since(*last_export);
+            if elapsed < self.interval {
+                let remaining = self.interval - elapsed;
+                return Err(ArchiveError::TooSoon(remaining.as_secs() + 1));
+            }
+        }
+        self.last_exports.insert(addr_payload.to_vec(), now);
+
+        // Prune stale entries
+        if self.last_exports.len() > PRUNE_THRESHOLD {
+            self.last_exports
+                .retain(|_, last_export| now.duration_since(*last_export) < self.interval);
+        }
+        Ok(())
+    }
+}
+
+fn namespace_name(namespace: u8) -> &'static str {
+    match namespace {
+        FEED_NAMESPACE => FEEDS_NAME,
+        _ => MESSAGES_NAME,
+    }
+}
+
+/// Serialize an archive item, prefixed by its length.
+fn encode_item(item: &ArchiveItem) -> Bytes {
+    let mut raw_item = Vec::with_capacity(item.encoded_len() + 10);
+    item.encode_length_delimited(&mut raw_item).unwrap(); // This is safe
+    Bytes::from(raw_item)
+}
+
+/// Deserialize the length-delimited items of an archive.
+pub fn decode_archive(mut raw_archive: &[u8]) -> Result<Vec<ArchiveItem>, ArchiveError> {
+    let mut items = Vec::new();
+    while raw_archive.has_remaining() {
+        let item = ArchiveItem::decode_length_delimited(&mut raw_archive)
+            .map_err(|err| ArchiveError::Decode(items.len(), err))?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Write the profile, messages and feed items of an address to an archive body.
+async fn write_archive(
+    addr_payload: Vec<u8>,
+    database: Database,
+    payload_store: Option<PayloadStore>,
+    sender: &mut Sender,
+) -> Result<(), ArchiveError> {
+    // Write profile
+    let profile_database = database.clone();
+    let profile_addr = addr_payload.clone();
+    let opt_raw_profile =
+        task::spawn_blocking(move || profile_database.get_raw_profile(&profile_addr))
+            .await
+            .unwrap()?; // Unrecoverable
+    if let Some(raw_profile) = opt_raw_profile {
+        let item = ArchiveItem {
+            namespace: PROFILE_NAME.to_string(),
+            profile: raw_profile,
+            ..Default::default()
+        };
+        if sender.send_data(encode_item(&item)).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    // Write messages and feed items, a page at a time
+    for namespace in [MESSAGE_NAMESPACE, FEED_NAMESPACE].iter().copied() {
+        let mut opt_after_key: Option<Vec<u8>> = None;
+        loop {
+            let page_database = database.clone();
+            let page_addr = addr_payload.clone();
+            let (messages, opt_last_key) = task::spawn_blocking(move || {
+                page_database.get_messages_after_key(
+                    &page_addr,
+                    namespace,
+                    opt_after_key.as_deref(),
+                    EXPORT_PAGE_SIZE,
+                )
+            })
+            .await
+            .unwrap(); // Unrecoverable
+
+            for mut message in messages {
+                // Inline offloaded payloads
+                if message.payload.is_empty()
+                    && database.is_offloaded(&addr_payload, &message.payload_digest)?
+                {
+                    if let Some(payload_store) = &payload_store {
+                        let body = payload_store
+                            .get(&message.payload_digest)
+                            .await
+                            .map_err(ArchiveError::PayloadStore)?;
+                        message.payload = hyper::body::to_bytes(body)
+                            .await
+                            .map_err(ArchiveError::PayloadRead)?
+                            .to_vec();
+                    }
+                }
+
+                let item = ArchiveItem {
+                    namespace: namespace_name(namespace).to_string(),
+                    message: Some(message),
+                    ..Default::default()
+                };
+                if sender.send_data(encode_item(&item)).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            opt_after_key = match opt_last_key {
+                Some(some) => Some(some),
+                None => break,
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Handles export requests, streaming the profile, messages and feed items of an address as a
+/// length-delimited archive.
+///
+/// Messages are exported as stored, their payloads remaining encrypted.
+pub async fn get_export(
+    addr: Address,
+    database: Database,
+    payload_store: Option<PayloadStore>,
+    limiter: ExportLimiter,
+) -> Result<Response<Body>, ArchiveError> {
+    limiter.check(addr.as_body())?;
+
+    let (mut sender, body) = Body::channel();
+    let addr_payload = addr.as_body().to_vec();
+    tokio::spawn(async move {
+        if let Err(err) = write_archive(addr_payload, database, payload_store, &mut sender).await {
+            warn!(message = "failed to export archive", error = %err);
+            sender.abort();
+        }
+    });
+
+    Ok(Response::builder().body(body).unwrap())
+}
+
+fn pubkey_hash(public_key: &[u8]) -> Vec<u8> {
+    Ripemd160::digest(digest(&SHA256, public_key).as_ref()).to_vec()
+}
+
+/// Validate a message of an archive, returning the hash of its source public key.
+fn validate_message(
+    index: usize,
+    addr_payload: &[u8],
+    message: &Message,
+) -> Result<Vec<u8>, ArchiveError> {
+    // Exports inline offloaded payloads, so an archive never holds one
+    if message.payload.is_empty() && message.payload_size != 0 {
+        return Err(ArchiveError::MissingPayload(index));
+    }
+    message
+        .clone()
+        .parse()
+        .map_err(|err| ArchiveError::MessageParsing(index, err))?;
+    let source_pubkey_hash = pubkey_hash(&message.source_public_key);
+    if source_pubkey_hash != addr_payload
+        && pubkey_hash(&message.destination_public_key) != addr_payload
+    {
+        return Err(ArchiveError::ForeignMessage(index));
+    }
+    Ok(source_pubkey_hash)
+}
+
+/// Store an imported message, keeping its received time, and recording whether its payload was
+/// offloaded.
+///
+/// Returns `false` if the message was already present.
+fn import_message(
+    database: &Database,
+    addr_payload: &[u8],
+    source_pubkey_hash: &[u8],
+    message: Message,
+    namespace: u8,
+    offloaded: bool,
+) -> Result<bool, ArchiveError> {
+    let payload_digest = message.digest().unwrap(); // This is safe
+    if database
+        .get_message_by_digest(addr_payload, &payload_digest, namespace)?
+        .is_some()
+    {
+        return Ok(false);
+    }
+
+    // Imported messages expire as they would have on the exporting relay
+    let timestamp = message.received_time.max(0) as u64;
+    if message.ttl != 0 {
+        database.schedule_expiry(
+            addr_payload,
+            timestamp,
+            &payload_digest,
+            namespace,
+            timestamp.saturating_add(message.ttl),
+        )?;
+    }
+    if offloaded {
+        database.mark_offloaded(addr_payload, &payload_digest)?;
+    }
+    database.push_sequenced_message(
+        addr_payload,
+        timestamp,
+        &payload_digest,
+        source_pubkey_hash,
+        namespace,
+        |sequence| {
+            let message = Message {
+                sequence,
+                ..message
+            };
+
+            // Serialize and compress message for storage
+            let mut raw_message = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut raw_message).unwrap(); // This is safe
+            compression::pack_value(
+                &raw_message,
+                SETTINGS.compression.threshold,
+                SETTINGS.compression.level,
+            )
+            .into_owned()
+        },
+    )?;
+    Ok(true)
+}
+
+/// Handles import requests, storing the contents of an archive exported from another relay.
+///
+/// The whole archive is validated before anything is stored. Messages already present are
+/// skipped and the profile is only imported if the address has none.
+pub async fn post_import(
+    addr: Address,
+    raw_archive: Bytes,
+    database: Database,
+    payload_store: Option<PayloadStore>,
+    schema: ProfileSchema,
+) -> Result<Response<Body>, ArchiveError> {
+    let addr_payload = addr.as_body().to_vec();
+
+    // Decode and validate
+    let items = decode_archive(&raw_archive)?;
+    let mut opt_raw_profile = None;
+    let mut messages = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        let namespace = match item.namespace.as_str() {
+            PROFILE_NAME => {
+                opt_raw_profile = Some(item.profile);
+                continue;
+            }
+            MESSAGES_NAME => MESSAGE_NAMESPACE,
+            FEEDS_NAME => FEED_NAMESPACE,
+            _ => return Err(ArchiveError::UnknownNamespace(index, item.namespace)),
+        };
+        let message = item.message.ok_or(ArchiveError::MissingMessage(index))?;
+        let source_pubkey_hash = validate_message(index, &addr_payload, &message)?;
+        messages.push((namespace, source_pubkey_hash, message));
+    }
+
+    // Import profile
+    let mut summary = ImportSummary::default();
+    if let Some(raw_profile) = opt_raw_profile {
+        let profile_database = database.clone();
+        let profile_addr = addr_payload.clone();
+        let existing_profile =
+            task::spawn_blocking(move || profile_database.get_raw_profile(&profile_addr))
+                .await
+                .unwrap()?; // Unrecoverable
+        if existing_profile.is_none() {
+            store_profile(
+                addr.clone(),
+                Bytes::from(raw_profile),
+                database.clone(),
+                schema,
+            )
+            .await
+            .map_err(ArchiveError::Profile)?;
+            summary.profile = true;
+        }
+    }
+
+    // Import messages and feed items
+    for (namespace, source_pubkey_hash, mut message) in messages {
+        // Move large payloads to the payload store
+        let offloaded = match &payload_store {
+            Some(payload_store) => payload_store
+                .offload(&mut message)
+                .await
+                .map_err(ArchiveError::PayloadStore)?,
+            None => false,
+        };
+
+        let message_database = database.clone();
+        let message_addr = addr_payload.clone();
+        let imported = task::spawn_blocking(move || {
+            import_message(
+                &message_database,
+                &message_addr,
+                &source_pubkey_hash,
+                message,
+                namespace,
+                offloaded,
+            )
+        })
+        .await
+        .unwrap()?; // Unrecoverable
+        match (imported, namespace) {
+            (false, _) => summary.skipped += 1,
+            (true, FEED_NAMESPACE) => summary.feeds += 1,
+            (true, _) => summary.messages += 1,
+        }
+    }
+
+    // Serialize summary
+    let mut raw_summary = Vec::with_capacity(summary.encoded_len());
+    summary.encode(&mut raw_summary).unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder().body(Body::from(raw_summary)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_exports() {
+        let limiter = ExportLimiter::new(Duration::from_secs(60));
+        assert!(limiter.check(&[1; 20]).is_ok());
+        assert!(matches!(
+            limiter.check(&[1; 20]),
+            Err(ArchiveError::TooSoon(_))
+        ));
+        assert!(limiter.check(&[2; 20]).is_ok());
+
+        let limiter = ExportLimiter::new(Duration::from_millis(0));
+        assert!(limiter.check(&[1; 20]).is_ok());
+        assert!(limiter.check(&[1; 20]).is_ok());
+    }
+
+    #[test]
+    fn import_exported_messages() {
+        let source = Database::try_new("./test_dbs/export_archive").unwrap();
+        let destination = Database::try_new("./test_dbs/import_archive").unwrap();
+        let addr_payload = [3; 20];
+
+        // Walk an inbox a page at a time, archiving each message
+        for received_time in 1..4u64 {
+            let message = Message {
+                received_time: received_time as i64,
+                payload: vec![received_time as u8],
+                ..Default::default()
+            };
+            let payload_digest = message.digest().unwrap();
+            let mut raw_message = Vec::with_capacity(message.encoded_len());
+            message.encode(&mut raw_message).unwrap();
+            source
+                .push_message(
+                    &addr_payload,
+                    received_time,
+                    &raw_message,
+                    &payload_digest,
+                    MESSAGE_NAMESPACE,
+                )
+                .unwrap();
+        }
+        let mut raw_archive = Vec::new();
+        let mut opt_after_key: Option<Vec<u8>> = None;
+        loop {
+            let (messages, opt_last_key) = source.get_messages_after_key(
+                &addr_payload,
+                MESSAGE_NAMESPACE,
+                opt_after_key.as_deref(),
+                2,
+            );
+            for message in messages {
+                let item = ArchiveItem {
+                    namespace: MESSAGES_NAME.to_string(),
+                    message: Some(message),
+                    ..Default::default()
+                };
+                raw_archive.extend_from_slice(&encode_item(&item));
+            }
+            opt_after_key = match opt_last_key {
+                Some(some) => Some(some),
+                None => break,
+            };
+        }
+        let items = decode_archive(&raw_archive).unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(decode_archive(&raw_archive[..raw_archive.len() - 1]).is_err());
+
+        // Import keeps received times and skips messages already present
+        for _ in 0..2 {
+            for item in &items {
+                let message = item.message.clone().unwrap();
+                import_message(
+                    &destination,
+                    &addr_payload,
+                    &[4; 20],
+                    message,
+                    MESSAGE_NAMESPACE,
+                    false,
+                )
+                .unwrap();
+            }
+        }
+        let (messages, _) =
+            destination.get_messages_after_key(&addr_payload, MESSAGE_NAMESPACE, None, 10);
+        let times: Vec<i64> = messages.iter().map(|m| m.received_time).collect();
+        assert_eq!(times, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn offloaded_imports() {
+        let database = Database::try_new("./test_dbs/offloaded_imports").unwrap();
+        let addr_payload = [5; 20];
+
+        // Archives may not claim a payload was offloaded
+        let stub = Message {
+            payload_digest: vec![6; 32],
+            payload_size: 1024,
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_message(0, &addr_payload, &stub),
+            Err(ArchiveError::MissingPayload(0))
+        ));
+
+        // Payloads are only marked offloaded by the importing relay
+        import_message(
+            &database,
+            &addr_payload,
+            &[4; 20],
+            stub,
+            MESSAGE_NAMESPACE,
+            true,
+        )
+        .unwrap();
+        assert!(database.is_offloaded(&addr_payload, &[6; 32]).unwrap());
+        assert!(!database.is_offloaded(&[4; 20], &[6; 32]).unwrap());
+    }
+}
@@ -0,0 +1,214 @@
+use std::io::Cursor;
+
+use bitcoincash_addr::Address;
+use cashweb::{auth_wrapper::AuthWrapper, relay::Profile};
+use image::{imageops::FilterType, io::Reader, ImageFormat, ImageOutputFormat};
+use prost::Message as _;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::task;
+use warp::{
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        Response,
+    },
+    hyper::Body,
+    reject::Reject,
+};
+
+use crate::{
+    db::Database,
+    net::{cache_control, ToResponse},
+    SETTINGS,
+};
+
+/// The kind of profile entry holding an avatar image.
+pub const AVATAR_KIND: &str = "avatar";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AvatarError {
+    #[error("unsupported image format")]
+    UnsupportedFormat,
+    #[error("image too large: {0} > {1}")]
+    TooLarge(usize, usize),
+    #[error("image dimensions too large: {0}x{1} > {2}")]
+    DimensionsTooLarge(u32, u32, u32),
+    #[error("failed to decode image: {0}")]
+    Decode(String),
+}
+
+/// Sniff the format of an image, returning it if supported.
+fn sniff_format(image: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(image) {
+        Ok(format @ ImageFormat::Png)
+        | Ok(format @ ImageFormat::Jpeg)
+        | Ok(format @ ImageFormat::Gif) => Some(format),
+        _ => None,
+    }
+}
+
+fn mime_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        _ => "image/png",
+    }
+}
+
+/// Validate an avatar image, checking its format, size and dimensions without decoding it.
+pub fn validate_avatar(
+    image: &[u8],
+    max_size: usize,
+    max_dimension: u32,
+) -> Result<ImageFormat, AvatarError> {
+    if image.len() > max_size {
+        return Err(AvatarError::TooLarge(image.len(), max_size));
+    }
+    let format = sniff_format(image).ok_or(AvatarError::UnsupportedFormat)?;
+    let (width, height) = Reader::with_format(Cursor::new(image), format)
+        .into_dimensions()
+        .map_err(|err| AvatarError::Decode(err.to_string()))?;
+    if width > max_dimension || height > max_dimension {
+        return Err(AvatarError::DimensionsTooLarge(
+            width,
+            height,
+            max_dimension,
+        ));
+    }
+    Ok(format)
+}
+
+/// Generate PNG thumbnails of an avatar, fitting within squares of the given sizes.
+pub fn generate_thumbnails(
+    image: &[u8],
+    sizes: &[u32],
+) -> Result<Vec<(u32, Vec<u8>)>, AvatarError> {
+    if sizes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let image =
+        image::load_from_memory(image).map_err(|err| AvatarError::Decode(err.to_string()))?;
+    sizes
+        .iter()
+        .map(|size| {
+            let thumbnail = image.resize(*size, *size, FilterType::Triangle);
+            let mut raw_thumbnail = Vec::new();
+            thumbnail
+                .write_to(&mut raw_thumbnail, ImageOutputFormat::Png)
+                .map_err(|err| AvatarError::Decode(err.to_string()))?;
+            Ok((*size, raw_thumbnail))
+        })
+        .collect()
+}
+
+/// Find the avatar of a profile.
+pub fn find_avatar(profile: &Profile) -> Option<&[u8]> {
+    profile
+        .entries
+        .iter()
+        .find(|entry| entry.kind == AVATAR_KIND)
+        .map(|entry| &entry.body[..])
+}
+
+#[derive(Debug, Error)]
+pub enum GetAvatarError {
+    #[error("not found")]
+    NotFound,
+    #[error("failed to read from database: {0}")]
+    Database(#[from] rocksdb::Error),
+}
+
+impl Reject for GetAvatarError {}
+
+impl ToResponse for GetAvatarError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::NotFound => 404,
+            Self::Database(_) => 500,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvatarQuery {
+    pub size: Option<u32>,
+}
+
+/// Handles avatar GET requests, serving the original avatar or, if a size is given, a thumbnail.
+pub async fn get_avatar(
+    addr: Address,
+    query: AvatarQuery,
+    database: Database,
+) -> Result<Response<Body>, GetAvatarError> {
+    let builder =
+        Response::builder().header(CACHE_CONTROL, cache_control(SETTINGS.cache.profile_max_age));
+
+    // Serve thumbnail
+    if let Some(size) = query.size {
+        let thumbnail = task::spawn_blocking(move || database.get_thumbnail(addr.as_body(), size))
+            .await
+            .unwrap()?
+            .ok_or(GetAvatarError::NotFound)?;
+        return Ok(builder
+            .header(CONTENT_TYPE, mime_type(ImageFormat::Png))
+            .body(Body::from(thumbnail))
+            .unwrap());
+    }
+
+    // Serve original
+    let raw_profile = task::spawn_blocking(move || database.get_raw_profile(addr.as_body()))
+        .await
+        .unwrap()?
+        .ok_or(GetAvatarError::NotFound)?;
+    let auth_wrapper = AuthWrapper::decode(&raw_profile[..]).unwrap(); // This panics if stored bytes are malformed
+    let profile = Profile::decode(&auth_wrapper.payload[..]).unwrap(); // This panics if stored bytes are malformed
+    let avatar = find_avatar(&profile).ok_or(GetAvatarError::NotFound)?;
+    let format = sniff_format(avatar).ok_or(GetAvatarError::NotFound)?;
+    Ok(builder
+        .header(CONTENT_TYPE, mime_type(format))
+        .body(Body::from(avatar.to_vec()))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        let mut raw_image = Vec::new();
+        image
+            .write_to(&mut raw_image, ImageOutputFormat::Png)
+            .unwrap();
+        raw_image
+    }
+
+    #[test]
+    fn validate_and_resize() {
+        let avatar = png(200, 100);
+        assert_eq!(
+            validate_avatar(&avatar, 1_000_000, 256),
+            Ok(ImageFormat::Png)
+        );
+        assert_eq!(
+            validate_avatar(&avatar, 1_000_000, 150),
+            Err(AvatarError::DimensionsTooLarge(200, 100, 150))
+        );
+        assert_eq!(
+            validate_avatar(&avatar, 10, 256),
+            Err(AvatarError::TooLarge(avatar.len(), 10))
+        );
+        assert_eq!(
+            validate_avatar(b"not an image", 1_000_000, 256),
+            Err(AvatarError::UnsupportedFormat)
+        );
+
+        // Thumbnails preserve the aspect ratio
+        let thumbnails = generate_thumbnails(&avatar, &[64]).unwrap();
+        assert_eq!(thumbnails[0].0, 64);
+        let thumbnail = image::load_from_memory(&thumbnails[0].1).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (64, 32));
+    }
+}
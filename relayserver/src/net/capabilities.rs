@@ -0,0 +1,52 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use cashweb::relay::Capabilities;
+use prost::Message as _;
+use warp::{http::Response, hyper::Body};
+
+use crate::SETTINGS;
+
+/// List the optional features enabled, both in the settings and at compile time.
+fn features() -> Vec<String> {
+    let mut features = Vec::new();
+    if SETTINGS.features.feeds {
+        features.push("feeds");
+    }
+    if SETTINGS.features.payloads {
+        features.push("payloads");
+    }
+    if SETTINGS.features.websocket {
+        features.push("websocket");
+    }
+    if SETTINGS.notifications.enabled {
+        features.push("notifications");
+    }
+    if SETTINGS.payload_storage.bucket.is_some() {
+        features.push("payload_storage");
+    }
+    #[cfg(feature = "monitoring")]
+    features.push("monitoring");
+    features.into_iter().map(str::to_string).collect()
+}
+
+/// Construct the serialized capabilities of the server.
+pub fn construct_capabilities() -> Bytes {
+    let capabilities = Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        network: SETTINGS.network.into(),
+        stamp_derivation_path: SETTINGS.stamps.derivation_path.clone(),
+        accept_burn_stamps: SETTINGS.stamps.accept_burns,
+        features: features(),
+    };
+    let mut raw_capabilities = Vec::with_capacity(capabilities.encoded_len());
+    capabilities.encode(&mut raw_capabilities).unwrap(); // This is safe
+    Bytes::from(raw_capabilities)
+}
+
+/// Handles capabilities GET and OPTIONS requests.
+pub async fn get_capabilities(raw_capabilities: Bytes) -> Result<Response<Body>, Infallible> {
+    Ok(Response::builder()
+        .body(Body::from(raw_capabilities))
+        .unwrap())
+}
@@ -0,0 +1,86 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use tracing::error;
+use warp::{
+    http::{
+        header::{CONTENT_ENCODING, CONTENT_LENGTH, VARY},
+        Response,
+    },
+    hyper::Body,
+    reject::Reject,
+};
+
+use crate::{
+    compression::{self, CompressionError, Encoding},
+    net::ToResponse,
+    SETTINGS,
+};
+
+impl Reject for CompressionError {}
+
+impl ToResponse for CompressionError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::Unsupported(_) => 415,
+            Self::TooLarge => 413,
+            Self::Io(_) => 400,
+        }
+    }
+}
+
+/// Decompress a request body according to its `Content-Encoding` header.
+pub async fn decode_body(
+    content_encoding: Option<String>,
+    body: Bytes,
+) -> Result<Bytes, CompressionError> {
+    let encoding = match content_encoding {
+        Some(some) => Encoding::from_header(&some)?,
+        None => return Ok(body),
+    };
+    if encoding == Encoding::Identity {
+        return Ok(body);
+    }
+    compression::decompress(encoding, &body, SETTINGS.limits.message_size).map(Bytes::from)
+}
+
+/// Compress a response body according to the `Accept-Encoding` header of the request.
+pub async fn encode_response(
+    response: Response<Body>,
+    accept_encoding: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let threshold = SETTINGS.compression.threshold;
+    let encoding = accept_encoding
+        .as_deref()
+        .map(Encoding::negotiate)
+        .unwrap_or(Encoding::Identity);
+    if threshold == 0
+        || encoding == Encoding::Identity
+        || !response.status().is_success()
+        || response.headers().contains_key(CONTENT_ENCODING)
+    {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let raw_body = match warp::hyper::body::to_bytes(body).await {
+        Ok(ok) => ok,
+        Err(err) => {
+            error!(message = "failed to buffer response body", error = %err);
+            return Ok(Response::builder().status(500).body(Body::empty()).unwrap());
+        }
+    };
+    parts
+        .headers
+        .insert(VARY, "accept-encoding".parse().unwrap()); // This is safe
+    if raw_body.len() < threshold {
+        return Ok(Response::from_parts(parts, Body::from(raw_body)));
+    }
+
+    let compressed = compression::compress(encoding, &raw_body, SETTINGS.compression.level);
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, encoding.as_str().parse().unwrap()); // This is safe
+    Ok(Response::from_parts(parts, Body::from(compressed)))
+}
@@ -0,0 +1,50 @@
+use thiserror::Error;
+use warp::{reject::Reject, Filter, Rejection};
+
+use crate::net::ToResponse;
+
+/// Error returned when a request targets a feature disabled in the settings.
+#[derive(Debug, Error)]
+#[error("{0} are disabled")]
+pub struct FeatureDisabled(pub &'static str);
+
+impl Reject for FeatureDisabled {}
+
+impl ToResponse for FeatureDisabled {
+    fn to_status(&self) -> u16 {
+        501
+    }
+}
+
+/// Filter rejecting every request with [`FeatureDisabled`] unless the feature is enabled.
+pub fn require_feature(
+    feature: &'static str,
+    enabled: bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(FeatureDisabled(feature)))
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn disabled_feature() {
+        let filter = require_feature("feeds", true);
+        assert!(warp::test::request().filter(&filter).await.is_ok());
+
+        let filter = require_feature("feeds", false);
+        let err = warp::test::request().filter(&filter).await.unwrap_err();
+        let disabled = err.find::<FeatureDisabled>().unwrap();
+        assert_eq!(disabled.to_status(), 501);
+        assert_eq!(disabled.to_string(), "feeds are disabled");
+    }
+}
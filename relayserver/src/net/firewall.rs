@@ -0,0 +1,387 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use thiserror::Error;
+use tracing::warn;
+use warp::{http::Response, hyper::Body, reject::Reject, Reply};
+
+use crate::net::ToResponse;
+
+/// Number of tracked addresses after which stale entries are pruned.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Header in which reverse proxies give the addresses a request was forwarded for.
+pub const FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Debug, Error)]
+pub enum CidrParseError {
+    #[error("invalid IP address: {0}")]
+    Address(std::net::AddrParseError),
+    #[error("invalid prefix length: {0}")]
+    PrefixLength(String),
+}
+
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8`.
+///
+/// A bare IP address is treated as a range containing only itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address_str, prefix_str) = match s.find('/') {
+            Some(index) => (&s[..index], Some(&s[index + 1..])),
+            None => (s, None),
+        };
+        let address = IpAddr::from_str(address_str.trim()).map_err(CidrParseError::Address)?;
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_str {
+            Some(prefix_str) => match prefix_str.trim().parse::<u8>() {
+                Ok(prefix_len) if prefix_len <= max_len => prefix_len,
+                _ => return Err(CidrParseError::PrefixLength(prefix_str.to_string())),
+            },
+            None => max_len,
+        };
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl Cidr {
+    /// Checks whether the range contains an IP address.
+    ///
+    /// IPv4-mapped IPv6 addresses are compared as IPv4 addresses.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+                IpAddr::V4(v6.to_ipv4().unwrap()) // This is safe
+            }
+            _ => *ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FirewallError {
+    #[error("address denied")]
+    Denied,
+    #[error("address temporarily banned")]
+    Banned,
+}
+
+impl Reject for FirewallError {}
+
+impl ToResponse for FirewallError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::Denied => 403,
+            Self::Banned => 429,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Strikes {
+    count: u32,
+    window_start: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// IP allow/deny lists and automatic temporary bans of addresses making repeated bad requests.
+#[derive(Clone, Debug)]
+pub struct Firewall {
+    allow: Arc<Vec<Cidr>>,
+    deny: Arc<Vec<Cidr>>,
+    trusted_proxies: Arc<Vec<Cidr>>,
+    strikes: Arc<DashMap<IpAddr, Strikes>>,
+    max_strikes: u32,
+    strike_window: Duration,
+    ban_duration: Duration,
+}
+
+impl Firewall {
+    /// Construct a firewall.
+    ///
+    /// If `allow` is empty then all addresses not denied are allowed. A `max_strikes` of zero
+    /// disables automatic bans.
+    pub fn new(
+        allow: Vec<Cidr>,
+        deny: Vec<Cidr>,
+        max_strikes: u32,
+        strike_window: Duration,
+        ban_duration: Duration,
+    ) -> Self {
+        Self {
+            allow: Arc::new(allow),
+            deny: Arc::new(deny),
+            trusted_proxies: Default::default(),
+            strikes: Default::default(),
+            max_strikes,
+            strike_window,
+            ban_duration,
+        }
+    }
+
+    /// Trust the `X-Forwarded-For` header of requests from these reverse proxies.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<Cidr>) -> Self {
+        self.trusted_proxies = Arc::new(trusted_proxies);
+        self
+    }
+
+    /// Find the address of the client making a request.
+    ///
+    /// If the request came through trusted proxies then the last address they were forwarded for,
+    /// which is not itself a trusted proxy, is taken. Otherwise the remote address is taken.
+    pub fn client_ip(
+        &self,
+        remote: Option<SocketAddr>,
+        forwarded_for: Option<&str>,
+    ) -> Option<IpAddr> {
+        let mut ip = remote?.ip();
+        let forwarded_for = match forwarded_for {
+            Some(some) => some,
+            None => return Some(ip),
+        };
+        for forwarded_ip in forwarded_for.rsplit(',') {
+            if !self.trusted_proxies.iter().any(|cidr| cidr.contains(&ip)) {
+                break;
+            }
+            ip = match IpAddr::from_str(forwarded_ip.trim()) {
+                Ok(ok) => ok,
+                Err(_) => break,
+            };
+        }
+        Some(ip)
+    }
+
+    /// Checks whether an address may make requests.
+    pub fn check(&self, ip: Option<IpAddr>) -> Result<(), FirewallError> {
+        let ip = match ip {
+            Some(some) => some,
+            None => return Ok(()),
+        };
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|cidr| cidr.contains(&ip)) {
+            return Err(FirewallError::Denied);
+        }
+        if self.deny.iter().any(|cidr| cidr.contains(&ip)) {
+            return Err(FirewallError::Denied);
+        }
+
+        if let Some(strikes) = self.strikes.get(&ip) {
+            if let Some(banned_until) = strikes.banned_until {
+                if Instant::now() < banned_until {
+                    return Err(FirewallError::Banned);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the status of a response to an address, banning it after too many malformed,
+    /// oversized or rate limited requests.
+    ///
+    /// Other client errors, such as challenges and missing resources, arise in normal operation
+    /// and are not counted.
+    pub fn record(&self, ip: IpAddr, status: u16) {
+        if self.max_strikes == 0 || !matches!(status, 400 | 413 | 429) {
+            return;
+        }
+
+        let now = Instant::now();
+        {
+            let mut strikes = self.strikes.entry(ip).or_insert_with(|| Strikes {
+                count: 0,
+                window_start: now,
+                banned_until: None,
+            });
+            if strikes
+                .banned_until
+                .map_or(false, |banned_until| now < banned_until)
+            {
+                return;
+            }
+            if now.duration_since(strikes.window_start) > self.strike_window {
+                strikes.count = 0;
+                strikes.window_start = now;
+                strikes.banned_until = None;
+            }
+            strikes.count += 1;
+            if strikes.count >= self.max_strikes {
+                warn!(message = "banning address", address = %ip);
+                strikes.count = 0;
+                strikes.banned_until = Some(now + self.ban_duration);
+            }
+        }
+
+        // Prune stale entries
+        if self.strikes.len() > PRUNE_THRESHOLD {
+            let strike_window = self.strike_window;
+            self.strikes.retain(|_, strikes| {
+                strikes
+                    .banned_until
+                    .map_or(false, |banned_until| now < banned_until)
+                    || now.duration_since(strikes.window_start) <= strike_window
+            });
+        }
+    }
+}
+
+/// Record the status of a reply against the client address.
+pub fn record_response<R: Reply>(
+    reply: R,
+    remote: Option<SocketAddr>,
+    forwarded_for: Option<String>,
+    firewall: Firewall,
+) -> Response<Body> {
+    let response = reply.into_response();
+    if let Some(ip) = firewall.client_ip(remote, forwarded_for.as_deref()) {
+        firewall.record(ip, response.status().as_u16());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_contains() {
+        let cidr: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(cidr.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains(&"11.0.0.0".parse().unwrap()));
+
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!cidr.contains(&"2001:db9::1".parse().unwrap()));
+
+        let cidr: Cidr = "127.0.0.1".parse().unwrap();
+        assert!(cidr.contains(&"127.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"127.0.0.2".parse().unwrap()));
+
+        let cidr: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(cidr.contains(&"1.2.3.4".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let firewall = Firewall::new(
+            vec!["10.0.0.0/8".parse().unwrap()],
+            vec!["10.0.0.1".parse().unwrap()],
+            0,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        assert!(firewall.check(Some("10.0.0.2".parse().unwrap())).is_ok());
+        assert!(matches!(
+            firewall.check(Some("10.0.0.1".parse().unwrap())),
+            Err(FirewallError::Denied)
+        ));
+        assert!(matches!(
+            firewall.check(Some("192.168.0.1".parse().unwrap())),
+            Err(FirewallError::Denied)
+        ));
+        assert!(firewall.check(None).is_ok());
+    }
+
+    #[test]
+    fn temporary_ban() {
+        let firewall = Firewall::new(
+            vec![],
+            vec![],
+            3,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+
+        // Challenges, missing resources and successes are not counted
+        for _ in 0..5 {
+            firewall.record(ip, 402);
+            firewall.record(ip, 401);
+            firewall.record(ip, 404);
+            firewall.record(ip, 200);
+        }
+        assert!(firewall.check(Some(ip)).is_ok());
+
+        firewall.record(ip, 400);
+        firewall.record(ip, 413);
+        assert!(firewall.check(Some(ip)).is_ok());
+        firewall.record(ip, 429);
+        assert!(matches!(
+            firewall.check(Some(ip)),
+            Err(FirewallError::Banned)
+        ));
+
+        // Other addresses are unaffected
+        assert!(firewall.check(Some("1.2.3.5".parse().unwrap())).is_ok());
+    }
+
+    #[test]
+    fn forwarded_clients() {
+        let firewall = Firewall::new(
+            vec![],
+            vec![],
+            0,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        let proxy: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        let forwarded_for = Some("1.2.3.4, 5.6.7.8, 10.0.0.2");
+
+        // Forwarded addresses are ignored unless the proxy is trusted
+        assert_eq!(
+            firewall.client_ip(Some(proxy), forwarded_for),
+            Some(proxy.ip())
+        );
+
+        // Forwarded addresses are followed back through trusted proxies only
+        let firewall = firewall.with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert_eq!(
+            firewall.client_ip(Some(proxy), forwarded_for),
+            Some("5.6.7.8".parse().unwrap())
+        );
+        assert_eq!(firewall.client_ip(Some(proxy), None), Some(proxy.ip()));
+        assert_eq!(
+            firewall.client_ip(Some(proxy), Some("spoofed")),
+            Some(proxy.ip())
+        );
+        let client: SocketAddr = "1.2.3.4:1234".parse().unwrap();
+        assert_eq!(
+            firewall.client_ip(Some(client), forwarded_for),
+            Some(client.ip())
+        );
+        assert_eq!(firewall.client_ip(None, forwarded_for), None);
+    }
+}
@@ -1,12 +1,13 @@
 use std::{
+    cmp::Reverse,
     convert::TryFrom,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use bitcoincash_addr::Address;
+use bitcoincash_addr::{Address, HashType, Scheme};
 use bytes::Bytes;
 use cashweb::{
-    bitcoin_client::{BitcoinClient, BitcoinClientHTTP, NodeError},
+    bitcoin_client::{BitcoinClient, ChainBackend, FailoverClient, NodeError},
     relay::{self, stamp::StampError},
 };
 use futures::future;
@@ -16,22 +17,50 @@ use ring::digest::{digest, SHA256};
 use ripemd160::{Digest, Ripemd160};
 use serde::Deserialize;
 use thiserror::Error;
-use tracing::warn;
+use tokio::{
+    task,
+    time::{interval, Duration},
+};
+use tracing::{error, info, warn};
 use warp::{http::Response, hyper::Body, reject::Reject};
 
 use crate::{
+    compression,
     db::{self, Database},
-    net::{ws::MessageBus, ToResponse},
+    net::{
+        ws::{Broadcast, MessageBus},
+        ToResponse,
+    },
+    notifications::{MessageEvent, Notifier},
+    payloads::{PayloadStore, PayloadStoreError},
     SETTINGS,
 };
 
+/// Header giving the TTL, in milliseconds, of the messages put which do not set their own.
+pub const MESSAGE_TTL: &str = "Message-TTL";
+
 #[derive(Debug, Deserialize)]
 pub struct Query {
     start_digest: Option<String>,
     end_digest: Option<String>,
     start_time: Option<u64>,
     end_time: Option<u64>,
+    after_sequence: Option<u64>,
+    sender: Option<String>,
     digest: Option<String>,
+    dry_run: Option<bool>,
+    limit: Option<usize>,
+    order: Option<Order>,
+}
+
+/// Order of the messages within a page.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// Oldest first.
+    TimeAsc,
+    /// Highest stamp value first, oldest first among messages of equal value.
+    StampDesc,
 }
 
 #[derive(Debug, Error)]
@@ -46,6 +75,12 @@ pub enum GetMessageError {
     NotFound,
     #[error("both start time and digest given")]
     StartBothGiven,
+    #[error("both sequence and start given")]
+    SequenceAndStartGiven,
+    #[error("failed to decode sender: {0}")]
+    SenderMalformed(FromHexError),
+    #[error("sender given with digest or sequence")]
+    SenderConflict,
     #[error("failed to decode start digest: {0}")]
     StartDigestMalformed(FromHexError),
     #[error("start digest not found")]
@@ -58,6 +93,12 @@ pub enum GetMessageError {
     EndDigestMalformed(FromHexError),
     #[error("end digest not found")]
     EndDigestNotFound,
+    #[error("failed to decode search: {0}")]
+    SearchDecode(prost::DecodeError),
+    #[error("unknown namespace: {0}")]
+    UnknownNamespace(String),
+    #[error("failed to get payload: {0}")]
+    PayloadStore(PayloadStoreError),
 }
 
 impl From<rocksdb::Error> for GetMessageError {
@@ -73,6 +114,8 @@ impl ToResponse for GetMessageError {
         match self {
             Self::DB(_) => 500,
             Self::NotFound => 404,
+            Self::PayloadStore(PayloadStoreError::NotFound) => 404,
+            Self::PayloadStore(_) => 500,
             _ => 400,
         }
     }
@@ -126,10 +169,74 @@ fn construct_prefixes(
     Ok((start_prefix, end_prefix))
 }
 
+/// Get the page of messages selected by the query, in the order requested.
+///
+/// The bounds of the page are those of the selected messages, regardless of order.
+fn get_message_page(
+    addr_payload: &[u8],
+    query: Query,
+    database: &Database,
+    namespace: u8,
+) -> Result<relay::MessagePage, GetMessageError> {
+    let order = query.order.unwrap_or(Order::TimeAsc);
+    let mut message_page = select_message_page(addr_payload, query, database, namespace)?;
+    if order == Order::StampDesc {
+        message_page
+            .messages
+            .sort_by_key(|message| Reverse(message.stamp_value));
+    }
+    Ok(message_page)
+}
+
+/// Select the page of messages, oldest first, either by sender, by sequence or by range.
+fn select_message_page(
+    addr_payload: &[u8],
+    query: Query,
+    database: &Database,
+    namespace: u8,
+) -> Result<relay::MessagePage, GetMessageError> {
+    if let Some(sender_hex) = query.sender {
+        if query.start_digest.is_some()
+            || query.end_digest.is_some()
+            || query.after_sequence.is_some()
+        {
+            return Err(GetMessageError::SenderConflict);
+        }
+        let sender_pubkey_hash =
+            hex::decode(sender_hex).map_err(GetMessageError::SenderMalformed)?;
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let message_page = database.get_messages_by_sender(
+            addr_payload,
+            namespace,
+            &sender_pubkey_hash,
+            query.start_time.unwrap_or(0),
+            query.end_time,
+            limit,
+        )?;
+        return Ok(message_page);
+    }
+
+    if let Some(after_sequence) = query.after_sequence {
+        if query.start_time.is_some() || query.start_digest.is_some() {
+            return Err(GetMessageError::SequenceAndStartGiven);
+        }
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let message_page =
+            database.get_messages_after_sequence(addr_payload, namespace, after_sequence, limit)?;
+        return Ok(message_page);
+    }
+
+    let (start_prefix, end_prefix) = construct_prefixes(addr_payload, query, database, namespace)?;
+    let message_page =
+        database.get_messages_range(&start_prefix, end_prefix.as_ref().map(|v| &v[..]))?;
+    Ok(message_page)
+}
+
 pub async fn get_payloads(
     addr: Address,
     query: Query,
     database: Database,
+    payload_store: Option<PayloadStore>,
     namespace: u8,
 ) -> Result<Response<Body>, GetMessageError> {
     // Extract address payload
@@ -142,15 +249,25 @@ pub async fn get_payloads(
             .get_message_by_digest(address_payload, &raw_digest[..], namespace)?
             .ok_or(GetMessageError::NotFound)?;
         let message = relay::Message::decode(&raw_message[..]).unwrap(); // This is safe
+
+        // If payload was offloaded then stream it from the payload store
+        if message.payload.is_empty()
+            && database.is_offloaded(address_payload, &message.payload_digest)?
+        {
+            if let Some(payload_store) = payload_store {
+                let body = payload_store
+                    .get(&message.payload_digest)
+                    .await
+                    .map_err(GetMessageError::PayloadStore)?;
+                return Ok(Response::builder().body(body).unwrap());
+            }
+        }
         return Ok(Response::builder()
             .body(Body::from(message.payload))
             .unwrap());
     }
 
-    let (start_prefix, end_prefix) =
-        construct_prefixes(address_payload, query, &database, namespace)?;
-    let message_page =
-        database.get_messages_range(&start_prefix, end_prefix.as_ref().map(|v| &v[..]))?;
+    let message_page = get_message_page(address_payload, query, &database, namespace)?;
     let payload_page = message_page.into_payload_page();
 
     // Serialize messages
@@ -181,10 +298,7 @@ pub async fn get_messages(
         return Ok(Response::builder().body(Body::from(message)).unwrap());
     }
 
-    let (start_prefix, end_prefix) =
-        construct_prefixes(address_payload, query, &database, namespace)?;
-    let message_set =
-        database.get_messages_range(&start_prefix, end_prefix.as_ref().map(|v| &v[..]))?;
+    let message_set = get_message_page(address_payload, query, &database, namespace)?;
 
     // Serialize messages
     let mut raw_message_page = Vec::with_capacity(message_set.encoded_len());
@@ -196,30 +310,301 @@ pub async fn get_messages(
         .unwrap()) // TODO: Headers
 }
 
+/// Checks whether a message matches the sender and stamp value constraints of a search.
+fn search_matches(search: &relay::MessageSearch, message: &relay::Message) -> bool {
+    if !search.source_pubkey_hash.is_empty() {
+        let source_pubkey_hash =
+            Ripemd160::digest(digest(&SHA256, &message.source_public_key).as_ref());
+        if source_pubkey_hash[..] != search.source_pubkey_hash[..] {
+            return false;
+        }
+    }
+    if search.min_stamp_value != 0 {
+        let stamp_value = message
+            .stamp
+            .as_ref()
+            .and_then(|stamp| stamp.value().ok())
+            .unwrap_or(0);
+        if stamp_value < search.min_stamp_value {
+            return false;
+        }
+    }
+    true
+}
+
+pub async fn search_messages(
+    addr: Address,
+    search_raw: Bytes,
+    database: Database,
+) -> Result<Response<Body>, GetMessageError> {
+    // Decode search
+    let search =
+        relay::MessageSearch::decode(&search_raw[..]).map_err(GetMessageError::SearchDecode)?;
+    let namespace = match search.namespace.as_str() {
+        "" | "messages" => db::MESSAGE_NAMESPACE,
+        "feeds" => db::FEED_NAMESPACE,
+        _ => return Err(GetMessageError::UnknownNamespace(search.namespace)),
+    };
+    let limit = match search.limit as usize {
+        0 => SETTINGS.limits.search_results,
+        limit => limit.min(SETTINGS.limits.search_results),
+    };
+
+    // Construct time range
+    let address_payload = addr.as_body();
+    let start_prefix = db::msg_prefix(address_payload, search.start_time.max(0) as u64, namespace);
+    let end_prefix = if search.end_time > 0 {
+        Some(db::msg_prefix(
+            address_payload,
+            search.end_time as u64,
+            namespace,
+        ))
+    } else {
+        None
+    };
+
+    let message_page = task::spawn_blocking(move || {
+        database.search_messages(
+            &start_prefix,
+            end_prefix.as_ref().map(|v| &v[..]),
+            limit,
+            |message| search_matches(&search, message),
+        )
+    })
+    .await
+    .unwrap(); // Unrecoverable
+
+    // Serialize messages
+    let mut raw_message_page = Vec::with_capacity(message_page.encoded_len());
+    message_page.encode(&mut raw_message_page).unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder()
+        .body(Body::from(raw_message_page))
+        .unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SummaryQuery {
+    read_time: Option<u64>,
+}
+
+pub async fn get_inbox_summary(
+    addr: Address,
+    query: SummaryQuery,
+    database: Database,
+) -> Result<Response<Body>, GetMessageError> {
+    // Extract address payload
+    let address_payload = addr.as_body();
+
+    // Messages received after the read time are unread
+    let read_time = query.read_time.unwrap_or(0);
+    let namespaces = [
+        ("messages", db::MESSAGE_NAMESPACE),
+        ("feeds", db::FEED_NAMESPACE),
+    ]
+    .iter()
+    .map(|(name, namespace)| {
+        let mut summary = database.summarize_namespace(address_payload, *namespace, read_time);
+        summary.namespace = name.to_string();
+        summary
+    })
+    .collect();
+    let summary = relay::InboxSummary { namespaces };
+
+    // Serialize summary
+    let mut raw_summary = Vec::with_capacity(summary.encoded_len());
+    summary.encode(&mut raw_summary).unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder().body(Body::from(raw_summary)).unwrap())
+}
+
+pub async fn get_conversations(
+    addr: Address,
+    database: Database,
+) -> Result<Response<Body>, GetMessageError> {
+    let conversations = task::spawn_blocking(move || {
+        database.get_conversations(addr.as_body(), db::MESSAGE_NAMESPACE)
+    })
+    .await
+    .unwrap()?; // Unrecoverable
+    let conversation_list = relay::ConversationList { conversations };
+
+    // Serialize conversations
+    let mut raw_conversation_list = Vec::with_capacity(conversation_list.encoded_len());
+    conversation_list
+        .encode(&mut raw_conversation_list)
+        .unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder()
+        .body(Body::from(raw_conversation_list))
+        .unwrap())
+}
+
 pub async fn remove_messages(
     addr: Address,
     query: Query,
     database: Database,
+    payload_store: Option<PayloadStore>,
     namespace: u8,
 ) -> Result<Response<Body>, GetMessageError> {
     // Convert address
     let address_payload = addr.as_body();
 
-    // If digest query then get single message
+    let dry_run = query.dry_run.unwrap_or(false);
+    let limit = query
+        .limit
+        .unwrap_or(SETTINGS.limits.removals)
+        .min(SETTINGS.limits.removals);
+
+    // Keep tombstones unless the grace period is disabled
+    let tombstone = SETTINGS.tombstones.grace_period != 0;
+
+    let mut summary = relay::RemovalSummary {
+        dry_run,
+        ..Default::default()
+    };
+
+    // If digest query then remove single message
     if let Some(digest) = query.digest {
         let raw_digest = hex::decode(digest).map_err(GetMessageError::DigestDecode)?;
-        database
-            .remove_message_by_digest(address_payload, &raw_digest[..], namespace)?
-            .ok_or(GetMessageError::NotFound)?;
-        return Ok(Response::builder().body(Body::empty()).unwrap());
+        if dry_run {
+            database
+                .get_message_by_digest(address_payload, &raw_digest[..], namespace)?
+                .ok_or(GetMessageError::NotFound)?;
+        } else {
+            let orphans = database
+                .remove_message_by_digest(address_payload, &raw_digest[..], namespace, tombstone)?
+                .ok_or(GetMessageError::NotFound)?;
+            if let Some(payload_store) = &payload_store {
+                payload_store.delete_orphans(&orphans).await;
+            }
+        }
+        summary.count = 1;
+    } else {
+        let (start_prefix, end_prefix) =
+            construct_prefixes(address_payload, query, &database, namespace)?;
+        let (count, truncated, orphans) = database.remove_messages_range(
+            &start_prefix,
+            end_prefix.as_ref().map(|v| &v[..]),
+            limit,
+            dry_run,
+            tombstone,
+        )?;
+        if let Some(payload_store) = &payload_store {
+            payload_store.delete_orphans(&orphans).await;
+        }
+        summary.count = count as u64;
+        summary.truncated = truncated;
     }
 
-    let (start_prefix, end_prefix) =
-        construct_prefixes(address_payload, query, &database, namespace)?;
-    database.remove_messages_range(&start_prefix, end_prefix.as_ref().map(|v| &v[..]))?;
+    // Serialize summary
+    let mut raw_summary = Vec::with_capacity(summary.encoded_len());
+    summary.encode(&mut raw_summary).unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder().body(Body::from(raw_summary)).unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreQuery {
+    digest: String,
+}
+
+/// Restore a removed message from its tombstone.
+pub async fn restore_message(
+    addr: Address,
+    query: RestoreQuery,
+    database: Database,
+    namespace: u8,
+) -> Result<Response<Body>, GetMessageError> {
+    let raw_digest = hex::decode(query.digest).map_err(GetMessageError::DigestDecode)?;
+    database
+        .restore_message_by_digest(addr.as_body(), &raw_digest[..], namespace)?
+        .ok_or(GetMessageError::NotFound)?;
 
     // Respond
-    Ok(Response::builder().body(Body::empty()).unwrap()) // TODO: Headers
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Periodically purge tombstones older than the grace period.
+pub async fn purge_tombstones(database: Database, payload_store: Option<PayloadStore>) {
+    let grace_period = SETTINGS.tombstones.grace_period;
+    let mut purge_interval = interval(Duration::from_millis(SETTINGS.tombstones.purge_interval));
+    loop {
+        purge_interval.tick().await;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let database = database.clone();
+        let result = task::spawn_blocking(move || {
+            database.purge_tombstones(now.saturating_sub(grace_period))
+        })
+        .await
+        .unwrap(); // Unrecoverable
+        match result {
+            Ok((0, _)) => (),
+            Ok((n_purged, orphans)) => {
+                info!(message = "purged tombstones", count = n_purged);
+                if let Some(payload_store) = &payload_store {
+                    payload_store.delete_orphans(&orphans).await;
+                }
+            }
+            Err(err) => error!(message = "failed to purge tombstones", error = %err),
+        }
+    }
+}
+
+/// Periodically remove messages whose TTL has elapsed.
+pub async fn prune_expired(database: Database, payload_store: Option<PayloadStore>) {
+    let mut prune_interval = interval(Duration::from_millis(SETTINGS.expiry.prune_interval));
+    loop {
+        prune_interval.tick().await;
+
+        let now = get_unix_now();
+        let database = database.clone();
+        let result = task::spawn_blocking(move || database.prune_expired(now))
+            .await
+            .unwrap(); // Unrecoverable
+        match result {
+            Ok((0, _)) => (),
+            Ok((n_pruned, orphans)) => {
+                info!(message = "pruned expired messages", count = n_pruned);
+                if let Some(payload_store) = &payload_store {
+                    payload_store.delete_orphans(&orphans).await;
+                }
+            }
+            Err(err) => error!(message = "failed to prune expired messages", error = %err),
+        }
+    }
+}
+
+/// Periodically remove dangling digest index entries.
+pub async fn scavenge_digests(database: Database) {
+    let mut scavenge_interval =
+        interval(Duration::from_millis(SETTINGS.tombstones.scavenge_interval));
+    loop {
+        scavenge_interval.tick().await;
+
+        let database = database.clone();
+        let result = task::spawn_blocking(move || database.scavenge_digests())
+            .await
+            .unwrap(); // Unrecoverable
+        match result {
+            Ok(0) => (),
+            Ok(n_orphans) => {
+                info!(message = "removed orphaned digests", count = n_orphans);
+
+                #[cfg(feature = "monitoring")]
+                crate::monitoring::DB_ORPHANED_DIGESTS_TOTAL.inc_by(n_orphans as u64);
+            }
+            Err(err) => error!(message = "failed to scavenge digests", error = %err),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -236,8 +621,14 @@ pub enum PutMessageError {
     PayloadDecode(prost::DecodeError),
     #[error("failed verify stamp: {0}")]
     StampVerify(StampError),
+    #[error("burn stamps are not accepted")]
+    BurnStampRejected,
     #[error("failed to broadcast stamp: {0}")]
     StampBroadcast(NodeError),
+    #[error("TTL exceeds maximum of {0}ms")]
+    TtlExceeded(u64),
+    #[error("failed to store payload: {0}")]
+    PayloadStore(PayloadStoreError),
 }
 
 impl From<rocksdb::Error> for PutMessageError {
@@ -252,6 +643,7 @@ impl ToResponse for PutMessageError {
     fn to_status(&self) -> u16 {
         match self {
             Self::DB(_) => 500,
+            Self::PayloadStore(_) => 500,
             Self::StampVerify(_) => 400,
             Self::StampBroadcast(err) => match err {
                 NodeError::Rpc(_) => 400,
@@ -262,16 +654,21 @@ impl ToResponse for PutMessageError {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn put_message(
     addr: Address,
     messages_raw: Bytes,
     database: Database,
-    bitcoin_client: BitcoinClientHTTP,
+    payload_store: Option<PayloadStore>,
+    bitcoin_client: FailoverClient<ChainBackend>,
     msg_bus: MessageBus,
+    notifier: Option<Notifier>,
+    default_ttl: Option<u64>,
     namespace: u8,
 ) -> Result<Response<Body>, PutMessageError> {
     // Time now
     let timestamp = get_unix_now();
+    let accepted = Instant::now();
 
     // Decode message
     let message_set =
@@ -281,6 +678,19 @@ pub async fn put_message(
         // Set received time
         message.received_time = timestamp as i64;
 
+        // Messages without a TTL take that of the request, if any
+        if message.ttl == 0 {
+            message.ttl = default_ttl.unwrap_or(0);
+        }
+        if message.ttl > SETTINGS.expiry.max_ttl {
+            return Err(PutMessageError::TtlExceeded(SETTINGS.expiry.max_ttl));
+        }
+
+        // Only the server may size a missing payload, and mark it offloaded
+        if message.payload.is_empty() {
+            message.payload_size = 0;
+        }
+
         // Get sender public key
         let source_pubkey = &message.source_public_key;
         let destination_pubkey = &message.destination_public_key;
@@ -293,10 +703,7 @@ pub async fn put_message(
             // TODO: What do we do here? Exit
         }
 
-        // Serialze message which is stored in database
-        let encoded_length = message.encoded_len();
-        let mut raw_message = Vec::with_capacity(encoded_length);
-        message.encode(&mut raw_message).unwrap(); // This is safe
+        let is_self_send = destination_pubkey_hash == source_pubkey_hash;
 
         // TODO: Parse does not enforce there is *ACTUALLY* a payload, only that there is a
         // payload digest. If the client is putting a message without a payload and only
@@ -304,14 +711,20 @@ pub async fn put_message(
         // errors.
         //
         // This needs to be fixed.
-        let parsed_message = message.parse().map_err(PutMessageError::MessageParsing)?;
-
-        let is_self_send = destination_pubkey_hash == source_pubkey_hash;
+        let parsed_message = message
+            .clone()
+            .parse()
+            .map_err(PutMessageError::MessageParsing)?;
 
         // If sender is not self then check stamp
         if !is_self_send {
+            if parsed_message.stamp.stamp_type == relay::StampType::Burn as i32
+                && !SETTINGS.stamps.accept_burns
+            {
+                return Err(PutMessageError::BurnStampRejected);
+            }
             parsed_message
-                .verify_stamp()
+                .verify_stamp_with_path(&SETTINGS.stamps.derivation_path)
                 .map_err(PutMessageError::StampVerify)?;
         }
 
@@ -325,55 +738,119 @@ pub async fn put_message(
                 async move { bitcoin_client_inner.send_tx(&stamp_oupoint.stamp_tx).await }
             });
 
-        future::try_join_all(broadcast)
+        let tx_ids = future::try_join_all(broadcast)
             .await
             .map_err(PutMessageError::StampBroadcast)?;
 
-        // Push to source key
-        database.push_message(
-            &source_pubkey_hash,
-            timestamp,
-            &raw_message[..],
-            &parsed_message.payload_digest[..],
-            namespace,
-        )?;
-
-        // Push to destination key
-        database.push_message(
-            &destination_pubkey_hash,
-            timestamp,
-            &raw_message[..],
-            &parsed_message.payload_digest[..],
-            namespace,
-        )?;
-
-        // If serialized payload too long then remove it
-        let raw_message_ws =
-            if parsed_message.payload.len() > SETTINGS.websocket.truncation_length as usize {
-                let mut pruned_message = parsed_message.into_message();
-                pruned_message.payload = Vec::with_capacity(0);
-                let mut pruned_raw_message = Vec::with_capacity(encoded_length);
-                pruned_message.encode(&mut pruned_raw_message).unwrap(); // This is safe
-                pruned_raw_message
-            } else {
-                raw_message
-            };
-
-        // Send to source
-        if !is_self_send {
-            if let Some(sender) = msg_bus.get(&source_pubkey_hash.to_vec()) {
-                if let Err(err) = sender.send(raw_message_ws.clone()) {
-                    warn!(message = "failed to broadcast to source", error = ?err);
+        // Store the stamp value, by which messages may be prioritized, and the outputs broadcast
+        message.stamp_value = parsed_message.stamp.value().unwrap_or(0);
+        message.broadcast_outpoints = tx_ids
+            .into_iter()
+            .zip(&parsed_message.stamp.stamp_outpoints)
+            .flat_map(|(tx_id, stamp_outpoint)| {
+                stamp_outpoint
+                    .vouts
+                    .iter()
+                    .map(move |vout| relay::BroadcastOutpoint {
+                        tx_id: tx_id.clone(),
+                        vout: *vout,
+                    })
+            })
+            .collect();
+
+        // Move large payloads to the payload store
+        let offloaded = match &payload_store {
+            Some(payload_store) => payload_store
+                .offload(&mut message)
+                .await
+                .map_err(PutMessageError::PayloadStore)?,
+            None => false,
+        };
+
+        // Push to source key and, if distinct, destination key
+        let recipients = if is_self_send {
+            vec![source_pubkey_hash]
+        } else {
+            vec![source_pubkey_hash, destination_pubkey_hash]
+        };
+        for pubkey_hash in recipients {
+            // Each recipient is assigned its own sequence number
+            let mut recipient_message = message.clone();
+
+            // Schedule removal before the push, so an expiring message is never left unscheduled
+            if message.ttl != 0 {
+                database.schedule_expiry(
+                    &pubkey_hash,
+                    timestamp,
+                    &parsed_message.payload_digest[..],
+                    namespace,
+                    timestamp.saturating_add(message.ttl),
+                )?;
+            }
+            if offloaded {
+                database.mark_offloaded(&pubkey_hash, &parsed_message.payload_digest)?;
+            }
+            database.push_sequenced_message(
+                &pubkey_hash,
+                timestamp,
+                &parsed_message.payload_digest[..],
+                &source_pubkey_hash,
+                namespace,
+                |sequence| {
+                    recipient_message.sequence = sequence;
+
+                    // Serialize and compress message for storage
+                    let mut raw_message = Vec::with_capacity(recipient_message.encoded_len());
+                    recipient_message.encode(&mut raw_message).unwrap(); // This is safe
+                    compression::pack_value(
+                        &raw_message,
+                        SETTINGS.compression.threshold,
+                        SETTINGS.compression.level,
+                    )
+                    .into_owned()
+                },
+            )?;
+
+            // If serialized payload too long then remove it
+            if recipient_message.payload.len() > SETTINGS.websocket.truncation_length as usize {
+                recipient_message.payload = Vec::with_capacity(0);
+            }
+            let mut raw_message_ws = Vec::with_capacity(recipient_message.encoded_len());
+            recipient_message.encode(&mut raw_message_ws).unwrap(); // This is safe
+
+            // Send to recipient
+            if let Some(sender) = msg_bus.get(&pubkey_hash.to_vec()) {
+                let broadcast = Broadcast {
+                    accepted,
+                    stamp_value: recipient_message.stamp_value,
+                    raw_message: raw_message_ws,
+                };
+                if let Err(err) = sender.send(broadcast) {
+                    warn!(message = "failed to broadcast to recipient", error = ?err);
                     // TODO: Make prettier
                 }
             }
         }
 
-        // Send to destination
-        if let Some(sender) = msg_bus.get(&destination_pubkey_hash.to_vec()) {
-            if let Err(err) = sender.send(raw_message_ws) {
-                warn!(message = "failed to broadcast to destination", error = ?err);
-                // TODO: Make prettier
+        // Notify the push endpoints registered by the destination
+        if !is_self_send {
+            if let Some(notifier) = &notifier {
+                let address = Address {
+                    body: destination_pubkey_hash.to_vec(),
+                    scheme: Scheme::CashAddr,
+                    hash_type: HashType::Key,
+                    ..addr.clone()
+                };
+                notifier.notify(
+                    database.clone(),
+                    destination_pubkey_hash.to_vec(),
+                    MessageEvent {
+                        address: address
+                            .encode()
+                            .unwrap_or_else(|_| hex::encode(destination_pubkey_hash)),
+                        digest: hex::encode(&parsed_message.payload_digest),
+                    },
+                );
             }
         }
     }
@@ -381,3 +858,61 @@ pub async fn put_message(
     // Respond
     Ok(Response::builder().body(Body::empty()).unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use warp::hyper::body::to_bytes;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn foreign_offload_stub() {
+        let database = Database::try_new("./test_dbs/foreign_offload_stub").unwrap();
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+
+        // A stub claiming another mailbox's offloaded payload
+        let stub = relay::Message {
+            payload_digest: vec![9; 32],
+            payload_size: 1024,
+            ..Default::default()
+        };
+        let mut raw_stub = Vec::with_capacity(stub.encoded_len());
+        stub.encode(&mut raw_stub).unwrap();
+        database
+            .push_message(
+                addr.as_body(),
+                100,
+                &raw_stub,
+                &stub.payload_digest,
+                db::MESSAGE_NAMESPACE,
+            )
+            .unwrap();
+        database
+            .mark_offloaded(&[0; 20], &stub.payload_digest)
+            .unwrap();
+
+        // The payload store, which is unreachable, is never queried
+        let payload_store = PayloadStore::new(
+            "bucket".to_string(),
+            "payloads/".to_string(),
+            "us-east-1".to_string(),
+            Some("http://127.0.0.1:9".to_string()),
+            Some(("access".to_string(), "secret".to_string())),
+            4,
+        );
+        let query: Query = serde_json::from_value(serde_json::json!({
+            "digest": hex::encode(&stub.payload_digest),
+        }))
+        .unwrap();
+        let response = get_payloads(
+            addr,
+            query,
+            database,
+            Some(payload_store),
+            db::MESSAGE_NAMESPACE,
+        )
+        .await
+        .unwrap();
+        assert!(to_bytes(response.into_body()).await.unwrap().is_empty());
+    }
+}
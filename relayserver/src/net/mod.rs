@@ -1,43 +1,54 @@
+mod archive;
+mod avatar;
+mod capabilities;
+mod encoding;
+mod features;
+mod firewall;
 mod messages;
+mod notifications;
 mod payments;
 mod profiles;
 mod protection;
+mod sync;
+mod tokens;
+mod webhook;
 mod ws;
 
+pub use archive::*;
+pub use avatar::*;
+pub use capabilities::*;
+pub use cashweb_server_core::{
+    address::AddressDecode, etag::*, head::*, load_shed::*, problem::*, ToResponse,
+};
+pub use encoding::*;
+pub use features::*;
+pub use firewall::*;
 pub use messages::*;
+pub use notifications::*;
 pub use payments::*;
 pub use profiles::*;
 pub use protection::*;
+pub use sync::*;
+pub use tokens::*;
+pub use webhook::*;
 pub use ws::*;
 
-use std::{convert::Infallible, fmt};
+use std::convert::Infallible;
 
 use bitcoincash_addr::Address;
-use thiserror::Error;
+use cashweb_server_core::handle_common_rejection;
 use tracing::error;
-use warp::{
-    http::Response,
-    hyper::Body,
-    reject::{PayloadTooLarge, Reject, Rejection},
-};
+use warp::{http::Response, hyper::Body, reject::Rejection};
 
-#[derive(Debug, Error)]
-pub enum AddressDecode {
-    #[error("address decoding failed: {0}, {1}")]
-    Decode(
-        bitcoincash_addr::cashaddr::DecodingError,
-        bitcoincash_addr::base58::DecodingError,
-    ),
-    #[error("expected address payload of length 20, found {0}")]
-    UnexpectedBodyLength(usize),
-}
-
-impl Reject for AddressDecode {}
+use crate::{compression::CompressionError, SETTINGS};
 
+/// Decode an address string on the configured network, requiring a 20 byte payload.
 pub fn address_decode(addr_str: &str) -> Result<Address, AddressDecode> {
-    // Convert address
-    let address = Address::decode(addr_str)
-        .map_err(|(cash_err, base58_err)| AddressDecode::Decode(cash_err, base58_err))?;
+    let address = cashweb_server_core::address::address_decode(
+        addr_str,
+        SETTINGS.network,
+        &SETTINGS.address_prefixes,
+    )?;
 
     // Check address payload is correct length
     let body_len = address.as_body().len();
@@ -47,35 +58,14 @@ pub fn address_decode(addr_str: &str) -> Result<Address, AddressDecode> {
     Ok(address)
 }
 
-impl ToResponse for AddressDecode {
-    fn to_status(&self) -> u16 {
-        400
+pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallible> {
+    if let Some(err) = err.find::<FirewallError>() {
+        error!(message = "firewall triggered", error = %err);
+        return Ok(err.to_response());
     }
-}
 
-pub trait ToResponse: fmt::Display + Sized {
-    fn to_status(&self) -> u16;
-
-    fn to_response(&self) -> Response<Body> {
-        let status = self.to_status();
-
-        if status != 500 {
-            Response::builder()
-                .status(status)
-                .body(Body::from(self.to_string()))
-                .unwrap()
-        } else {
-            Response::builder()
-                .status(status)
-                .body(Body::empty())
-                .unwrap()
-        }
-    }
-}
-
-pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallible> {
-    if let Some(err) = err.find::<AddressDecode>() {
-        error!(message = "failed to decode address", error = %err);
+    if let Some(err) = err.find::<FeatureDisabled>() {
+        error!(message = "feature disabled", error = %err);
         return Ok(err.to_response());
     }
 
@@ -84,6 +74,11 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<GetAvatarError>() {
+        error!(message = "failed to get avatar", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<PutProfileError>() {
         error!(message = "failed to put profile", error = %err);
         return Ok(err.to_response());
@@ -99,8 +94,34 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<ArchiveError>() {
+        error!(message = "failed to transfer archive", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<NotificationError>() {
+        error!(message = "failed to register push endpoint", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<SyncError>() {
+        error!(message = "failed to sync", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<CompressionError>() {
+        error!(message = "failed to decode body", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<PaymentError>() {
         error!(message = "payment failed", error = %err);
+        payments::record_payment_rejection(err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<TokenError>() {
+        error!(message = "failed to issue token", error = %err);
         return Ok(err.to_response());
     }
 
@@ -109,16 +130,5 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(protection_error_recovery(err).await);
     }
 
-    if err.find::<PayloadTooLarge>().is_some() {
-        error!("payload too large");
-        return Ok(Response::builder().status(413).body(Body::empty()).unwrap());
-    }
-
-    if err.is_not_found() {
-        error!("page not found");
-        return Ok(Response::builder().status(404).body(Body::empty()).unwrap());
-    }
-
-    error!(message = "unexpected error", error = ?err);
-    Ok(Response::builder().status(500).body(Body::empty()).unwrap())
+    Ok(handle_common_rejection(&err))
 }
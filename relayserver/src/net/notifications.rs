@@ -0,0 +1,187 @@
+use bitcoincash_addr::Address;
+use bytes::Bytes;
+use cashweb::relay::{PushRegistration, PushService};
+use prost::Message as _;
+use serde::Deserialize;
+use thiserror::Error;
+use url::Url;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{db::Database, net::ToResponse, notifications::Notifier, SETTINGS};
+
+/// Length of an uncompressed P-256 public key.
+const P256_PUBLIC_KEY_LEN: usize = 65;
+
+/// Length of a Web Push authentication secret.
+const AUTH_SECRET_LEN: usize = 16;
+
+#[derive(Debug, Deserialize)]
+pub struct PushQuery {
+    endpoint: String,
+}
+
+#[derive(Debug, Error)]
+pub enum NotificationError {
+    #[error("failed to read from database: {0}")]
+    DB(#[from] rocksdb::Error),
+    #[error("failed to decode registration: {0}")]
+    Decode(prost::DecodeError),
+    #[error("unknown push service")]
+    UnknownService,
+    #[error("invalid endpoint")]
+    InvalidEndpoint,
+    #[error("invalid subscription keys")]
+    InvalidKeys,
+    #[error("push service not supported")]
+    Unsupported,
+    #[error("too many registrations, limit is {0}")]
+    TooManyRegistrations(usize),
+    #[error("registration not found")]
+    NotFound,
+}
+
+impl Reject for NotificationError {}
+
+impl ToResponse for NotificationError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::DB(_) => 500,
+            Self::NotFound => 404,
+            _ => 400,
+        }
+    }
+}
+
+/// Check a registration is well-formed, returning its push service.
+fn validate_registration(
+    registration: &PushRegistration,
+) -> Result<PushService, NotificationError> {
+    let service =
+        PushService::from_i32(registration.service).ok_or(NotificationError::UnknownService)?;
+    match service {
+        PushService::WebPush => {
+            let endpoint = Url::parse(&registration.endpoint)
+                .map_err(|_| NotificationError::InvalidEndpoint)?;
+            if endpoint.scheme() != "https" {
+                return Err(NotificationError::InvalidEndpoint);
+            }
+            if registration.p256dh.len() != P256_PUBLIC_KEY_LEN
+                || registration.p256dh[0] != 4
+                || registration.auth.len() != AUTH_SECRET_LEN
+            {
+                return Err(NotificationError::InvalidKeys);
+            }
+        }
+        PushService::Fcm => {
+            if registration.endpoint.is_empty() {
+                return Err(NotificationError::InvalidEndpoint);
+            }
+        }
+    }
+    Ok(service)
+}
+
+/// Handles push registration PUT requests.
+pub async fn put_push_registration(
+    addr: Address,
+    registration_raw: Bytes,
+    database: Database,
+    notifier: Option<Notifier>,
+) -> Result<Response<Body>, NotificationError> {
+    let notifier = notifier.ok_or(NotificationError::Unsupported)?;
+    let registration =
+        PushRegistration::decode(registration_raw).map_err(NotificationError::Decode)?;
+    let service = validate_registration(&registration)?;
+    if !notifier.supports(service) {
+        return Err(NotificationError::Unsupported);
+    }
+
+    // Limit the number of distinct endpoints registered
+    let registrations = database.get_push_registrations(addr.as_body())?;
+    let is_new = registrations
+        .iter()
+        .all(|existing| existing.endpoint != registration.endpoint);
+    let max_registrations = SETTINGS.notifications.max_registrations;
+    if is_new && registrations.len() >= max_registrations {
+        return Err(NotificationError::TooManyRegistrations(max_registrations));
+    }
+
+    database.put_push_registration(addr.as_body(), &registration)?;
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles push registration DELETE requests.
+pub async fn delete_push_registration(
+    addr: Address,
+    query: PushQuery,
+    database: Database,
+) -> Result<Response<Body>, NotificationError> {
+    if !database.remove_push_registration(addr.as_body(), &query.endpoint)? {
+        return Err(NotificationError::NotFound);
+    }
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registration_validation() {
+        let mut registration = PushRegistration {
+            service: PushService::WebPush as i32,
+            endpoint: "https://push.example.com/send/abc".to_string(),
+            p256dh: [&[4][..], &[1; 64]].concat(),
+            auth: vec![2; 16],
+        };
+        assert_eq!(
+            validate_registration(&registration).unwrap(),
+            PushService::WebPush
+        );
+
+        // Web Push endpoints must be HTTPS URLs
+        registration.endpoint = "http://push.example.com/send/abc".to_string();
+        assert!(matches!(
+            validate_registration(&registration),
+            Err(NotificationError::InvalidEndpoint)
+        ));
+        registration.endpoint = "https://push.example.com/send/abc".to_string();
+
+        // Web Push keys must be an uncompressed point and a 16 byte secret
+        registration.p256dh[0] = 2;
+        assert!(matches!(
+            validate_registration(&registration),
+            Err(NotificationError::InvalidKeys)
+        ));
+        registration.p256dh[0] = 4;
+        registration.auth.pop();
+        assert!(matches!(
+            validate_registration(&registration),
+            Err(NotificationError::InvalidKeys)
+        ));
+
+        // FCM registrations only carry a token
+        let registration = PushRegistration {
+            service: PushService::Fcm as i32,
+            endpoint: "fcm-token".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_registration(&registration).unwrap(),
+            PushService::Fcm
+        );
+
+        let registration = PushRegistration {
+            service: 7,
+            ..registration
+        };
+        assert!(matches!(
+            validate_registration(&registration),
+            Err(NotificationError::UnknownService)
+        ));
+    }
+}
@@ -1,21 +1,23 @@
 use std::{
+    convert::TryInto,
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use bitcoincash_addr::{base58, cashaddr, Address};
+use bitcoincash_addr::{base58, cashaddr, Address, HashType, Scheme};
 use cashweb::{
     bitcoin::{
-        transaction::{self, Transaction},
+        transaction::{self, script::Script, Transaction},
         Decodable,
     },
-    bitcoin_client::{BitcoinClient, BitcoinClientHTTP, NodeError},
+    bitcoin_client::{BitcoinClient, ChainBackend, FailoverClient, NodeError},
     payments::bip70::{Output, Payment, PaymentAck, PaymentDetails, PaymentRequest},
     payments::{
         wallet::{self, UnexpectedOutputs},
+        zeroconf::{ZeroConfError, ZeroConfPolicy},
         PreprocessingError,
     },
-    token::schemes::hmac_bearer::HmacScheme,
+    token::schemes::hmac_bearer::{HmacScheme, Scopes},
 };
 use prost::Message as _;
 use thiserror::Error;
@@ -26,26 +28,97 @@ use warp::{
     reject::Reject,
 };
 
-use crate::{net::ToResponse, PAYMENTS_PATH, SETTINGS};
+use crate::{
+    net::{self, AddressDecode, ToResponse},
+    PAYMENTS_PATH, SETTINGS,
+};
 
 pub type Wallet = wallet::Wallet<Vec<u8>, Output>;
 
+/// Header carrying a BIP21 payment URI, for wallets which don't support BIP70.
+pub const PAYMENT_URI: &str = "X-Payment-URI";
+
+/// Header carrying a payment URI suited to QR codes.
+pub const PAYMENT_QR: &str = "X-Payment-QR";
+
+const SATS_PER_COIN: u64 = 100_000_000;
+
 #[derive(Debug, Error)]
 pub enum PaymentError {
     #[error("preprocessing failed: {0}")]
     Preprocess(PreprocessingError),
     #[error(transparent)]
     Wallet(UnexpectedOutputs),
+    #[error("too many transactions: {0} > {1}")]
+    TooManyTransactions(usize, usize),
     #[error("malformed tx: {0}")]
     MalformedTx(transaction::DecodeError),
     #[error("missing merchant data")]
     MissingMerchantData,
+    #[error("malformed merchant data")]
+    MalformedMerchantData,
     #[error("bitcoin request failed: {0}")]
     Node(NodeError),
+    #[error("zero-conf policy violated: {0}")]
+    ZeroConf(ZeroConfError),
 }
 
 impl Reject for PaymentError {}
 
+impl PaymentError {
+    /// A short label describing why the payment was rejected.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PaymentError::Preprocess(_) => "preprocess",
+            PaymentError::Wallet(_) => "unexpected_outputs",
+            PaymentError::TooManyTransactions(..) => "too_many_transactions",
+            PaymentError::MalformedTx(_) => "malformed_tx",
+            PaymentError::MissingMerchantData => "missing_merchant_data",
+            PaymentError::MalformedMerchantData => "malformed_merchant_data",
+            PaymentError::Node(err) => match err {
+                NodeError::TxAlreadyKnown(_) => "tx_already_known",
+                NodeError::MissingInputs(_) => "missing_inputs",
+                NodeError::TxRejectedByPolicy(_) => "tx_rejected",
+                _ => "node",
+            },
+            PaymentError::ZeroConf(err) => match err {
+                ZeroConfError::LowFeeRate(..) => "low_fee_rate",
+                ZeroConfError::UnconfirmedParent(_) => "unconfirmed_parent",
+                ZeroConfError::DoubleSpent(_) => "double_spent",
+                ZeroConfError::MissingInput(..) => "missing_input",
+                ZeroConfError::Transaction(_) => "malformed_parent",
+                ZeroConfError::Node(_) => "node",
+            },
+        }
+    }
+}
+
+/// Get the route of a request path, its first segment, for labeling metrics.
+pub fn route_label(path: &str) -> &str {
+    path.trim_start_matches('/').split('/').next().unwrap() // This is safe
+}
+
+pub fn record_invoice(_route: &str) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::PAYMENT_INVOICE_TOTAL
+        .with_label_values(&[_route])
+        .inc();
+}
+
+pub fn record_payment_rejection(_err: &PaymentError) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::PAYMENT_REJECTED_TOTAL
+        .with_label_values(&[_err.reason()])
+        .inc();
+}
+
+pub fn record_token_issued(_route: &str) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::TOKEN_ISSUED_TOTAL
+        .with_label_values(&[_route])
+        .inc();
+}
+
 impl ToResponse for PaymentError {
     fn to_status(&self) -> u16 {
         match self {
@@ -55,46 +128,111 @@ impl ToResponse for PaymentError {
                 PreprocessingError::PaymentDecode(_) => 400,
             },
             PaymentError::Wallet(_) => 404,
+            PaymentError::TooManyTransactions(..) => 400,
             PaymentError::MalformedTx(_) => 400,
             PaymentError::MissingMerchantData => 400,
+            PaymentError::MalformedMerchantData => 400,
             PaymentError::Node(err) => match err {
-                NodeError::Rpc(_) => 400,
+                NodeError::TxAlreadyKnown(_) => 409,
+                NodeError::NotFound(_) => 404,
+                NodeError::WalletNotLoaded(_) => 503,
+                NodeError::MissingInputs(_)
+                | NodeError::TxRejectedByPolicy(_)
+                | NodeError::Rpc(_) => 400,
                 _ => 500,
             },
+            PaymentError::ZeroConf(err) => match err {
+                ZeroConfError::LowFeeRate(..) => 400,
+                ZeroConfError::UnconfirmedParent(_) => 400,
+                ZeroConfError::DoubleSpent(_) => 409,
+                ZeroConfError::MissingInput(..) => 400,
+                ZeroConfError::Transaction(_) => 500,
+                ZeroConfError::Node(_) => 500,
+            },
+        }
+    }
+}
+
+/// The policies an unconfirmed payment must satisfy before a token is issued.
+pub fn zero_conf_policy() -> ZeroConfPolicy {
+    ZeroConfPolicy {
+        min_fee_rate: SETTINGS.payments.min_fee_rate,
+        reject_unconfirmed_parents: SETTINGS.payments.reject_unconfirmed_parents,
+        dsproof_wait: Duration::from_millis(SETTINGS.payments.dsproof_wait),
+    }
+}
+
+/// Construct the merchant data of a payment request, committing to the address and the scopes
+/// paid for.
+fn construct_merchant_data(pubkey_hash: &[u8], scopes: Scopes) -> Vec<u8> {
+    [pubkey_hash, &[scopes.bits()]].concat()
+}
+
+/// Parse the address and scopes from merchant data.
+///
+/// Merchant data without scopes was issued before pricing was introduced and grants every scope.
+fn parse_merchant_data(merchant_data: &[u8]) -> Result<(&[u8], Scopes), PaymentError> {
+    match merchant_data.len() {
+        20 => Ok((merchant_data, Scopes::ALL)),
+        21 => {
+            let scopes =
+                Scopes::from_bits(merchant_data[20]).ok_or(PaymentError::MalformedMerchantData)?;
+            Ok((&merchant_data[..20], scopes))
         }
+        _ => Err(PaymentError::MalformedMerchantData),
     }
 }
 
 pub async fn process_payment(
     payment: Payment,
     wallet: Wallet,
-    bitcoin_client: BitcoinClientHTTP,
+    bitcoin_client: FailoverClient<ChainBackend>,
     token_state: Arc<HmacScheme>,
 ) -> Result<Response<Body>, PaymentError> {
+    // Bound the number of transactions before any decoding or RPC calls
+    let n_transactions = payment.transactions.len();
+    if n_transactions > SETTINGS.limits.payment_transactions {
+        return Err(PaymentError::TooManyTransactions(
+            n_transactions,
+            SETTINGS.limits.payment_transactions,
+        ));
+    }
+
     let txs_res: Result<Vec<Transaction>, transaction::DecodeError> = payment
         .transactions
         .iter()
         .map(|raw_tx: &Vec<u8>| Transaction::decode(&mut raw_tx.as_slice()))
         .collect();
     let txs = txs_res.map_err(PaymentError::MalformedTx)?;
+    let tx_ids: Vec<Vec<u8>> = txs
+        .iter()
+        .map(|tx| tx.transaction_id_rev().to_vec())
+        .collect();
+    let txids: Vec<String> = tx_ids.iter().map(hex::encode).collect();
     let outputs: Vec<Output> = txs
-        .into_iter()
-        .map(move |tx| tx.outputs)
-        .flatten()
-        .map(move |output| Output {
+        .iter()
+        .flat_map(|tx| tx.outputs.iter())
+        .map(|output| Output {
             amount: Some(output.value),
-            script: output.script.into_bytes(),
+            script: output.script.as_bytes().to_vec(),
         })
         .collect();
 
-    let pubkey_hash = payment
+    let merchant_data = payment
         .merchant_data
         .as_ref()
         .ok_or(PaymentError::MissingMerchantData)?;
+    let (pubkey_hash, scopes) = parse_merchant_data(merchant_data)?;
+
+    // Reject cheap or chained payments before they are broadcast
+    zero_conf_policy()
+        .check_transactions(&bitcoin_client, &txs)
+        .await
+        .map_err(PaymentError::ZeroConf)?;
 
     info!(message = "checking wallet", outputs = ?outputs, address_payload = ?pubkey_hash);
     wallet
-        .recv_outputs(pubkey_hash, &outputs)
+        .recv_outputs(merchant_data, &outputs)
         .map_err(PaymentError::Wallet)?;
 
     for tx in &payment.transactions {
@@ -104,8 +242,38 @@ pub async fn process_payment(
             .map_err(PaymentError::Node)?;
     }
 
+    // Guard against the payment being double-spent once the token is issued
+    zero_conf_policy()
+        .check_broadcast(&bitcoin_client, &tx_ids)
+        .await
+        .map_err(PaymentError::ZeroConf)?;
+
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::PAYMENT_RECEIVED_TOTAL.inc();
+
     // Construct token
-    let token = format!("POP {}", token_state.construct_token(pubkey_hash));
+    let token = format!(
+        "POP {}",
+        token_state.construct_scoped_token(pubkey_hash, scopes)
+    );
+    record_token_issued(PAYMENTS_PATH);
+
+    // Notify operator
+    let address = Address {
+        body: pubkey_hash.to_vec(),
+        ..Default::default()
+    };
+    net::notify_payment(net::PaymentEvent {
+        address: address
+            .encode()
+            .unwrap_or_else(|_| hex::encode(pubkey_hash)),
+        txids,
+        amount: SETTINGS.pricing.price(scopes, SETTINGS.payments.token_fee),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    });
 
     // Create PaymentAck
     let memo = Some(SETTINGS.payments.memo.clone());
@@ -129,34 +297,98 @@ pub enum PaymentRequestError {
     Node(NodeError),
     #[error("mismatched network")]
     MismatchedNetwork,
+    #[error("invalid fee address: {0}")]
+    FeeAddress(AddressDecode),
+    #[error("expected output address payload of length 20, found {0}")]
+    UnexpectedBodyLength(usize),
+    #[error("address encoding failed: {0}")]
+    AddressEncode(cashaddr::EncodingError),
+}
+
+/// Format an amount of satoshis in coins, without trailing zeros.
+fn format_amount(amount: u64) -> String {
+    let fraction = format!("{:08}", amount % SATS_PER_COIN);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        (amount / SATS_PER_COIN).to_string()
+    } else {
+        format!("{}.{}", amount / SATS_PER_COIN, fraction)
+    }
+}
+
+/// Construct a BIP21 payment URI, and its QR code friendly counterpart, paying `amount` to an
+/// address.
+///
+/// The URI includes the BIP70 payment URL, if given, under the `r` parameter. The QR code friendly
+/// URI omits it and is upper case, except for its parameters, allowing the alphanumeric QR mode.
+fn payment_uris(
+    address: &Address,
+    amount: u64,
+    opt_payment_url: Option<&str>,
+) -> Result<(String, String), PaymentRequestError> {
+    let address = Address {
+        scheme: Scheme::CashAddr,
+        ..address.clone()
+    };
+    let address_str = address
+        .encode()
+        .map_err(PaymentRequestError::AddressEncode)?;
+    let amount_param = format!("amount={}", format_amount(amount));
+
+    let uri = match opt_payment_url {
+        Some(payment_url) => format!("{}?{}&r={}", address_str, amount_param, payment_url),
+        None => format!("{}?{}", address_str, amount_param),
+    };
+    let qr_uri = format!("{}?{}", address_str.to_uppercase(), amount_param);
+    Ok((uri, qr_uri))
 }
 
+/// Construct the script paying to an output address, supporting both P2PKH and P2SH addresses.
+pub fn output_script(address: &Address) -> Result<Script, PaymentRequestError> {
+    let hash: &[u8; 20] = address
+        .as_body()
+        .try_into()
+        .map_err(|_| PaymentRequestError::UnexpectedBodyLength(address.as_body().len()))?;
+    Ok(match address.hash_type {
+        HashType::Key => Script::p2pkh(hash),
+        HashType::Script => Script::p2sh(hash),
+    })
+}
+
+/// Generate a payment request for a token granting the `required` scopes, priced according to the
+/// pricing table.
 pub async fn generate_payment_request(
     addr: Address,
+    required: Scopes,
     wallet: Wallet,
-    bitcoin_client: BitcoinClientHTTP,
+    bitcoin_client: FailoverClient<ChainBackend>,
 ) -> Result<Response<Body>, PaymentRequestError> {
-    let output_addr_str = bitcoin_client
-        .get_new_addr()
-        .await
-        .map_err(PaymentRequestError::Node)?;
-    let output_addr = Address::decode(&output_addr_str)
-        .map_err(|(cash_err, base58_err)| PaymentRequestError::Address(cash_err, base58_err))?;
+    // Pay to the operator address if given, else to a fresh address from the node
+    let output_addr = match &SETTINGS.payments.fee_address {
+        Some(fee_address) => {
+            net::address_decode(fee_address).map_err(PaymentRequestError::FeeAddress)?
+        }
+        None => {
+            let output_addr_str = bitcoin_client
+                .get_new_addr()
+                .await
+                .map_err(PaymentRequestError::Node)?;
+            Address::decode(&output_addr_str).map_err(|(cash_err, base58_err)| {
+                PaymentRequestError::Address(cash_err, base58_err)
+            })?
+        }
+    };
 
     // Generate output
-    let p2pkh_script_pre: [u8; 3] = [118, 169, 20];
-    let p2pkh_script_post: [u8; 2] = [136, 172];
-    let script = [
-        &p2pkh_script_pre[..],
-        output_addr.as_body(),
-        &p2pkh_script_post[..],
-    ]
-    .concat();
+    let scopes = SETTINGS.pricing.granted_scopes(required);
+    let script = output_script(&output_addr)?.into_bytes();
+    let amount = SETTINGS.pricing.price(scopes, SETTINGS.payments.token_fee);
     let output = Output {
-        amount: Some(SETTINGS.payments.token_fee),
+        amount: Some(amount),
         script,
     };
-    let cleanup = wallet.add_outputs(addr.as_body().to_vec(), vec![output.clone()]);
+    let merchant_data = construct_merchant_data(addr.as_body(), scopes);
+    let cleanup = wallet.add_outputs(merchant_data.clone(), vec![output.clone()]);
     info!(message = "added to wallet", output = ?output, address_payload = ?addr.as_body());
     tokio::spawn(cleanup);
 
@@ -169,7 +401,7 @@ pub async fn generate_payment_request(
         time: current_time.duration_since(UNIX_EPOCH).unwrap().as_secs(),
         expires: Some(expiry_time.duration_since(UNIX_EPOCH).unwrap().as_secs()),
         memo: None,
-        merchant_data: Some(addr.into_body()),
+        merchant_data: Some(merchant_data),
         outputs: vec![output],
         payment_url: Some(format!("/{}", PAYMENTS_PATH)),
     };
@@ -191,8 +423,65 @@ pub async fn generate_payment_request(
     let mut payment_invoice_raw = Vec::with_capacity(payment_invoice.encoded_len());
     payment_invoice.encode(&mut payment_invoice_raw).unwrap();
 
+    // Generate payment URIs
+    let payment_url = SETTINGS
+        .payments
+        .public_url
+        .as_ref()
+        .map(|public_url| format!("{}/{}", public_url.trim_end_matches('/'), PAYMENTS_PATH));
+    let (uri, qr_uri) = payment_uris(&output_addr, amount, payment_url.as_deref())?;
+
     Ok(Response::builder()
         .status(402)
+        .header(PAYMENT_URI, uri)
+        .header(PAYMENT_QR, qr_uri)
         .body(Body::from(payment_invoice_raw))
         .unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoincash_addr::Network;
+
+    use super::*;
+
+    #[test]
+    fn construct_payment_uris() {
+        assert_eq!(format_amount(100_000), "0.001");
+        assert_eq!(format_amount(250_000_000), "2.5");
+        assert_eq!(format_amount(SATS_PER_COIN), "1");
+        assert_eq!(format_amount(1), "0.00000001");
+
+        let address = Address {
+            body: vec![0; 20],
+            scheme: Scheme::Base58,
+            hash_type: HashType::Key,
+            network: Network::Main,
+        };
+        let (uri, qr_uri) =
+            payment_uris(&address, 100_000, Some("https://relay.example/payments")).unwrap();
+        assert!(uri.starts_with("bitcoincash:qq"));
+        assert!(uri.ends_with("?amount=0.001&r=https://relay.example/payments"));
+        assert!(qr_uri.starts_with("BITCOINCASH:QQ"));
+        assert!(qr_uri.ends_with("?amount=0.001"));
+    }
+    #[test]
+    fn funnel_labels() {
+        assert_eq!(route_label("/messages/qq0000"), "messages");
+        assert_eq!(route_label("/tokens"), "tokens");
+        assert_eq!(route_label("/"), "");
+
+        assert_eq!(
+            PaymentError::TooManyTransactions(5, 4).reason(),
+            "too_many_transactions"
+        );
+        assert_eq!(
+            PaymentError::Node(NodeError::MissingInputs(String::new())).reason(),
+            "missing_inputs"
+        );
+        assert_eq!(
+            PaymentError::Node(NodeError::EmptyResponse).reason(),
+            "node"
+        );
+    }
+}
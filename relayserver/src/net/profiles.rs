@@ -1,12 +1,31 @@
 use bitcoincash_addr::Address;
 use bytes::Bytes;
-use cashweb::auth_wrapper::{AuthWrapper, ParseError, VerifyError};
+use std::sync::Arc;
+
+use cashweb::{
+    auth_wrapper::{AuthWrapper, ParseError, VerifyError},
+    relay::Profile,
+};
 use prost::Message as _;
 use thiserror::Error;
 use tokio::task;
-use warp::{http::Response, hyper::Body, reject::Reject};
+use warp::{
+    http::{
+        header::{CACHE_CONTROL, ETAG, LAST_MODIFIED, VARY},
+        Response,
+    },
+    hyper::Body,
+    reject::Reject,
+};
 
-use crate::{db::Database, net::ToResponse};
+use crate::{
+    db::Database,
+    net::{
+        cache_control, etag, etag_matches, find_avatar, generate_thumbnails, last_modified,
+        validate_avatar, AvatarError, ToResponse, AVATAR_KIND,
+    },
+    SETTINGS,
+};
 
 #[derive(Debug, Error)]
 pub enum GetProfileError {
@@ -37,6 +56,10 @@ pub enum PutProfileError {
     Verify(VerifyError),
     #[error("failed to parse authorization wrapper: {0}")]
     Parse(ParseError),
+    #[error("invalid profile: {0}")]
+    Validation(#[from] ProfileValidationError),
+    #[error("failed to generate avatar thumbnail: {0}")]
+    Thumbnail(AvatarError),
 }
 
 impl Reject for PutProfileError {}
@@ -50,8 +73,91 @@ impl ToResponse for PutProfileError {
     }
 }
 
+#[derive(Debug, Error, PartialEq)]
+pub enum ProfileValidationError {
+    #[error("missing payload")]
+    MissingPayload,
+    #[error("failed to decode profile: {0}")]
+    Decode(prost::DecodeError),
+    #[error("invalid timestamp")]
+    InvalidTimestamp,
+    #[error("invalid ttl")]
+    InvalidTtl,
+    #[error("too many entries: {0} > {1}")]
+    TooManyEntries(usize, usize),
+    #[error("entry {0} has an empty kind")]
+    EmptyKind(usize),
+    #[error("entry {0} has unknown kind {1}")]
+    UnknownKind(usize, String),
+    #[error("entry {0} is too large: {1} > {2}")]
+    EntryTooLarge(usize, usize, usize),
+    #[error("entry {0} is an invalid avatar: {1}")]
+    Avatar(usize, AvatarError),
+}
+
+/// Constraints placed on profiles.
+#[derive(Clone, Debug)]
+pub struct ProfileSchema {
+    pub max_entries: usize,
+    pub max_entry_size: usize,
+    /// If empty, all entry kinds are allowed.
+    pub allowed_kinds: Arc<Vec<String>>,
+    pub max_avatar_size: usize,
+    pub max_avatar_dimension: u32,
+    /// Sizes of the avatar thumbnails generated. If empty, no thumbnails are generated.
+    pub thumbnail_sizes: Arc<Vec<u32>>,
+}
+
+impl ProfileSchema {
+    /// Decode and validate a profile against the schema.
+    pub fn validate(&self, raw_profile: &[u8]) -> Result<Profile, ProfileValidationError> {
+        if raw_profile.is_empty() {
+            return Err(ProfileValidationError::MissingPayload);
+        }
+        let profile = Profile::decode(raw_profile).map_err(ProfileValidationError::Decode)?;
+
+        if profile.timestamp <= 0 {
+            return Err(ProfileValidationError::InvalidTimestamp);
+        }
+        if profile.ttl < 0 {
+            return Err(ProfileValidationError::InvalidTtl);
+        }
+        if profile.entries.len() > self.max_entries {
+            return Err(ProfileValidationError::TooManyEntries(
+                profile.entries.len(),
+                self.max_entries,
+            ));
+        }
+        for (index, entry) in profile.entries.iter().enumerate() {
+            if entry.kind.is_empty() {
+                return Err(ProfileValidationError::EmptyKind(index));
+            }
+            if !self.allowed_kinds.is_empty() && !self.allowed_kinds.contains(&entry.kind) {
+                return Err(ProfileValidationError::UnknownKind(
+                    index,
+                    entry.kind.clone(),
+                ));
+            }
+            if entry.body.len() > self.max_entry_size {
+                return Err(ProfileValidationError::EntryTooLarge(
+                    index,
+                    entry.body.len(),
+                    self.max_entry_size,
+                ));
+            }
+            if entry.kind == AVATAR_KIND {
+                validate_avatar(&entry.body, self.max_avatar_size, self.max_avatar_dimension)
+                    .map_err(|err| ProfileValidationError::Avatar(index, err))?;
+            }
+        }
+
+        Ok(profile)
+    }
+}
+
 pub async fn get_profile(
     addr: Address,
+    if_none_match: Option<String>,
     database: Database,
 ) -> Result<Response<Body>, GetProfileError> {
     // Get profile
@@ -60,31 +166,161 @@ pub async fn get_profile(
         .unwrap()?
         .ok_or(GetProfileError::NotFound)?;
 
+    // Derive ETag from the payload digest
+    let profile = AuthWrapper::decode(&raw_profile[..]).unwrap(); // This panics if stored bytes are malformed
+    let tag = etag(&profile.digest());
+    let mut builder = Response::builder()
+        .header(ETAG, &tag)
+        .header(CACHE_CONTROL, cache_control(SETTINGS.cache.profile_max_age))
+        .header(VARY, "Accept-Encoding");
+
+    // Derive Last-Modified from the signed timestamp
+    let timestamp = Profile::decode(&profile.payload[..])
+        .map(|profile| profile.timestamp)
+        .unwrap_or_default();
+    if let Some(last_modified) = last_modified(timestamp) {
+        builder = builder.header(LAST_MODIFIED, last_modified);
+    }
+
     // Respond
-    Ok(Response::builder().body(Body::from(raw_profile)).unwrap())
+    if if_none_match.map_or(false, |value| etag_matches(&value, &tag)) {
+        return Ok(builder.status(304).body(Body::empty()).unwrap());
+    }
+    Ok(builder.body(Body::from(raw_profile)).unwrap())
 }
 
 pub async fn put_profile(
     addr: Address,
     profile_raw: Bytes,
     database: Database,
+    schema: ProfileSchema,
 ) -> Result<Response<Body>, PutProfileError> {
+    store_profile(addr, profile_raw, database, schema).await?;
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Verify and validate a profile, then store it alongside its avatar thumbnails.
+pub async fn store_profile(
+    addr: Address,
+    profile_raw: Bytes,
+    database: Database,
+    schema: ProfileSchema,
+) -> Result<(), PutProfileError> {
     // Decode profile
     let profile =
         AuthWrapper::decode(profile_raw.clone()).map_err(PutProfileError::ProfileDecode)?;
 
     // Verify signatures
-    profile
-        .parse()
-        .map_err(PutProfileError::Parse)?
-        .verify()
-        .map_err(PutProfileError::Verify)?;
+    let parsed_profile = profile.parse().map_err(PutProfileError::Parse)?;
+    parsed_profile.verify().map_err(PutProfileError::Verify)?;
+
+    // Validate payload
+    let profile = schema.validate(&parsed_profile.payload)?;
+
+    // Generate avatar thumbnails
+    let thumbnails = match find_avatar(&profile) {
+        Some(avatar) => {
+            let avatar = avatar.to_vec();
+            task::spawn_blocking(move || generate_thumbnails(&avatar, &schema.thumbnail_sizes))
+                .await
+                .unwrap()
+                .map_err(PutProfileError::Thumbnail)?
+        }
+        None => Vec::new(),
+    };
 
     // Put to database
-    task::spawn_blocking(move || database.put_profile(addr.as_body(), &profile_raw))
+    task::spawn_blocking(move || database.put_profile(addr.as_body(), &profile_raw, &thumbnails))
         .await
         .unwrap()?;
 
-    // Respond
-    Ok(Response::builder().body(Body::empty()).unwrap())
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb::relay::ProfileEntry;
+
+    use super::*;
+
+    fn schema() -> ProfileSchema {
+        ProfileSchema {
+            max_entries: 2,
+            max_entry_size: 8,
+            allowed_kinds: Arc::new(vec![
+                "name".to_string(),
+                "bio".to_string(),
+                AVATAR_KIND.to_string(),
+            ]),
+            max_avatar_size: 8,
+            max_avatar_dimension: 64,
+            thumbnail_sizes: Arc::new(vec![]),
+        }
+    }
+
+    fn encode(profile: &Profile) -> Vec<u8> {
+        let mut raw_profile = Vec::with_capacity(profile.encoded_len());
+        profile.encode(&mut raw_profile).unwrap();
+        raw_profile
+    }
+
+    fn entry(kind: &str, body: &[u8]) -> ProfileEntry {
+        ProfileEntry {
+            kind: kind.to_string(),
+            headers: vec![],
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn validate_profile() {
+        let schema = schema();
+        let mut profile = Profile {
+            timestamp: 1,
+            ttl: 0,
+            entries: vec![entry("name", b"alice")],
+        };
+        assert_eq!(schema.validate(&encode(&profile)), Ok(profile.clone()));
+
+        assert_eq!(
+            schema.validate(&[]),
+            Err(ProfileValidationError::MissingPayload)
+        );
+
+        profile.entries = vec![entry("banner", b"")];
+        assert_eq!(
+            schema.validate(&encode(&profile)),
+            Err(ProfileValidationError::UnknownKind(0, "banner".to_string()))
+        );
+
+        profile.entries = vec![entry(AVATAR_KIND, b"avatar")];
+        assert_eq!(
+            schema.validate(&encode(&profile)),
+            Err(ProfileValidationError::Avatar(
+                0,
+                AvatarError::UnsupportedFormat
+            ))
+        );
+
+        profile.entries = vec![entry("name", b"alice"), entry("bio", b"too long bio")];
+        assert_eq!(
+            schema.validate(&encode(&profile)),
+            Err(ProfileValidationError::EntryTooLarge(1, 12, 8))
+        );
+
+        profile.entries = vec![entry("name", b""); 3];
+        assert_eq!(
+            schema.validate(&encode(&profile)),
+            Err(ProfileValidationError::TooManyEntries(3, 2))
+        );
+
+        profile.entries = vec![];
+        profile.timestamp = 0;
+        assert_eq!(
+            schema.validate(&encode(&profile)),
+            Err(ProfileValidationError::InvalidTimestamp)
+        );
+    }
 }
@@ -1,24 +1,32 @@
 use std::sync::Arc;
 
 use bitcoincash_addr::Address;
-use cashweb::bitcoin_client::BitcoinClientHTTP;
+use cashweb::bitcoin_client::{ChainBackend, FailoverClient};
 use cashweb::token::{
     extract_pop,
-    schemes::hmac_bearer::{HmacScheme, ValidationError},
+    schemes::hmac_bearer::{HmacScheme, Scopes, ValidationError},
     split_pop_token,
 };
 use http::header::HeaderMap;
 use thiserror::Error;
-use warp::{http::Response, hyper::Body, reject::Reject};
+use warp::{http::Response, hyper::Body, path::FullPath, reject::Reject};
 
-use crate::net::payments::{generate_payment_request, Wallet};
+use crate::net::payments::{generate_payment_request, record_invoice, route_label, Wallet};
 
 #[derive(Debug, Error)]
 pub enum ProtectionError {
     #[error("missing token: {0:?}")] // TODO: Make this prettier
-    MissingToken(Address, Wallet, BitcoinClientHTTP),
+    MissingToken(
+        Address,
+        Scopes,
+        Wallet,
+        FailoverClient<ChainBackend>,
+        String,
+    ),
     #[error("validation failed: {0}")]
     Validation(ValidationError),
+    #[error("token lacks the required scopes: {0}")]
+    InsufficientScopes(Scopes),
 }
 
 pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body> {
@@ -27,12 +35,24 @@ pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body>
             .status(400)
             .body(Body::from(err.to_string()))
             .unwrap(),
-        ProtectionError::MissingToken(addr, wallet, bitcoin_client) => {
+        ProtectionError::InsufficientScopes(_) => Response::builder()
+            .status(403)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
+        ProtectionError::MissingToken(addr, scopes, wallet, bitcoin_client, route) => {
             // TODO: Remove clones here
-            match generate_payment_request(addr.clone(), wallet.clone(), bitcoin_client.clone())
-                .await
+            match generate_payment_request(
+                addr.clone(),
+                *scopes,
+                wallet.clone(),
+                bitcoin_client.clone(),
+            )
+            .await
             {
-                Ok(ok) => ok,
+                Ok(ok) => {
+                    record_invoice(route);
+                    ok
+                }
                 Err(err) => Response::builder()
                     .status(400)
                     .body(Body::from(err.to_string()))
@@ -44,13 +64,16 @@ pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body>
 
 impl Reject for ProtectionError {}
 
+/// Validate the POP token of a request, requiring that it grants the `required` scopes.
 pub async fn pop_protection(
     addr: Address,
+    path: FullPath,
     header_map: HeaderMap,
     access_token: Option<String>,
     token_scheme: Arc<HmacScheme>,
     wallet: Wallet,
-    bitcoin_client: BitcoinClientHTTP,
+    bitcoin_client: FailoverClient<ChainBackend>,
+    required: Scopes,
 ) -> Result<Address, ProtectionError> {
     match extract_pop(&header_map).or_else(|| {
         access_token
@@ -58,11 +81,20 @@ pub async fn pop_protection(
             .and_then(|access_token| split_pop_token(access_token))
     }) {
         Some(pop_token) => {
-            token_scheme
+            let scopes = token_scheme
                 .validate_token(&addr.as_body().to_vec(), pop_token)
                 .map_err(ProtectionError::Validation)?;
+            if !scopes.contains(required) {
+                return Err(ProtectionError::InsufficientScopes(required));
+            }
             Ok(addr)
         }
-        None => Err(ProtectionError::MissingToken(addr, wallet, bitcoin_client)),
+        None => Err(ProtectionError::MissingToken(
+            addr,
+            required,
+            wallet,
+            bitcoin_client,
+            route_label(path.as_str()).to_string(),
+        )),
     }
 }
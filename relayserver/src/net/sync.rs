@@ -0,0 +1,209 @@
+use bitcoincash_addr::Address;
+use cashweb::{
+    auth_wrapper::AuthWrapper,
+    relay::{Deletion, SyncCursor, SyncPage},
+};
+use hex::FromHexError;
+use prost::Message as _;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::task;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{
+    db::{Database, FEED_NAMESPACE, MESSAGE_NAMESPACE},
+    net::ToResponse,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SyncQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("failed to read from database: {0}")]
+    DB(#[from] rocksdb::Error),
+    #[error("failed to decode cursor: {0}")]
+    CursorDecode(FromHexError),
+    #[error("cursor malformed: {0}")]
+    CursorMalformed(prost::DecodeError),
+}
+
+impl Reject for SyncError {}
+
+impl ToResponse for SyncError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::DB(_) => 500,
+            _ => 400,
+        }
+    }
+}
+
+fn namespace_name(namespace: u8) -> &'static str {
+    match namespace {
+        FEED_NAMESPACE => "feeds",
+        _ => "messages",
+    }
+}
+
+/// Collect the changes to an inbox since the cursor.
+///
+/// At most `limit` messages and `limit` feed items are returned, the cursor only advancing past
+/// those returned.
+fn construct_sync_page(
+    addr_payload: &[u8],
+    mut cursor: SyncCursor,
+    limit: usize,
+    database: &Database,
+) -> Result<SyncPage, SyncError> {
+    let mut sync_page = SyncPage::default();
+
+    // Get new messages and feed items
+    let message_page = database.get_messages_after_sequence(
+        addr_payload,
+        MESSAGE_NAMESPACE,
+        cursor.message_sequence,
+        limit,
+    )?;
+    if !message_page.messages.is_empty() {
+        cursor.message_sequence = message_page.end_sequence;
+    }
+    sync_page.messages = message_page.messages;
+
+    let feed_page = database.get_messages_after_sequence(
+        addr_payload,
+        FEED_NAMESPACE,
+        cursor.feed_sequence,
+        limit,
+    )?;
+    if !feed_page.messages.is_empty() {
+        cursor.feed_sequence = feed_page.end_sequence;
+    }
+    sync_page.feeds = feed_page.messages;
+
+    // Get profile if changed
+    if let Some(raw_profile) = database.get_raw_profile(addr_payload)? {
+        let profile = AuthWrapper::decode(&raw_profile[..]).unwrap(); // This panics if stored bytes are malformed
+        let profile_digest = profile.digest().to_vec();
+        if profile_digest != cursor.profile_digest {
+            cursor.profile_digest = profile_digest;
+            sync_page.profile = raw_profile;
+        }
+    }
+
+    // Get deletions
+    let deleted_after = cursor.deletion_time.max(0) as u64;
+    for (namespace, message, deleted_time) in database.get_deletions(addr_payload, deleted_after) {
+        cursor.deletion_time = cursor.deletion_time.max(deleted_time as i64);
+        sync_page.deletions.push(Deletion {
+            namespace: namespace_name(namespace).to_string(),
+            payload_digest: message.payload_digest,
+            deleted_time: deleted_time as i64,
+        });
+    }
+    sync_page
+        .deletions
+        .sort_by_key(|deletion| deletion.deleted_time);
+
+    // Encode cursor
+    let mut raw_cursor = Vec::with_capacity(cursor.encoded_len());
+    cursor.encode(&mut raw_cursor).unwrap(); // This is safe
+    sync_page.cursor = hex::encode(raw_cursor);
+
+    Ok(sync_page)
+}
+
+/// Get the messages, feed items, profile updates and deletions since the cursor.
+pub async fn get_sync(
+    addr: Address,
+    query: SyncQuery,
+    database: Database,
+) -> Result<Response<Body>, SyncError> {
+    // Decode cursor, starting from the beginning if none is given
+    let cursor = match query.cursor {
+        Some(cursor_hex) => {
+            let raw_cursor = hex::decode(cursor_hex).map_err(SyncError::CursorDecode)?;
+            SyncCursor::decode(&raw_cursor[..]).map_err(SyncError::CursorMalformed)?
+        }
+        None => SyncCursor::default(),
+    };
+    let limit = query.limit.unwrap_or(usize::MAX);
+
+    let sync_page =
+        task::spawn_blocking(move || construct_sync_page(addr.as_body(), cursor, limit, &database))
+            .await
+            .unwrap()?; // Unrecoverable
+
+    // Serialize page
+    let mut raw_sync_page = Vec::with_capacity(sync_page.encoded_len());
+    sync_page.encode(&mut raw_sync_page).unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder().body(Body::from(raw_sync_page)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb::relay::Message;
+
+    use super::*;
+
+    #[test]
+    fn resume_from_cursor() {
+        let database = Database::try_new("./test_dbs/resume_from_cursor").unwrap();
+        let addr_payload = [7; 20];
+
+        let push = |timestamp: u64, namespace: u8| {
+            let message = Message {
+                received_time: timestamp as i64,
+                payload_digest: vec![timestamp as u8; 32],
+                ..Default::default()
+            };
+            database
+                .push_sequenced_message(
+                    &addr_payload,
+                    timestamp,
+                    &message.payload_digest,
+                    &[8; 20],
+                    namespace,
+                    |sequence| {
+                        let message = Message {
+                            sequence,
+                            ..message.clone()
+                        };
+                        let mut raw_message = Vec::with_capacity(message.encoded_len());
+                        message.encode(&mut raw_message).unwrap();
+                        raw_message
+                    },
+                )
+                .unwrap()
+        };
+
+        // Start from the latest changes
+        let sync_page =
+            construct_sync_page(&addr_payload, SyncCursor::default(), usize::MAX, &database)
+                .unwrap();
+        let cursor = SyncCursor::decode(&hex::decode(sync_page.cursor).unwrap()[..]).unwrap();
+
+        push(100, MESSAGE_NAMESPACE);
+        push(101, MESSAGE_NAMESPACE);
+        push(102, FEED_NAMESPACE);
+
+        // Limit applies to each namespace
+        let sync_page = construct_sync_page(&addr_payload, cursor, 1, &database).unwrap();
+        assert_eq!(sync_page.messages.len(), 1);
+        assert_eq!(sync_page.messages[0].received_time, 100);
+        assert_eq!(sync_page.feeds.len(), 1);
+        assert!(sync_page.profile.is_empty());
+
+        // Resume where the page ended
+        let cursor = SyncCursor::decode(&hex::decode(sync_page.cursor).unwrap()[..]).unwrap();
+        let sync_page = construct_sync_page(&addr_payload, cursor, usize::MAX, &database).unwrap();
+        assert_eq!(sync_page.messages.len(), 1);
+        assert_eq!(sync_page.messages[0].received_time, 101);
+        assert!(sync_page.feeds.is_empty());
+    }
+}
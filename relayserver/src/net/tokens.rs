@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use bitcoincash_addr::Address;
+use cashweb::{
+    relay::TokenInfo,
+    token::{
+        extract_pop,
+        schemes::hmac_bearer::{HmacScheme, Scopes, UnknownScope},
+        split_pop_token,
+    },
+};
+use http::header::HeaderMap;
+use prost::Message as _;
+use serde::Deserialize;
+use thiserror::Error;
+use warp::{
+    http::{header::AUTHORIZATION, Response},
+    hyper::Body,
+    reject::Reject,
+};
+
+use crate::{
+    net::{address_decode, payments::record_token_issued, AddressDecode, ToResponse},
+    TOKENS_PATH,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ScopesQuery {
+    scopes: String,
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("invalid scopes: {0}")]
+    Scopes(UnknownScope),
+    #[error("no scopes requested")]
+    EmptyScopes,
+    #[error("invalid address: {0}")]
+    Address(AddressDecode),
+    #[error("missing token")]
+    MissingToken,
+}
+
+impl Reject for TokenError {}
+
+impl ToResponse for TokenError {
+    fn to_status(&self) -> u16 {
+        400
+    }
+}
+
+/// Issue a token restricted to the requested scopes, for handing to third parties.
+pub async fn issue_scoped_token(
+    addr: Address,
+    query: ScopesQuery,
+    token_scheme: Arc<HmacScheme>,
+) -> Result<Response<Body>, TokenError> {
+    let scopes: Scopes = query.scopes.parse().map_err(TokenError::Scopes)?;
+    if scopes == Scopes::empty() {
+        return Err(TokenError::EmptyScopes);
+    }
+
+    let token = format!(
+        "POP {}",
+        token_scheme.construct_scoped_token(addr.as_body(), scopes)
+    );
+    record_token_issued(TOKENS_PATH);
+    Ok(Response::builder()
+        .header(AUTHORIZATION, token)
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntrospectQuery {
+    address: String,
+    access_token: Option<String>,
+}
+
+/// Describe a POP token, as validated against an address, without requesting payment.
+pub async fn introspect_token(
+    query: IntrospectQuery,
+    header_map: HeaderMap,
+    token_scheme: Arc<HmacScheme>,
+) -> Result<Response<Body>, TokenError> {
+    let addr = address_decode(&query.address).map_err(TokenError::Address)?;
+    let pop_token = extract_pop(&header_map)
+        .or_else(|| {
+            query
+                .access_token
+                .as_ref()
+                .and_then(|access_token| split_pop_token(access_token))
+        })
+        .ok_or(TokenError::MissingToken)?;
+
+    let mut token_info = TokenInfo {
+        address: query.address.clone(),
+        scheme: "hmac".to_string(),
+        ..Default::default()
+    };
+    match token_scheme.validate_token(addr.as_body(), pop_token) {
+        Ok(scopes) => {
+            token_info.valid = true;
+            token_info.scopes = scopes.names().into_iter().map(str::to_string).collect();
+        }
+        Err(err) => token_info.error = err.to_string(),
+    }
+
+    // Serialize token info
+    let mut raw_token_info = Vec::with_capacity(token_info.encoded_len());
+    token_info.encode(&mut raw_token_info).unwrap(); // This is safe
+
+    Ok(Response::builder()
+        .body(Body::from(raw_token_info))
+        .unwrap())
+}
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{cmp::Reverse, sync::Arc, time::Instant};
 
 use async_stream::stream;
 use bitcoincash_addr::Address;
@@ -6,11 +6,14 @@ use dashmap::DashMap;
 use futures::{pin_mut, prelude::*};
 use thiserror::Error;
 use tokio::{
-    sync::broadcast,
+    sync::broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
     time::{interval, Duration},
 };
 use tokio_stream::wrappers::IntervalStream;
-use tracing::error;
+use tracing::{error, warn};
 use warp::{
     ws::{Message, WebSocket, Ws},
     Reply,
@@ -20,22 +23,119 @@ use crate::SETTINGS;
 
 const BROADCAST_CHANNEL_CAPACITY: usize = 256;
 
-pub type MessageBus = Arc<DashMap<Vec<u8>, broadcast::Sender<Vec<u8>>>>;
+/// Close code sent to clients violating the inbound frame limits.
+const POLICY_VIOLATION: u16 = 1008;
+
+/// A serialized message, the time its `put_message` request was accepted and its stamp value.
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    pub accepted: Instant,
+    pub stamp_value: u64,
+    pub raw_message: Vec<u8>,
+}
+
+pub type MessageBus = Arc<DashMap<Vec<u8>, broadcast::Sender<Broadcast>>>;
+
+fn record_delivery(_accepted: Instant) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::WS_DELIVERY_ELAPSED.observe(_accepted.elapsed().as_millis() as f64);
+}
+
+fn record_lagged(_n_dropped: u64) {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::WS_DROPPED_TOTAL.inc_by(_n_dropped);
+}
+
+fn record_violation() {
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::WS_VIOLATIONS_TOTAL.inc();
+}
+
+/// Order a backlog of broadcasts highest stamp value first, preserving arrival order among
+/// broadcasts of equal value.
+fn prioritize(backlog: &mut [Broadcast]) {
+    backlog.sort_by_key(|broadcast| Reverse(broadcast.stamp_value));
+}
 
 pub fn upgrade_ws(addr: Address, ws: Ws, msg_bus: MessageBus) -> impl Reply {
     // Convert address
     let pubkey_hash = addr.into_body();
 
     // Upgrade socket
-    ws.on_upgrade(move |socket| connect_ws(pubkey_hash, socket, msg_bus))
+    limit_size(ws, SETTINGS.websocket.max_frame_size)
+        .on_upgrade(move |socket| connect_ws(pubkey_hash, socket, msg_bus))
+}
+
+/// Limit the size of the frames, and messages, received from a client.
+///
+/// Oversized frames are refused from their header, before they are buffered. A maximum of zero
+/// keeps the default limits.
+fn limit_size(ws: Ws, max_frame_size: usize) -> Ws {
+    if max_frame_size == 0 {
+        return ws;
+    }
+    ws.max_frame_size(max_frame_size)
+        .max_message_size(max_frame_size)
+}
+
+/// Violation of the limits on frames received from a client.
+#[derive(Debug, Error, PartialEq)]
+enum FrameViolation {
+    #[error("too many frames: {0} within {1}ms")]
+    Rate(u32, u64),
+}
+
+/// Limits the number of frames received from a client within each window.
+struct FrameLimiter {
+    max_frames: u32,
+    window: Duration,
+    window_start: Instant,
+    n_frames: u32,
+}
+
+impl FrameLimiter {
+    fn new(max_frames: u32, window: Duration, now: Instant) -> Self {
+        Self {
+            max_frames,
+            window,
+            window_start: now,
+            n_frames: 0,
+        }
+    }
+
+    /// Count a frame received at `now`, checking it against the limit.
+    ///
+    /// A maximum of zero disables the limit.
+    fn check(&mut self, now: Instant) -> Result<(), FrameViolation> {
+        if self.max_frames == 0 {
+            return Ok(());
+        }
+
+        if now.duration_since(self.window_start) >= self.window {
+            self.window_start = now;
+            self.n_frames = 0;
+        }
+        self.n_frames += 1;
+        if self.n_frames > self.max_frames {
+            return Err(FrameViolation::Rate(
+                self.n_frames,
+                self.window.as_millis() as u64,
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Error)]
 enum WsError {
     #[error("websocket send failed: {0}")]
     SinkError(warp::Error),
+    #[error("websocket receive failed: {0}")]
+    StreamError(warp::Error),
     #[error("broadcast failure: {0}")]
-    BusError(broadcast::error::RecvError),
+    BusError(RecvError),
+    #[error("policy violation: {0}")]
+    Violation(FrameViolation),
 }
 
 pub async fn connect_ws(pubkey_hash: Vec<u8>, ws: WebSocket, msg_bus: MessageBus) {
@@ -45,31 +145,183 @@ pub async fn connect_ws(pubkey_hash: Vec<u8>, ws: WebSocket, msg_bus: MessageBus
         .subscribe();
 
     // Do this until broadcast::Receiver has a stream wrapper in tokio-stream library
+    //
+    // Broadcasts queued while the socket is busy are forwarded highest stamp value first.
     let rx = stream! {
         pin_mut!(rx);
 
         loop {
-            yield rx.recv().await;
+            let mut backlog = match rx.recv().await {
+                Ok(broadcast) => vec![broadcast],
+                Err(err) => {
+                    yield Err(err);
+                    continue;
+                }
+            };
+            let mut opt_err = None;
+            loop {
+                match rx.try_recv() {
+                    Ok(broadcast) => backlog.push(broadcast),
+                    Err(TryRecvError::Lagged(n_dropped)) => {
+                        opt_err = Some(RecvError::Lagged(n_dropped));
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+            prioritize(&mut backlog);
+            for broadcast in backlog {
+                yield Ok(broadcast);
+            }
+            if let Some(err) = opt_err {
+                yield Err(err);
+            }
         }
     };
-    let rx = rx.map_ok(Message::binary).map_err(WsError::BusError);
+    let rx = rx.map(|res| match res {
+        Ok(broadcast) => Ok((
+            Some(broadcast.accepted),
+            Message::binary(broadcast.raw_message),
+        )),
+        Err(err) => {
+            if let RecvError::Lagged(n_dropped) = err {
+                record_lagged(n_dropped);
+            }
+            Err(WsError::BusError(err))
+        }
+    });
 
-    let (user_ws_tx, _) = ws.split();
+    let (mut user_ws_tx, mut user_ws_rx) = ws.split();
 
     // Setup periodic ping
     let periodic_ping = IntervalStream::new(interval(Duration::from_millis(
         SETTINGS.websocket.ping_interval,
     )))
-    .map(move |_| Ok(Message::ping(vec![])));
+    .map(move |_| Ok((None, Message::ping(vec![]))));
     let merged = stream::select(rx, periodic_ping);
+    pin_mut!(merged);
+
+    let result = {
+        // Forward messages, recording the delivery latency of each broadcast
+        let forward = async {
+            while let Some((opt_accepted, message)) = merged.try_next().await? {
+                user_ws_tx.send(message).await.map_err(WsError::SinkError)?;
+                if let Some(accepted) = opt_accepted {
+                    record_delivery(accepted);
+                }
+            }
+            Ok::<_, WsError>(())
+        };
 
-    if let Err(err) = merged
-        .forward(user_ws_tx.sink_map_err(WsError::SinkError))
-        .await
-    {
-        error!(message = "forwarding error", error = %err);
+        // Receive frames, checking them against the limits, until the client closes
+        let receive = async {
+            let mut limiter = FrameLimiter::new(
+                SETTINGS.websocket.max_inbound_frames,
+                Duration::from_millis(SETTINGS.websocket.inbound_window),
+                Instant::now(),
+            );
+            while let Some(message) = user_ws_rx.try_next().await.map_err(WsError::StreamError)? {
+                if message.is_close() {
+                    break;
+                }
+                limiter.check(Instant::now()).map_err(WsError::Violation)?;
+            }
+            Ok::<_, WsError>(())
+        };
+
+        tokio::select! {
+            result = forward => result,
+            result = receive => result,
+        }
+    };
+    match result {
+        Err(WsError::Violation(violation)) => {
+            warn!(message = "closing abusive websocket", error = %violation);
+            record_violation();
+            let close = Message::close_with(POLICY_VIOLATION, violation.to_string());
+            if let Err(err) = user_ws_tx.send(close).await {
+                error!(message = "failed to close websocket", error = %err);
+            }
+        }
+        Err(err) => error!(message = "forwarding error", error = %err),
+        Ok(()) => (),
     }
 
     // TODO: Double check this is atomic
     msg_bus.remove_if(&pubkey_hash, |_, sender| sender.receiver_count() == 0);
 }
+
+#[cfg(test)]
+mod tests {
+    use warp::Filter;
+
+    use super::*;
+
+    #[test]
+    fn prioritize_backlog() {
+        let accepted = Instant::now();
+        let mut backlog: Vec<Broadcast> = [(0, 1), (500, 2), (0, 3), (500, 4), (1_000, 5)]
+            .iter()
+            .map(|(stamp_value, id)| Broadcast {
+                accepted,
+                stamp_value: *stamp_value,
+                raw_message: vec![*id],
+            })
+            .collect();
+        prioritize(&mut backlog);
+        let ids: Vec<u8> = backlog
+            .iter()
+            .map(|broadcast| broadcast.raw_message[0])
+            .collect();
+        assert_eq!(ids, vec![5, 2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn frame_limits() {
+        let start = Instant::now();
+        let mut limiter = FrameLimiter::new(2, Duration::from_secs(1), start);
+        assert_eq!(limiter.check(start), Ok(()));
+        assert_eq!(limiter.check(start), Ok(()));
+        assert_eq!(limiter.check(start), Err(FrameViolation::Rate(3, 1_000)));
+
+        // Counts reset in the next window
+        let next_window = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(next_window), Ok(()));
+        assert_eq!(limiter.check(next_window), Ok(()));
+
+        // Zero disables the limit
+        let mut limiter = FrameLimiter::new(0, Duration::from_secs(1), start);
+        for _ in 0..100 {
+            assert_eq!(limiter.check(start), Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_frames() {
+        let (result_tx, mut result_rx) = tokio::sync::mpsc::unbounded_channel();
+        let filter = warp::ws().map(move |ws: Ws| {
+            let result_tx = result_tx.clone();
+            limit_size(ws, 16).on_upgrade(move |socket| async move {
+                let (_, mut socket_rx) = socket.split();
+                while let Some(result) = socket_rx.next().await {
+                    let is_err = result.is_err();
+                    result_tx
+                        .send(result.map(|message| message.as_bytes().to_vec()))
+                        .unwrap();
+                    if is_err {
+                        break;
+                    }
+                }
+            })
+        });
+        let mut client = warp::test::ws().handshake(filter).await.unwrap();
+
+        // Frames within the limit are received
+        client.send(Message::binary(vec![0; 16])).await;
+        assert_eq!(result_rx.recv().await.unwrap().unwrap(), vec![0; 16]);
+
+        // Oversized frames are refused
+        client.send(Message::binary(vec![0; 1 << 20])).await;
+        assert!(result_rx.recv().await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,569 @@
+use std::{
+    fs,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use cashweb::relay::{PushRegistration, PushService};
+use hyper::{
+    client::HttpConnector,
+    header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE},
+    Body, Client, Method, Request, StatusCode,
+};
+use hyper_tls::HttpsConnector;
+use ring::{
+    aead, agreement, hkdf,
+    rand::{SecureRandom, SystemRandom},
+    signature::{self, EcdsaKeyPair, KeyPair, RsaKeyPair},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use url::Url;
+
+use crate::db::Database;
+
+/// Record size advertised in the header of encrypted Web Push messages.
+const RECORD_SIZE: u32 = 4096;
+
+/// Lifetime, in seconds, of the VAPID tokens and FCM assertions issued.
+const TOKEN_LIFETIME: u64 = 60 * 60;
+
+/// OAuth scope required to send FCM messages.
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+
+/// OAuth grant type exchanging a signed assertion for an access token.
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+#[derive(Debug, Error)]
+pub enum PushError {
+    #[error("invalid VAPID key")]
+    VapidKey,
+    #[error("failed to read FCM credentials: {0}")]
+    CredentialsRead(std::io::Error),
+    #[error("failed to decode FCM credentials: {0}")]
+    CredentialsDecode(serde_json::Error),
+    #[error("invalid FCM private key")]
+    FcmKey,
+    #[error("push service not configured")]
+    Unsupported,
+    #[error("invalid subscription")]
+    InvalidSubscription,
+    #[error("failed to encrypt notification")]
+    Encryption,
+    #[error("failed to sign token")]
+    Signing,
+    #[error("request failed: {0}")]
+    Http(hyper::Error),
+    #[error("failed to decode access token: {0}")]
+    TokenDecode(serde_json::Error),
+    #[error("push service responded with {0}")]
+    Rejected(StatusCode),
+}
+
+/// Notification of a received message, carrying only its destination and payload digest.
+#[derive(Debug, Serialize)]
+pub struct MessageEvent {
+    /// Address the message was sent to.
+    pub address: String,
+    /// Hex encoded payload digest of the message.
+    pub digest: String,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn base64_url<T: AsRef<[u8]>>(data: T) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+/// Construct a JSON web token, signing the header and claims with `sign`.
+fn jwt<F>(alg: &str, claims: &serde_json::Value, sign: F) -> Result<String, PushError>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>, ring::error::Unspecified>,
+{
+    let header = json!({ "typ": "JWT", "alg": alg });
+    let signing_input = format!(
+        "{}.{}",
+        base64_url(header.to_string()),
+        base64_url(claims.to_string())
+    );
+    let signature = sign(signing_input.as_bytes()).map_err(|_| PushError::Signing)?;
+    Ok(format!("{}.{}", signing_input, base64_url(signature)))
+}
+
+/// Output length of an HKDF expansion.
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf_expand(prk: &hkdf::Prk, info: &[u8], out: &mut [u8]) {
+    prk.expand(&[info], Len(out.len()))
+        .unwrap() // This is safe as the lengths are below the limit
+        .fill(out)
+        .unwrap(); // This is safe
+}
+
+/// Derive the content encryption key and nonce of a Web Push message, per RFC 8291.
+fn derive_content_keys(
+    ecdh_secret: &[u8],
+    auth_secret: &[u8],
+    ua_public: &[u8],
+    as_public: &[u8],
+    salt: &[u8],
+) -> ([u8; 16], [u8; 12]) {
+    let prk_key = hkdf::Salt::new(hkdf::HKDF_SHA256, auth_secret).extract(ecdh_secret);
+    let key_info = [&b"WebPush: info\0"[..], ua_public, as_public].concat();
+    let mut ikm = [0; 32];
+    hkdf_expand(&prk_key, &key_info, &mut ikm);
+
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&ikm);
+    let mut cek = [0; 16];
+    hkdf_expand(&prk, b"Content-Encoding: aes128gcm\0", &mut cek);
+    let mut nonce = [0; 12];
+    hkdf_expand(&prk, b"Content-Encoding: nonce\0", &mut nonce);
+    (cek, nonce)
+}
+
+/// Encrypt a message body as a single record in the `aes128gcm` content coding.
+fn encrypt_record(
+    cek: &[u8; 16],
+    nonce: [u8; 12],
+    salt: &[u8; 16],
+    as_public: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let key = aead::UnboundKey::new(&aead::AES_128_GCM, cek).unwrap(); // This is safe
+    let key = aead::LessSafeKey::new(key);
+
+    // The final record is delimited by 2
+    let mut record = [plaintext, &[2]].concat();
+    key.seal_in_place_append_tag(
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::Aad::empty(),
+        &mut record,
+    )
+    .unwrap(); // This is safe
+    [
+        &salt[..],
+        &RECORD_SIZE.to_be_bytes(),
+        &[as_public.len() as u8],
+        as_public,
+        &record,
+    ]
+    .concat()
+}
+
+/// Encrypt a Web Push message body to the keys of a subscription, per RFC 8291.
+fn encrypt(
+    rng: &SystemRandom,
+    ua_public: &[u8],
+    auth_secret: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, PushError> {
+    let as_private = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, rng)
+        .map_err(|_| PushError::Encryption)?;
+    let as_public = as_private
+        .compute_public_key()
+        .map_err(|_| PushError::Encryption)?;
+    let mut salt = [0; 16];
+    rng.fill(&mut salt).map_err(|_| PushError::Encryption)?;
+
+    let ua_public_key = agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, ua_public);
+    let (cek, nonce) = agreement::agree_ephemeral(
+        as_private,
+        &ua_public_key,
+        PushError::InvalidSubscription,
+        |ecdh_secret| {
+            Ok(derive_content_keys(
+                ecdh_secret,
+                auth_secret,
+                ua_public,
+                as_public.as_ref(),
+                &salt,
+            ))
+        },
+    )?;
+    Ok(encrypt_record(
+        &cek,
+        nonce,
+        &salt,
+        as_public.as_ref(),
+        plaintext,
+    ))
+}
+
+/// Key identifying the server to Web Push services, per RFC 8292.
+struct Vapid {
+    key_pair: EcdsaKeyPair,
+    public_key: String,
+    subject: String,
+}
+
+impl Vapid {
+    /// Construct from a base64 encoded PKCS#8 P-256 private key and a contact URI.
+    fn new(pkcs8: &str, subject: String) -> Result<Self, PushError> {
+        let pkcs8 = base64::decode(pkcs8.trim()).map_err(|_| PushError::VapidKey)?;
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8)
+                .map_err(|_| PushError::VapidKey)?;
+        let public_key = base64_url(key_pair.public_key());
+        Ok(Self {
+            key_pair,
+            public_key,
+            subject,
+        })
+    }
+
+    /// Construct the `Authorization` header of a request to a push service endpoint.
+    fn authorization(&self, rng: &SystemRandom, endpoint: &Url) -> Result<String, PushError> {
+        let claims = json!({
+            "aud": endpoint.origin().ascii_serialization(),
+            "exp": unix_now() + TOKEN_LIFETIME,
+            "sub": self.subject,
+        });
+        let token = jwt("ES256", &claims, |signing_input| {
+            self.key_pair
+                .sign(rng, signing_input)
+                .map(|signature| signature.as_ref().to_vec())
+        })?;
+        Ok(format!("vapid t={}, k={}", token, self.public_key))
+    }
+}
+
+/// Service account credentials, as downloaded from the Firebase console.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Sends messages by the FCM HTTP v1 API, authenticating as a service account.
+struct Fcm {
+    key_pair: RsaKeyPair,
+    client_email: String,
+    token_uri: String,
+    send_url: String,
+    access_token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Fcm {
+    /// Construct from the service account credentials file at `path`.
+    fn new(path: &str) -> Result<Self, PushError> {
+        let raw_account = fs::read_to_string(path).map_err(PushError::CredentialsRead)?;
+        let account: ServiceAccount =
+            serde_json::from_str(&raw_account).map_err(PushError::CredentialsDecode)?;
+
+        // Decode the PEM encoded PKCS#8 private key
+        let der: String = account
+            .private_key
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = base64::decode(der).map_err(|_| PushError::FcmKey)?;
+        let key_pair = RsaKeyPair::from_pkcs8(&der).map_err(|_| PushError::FcmKey)?;
+
+        Ok(Self {
+            key_pair,
+            client_email: account.client_email,
+            token_uri: account.token_uri,
+            send_url: format!(
+                "https://fcm.googleapis.com/v1/projects/{}/messages:send",
+                account.project_id
+            ),
+            access_token: Mutex::new(None),
+        })
+    }
+
+    /// Get an access token, exchanging a signed assertion for a new token if the last has expired.
+    async fn access_token(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        rng: &SystemRandom,
+    ) -> Result<String, PushError> {
+        let mut cached = self.access_token.lock().await;
+        if let Some((access_token, expiry)) = &*cached {
+            if Instant::now() < *expiry {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let now = unix_now();
+        let claims = json!({
+            "iss": self.client_email,
+            "scope": FCM_SCOPE,
+            "aud": self.token_uri,
+            "iat": now,
+            "exp": now + TOKEN_LIFETIME,
+        });
+        let assertion = jwt("RS256", &claims, |signing_input| {
+            let mut signature = vec![0; self.key_pair.public_modulus_len()];
+            self.key_pair
+                .sign(
+                    &signature::RSA_PKCS1_SHA256,
+                    rng,
+                    signing_input,
+                    &mut signature,
+                )
+                .map(|_| signature)
+        })?;
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", JWT_BEARER_GRANT)
+            .append_pair("assertion", &assertion)
+            .finish();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.token_uri.as_str())
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .map_err(|_| PushError::InvalidSubscription)?;
+
+        let response = client.request(request).await.map_err(PushError::Http)?;
+        if !response.status().is_success() {
+            return Err(PushError::Rejected(response.status()));
+        }
+        let raw_token = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(PushError::Http)?;
+        let token: AccessToken =
+            serde_json::from_slice(&raw_token).map_err(PushError::TokenDecode)?;
+
+        // Refresh the token a minute before it expires
+        let expiry = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        *cached = Some((token.access_token.clone(), expiry));
+        Ok(token.access_token)
+    }
+}
+
+/// Sends push notifications to the endpoints registered by addresses.
+#[derive(Clone)]
+pub struct Notifier {
+    client: Client<HttpsConnector<HttpConnector>>,
+    rng: SystemRandom,
+    vapid: Option<Arc<Vapid>>,
+    fcm: Option<Arc<Fcm>>,
+    ttl: u64,
+}
+
+impl Notifier {
+    /// Construct a notifier.
+    ///
+    /// Web Push is supported if a VAPID key and subject are given, FCM if the path of service
+    /// account credentials is given. Notifications are retained by push services for `ttl`
+    /// seconds.
+    pub fn new(
+        vapid: Option<(&str, String)>,
+        fcm_credentials: Option<&str>,
+        ttl: u64,
+    ) -> Result<Self, PushError> {
+        let vapid = vapid
+            .map(|(key, subject)| Vapid::new(key, subject))
+            .transpose()?
+            .map(Arc::new);
+        let fcm = fcm_credentials.map(Fcm::new).transpose()?.map(Arc::new);
+        Ok(Self {
+            client: Client::builder().build(HttpsConnector::new()),
+            rng: SystemRandom::new(),
+            vapid,
+            fcm,
+            ttl,
+        })
+    }
+
+    /// Checks whether notifications can be sent by a push service.
+    pub fn supports(&self, service: PushService) -> bool {
+        match service {
+            PushService::WebPush => self.vapid.is_some(),
+            PushService::Fcm => self.fcm.is_some(),
+        }
+    }
+
+    fn web_push_request(
+        &self,
+        vapid: &Vapid,
+        registration: &PushRegistration,
+        event: &MessageEvent,
+    ) -> Result<Request<Body>, PushError> {
+        let endpoint =
+            Url::parse(&registration.endpoint).map_err(|_| PushError::InvalidSubscription)?;
+        let plaintext = serde_json::to_vec(event).unwrap(); // This is safe
+        let body = encrypt(
+            &self.rng,
+            &registration.p256dh,
+            &registration.auth,
+            &plaintext,
+        )?;
+        Request::builder()
+            .method(Method::POST)
+            .uri(registration.endpoint.as_str())
+            .header(AUTHORIZATION, vapid.authorization(&self.rng, &endpoint)?)
+            .header(CONTENT_ENCODING, "aes128gcm")
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header("TTL", self.ttl)
+            .body(Body::from(body))
+            .map_err(|_| PushError::InvalidSubscription)
+    }
+
+    async fn fcm_request(
+        &self,
+        fcm: &Fcm,
+        registration: &PushRegistration,
+        event: &MessageEvent,
+    ) -> Result<Request<Body>, PushError> {
+        let access_token = fcm.access_token(&self.client, &self.rng).await?;
+        let message = json!({
+            "message": {
+                "token": registration.endpoint,
+                "data": {
+                    "address": event.address,
+                    "digest": event.digest,
+                },
+                "android": { "ttl": format!("{}s", self.ttl) },
+            }
+        });
+        Request::builder()
+            .method(Method::POST)
+            .uri(fcm.send_url.as_str())
+            .header(AUTHORIZATION, format!("Bearer {}", access_token))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(message.to_string()))
+            .map_err(|_| PushError::InvalidSubscription)
+    }
+
+    async fn send(
+        &self,
+        registration: &PushRegistration,
+        event: &MessageEvent,
+    ) -> Result<(), PushError> {
+        let request = match PushService::from_i32(registration.service) {
+            Some(PushService::WebPush) => {
+                let vapid = self.vapid.as_ref().ok_or(PushError::Unsupported)?;
+                self.web_push_request(vapid, registration, event)?
+            }
+            Some(PushService::Fcm) => {
+                let fcm = self.fcm.as_ref().ok_or(PushError::Unsupported)?;
+                self.fcm_request(fcm, registration, event).await?
+            }
+            None => return Err(PushError::Unsupported),
+        };
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(PushError::Http)?;
+        if !response.status().is_success() {
+            return Err(PushError::Rejected(response.status()));
+        }
+        Ok(())
+    }
+
+    /// Notify the endpoints registered by an address of a message, removing registrations which
+    /// the push service reports have expired.
+    ///
+    /// Notifications are sent in the background and failures are only logged.
+    pub fn notify(&self, database: Database, pubkey_hash: Vec<u8>, event: MessageEvent) {
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            let registrations = match database.get_push_registrations(&pubkey_hash) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    error!(message = "failed to get push registrations", error = %err);
+                    return;
+                }
+            };
+            for registration in registrations {
+                match notifier.send(&registration, &event).await {
+                    Ok(()) => info!(message = "push notification sent", address = %event.address),
+                    Err(PushError::Rejected(status))
+                        if status == StatusCode::NOT_FOUND || status == StatusCode::GONE =>
+                    {
+                        info!(
+                            message = "removing expired push registration",
+                            address = %event.address
+                        );
+                        if let Err(err) =
+                            database.remove_push_registration(&pubkey_hash, &registration.endpoint)
+                        {
+                            error!(message = "failed to remove push registration", error = %err);
+                        }
+                    }
+                    Err(err) => warn!(message = "push notification failed", error = %err),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_record() {
+        let salt = [1; 16];
+        let as_public = [4; 65];
+        let (cek, nonce) = derive_content_keys(&[2; 32], &[3; 16], &[5; 65], &as_public, &salt);
+        let plaintext = b"{\"address\":\"a\",\"digest\":\"00\"}";
+        let body = encrypt_record(&cek, nonce, &salt, &as_public, plaintext);
+
+        // Header carries the salt, record size and the application server public key
+        assert_eq!(&body[..16], &salt);
+        assert_eq!(&body[16..20], &RECORD_SIZE.to_be_bytes());
+        assert_eq!(body[20], 65);
+        assert_eq!(&body[21..86], &as_public[..]);
+
+        // Record decrypts to the delimited plaintext
+        let mut record = body[86..].to_vec();
+        assert_eq!(record.len(), plaintext.len() + 1 + 16);
+        let key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek).unwrap());
+        let opened = key
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut record,
+            )
+            .unwrap();
+        assert_eq!(&opened[..plaintext.len()], &plaintext[..]);
+        assert_eq!(opened[plaintext.len()], 2);
+
+        // Keys depend on the salt
+        let (other_cek, _) =
+            derive_content_keys(&[2; 32], &[3; 16], &[5; 65], &as_public, &[0; 16]);
+        assert_ne!(cek, other_cek);
+    }
+
+    #[test]
+    fn signed_jwt() {
+        let claims = json!({ "sub": "mailto:ops@example.com" });
+        let token = jwt("ES256", &claims, |_| Ok(vec![1, 2, 3])).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let header: serde_json::Value = serde_json::from_slice(
+            &base64::decode_config(parts[0], base64::URL_SAFE_NO_PAD).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(header["alg"], "ES256");
+        assert_eq!(
+            base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD).unwrap(),
+            vec![1, 2, 3]
+        );
+    }
+}
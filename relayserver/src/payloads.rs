@@ -0,0 +1,172 @@
+use cashweb::relay::Message;
+use rusoto_core::{credential::StaticProvider, ByteStream, HttpClient, Region, RusotoError};
+use rusoto_s3::{
+    DeleteObjectError, DeleteObjectRequest, GetObjectError, GetObjectRequest, PutObjectError,
+    PutObjectRequest, S3Client, S3,
+};
+use thiserror::Error;
+use tracing::warn;
+use warp::hyper::Body;
+
+#[derive(Debug, Error)]
+pub enum PayloadStoreError {
+    #[error("failed to put payload: {0}")]
+    Put(RusotoError<PutObjectError>),
+    #[error("failed to get payload: {0}")]
+    Get(RusotoError<GetObjectError>),
+    #[error("failed to delete payload: {0}")]
+    Delete(RusotoError<DeleteObjectError>),
+    #[error("payload not found")]
+    NotFound,
+}
+
+/// Stores large message payloads in S3-compatible object storage.
+///
+/// Payloads are keyed by their digest, so the copies delivered to the source and destination share
+/// a single object. The message, with its payload removed, remains in the database, which records
+/// the mailboxes holding the object. The object is deleted once no mailbox holds it.
+#[derive(Clone)]
+pub struct PayloadStore {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+    threshold: usize,
+}
+
+impl PayloadStore {
+    /// Construct a payload store.
+    ///
+    /// If no endpoint is given then the AWS endpoint for the region is used. If no credentials are
+    /// given then they are sourced from the environment.
+    pub fn new(
+        bucket: String,
+        prefix: String,
+        region: String,
+        endpoint: Option<String>,
+        credentials: Option<(String, String)>,
+        threshold: usize,
+    ) -> Self {
+        let region = match endpoint {
+            Some(endpoint) => Region::Custom {
+                name: region,
+                endpoint,
+            },
+            None => region.parse().expect("unable to interpret region"),
+        };
+        let client = match credentials {
+            Some((access_key, secret_key)) => S3Client::new_with(
+                HttpClient::new().expect("failed to construct http client"),
+                StaticProvider::new_minimal(access_key, secret_key),
+                region,
+            ),
+            None => S3Client::new(region),
+        };
+        Self {
+            client,
+            bucket,
+            prefix,
+            threshold,
+        }
+    }
+
+    fn object_key(&self, payload_digest: &[u8]) -> String {
+        format!("{}{}", self.prefix, hex::encode(payload_digest))
+    }
+
+    /// Checks whether a payload is large enough to be offloaded.
+    pub fn should_offload(&self, payload: &[u8]) -> bool {
+        !payload.is_empty() && payload.len() >= self.threshold
+    }
+
+    /// Moves the payload of a message to object storage, if it meets the threshold.
+    ///
+    /// Returns whether the payload was offloaded.
+    pub async fn offload(&self, message: &mut Message) -> Result<bool, PayloadStoreError> {
+        if !self.should_offload(&message.payload) {
+            return Ok(false);
+        }
+
+        // The size is kept so clients know what to fetch
+        let payload = std::mem::take(&mut message.payload);
+        message.payload_size = payload.len() as u64;
+        let request = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.object_key(&message.payload_digest),
+            content_length: Some(payload.len() as i64),
+            body: Some(ByteStream::from(payload)),
+            ..Default::default()
+        };
+        self.client
+            .put_object(request)
+            .await
+            .map_err(PayloadStoreError::Put)?;
+        Ok(true)
+    }
+
+    /// Streams a payload from object storage.
+    pub async fn get(&self, payload_digest: &[u8]) -> Result<Body, PayloadStoreError> {
+        let request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.object_key(payload_digest),
+            ..Default::default()
+        };
+        let output = self
+            .client
+            .get_object(request)
+            .await
+            .map_err(|err| match err {
+                RusotoError::Service(GetObjectError::NoSuchKey(_)) => PayloadStoreError::NotFound,
+                err => PayloadStoreError::Get(err),
+            })?;
+        let body = output.body.ok_or(PayloadStoreError::NotFound)?;
+        Ok(Body::wrap_stream(body))
+    }
+
+    /// Deletes a payload from object storage.
+    pub async fn delete(&self, payload_digest: &[u8]) -> Result<(), PayloadStoreError> {
+        let request = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.object_key(payload_digest),
+            ..Default::default()
+        };
+        self.client
+            .delete_object(request)
+            .await
+            .map_err(PayloadStoreError::Delete)?;
+        Ok(())
+    }
+
+    /// Deletes payloads which are no longer referred to, logging failures.
+    pub async fn delete_orphans(&self, payload_digests: &[[u8; 32]]) {
+        for payload_digest in payload_digests {
+            if let Err(err) = self.delete(payload_digest).await {
+                warn!(
+                    message = "failed to delete payload",
+                    payload_digest = %hex::encode(payload_digest),
+                    error = %err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offload_threshold() {
+        let store = PayloadStore::new(
+            "bucket".to_string(),
+            "payloads/".to_string(),
+            "us-east-1".to_string(),
+            Some("http://127.0.0.1:9000".to_string()),
+            Some(("access".to_string(), "secret".to_string())),
+            4,
+        );
+        assert!(!store.should_offload(&[]));
+        assert!(!store.should_offload(&[0; 3]));
+        assert!(store.should_offload(&[0; 4]));
+        assert_eq!(store.object_key(&[0xab, 0xcd]), "payloads/abcd");
+    }
+}
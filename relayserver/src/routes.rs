@@ -0,0 +1,572 @@
+//! This module contains the construction of the REST API, shared by `main` and the test harness.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use cashweb::{
+    bitcoin_client::{ChainBackend, FailoverClient},
+    payments::{preprocess_payment, wallet::Wallet},
+    token::schemes::hmac_bearer::{HmacScheme, Scopes},
+};
+use futures::prelude::*;
+use serde::Deserialize;
+use tracing::info;
+use warp::{filters::BoxedFilter, http::Response, hyper::Body, Filter};
+
+use crate::{
+    db::{Database, FEED_NAMESPACE, MESSAGE_NAMESPACE},
+    net::{self, MessageBus},
+    notifications::Notifier,
+    payloads::PayloadStore,
+    AVATAR_PATH, CAPABILITIES_PATH, CONVERSATIONS_PATH, EXPORT_PATH, FEEDS_PATH, IMPORT_PATH,
+    INBOX_PATH, INTROSPECT_PATH, MESSAGES_PATH, NOTIFICATIONS_PATH, PAYLOADS_PATH, PAYMENTS_PATH,
+    PROFILES_PATH, RESTORE_PATH, SEARCH_PATH, SEARCH_SIZE_LIMIT, SETTINGS, SUMMARY_PATH, SYNC_PATH,
+    TOKENS_PATH, WS_PATH,
+};
+
+#[derive(Debug, Deserialize)]
+struct QueryAccessToken {
+    access_token: Option<String>,
+}
+
+/// Construct the REST API, without CORS or tracing.
+pub fn routes(
+    db: Database,
+    payload_store: Option<PayloadStore>,
+    bitcoin_client: FailoverClient<ChainBackend>,
+    notifier: Option<Notifier>,
+    msg_bus: MessageBus,
+    feed_bus: MessageBus,
+) -> BoxedFilter<(Response<Body>,)> {
+    // Database state
+    let db_state = warp::any().map(move || db.clone());
+    let payload_store_state = warp::any().map(move || payload_store.clone());
+
+    // Push notification state
+    let notifier_state = warp::any().map(move || notifier.clone());
+
+    // Message broadcast state
+    let msg_bus_state = warp::any().map(move || msg_bus.clone());
+
+    // Feed broadcast state
+    let feed_bus_state = warp::any().map(move || feed_bus.clone());
+
+    // Bitcoin client state
+    let bitcoin_client_state = warp::any().map(move || bitcoin_client.clone());
+
+    // Wallet state
+    info!(
+        message = "constructing wallet",
+        timeout = SETTINGS.payments.timeout
+    );
+    let wallet = Wallet::new(Duration::from_millis(SETTINGS.payments.timeout));
+    let wallet_state = warp::any().map(move || wallet.clone());
+
+    // Profile schema state
+    let profile_schema = net::ProfileSchema {
+        max_entries: SETTINGS.profiles.max_entries,
+        max_entry_size: SETTINGS.profiles.max_entry_size,
+        allowed_kinds: Arc::new(SETTINGS.profiles.allowed_kinds.clone()),
+        max_avatar_size: SETTINGS.profiles.avatar_max_size,
+        max_avatar_dimension: SETTINGS.profiles.avatar_max_dimension,
+        thumbnail_sizes: Arc::new(SETTINGS.profiles.thumbnail_sizes.clone()),
+    };
+    let profile_schema_state = warp::any().map(move || profile_schema.clone());
+
+    // Export limiter state
+    let export_limiter =
+        net::ExportLimiter::new(Duration::from_millis(SETTINGS.archive.export_interval));
+    let export_limiter_state = warp::any().map(move || export_limiter.clone());
+
+    // Firewall
+    let parse_cidrs = |cidrs: &[String]| -> Vec<net::Cidr> {
+        cidrs
+            .iter()
+            .map(|cidr| cidr.parse().expect("unable to interpret firewall CIDR"))
+            .collect()
+    };
+    let firewall = net::Firewall::new(
+        parse_cidrs(&SETTINGS.firewall.allow),
+        parse_cidrs(&SETTINGS.firewall.deny),
+        SETTINGS.firewall.max_strikes,
+        Duration::from_millis(SETTINGS.firewall.strike_window),
+        Duration::from_millis(SETTINGS.firewall.ban_duration),
+    )
+    .with_trusted_proxies(parse_cidrs(&SETTINGS.firewall.trusted_proxies));
+    let firewall_state = warp::any().map(move || firewall.clone());
+    let firewall_check = warp::addr::remote()
+        .and(warp::header::optional::<String>(net::FORWARDED_FOR))
+        .and(firewall_state.clone())
+        .and_then(
+            move |remote: Option<SocketAddr>,
+                  forwarded_for: Option<String>,
+                  firewall: net::Firewall| async move {
+                firewall
+                    .check(firewall.client_ip(remote, forwarded_for.as_deref()))
+                    .map_err(warp::reject::custom)
+            },
+        )
+        .untuple_one();
+
+    // Load shedding
+    let load_shedder = net::LoadShedder::new(
+        SETTINGS.load_shedding.max_concurrent,
+        Duration::from_millis(SETTINGS.load_shedding.queue_timeout),
+    );
+    let load_shed = warp::any().and_then(move || {
+        let load_shedder = load_shedder.clone();
+        async move { load_shedder.acquire().await.map_err(warp::reject::custom) }
+    });
+
+    // Address string converter
+    let addr_base = warp::path::param().and_then(|addr_str: String| async move {
+        net::address_decode(&addr_str).map_err(warp::reject::custom)
+    });
+
+    // Token generator
+    let key =
+        hex::decode(&SETTINGS.payments.hmac_secret).expect("unable to interpret hmac key as hex");
+    let token_scheme = Arc::new(HmacScheme::new(&key));
+    let token_scheme_state = warp::any().map(move || token_scheme.clone());
+
+    // Protection
+    let addr_protected = |scopes: Scopes| {
+        addr_base
+            .and(warp::path::full())
+            .and(warp::header::headers_cloned())
+            .and(warp::query())
+            .and(token_scheme_state.clone())
+            .and(wallet_state.clone())
+            .and(bitcoin_client_state.clone())
+            .and_then(
+                move |addr,
+                      path,
+                      headers,
+                      query: QueryAccessToken,
+                      token_scheme,
+                      wallet,
+                      bitcoin| {
+                    net::pop_protection(
+                        addr,
+                        path,
+                        headers,
+                        query.access_token,
+                        token_scheme,
+                        wallet,
+                        bitcoin,
+                        scopes,
+                    )
+                    .map_err(warp::reject::custom)
+                },
+            )
+    };
+
+    info!("constructing handlers");
+
+    // Message handlers
+    let messages_get = warp::path(MESSAGES_PATH)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(net::get_or_head())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::get_messages(addr, query, db, MESSAGE_NAMESPACE).map_err(warp::reject::custom)
+        })
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(net::encode_response);
+    let messages_put = warp::path(MESSAGES_PATH)
+        .and(addr_base)
+        .and(warp::put())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.message_size,
+        ))
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(move |content_encoding, body| {
+            net::decode_body(content_encoding, body).map_err(warp::reject::custom)
+        })
+        .and(warp::header::optional::<u64>(net::MESSAGE_TTL))
+        .and(db_state.clone())
+        .and(payload_store_state.clone())
+        .and(bitcoin_client_state.clone())
+        .and(msg_bus_state.clone())
+        .and(notifier_state.clone())
+        .and_then(
+            move |addr, body, ttl, db, payload_store, bitcoin_client, msg_bus, notifier| {
+                net::put_message(
+                    addr,
+                    body,
+                    db,
+                    payload_store,
+                    bitcoin_client,
+                    msg_bus,
+                    notifier,
+                    ttl,
+                    MESSAGE_NAMESPACE,
+                )
+                .map_err(warp::reject::custom)
+            },
+        );
+    let messages_restore = warp::path(MESSAGES_PATH)
+        .and(addr_protected(Scopes::DELETE))
+        .and(warp::path(RESTORE_PATH))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::restore_message(addr, query, db, MESSAGE_NAMESPACE).map_err(warp::reject::custom)
+        });
+    let messages_search = warp::path(MESSAGES_PATH)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::path(SEARCH_PATH))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(SEARCH_SIZE_LIMIT))
+        .and(warp::body::bytes())
+        .and(db_state.clone())
+        .and_then(move |addr, body, db| {
+            net::search_messages(addr, body, db).map_err(warp::reject::custom)
+        })
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(net::encode_response);
+    let messages_delete = warp::path(MESSAGES_PATH)
+        .and(addr_protected(Scopes::DELETE))
+        .and(warp::delete())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and(payload_store_state.clone())
+        .and_then(move |addr, query, db, payload_store| {
+            net::remove_messages(addr, query, db, payload_store, MESSAGE_NAMESPACE)
+                .map_err(warp::reject::custom)
+        });
+
+    // Feature toggles
+    let feeds_enabled = net::require_feature("feeds", SETTINGS.features.feeds);
+    let payloads_enabled = net::require_feature("payloads", SETTINGS.features.payloads);
+    let websocket_enabled = net::require_feature("websockets", SETTINGS.features.websocket);
+
+    // Feed handlers
+    let feeds_get = warp::path(FEEDS_PATH)
+        .and(feeds_enabled.clone())
+        .and(addr_base)
+        .and(net::get_or_head())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::get_messages(addr, query, db, FEED_NAMESPACE).map_err(warp::reject::custom)
+        })
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(net::encode_response);
+    let feeds_put = warp::path(FEEDS_PATH)
+        .and(feeds_enabled.clone())
+        .and(addr_protected(Scopes::FEEDS))
+        .and(warp::put())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.message_size,
+        ))
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(move |content_encoding, body| {
+            net::decode_body(content_encoding, body).map_err(warp::reject::custom)
+        })
+        .and(warp::header::optional::<u64>(net::MESSAGE_TTL))
+        .and(db_state.clone())
+        .and(payload_store_state.clone())
+        .and(bitcoin_client_state.clone())
+        .and(msg_bus_state.clone())
+        .and_then(
+            move |addr, body, ttl, db, payload_store, bitcoin_client, msg_bus| {
+                net::put_message(
+                    addr,
+                    body,
+                    db,
+                    payload_store,
+                    bitcoin_client,
+                    msg_bus,
+                    None,
+                    ttl,
+                    FEED_NAMESPACE,
+                )
+                .map_err(warp::reject::custom)
+            },
+        );
+    let feeds_restore = warp::path(FEEDS_PATH)
+        .and(feeds_enabled.clone())
+        .and(addr_protected(Scopes::FEEDS))
+        .and(warp::path(RESTORE_PATH))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::restore_message(addr, query, db, FEED_NAMESPACE).map_err(warp::reject::custom)
+        });
+    let feeds_delete = warp::path(FEEDS_PATH)
+        .and(feeds_enabled.clone())
+        .and(addr_protected(Scopes::FEEDS))
+        .and(warp::delete())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and(payload_store_state.clone())
+        .and_then(move |addr, query, db, payload_store| {
+            net::remove_messages(addr, query, db, payload_store, FEED_NAMESPACE)
+                .map_err(warp::reject::custom)
+        });
+
+    // Payload handlers
+    let payloads_get = warp::path(PAYLOADS_PATH)
+        .and(payloads_enabled)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(net::get_or_head())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and(payload_store_state.clone())
+        .and_then(move |addr, query, db, payload_store| {
+            net::get_payloads(addr, query, db, payload_store, MESSAGE_NAMESPACE)
+                .map_err(warp::reject::custom)
+        });
+
+    // Notification handlers
+    let notifications_enabled =
+        net::require_feature("notifications", SETTINGS.notifications.enabled);
+    let notifications_put = warp::path(NOTIFICATIONS_PATH)
+        .and(notifications_enabled.clone())
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.profile_size,
+        ))
+        .and(warp::body::bytes())
+        .and(db_state.clone())
+        .and(notifier_state)
+        .and_then(move |addr, body, db, notifier| {
+            net::put_push_registration(addr, body, db, notifier).map_err(warp::reject::custom)
+        });
+    let notifications_delete = warp::path(NOTIFICATIONS_PATH)
+        .and(notifications_enabled)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::delete_push_registration(addr, query, db).map_err(warp::reject::custom)
+        });
+
+    // Inbox handlers
+    let inbox_summary_get = warp::path(INBOX_PATH)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::path(SUMMARY_PATH))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::get_inbox_summary(addr, query, db).map_err(warp::reject::custom)
+        });
+
+    // Conversation handlers
+    let conversations_get = warp::path(CONVERSATIONS_PATH)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::get())
+        .and(db_state.clone())
+        .and_then(move |addr, db| net::get_conversations(addr, db).map_err(warp::reject::custom));
+
+    // Sync handlers
+    let sync_get = warp::path(SYNC_PATH)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::get())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::get_sync(addr, query, db).map_err(warp::reject::custom)
+        })
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(net::encode_response);
+
+    // Archive handlers
+    let export_get = warp::path(EXPORT_PATH)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_state.clone())
+        .and(payload_store_state.clone())
+        .and(export_limiter_state)
+        .and_then(move |addr, db, payload_store, limiter| {
+            net::get_export(addr, db, payload_store, limiter).map_err(warp::reject::custom)
+        });
+    let import_post = warp::path(IMPORT_PATH)
+        .and(addr_protected(Scopes::ALL))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            SETTINGS.archive.import_size,
+        ))
+        .and(warp::body::bytes())
+        .and(db_state.clone())
+        .and(payload_store_state)
+        .and(profile_schema_state.clone())
+        .and_then(move |addr, body, db, payload_store, schema| {
+            net::post_import(addr, body, db, payload_store, schema).map_err(warp::reject::custom)
+        });
+
+    // Websocket handlers
+    let websocket_messages = warp::path(WS_PATH)
+        .and(websocket_enabled.clone())
+        .and(warp::path(MESSAGES_PATH))
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::ws())
+        .and(msg_bus_state.clone())
+        .map(net::upgrade_ws);
+
+    let websocket_feeds = warp::path(WS_PATH)
+        .and(websocket_enabled.clone())
+        .and(warp::path(FEEDS_PATH))
+        .and(feeds_enabled)
+        .and(addr_base)
+        .and(warp::ws())
+        .and(feed_bus_state)
+        .map(net::upgrade_ws);
+
+    let websocket_messages_fallback = warp::path(WS_PATH)
+        .and(websocket_enabled)
+        .and(addr_protected(Scopes::READ_MESSAGES))
+        .and(warp::ws())
+        .and(msg_bus_state.clone())
+        .map(net::upgrade_ws);
+
+    // Profile handlers
+    let profile_get = warp::path(PROFILES_PATH)
+        .and(addr_base)
+        .and(net::get_or_head())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(db_state.clone())
+        .and_then(move |addr, if_none_match, db| {
+            net::get_profile(addr, if_none_match, db).map_err(warp::reject::custom)
+        });
+    let avatar_get = warp::path(PROFILES_PATH)
+        .and(addr_base)
+        .and(warp::path(AVATAR_PATH))
+        .and(warp::path::end())
+        .and(net::get_or_head())
+        .and(warp::query::<net::AvatarQuery>())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::get_avatar(addr, query, db).map_err(warp::reject::custom)
+        });
+    let profile_put = warp::path(PROFILES_PATH)
+        .and(addr_protected(Scopes::WRITE_PROFILE))
+        .and(warp::put())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.profile_size,
+        ))
+        .and(warp::body::bytes())
+        .and(db_state)
+        .and(profile_schema_state)
+        .and_then(move |addr, body, db, schema| {
+            net::put_profile(addr, body, db, schema).map_err(warp::reject::custom)
+        });
+
+    // Token handlers
+    let tokens_introspect = warp::path(TOKENS_PATH)
+        .and(warp::path(INTROSPECT_PATH))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::header::headers_cloned())
+        .and(token_scheme_state.clone())
+        .and_then(move |query, headers, token_scheme| {
+            net::introspect_token(query, headers, token_scheme).map_err(warp::reject::custom)
+        });
+    let tokens_post = warp::path(TOKENS_PATH)
+        .and(addr_protected(Scopes::ALL))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query())
+        .and(token_scheme_state.clone())
+        .and_then(move |addr, query, token_scheme| {
+            net::issue_scoped_token(addr, query, token_scheme).map_err(warp::reject::custom)
+        });
+
+    // Payment handler
+    let payments = warp::path(PAYMENTS_PATH)
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.profile_size,
+        ))
+        .and(warp::body::bytes())
+        .and_then(move |headers, body| {
+            preprocess_payment(headers, body)
+                .map_err(net::PaymentError::Preprocess)
+                .map_err(warp::reject::custom)
+        })
+        .and(wallet_state.clone())
+        .and(bitcoin_client_state.clone())
+        .and(token_scheme_state)
+        .and_then(
+            move |payment, wallet, bitcoin_client, token_state| async move {
+                net::process_payment(payment, wallet, bitcoin_client, token_state)
+                    .await
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // Capabilities handler
+    let raw_capabilities = net::construct_capabilities();
+    let capabilities_get = warp::path(CAPABILITIES_PATH)
+        .and(warp::path::end())
+        .and(net::get_or_head().or(warp::options()).unify())
+        .map(move || raw_capabilities.clone())
+        .and_then(net::get_capabilities);
+
+    // Root handler
+    let root = warp::path::end()
+        .and(warp::get())
+        .and(warp::fs::file("./static/index.html"));
+
+    firewall_check
+        .and(load_shed)
+        .and(
+            root.or(capabilities_get)
+                .or(payments)
+                .or(websocket_messages)
+                .or(websocket_feeds)
+                .or(websocket_messages_fallback)
+                .or(messages_get)
+                .or(messages_restore)
+                .or(messages_search)
+                .or(messages_delete)
+                .or(messages_put)
+                .or(feeds_get)
+                .or(feeds_restore)
+                .or(feeds_delete)
+                .or(feeds_put)
+                .or(payloads_get)
+                .or(notifications_put)
+                .or(notifications_delete)
+                .or(inbox_summary_get)
+                .or(sync_get)
+                .or(conversations_get)
+                .or(export_get)
+                .or(import_post)
+                .or(avatar_get)
+                .or(profile_get)
+                .or(profile_put)
+                .or(tokens_introspect)
+                .or(tokens_post),
+        )
+        .map(net::release_permit)
+        .recover(net::handle_rejection)
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::optional::<String>(net::REQUEST_ID))
+        .map(net::render_problem)
+        .and(warp::method())
+        .map(net::head_response)
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>(net::FORWARDED_FOR))
+        .and(firewall_state)
+        .map(net::record_response)
+        .boxed()
+}
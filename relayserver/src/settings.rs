@@ -1,33 +1,138 @@
-use std::net::SocketAddr;
-
-use cashweb::bitcoin::Network;
-use clap::App;
-use config::{Config, ConfigError, File};
+use cashweb::{
+    bitcoin::Network,
+    bitcoin_client::{Auth, BitcoinClientHTTP, BitcoinClientUnix, ChainBackend, IndexerClient},
+    relay::stamp::DEFAULT_STAMP_DERIVATION_PATH,
+    token::schemes::hmac_bearer::Scopes,
+};
+use cashweb_server_core::settings::{
+    home_dir, merge_config_file, one_or_many, BindAddr, LoadShedding, UNIX_PREFIX,
+};
+use clap::{App, ArgMatches};
+use config::{Config, ConfigError};
 use serde::Deserialize;
 
 const FOLDER_DIR: &str = ".relay";
 const DEFAULT_BIND: &str = "127.0.0.1:8080";
+const DEFAULT_DB_SLOW_THRESHOLD: u64 = 100;
 const DEFAULT_RPC_ADDR: &str = "http://127.0.0.1:18443";
 const DEFAULT_RPC_USER: &str = "user";
 const DEFAULT_RPC_PASSWORD: &str = "password";
+const DEFAULT_RPC_AUTH: &str = "password";
+const DEFAULT_RPC_BACKEND: &str = "bitcoind";
+const DEFAULT_RPC_HEALTH_CHECK_INTERVAL: u64 = 10_000; // 10 seconds
 const DEFAULT_NETWORK: &str = "regtest";
+const DEFAULT_ADDRESS_PREFIXES: &[String] = &[];
 const DEFAULT_PING_INTERVAL: u64 = 10_000;
+const DEFAULT_WS_MAX_FRAME_SIZE: usize = 1024 * 4; // 4Kb
+const DEFAULT_WS_MAX_INBOUND_FRAMES: u32 = 30;
+const DEFAULT_WS_INBOUND_WINDOW: u64 = 1_000 * 10; // 10 seconds
 const DEFAULT_MESSAGE_LIMIT: usize = 1024 * 1024 * 20; // 20Mb
 const DEFAULT_PROFILE_LIMIT: usize = 1024 * 512; // 512Kb
 const DEFAULT_PAYMENT_LIMIT: usize = 1024 * 3; // 3Kb
+const DEFAULT_PAYMENT_TRANSACTIONS_LIMIT: usize = 8;
+const DEFAULT_REMOVALS_LIMIT: usize = 1_000;
+const DEFAULT_SEARCH_RESULTS_LIMIT: usize = 100;
+const DEFAULT_TOMBSTONE_GRACE_PERIOD: u64 = 1_000 * 60 * 60 * 24; // 24 hours
+const DEFAULT_TOMBSTONE_PURGE_INTERVAL: u64 = 1_000 * 60 * 5; // 5 minutes
+const DEFAULT_MAX_TTL: u64 = 1_000 * 60 * 60 * 24 * 30; // 30 days
+const DEFAULT_EXPIRY_PRUNE_INTERVAL: u64 = 1_000 * 60; // 1 minute
+const DEFAULT_DIGEST_SCAVENGE_INTERVAL: u64 = 1_000 * 60 * 60; // 1 hour
 const DEFAULT_PAYMENT_TIMEOUT: usize = 1_000 * 60; // 60 seconds
 const DEFAULT_TRUNCATION_LENGTH: usize = 500;
+const DEFAULT_PROFILE_MAX_ENTRIES: usize = 32;
+const DEFAULT_PROFILE_MAX_ENTRY_SIZE: usize = 1024 * 256; // 256Kb
+const DEFAULT_AVATAR_MAX_SIZE: usize = 1024 * 128; // 128Kb
+const DEFAULT_AVATAR_MAX_DIMENSION: u32 = 1024;
+const DEFAULT_THUMBNAIL_SIZES: [u32; 2] = [64, 256];
 const DEFAULT_TOKEN_FEE: u64 = 100_000;
+const DEFAULT_MIN_FEE_RATE: u64 = 0;
+const DEFAULT_REJECT_UNCONFIRMED_PARENTS: bool = false;
+const DEFAULT_DSPROOF_WAIT: u64 = 0;
+const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024; // 1Kb
+const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
 const DEFAULT_MEMO: &str = "Thanks for your custom!";
+const DEFAULT_NOTIFICATIONS_ENABLED: bool = false;
+const DEFAULT_NOTIFICATION_TTL: u64 = 60 * 60 * 24; // 24 hours
+const DEFAULT_MAX_PUSH_REGISTRATIONS: usize = 8;
+const DEFAULT_PAYLOAD_STORAGE_REGION: &str = "us-east-1";
+const DEFAULT_PAYLOAD_STORAGE_PREFIX: &str = "payloads/";
+const DEFAULT_PAYLOAD_STORAGE_THRESHOLD: usize = 1024 * 64; // 64Kb
+const DEFAULT_PROFILE_MAX_AGE: u64 = 60;
+const DEFAULT_MAX_CONCURRENT: usize = 1024;
+const DEFAULT_QUEUE_TIMEOUT: u64 = 1_000; // 1 second
+const DEFAULT_FIREWALL_MAX_STRIKES: u32 = 0;
+const DEFAULT_FIREWALL_STRIKE_WINDOW: u64 = 1_000 * 60; // 1 minute
+const DEFAULT_FIREWALL_BAN_DURATION: u64 = 1_000 * 60 * 10; // 10 minutes
+const DEFAULT_EXPORT_INTERVAL: u64 = 1_000 * 60 * 60; // 1 hour
+const DEFAULT_IMPORT_LIMIT: usize = 1024 * 1024 * 256; // 256Mb
+const DEFAULT_ACCEPT_BURN_STAMPS: bool = false;
+const DEFAULT_FEATURE_FEEDS: bool = true;
+const DEFAULT_FEATURE_PAYLOADS: bool = true;
+const DEFAULT_FEATURE_WEBSOCKET: bool = true;
+const DEFAULT_SWEEP_INTERVAL: u64 = 1_000 * 60 * 60; // 1 hour
+const DEFAULT_SWEEP_THRESHOLD: u64 = 1_000_000;
+const DEFAULT_SWEEP_FEE_RATE: u64 = 1_000;
+const DEFAULT_SWEEP_MIN_CONFIRMATIONS: u32 = 1;
+const DEFAULT_SWEEP_MAX_INPUTS: usize = 500;
 
 #[cfg(feature = "monitoring")]
 const DEFAULT_BIND_PROM: &str = "127.0.0.1:9095";
 
+/// Method of authenticating with the bitcoind JSON-RPC server.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcAuth {
+    Password,
+    Cookie,
+    None,
+}
+
+/// Chain backend the bitcoin client connects to.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcBackend {
+    Bitcoind,
+    Indexer,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct BitcoinRpc {
+    pub backend: RpcBackend,
     pub address: String,
+    pub auth: RpcAuth,
     pub username: String,
     pub password: String,
+    pub cookie_file: Option<String>,
+    pub fallback_addresses: Vec<String>,
+    pub health_check_interval: u64,
+}
+
+impl BitcoinRpc {
+    /// The authentication used by the bitcoin client.
+    pub fn client_auth(&self) -> Auth {
+        match self.auth {
+            RpcAuth::Password => Auth::UserPass(self.username.clone(), self.password.clone()),
+            RpcAuth::Cookie => {
+                Auth::CookieFile(self.cookie_file.clone().unwrap_or_default().into())
+            }
+            RpcAuth::None => Auth::None,
+        }
+    }
+
+    /// Construct a client for the chain backend at `address`.
+    pub fn client(&self, address: &str) -> ChainBackend {
+        match self.backend {
+            RpcBackend::Bitcoind => match address.strip_prefix(UNIX_PREFIX) {
+                Some(socket_path) => {
+                    BitcoinClientUnix::with_auth(socket_path, self.client_auth()).into()
+                }
+                None => {
+                    BitcoinClientHTTP::with_auth(address.to_string(), self.client_auth()).into()
+                }
+            },
+            RpcBackend::Indexer => IndexerClient::new(address.to_string()).into(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,33 +140,249 @@ pub struct Limits {
     pub message_size: u64,
     pub profile_size: u64,
     pub payment_size: u64,
+    pub payment_transactions: usize,
+    pub removals: usize,
+    pub search_results: usize,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Payment {
     pub timeout: u64,
     pub token_fee: u64,
+    pub min_fee_rate: u64,
+    pub reject_unconfirmed_parents: bool,
+    pub dsproof_wait: u64,
+    pub fee_address: Option<String>,
+    pub public_url: Option<String>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
     pub memo: String,
     pub hmac_secret: String,
 }
 
+/// Prices, in satoshis, of tokens granting each scope.
+///
+/// Unpriced scopes cost the token fee. If no scope is priced then a single payment of the token fee
+/// grants every scope.
+#[derive(Debug, Default, Deserialize)]
+pub struct Pricing {
+    pub read_messages: Option<u64>,
+    pub write_profile: Option<u64>,
+    pub delete: Option<u64>,
+    pub feeds: Option<u64>,
+}
+
+impl Pricing {
+    fn prices(&self) -> [(Scopes, Option<u64>); 4] {
+        [
+            (Scopes::READ_MESSAGES, self.read_messages),
+            (Scopes::WRITE_PROFILE, self.write_profile),
+            (Scopes::DELETE, self.delete),
+            (Scopes::FEEDS, self.feeds),
+        ]
+    }
+
+    fn is_uniform(&self) -> bool {
+        self.prices().iter().all(|(_, price)| price.is_none())
+    }
+
+    /// The scopes granted by paying for a token required to have the `required` scopes.
+    pub fn granted_scopes(&self, required: Scopes) -> Scopes {
+        if self.is_uniform() {
+            Scopes::ALL
+        } else {
+            required
+        }
+    }
+
+    /// The price of a token granting `scopes`.
+    pub fn price(&self, scopes: Scopes, token_fee: u64) -> u64 {
+        if self.is_uniform() {
+            return token_fee;
+        }
+        self.prices()
+            .iter()
+            .filter(|(scope, _)| scopes.contains(*scope))
+            .map(|(_, price)| price.unwrap_or(token_fee))
+            .sum()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Websocket {
     pub ping_interval: u64,
     pub truncation_length: u64,
+    pub max_frame_size: usize,
+    pub max_inbound_frames: u32,
+    pub inbound_window: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tombstones {
+    pub grace_period: u64,
+    pub purge_interval: u64,
+    pub scavenge_interval: u64,
+}
+
+/// Object storage for large message payloads, enabled when a bucket is given.
+#[derive(Debug, Deserialize)]
+pub struct PayloadStorage {
+    pub bucket: Option<String>,
+    pub prefix: String,
+    pub region: String,
+    pub endpoint: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub threshold: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Cache {
+    pub profile_max_age: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Archive {
+    pub export_interval: u64,
+    pub import_size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Firewall {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub trusted_proxies: Vec<String>,
+    pub max_strikes: u32,
+    pub strike_window: u64,
+    pub ban_duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Compression {
+    pub threshold: usize,
+    pub level: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Profiles {
+    pub max_entries: usize,
+    pub max_entry_size: usize,
+    pub allowed_kinds: Vec<String>,
+    pub avatar_max_size: usize,
+    pub avatar_max_dimension: u32,
+    pub thumbnail_sizes: Vec<u32>,
+}
+
+/// The subcommand given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    DbCompact,
+    DbStats,
+    TokenIssue(String, Option<String>),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self::Serve
+    }
+}
+
+impl Command {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        // NOTE: Required arguments are enforced by clap
+        match matches.subcommand() {
+            ("db", Some(db)) => match db.subcommand_name() {
+                Some("compact") => Self::DbCompact,
+                _ => Self::DbStats,
+            },
+            ("token", Some(token)) => match token.subcommand() {
+                ("issue", Some(issue)) => Self::TokenIssue(
+                    issue.value_of("addr").unwrap().to_string(),
+                    issue.value_of("scopes").map(str::to_string),
+                ),
+                _ => Self::Serve,
+            },
+            _ => Self::Serve,
+        }
+    }
+}
+
+/// Removal of messages whose sender set a TTL.
+#[derive(Debug, Deserialize)]
+pub struct Expiry {
+    pub max_ttl: u64,
+    pub prune_interval: u64,
+}
+
+/// Push notifications of received messages, sent by Web Push and/or FCM.
+#[derive(Debug, Deserialize)]
+pub struct Notifications {
+    pub enabled: bool,
+    pub ttl: u64,
+    pub max_registrations: usize,
+    pub vapid_key: Option<String>,
+    pub vapid_subject: Option<String>,
+    pub fcm_credentials: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Stamps {
+    pub accept_burns: bool,
+    pub derivation_path: Vec<u32>,
+}
+
+/// Endpoints which may be disabled, responding with 501 Not Implemented.
+#[derive(Debug, Deserialize)]
+pub struct Features {
+    pub feeds: bool,
+    pub payloads: bool,
+    pub websocket: bool,
+}
+
+/// Consolidation of token fees received by the node wallet, enabled when a destination is given.
+#[derive(Debug, Deserialize)]
+pub struct Sweep {
+    pub destination: Option<String>,
+    pub threshold: u64,
+    pub fee_rate: u64,
+    pub interval: u64,
+    pub min_confirmations: u32,
+    pub max_inputs: usize,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
-    pub bind: SocketAddr,
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind: Vec<BindAddr>,
     #[cfg(feature = "monitoring")]
-    pub bind_prom: SocketAddr,
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind_prom: Vec<BindAddr>,
     pub db_path: String,
+    pub db_slow_threshold: u64,
     pub network: Network,
+    pub address_prefixes: Vec<String>,
     pub bitcoin_rpc: BitcoinRpc,
     pub limits: Limits,
     pub payments: Payment,
+    #[serde(default)]
+    pub pricing: Pricing,
     pub websocket: Websocket,
+    pub profiles: Profiles,
+    pub compression: Compression,
+    pub tombstones: Tombstones,
+    pub expiry: Expiry,
+    pub notifications: Notifications,
+    pub payload_storage: PayloadStorage,
+    pub firewall: Firewall,
+    pub cache: Cache,
+    pub load_shedding: LoadShedding,
+    pub archive: Archive,
+    pub stamps: Stamps,
+    pub features: Features,
+    pub sweep: Sweep,
+    #[serde(skip)]
+    pub command: Command,
 }
 
 impl Settings {
@@ -76,24 +397,41 @@ impl Settings {
             .author(crate_authors!("\n"))
             .version(crate_version!())
             .get_matches();
-        let home_dir = match dirs::home_dir() {
-            Some(some) => some,
-            None => return Err(ConfigError::Message("no home directory".to_string())),
-        };
+        let data_dir = home_dir()?.join(FOLDER_DIR);
         s.set_default("bind", DEFAULT_BIND)?;
         #[cfg(feature = "monitoring")]
         s.set_default("bind_prom", DEFAULT_BIND_PROM)?;
         s.set_default("network", DEFAULT_NETWORK)?;
-        let mut default_db = home_dir.clone();
-        default_db.push(format!("{}/db", FOLDER_DIR));
+        s.set_default("address_prefixes", DEFAULT_ADDRESS_PREFIXES.to_vec())?;
+        let default_db = data_dir.join("db");
         s.set_default("db_path", default_db.to_str())?;
+        s.set_default("db_slow_threshold", DEFAULT_DB_SLOW_THRESHOLD as i64)?;
+        s.set_default("bitcoin_rpc.backend", DEFAULT_RPC_BACKEND)?;
         s.set_default("bitcoin_rpc.address", DEFAULT_RPC_ADDR)?;
+        s.set_default("bitcoin_rpc.auth", DEFAULT_RPC_AUTH)?;
         s.set_default("bitcoin_rpc.username", DEFAULT_RPC_USER)?;
         s.set_default("bitcoin_rpc.password", DEFAULT_RPC_PASSWORD)?;
+        s.set_default("bitcoin_rpc.fallback_addresses", Vec::<String>::new())?;
+        s.set_default(
+            "bitcoin_rpc.health_check_interval",
+            DEFAULT_RPC_HEALTH_CHECK_INTERVAL as i64,
+        )?;
         s.set_default("limits.message_size", DEFAULT_MESSAGE_LIMIT as i64)?;
         s.set_default("limits.profile_size", DEFAULT_PROFILE_LIMIT as i64)?;
         s.set_default("limits.payment_size", DEFAULT_PAYMENT_LIMIT as i64)?;
+        s.set_default(
+            "limits.payment_transactions",
+            DEFAULT_PAYMENT_TRANSACTIONS_LIMIT as i64,
+        )?;
+        s.set_default("limits.removals", DEFAULT_REMOVALS_LIMIT as i64)?;
+        s.set_default("limits.search_results", DEFAULT_SEARCH_RESULTS_LIMIT as i64)?;
         s.set_default("payments.token_fee", DEFAULT_TOKEN_FEE as i64)?;
+        s.set_default("payments.min_fee_rate", DEFAULT_MIN_FEE_RATE as i64)?;
+        s.set_default(
+            "payments.reject_unconfirmed_parents",
+            DEFAULT_REJECT_UNCONFIRMED_PARENTS,
+        )?;
+        s.set_default("payments.dsproof_wait", DEFAULT_DSPROOF_WAIT as i64)?;
         s.set_default("payments.memo", DEFAULT_MEMO)?;
         s.set_default("payments.timeout", DEFAULT_PAYMENT_TIMEOUT as i64)?;
         s.set_default(
@@ -101,6 +439,103 @@ impl Settings {
             DEFAULT_TRUNCATION_LENGTH as i64,
         )?;
         s.set_default("websocket.ping_interval", DEFAULT_PING_INTERVAL as i64)?;
+        s.set_default("websocket.max_frame_size", DEFAULT_WS_MAX_FRAME_SIZE as i64)?;
+        s.set_default(
+            "websocket.max_inbound_frames",
+            DEFAULT_WS_MAX_INBOUND_FRAMES as i64,
+        )?;
+        s.set_default("websocket.inbound_window", DEFAULT_WS_INBOUND_WINDOW as i64)?;
+        s.set_default("profiles.max_entries", DEFAULT_PROFILE_MAX_ENTRIES as i64)?;
+        s.set_default(
+            "profiles.max_entry_size",
+            DEFAULT_PROFILE_MAX_ENTRY_SIZE as i64,
+        )?;
+        s.set_default("profiles.allowed_kinds", Vec::<String>::new())?;
+        s.set_default("profiles.avatar_max_size", DEFAULT_AVATAR_MAX_SIZE as i64)?;
+        s.set_default(
+            "profiles.avatar_max_dimension",
+            DEFAULT_AVATAR_MAX_DIMENSION as i64,
+        )?;
+        s.set_default(
+            "profiles.thumbnail_sizes",
+            DEFAULT_THUMBNAIL_SIZES
+                .iter()
+                .map(|size| *size as i64)
+                .collect::<Vec<_>>(),
+        )?;
+        s.set_default(
+            "compression.threshold",
+            DEFAULT_COMPRESSION_THRESHOLD as i64,
+        )?;
+        s.set_default("compression.level", DEFAULT_COMPRESSION_LEVEL as i64)?;
+        s.set_default(
+            "tombstones.grace_period",
+            DEFAULT_TOMBSTONE_GRACE_PERIOD as i64,
+        )?;
+        s.set_default(
+            "tombstones.purge_interval",
+            DEFAULT_TOMBSTONE_PURGE_INTERVAL as i64,
+        )?;
+        s.set_default(
+            "tombstones.scavenge_interval",
+            DEFAULT_DIGEST_SCAVENGE_INTERVAL as i64,
+        )?;
+        s.set_default("expiry.max_ttl", DEFAULT_MAX_TTL as i64)?;
+        s.set_default(
+            "expiry.prune_interval",
+            DEFAULT_EXPIRY_PRUNE_INTERVAL as i64,
+        )?;
+        s.set_default("notifications.enabled", DEFAULT_NOTIFICATIONS_ENABLED)?;
+        s.set_default("notifications.ttl", DEFAULT_NOTIFICATION_TTL as i64)?;
+        s.set_default(
+            "notifications.max_registrations",
+            DEFAULT_MAX_PUSH_REGISTRATIONS as i64,
+        )?;
+        s.set_default("payload_storage.prefix", DEFAULT_PAYLOAD_STORAGE_PREFIX)?;
+        s.set_default("payload_storage.region", DEFAULT_PAYLOAD_STORAGE_REGION)?;
+        s.set_default(
+            "payload_storage.threshold",
+            DEFAULT_PAYLOAD_STORAGE_THRESHOLD as i64,
+        )?;
+        s.set_default("cache.profile_max_age", DEFAULT_PROFILE_MAX_AGE as i64)?;
+        s.set_default(
+            "load_shedding.max_concurrent",
+            DEFAULT_MAX_CONCURRENT as i64,
+        )?;
+        s.set_default("load_shedding.queue_timeout", DEFAULT_QUEUE_TIMEOUT as i64)?;
+        s.set_default("stamps.accept_burns", DEFAULT_ACCEPT_BURN_STAMPS)?;
+        s.set_default(
+            "stamps.derivation_path",
+            DEFAULT_STAMP_DERIVATION_PATH
+                .iter()
+                .map(|index| *index as i64)
+                .collect::<Vec<_>>(),
+        )?;
+        s.set_default("features.feeds", DEFAULT_FEATURE_FEEDS)?;
+        s.set_default("features.payloads", DEFAULT_FEATURE_PAYLOADS)?;
+        s.set_default("features.websocket", DEFAULT_FEATURE_WEBSOCKET)?;
+        s.set_default("sweep.threshold", DEFAULT_SWEEP_THRESHOLD as i64)?;
+        s.set_default("sweep.fee_rate", DEFAULT_SWEEP_FEE_RATE as i64)?;
+        s.set_default("sweep.interval", DEFAULT_SWEEP_INTERVAL as i64)?;
+        s.set_default(
+            "sweep.min_confirmations",
+            DEFAULT_SWEEP_MIN_CONFIRMATIONS as i64,
+        )?;
+        s.set_default("sweep.max_inputs", DEFAULT_SWEEP_MAX_INPUTS as i64)?;
+        s.set_default("firewall.allow", Vec::<String>::new())?;
+        s.set_default("firewall.deny", Vec::<String>::new())?;
+        s.set_default("firewall.trusted_proxies", Vec::<String>::new())?;
+        s.set_default("firewall.max_strikes", DEFAULT_FIREWALL_MAX_STRIKES as i64)?;
+        s.set_default(
+            "firewall.strike_window",
+            DEFAULT_FIREWALL_STRIKE_WINDOW as i64,
+        )?;
+        s.set_default(
+            "firewall.ban_duration",
+            DEFAULT_FIREWALL_BAN_DURATION as i64,
+        )?;
+        s.set_default("archive.export_interval", DEFAULT_EXPORT_INTERVAL as i64)?;
+        s.set_default("archive.import_size", DEFAULT_IMPORT_LIMIT as i64)?;
 
         // NOTE: Don't set HMAC key to a default during release for security reasons
         #[cfg(debug_assertions)]
@@ -109,20 +544,16 @@ impl Settings {
         }
 
         // Load config from file
-        let mut default_config = home_dir;
-        default_config.push(format!("{}/config", FOLDER_DIR));
-        let default_config_str = default_config.to_str().unwrap();
-        let config_path = matches.value_of("config").unwrap_or(default_config_str);
-        s.merge(File::with_name(config_path).required(false))?;
+        merge_config_file(&mut s, &data_dir, matches.value_of("config"))?;
 
-        // Set bind address from cmd line
-        if let Some(bind) = matches.value_of("bind") {
-            s.set("bind", bind)?;
+        // Set bind addresses from cmd line
+        if let Some(bind) = matches.values_of("bind") {
+            s.set("bind", bind.collect::<Vec<_>>())?;
         }
 
-        // Set bind address from cmd line
-        if let Some(bind_prom) = matches.value_of("bind-prom") {
-            s.set("bind_prom", bind_prom)?;
+        // Set bind addresses from cmd line
+        if let Some(bind_prom) = matches.values_of("bind-prom") {
+            s.set("bind_prom", bind_prom.collect::<Vec<_>>())?;
         }
 
         // Set the bitcoin network
@@ -155,6 +586,89 @@ impl Settings {
             s.set("payments.hmac_secret", hmac_secret)?;
         }
 
-        s.try_into()
+        let mut settings: Self = s.try_into()?;
+        if settings.bind.is_empty() {
+            return Err(ConfigError::Message(
+                "at least one bind address is required".to_string(),
+            ));
+        }
+        #[cfg(feature = "monitoring")]
+        {
+            if settings.bind_prom.is_empty() {
+                return Err(ConfigError::Message(
+                    "at least one prometheus bind address is required".to_string(),
+                ));
+            }
+        }
+        if settings.bitcoin_rpc.auth == RpcAuth::Cookie
+            && settings.bitcoin_rpc.cookie_file.is_none()
+        {
+            return Err(ConfigError::Message(
+                "cookie authentication requires a cookie file".to_string(),
+            ));
+        }
+        if settings.bitcoin_rpc.backend == RpcBackend::Indexer
+            && settings.payments.fee_address.is_none()
+        {
+            return Err(ConfigError::Message(
+                "indexer backend requires a payment fee address".to_string(),
+            ));
+        }
+        if settings.bitcoin_rpc.backend == RpcBackend::Indexer
+            && settings.sweep.destination.is_some()
+        {
+            return Err(ConfigError::Message(
+                "sweeping requires the bitcoind backend".to_string(),
+            ));
+        }
+        if settings
+            .stamps
+            .derivation_path
+            .iter()
+            .any(|index| index & (1 << 31) != 0)
+        {
+            return Err(ConfigError::Message(
+                "stamp derivation path must not be hardened".to_string(),
+            ));
+        }
+        if settings.notifications.enabled
+            && settings.notifications.vapid_key.is_none()
+            && settings.notifications.fcm_credentials.is_none()
+        {
+            return Err(ConfigError::Message(
+                "notifications require a VAPID key or FCM credentials".to_string(),
+            ));
+        }
+        if settings.notifications.vapid_key.is_some()
+            && settings.notifications.vapid_subject.is_none()
+        {
+            return Err(ConfigError::Message(
+                "a VAPID key requires a VAPID subject".to_string(),
+            ));
+        }
+        settings.command = Command::from_matches(&matches);
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pricing() {
+        let uniform = Pricing::default();
+        assert_eq!(uniform.granted_scopes(Scopes::DELETE), Scopes::ALL);
+        assert_eq!(uniform.price(Scopes::ALL, 100), 100);
+
+        let pricing = Pricing {
+            write_profile: Some(500),
+            delete: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(pricing.granted_scopes(Scopes::DELETE), Scopes::DELETE);
+        assert_eq!(pricing.price(Scopes::DELETE, 100), 10);
+        assert_eq!(pricing.price(Scopes::READ_MESSAGES, 100), 100);
+        assert_eq!(pricing.price(Scopes::ALL, 100), 710);
     }
 }
@@ -0,0 +1,417 @@
+use std::{cmp::Ordering, convert::TryInto, time::Duration};
+
+use cashweb::{
+    bitcoin::{
+        psbt::PartiallySignedTransaction,
+        transaction::{
+            input::Input,
+            outpoint::Outpoint,
+            output::Output,
+            script::{opcodes, Script},
+            Transaction,
+        },
+        Encodable,
+    },
+    bitcoin_client::{BitcoinClient, ChainBackend, FailoverClient, NodeError, Unspent},
+};
+use thiserror::Error;
+use tokio::time::interval;
+use tracing::{error, info, warn};
+
+use crate::SETTINGS;
+
+const SATS_PER_COIN: f64 = 100_000_000.;
+
+/// Length of a signed P2PKH input script, a push of a DER signature and its hash type followed by a
+/// push of a compressed public key.
+const P2PKH_INPUT_SCRIPT_LEN: usize = 1 + 72 + 1 + 33;
+
+/// Length of a signed P2PK input script, a push of a DER signature and its hash type.
+const P2PK_INPUT_SCRIPT_LEN: usize = 1 + 72;
+
+/// Minimum value of the swept output, below which it would be rejected as dust.
+const DUST_THRESHOLD: u64 = 546;
+
+#[derive(Debug, Error)]
+pub enum SweepError {
+    #[error("malformed unspent output: {0}")]
+    MalformedUnspent(String),
+    #[error("unsupported script of unspent output: {0}")]
+    UnsupportedScript(String),
+    #[error("insufficient value: {0} < {1}")]
+    InsufficientValue(u64, u64),
+    #[error("failed to list unspent outputs: {0}")]
+    ListUnspent(NodeError),
+    #[error("failed to sign sweep: {0}")]
+    Sign(NodeError),
+    #[error("failed to broadcast sweep: {0}")]
+    Broadcast(NodeError),
+}
+
+/// A completed sweep.
+#[derive(Debug)]
+pub struct Swept {
+    pub tx_id: String,
+    pub n_inputs: usize,
+    pub value: u64,
+    pub fee: u64,
+}
+
+fn to_satoshis(amount: f64) -> u64 {
+    (amount * SATS_PER_COIN).round() as u64
+}
+
+/// Length of the signed input script spending an output script, if the node wallet can sign it.
+///
+/// P2PKH outputs are assumed to pay to compressed public keys, as issued by the node wallet.
+fn input_script_len(script: &Script) -> Option<usize> {
+    let raw_script = script.as_bytes();
+    let is_p2pk = matches!(
+        raw_script,
+        [33, .., opcodes::OP_CHECKSIG] if raw_script.len() == 35
+    ) || matches!(
+        raw_script,
+        [65, .., opcodes::OP_CHECKSIG] if raw_script.len() == 67
+    );
+    if script.is_p2pkh() {
+        Some(P2PKH_INPUT_SCRIPT_LEN)
+    } else if is_p2pk {
+        Some(P2PK_INPUT_SCRIPT_LEN)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the output can be swept, its script being one the node wallet can sign.
+fn is_sweepable(output: &Unspent) -> bool {
+    hex::decode(&output.script_pub_key)
+        .map(|script| input_script_len(&Script::from(script)).is_some())
+        .unwrap_or(false)
+}
+
+/// Construct an unsigned transaction spending every given output to the destination script, at a
+/// fee rate given in satoshis per kilobyte.
+///
+/// The fee is calculated with input scripts sized as signed inputs of the script types spent, P2PKH
+/// or P2PK. The returned transaction carries the outputs it spends.
+pub fn build_sweep(
+    unspent: &[Unspent],
+    destination: Script,
+    fee_per_kb: u64,
+) -> Result<PartiallySignedTransaction, SweepError> {
+    let mut prev_outputs = Vec::with_capacity(unspent.len());
+    let mut inputs = Vec::with_capacity(unspent.len());
+    for output in unspent {
+        let malformed = || SweepError::MalformedUnspent(format!("{}:{}", output.txid, output.vout));
+
+        // Outpoints are serialized with the transaction ID reversed
+        let mut tx_id: [u8; 32] = hex::decode(&output.txid)
+            .ok()
+            .and_then(|tx_id| tx_id.try_into().ok())
+            .ok_or_else(malformed)?;
+        tx_id.reverse();
+        let script = Script::from(hex::decode(&output.script_pub_key).map_err(|_| malformed())?);
+        let input_script_len = input_script_len(&script).ok_or_else(|| {
+            SweepError::UnsupportedScript(format!("{}:{}", output.txid, output.vout))
+        })?;
+
+        inputs.push(Input {
+            outpoint: Outpoint {
+                tx_id,
+                vout: output.vout,
+            },
+            script: Script::from(vec![0; input_script_len]),
+            sequence: u32::MAX,
+        });
+        prev_outputs.push(Output {
+            value: to_satoshis(output.amount),
+            script,
+        });
+    }
+    let value: u64 = prev_outputs.iter().map(|output| output.value).sum();
+
+    let mut transaction = Transaction {
+        version: 2,
+        inputs,
+        outputs: vec![Output {
+            value,
+            script: destination,
+        }],
+        lock_time: 0,
+    };
+    let fee = transaction.required_fee(fee_per_kb);
+    let required = fee + DUST_THRESHOLD;
+    if value < required {
+        return Err(SweepError::InsufficientValue(value, required));
+    }
+    transaction.outputs[0].value = value - fee;
+
+    let mut psbt = PartiallySignedTransaction::new(transaction);
+    for (input, prev_output) in psbt.inputs.iter_mut().zip(prev_outputs) {
+        input.prev_output = Some(prev_output);
+    }
+    Ok(psbt)
+}
+
+/// Construct unsigned transactions sweeping the given outputs to the destination script, each
+/// spending at most `max_inputs` outputs.
+///
+/// The largest outputs are swept first. Outputs too small to pay the fee of a transaction of their
+/// own are left unspent, unless no transaction can be built at all.
+pub fn build_sweeps(
+    unspent: &[Unspent],
+    destination: Script,
+    fee_per_kb: u64,
+    max_inputs: usize,
+) -> Result<Vec<PartiallySignedTransaction>, SweepError> {
+    let mut unspent = unspent.to_vec();
+    unspent.sort_by(|a, b| b.amount.partial_cmp(&a.amount).unwrap_or(Ordering::Equal));
+
+    let mut sweeps = Vec::new();
+    for batch in unspent.chunks(max_inputs.max(1)) {
+        match build_sweep(batch, destination.clone(), fee_per_kb) {
+            Ok(psbt) => sweeps.push(psbt),
+            Err(SweepError::InsufficientValue(..)) if !sweeps.is_empty() => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(sweeps)
+}
+
+/// Construct the sweeps of the unspent outputs of the node wallet to the destination, if their
+/// total value meets the threshold.
+///
+/// Outputs whose scripts the node wallet cannot sign are left unspent.
+async fn pending_sweeps(
+    bitcoin_client: &FailoverClient<ChainBackend>,
+    destination: Script,
+) -> Result<Vec<PartiallySignedTransaction>, SweepError> {
+    let mut unspent = bitcoin_client
+        .list_unspent(SETTINGS.sweep.min_confirmations)
+        .await
+        .map_err(SweepError::ListUnspent)?;
+    let n_unspent = unspent.len();
+    unspent.retain(is_sweepable);
+    if unspent.len() < n_unspent {
+        warn!(
+            message = "skipping unsupported unspent outputs",
+            skipped = n_unspent - unspent.len()
+        );
+    }
+
+    let value: u64 = unspent
+        .iter()
+        .map(|output| to_satoshis(output.amount))
+        .sum();
+    if unspent.is_empty() || value < SETTINGS.sweep.threshold {
+        return Ok(Vec::new());
+    }
+
+    build_sweeps(
+        &unspent,
+        destination,
+        SETTINGS.sweep.fee_rate,
+        SETTINGS.sweep.max_inputs,
+    )
+}
+
+/// Sign and broadcast a sweep.
+///
+/// The node wallet holds the keys of the addresses it issued for token fees, so it signs the sweep.
+async fn send_sweep(
+    bitcoin_client: &FailoverClient<ChainBackend>,
+    psbt: PartiallySignedTransaction,
+) -> Result<Swept, SweepError> {
+    let value: u64 = psbt
+        .inputs
+        .iter()
+        .filter_map(|input| input.prev_output.as_ref())
+        .map(|prev_output| prev_output.value)
+        .sum();
+    let fee = value - psbt.transaction.outputs[0].value;
+    let mut raw_tx = Vec::with_capacity(psbt.transaction.encoded_len());
+    psbt.transaction.encode_raw(&mut raw_tx);
+
+    let signed_tx = bitcoin_client
+        .sign_raw_transaction(&raw_tx)
+        .await
+        .map_err(SweepError::Sign)?;
+    let tx_id = bitcoin_client
+        .send_tx(&signed_tx)
+        .await
+        .map_err(SweepError::Broadcast)?;
+    Ok(Swept {
+        tx_id,
+        n_inputs: psbt.inputs.len(),
+        value,
+        fee,
+    })
+}
+
+fn record_sweep(swept: &Swept) {
+    info!(
+        message = "swept token fees",
+        tx_id = %swept.tx_id,
+        inputs = swept.n_inputs,
+        value = swept.value,
+        fee = swept.fee
+    );
+
+    #[cfg(feature = "monitoring")]
+    {
+        crate::monitoring::SWEEP_TOTAL.inc();
+        crate::monitoring::SWEPT_SATOSHIS_TOTAL.inc_by(swept.value);
+        crate::monitoring::SWEEP_FEE_SATOSHIS_TOTAL.inc_by(swept.fee);
+    }
+}
+
+fn record_failure(err: &SweepError) {
+    error!(message = "failed to sweep token fees", error = %err);
+
+    #[cfg(feature = "monitoring")]
+    crate::monitoring::SWEEP_FAILED_TOTAL.inc();
+}
+
+/// Periodically sweep token fees received by the node wallet to the destination script.
+pub async fn sweep_fees(bitcoin_client: FailoverClient<ChainBackend>, destination: Script) {
+    let mut sweep_interval = interval(Duration::from_millis(SETTINGS.sweep.interval));
+    loop {
+        sweep_interval.tick().await;
+
+        let sweeps = match pending_sweeps(&bitcoin_client, destination.clone()).await {
+            Ok(ok) => ok,
+            Err(err) => {
+                record_failure(&err);
+                continue;
+            }
+        };
+        for psbt in sweeps {
+            match send_sweep(&bitcoin_client, psbt).await {
+                Ok(swept) => record_sweep(&swept),
+                Err(err) => {
+                    record_failure(&err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unspent(tx_id: u8, amount: f64) -> Unspent {
+        Unspent {
+            txid: hex::encode([tx_id; 32]),
+            vout: 1,
+            amount,
+            confirmations: 1,
+            script_pub_key: hex::encode(Script::p2pkh(&[tx_id; 20]).as_bytes()),
+        }
+    }
+
+    #[test]
+    fn sweep_transaction() {
+        let destination = Script::p2pkh(&[9; 20]);
+        let psbt = build_sweep(
+            &[unspent(1, 0.001), unspent(2, 0.002)],
+            destination.clone(),
+            1_000,
+        )
+        .unwrap();
+
+        // Inputs are unsigned and carry the outputs they spend
+        assert_eq!(psbt.transaction.inputs.len(), 2);
+        assert!(psbt
+            .transaction
+            .inputs
+            .iter()
+            .all(|input| input.script.is_empty()));
+        assert_eq!(psbt.inputs[1].prev_output.as_ref().unwrap().value, 200_000);
+
+        // The fee is paid at the fee rate of the signed transaction
+        let output = &psbt.transaction.outputs[0];
+        assert_eq!(output.script, destination);
+        let fee = 300_000 - output.value;
+        assert!(fee > 0 && fee < 1_000);
+        let input_values = [100_000, 200_000];
+        assert_eq!(psbt.transaction.fee(&input_values), Some(fee));
+    }
+
+    #[test]
+    fn sweep_insufficient_value() {
+        let destination = Script::p2pkh(&[9; 20]);
+        assert!(matches!(
+            build_sweep(&[unspent(1, 0.000_01)], destination, 1_000),
+            Err(SweepError::InsufficientValue(1_000, _))
+        ));
+    }
+
+    #[test]
+    fn sweep_malformed() {
+        let destination = Script::p2pkh(&[9; 20]);
+        let mut malformed = unspent(1, 0.001);
+        malformed.txid = "abcd".to_string();
+        assert!(matches!(
+            build_sweep(&[malformed], destination, 1_000),
+            Err(SweepError::MalformedUnspent(_))
+        ));
+    }
+
+    #[test]
+    fn sweep_script_types() {
+        let destination = Script::p2pkh(&[9; 20]);
+        let p2pkh_psbt = build_sweep(&[unspent(1, 0.001)], destination.clone(), 1_000).unwrap();
+
+        // P2PK inputs are signed without a public key, so their fee is smaller
+        let mut p2pk = unspent(1, 0.001);
+        p2pk.script_pub_key =
+            hex::encode([&[33][..], &[2; 33][..], &[opcodes::OP_CHECKSIG]].concat());
+        let p2pk_psbt = build_sweep(&[p2pk], destination.clone(), 1_000).unwrap();
+        assert_eq!(
+            p2pk_psbt.transaction.outputs[0].value - p2pkh_psbt.transaction.outputs[0].value,
+            (P2PKH_INPUT_SCRIPT_LEN - P2PK_INPUT_SCRIPT_LEN) as u64
+        );
+
+        // The node wallet cannot sign P2SH inputs
+        let mut p2sh = unspent(2, 0.001);
+        p2sh.script_pub_key = hex::encode(Script::p2sh(&[2; 20]).as_bytes());
+        assert!(!is_sweepable(&p2sh));
+        assert!(matches!(
+            build_sweep(&[unspent(1, 0.001), p2sh], destination, 1_000),
+            Err(SweepError::UnsupportedScript(_))
+        ));
+    }
+
+    #[test]
+    fn sweep_batches() {
+        let destination = Script::p2pkh(&[9; 20]);
+        let mut outputs: Vec<_> = (1..=5)
+            .map(|tx_id| unspent(tx_id, 0.001 * f64::from(tx_id)))
+            .collect();
+        let sweeps = build_sweeps(&outputs, destination.clone(), 1_000, 2).unwrap();
+
+        // The largest outputs are swept first, at most two per transaction
+        let n_inputs: Vec<_> = sweeps.iter().map(|psbt| psbt.inputs.len()).collect();
+        assert_eq!(n_inputs, vec![2, 2, 1]);
+        let first_values: Vec<_> = sweeps[0]
+            .inputs
+            .iter()
+            .map(|input| input.prev_output.as_ref().unwrap().value)
+            .collect();
+        assert_eq!(first_values, vec![500_000, 400_000]);
+
+        // Outputs too small for a transaction of their own are left for a later sweep
+        outputs.push(unspent(6, 0.000_01));
+        let sweeps = build_sweeps(&outputs, destination.clone(), 1_000, 5).unwrap();
+        let n_inputs: Vec<_> = sweeps.iter().map(|psbt| psbt.inputs.len()).collect();
+        assert_eq!(n_inputs, vec![5]);
+
+        // Unless no transaction can be built at all
+        assert!(matches!(
+            build_sweeps(&outputs[5..], destination, 1_000, 5),
+            Err(SweepError::InsufficientValue(1_000, _))
+        ));
+    }
+}